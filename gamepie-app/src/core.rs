@@ -1,53 +1,157 @@
 use log::{debug, error, info, trace, warn};
+use std::cell::OnceCell;
 use std::error::Error;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{mpsc, Arc};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use gamepie_core::commands::{AudioCmd, AudioMsg};
+use gamepie_core::aspect::{AspectRatio, ScaleMode};
+use gamepie_core::commands::{AudioCmd, AudioMsg, AudioStatus, ScreenMessage, ScreenToast};
+use gamepie_core::config::Config;
+use gamepie_core::dirs::GamepieDirs;
 use gamepie_core::error::GamepieError;
 use gamepie_core::portable::PString;
 use gamepie_core::problem::Problem;
-use gamepie_core::{CoreInfo, SAVEDATA_EXT, SAVE_PATH, SYS_PATH};
+use gamepie_core::stats::{GameStats, Stats};
+use gamepie_core::{metadata, CoreInfo};
+use gamepie_libretro::proxy::MAX_PORTS;
+use gamepie_libretro::stats::FrameStats;
 use gamepie_libretrobind::functions;
-use gamepie_libretrobind::functions::RetroGameInfo;
+use gamepie_libretrobind::functions::{RetroGameInfo, RetroGameInfoExt};
+use gamepie_libretrobind::memory::MemoryMap;
 use gamepie_libretrobind::types::RetroSystemAvInfo;
 use gamepie_libretrobind::utils;
-use gamepie_screen::Screen;
+use gamepie_libretrobind::utils::SaveRegion;
+use gamepie_screen::ScreenHandle;
+
+use crate::frameskip::FrameSkip;
+use crate::recorder::Recorder;
+use crate::rewind::Rewind;
+use crate::slots::{SaveSlot, TimedSaves};
+use crate::states::StateHeader;
+use crate::thermal::Thermal;
 
 enum SaveType {
     Timed,
     Full,
 }
 
+// Until set from the settings
 const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+// Frame stats are logged this often while a game runs
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+// Gaps longer than this many frames are taken to be pauses, and cores that
+// pace themselves are given their reference frame time instead
+const FRAME_GAP_MAX: u32 = 8;
 
 pub struct Core {
     lib: Arc<libloading::Library>,
-    _info: CoreInfo,
+    info: CoreInfo,
     _av: RetroSystemAvInfo,
     frame_count: u64,
     frame_time: Duration,
+    // When the core was last run, for its frame time callback
+    last_run: Option<Instant>,
+    // Last fill level reported by the audio thread
+    audio_status: Option<AudioStatus>,
+    // Highest audio latency this session, warned about once over the limit
+    peak_latency: Duration,
+    latency_warn: Duration,
+    latency_warned: bool,
     save_path: Option<String>,
+    // Save RAM from the memory map, used if the core has no save RAM
+    save_regions: Vec<SaveRegion>,
     audio: mpsc::Sender<AudioMsg>,
     save_time: Instant,
-    save_mod: bool,
+    save_interval: Duration,
+    timed_saves: Option<TimedSaves>,
+    quick_state: Option<Vec<u8>>,
+    state_budget: usize,
+    // Written in state files, the core is only hashed once one is used
+    fingerprint: OnceCell<u64>,
+    rom_crc: Option<u32>,
+    rewind: Option<Rewind>,
+    thermal: Option<Thermal>,
+    frameskip: Option<FrameSkip>,
+    // Frames skipped for each drawn, and whether the last frame run was
+    skip: u32,
+    skipped: bool,
+    // Frames run for each shown while fast-forwarding, and whether the
+    // frame being run is one that isn't shown
+    fast_forward_max: u32,
+    fast_forwarding: bool,
+    hidden: bool,
+    recorder: Option<Recorder>,
+    error_tx: mpsc::Sender<Problem>,
+    game_name: String,
+    // Performance statistics for this session
+    stats_path: PathBuf,
+    game_path: PathBuf,
+    start_time: Instant,
+    dropped: u64,
+    start_screen_dropped: u64,
+    start_underruns: u32,
+    // Frame stats since they were last logged
+    log_stats: FrameStats,
 }
 
 impl Core {
+    /// Create a new proxy for the next core, lending it the screen. Done on
+    /// the thread that owns the screen, before the core is loaded by `new`.
+    pub fn create_proxy(
+        config: &Config,
+        screens: &mut ScreenHandle,
+        error_channel: mpsc::Sender<Problem>,
+        audio: mpsc::Sender<AudioMsg>,
+    ) -> Result<(), Box<dyn Error>> {
+        let dirs = &config.dirs;
+        let sys_dir = PString::from_str(dirs.system().to_str().ok_or(GamepieError::String)?)?;
+        let save_dir = PString::from_str(dirs.save_dir().to_str().ok_or(GamepieError::String)?)?;
+        crate::proxy::libretro::create(
+            sys_dir,
+            save_dir,
+            screens,
+            error_channel,
+            audio,
+            &config.input,
+        )?;
+        Ok(())
+    }
+
+    /// Load the core and game, using the proxy from `create_proxy`. This can
+    /// take a while for large games so is done on a worker thread.
     pub fn new(
         info: CoreInfo,
         game: &Path,
-        root_dir: PString,
-        screen: Option<Screen>,
-        error_channel: mpsc::Sender<Problem>,
+        config: &Config,
+        error_tx: mpsc::Sender<Problem>,
         audio: mpsc::Sender<AudioMsg>,
+        trace_env: bool,
+        preloaded: Option<Vec<u8>>,
     ) -> Result<Core, Box<dyn Error>> {
-        // Create new proxy for this core
-        let sys_dir_path = Path::new(root_dir.to_str()).join(SYS_PATH);
-        let sys_dir = PString::from_str(sys_dir_path.to_str().ok_or(GamepieError::String)?)?;
-        crate::proxy::libretro::create(sys_dir, screen, error_channel, audio.clone());
+        let dirs = &config.dirs;
+        if config.core_options.presets {
+            // Must be set before the environment callback, as that is when
+            // cores will provide their options.
+            crate::proxy::libretro::with_proxy(|p| p.use_presets(&info.name()));
+        }
+        let options = dirs.core_options_file(&info.name());
+        crate::proxy::libretro::with_proxy(|p| p.use_saved_options(&options));
+        let metadata = metadata::load(game);
+        if let Some(m) = &metadata {
+            let overrides = metadata::options(m);
+            crate::proxy::libretro::with_proxy(|p| p.use_game_options(overrides));
+        }
+        if trace_env {
+            // One trace per game session
+            let time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let path = dirs.trace_file(&info.name(), time);
+            crate::proxy::libretro::with_proxy(|p| p.trace_env(&path));
+        }
 
         let lib = functions::load_library(info.path())?;
 
@@ -64,25 +168,59 @@ impl Core {
 
         debug!("Loading game: {}", game.display());
 
-        let game_info = RetroGameInfo::new(game.to_str().expect("Invalid path"));
-        let save_path = Self::save(root_dir.to_str(), game);
+        let meta = metadata
+            .as_ref()
+            .and_then(|m| m.get("meta"))
+            .and_then(|m| m.as_str())
+            .unwrap_or("");
+        let save_path = Self::save(dirs, game);
         match &save_path {
             Some(path) => info!("Save path: {}", path),
             None => warn!("No save path"),
         };
-        let loaded = functions::load_game(&lib, info.sys_info(), game_info)?;
+        let mut rom_crc = None;
+        let loaded = match metadata
+            .as_ref()
+            .and_then(|m| Self::subsystem_roms(game, m))
+        {
+            Some((ident, roms)) => Self::load_subsystem(&lib, &ident, &roms, meta)?,
+            None => {
+                let path = game.to_str().expect("Invalid path");
+                let mut game_info = RetroGameInfo::new(path).with_meta(meta);
+                if !info.sys_info().need_fullpath {
+                    match preloaded {
+                        Some(data) => game_info = game_info.with_data(data),
+                        None => game_info.read()?,
+                    }
+                }
+                rom_crc = game_info.crc();
+                Self::with_game_info(&[&game_info], || {
+                    functions::load_game(&lib, info.sys_info(), &game_info)
+                })?
+            }
+        };
 
         if loaded {
+            // Memory maps are set while loading the game
+            let save_regions =
+                crate::proxy::libretro::with_proxy(|p| p.save_regions()).unwrap_or_default();
+            if !save_regions.is_empty() && !utils::has_save_memory(&lib, &[])? {
+                info!(
+                    "No save RAM, saving {} regions from memory map",
+                    save_regions.len()
+                );
+            }
+
             // Load save
             if let Some(save) = &save_path {
-                if utils::has_save_memory(&lib)? {
-                    utils::try_read_into_save_mem(&lib, save)?;
+                if utils::has_save_memory(&lib, &save_regions)? {
+                    Self::load_save(&lib, &save_regions, save, &error_tx)?;
                 }
             } else {
                 error!("No valid save path");
             }
 
-            functions::set_controller_port_device(&lib)?;
+            functions::set_controller_port_device(&lib, MAX_PORTS)?;
             trace!("Getting system AV info");
             let av = functions::get_system_av_info(&lib)?;
 
@@ -91,6 +229,25 @@ impl Core {
                 av.geometry.base_width, av.geometry.base_height
             );
             crate::proxy::libretro::set_av(av);
+            let aspect = metadata
+                .as_ref()
+                .and_then(|m| m.get("aspect_ratio"))
+                .and_then(AspectRatio::parse);
+            if let Some(a) = aspect {
+                debug!("Aspect ratio: {:?}", a);
+                crate::proxy::libretro::with_proxy(|p| p.set_aspect(aspect));
+            }
+            let scaling = metadata
+                .as_ref()
+                .and_then(|m| m.get("scaling"))
+                .and_then(|s| s.as_str())
+                .and_then(ScaleMode::parse)
+                .unwrap_or(config.video.scaling);
+            debug!("Scaling: {:?}", scaling);
+            crate::proxy::libretro::with_proxy(|p| {
+                p.set_scale_mode(scaling);
+                p.set_hud(config.video.hud);
+            });
             debug!("Audio sample rate: {} Hz", av.timing.sample_rate);
 
             let freq: i32 = av.timing.sample_rate as i32;
@@ -102,18 +259,59 @@ impl Core {
             debug!("Frame time: {:?}", frame_time);
 
             let save_time = Instant::now();
-            let save_mod = false;
+            let timed_saves = save_path.as_deref().map(TimedSaves::new);
 
             Ok(Core {
                 lib,
-                _info: info,
+                info,
                 _av: av,
                 frame_count: 0,
                 frame_time,
+                last_run: None,
+                audio_status: None,
+                peak_latency: Duration::ZERO,
+                latency_warn: config.audio.latency_warn,
+                latency_warned: false,
                 save_path,
+                save_regions,
                 audio,
                 save_time,
-                save_mod,
+                save_interval: SAVE_INTERVAL,
+                timed_saves,
+                quick_state: None,
+                state_budget: config.save_states.memory_budget,
+                fingerprint: OnceCell::new(),
+                rom_crc,
+                rewind: if config.rewind.enabled {
+                    Some(Rewind::new(
+                        &config.rewind,
+                        config.save_states.memory_budget,
+                    ))
+                } else {
+                    None
+                },
+                thermal: if config.thermal.enabled {
+                    Some(Thermal::new(&config.thermal))
+                } else {
+                    None
+                },
+                frameskip: (config.video.max_frameskip > 0)
+                    .then(|| FrameSkip::new(config.video.max_frameskip)),
+                skip: 0,
+                skipped: false,
+                fast_forward_max: config.fast_forward.max_speed,
+                fast_forwarding: false,
+                hidden: false,
+                recorder: None,
+                error_tx,
+                game_name: Self::game_name_from_path(game),
+                stats_path: dirs.stats_file(),
+                game_path: game.to_path_buf(),
+                start_time: Instant::now(),
+                dropped: 0,
+                start_screen_dropped: Self::screen_dropped(),
+                start_underruns: crate::proxy::audio::underruns(),
+                log_stats: FrameStats::default(),
             })
         } else {
             error!("Failed to load game");
@@ -121,35 +319,242 @@ impl Core {
         }
     }
 
-    fn save(root_dir: &str, game: &Path) -> Option<String> {
-        if let Some(filename) = game.file_name() {
-            match filename.to_str() {
-                Some(f) => {
-                    let mut save_file = String::from(f);
-                    save_file.push('.');
-                    save_file.push_str(SAVEDATA_EXT);
-                    let save_path = Path::new(root_dir).join(SAVE_PATH).join(save_file);
-                    // Can assume the path is utf-8 as already matched on the filename
-                    Some(String::from(save_path.to_str().expect("non UTF-8")))
+    // Let the core ask for extended information about the games while
+    // they are loaded.
+    fn with_game_info<F>(games: &[&RetroGameInfo], load: F) -> Result<bool, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<bool, Box<dyn Error>>,
+    {
+        for game in games {
+            if let Some(crc) = game.crc() {
+                info!("CRC32: {:08x}", crc);
+            }
+        }
+        let ext = RetroGameInfoExt::new(games)?;
+        crate::proxy::libretro::with_proxy(|p| {
+            p.set_game_info(Some(ext));
+            // A map from an earlier game that failed to load is stale
+            p.set_memory_map(MemoryMap::default());
+        });
+        let loaded = load();
+        crate::proxy::libretro::with_proxy(|p| p.set_game_info(None));
+        loaded
+    }
+
+    // Subsystem and content to load for a game from its metadata, e.g.
+    //
+    // [subsystem]
+    // ident = "sgb"
+    // roms = ["Super Game Boy (World).sfc", "Tetris (World).gb"]
+    //
+    // with the ROMs relative to the game's directory.
+    fn subsystem_roms(game: &Path, metadata: &toml::Value) -> Option<(String, Vec<PathBuf>)> {
+        let subsystem = metadata.get("subsystem")?;
+        let ident = subsystem.get("ident")?.as_str()?;
+        let dir = game.parent().unwrap_or_else(|| Path::new(""));
+        let roms = subsystem
+            .get("roms")?
+            .as_array()?
+            .iter()
+            .filter_map(|r| r.as_str())
+            .map(|r| dir.join(r))
+            .collect();
+        Some((String::from(ident), roms))
+    }
+
+    fn load_subsystem(
+        lib: &libloading::Library,
+        ident: &str,
+        roms: &[PathBuf],
+        meta: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        // Advertised by the core when the environment callback was set
+        let subsystem = crate::proxy::libretro::with_proxy(|p| p.subsystem(ident)).flatten();
+        let subsystem = match subsystem {
+            Some(s) if s.accepts(roms.len()) => s,
+            Some(_) => {
+                error!("Wrong number of ROMs for subsystem '{}'", ident);
+                return Err(Box::new(GamepieError::GameLoadError));
+            }
+            None => {
+                error!("Core has no subsystem '{}'", ident);
+                return Err(Box::new(GamepieError::GameLoadError));
+            }
+        };
+        debug!("Loading as {}", subsystem.desc);
+        let games: Vec<_> = roms
+            .iter()
+            .zip(&subsystem.roms)
+            .map(|(path, rom)| {
+                let path = path.to_str().ok_or(GamepieError::String)?;
+                let mut game = RetroGameInfo::new(path).with_meta(meta);
+                if !rom.need_fullpath {
+                    game.read()?;
                 }
-                None => {
-                    error!("Filename is not valid UTF-8");
-                    None
+                Ok((game, rom.need_fullpath))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        let infos: Vec<_> = games.iter().map(|(g, _)| g).collect();
+        Self::with_game_info(&infos, || {
+            functions::load_game_special(lib, subsystem.id, &games)
+        })
+    }
+
+    // Load the save, if the save is missing or the wrong size then the
+    // newest valid timed save is used instead and a message is shown.
+    fn load_save(
+        lib: &libloading::Library,
+        mapped: &[SaveRegion],
+        save: &str,
+        error_tx: &mpsc::Sender<Problem>,
+    ) -> Result<(), Box<dyn Error>> {
+        if utils::save_is_valid(lib, mapped, save) {
+            return utils::try_read_into_save_mem(lib, mapped, save);
+        }
+
+        let newest =
+            crate::slots::newest_timed(save, |path| utils::save_is_valid(lib, mapped, path));
+        match newest {
+            Some(SaveSlot { modified, path, .. }) => {
+                let reason = if Path::new(save).exists() {
+                    "damaged"
+                } else {
+                    "missing"
+                };
+                let age = modified.elapsed().unwrap_or_default().as_secs() / 60;
+                warn!(
+                    "Save '{}' {}, recovering from '{}' ({} minutes old)",
+                    save, reason, path, age
+                );
+                utils::try_read_into_save_mem(lib, mapped, &path)?;
+                let msg = format!("Save {}, used {}m old backup", reason, age);
+                let toast = ScreenToast::error(ScreenMessage::Message(msg));
+                if error_tx.send(Problem::warn(toast)).is_err() {
+                    warn!("Failed to send save recovery message");
                 }
+                Ok(())
+            }
+            // Nothing to recover from, so report the original problem
+            None => utils::try_read_into_save_mem(lib, mapped, save),
+        }
+    }
+
+    fn save(dirs: &GamepieDirs, game: &Path) -> Option<String> {
+        match dirs
+            .save_file(game)
+            .map(|p| p.into_os_string().into_string())
+        {
+            Some(Ok(save_path)) => Some(save_path),
+            Some(Err(_)) => {
+                error!("Save path is not valid UTF-8");
+                None
+            }
+            None => {
+                error!("Game has no filename");
+                None
             }
-        } else {
-            error!("Game has no filename");
-            None
         }
     }
 
+    fn game_name_from_path(game: &Path) -> String {
+        match game.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => String::from("unknown"),
+        }
+    }
+
+    /// Saves of the game that can be restored, newest first
+    pub fn save_slots(&self) -> Vec<SaveSlot> {
+        self.save_path
+            .as_deref()
+            .map(crate::slots::slots)
+            .unwrap_or_default()
+    }
+
+    /// Replace the game's save RAM with a slot, restarting the game so it
+    /// is read. The slot is written to the save when the game exits.
+    pub fn restore_slot(&mut self, slot: &SaveSlot) -> Result<(), Box<dyn Error>> {
+        if !utils::save_is_valid(&self.lib, &self.save_regions, &slot.path) {
+            error!("Save slot '{}' doesn't match the game", slot.path);
+            return Err(Box::new(GamepieError::MismatchSave));
+        }
+        utils::try_read_into_save_mem(&self.lib, &self.save_regions, &slot.path)?;
+        functions::reset(&self.lib)?;
+        info!("Restored save from '{}'", slot.path);
+        Ok(())
+    }
+
+    /// How often save RAM is written while the game runs
+    pub fn set_save_interval(&mut self, interval: Duration) {
+        self.save_interval = interval;
+    }
+
+    pub fn game_name(&self) -> &str {
+        &self.game_name
+    }
+
     pub fn tick(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_fast_forward(false);
+        self.run_frame()
+    }
+
+    // Tell the core when fast-forwarding starts and stops
+    fn set_fast_forward(&mut self, on: bool) {
+        if self.fast_forwarding != on {
+            self.fast_forwarding = on;
+            crate::proxy::libretro::with_proxy(|p| p.set_fast_forward(on));
+        }
+    }
+
+    /// Frames run for each one shown while fast-forwarding, lower while the
+    /// SoC is hot
+    pub fn fast_forward_speed(&self) -> u32 {
+        self.thermal.as_ref().map_or(self.fast_forward_max, |t| {
+            t.fast_forward_cap(self.fast_forward_max)
+        })
+    }
+
+    /// Run as many frames as fit in the time of one, up to the fast-forward
+    /// speed, only drawing the last
+    pub fn fast_forward(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_fast_forward(true);
+        let speed = self.fast_forward_speed();
+        let start = Instant::now();
+        for run in 1..=speed {
+            // Stop once another frame and the drawn one wouldn't both fit
+            let elapsed = start.elapsed();
+            let average = elapsed / (run - 1).max(1);
+            let last = run == speed || (run > 1 && elapsed + average * 2 > self.frame_time);
+            self.hidden = !last;
+            let result = self.run_frame();
+            if last || result.is_err() {
+                self.hidden = false;
+                return result;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_frame(&mut self) -> Result<(), Box<dyn Error>> {
         trace!("Tick core");
+        self.update_frameskip();
+        self.frame_time_callback();
+        self.audio_buffer_status();
+        let start = Instant::now();
         functions::run(&self.lib)?;
+        let run = start.elapsed();
+        let latency = crate::proxy::audio::latency();
+        self.check_audio_latency(latency);
+        self.add_stats(run, latency);
+        self.update_timing();
 
         self.frame_count += 1;
+        if let Some(rewind) = &mut self.rewind {
+            let quick = self.quick_state.as_ref().map_or(0, |s| s.len());
+            rewind.capture(&self.lib, self.frame_time, quick);
+        }
 
-        if (Instant::now() - self.save_time) > SAVE_INTERVAL {
+        if (Instant::now() - self.save_time) > self.save_interval {
             self.do_save(SaveType::Timed);
             self.save_time = Instant::now();
         }
@@ -157,29 +562,354 @@ impl Core {
         Ok(())
     }
 
+    // Tell cores that pace themselves how long it has been since the last
+    // frame, called without the proxy locked in case the core calls back
+    fn frame_time_callback(&mut self) {
+        let now = Instant::now();
+        let last = self.last_run.replace(now);
+        let cb = crate::proxy::libretro::with_proxy(|p| p.frame_time_callback()).flatten();
+        if let Some((f, cb)) = cb.and_then(|cb| cb.callback.map(|f| (f, cb))) {
+            let usec = match last.map(|l| now - l) {
+                Some(d) if d <= self.frame_time * FRAME_GAP_MAX => d.as_micros() as i64,
+                _ => cb.reference,
+            };
+            trace!("Frame time: {} us", usec);
+            unsafe { f(usec) };
+        }
+    }
+
+    // Track the audio latency, so slow combinations of core and audio
+    // settings can be found
+    fn check_audio_latency(&mut self, latency: Duration) {
+        self.peak_latency = self.peak_latency.max(latency);
+        if latency > self.latency_warn && !self.latency_warned {
+            warn!(
+                "Audio latency of {} ms with core '{}' is over {} ms",
+                latency.as_millis(),
+                self.info.name(),
+                self.latency_warn.as_millis()
+            );
+            self.latency_warned = true;
+        }
+    }
+
+    /// Current audio latency, for display
+    pub fn audio_latency(&self) -> Duration {
+        crate::proxy::audio::latency()
+    }
+
+    // Tell cores that skip frames themselves how full the audio queue is
+    fn audio_buffer_status(&mut self) {
+        let cb = crate::proxy::libretro::with_proxy(|p| {
+            p.audio_buffer_status_callback()
+                .map(|cb| (cb, p.audio_enabled()))
+        });
+        if let Some((cb, enabled)) = cb.flatten() {
+            if let Some(status) = crate::proxy::audio::take_status() {
+                self.audio_status = Some(status);
+            }
+            match self.audio_status.filter(|_| enabled) {
+                Some(s) => unsafe { cb(true, s.occupancy, s.underrun_likely) },
+                None => unsafe { cb(false, 0, false) },
+            }
+        }
+    }
+
+    // Follow frame rate changes made by the core through SET_SYSTEM_AV_INFO
+    fn update_timing(&mut self) {
+        if let Some(av) = crate::proxy::libretro::with_proxy(|p| p.take_timing_change()).flatten() {
+            self.frame_time = Duration::from_secs_f64(1.0 / av.timing.fps);
+            debug!("Frame time changed to {:?}", self.frame_time);
+        }
+    }
+
+    // Skip frames while the SoC is hot, letting the user know when this
+    // starts and stops, or while the core can't keep up.
+    fn update_frameskip(&mut self) {
+        let changed = self.thermal.as_mut().and_then(|t| t.update());
+        let skip = self
+            .thermal
+            .as_ref()
+            .map_or(0, |t| t.frameskip())
+            .max(self.frameskip.as_ref().map_or(0, |f| f.frameskip()));
+        let skipped =
+            self.hidden || (skip > 0 && !self.frame_count.is_multiple_of(u64::from(skip + 1)));
+        if skipped != self.skipped {
+            self.skipped = skipped;
+            crate::proxy::libretro::with_proxy(|p| p.set_skip_frame(skipped));
+        }
+        self.skip = skip;
+        if let Some(skipping) = changed {
+            let msg = if skipping {
+                "Running hot, skipping frames"
+            } else {
+                "Cooled down, frame skip off"
+            };
+            let toast = ScreenToast::info(ScreenMessage::Message(String::from(msg)));
+            if self.error_tx.send(Problem::warn(toast)).is_err() {
+                warn!("Failed to send frame skip message");
+            }
+        }
+    }
+
+    /// Step back to the last captured state and run a frame from it, or run
+    /// normally if rewind is disabled.
+    pub fn rewind(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_fast_forward(false);
+        match &mut self.rewind {
+            Some(rewind) => {
+                if !rewind.step_back(&self.lib) {
+                    trace!("Nothing to rewind");
+                }
+                functions::run(&self.lib)?;
+                self.frame_count += 1;
+                Ok(())
+            }
+            None => self.tick(),
+        }
+    }
+
+    /// Library file of the core
+    pub fn core_path(&self) -> &Path {
+        Path::new(self.info.path())
+    }
+
+    pub fn game_path(&self) -> &Path {
+        &self.game_path
+    }
+
     pub fn frame_time(&self) -> Duration {
         self.frame_time
     }
 
+    /// Note how long the last frame took to run and draw, to skip frames
+    /// if the core can't keep up
+    pub fn frame_finished(&mut self, duration: Duration) {
+        // Fast-forwarding fills the frame time on purpose
+        if self.fast_forwarding {
+            return;
+        }
+        if let Some(frameskip) = &mut self.frameskip {
+            frameskip.frame(duration, self.frame_time, !self.skipped);
+        }
+    }
+
+    pub fn frame_dropped(&mut self) {
+        self.dropped += 1;
+        crate::proxy::libretro::with_proxy(|p| p.frame_dropped());
+    }
+
+    // Add the frame just run to the stats, logging a summary every so often
+    fn add_stats(&mut self, run: Duration, audio: Duration) {
+        let second = crate::proxy::libretro::with_proxy(|p| p.end_frame(run, audio)).flatten();
+        if let Some(second) = second {
+            trace!("Frame stats: {}", second.summary());
+            self.log_stats.add(&second);
+            if self.log_stats.elapsed >= STATS_LOG_INTERVAL {
+                info!("Frame stats: {}", self.log_stats.summary());
+                self.log_stats = FrameStats::default();
+            }
+        }
+    }
+
+    // Frames the screen has dropped since it started
+    fn screen_dropped() -> u64 {
+        crate::proxy::libretro::with_proxy(|p| p.borrow_screen().dropped_frames()).unwrap_or(0)
+    }
+
+    // Add this session to the stored statistics for the game
+    fn save_stats(&self) {
+        let session = GameStats {
+            sessions: 1,
+            frames: self.frame_count,
+            seconds: self.start_time.elapsed().as_secs_f64(),
+            dropped: self.dropped,
+            screen_dropped: Self::screen_dropped().wrapping_sub(self.start_screen_dropped),
+            underruns: crate::proxy::audio::underruns()
+                .wrapping_sub(self.start_underruns)
+                .into(),
+            peak_latency_ms: self.peak_latency.as_millis() as u64,
+        };
+        debug!("Session: {}", session.summary());
+        let mut stats = Stats::load(&self.stats_path);
+        stats.add(&self.game_path, &session);
+        stats.save();
+    }
+
+    /// Save the emulator state to memory, replacing any previous quick save.
+    /// The quick save and rewind states share the memory budget, the oldest
+    /// rewind states are dropped to make room.
+    pub fn quick_save(&mut self) -> Result<(), Box<dyn Error>> {
+        let size = functions::serialize_size(&self.lib)?;
+        if size > self.state_budget {
+            error!(
+                "Save state of {} bytes exceeds limit of {} bytes",
+                size, self.state_budget
+            );
+            return Err(Box::new(GamepieError::SaveState));
+        }
+        if let Some(rewind) = &mut self.rewind {
+            rewind.trim(self.state_budget - size);
+        }
+        // The previous quick save's buffer is reused rather than held
+        // alongside the new one
+        let mut state = self.quick_state.take().unwrap_or_default();
+        utils::serialize_into(&self.lib, &mut state)?;
+        info!("Quick saved ({} bytes)", state.len());
+        self.quick_state = Some(state);
+        Ok(())
+    }
+
+    /// Restore the emulator state from the last quick save
+    pub fn quick_load(&mut self) -> Result<(), Box<dyn Error>> {
+        match &self.quick_state {
+            Some(state) => {
+                utils::unserialize_state(&self.lib, state)?;
+                info!("Quick loaded");
+                Ok(())
+            }
+            None => {
+                warn!("No quick save to load");
+                Err(Box::new(GamepieError::SaveState))
+            }
+        }
+    }
+
+    // Hash of the core library, the same as kept by the integrity check
+    fn fingerprint(&self) -> Result<u64, Box<dyn Error>> {
+        if let Some(f) = self.fingerprint.get() {
+            return Ok(*f);
+        }
+        let f = crate::integrity::hash_file(Path::new(self.info.path()))?;
+        Ok(*self.fingerprint.get_or_init(|| f))
+    }
+
+    /// Save the emulator state to the game's state file, after a header
+    /// with the core build and ROM it is for
+    pub fn save_state_file(&self, dirs: &GamepieDirs) -> Result<(), Box<dyn Error>> {
+        let path = dirs
+            .state_file(&self.game_path)
+            .ok_or(GamepieError::SaveState)?;
+        let sys_info = self.info.sys_info();
+        let header = StateHeader::new(
+            &sys_info.library_name,
+            &sys_info.library_version,
+            self.fingerprint()?,
+            self.rom_crc,
+        );
+        let state = utils::serialize_state(&self.lib, self.state_budget)?;
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(&header.to_bytes())?;
+        file.write_all(&state)?;
+        info!(
+            "Saved state to '{}' ({} bytes)",
+            path.display(),
+            state.len()
+        );
+        Ok(())
+    }
+
+    /// Restore the emulator state from the game's state file, if it has one
+    pub fn load_state_file(&mut self, dirs: &GamepieDirs) -> Result<(), Box<dyn Error>> {
+        let path = dirs
+            .state_file(&self.game_path)
+            .ok_or(GamepieError::SaveState)?;
+        if !path.is_file() {
+            return Ok(());
+        }
+        let data = std::fs::read(&path)?;
+        let (header, state) = StateHeader::parse(&data)?;
+        match header {
+            Some(h) if h.fingerprint != self.fingerprint()? => {
+                warn!(
+                    "State '{}' is from {} {}, not this build of the core",
+                    path.display(),
+                    h.core,
+                    h.version
+                );
+                return Err(Box::new(GamepieError::SaveState));
+            }
+            Some(h)
+                if h.rom_crc.is_some() && self.rom_crc.is_some() && h.rom_crc != self.rom_crc =>
+            {
+                warn!("State '{}' is from a different ROM", path.display());
+                return Err(Box::new(GamepieError::SaveState));
+            }
+            Some(_) => {}
+            None => warn!(
+                "State '{}' has no header, loading unchecked",
+                path.display()
+            ),
+        }
+        utils::unserialize_state(&self.lib, state)?;
+        info!("Loaded state from '{}'", path.display());
+        Ok(())
+    }
+
+    /// Whether gameplay is being recorded
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Record what is drawn and played to a video at `path`, until stopped
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let av = crate::proxy::libretro::with_proxy(|p| p.get_av())
+            .flatten()
+            .ok_or(GamepieError::NoVideo)?;
+        self.recorder = Some(Recorder::start(
+            path,
+            av.timing.fps,
+            av.timing.sample_rate as u32,
+        )?);
+        Ok(())
+    }
+
+    /// Stop recording, once the video has been finished
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    /// Write the save now rather than waiting for the game to close, e.g.
+    /// before power is lost
+    pub fn flush_save(&mut self) {
+        self.do_save(SaveType::Full);
+    }
+
+    /// Writes the save from another thread, for the watchdog once the core
+    /// has hung and can't be dropped. The save RAM is read while the core
+    /// is stuck, so it is only as good as what the game last wrote.
+    pub fn hang_save(&self) -> Box<dyn FnOnce() + Send> {
+        let lib = self.lib.clone();
+        let regions = self.save_regions.clone();
+        let save = self.save_path.clone();
+        Box::new(move || {
+            let save = match save {
+                Some(s) => s,
+                None => return,
+            };
+            match utils::has_save_memory(&lib, &regions) {
+                Ok(true) => {
+                    if let Err(e) = utils::save_to_file(&lib, &regions, &save) {
+                        error!("Failed to save after core hung: {}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(_) => warn!("Failed to determine if emulator has save RAM"),
+            }
+        })
+    }
+
     fn do_save(&mut self, kind: SaveType) {
         trace!("Starting save");
         if let Some(save) = &self.save_path {
-            let save = String::from(save);
-            let save = match kind {
-                SaveType::Timed => {
-                    self.save_mod = !self.save_mod;
-                    if self.save_mod {
-                        save + ".0"
-                    } else {
-                        save + ".1"
-                    }
-                }
-                SaveType::Full => save,
+            let save = match (kind, &mut self.timed_saves) {
+                (SaveType::Timed, Some(timed)) => timed.next_path(),
+                _ => String::from(save),
             };
             debug!("Saving data to {}", save);
-            if let Ok(has_save) = utils::has_save_memory(&self.lib) {
+            if let Ok(has_save) = utils::has_save_memory(&self.lib, &self.save_regions) {
                 if has_save {
-                    match utils::save_to_file(&self.lib, &save) {
+                    match utils::save_to_file(&self.lib, &self.save_regions, &save) {
                         Ok(_) => {}
                         Err(_) => error!("Failed to save"),
                     }
@@ -193,14 +923,24 @@ impl Core {
 
 impl Drop for Core {
     fn drop(&mut self) {
+        self.stop_recording();
         self.do_save(SaveType::Full);
+        self.save_stats();
+        crate::proxy::libretro::with_proxy(|p| p.save_options());
         trace!("Dropping core");
         match functions::deinit(&self.lib) {
             Ok(_) => debug!("Unloaded core"),
             Err(e) => warn!("Failed to unload core: {}", e),
         }
+        crate::proxy::libretro::with_proxy(|p| p.set_memory_map(MemoryMap::default()));
 
-        if self.audio.send(AudioMsg::Command(AudioCmd::Stop)).is_err() {
+        // Settings the core asked for don't carry over to the next one
+        let stop = [
+            AudioMsg::Command(AudioCmd::Stop),
+            AudioMsg::Command(AudioCmd::ReportStatus(false)),
+            AudioMsg::Command(AudioCmd::MinLatency(0)),
+        ];
+        if stop.into_iter().any(|m| self.audio.send(m).is_err()) {
             warn!("Error on sending audio stop command");
         }
 
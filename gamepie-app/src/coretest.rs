@@ -0,0 +1,292 @@
+use log::{debug, info};
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::Instant;
+
+use gamepie_controller::{button_from_str, set_external_buttons};
+use gamepie_core::checksum::{hex, sha1};
+use gamepie_core::config::Config;
+use gamepie_core::error::GamepieError;
+use gamepie_core::portable::PString;
+use gamepie_core::problem::Problem;
+use gamepie_libretro::proxy::MAX_PORTS;
+use gamepie_libretrobind::enums::RetroPadButton;
+use gamepie_libretrobind::functions;
+use gamepie_libretrobind::functions::{RetroGameInfo, RetroGameInfoExt};
+use gamepie_screen::{Headless, Screen};
+
+// Frames are drawn as they would be on the ST7789
+const SCREEN_SIZE: u16 = 240;
+
+// Changes to the buttons held, with the frame each is made on
+type Script = Vec<(u32, Vec<RetroPadButton>)>;
+
+// Read an input script. Each line is a frame number and the buttons held
+// from that frame on, e.g. "120 Start" then "125" to let go of it. Lines
+// starting with '#' are comments.
+fn parse_script(text: &str) -> Result<Script, String> {
+    let mut script = Script::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let frame = words
+            .next()
+            .and_then(|f| f.parse::<u32>().ok())
+            .ok_or_else(|| format!("line {}: no frame number", n + 1))?;
+        if script.last().is_some_and(|(last, _)| *last > frame) {
+            return Err(format!("line {}: frame {} is out of order", n + 1, frame));
+        }
+        let buttons = words
+            .map(|w| {
+                button_from_str(w).ok_or_else(|| format!("line {}: unknown button '{}'", n + 1, w))
+            })
+            .collect::<Result<_, _>>()?;
+        script.push((frame, buttons));
+    }
+    Ok(script)
+}
+
+// Hash of a frame drawn to the screen, to compare between runs
+fn frame_hash(frame: &[u16]) -> String {
+    let bytes: Vec<u8> = frame.iter().flat_map(|p| p.to_le_bytes()).collect();
+    hex(&sha1(&bytes))
+}
+
+// What happened while testing a core, printed once the core is unloaded
+#[derive(Default)]
+struct Report {
+    loaded: Option<bool>,
+    frames: u32,
+    fps: f64,
+    sample_rate: f64,
+    run_ms: u128,
+    video_frames: u64,
+    audio_frames: u64,
+    // Hash of the last frame drawn
+    screen: Option<String>,
+    env_calls: Vec<(String, u32, u32)>,
+    problems: Vec<String>,
+}
+
+impl Report {
+    fn print(&self) {
+        match self.loaded {
+            Some(true) => println!("Game: loaded"),
+            Some(false) => println!("Game: failed to load"),
+            None => println!("Game: none given, not run"),
+        }
+        if self.loaded == Some(true) {
+            println!(
+                "Ran {} frames in {} ms (core reports {:.2} fps)",
+                self.frames, self.run_ms, self.fps
+            );
+            println!("Video: {} frames", self.video_frames);
+            println!(
+                "Audio: {} frames (core reports {:.0} Hz)",
+                self.audio_frames, self.sample_rate
+            );
+            match &self.screen {
+                Some(hash) => println!("Screen: {}", hash),
+                None => println!("Screen: nothing drawn"),
+            }
+        }
+        println!("Environment calls:");
+        for (name, calls, supported) in &self.env_calls {
+            println!("  {:<40} x{:<6} {} supported", name, calls, supported);
+        }
+        if !self.problems.is_empty() {
+            println!("Problems:");
+            for p in &self.problems {
+                println!("  {}", p);
+            }
+        }
+    }
+
+    // Whether the core did everything a working core should
+    fn passed(&self) -> bool {
+        let ran = match self.loaded {
+            Some(true) => self.video_frames > 0 && self.audio_frames > 0,
+            Some(false) => false,
+            None => true,
+        };
+        ran && self.problems.is_empty()
+    }
+}
+
+fn run(
+    core: &Path,
+    rom: Option<&Path>,
+    frames: u32,
+    script: &Script,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let lib = functions::load_library(core)?;
+    let sys_info = functions::get_system_info(&lib)?;
+    println!(
+        "Core: {} {} (API {})",
+        sys_info.library_name,
+        sys_info.library_version,
+        functions::api_version(&lib)?
+    );
+    println!("Extensions: {}", sys_info.valid_extensions);
+
+    crate::proxy::functions::retro_set_environment(&lib)?;
+    crate::proxy::functions::retro_set_video_refresh(&lib)?;
+    crate::proxy::functions::retro_set_input_poll(&lib)?;
+    crate::proxy::functions::retro_set_input_state(&lib)?;
+    crate::proxy::functions::retro_set_audio_sample(&lib)?;
+    crate::proxy::functions::retro_set_audio_sample_batch(&lib)?;
+    functions::init(&lib)?;
+
+    if let Some(rom) = rom {
+        let path = rom.to_str().ok_or(GamepieError::String)?;
+        let mut game_info = RetroGameInfo::new(path);
+        if !sys_info.need_fullpath {
+            game_info.read()?;
+        }
+        let ext = RetroGameInfoExt::new(&[&game_info])?;
+        crate::proxy::libretro::with_proxy(|p| p.set_game_info(Some(ext)));
+        let loaded = functions::load_game(&lib, &sys_info, &game_info)?;
+        crate::proxy::libretro::with_proxy(|p| p.set_game_info(None));
+        report.loaded = Some(loaded);
+
+        if loaded {
+            functions::set_controller_port_device(&lib, MAX_PORTS)?;
+            let av = functions::get_system_av_info(&lib)?;
+            crate::proxy::libretro::set_av(av);
+            report.fps = av.timing.fps;
+            report.sample_rate = av.timing.sample_rate;
+
+            debug!("Running {} frames", frames);
+            let start = Instant::now();
+            let mut changes = script.iter().peekable();
+            for frame in 0..frames {
+                while let Some((_, buttons)) = changes.next_if(|(f, _)| *f <= frame) {
+                    set_external_buttons(buttons);
+                }
+                functions::run(&lib)?;
+            }
+            set_external_buttons(&[]);
+            report.run_ms = start.elapsed().as_millis();
+            report.frames = frames;
+        }
+    }
+
+    functions::deinit(&lib)?;
+    Ok(())
+}
+
+/// Load a core without the screen or audio, run it with a game for a number
+/// of frames and print which parts of the libretro API it used, so new core
+/// builds can be checked before they are put on a device. Fails if the core
+/// doesn't load the game or doesn't produce video and audio.
+///
+/// Buttons are pressed as the `input` script says, and frames are drawn to
+/// a screen in memory. The last frame's hash is printed, and if
+/// `expect_screen` is given it must match, for regression tests in CI.
+pub fn test_core(
+    root_dir: &str,
+    config_file: Option<&str>,
+    core: &Path,
+    rom: Option<&Path>,
+    frames: u32,
+    input: Option<&Path>,
+    expect_screen: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let script = match input {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)?;
+            parse_script(&text)
+                .map_err(|e| format!("Invalid input script '{}': {}", path.display(), e))?
+        }
+        None => Script::new(),
+    };
+    let config = Config::load(root_dir, config_file);
+    let dirs = &config.dirs;
+    let sys_dir = PString::from_str(dirs.system().to_str().ok_or(GamepieError::String)?)?;
+    let save_dir = PString::from_str(dirs.save_dir().to_str().ok_or(GamepieError::String)?)?;
+    let (error_tx, errors) = mpsc::channel();
+    // Without creating the audio output first this is a dummy output
+    let audio = crate::proxy::audio::get();
+    let screen = Screen::with_driver(
+        Box::new(Headless::new(SCREEN_SIZE, SCREEN_SIZE)),
+        error_tx.clone(),
+    );
+    crate::proxy::libretro::create_headless(
+        sys_dir,
+        save_dir,
+        Some(screen),
+        error_tx,
+        audio,
+        &config.input,
+    );
+    crate::proxy::libretro::with_proxy(|p| p.count_env_calls());
+
+    info!("Testing core '{}'", core.display());
+    let mut report = Report::default();
+    let result = run(core, rom, frames, &script, &mut report);
+    if let Some((env_calls, video, audio)) = crate::proxy::libretro::with_proxy(|p| {
+        (p.env_call_counts(), p.video_frames(), p.audio_frames())
+    }) {
+        report.env_calls = env_calls;
+        report.video_frames = video;
+        report.audio_frames = audio;
+    }
+    if let Some(screen) = crate::proxy::libretro::with_proxy(|p| p.take_screen()).flatten() {
+        report.screen = screen.last_frame().map(frame_hash);
+    }
+    if let (Some(expected), true) = (expect_screen, report.loaded == Some(true)) {
+        match &report.screen {
+            Some(hash) if hash.eq_ignore_ascii_case(expected) => {}
+            Some(hash) => report
+                .problems
+                .push(format!("screen is {}, expected {}", hash, expected)),
+            None => report
+                .problems
+                .push(format!("nothing drawn, expected screen {}", expected)),
+        }
+    }
+    if let Err(e) = result {
+        report.problems.push(format!("error: {}", e));
+    }
+    report.problems.extend(errors.try_iter().map(|p| match p {
+        Problem::Fatal(e) => format!("fatal: {}", e),
+        Problem::Warn(t) => format!("warning: {}", t.text()),
+    }));
+    report.print();
+
+    if report.passed() {
+        println!("Result: passed");
+        Ok(())
+    } else {
+        println!("Result: failed");
+        Err(format!("'{}' failed the core test", core.display()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_script;
+    use gamepie_libretrobind::enums::RetroPadButton;
+
+    #[test]
+    fn input_script() {
+        let script = parse_script("# title screen\n120 Start\n125\n\n300 A Right\n").unwrap();
+        assert_eq!(
+            script,
+            vec![
+                (120, vec![RetroPadButton::Start]),
+                (125, vec![]),
+                (300, vec![RetroPadButton::A, RetroPadButton::Right]),
+            ]
+        );
+        assert!(parse_script("Start").is_err());
+        assert!(parse_script("10 Turbo").is_err());
+        assert!(parse_script("20 A\n10").is_err());
+    }
+}
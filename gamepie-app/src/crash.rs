@@ -0,0 +1,315 @@
+use log::{error, info, warn, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use gamepie_core::CoreInfo;
+
+// Log lines and state changes kept for crash reports
+const RECENT_LINES: usize = 200;
+const RECENT_STATES: usize = 32;
+// Holds the name of the newest report, until the user has been offered it
+const PENDING_FILE: &str = "pending";
+// Where desktop systems and usbmount mount removable drives
+const REMOVABLE_MOUNTS: &str = "/media/";
+const TAR_BLOCK: usize = 512;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static CONTEXT: Mutex<Option<Context>> = Mutex::new(None);
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+// What was going on, for crash reports
+struct Context {
+    dir: PathBuf,
+    config_file: PathBuf,
+    states: VecDeque<(u64, &'static str)>,
+    core: Option<String>,
+    game: Option<PathBuf>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn with_context<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce(&mut Context) -> T,
+{
+    let mut guard = match CONTEXT.lock() {
+        Ok(g) => g,
+        Err(e) => e.into_inner(),
+    };
+    (*guard).as_mut().map(f)
+}
+
+/// Most recent log lines, oldest first
+pub(crate) fn recent_log() -> Vec<String> {
+    match RECENT.lock() {
+        Ok(lines) => lines.iter().cloned().collect(),
+        Err(e) => e.into_inner().iter().cloned().collect(),
+    }
+}
+
+/// Keep the last error shown, for the diagnostics page
+pub(crate) fn set_last_error(error: String) {
+    match LAST_ERROR.lock() {
+        Ok(mut e) => *e = Some(error),
+        Err(e) => *e.into_inner() = Some(error),
+    }
+}
+
+/// The last error shown, if there has been one
+pub(crate) fn last_error() -> Option<String> {
+    match LAST_ERROR.lock() {
+        Ok(e) => e.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+/// Logger that keeps the most recent lines for crash reports, passing
+/// everything on to another logger
+pub struct RecentLog {
+    inner: Box<dyn Log>,
+}
+
+impl RecentLog {
+    /// Install as the logger in front of `inner`, logging up to `level`
+    pub fn init(inner: Box<dyn Log>, level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(RecentLog { inner }))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for RecentLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            let mut lines = match RECENT.lock() {
+                Ok(g) => g,
+                Err(e) => e.into_inner(),
+            };
+            if lines.len() == RECENT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(format!(
+                "{} {:<5} [{}] {}",
+                now(),
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Start collecting crash reports into `dir`, including on a panic
+pub(crate) fn init(dir: &Path, config_file: &Path) {
+    let mut guard = match CONTEXT.lock() {
+        Ok(g) => g,
+        Err(e) => e.into_inner(),
+    };
+    *guard = Some(Context {
+        dir: dir.to_path_buf(),
+        config_file: config_file.to_path_buf(),
+        states: VecDeque::new(),
+        core: None,
+        game: None,
+    });
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        write_report(&format!("panic: {}", info));
+        default_hook(info);
+    }));
+}
+
+/// Note the state the main loop is in, changes are kept
+pub(crate) fn state(name: &'static str) {
+    with_context(|c| {
+        if c.states.back().is_none_or(|(_, s)| *s != name) {
+            if c.states.len() == RECENT_STATES {
+                c.states.pop_front();
+            }
+            c.states.push_back((now(), name));
+        }
+    });
+}
+
+/// Note the core and game being run, if any
+pub(crate) fn running(core: Option<&CoreInfo>, game: Option<&Path>) {
+    with_context(|c| {
+        c.core = core.map(|i| {
+            let sys = i.sys_info();
+            format!(
+                "{} {} ({})",
+                sys.library_name,
+                sys.library_version,
+                i.path()
+            )
+        });
+        c.game = game.map(Path::to_path_buf);
+    });
+}
+
+// Write a tar archive of text files, which can be read anywhere without
+// gamepie needing an archive library
+fn tar(files: &[(&str, Vec<u8>)], mtime: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, data) in files {
+        let mut header = [0u8; TAR_BLOCK];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, &name.as_bytes()[..name.len().min(99)]);
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", data.len()).as_bytes());
+        field(136, format!("{:011o}\0", mtime).as_bytes());
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        // The checksum is taken with its own field as spaces
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(data);
+        out.resize(out.len().next_multiple_of(TAR_BLOCK), 0);
+    }
+    // Two empty blocks end the archive
+    out.resize(out.len() + TAR_BLOCK * 2, 0);
+    out
+}
+
+/// Gather the recent logs, state changes, running core, configuration and
+/// environment calls into an archive in the crash directory. Returns the
+/// archive, which is left to be offered to the user.
+pub(crate) fn write_report(reason: &str) -> Option<PathBuf> {
+    // A panic while the context is locked can't wait for it
+    let mut guard = match CONTEXT.try_lock() {
+        Ok(g) => g,
+        Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return None,
+    };
+    let context = (*guard).as_mut()?;
+    let time = now();
+
+    let mut report = format!(
+        "{}\ngamepie {}\ntime: {}\n",
+        reason,
+        env!("CARGO_PKG_VERSION"),
+        time
+    );
+    if let Some(core) = &context.core {
+        report.push_str(&format!("core: {}\n", core));
+    }
+    if let Some(game) = &context.game {
+        report.push_str(&format!("game: {}\n", game.display()));
+    }
+    report.push_str("\nstates:\n");
+    for (t, s) in &context.states {
+        report.push_str(&format!("{} {}\n", t, s));
+    }
+    let log = match RECENT.try_lock() {
+        Ok(lines) => lines.iter().map(|l| format!("{}\n", l)).collect(),
+        Err(_) => String::from("log unavailable\n"),
+    };
+    let env = crate::proxy::libretro::try_with_proxy(|p| p.recent_env_calls()).map_or_else(
+        || String::from("environment calls unavailable\n"),
+        |calls| calls.iter().map(|c| format!("{}\n", c)).collect(),
+    );
+    let config = std::fs::read(&context.config_file).unwrap_or_default();
+    let files = [
+        ("report.txt", report.into_bytes()),
+        ("log.txt", log.into_bytes()),
+        ("environment.txt", env.into_bytes()),
+        ("gamepie.toml", config),
+    ];
+
+    let name = format!("crash-{}.tar", time);
+    let path = context.dir.join(&name);
+    let written = std::fs::create_dir_all(&context.dir)
+        .and_then(|_| std::fs::write(&path, tar(&files, time)))
+        .and_then(|_| std::fs::write(context.dir.join(PENDING_FILE), &name));
+    match written {
+        Ok(_) => {
+            error!("Crash report saved to '{}'", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            error!("Failed to save crash report: {}", e);
+            None
+        }
+    }
+}
+
+/// Newest crash report that hasn't been offered to the user
+pub(crate) fn pending(dir: &Path) -> Option<PathBuf> {
+    let name = std::fs::read_to_string(dir.join(PENDING_FILE)).ok()?;
+    let path = dir.join(name.trim());
+    path.is_file().then_some(path)
+}
+
+/// The user has seen the newest report, it is kept in the crash directory
+pub(crate) fn clear_pending(dir: &Path) {
+    if let Err(e) = std::fs::remove_file(dir.join(PENDING_FILE)) {
+        warn!("Failed to clear pending crash report: {}", e);
+    }
+}
+
+/// A mounted removable drive to copy reports to
+pub(crate) fn usb_drive() -> Option<PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|l| l.split_whitespace().nth(1))
+        // Spaces in mount points are escaped
+        .map(|m| m.replace("\\040", " "))
+        .find(|m| m.starts_with(REMOVABLE_MOUNTS))
+        .map(PathBuf::from)
+}
+
+/// Copy a report to a drive, returning where it was copied to
+pub(crate) fn copy_to(report: &Path, drive: &Path) -> std::io::Result<PathBuf> {
+    let name = report.file_name().ok_or(std::io::ErrorKind::InvalidInput)?;
+    let dest = drive.join(name);
+    std::fs::copy(report, &dest)?;
+    info!("Crash report copied to '{}'", dest.display());
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tar, TAR_BLOCK};
+
+    #[test]
+    fn archive() {
+        let files = [("report.txt", b"panic".to_vec()), ("empty", Vec::new())];
+        let archive = tar(&files, 0o17);
+        // Header and a block of data, a header for the empty file, then the
+        // two blocks that end the archive
+        assert_eq!(archive.len(), TAR_BLOCK * 5);
+        assert_eq!(&archive[..10], b"report.txt");
+        assert_eq!(&archive[124..136], b"00000000005\0");
+        assert_eq!(&archive[136..148], b"00000000017\0");
+        assert_eq!(&archive[TAR_BLOCK..TAR_BLOCK + 5], b"panic");
+        // Checksum of the header with the checksum field as spaces
+        let mut header = archive[..TAR_BLOCK].to_vec();
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+        assert_eq!(&archive[148..156], format!("{:06o}\0 ", sum).as_bytes());
+        assert_eq!(&archive[TAR_BLOCK * 2..TAR_BLOCK * 2 + 5], b"empty");
+    }
+}
@@ -0,0 +1,305 @@
+use log::{debug, error, info};
+use sdl2::sys as sdl;
+use sdl2::sys::SDL_Scancode as Key;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use gamepie_core::error::GamepieError;
+use gamepie_libretrobind::enums::RetroPadButton;
+use gamepie_screen::ScreenDriver;
+
+// Same size as the ST7789, shown at a multiple of it to start with
+const WIDTH: u16 = 240;
+const HEIGHT: u16 = 240;
+const SCALE: i32 = 3;
+// The keyboard is polled this often, like the GPIO pad
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// RetroArch's default keyboard controls for player 1
+const KEYS: [(Key, RetroPadButton); 12] = [
+    (Key::SDL_SCANCODE_UP, RetroPadButton::Up),
+    (Key::SDL_SCANCODE_DOWN, RetroPadButton::Down),
+    (Key::SDL_SCANCODE_LEFT, RetroPadButton::Left),
+    (Key::SDL_SCANCODE_RIGHT, RetroPadButton::Right),
+    (Key::SDL_SCANCODE_X, RetroPadButton::A),
+    (Key::SDL_SCANCODE_Z, RetroPadButton::B),
+    (Key::SDL_SCANCODE_S, RetroPadButton::X),
+    (Key::SDL_SCANCODE_A, RetroPadButton::Y),
+    (Key::SDL_SCANCODE_Q, RetroPadButton::L),
+    (Key::SDL_SCANCODE_W, RetroPadButton::R),
+    (Key::SDL_SCANCODE_RETURN, RetroPadButton::Start),
+    (Key::SDL_SCANCODE_RSHIFT, RetroPadButton::Select),
+];
+
+// Buttons held, from SDL's state of each key by scancode
+fn held(keys: &[u8]) -> Vec<RetroPadButton> {
+    KEYS.iter()
+        .filter(|(key, _)| keys.get(*key as usize).is_some_and(|k| *k != 0))
+        .map(|(_, button)| *button)
+        .collect()
+}
+
+fn sdl_error() -> String {
+    // SAFETY: SDL returns a valid C string, empty if there is no error
+    unsafe { CStr::from_ptr(sdl::SDL_GetError()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The window and what draws to it. The video subsystem is used directly, as
+// the audio backend keeps the only context the sdl2 crate allows.
+struct Window {
+    window: *mut sdl::SDL_Window,
+    renderer: *mut sdl::SDL_Renderer,
+    texture: *mut sdl::SDL_Texture,
+}
+
+impl Window {
+    fn open() -> Result<Self, String> {
+        // SAFETY: SDL is only used from this thread for video
+        if unsafe { sdl::SDL_InitSubSystem(sdl::SDL_INIT_VIDEO) } != 0 {
+            return Err(sdl_error());
+        }
+        // Anything created is destroyed on drop if a later step fails
+        let mut w = Window {
+            window: std::ptr::null_mut(),
+            renderer: std::ptr::null_mut(),
+            texture: std::ptr::null_mut(),
+        };
+        let title = CString::new("GamePIE").unwrap();
+        let centred = sdl::SDL_WINDOWPOS_CENTERED_MASK as i32;
+        let (width, height) = (i32::from(WIDTH), i32::from(HEIGHT));
+        // SAFETY: each step checks the last succeeded
+        unsafe {
+            w.window = sdl::SDL_CreateWindow(
+                title.as_ptr(),
+                centred,
+                centred,
+                width * SCALE,
+                height * SCALE,
+                sdl::SDL_WindowFlags::SDL_WINDOW_RESIZABLE as u32,
+            );
+            if w.window.is_null() {
+                return Err(sdl_error());
+            }
+            w.renderer = sdl::SDL_CreateRenderer(w.window, -1, 0);
+            if w.renderer.is_null() {
+                return Err(sdl_error());
+            }
+            // Keeps the aspect ratio when the window is resized
+            sdl::SDL_RenderSetLogicalSize(w.renderer, width, height);
+            w.texture = sdl::SDL_CreateTexture(
+                w.renderer,
+                sdl::SDL_PixelFormatEnum::SDL_PIXELFORMAT_RGB565 as u32,
+                sdl::SDL_TextureAccess::SDL_TEXTUREACCESS_STREAMING as i32,
+                width,
+                height,
+            );
+            if w.texture.is_null() {
+                return Err(sdl_error());
+            }
+        }
+        Ok(w)
+    }
+
+    fn draw(&mut self, frame: &[u16]) {
+        let pitch = i32::from(WIDTH) * 2;
+        // SAFETY: the frame is the size of the texture, as checked in tick
+        unsafe {
+            if sdl::SDL_UpdateTexture(self.texture, std::ptr::null(), frame.as_ptr().cast(), pitch)
+                != 0
+            {
+                debug!("Failed to update window: {}", sdl_error());
+                return;
+            }
+            sdl::SDL_RenderClear(self.renderer);
+            sdl::SDL_RenderCopy(
+                self.renderer,
+                self.texture,
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+            sdl::SDL_RenderPresent(self.renderer);
+        }
+    }
+
+    // Handle window events, setting `request_back` for Escape and
+    // `request_exit` if the window is closed, then the buttons held
+    fn poll(&mut self, request_exit: &AtomicBool, request_back: &AtomicBool) {
+        // SAFETY: SDL_Event is plain data and filled in by SDL, the
+        // keyboard state is valid for as long as SDL is
+        unsafe {
+            let mut event: sdl::SDL_Event = std::mem::zeroed();
+            while sdl::SDL_PollEvent(&mut event) != 0 {
+                if event.type_ == sdl::SDL_EventType::SDL_QUIT as u32 {
+                    info!("Window closed");
+                    request_exit.store(true, Ordering::Release);
+                } else if event.type_ == sdl::SDL_EventType::SDL_KEYDOWN as u32
+                    && event.key.repeat == 0
+                    && event.key.keysym.scancode == Key::SDL_SCANCODE_ESCAPE
+                {
+                    request_back.store(true, Ordering::Release);
+                }
+            }
+            let mut len = 0;
+            let keys = sdl::SDL_GetKeyboardState(&mut len);
+            let keys = std::slice::from_raw_parts(keys, len.max(0) as usize);
+            gamepie_controller::set_external_buttons(&held(keys));
+        }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        // SAFETY: only what was created is destroyed, in reverse order
+        unsafe {
+            if !self.texture.is_null() {
+                sdl::SDL_DestroyTexture(self.texture);
+            }
+            if !self.renderer.is_null() {
+                sdl::SDL_DestroyRenderer(self.renderer);
+            }
+            if !self.window.is_null() {
+                sdl::SDL_DestroyWindow(self.window);
+            }
+            sdl::SDL_QuitSubSystem(sdl::SDL_INIT_VIDEO);
+        }
+    }
+}
+
+fn run(
+    opened: mpsc::Sender<Result<(), String>>,
+    frames: mpsc::Receiver<Vec<u16>>,
+    request_exit: Arc<AtomicBool>,
+    request_back: Arc<AtomicBool>,
+) {
+    let mut window = match Window::open() {
+        Ok(w) => w,
+        Err(e) => {
+            let _ = opened.send(Err(e));
+            return;
+        }
+    };
+    let _ = opened.send(Ok(()));
+    loop {
+        match frames.recv_timeout(POLL_INTERVAL) {
+            Ok(frame) => window.draw(&frame),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // The screen has been released
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        window.poll(&request_exit, &request_back);
+    }
+    gamepie_controller::set_external_buttons(&[]);
+    debug!("Window thread finished");
+}
+
+/// Desktop window in place of the screen, with the keyboard as player 1's
+/// RetroPad, for developing gamepie on a PC. SDL runs on a thread of its
+/// own so the keyboard is read while a game is drawing.
+pub(crate) struct SdlWindow {
+    frames: Option<mpsc::SyncSender<Vec<u16>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SdlWindow {
+    pub fn open(
+        request_exit: Arc<AtomicBool>,
+        request_back: Arc<AtomicBool>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (opened_tx, opened) = mpsc::channel();
+        // Frames are dropped rather than queued if the window falls behind
+        let (frames, frames_rx) = mpsc::sync_channel(1);
+        let thread = std::thread::Builder::new()
+            .name(String::from("window"))
+            .spawn(move || run(opened_tx, frames_rx, request_exit, request_back))?;
+        match opened.recv() {
+            Ok(Ok(())) => {
+                info!("Using a {}x{} desktop window", WIDTH, HEIGHT);
+                Ok(SdlWindow {
+                    frames: Some(frames),
+                    thread: Some(thread),
+                })
+            }
+            Ok(Err(e)) => {
+                error!("Can't open window: {}", e);
+                Err(Box::new(GamepieError::NoVideo))
+            }
+            Err(_) => {
+                error!("Window thread stopped");
+                Err(Box::new(GamepieError::NoVideo))
+            }
+        }
+    }
+}
+
+impl ScreenDriver for SdlWindow {
+    fn width(&self) -> u16 {
+        WIDTH
+    }
+
+    fn height(&self) -> u16 {
+        HEIGHT
+    }
+
+    fn tick(&mut self, data: &[u16], _force_full: bool) -> std::io::Result<()> {
+        // SDL reads a whole texture from the frame
+        if data.len() != usize::from(WIDTH) * usize::from(HEIGHT) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("frame of {} pixels isn't {}x{}", data.len(), WIDTH, HEIGHT),
+            ));
+        }
+        let sent = match &self.frames {
+            Some(frames) => frames.try_send(data.to_vec()),
+            None => return Ok(()),
+        };
+        match sent {
+            Ok(()) | Err(mpsc::TrySendError::Full(_)) => Ok(()),
+            Err(mpsc::TrySendError::Disconnected(_)) => Err(std::io::Error::new(
+                ErrorKind::BrokenPipe,
+                "window thread stopped",
+            )),
+        }
+    }
+}
+
+impl Drop for SdlWindow {
+    fn drop(&mut self) {
+        self.frames = None;
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("Window thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{held, Key};
+    use gamepie_libretrobind::enums::RetroPadButton;
+
+    #[test]
+    fn keys() {
+        let mut keys = [0u8; 512];
+        keys[Key::SDL_SCANCODE_LEFT as usize] = 1;
+        keys[Key::SDL_SCANCODE_X as usize] = 1;
+        keys[Key::SDL_SCANCODE_RETURN as usize] = 1;
+        keys[Key::SDL_SCANCODE_P as usize] = 1;
+        assert_eq!(
+            held(&keys),
+            vec![
+                RetroPadButton::Left,
+                RetroPadButton::A,
+                RetroPadButton::Start
+            ]
+        );
+        assert!(held(&[]).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use log::{debug, info, warn};
+use rppal::gpio::OutputPin;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use gamepie_core::commands::{FeedbackMsg, FeedbackSource, RumbleEffect};
+use gamepie_core::config::FeedbackConfig;
+
+const PWM_FREQUENCY: f64 = 200.0;
+// Piezo tone, the duty cycle sets the volume
+const PIEZO_FREQUENCY: f64 = 2000.0;
+// Longest wait for a request, so the thread notices when it should stop
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Length of the pulse for moving through a menu
+pub const MENU_PULSE: Duration = Duration::from_millis(15);
+/// Length of the pulse for a toast or error
+pub const NOTIFICATION_PULSE: Duration = Duration::from_millis(120);
+
+// An output and the level (0-1) it was last set to
+struct Actuator {
+    name: &'static str,
+    pin: OutputPin,
+    frequency: f64,
+    // Largest duty cycle, a piezo is loudest at half
+    max_duty: f64,
+    level: f32,
+}
+
+impl Actuator {
+    fn new(
+        gpio: &rppal::gpio::Gpio,
+        name: &'static str,
+        pin: Option<u8>,
+        frequency: f64,
+        max_duty: f64,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        match pin {
+            Some(p) => {
+                info!("Feedback {} on pin {}", name, p);
+                Ok(Some(Actuator {
+                    name,
+                    pin: gpio.get(p)?.into_output_low(),
+                    frequency,
+                    max_duty,
+                    level: 0.0,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, level: f32) {
+        if level == self.level {
+            return;
+        }
+        let result = if level <= 0.0 {
+            self.pin.clear_pwm().map(|_| self.pin.set_low())
+        } else {
+            let duty = level as f64 * self.max_duty;
+            self.pin.set_pwm_frequency(self.frequency, duty)
+        };
+        match result {
+            Ok(_) => self.level = level,
+            Err(e) => warn!("Failed to set feedback {}: {}", self.name, e),
+        }
+    }
+}
+
+/// Start the thread that drives the feedback actuators, requests are sent
+/// through `proxy::feedback`. Nothing is started if there are no
+/// actuators configured.
+pub(crate) fn start(config: &FeedbackConfig, running: Arc<AtomicBool>) {
+    if !config.any() {
+        debug!("No feedback actuators");
+        return;
+    }
+    let gpio = match rppal::gpio::Gpio::new() {
+        Ok(g) => g,
+        Err(e) => {
+            warn!("Feedback unavailable: {}", e);
+            return;
+        }
+    };
+    let actuators = [
+        Actuator::new(&gpio, "motor", config.motor, PWM_FREQUENCY, 1.0),
+        Actuator::new(&gpio, "piezo", config.piezo, PIEZO_FREQUENCY, 0.5),
+        Actuator::new(&gpio, "LED", config.led, PWM_FREQUENCY, 1.0),
+    ];
+    let [motor, piezo, led] = match actuators {
+        [Ok(m), Ok(p), Ok(l)] => [m, p, l],
+        _ => {
+            warn!("Feedback unavailable, failed to get actuator pins");
+            return;
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    crate::proxy::feedback::set(tx);
+    let config = config.clone();
+    std::thread::spawn(move || {
+        let mut motor = motor;
+        let mut piezo = piezo;
+        let mut led = led;
+        // Strong and weak rumble requested by the core
+        let mut rumble = [0.0; 2];
+        // Level and end of the current pulse
+        let mut pulse: Option<(f32, Instant)> = None;
+
+        while running.load(Ordering::Acquire) {
+            let timeout = match pulse {
+                Some((_, end)) => end
+                    .saturating_duration_since(Instant::now())
+                    .min(IDLE_TIMEOUT),
+                None => IDLE_TIMEOUT,
+            };
+            match rx.recv_timeout(timeout) {
+                Ok(FeedbackMsg::Rumble(effect, strength)) => {
+                    let i = match effect {
+                        RumbleEffect::Strong => 0,
+                        RumbleEffect::Weak => 1,
+                    };
+                    rumble[i] = strength * config.intensity(FeedbackSource::Rumble);
+                }
+                Ok(FeedbackMsg::Pulse(source, length)) => {
+                    let level = config.intensity(source);
+                    let end = Instant::now() + length;
+                    // A stronger pulse isn't cut short by a weaker one
+                    pulse = match pulse {
+                        Some((l, e)) if l > level && e > end => Some((l, e)),
+                        _ if level > 0.0 => Some((level, end)),
+                        p => p,
+                    };
+                }
+                Ok(FeedbackMsg::Stop) => {
+                    rumble = [0.0; 2];
+                    pulse = None;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if matches!(pulse, Some((_, end)) if end <= Instant::now()) {
+                pulse = None;
+            }
+
+            let pulse_level = pulse.map_or(0.0, |(l, _)| l);
+            if let Some(m) = motor.as_mut() {
+                m.set(rumble[0].max(rumble[1]).max(pulse_level));
+            }
+            if let Some(p) = piezo.as_mut() {
+                p.set(pulse_level);
+            }
+            if let Some(l) = led.as_mut() {
+                l.set(pulse_level);
+            }
+        }
+
+        for a in [&mut motor, &mut piezo, &mut led].into_iter().flatten() {
+            a.set(0.0);
+        }
+        debug!("Feedback thread finished");
+    });
+}
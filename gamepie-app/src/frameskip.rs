@@ -0,0 +1,147 @@
+use log::{info, trace};
+use std::time::Duration;
+
+// Frames looked at before changing how many are skipped
+const WINDOW: u32 = 30;
+// Skip more when this many frames in a window are late
+const LATE_LIMIT: u32 = WINDOW / 4;
+// Skip fewer only if the frames would then use no more than this share of
+// the time, so it doesn't flip back and forth
+const HEADROOM: f64 = 0.8;
+
+/// Skips drawing some frames while the core can't keep up, so the audio
+/// carries on without gaps. The core is still run every frame, and is told
+/// video is off for the skipped ones so it can skip rendering too.
+pub(crate) struct FrameSkip {
+    max: u32,
+    skip: u32,
+    frames: u32,
+    late: u32,
+    drawn: (u32, Duration),
+    skipped: (u32, Duration),
+}
+
+impl FrameSkip {
+    pub fn new(max: u32) -> Self {
+        FrameSkip {
+            max,
+            skip: 0,
+            frames: 0,
+            late: 0,
+            drawn: (0, Duration::ZERO),
+            skipped: (0, Duration::ZERO),
+        }
+    }
+
+    /// Frames to skip after each frame drawn
+    pub fn frameskip(&self) -> u32 {
+        self.skip
+    }
+
+    // Time a frame would take on average skipping `skip` frames for each
+    // drawn, from the frames of this window
+    fn estimate(&self, skip: u32) -> Option<Duration> {
+        let drawn = self.drawn.1.checked_div(self.drawn.0)?;
+        let skipped = self.skipped.1.checked_div(self.skipped.0).unwrap_or(drawn);
+        let extra = drawn.saturating_sub(skipped);
+        Some(skipped + extra / (skip + 1))
+    }
+
+    /// Add a frame that took `duration` to run and, if `drawn`, draw.
+    /// Returns the new frameskip if it has changed.
+    pub fn frame(&mut self, duration: Duration, frame_time: Duration, drawn: bool) -> Option<u32> {
+        self.frames += 1;
+        if duration > frame_time {
+            self.late += 1;
+        }
+        let (count, total) = if drawn {
+            &mut self.drawn
+        } else {
+            &mut self.skipped
+        };
+        *count += 1;
+        *total += duration;
+        if self.frames < WINDOW {
+            return None;
+        }
+
+        let skip = if self.late >= LATE_LIMIT {
+            (self.skip + 1).min(self.max)
+        } else if self.skip > 0
+            && self.late == 0
+            && self
+                .estimate(self.skip - 1)
+                .is_some_and(|t| t.as_secs_f64() <= frame_time.as_secs_f64() * HEADROOM)
+        {
+            self.skip - 1
+        } else {
+            self.skip
+        };
+        trace!(
+            "Frameskip window: {} late, estimate {:?}",
+            self.late,
+            self.estimate(skip)
+        );
+        *self = FrameSkip {
+            skip: self.skip,
+            ..FrameSkip::new(self.max)
+        };
+        if skip == self.skip {
+            return None;
+        }
+        if skip > 0 {
+            info!("Can't keep up, skipping {} frames in {}", skip, skip + 1);
+        } else {
+            info!("Caught up, no longer skipping frames");
+        }
+        self.skip = skip;
+        Some(skip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameSkip, WINDOW};
+    use std::time::Duration;
+
+    #[test]
+    fn adapts() {
+        let frame_time = Duration::from_micros(16_667);
+        let ms = Duration::from_millis;
+        let mut skip = FrameSkip::new(2);
+        // Keeping up
+        for _ in 0..WINDOW {
+            assert_eq!(skip.frame(ms(10), frame_time, true), None);
+        }
+        // Drawing takes too long
+        for _ in 1..WINDOW {
+            assert_eq!(skip.frame(ms(20), frame_time, true), None);
+        }
+        assert_eq!(skip.frame(ms(20), frame_time, true), Some(1));
+        // Skipped frames are quick, but drawing every frame would still be
+        // too slow
+        for n in 0..WINDOW {
+            skip.frame(
+                if n % 2 == 0 { ms(16) } else { ms(8) },
+                frame_time,
+                n % 2 == 0,
+            );
+        }
+        assert_eq!(skip.frameskip(), 1);
+        // Drawing got quicker
+        for n in 1..WINDOW {
+            skip.frame(
+                if n % 2 == 0 { ms(11) } else { ms(5) },
+                frame_time,
+                n % 2 == 0,
+            );
+        }
+        assert_eq!(skip.frame(ms(5), frame_time, false), Some(0));
+        // No more than the max
+        let mut skip = FrameSkip::new(1);
+        for _ in 0..WINDOW * 3 {
+            skip.frame(ms(30), frame_time, true);
+        }
+        assert_eq!(skip.frameskip(), 1);
+    }
+}
@@ -1,131 +1,484 @@
 use log::{debug, error, info, trace, warn};
 use rppal::system::DeviceInfo;
+use std::collections::VecDeque;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{mpsc, Arc};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-use gamepie_core::commands::{AudioCmd, AudioMsg, ScreenToast};
+use gamepie_core::commands::{
+    AudioCmd, AudioMsg, FeedbackMsg, FeedbackSource, ScreenMessage, ScreenToast,
+};
+use gamepie_core::config::{
+    AudioBackend, AutosaveEvent, BootChime, Config, EncoderControl, CONFIG_FILE,
+};
 use gamepie_core::error::GamepieError;
+use gamepie_core::history::History;
+use gamepie_core::metadata;
 use gamepie_core::portable::PString;
 use gamepie_core::problem::Problem;
+use gamepie_core::settings::Settings;
+use gamepie_core::stats::Stats;
 use gamepie_core::{
-    CoreInfo, BUTTON_BLANK_DURATION, EMU_PATH, ERROR_DURATION, MENU_FRAME_DURATION,
-    SPLASH_DURATION, SYS_PATH,
+    CoreInfo, BUTTON_BLANK_DURATION, ERROR_DURATION, INFO_COLOUR, MENU_FRAME_DURATION,
+    SPLASH_DURATION,
 };
+use gamepie_libretro::requirements::get_requirements;
+use gamepie_libretrobind::bind::{
+    RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_ID_ANALOG_Y, RETRO_DEVICE_INDEX_ANALOG_LEFT,
+    RETRO_DEVICE_INDEX_ANALOG_RIGHT,
+};
+use gamepie_libretrobind::elf::ElfError;
 use gamepie_libretrobind::enums::RetroPadButton;
 use gamepie_libretrobind::functions::{
     api_version, frontend_api_version, get_system_info, load_library,
 };
-use gamepie_screen::{Menu, MenuSel, Screen};
+use gamepie_screen::{
+    move_cursor, Key, Menu, MenuSel, Move, PadView, PauseItem, PowerItem, Screen, ScreenHandle,
+    PAUSE_ITEMS, POWER_ITEMS,
+};
 
 use crate::core::Core;
+use crate::feedback::MENU_PULSE;
+use crate::governor::Governor;
+use crate::gpio::{LightEdge, PAD_POLL_INTERVAL};
+use crate::hotkey::{Hotkey, Hotkeys};
+use crate::integrity::CoreRecords;
+use crate::latency::LatencyTest;
+use crate::library::LibraryScanner;
+use crate::loading::Loading;
+use crate::mqtt::{Mqtt, MqttEvent};
+use crate::netplay::Netplay;
+use crate::notify::Notifier;
+use crate::party::Party;
+use crate::pipe::PipeCommand;
+use crate::plugin::{Plugin, Plugins};
+use crate::preload::Preloader;
+use crate::recovery::{Boot, ConfigBackup};
+use crate::slots::SaveSlot;
+use crate::storage::Storage;
+use crate::watchdog::{Quarantine, Watchdog};
+use crate::watcher::RomWatcher;
+
+// Raw events kept for the input tester
+const INPUT_TEST_EVENTS: usize = 64;
+// Recently played games shown at the top of the game list
+const RECENT_GAMES: usize = 5;
+// Party countdown messages are shown every second near the end of a turn
+const PARTY_TOAST_DURATION: Duration = Duration::from_millis(900);
+
+// Longest text typed into a game at once
+const TYPING_MAX: usize = 32;
 
 // Function to get an Ok value with an explicit error type
 fn ok_res() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Hold a direction this long before the menu starts scrolling
+const REPEAT_DELAY: Duration = Duration::from_millis(400);
+const REPEAT_FAST: Duration = Duration::from_millis(1500);
+const REPEAT_FASTEST: Duration = Duration::from_millis(3000);
+
+// Time between moves while a direction is held, which speeds up the longer it
+// is held. None until it has been held long enough to repeat.
+fn repeat_interval(held: Duration) -> Option<Duration> {
+    if held < REPEAT_DELAY {
+        None
+    } else if held < REPEAT_FAST {
+        Some(Duration::from_millis(150))
+    } else if held < REPEAT_FASTEST {
+        Some(Duration::from_millis(60))
+    } else {
+        // Every frame
+        Some(Duration::ZERO)
+    }
+}
+
+// Autosave intervals the pause menu cycles through, in seconds
+const AUTOSAVE_CHOICES: [u64; 4] = [30, 60, 120, 300];
+
+// Next autosave interval longer than the current one, back to the shortest
+// after the longest
+fn next_autosave(current: Duration) -> Duration {
+    let next = AUTOSAVE_CHOICES
+        .iter()
+        .find(|s| Duration::from_secs(**s) > current)
+        .unwrap_or(&AUTOSAVE_CHOICES[0]);
+    Duration::from_secs(*next)
+}
+
+// RetroPad buttons read by the on-screen keyboard
+const KEYBOARD_BUTTONS: [RetroPadButton; 7] = [
+    RetroPadButton::Up,
+    RetroPadButton::Down,
+    RetroPadButton::Left,
+    RetroPadButton::Right,
+    RetroPadButton::A,
+    RetroPadButton::B,
+    RetroPadButton::Start,
+];
+
+// What a press on the on-screen keyboard did
+enum KeyboardInput {
+    // Nothing typed, the cursor may have moved
+    Cursor(MenuState),
+    Key(Key),
+    // B or back, which deletes or leaves if there is nothing to delete
+    Back,
+}
+
+// Work out what the buttons held (in the order of KEYBOARD_BUTTONS) do on the
+// on-screen keyboard. A button must be released before the next press.
+fn keyboard_input(state: &MenuState, pressed: [bool; 7], back: bool) -> KeyboardInput {
+    let any = pressed.iter().any(|p| *p);
+    if state.pressed && !back {
+        return KeyboardInput::Cursor(MenuState::new(state.index, any));
+    }
+    let [up, down, left, right, a, b, start] = pressed;
+    if back || b {
+        KeyboardInput::Back
+    } else if start {
+        KeyboardInput::Key(Key::Done)
+    } else if a {
+        KeyboardInput::Key(Key::at(state.index))
+    } else {
+        let cursor = [
+            (up, Move::Up),
+            (down, Move::Down),
+            (left, Move::Left),
+            (right, Move::Right),
+        ]
+        .into_iter()
+        .find(|(p, _)| *p)
+        .map_or(state.index, |(_, m)| move_cursor(state.index, m));
+        KeyboardInput::Cursor(MenuState::new(cursor, any))
+    }
+}
+
 struct MenuState {
     pub index: usize,
     pub pressed: bool,
+    // When a direction was first held and when it last moved, while held
+    pub held: Option<(Instant, Instant)>,
 }
 
 impl MenuState {
     fn new(index: usize, pressed: bool) -> Self {
-        Self { index, pressed }
+        Self {
+            index,
+            pressed,
+            held: None,
+        }
+    }
+
+    // Next state after moving to index, keeping any held direction
+    fn moved(index: usize, info: &MenuInfo) -> Self {
+        Self {
+            index,
+            pressed: info.new_pressed,
+            held: info.held,
+        }
     }
 }
 
 impl Default for MenuState {
     fn default() -> Self {
-        Self {
-            index: 0,
-            pressed: true,
-        }
+        Self::new(0, true)
     }
 }
 
 enum GamepieState {
     /// Initial state
     Init,
+    /// Splash screen shown (time to move on to the menu)
+    Splash(Instant),
     /// Select a game (current index, button was pressed)
     SelectGame(MenuState),
+    /// Search for a game (on-screen keyboard cursor, button was pressed)
+    Search(MenuState),
+    /// Controller input tester (game index to return to, recent raw events)
+    InputTest(usize, VecDeque<String>),
+    /// Input-to-display latency test (game index to return to, measurements)
+    LatencyTest(usize, LatencyTest),
+    /// Select a version of a game (game index, current index, button was pressed)
+    SelectVariant(usize, MenuState),
     /// Start a game (path to game, current index, button was pressed, game index)
     StartGame(String, usize, MenuState),
+    /// Loading a game on a worker thread (game being loaded)
+    Loading(Box<Loading>),
     /// Running game (loaded core)
     Game(Box<Core>),
+    /// Game paused with a menu over it (loaded core, current index, button
+    /// was pressed)
+    Pause(Box<Core>, MenuState),
     /// Exit game
     ExitGame,
     /// Got an error (error)
     Error(GamepieError),
+    /// Error shown on screen (time to go back to the start)
+    ErrorShown(Instant),
+    /// Offer to copy a crash report (report, button was pressed)
+    CrashReport(PathBuf, MenuState),
+    /// Choose a save to restore while paused (loaded core, saves, current
+    /// index, button was pressed)
+    SaveSlots(Box<Core>, Vec<SaveSlot>, MenuState),
+    /// Type into a paused game with the on-screen keyboard (loaded core, text
+    /// typed, keyboard cursor and button was pressed)
+    Typing(Box<Core>, String, MenuState),
+    /// Safe mode recovery menu (options, current index, button was pressed)
+    Recovery(Vec<RecoveryItem>, MenuState),
+    /// Shutdown or reboot (game index to return to, current index, button
+    /// was pressed)
+    Power(usize, MenuState),
+    /// Screen added by a plugin (game index to return to, screen, current
+    /// index, button was pressed)
+    PluginScreen(usize, usize, MenuState),
+}
+
+impl GamepieState {
+    // Name of the state for crash reports
+    fn name(&self) -> &'static str {
+        match self {
+            GamepieState::Init => "Init",
+            GamepieState::Splash(_) => "Splash",
+            GamepieState::SelectGame(_) => "SelectGame",
+            GamepieState::Search(_) => "Search",
+            GamepieState::InputTest(..) => "InputTest",
+            GamepieState::LatencyTest(..) => "LatencyTest",
+            GamepieState::SelectVariant(..) => "SelectVariant",
+            GamepieState::StartGame(..) => "StartGame",
+            GamepieState::Loading(_) => "Loading",
+            GamepieState::Game(_) => "Game",
+            GamepieState::Pause(..) => "Pause",
+            GamepieState::ExitGame => "ExitGame",
+            GamepieState::Error(_) => "Error",
+            GamepieState::ErrorShown(_) => "ErrorShown",
+            GamepieState::CrashReport(..) => "CrashReport",
+            GamepieState::SaveSlots(..) => "SaveSlots",
+            GamepieState::Typing(..) => "Typing",
+            GamepieState::Power(..) => "Power",
+            GamepieState::PluginScreen(..) => "PluginScreen",
+            GamepieState::Recovery(..) => "Recovery",
+        }
+    }
+}
+
+// Options in the recovery menu, shown when starting in safe mode
+enum RecoveryItem {
+    // Carry on to the game list in safe mode
+    Continue,
+    // Exit to be restarted by the service, without safe mode
+    Restart,
+    Restore(ConfigBackup),
+    // Remove the settings changed from the menus
+    ResetSettings,
+    Shutdown,
+}
+
+impl RecoveryItem {
+    fn label(&self) -> &'static str {
+        match self {
+            RecoveryItem::Continue => "Continue in safe mode",
+            RecoveryItem::Restart => "Restart normally",
+            RecoveryItem::Restore(_) => "Restore config",
+            RecoveryItem::ResetSettings => "Reset settings",
+            RecoveryItem::Shutdown => "Shutdown",
+        }
+    }
+}
+
+// Run once gamepie has exited and the screen has been released
+#[derive(Clone, Copy, Debug)]
+enum PowerAction {
+    Off,
+    Reboot,
+}
+
+impl PowerAction {
+    fn run(self) {
+        let (verb, fallback) = match self {
+            PowerAction::Off => ("poweroff", "/sbin/poweroff"),
+            PowerAction::Reboot => ("reboot", "/sbin/reboot"),
+        };
+        info!("Running {}", verb);
+        // Make sure saves are on the SD card before the power goes
+        unsafe { libc::sync() };
+        match std::process::Command::new("systemctl").arg(verb).status() {
+            Ok(s) if s.success() => return,
+            Ok(s) => error!("systemctl {} failed: {}", verb, s),
+            Err(e) => error!("Failed to run systemctl {}: {}", verb, e),
+        }
+        match std::process::Command::new(fallback).status() {
+            Ok(s) if s.success() => {}
+            Ok(s) => error!("{} failed: {}", fallback, s),
+            Err(e) => error!("Failed to run {}: {}", fallback, e),
+        }
+    }
 }
 
 pub struct Gamepie {
-    root_dir: PString,
+    config: Config,
     cores: Vec<CoreInfo>,
     menu: Menu,
     state: Option<GamepieState>,
     // Request exit is sticky, request back gets cleared
     request_exit: Arc<AtomicBool>,
     request_back: Arc<AtomicBool>,
+    // Screen and backlight off while a game keeps running
+    screen_off: Arc<AtomicBool>,
+    // Set by the GPIO thread when the battery becomes low
+    low_battery: Arc<AtomicBool>,
+    // Power off or reboot once gamepie has exited
+    power: Option<PowerAction>,
     running: Arc<AtomicBool>,
     gpio_thread: Option<JoinHandle<()>>,
+    // Photodiode for the latency test
+    light_edge: Option<LightEdge>,
+    // In a desktop window rather than on the Pi
+    desktop: bool,
     error_channel: mpsc::Receiver<Problem>,
     error_tx: mpsc::Sender<Problem>,
-    screen: Option<Screen>,
-    toast_tx: mpsc::Sender<ScreenToast>,
+    screen: ScreenHandle,
+    notifier: Notifier,
+    hotkeys: Hotkeys,
+    plugins: Plugins,
+    mqtt: Option<Mqtt>,
+    netplay: Option<Netplay>,
+    library: Option<LibraryScanner>,
+    rom_watcher: Option<RomWatcher>,
+    // Whether a game was running at the end of the last loop
+    playing: bool,
+    // Commands from the command pipe and HTTP server
+    commands: mpsc::Receiver<PipeCommand>,
+    command_tx: mpsc::Sender<PipeCommand>,
+    // Port of the HTTP server, linked to from error screens
+    http_port: Option<u16>,
+    // Games are found again once the game list is shown
+    rescan_pending: bool,
+    trace_env: bool,
+    stats: Stats,
+    history: History,
+    settings: Settings,
+    party: Option<Party>,
+    storage: Storage,
+    watchdog: Option<Watchdog>,
+    governor: Option<Governor>,
+    preload: Preloader,
+    boot: Boot,
+    // Started in safe mode, and the recovery menu is still to be shown
+    safe: bool,
+    recovery_pending: bool,
 }
 
 struct MenuInfo {
     pub start_game: bool,
     pub back: bool,
+    pub input_test: bool,
+    // Mark the game for party mode
+    pub mark: bool,
+    pub search: bool,
+    pub favourite: bool,
     pub unsafe_index: usize,
     pub new_pressed: bool,
+    pub held: Option<(Instant, Instant)>,
+    // Show the next (true) or previous system in the game list
+    pub switch_system: Option<bool>,
 }
 
 impl Gamepie {
-    fn try_load_core(path: std::fs::DirEntry) -> Result<CoreInfo, ()> {
+    // The error holds a message to show if the file is a core built for
+    // another system, other files are only logged.
+    fn try_load_core(path: std::fs::DirEntry) -> Result<CoreInfo, Option<String>> {
         trace!("Trying to load core: {}", path.path().display());
 
-        if let Ok(lib) = load_library(path.path()) {
-            if let Ok(info) = get_system_info(&lib) {
-                debug!(
-                    "Found Core '{} ({})'",
-                    info.library_name, info.library_version
-                );
-                if let Ok(api_ver) = api_version(&lib) {
-                    let exp_ver = frontend_api_version();
-                    if api_ver == exp_ver {
-                        let core = CoreInfo::new(path, info);
-                        debug!("  Supported extensions \"{}\"", core.extensions_str());
-                        return Ok(core);
-                    } else {
-                        warn!(
-                            "Frontend APIv{} doesn't match Core APIv{}",
-                            exp_ver, api_ver
-                        );
-                    }
+        let lib = match load_library(path.path()) {
+            Ok(lib) => lib,
+            Err(e) => {
+                let file = path.file_name();
+                let file = file.to_string_lossy();
+                warn!("Failed to load core '{}': {}", file, e);
+                return match e.downcast_ref::<ElfError>() {
+                    Some(e) if e.is_mismatch() => Err(Some(format!("'{}': {}", file, e))),
+                    _ => Err(None),
+                };
+            }
+        };
+        if let Ok(info) = get_system_info(&lib) {
+            debug!(
+                "Found Core '{} ({})'",
+                info.library_name, info.library_version
+            );
+            if let Ok(api_ver) = api_version(&lib) {
+                let exp_ver = frontend_api_version();
+                if api_ver == exp_ver {
+                    let core = CoreInfo::new(path, info);
+                    debug!("  Supported extensions \"{}\"", core.extensions_str());
+                    return Ok(core);
+                } else {
+                    warn!(
+                        "Frontend APIv{} doesn't match Core APIv{}",
+                        exp_ver, api_ver
+                    );
                 }
             }
         }
 
-        Err(())
+        Err(None)
     }
 
-    fn find_cores(root_dir: &str) -> Vec<CoreInfo> {
+    // Find the cores that can be loaded, and messages for any that have
+    // changed since they were last seen, were built for another system or
+    // are quarantined after hanging. With `verified_only` cores that are new
+    // or have changed aren't loaded.
+    fn find_cores(
+        emu_dir: &Path,
+        records: &mut CoreRecords,
+        quarantine: &mut Quarantine,
+        verified_only: bool,
+    ) -> (Vec<CoreInfo>, Vec<String>) {
         trace!("Finding cores");
         let mut cores = Vec::new();
+        let mut messages = Vec::new();
 
-        match std::fs::read_dir(Path::new(root_dir).join(EMU_PATH)) {
+        match std::fs::read_dir(emu_dir) {
             Ok(paths) => {
                 for path in paths {
                     match path {
                         Ok(path) => {
-                            if let Ok(c) = Self::try_load_core(path) {
-                                cores.push(c);
+                            let known = records.contains(&path.path());
+                            let core_changed = records.check(&path.path());
+                            if verified_only && (!known || core_changed) {
+                                warn!("Core '{}' not verified", path.path().display());
+                                messages.push(format!(
+                                    "Core '{}' not verified, not loaded",
+                                    path.file_name().to_string_lossy()
+                                ));
+                                continue;
+                            }
+                            if core_changed {
+                                // A new build may have fixed the hang
+                                quarantine.release(&path.path());
+                            } else if quarantine.contains(&path.path()) {
+                                warn!("Core '{}' is quarantined", path.path().display());
+                                messages.push(format!(
+                                    "Core '{}' hung, not loaded",
+                                    path.file_name().to_string_lossy()
+                                ));
+                                continue;
+                            }
+                            match Self::try_load_core(path) {
+                                Ok(c) => {
+                                    if core_changed {
+                                        messages.push(format!("Core '{}' changed", c.name()));
+                                    }
+                                    cores.push(c);
+                                }
+                                Err(Some(msg)) => messages.push(msg),
+                                Err(None) => {}
                             }
                         }
                         Err(e) => warn!("Error getting path: {}", e),
@@ -136,23 +489,91 @@ impl Gamepie {
                 error!("Failed to read cores directory");
             }
         }
+        records.save();
 
-        cores
+        (cores, messages)
     }
 
-    fn init(root_dir: &str) -> Result<Self, Box<dyn Error>> {
-        let root_dir = PString::from_str(root_dir)?;
+    fn init(
+        root_dir: &str,
+        config_file: Option<&str>,
+        safe_mode: bool,
+        desktop: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let config_path =
+            config_file.map_or_else(|| Path::new(root_dir).join(CONFIG_FILE), PathBuf::from);
+        let (boot, crash_loop) = Boot::start(Path::new(root_dir), &config_path);
+        let safe = if safe_mode {
+            true
+        } else if crash_loop {
+            warn!("Gamepie keeps stopping, starting in safe mode");
+            true
+        } else if !desktop && crate::gpio::safe_mode_held() {
+            info!("Y held, starting in safe mode");
+            true
+        } else {
+            false
+        };
+        // Safe mode skips everything but the paths in the config file, and
+        // the settings changed from the menus
+        let mut config = if safe {
+            Config::load_safe(root_dir, config_file)
+        } else {
+            Config::load(root_dir, config_file)
+        };
+        config.dirs.create();
+        // The Pi's ALSA device won't be there on a PC
+        if desktop {
+            config.audio.backend = AudioBackend::Sdl;
+        }
+        let settings = Settings::load(&config.dirs.settings_file());
+        if let (Some(scaling), false) = (settings.scaling, safe) {
+            config.video.scaling = scaling;
+        }
+        crate::crash::init(&config.dirs.crashes(), &config_path);
         let (error_tx, error_channel) = mpsc::channel();
-        let screen = Screen::new()?;
-        crate::proxy::audio::try_create(screen.overlay_channel(), error_tx.clone());
+        let request_exit = Arc::new(AtomicBool::new(false));
+        let request_back = Arc::new(AtomicBool::new(false));
+        let mut screen = if desktop {
+            Self::desktop_screen(&request_exit, &request_back, error_tx.clone())?
+        } else {
+            Screen::new(&config.video.display, error_tx.clone())?
+        };
+        crate::proxy::audio::try_create(&config.audio, screen.overlay_channel(), error_tx.clone());
+        if let (Some(volume), false) = (settings.volume, safe) {
+            let msg = AudioMsg::Command(AudioCmd::RestoreVolume(volume));
+            if crate::proxy::audio::get().send(msg).is_err() {
+                warn!("Failed to restore volume");
+            }
+        }
+        let chime = match &config.audio.chime {
+            BootChime::Silent => None,
+            BootChime::Tone => Some(None),
+            BootChime::Wav(path) => Some(Some(path.clone())),
+        };
+        if let Some(path) = chime {
+            // A failure to play is reported by the audio thread
+            let msg = AudioMsg::Command(AudioCmd::Chime(path));
+            if crate::proxy::audio::get().send(msg).is_err() {
+                warn!("Failed to send boot chime");
+            }
+        }
         let toast_tx = screen.overlay_channel();
 
         // TODO After initialising screen, drop capabilities
 
-        let cores = Self::find_cores(root_dir.to_str());
+        let mut records = CoreRecords::load(&config.dirs.core_records_file());
+        let mut quarantine = Quarantine::load(&config.dirs.quarantine_file());
+        let (cores, messages) =
+            Self::find_cores(config.dirs.emulators(), &mut records, &mut quarantine, safe);
+        let safe_msg = safe.then(|| String::from("Safe mode"));
+        for msg in safe_msg.into_iter().chain(messages) {
+            let toast = ScreenToast::error(ScreenMessage::Message(msg));
+            if error_tx.send(Problem::warn(toast)).is_err() {
+                warn!("Failed to send core message");
+            }
+        }
 
-        let request_exit = Arc::new(AtomicBool::new(false));
-        let request_back = Arc::new(AtomicBool::new(false));
         let running = Arc::new(AtomicBool::new(true));
         let re2 = request_exit.clone();
         let ctrlc_count = AtomicU8::new(0);
@@ -168,65 +589,224 @@ impl Gamepie {
         })
         .expect("Error setting Ctrl-C handler");
 
-        let r2 = running.clone();
-        let rb2 = request_back.clone();
-        let gpio = crate::gpio::Gpio::new()?;
-        let gpio_thread = Some(std::thread::spawn(move || {
-            let audio = crate::proxy::audio::get();
+        crate::feedback::start(&config.feedback, running.clone());
+        crate::sensor::start(&config.sensor, running.clone());
 
-            while r2.load(Ordering::Acquire) {
-                // Read GPIO
-                let gpio_val = gpio.read();
+        let screen_off = Arc::new(AtomicBool::new(false));
+        let low_battery = Arc::new(AtomicBool::new(false));
+        // The keyboard is read by the window on the desktop
+        // Lets the watchdog know the backlight is off if it has to exit
+        let (gpio_stopped_tx, gpio_stopped) = mpsc::channel::<()>();
+        let (gpio_thread, light_edge) = if desktop {
+            (None, None)
+        } else {
+            let r2 = running.clone();
+            let rb2 = request_back.clone();
+            let so2 = screen_off.clone();
+            let lb2 = low_battery.clone();
+            let mut gpio = crate::gpio::Gpio::new(&config.gpio)?;
+            if config.gpio.pad.is_some() {
+                info!("GPIO buttons used as a RetroPad");
+            }
+            let light_edge = gpio.light_edge();
+            let gpio_toast = toast_tx.clone();
+            let thread = std::thread::spawn(move || {
+                let _stopped = gpio_stopped_tx;
+                let audio = crate::proxy::audio::get();
+                let mut backlight = true;
 
-                if gpio_val.b {
-                    if audio.send(AudioMsg::Command(AudioCmd::VolumeDown)).is_err() {
-                        warn!("Failed to send volume command");
+                while r2.load(Ordering::Acquire) {
+                    let want_backlight = !so2.load(Ordering::Acquire);
+                    if want_backlight != backlight {
+                        gpio.set_backlight(want_backlight);
+                        backlight = want_backlight;
                     }
-                } else if gpio_val.a {
-                    if audio.send(AudioMsg::Command(AudioCmd::VolumeUp)).is_err() {
-                        warn!("Failed to send volume command");
+
+                    // Rotary encoder
+                    match gpio.encoder_steps() {
+                        Some((_, 0)) | None => {}
+                        Some((EncoderControl::Volume, steps)) => {
+                            for _ in 0..steps.abs() {
+                                let cmd = if steps > 0 {
+                                    AudioCmd::VolumeUp
+                                } else {
+                                    AudioCmd::VolumeDown
+                                };
+                                if audio.send(AudioMsg::Command(cmd)).is_err() {
+                                    warn!("Failed to send volume command");
+                                }
+                            }
+                        }
+                        Some((EncoderControl::Brightness, _)) if !backlight => {}
+                        Some((EncoderControl::Brightness, steps)) => {
+                            let brightness = gpio.adjust_brightness(steps);
+                            let toast = ScreenToast::info(ScreenMessage::Brightness(brightness));
+                            if gpio_toast.send(toast).is_err() {
+                                warn!("Failed to send brightness toast");
+                            }
+                        }
+                    }
+
+                    if gpio.low_battery() {
+                        lb2.store(true, Ordering::Release);
+                    }
+
+                    // Read GPIO
+                    let gpio_val = gpio.read();
+
+                    if let Some(buttons) = gpio.pad(&gpio_val) {
+                        // Polled quickly for games, with no repeat to debounce
+                        gamepie_controller::set_external_buttons(&buttons);
+                        std::thread::sleep(PAD_POLL_INTERVAL);
+                        continue;
+                    }
+                    if gpio_val.b {
+                        if audio.send(AudioMsg::Command(AudioCmd::VolumeDown)).is_err() {
+                            warn!("Failed to send volume command");
+                        }
+                    } else if gpio_val.a {
+                        if audio.send(AudioMsg::Command(AudioCmd::VolumeUp)).is_err() {
+                            warn!("Failed to send volume command");
+                        }
+                    } else if gpio_val.x {
+                        // Set request_back if pressed
+                        rb2.store(true, Ordering::Release);
+                    }
+
+                    // As a very basic form of debouncing, wait for half a second
+                    // before polling gpio again.
+                    // Allows repeating to keep increasing volume if held.
+                    if gpio_val.any() {
+                        std::thread::sleep(BUTTON_BLANK_DURATION)
+                    } else {
+                        std::thread::sleep(MENU_FRAME_DURATION);
                     }
-                } else if gpio_val.x {
-                    // Set request_back if pressed
-                    rb2.store(true, Ordering::Release);
                 }
+                // Before `_stopped` is dropped
+                drop(gpio);
+                debug!("GPIO thread finished");
+            });
+            (Some(thread), light_edge)
+        };
 
-                // As a very basic form of debouncing, wait for half a second
-                // before polling gpio again.
-                // Allows repeating to keep increasing volume if held.
-                if gpio_val.any() {
-                    std::thread::sleep(BUTTON_BLANK_DURATION)
-                } else {
-                    std::thread::sleep(MENU_FRAME_DURATION);
+        let mqtt = config.mqtt.clone().map(|m| {
+            Mqtt::start(
+                m,
+                running.clone(),
+                request_back.clone(),
+                request_exit.clone(),
+            )
+        });
+        let netplay = if config.netplay.enabled {
+            match Netplay::start(&config.netplay, &mut screen, running.clone()) {
+                Ok(netplay) => Some(netplay),
+                Err(e) => {
+                    warn!("Failed to start netplay: {}", e);
+                    None
                 }
             }
-            debug!("GPIO thread finished");
-        }));
+        } else {
+            None
+        };
+        let (command_tx, commands) = mpsc::channel();
+        let rom_watcher = if config.menu.watch {
+            let watched =
+                RomWatcher::start(&config.dirs.rom_dirs(), command_tx.clone(), running.clone());
+            match watched {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    warn!("Failed to watch ROM directories: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut notifier = Notifier::new(&config.notify, toast_tx, &config.dirs.notify_log_file());
+        if let Some(mqtt) = &mqtt {
+            notifier.set_mqtt(mqtt.sender());
+        }
+        let plugins = Plugins::default();
+        notifier.set_plugins(plugins.clone());
 
-        let menu = Menu::new(root_dir.to_str(), screen.width(), screen.height());
+        let stats = Stats::load(&config.dirs.stats_file());
+        let history = History::load(&config.dirs.history_file());
+        let mut menu = Menu::new(&config.dirs, &config.menu, screen.width(), screen.height());
+        // Not started in safe mode, in case hashing a ROM was the problem
+        let library = (config.library.enabled && !safe).then(|| {
+            menu.set_titles(LibraryScanner::saved_titles(&config));
+            LibraryScanner::start(&config, running.clone())
+        });
+
+        let storage = Storage::new(&config.storage);
+        let watchdog = Watchdog::new(
+            &config.watchdog,
+            &config.dirs.quarantine_file(),
+            running.clone(),
+            gpio_thread.is_some().then_some(gpio_stopped),
+        );
+        // Left alone on the desktop, and in safe mode in case it was the
+        // problem
+        let governor = (config.cpu.enabled && !desktop && !safe)
+            .then(|| Governor::start(&config.cpu))
+            .flatten();
 
         Ok(Gamepie {
-            root_dir,
+            config,
             cores,
             state: Some(GamepieState::Init),
             menu,
             request_exit,
             request_back,
+            screen_off,
+            low_battery,
+            power: None,
             running,
             gpio_thread,
+            light_edge,
+            desktop,
             error_channel,
             error_tx,
-            screen: Some(screen),
-            toast_tx,
+            screen: ScreenHandle::new(screen),
+            notifier,
+            hotkeys: Hotkeys::new(),
+            plugins,
+            mqtt,
+            netplay,
+            library,
+            rom_watcher,
+            playing: false,
+            commands,
+            command_tx,
+            http_port: None,
+            rescan_pending: false,
+            trace_env: false,
+            stats,
+            history,
+            settings,
+            party: None,
+            storage,
+            watchdog,
+            governor,
+            preload: Preloader::default(),
+            boot,
+            safe,
+            recovery_pending: safe,
         })
     }
 
-    pub fn new(root_dir: &str) -> Result<Self, Box<dyn Error>> {
+    /// Start gamepie, in safe mode if `safe_mode` is set. Safe mode is also
+    /// used if the last few starts stopped early, or the Y button is held.
+    pub fn new(
+        root_dir: &str,
+        config_file: Option<&str>,
+        safe_mode: bool,
+    ) -> Result<Self, Box<dyn Error>> {
         let rpi = DeviceInfo::new();
         match rpi {
             Ok(r) => {
                 info!("Device: {} ({})", r.model(), r.soc());
-                Self::init(root_dir)
+                Self::init(root_dir, config_file, safe_mode, false)
             }
             Err(e) => {
                 error!("Can't identify Raspberry Pi: {}", e);
@@ -235,6 +815,78 @@ impl Gamepie {
         }
     }
 
+    /// Start gamepie in a window on a PC, with the keyboard in place of the
+    /// buttons and no GPIO. Shutdown and reboot just exit.
+    pub fn new_desktop(
+        root_dir: &str,
+        config_file: Option<&str>,
+        safe_mode: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::init(root_dir, config_file, safe_mode, true)
+    }
+
+    #[cfg(feature = "sdl")]
+    fn desktop_screen(
+        request_exit: &Arc<AtomicBool>,
+        request_back: &Arc<AtomicBool>,
+        error_tx: mpsc::Sender<Problem>,
+    ) -> Result<Screen, Box<dyn Error>> {
+        let window = crate::desktop::SdlWindow::open(request_exit.clone(), request_back.clone())?;
+        Ok(Screen::with_driver(Box::new(window), error_tx))
+    }
+
+    #[cfg(not(feature = "sdl"))]
+    fn desktop_screen(
+        _request_exit: &Arc<AtomicBool>,
+        _request_back: &Arc<AtomicBool>,
+        _error_tx: mpsc::Sender<Problem>,
+    ) -> Result<Screen, Box<dyn Error>> {
+        error!("Built without SDL, so there is no desktop window");
+        Err(Box::new(GamepieError::NoVideo))
+    }
+
+    /// Add a plugin, before `run`
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.hotkeys.add_plugin_hotkeys(plugin.hotkeys());
+        self.plugins.add(plugin);
+        self.menu.set_extras(self.plugins.screens());
+    }
+
+    /// Accept commands from an external launcher on a FIFO, or stdin if
+    /// `path` is "-"
+    pub fn listen(&mut self, path: &str) {
+        if self.safe {
+            info!("Not reading commands in safe mode");
+            return;
+        }
+        crate::pipe::start(path, self.command_tx.clone());
+    }
+
+    /// Serve the HTTP management API on `addr`, e.g. "0.0.0.0:8080", and
+    /// advertise it over mDNS
+    pub fn serve_http(&mut self, addr: &str) -> Result<(), Box<dyn Error>> {
+        if self.safe {
+            info!("Not serving HTTP in safe mode");
+            return Ok(());
+        }
+        let port = crate::http::start(addr, &self.config.dirs, self.command_tx.clone())?;
+        self.http_port = Some(port);
+        let model = DeviceInfo::new().ok().map(|d| d.model().to_string());
+        let netplay = self.netplay.as_ref().map(|_| self.config.netplay.port);
+        if let Err(e) = crate::mdns::start(port, model, netplay) {
+            warn!("Failed to advertise over mDNS: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Write the environment calls made by each core to a file in the
+    /// traces directory
+    pub fn trace_env(&mut self) {
+        self.trace_env = true;
+    }
+
+    // Cores that support a game, only the one named by `preferred_core` in
+    // its metadata if that is one of them, so the core menu is skipped
     fn get_cores_for_game(&self, path: &str) -> Vec<CoreInfo> {
         let path = Path::new(path);
         let mut cores = Vec::new();
@@ -249,55 +901,340 @@ impl Gamepie {
         } else {
             error!("No file extension to determine emulator");
         }
+        if let Some(preferred) = metadata::get_str(path, "preferred_core") {
+            match cores.iter().find(|c| c.matches(&preferred)) {
+                Some(c) => return vec![c.clone()],
+                None => warn!("Preferred core '{}' doesn't support this game", preferred),
+            }
+        }
         cores
     }
 
+    // Show the stats for a game above the menu
+    fn show_stats(&mut self, path: &str) {
+        let details = self.stats.get(Path::new(path)).map(|s| s.summary());
+        self.menu.set_details(details);
+    }
+
+    // Offer the cores for a game in the core menu, with hints for those
+    // whose requirements aren't met. A single core is started without the
+    // menu, so its hints are shown as a message instead.
+    fn offer_cores(&mut self, cores: Vec<CoreInfo>, game: &str) {
+        let analog = crate::proxy::libretro::with_proxy(|p| p.has_analog()).unwrap_or(false);
+        let hints: Vec<Vec<String>> = cores
+            .iter()
+            .map(|c| {
+                get_requirements(&c.name()).hints(
+                    Path::new(game),
+                    self.config.dirs.system(),
+                    analog,
+                )
+            })
+            .collect();
+        for (core, hints) in cores.iter().zip(&hints).filter(|(_, h)| !h.is_empty()) {
+            info!("Core '{}': {}", core.name(), hints.join(", "));
+        }
+        if let [hints] = hints.as_slice() {
+            if !hints.is_empty() {
+                let msg = format!("{}: {}", cores[0].name(), hints.join(", "));
+                let toast = ScreenToast::error(ScreenMessage::Message(msg));
+                if self.error_tx.send(Problem::warn(toast)).is_err() {
+                    warn!("Failed to send core requirements");
+                }
+            }
+        }
+        self.menu.set_cores(cores);
+        self.menu.set_core_hints(hints);
+    }
+
+    // Check a game can be started and move on to picking a core for it
+    fn start_game(&mut self, path: String, game_index: usize) -> GamepieState {
+        let cores = self.get_cores_for_game(&path);
+        if !Path::new(&path).is_file() {
+            // Game may have been on removable storage
+            GamepieState::Error(GamepieError::GameMissing)
+        } else if cores.is_empty() {
+            GamepieState::Error(GamepieError::NoCore)
+        } else {
+            self.offer_cores(cores, &path);
+            info!("Gamepie State: Start Game");
+            // Force pressed to 'debounce' start button
+            GamepieState::StartGame(path, game_index, MenuState::default())
+        }
+    }
+
     // Get buttons pressed on controller to control menu,
     // GPIO buttons are used for volume/exit so can't be
     // used for the menu.
-    fn get_menu_info(&self, state: &MenuState) -> Option<MenuInfo> {
-        crate::proxy::libretro::with_proxy(|p| {
+    // Left and right move a page at a time, without wrapping, len is the
+    // number of items in the menu.
+    fn get_menu_info(&self, state: &MenuState, sel: MenuSel) -> Option<MenuInfo> {
+        let page = self.menu.page_size();
+        let last = self.menu.num_items(sel).saturating_sub(1);
+        let info = crate::proxy::libretro::with_proxy(|p| {
             p.input_poll();
-            let a_press = p.input_state(RetroPadButton::A) == 1;
-            let b_press = p.input_state(RetroPadButton::B) == 1;
-            let up_press = p.input_state(RetroPadButton::Up) == 1;
-            let dn_press = p.input_state(RetroPadButton::Down) == 1;
-            let new_pressed = up_press | dn_press | a_press;
-            let delta = if state.pressed {
+            let a_press = p.input_state(0, RetroPadButton::A) == 1;
+            let b_press = p.input_state(0, RetroPadButton::B) == 1;
+            let up_press = p.input_state(0, RetroPadButton::Up) == 1;
+            let dn_press = p.input_state(0, RetroPadButton::Down) == 1;
+            let lt_press = p.input_state(0, RetroPadButton::Left) == 1;
+            let rt_press = p.input_state(0, RetroPadButton::Right) == 1;
+            let l_press = p.input_state(0, RetroPadButton::L) == 1;
+            let r_press = p.input_state(0, RetroPadButton::R) == 1;
+            let x_press = p.input_state(0, RetroPadButton::X) == 1;
+            let y_press = p.input_state(0, RetroPadButton::Y) == 1;
+            let sel_press = p.input_state(0, RetroPadButton::Select) == 1;
+            let start_press = p.input_state(0, RetroPadButton::Start) == 1;
+            let scroll = up_press | dn_press | lt_press | rt_press | l_press | r_press;
+            let new_pressed =
+                scroll | a_press | b_press | sel_press | start_press | x_press | y_press;
+
+            // A fresh press moves straight away, holding it down repeats
+            let now = Instant::now();
+            let (moved, repeat, held) = match state.held {
+                _ if !scroll => (false, false, None),
+                None if state.pressed => (false, false, None),
+                None => (true, false, Some((now, now))),
+                Some((since, last_move)) => match repeat_interval(now - since) {
+                    Some(interval) if now - last_move >= interval => {
+                        (true, true, Some((since, now)))
+                    }
+                    _ => (false, false, state.held),
+                },
+            };
+            // Left and Right switch systems when the game list is grouped
+            let switch =
+                (lt_press | rt_press) && matches!(sel, MenuSel::Game) && self.menu.has_systems();
+            // Only a fresh press wraps round the ends of the menu, so holding
+            // a direction stops at the end
+            let delta = if !moved || switch {
                 state.index
+            } else if up_press && repeat {
+                state.index.saturating_sub(1)
             } else if up_press {
                 state.index.wrapping_sub(1)
+            } else if dn_press && repeat {
+                std::cmp::min(state.index + 1, last)
             } else if dn_press {
                 state.index.wrapping_add(1)
+            } else if l_press {
+                state.index.saturating_sub(page)
+            } else if r_press {
+                std::cmp::min(state.index + page, last)
             } else {
-                state.index
+                self.menu.jump(sel, state.index, rt_press)
             };
             MenuInfo {
                 start_game: a_press & !state.pressed,
                 back: b_press & !state.pressed,
+                input_test: sel_press & !state.pressed,
+                mark: start_press & !state.pressed,
+                search: x_press & !state.pressed,
+                favourite: y_press & !state.pressed,
                 unsafe_index: delta,
                 new_pressed,
+                held,
+                switch_system: (switch && moved && !repeat).then_some(rt_press),
             }
-        })
+        });
+        if matches!(&info, Some(i) if i.unsafe_index != state.index) {
+            let pulse = FeedbackMsg::Pulse(FeedbackSource::Menu, MENU_PULSE);
+            crate::proxy::feedback::send(pulse);
+        }
         // None will be returned if there is no proxy available
+        info
+    }
+
+    // Timestamped file in the screenshot directory
+    fn capture_path(&self, ext: &str) -> PathBuf {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let file = PathBuf::from(format!("gamepie-{}.{}", time, ext));
+        self.config.dirs.screenshots().join(file)
+    }
+
+    // Start recording a video of the game, or finish the one being recorded
+    fn toggle_recording(&mut self, core: &mut Core) {
+        let msg = |text: &str| ScreenMessage::Message(String::from(text));
+        let toast = if core.recording() {
+            core.stop_recording();
+            ScreenToast::info(msg("Video saved"))
+        } else {
+            match core.start_recording(&self.capture_path("avi")) {
+                Ok(()) => ScreenToast::info(msg("Recording video")),
+                Err(e) => {
+                    error!("Failed to start recording: {}", e);
+                    ScreenToast::error(msg("Can't record video"))
+                }
+            }
+        };
+        if !self.notifier.toast(toast) {
+            warn!("Failed to send recording message");
+        }
+    }
+
+    fn handle_command(&mut self, state: GamepieState, cmd: PipeCommand) -> GamepieState {
+        match cmd {
+            PipeCommand::Launch(path, core) => {
+                // A game being loaded can't be stopped part way through
+                if matches!(
+                    state,
+                    GamepieState::Init | GamepieState::ExitGame | GamepieState::Loading(_)
+                ) {
+                    warn!("Not ready to launch a game");
+                    return state;
+                }
+                let path = match path.to_str() {
+                    Some(p) => String::from(p),
+                    None => {
+                        warn!("Game path is not valid UTF-8");
+                        return state;
+                    }
+                };
+                if matches!(state, GamepieState::InputTest(..)) {
+                    crate::proxy::libretro::with_proxy(|p| p.watch_raw_input(false));
+                }
+                // Any running game is dropped here, so it will be saved
+                // before the new game is loaded.
+                drop(state);
+                if !Path::new(&path).is_file() {
+                    return GamepieState::Error(GamepieError::GameMissing);
+                }
+                let mut cores = self.get_cores_for_game(&path);
+                if let Some(name) = core {
+                    cores.retain(|c| c.name().eq_ignore_ascii_case(&name));
+                }
+                if cores.is_empty() {
+                    GamepieState::Error(GamepieError::NoCore)
+                } else {
+                    info!("Launching '{}'", path);
+                    self.offer_cores(cores, &path);
+                    GamepieState::StartGame(path, 0, MenuState::default())
+                }
+            }
+            PipeCommand::Stop => match state {
+                GamepieState::Game(_) | GamepieState::Pause(..) => GamepieState::Init,
+                s => s,
+            },
+            PipeCommand::Screenshot(path) => {
+                let path = path.unwrap_or_else(|| self.capture_path("ppm"));
+                if crate::proxy::libretro::with_proxy(|p| {
+                    p.borrow_screen().request_screenshot(path);
+                })
+                .is_none()
+                {
+                    warn!("No screen for screenshot");
+                }
+                state
+            }
+            PipeCommand::Volume(delta) => {
+                let audio = crate::proxy::audio::get();
+                for _ in 0..delta.abs() {
+                    let cmd = if delta > 0 {
+                        AudioCmd::VolumeUp
+                    } else {
+                        AudioCmd::VolumeDown
+                    };
+                    if audio.send(AudioMsg::Command(cmd)).is_err() {
+                        warn!("Failed to send volume command");
+                    }
+                }
+                state
+            }
+            PipeCommand::Record(path) => {
+                let path = path.unwrap_or_else(|| self.capture_path("wav"));
+                let msg = AudioMsg::Command(AudioCmd::StartRecord(path));
+                if crate::proxy::audio::get().send(msg).is_err() {
+                    warn!("Failed to send record command");
+                }
+                state
+            }
+            PipeCommand::StopRecord => {
+                let msg = AudioMsg::Command(AudioCmd::StopRecord);
+                if crate::proxy::audio::get().send(msg).is_err() {
+                    warn!("Failed to send record command");
+                }
+                state
+            }
+            PipeCommand::Rescan => {
+                self.rescan_pending = true;
+                state
+            }
+            PipeCommand::DumpMemory(address, length, path) => {
+                // The memory map only points at memory while a game is loaded
+                if !matches!(state, GamepieState::Game(_) | GamepieState::Pause(..)) {
+                    warn!("No game running to dump memory from");
+                    return state;
+                }
+                let mut data = vec![0; length];
+                let read =
+                    crate::proxy::libretro::with_proxy(|p| p.peek_memory(address, &mut data))
+                        .unwrap_or(0);
+                if read < length {
+                    warn!(
+                        "Only {} of {} bytes from {:#x} are mapped",
+                        read, length, address
+                    );
+                }
+                match std::fs::write(&path, &data[..read]) {
+                    Ok(_) => info!("Dumped {} bytes of memory to '{}'", read, path.display()),
+                    Err(e) => warn!("Failed to write '{}': {}", path.display(), e),
+                }
+                state
+            }
+        }
     }
 
     fn main_loop_inner(&mut self) -> Result<(), Box<dyn Error>> {
         let start = std::time::Instant::now();
+        self.boot.tick(self.safe);
+        if let Ok(cmd) = self.commands.try_recv() {
+            if let Some(state) = self.state.take() {
+                self.state = Some(self.handle_command(state, cmd));
+            }
+        }
+        if let Some(titles) = self.library.as_ref().and_then(|l| l.titles()) {
+            self.menu.set_titles(titles);
+        }
+        // Waits for the game list, as other states hold indexes into it
+        if self.rescan_pending {
+            if let Some(GamepieState::SelectGame(state)) = &self.state {
+                self.rescan_pending = false;
+                let index = state.index;
+                let selected = self.menu.get_path(index);
+                self.menu.rescan(&self.config.menu);
+                self.update_pinned();
+                let index = self.menu.find_game(&selected, index).unwrap_or(0);
+                if let Some(library) = &self.library {
+                    library.rescan();
+                }
+                self.state = Some(GamepieState::SelectGame(MenuState::new(index, true)));
+            }
+        }
         let next_state = match self.state.take() {
             Some(GamepieState::Init) => {
                 info!("Gamepie State: Init");
+                // Leaving a game ends any party
+                self.party = None;
+                crate::crash::running(None, None);
+                self.screen_off.store(false, Ordering::Release);
+                crate::proxy::feedback::send(FeedbackMsg::Stop);
                 // Create proxy for use in menu
-                let sys_dir_path = Path::new(self.root_dir.to_str()).join(SYS_PATH);
+                let dirs = &self.config.dirs;
                 let sys_dir =
-                    PString::from_str(sys_dir_path.to_str().ok_or(GamepieError::String)?)?;
+                    PString::from_str(dirs.system().to_str().ok_or(GamepieError::String)?)?;
+                let save_dir =
+                    PString::from_str(dirs.save_dir().to_str().ok_or(GamepieError::String)?)?;
                 let audio_channel = crate::proxy::audio::get();
                 crate::proxy::libretro::create(
                     sys_dir,
-                    self.screen.take(),
+                    save_dir,
+                    &mut self.screen,
                     self.error_tx.clone(),
                     audio_channel,
-                );
+                    &self.config.input,
+                )?;
                 // Draw an intro logo
                 match crate::proxy::libretro::with_proxy(|p| {
                     self.menu.draw_splash(p.borrow_screen())?;
@@ -307,22 +1244,44 @@ impl Gamepie {
                     None => error!("Menu executed before proxy created"),
                 };
                 // Show splash screen for a while
-                std::thread::sleep(SPLASH_DURATION);
-                info!("Gamepie State: Select Game");
-                self.menu.log();
+                GamepieState::Splash(Instant::now() + SPLASH_DURATION)
+            }
+            Some(GamepieState::Splash(until)) => {
                 // If Exit(Ctrl-C) or back(Button) then exit, will
                 // be restarted by service.
                 if self.request_exit.load(Ordering::Acquire)
                     || self.request_back.load(Ordering::Acquire)
                 {
                     GamepieState::ExitGame
-                } else if self.menu.num_games() == 0 {
-                    GamepieState::Error(GamepieError::NoGames)
+                } else if Instant::now() < until {
+                    GamepieState::Splash(until)
+                } else if self.recovery_pending {
+                    self.recovery_pending = false;
+                    info!("Gamepie State: Recovery");
+                    self.menu.set_details(Some(String::from("Safe mode")));
+                    GamepieState::Recovery(self.recovery_items(), MenuState::new(0, true))
+                } else if let Some(report) = crate::crash::pending(&self.config.dirs.crashes()) {
+                    info!("Gamepie State: Crash Report");
+                    // Pressed so a held button isn't taken as an answer
+                    GamepieState::CrashReport(report, MenuState::new(0, true))
                 } else {
-                    GamepieState::SelectGame(MenuState::default())
+                    self.enter_menu()
                 }
             }
+            Some(GamepieState::CrashReport(report, state)) => self.crash_report(report, state)?,
+            Some(GamepieState::SaveSlots(core, slots, state)) => {
+                self.save_slots(core, slots, state)?
+            }
+            Some(GamepieState::Typing(core, text, state)) => self.typing(core, text, state)?,
+            Some(GamepieState::Power(game_index, state)) => self.power(game_index, state)?,
+            Some(GamepieState::PluginScreen(game_index, screen, state)) => {
+                self.plugin_screen(game_index, screen, state)?
+            }
+            Some(GamepieState::Recovery(items, state)) => self.recovery(items, state)?,
             Some(GamepieState::SelectGame(state)) => {
+                let path = self.menu.get_path(state.index);
+                self.show_stats(&path);
+                self.preload.hover(Path::new(&path));
                 // Draw menu
                 match crate::proxy::libretro::with_proxy(|p| {
                     self.menu
@@ -334,7 +1293,7 @@ impl Gamepie {
                 };
 
                 // Check for button presses to change index
-                match self.get_menu_info(&state) {
+                match self.get_menu_info(&state, MenuSel::Game) {
                     None => GamepieState::Error(GamepieError::System),
                     Some(info) => {
                         if self.request_exit.load(Ordering::Acquire) {
@@ -342,28 +1301,142 @@ impl Gamepie {
                         } else if self.request_back.load(Ordering::Acquire) {
                             self.request_back.store(false, Ordering::Release);
                             GamepieState::ExitGame
+                        } else if info.start_game && !self.menu.marked_paths().is_empty() {
+                            self.start_party()?
                         } else if info.start_game {
-                            // Get path to game
-                            let path = self.menu.get_path(state.index);
-                            let cores = self.get_cores_for_game(&path);
-                            if cores.is_empty() {
-                                GamepieState::Error(GamepieError::NoCore)
+                            if self.menu.num_variants(state.index) > 1 {
+                                info!("Gamepie State: Select Variant");
+                                GamepieState::SelectVariant(state.index, MenuState::default())
                             } else {
-                                self.menu.set_cores(cores);
-                                info!("Gamepie State: Start Game");
-                                // Force pressed to 'debounce' start button
-                                GamepieState::StartGame(path, state.index, MenuState::default())
+                                let path = self.menu.get_path(state.index);
+                                self.start_game(path, state.index)
                             }
+                        } else if info.back && !self.menu.query().is_empty() {
+                            self.menu.set_query("");
+                            GamepieState::SelectGame(MenuState::default())
+                        } else if info.back {
+                            info!("Gamepie State: Power");
+                            GamepieState::Power(state.index, MenuState::new(0, true))
+                        } else if info.search {
+                            info!("Gamepie State: Search");
+                            GamepieState::Search(MenuState::default())
+                        } else if info.favourite {
+                            self.toggle_favourite(state.index);
+                            GamepieState::SelectGame(MenuState::moved(state.index, &info))
+                        } else if info.mark {
+                            self.menu.toggle_mark(state.index);
+                            GamepieState::SelectGame(MenuState::moved(state.index, &info))
+                        } else if let Some(forward) = info.switch_system {
+                            self.menu.next_system(forward);
+                            GamepieState::SelectGame(MenuState::moved(0, &info))
+                        } else if info.input_test {
+                            info!("Gamepie State: Input Test");
+                            crate::proxy::libretro::with_proxy(|p| p.watch_raw_input(true));
+                            GamepieState::InputTest(state.index, VecDeque::new())
                         } else {
-                            std::thread::sleep(MENU_FRAME_DURATION);
                             let new_index = self.menu.safe_index(MenuSel::Game, info.unsafe_index);
-                            GamepieState::SelectGame(MenuState::new(new_index, info.new_pressed))
+                            GamepieState::SelectGame(MenuState::moved(new_index, &info))
+                        }
+                    }
+                }
+            }
+            Some(GamepieState::Search(state)) => self.search(state)?,
+            Some(GamepieState::InputTest(game_index, mut events)) => {
+                // Whether Start and Select are held, to exit
+                let held = crate::proxy::libretro::with_proxy(|p| {
+                    p.input_poll();
+                    let mut pad = PadView::default();
+                    for (id, held) in pad.buttons.iter_mut().enumerate() {
+                        *held = p.input_state(0, RetroPadButton::new(id as u32)) == 1;
+                    }
+                    for (n, (index, id)) in [
+                        (RETRO_DEVICE_INDEX_ANALOG_LEFT, RETRO_DEVICE_ID_ANALOG_X),
+                        (RETRO_DEVICE_INDEX_ANALOG_LEFT, RETRO_DEVICE_ID_ANALOG_Y),
+                        (RETRO_DEVICE_INDEX_ANALOG_RIGHT, RETRO_DEVICE_ID_ANALOG_X),
+                        (RETRO_DEVICE_INDEX_ANALOG_RIGHT, RETRO_DEVICE_ID_ANALOG_Y),
+                    ]
+                    .into_iter()
+                    .enumerate()
+                    {
+                        pad.axes[n] = p.input_state_analog(0, index, id);
+                    }
+                    events.extend(p.take_raw_input());
+                    let excess = events.len().saturating_sub(INPUT_TEST_EVENTS);
+                    events.drain(..excess);
+                    self.menu
+                        .draw_input_test(p.borrow_screen(), &pad, events.make_contiguous())?;
+                    let held = |b: RetroPadButton| pad.buttons[b as usize];
+                    let exit = held(RetroPadButton::Start) && held(RetroPadButton::Select);
+                    let latency = held(RetroPadButton::L) && held(RetroPadButton::R);
+                    Ok::<_, Box<dyn Error>>((exit, latency))
+                });
+
+                match held {
+                    None => GamepieState::Error(GamepieError::System),
+                    Some(held) => {
+                        let (exit, latency) = held?;
+                        if self.request_exit.load(Ordering::Acquire) {
+                            crate::proxy::libretro::with_proxy(|p| p.watch_raw_input(false));
+                            GamepieState::ExitGame
+                        } else if exit || self.request_back.load(Ordering::Acquire) {
+                            self.request_back.store(false, Ordering::Release);
+                            crate::proxy::libretro::with_proxy(|p| p.watch_raw_input(false));
+                            info!("Gamepie State: Select Game");
+                            GamepieState::SelectGame(MenuState::new(game_index, true))
+                        } else if latency {
+                            crate::proxy::libretro::with_proxy(|p| p.watch_raw_input(false));
+                            info!("Gamepie State: Latency Test");
+                            let test = LatencyTest::new(self.light_edge.clone());
+                            GamepieState::LatencyTest(game_index, test)
+                        } else {
+                            GamepieState::InputTest(game_index, events)
+                        }
+                    }
+                }
+            }
+            Some(GamepieState::LatencyTest(game_index, test)) => {
+                self.latency_test(game_index, test)?
+            }
+            Some(GamepieState::SelectVariant(game_index, state)) => {
+                self.show_stats(&self.menu.get_variant_path(game_index, state.index));
+                match crate::proxy::libretro::with_proxy(|p| {
+                    self.menu.draw_menu(
+                        p.borrow_screen(),
+                        MenuSel::Variant(game_index),
+                        state.index,
+                    )?;
+                    ok_res()
+                }) {
+                    Some(res) => res?,
+                    None => error!("Menu executed before proxy created"),
+                };
+
+                match self.get_menu_info(&state, MenuSel::Variant(game_index)) {
+                    None => GamepieState::Error(GamepieError::System),
+                    Some(info) => {
+                        if self.request_exit.load(Ordering::Acquire) {
+                            GamepieState::ExitGame
+                        } else if self.request_back.load(Ordering::Acquire) || info.back {
+                            self.request_back.store(false, Ordering::Release);
+                            GamepieState::SelectGame(MenuState::new(game_index, true))
+                        } else if info.start_game {
+                            let path = self.menu.get_variant_path(game_index, state.index);
+                            self.start_game(path, game_index)
+                        } else {
+                            let new_index = self
+                                .menu
+                                .safe_index(MenuSel::Variant(game_index), info.unsafe_index);
+                            GamepieState::SelectVariant(
+                                game_index,
+                                MenuState::moved(new_index, &info),
+                            )
                         }
                     }
                 }
             }
             Some(GamepieState::StartGame(game, game_index, state)) => {
                 let cores = self.menu.num_cores();
+                self.show_stats(&game);
                 // If only one core, going to force loading that emulator anyway
                 if cores > 1 {
                     match crate::proxy::libretro::with_proxy(|p| {
@@ -376,7 +1449,7 @@ impl Gamepie {
                     };
                 };
 
-                match self.get_menu_info(&state) {
+                match self.get_menu_info(&state, MenuSel::Core) {
                     None => GamepieState::Error(GamepieError::System),
                     Some(info) => {
                         if self.request_exit.load(Ordering::Acquire) {
@@ -386,30 +1459,39 @@ impl Gamepie {
                             GamepieState::SelectGame(MenuState::new(game_index, true))
                         } else if info.start_game || cores == 1 {
                             let cinfo = self.menu.get_core(state.index);
-                            let path = Path::new(&game);
-                            trace!("Loading game: {}", path.display());
-                            let core = Core::new(
-                                cinfo,
-                                path,
-                                self.root_dir.clone(),
-                                self.screen.take(),
-                                self.error_tx.clone(),
-                                crate::proxy::audio::get(),
-                            )?;
-                            info!("Gamepie State: Game");
-                            GamepieState::Game(Box::new(core))
+                            trace!("Loading game: {}", game);
+                            self.start_loading(cinfo, &game, false)?
                         } else {
-                            std::thread::sleep(MENU_FRAME_DURATION);
                             let new_index = self.menu.safe_index(MenuSel::Core, info.unsafe_index);
                             GamepieState::StartGame(
                                 game,
                                 game_index,
-                                MenuState::new(new_index, info.new_pressed),
+                                MenuState::moved(new_index, &info),
                             )
                         }
                     }
                 }
             }
+            Some(GamepieState::Loading(loading)) => {
+                // Back and exit are left until the game has loaded, as the
+                // core can't be stopped part way through loading
+                if loading.is_finished() {
+                    self.finish_loading(*loading)
+                } else {
+                    match crate::proxy::libretro::with_proxy(|p| {
+                        self.menu.draw_loading(
+                            p.borrow_screen(),
+                            loading.name(),
+                            loading.step(),
+                        )?;
+                        ok_res()
+                    }) {
+                        Some(res) => res?,
+                        None => error!("Menu executed before proxy created"),
+                    };
+                    GamepieState::Loading(loading)
+                }
+            }
             Some(GamepieState::Game(mut core)) => {
                 // If going back to init, core will end up dropped which will
                 // trigger saving and any core-related cleanup.
@@ -417,24 +1499,204 @@ impl Gamepie {
                     GamepieState::Init
                 } else if self.request_back.load(Ordering::Acquire) {
                     self.request_back.store(false, Ordering::Release);
+                    self.autosave(&mut core, AutosaveEvent::Back);
                     GamepieState::Init
+                } else if self.low_battery.swap(false, Ordering::AcqRel) {
+                    let saved = self.autosave(&mut core, AutosaveEvent::LowBattery);
+                    self.handle_low_battery(saved);
+                    GamepieState::Game(core)
+                } else if self.party.as_ref().is_some_and(|p| p.remaining().is_zero()) {
+                    if let Err(e) = core.save_state_file(&self.config.dirs) {
+                        warn!("Failed to save party state: {}", e);
+                    }
+                    // Dropped before the next game loads, which saves it
+                    drop(core);
+                    // So the next game is reported as started
+                    self.playing = false;
+                    if let Some(party) = &mut self.party {
+                        party.next();
+                    }
+                    self.load_party_game()?
                 } else {
-                    core.tick()?;
+                    if let Some(secs) = self.party.as_mut().and_then(|p| p.countdown()) {
+                        self.party_countdown(secs);
+                    }
+                    let disconnected =
+                        crate::proxy::libretro::with_proxy(|p| p.take_disconnected());
+                    if disconnected == Some(true) {
+                        self.autosave(&mut core, AutosaveEvent::Disconnect);
+                    }
+                    match self.hotkeys.held() {
+                        Some(Hotkey::Rewind) => core.rewind()?,
+                        Some(Hotkey::FastForward) => core.fast_forward()?,
+                        _ => core.tick()?,
+                    }
+                    let hotkey = self.hotkeys.check();
+                    if let Some(hotkey) = hotkey {
+                        self.handle_hotkey(&mut core, hotkey);
+                    }
                     let duration = start.elapsed();
                     trace!("Time elapsed in tick() is: {:?}", duration);
+                    core.frame_finished(duration);
                     match core.frame_time().checked_sub(duration) {
                         Some(t) => std::thread::sleep(t),
                         None => {
                             warn!("Dropped frame {:?}", duration);
+                            core.frame_dropped();
                         }
                     }
 
-                    GamepieState::Game(core)
+                    if hotkey == Some(Hotkey::Pause) {
+                        self.autosave(&mut core, AutosaveEvent::Pause);
+                        info!("Gamepie State: Pause");
+                        let details = format!(
+                            "Paused: {}, audio {} ms",
+                            core.game_name(),
+                            core.audio_latency().as_millis()
+                        );
+                        self.menu.set_details(Some(details));
+                        GamepieState::Pause(core, MenuState::default())
+                    } else {
+                        GamepieState::Game(core)
+                    }
+                }
+            }
+            Some(GamepieState::Pause(mut core, state)) => {
+                // The core isn't run, the menu is drawn over its last frame
+                match crate::proxy::libretro::with_proxy(|p| {
+                    self.menu.set_scale_mode(p.scale_mode());
+                    self.menu.set_hud(p.hud());
+                    self.menu.set_cpu_boost(self.settings.cpu_boost);
+                    self.menu.set_autosave(self.settings.autosave_interval);
+                    self.menu.set_recording(core.recording());
+                    self.menu
+                        .draw_menu(p.borrow_screen(), MenuSel::Pause, state.index)?;
+                    ok_res()
+                }) {
+                    Some(res) => res?,
+                    None => error!("Menu executed before proxy created"),
+                };
+
+                match self.get_menu_info(&state, MenuSel::Pause) {
+                    None => GamepieState::Error(GamepieError::System),
+                    Some(info) => {
+                        if self.request_exit.load(Ordering::Acquire) {
+                            GamepieState::Init
+                        } else if self.request_back.load(Ordering::Acquire) {
+                            self.request_back.store(false, Ordering::Release);
+                            self.autosave(&mut core, AutosaveEvent::Back);
+                            GamepieState::Init
+                        } else if self.low_battery.swap(false, Ordering::AcqRel) {
+                            let saved = self.autosave(&mut core, AutosaveEvent::LowBattery);
+                            self.handle_low_battery(saved);
+                            GamepieState::Pause(core, state)
+                        } else if info.back {
+                            info!("Gamepie State: Game");
+                            GamepieState::Game(core)
+                        } else if info.start_game {
+                            let item = PAUSE_ITEMS[state.index];
+                            debug!("Pause menu: {:?}", item);
+                            let hotkey = match item {
+                                PauseItem::QuickSave => Some(Hotkey::QuickSave),
+                                PauseItem::QuickLoad => Some(Hotkey::QuickLoad),
+                                PauseItem::Resume
+                                | PauseItem::Scaling
+                                | PauseItem::Performance
+                                | PauseItem::CpuBoost
+                                | PauseItem::Autosave
+                                | PauseItem::SaveSlots
+                                | PauseItem::Keyboard
+                                | PauseItem::Record
+                                | PauseItem::ResetOptions
+                                | PauseItem::Exit => None,
+                            };
+                            if let Some(hotkey) = hotkey {
+                                self.handle_hotkey(&mut core, hotkey);
+                            }
+                            if item == PauseItem::Exit {
+                                // Dropping the core saves the game
+                                GamepieState::Init
+                            } else if item == PauseItem::Scaling {
+                                // Stays paused so the new mode can be seen,
+                                // and is used for games started after this
+                                let mode = crate::proxy::libretro::with_proxy(|p| {
+                                    let mode = p.scale_mode().next();
+                                    p.set_scale_mode(mode);
+                                    mode
+                                });
+                                if let Some(mode) = mode {
+                                    info!("Scaling: {:?}", mode);
+                                    self.config.video.scaling = mode;
+                                    self.settings.scaling = Some(mode);
+                                }
+                                GamepieState::Pause(core, state)
+                            } else if item == PauseItem::Performance {
+                                // Also shown in games started after this
+                                let hud = !self.config.video.hud;
+                                info!("Performance HUD: {}", hud);
+                                crate::proxy::libretro::with_proxy(|p| p.set_hud(hud));
+                                self.config.video.hud = hud;
+                                GamepieState::Pause(core, state)
+                            } else if item == PauseItem::CpuBoost {
+                                // Takes effect when the game is resumed
+                                let boost = !self.settings.cpu_boost;
+                                info!("CPU boost: {}", boost);
+                                self.settings.cpu_boost = boost;
+                                GamepieState::Pause(core, state)
+                            } else if item == PauseItem::Autosave {
+                                let interval = next_autosave(self.settings.autosave_interval);
+                                info!("Autosave every {:?}", interval);
+                                core.set_save_interval(interval);
+                                self.settings.autosave_interval = interval;
+                                GamepieState::Pause(core, state)
+                            } else if item == PauseItem::SaveSlots {
+                                let slots = core.save_slots();
+                                if slots.is_empty() {
+                                    let msg = ScreenMessage::Message(String::from("No saves yet"));
+                                    if !self.notifier.toast(ScreenToast::info(msg)) {
+                                        warn!("Failed to send save slots message");
+                                    }
+                                    GamepieState::Pause(core, state)
+                                } else {
+                                    info!("Gamepie State: Save Slots");
+                                    GamepieState::SaveSlots(core, slots, MenuState::default())
+                                }
+                            } else if item == PauseItem::Keyboard {
+                                info!("Gamepie State: Typing");
+                                GamepieState::Typing(core, String::new(), MenuState::default())
+                            } else if item == PauseItem::Record {
+                                self.toggle_recording(&mut core);
+                                GamepieState::Pause(core, state)
+                            } else if item == PauseItem::ResetOptions {
+                                info!("Resetting core options");
+                                crate::proxy::libretro::with_proxy(|p| p.reset_options());
+                                let msg = ScreenMessage::Message(String::from("Options reset"));
+                                if !self.notifier.toast(ScreenToast::info(msg)) {
+                                    warn!("Failed to send options reset message");
+                                }
+                                GamepieState::Pause(core, state)
+                            } else {
+                                info!("Gamepie State: Game");
+                                GamepieState::Game(core)
+                            }
+                        } else {
+                            let new_index = self.menu.safe_index(MenuSel::Pause, info.unsafe_index);
+                            GamepieState::Pause(core, MenuState::moved(new_index, &info))
+                        }
+                    }
                 }
             }
             Some(GamepieState::ExitGame) => GamepieState::ExitGame,
             Some(GamepieState::Error(error)) => {
                 error!("{}", error);
+                crate::crash::set_last_error(format!("{} {}", error.code_label(), error));
+                // Looked up now, as the address can change while running
+                let link = self.http_port.and_then(|port| {
+                    let ip = crate::mdns::ipv4_addrs().into_iter().next()?;
+                    Some(format!("http://{}:{}/diagnostics", ip, port))
+                });
+                self.menu
+                    .set_error_details(link, crate::crash::recent_log());
                 match crate::proxy::libretro::with_proxy(|p| {
                     self.menu.draw_error(p.borrow_screen(), error)?;
                     ok_res()
@@ -442,31 +1704,47 @@ impl Gamepie {
                     Some(res) => res?,
                     None => error!("Menu executed before proxy created"),
                 };
-                std::thread::sleep(ERROR_DURATION);
-                GamepieState::Init
+                GamepieState::ErrorShown(Instant::now() + ERROR_DURATION)
+            }
+            Some(GamepieState::ErrorShown(until)) => {
+                if self.request_exit.load(Ordering::Acquire) {
+                    GamepieState::ExitGame
+                } else if Instant::now() < until {
+                    GamepieState::ErrorShown(until)
+                } else {
+                    GamepieState::Init
+                }
             }
             None => GamepieState::Error(GamepieError::System),
         };
 
+        if self.low_battery.swap(false, Ordering::AcqRel) {
+            self.handle_low_battery(false);
+        }
+
         // Handle errors - only handle one error at a time, as the error
         // state will eventually loop through them all
         let error = match self.error_channel.try_recv() {
-            Ok(problem) => match problem {
-                Problem::Fatal(e) => {
-                    error!("{}", e);
-                    Some(e)
-                }
-                Problem::Warn(e) => {
-                    e.log();
-                    if self.toast_tx.send(e).is_err() {
-                        // If the rx for the screen has been dropped then the
-                        // screen may not be working.
-                        Some(GamepieError::NoVideo)
-                    } else {
-                        None
+            Ok(problem) => {
+                match problem {
+                    Problem::Fatal(e) => {
+                        error!("{}", e);
+                        self.notifier.fatal(&e);
+                        crate::crash::write_report(&format!("fatal error: {}", e));
+                        Some(e)
+                    }
+                    Problem::Warn(e) => {
+                        e.log();
+                        if !self.notifier.toast(e) {
+                            // If the rx for the screen has been dropped then the
+                            // screen may not be working.
+                            Some(GamepieError::NoVideo)
+                        } else {
+                            None
+                        }
                     }
                 }
-            },
+            }
             Err(e) => match e {
                 mpsc::TryRecvError::Empty => None,
                 mpsc::TryRecvError::Disconnected => {
@@ -482,9 +1760,815 @@ impl Gamepie {
             Some(e) => Some(GamepieState::Error(e)),
             None => Some(next_state),
         };
+        if let Some(state) = &self.state {
+            crate::crash::state(state.name());
+        }
+
+        if let Some(mqtt) = &self.mqtt {
+            match (&self.state, self.playing) {
+                (Some(GamepieState::Game(core)), false) => {
+                    mqtt.send(MqttEvent::GameStarted(String::from(core.game_name())));
+                    self.playing = true;
+                }
+                (Some(GamepieState::Game(_) | GamepieState::Pause(..)), true) => {}
+                (_, true) => {
+                    mqtt.send(MqttEvent::GameStopped);
+                    self.playing = false;
+                }
+                (_, false) => {}
+            }
+        }
+
+        // Fed each time round the loop while a core is loaded. It is only
+        // disarmed after the state has changed, so a core that hangs while
+        // being unloaded is caught too.
+        if let Some(watchdog) = &self.watchdog {
+            match &self.state {
+                Some(GamepieState::Game(core) | GamepieState::Pause(core, _)) => {
+                    watchdog.feed(core)
+                }
+                _ => watchdog.disarm(),
+            }
+        }
+
+        if let Some(governor) = &mut self.governor {
+            let boost = match &self.state {
+                Some(GamepieState::Game(_)) if self.settings.cpu_boost => {
+                    let level = crate::proxy::libretro::with_proxy(|p| p.performance_level())
+                        .flatten()
+                        .unwrap_or(0);
+                    level >= self.config.cpu.performance_level
+                }
+                _ => false,
+            };
+            governor.update(boost);
+        }
+
+        if let Some(msg) = self.storage.update(&self.config.dirs) {
+            let toast = ScreenToast::error(ScreenMessage::Message(msg));
+            if self.error_tx.send(Problem::warn(toast)).is_err() {
+                warn!("Failed to send low storage warning");
+            }
+        }
+
+        // Everything but a game is paced to the menu frame rate here, so
+        // the loop never blocks for longer than a frame and timed screens
+        // check their deadline each frame. Games pace themselves to the
+        // core's frame rate.
+        if !matches!(self.state, Some(GamepieState::Game(_))) {
+            if let Some(t) = MENU_FRAME_DURATION.checked_sub(start.elapsed()) {
+                std::thread::sleep(t);
+            }
+        }
         Ok(())
     }
 
+    // Type a search with the on-screen keyboard, the game list is filtered as
+    // each character is typed
+    fn search(&mut self, state: MenuState) -> Result<GamepieState, Box<dyn Error>> {
+        let input = crate::proxy::libretro::with_proxy(|p| {
+            self.menu.draw_search(p.borrow_screen(), state.index)?;
+            p.input_poll();
+            let pressed = KEYBOARD_BUTTONS.map(|b| p.input_state(0, b) == 1);
+            Ok::<_, Box<dyn Error>>(pressed)
+        });
+        let pressed = match input {
+            Some(res) => res?,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::ExitGame);
+        }
+        let back = self.request_back.swap(false, Ordering::AcqRel);
+
+        let mut query = String::from(self.menu.query());
+        let key = match keyboard_input(&state, pressed, back) {
+            KeyboardInput::Cursor(state) => return Ok(GamepieState::Search(state)),
+            // Back deletes, or leaves the search if there is nothing to delete
+            KeyboardInput::Back if query.is_empty() => Key::Done,
+            KeyboardInput::Back => Key::Delete,
+            KeyboardInput::Key(key) => key,
+        };
+        match key {
+            Key::Char(c) => query.push(c),
+            Key::Delete => {
+                query.pop();
+            }
+            Key::Done if self.menu.num_games() > 0 => {
+                info!("Gamepie State: Select Game");
+                return Ok(GamepieState::SelectGame(MenuState::default()));
+            }
+            Key::Done => {}
+        }
+        self.menu.set_query(&query);
+        Ok(GamepieState::Search(MenuState::new(state.index, true)))
+    }
+
+    // Type text into a paused game with the on-screen keyboard. The text is
+    // sent, followed by Return, once the game is running again.
+    fn typing(
+        &mut self,
+        core: Box<Core>,
+        mut text: String,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        let input = crate::proxy::libretro::with_proxy(|p| {
+            self.menu
+                .draw_typing(p.borrow_screen(), state.index, &text)?;
+            p.input_poll();
+            let pressed = KEYBOARD_BUTTONS.map(|b| p.input_state(0, b) == 1);
+            Ok::<_, Box<dyn Error>>(pressed)
+        });
+        let pressed = match input {
+            Some(res) => res?,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::Init);
+        }
+        let back = self.request_back.swap(false, Ordering::AcqRel);
+
+        match keyboard_input(&state, pressed, back) {
+            KeyboardInput::Cursor(state) => return Ok(GamepieState::Typing(core, text, state)),
+            KeyboardInput::Back if text.is_empty() => {
+                info!("Gamepie State: Pause");
+                return Ok(GamepieState::Pause(core, MenuState::default()));
+            }
+            KeyboardInput::Back | KeyboardInput::Key(Key::Delete) => {
+                text.pop();
+            }
+            KeyboardInput::Key(Key::Char(c)) => {
+                if text.len() < TYPING_MAX {
+                    text.push(c);
+                }
+            }
+            KeyboardInput::Key(Key::Done) => {
+                debug!("Typing '{}'", text);
+                text.push('\n');
+                crate::proxy::libretro::with_proxy(|p| p.type_text(&text));
+                info!("Gamepie State: Game");
+                return Ok(GamepieState::Game(core));
+            }
+        }
+        Ok(GamepieState::Typing(
+            core,
+            text,
+            MenuState::new(state.index, true),
+        ))
+    }
+
+    // Move on to the game list once the splash screen has been shown
+    fn enter_menu(&mut self) -> GamepieState {
+        if self.menu.num_games() == 0 {
+            return GamepieState::Error(GamepieError::NoGames);
+        }
+        info!("Gamepie State: Select Game");
+        self.menu.log();
+        // Pick up stats from the last game
+        self.stats = Stats::load(&self.config.dirs.stats_file());
+        self.menu.refresh_badges();
+        self.update_pinned();
+        // Carry on from the game last played
+        let index =
+            self.settings.last_game.as_ref().and_then(|last| {
+                (0..self.menu.num_games()).find(|i| self.menu.get_path(*i) == *last)
+            });
+        GamepieState::SelectGame(MenuState::new(index.unwrap_or(0), true))
+    }
+
+    // List the saves of the paused game, restoring the one chosen
+    fn save_slots(
+        &mut self,
+        mut core: Box<Core>,
+        slots: Vec<SaveSlot>,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        self.menu
+            .set_slots(slots.iter().map(|s| (s.name.clone(), s.age())).collect());
+        match crate::proxy::libretro::with_proxy(|p| {
+            self.menu
+                .draw_menu(p.borrow_screen(), MenuSel::Slots, state.index)
+        }) {
+            Some(res) => res?,
+            None => error!("Menu executed before proxy created"),
+        };
+
+        let info = match self.get_menu_info(&state, MenuSel::Slots) {
+            Some(info) => info,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            Ok(GamepieState::Init)
+        } else if info.back || self.request_back.swap(false, Ordering::AcqRel) {
+            info!("Gamepie State: Pause");
+            Ok(GamepieState::Pause(core, MenuState::default()))
+        } else if info.start_game {
+            let slot = &slots[state.index];
+            let toast = match core.restore_slot(slot) {
+                Ok(_) => {
+                    ScreenToast::info(ScreenMessage::Message(format!("Restored {}", slot.name)))
+                }
+                Err(e) => {
+                    warn!("Failed to restore '{}': {}", slot.path, e);
+                    ScreenToast::error(ScreenMessage::Message(String::from("Save not restored")))
+                }
+            };
+            if !self.notifier.toast(toast) {
+                warn!("Failed to send save slot message");
+            }
+            info!("Gamepie State: Game");
+            Ok(GamepieState::Game(core))
+        } else {
+            let new_index = self.menu.safe_index(MenuSel::Slots, info.unsafe_index);
+            Ok(GamepieState::SaveSlots(
+                core,
+                slots,
+                MenuState::moved(new_index, &info),
+            ))
+        }
+    }
+
+    fn recovery_items(&mut self) -> Vec<RecoveryItem> {
+        let mut items = vec![RecoveryItem::Continue, RecoveryItem::Restart];
+        items.extend(self.boot.backups().into_iter().map(RecoveryItem::Restore));
+        items.extend([RecoveryItem::ResetSettings, RecoveryItem::Shutdown]);
+        self.menu.set_recovery(
+            items
+                .iter()
+                .map(|i| {
+                    let age = match i {
+                        RecoveryItem::Restore(b) => Some(b.age),
+                        _ => None,
+                    };
+                    (String::from(i.label()), age)
+                })
+                .collect(),
+        );
+        items
+    }
+
+    // Recovery menu shown first in safe mode, to restore a backup of the
+    // config file or reset the settings, then restart without safe mode
+    fn recovery(
+        &mut self,
+        items: Vec<RecoveryItem>,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        match crate::proxy::libretro::with_proxy(|p| {
+            self.menu
+                .draw_menu(p.borrow_screen(), MenuSel::Recovery, state.index)
+        }) {
+            Some(res) => res?,
+            None => error!("Menu executed before proxy created"),
+        };
+
+        let info = match self.get_menu_info(&state, MenuSel::Recovery) {
+            Some(info) => info,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::ExitGame);
+        }
+        let back = info.back || self.request_back.swap(false, Ordering::AcqRel);
+        if !back && !info.start_game {
+            let new_index = self.menu.safe_index(MenuSel::Recovery, info.unsafe_index);
+            return Ok(GamepieState::Recovery(
+                items,
+                MenuState::moved(new_index, &info),
+            ));
+        }
+        let item = if back {
+            &RecoveryItem::Continue
+        } else {
+            &items[state.index]
+        };
+        match item {
+            RecoveryItem::Continue => {
+                self.menu.set_details(None);
+                // On to any crash report, then the game list
+                Ok(GamepieState::Splash(Instant::now()))
+            }
+            RecoveryItem::Restart => {
+                info!("Gamepie State: Exit (restart)");
+                self.boot.reset();
+                Ok(GamepieState::ExitGame)
+            }
+            RecoveryItem::Restore(backup) => match self.boot.restore(backup) {
+                Ok(_) => {
+                    info!("Gamepie State: Exit (restored config)");
+                    self.boot.reset();
+                    Ok(GamepieState::ExitGame)
+                }
+                Err(e) => {
+                    warn!("Failed to restore '{}': {}", backup.path.display(), e);
+                    let msg = ScreenMessage::Message(String::from("Config not restored"));
+                    if !self.notifier.toast(ScreenToast::error(msg)) {
+                        warn!("Failed to send restore message");
+                    }
+                    Ok(GamepieState::Recovery(
+                        items,
+                        MenuState::new(state.index, true),
+                    ))
+                }
+            },
+            RecoveryItem::ResetSettings => {
+                let path = self.config.dirs.settings_file();
+                let msg = match std::fs::remove_file(&path) {
+                    Ok(_) => {
+                        info!("Removed settings '{}'", path.display());
+                        "Settings reset"
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => "Settings reset",
+                    Err(e) => {
+                        warn!("Failed to remove '{}': {}", path.display(), e);
+                        "Settings not reset"
+                    }
+                };
+                self.settings = Settings::load(&path);
+                let toast = ScreenToast::info(ScreenMessage::Message(String::from(msg)));
+                if !self.notifier.toast(toast) {
+                    warn!("Failed to send settings message");
+                }
+                Ok(GamepieState::Recovery(
+                    items,
+                    MenuState::new(state.index, true),
+                ))
+            }
+            RecoveryItem::Shutdown => {
+                info!("Gamepie State: Exit ({:?})", PowerAction::Off);
+                self.power = Some(PowerAction::Off);
+                Ok(GamepieState::ExitGame)
+            }
+        }
+    }
+
+    // Shutdown or reboot from the game list. Saves are written when a game
+    // exits so only the settings are left, which are saved on exit.
+    fn power(
+        &mut self,
+        game_index: usize,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        match crate::proxy::libretro::with_proxy(|p| {
+            self.menu
+                .draw_menu(p.borrow_screen(), MenuSel::Power, state.index)
+        }) {
+            Some(res) => res?,
+            None => error!("Menu executed before proxy created"),
+        };
+
+        let info = match self.get_menu_info(&state, MenuSel::Power) {
+            Some(info) => info,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::ExitGame);
+        }
+        let back = info.back || self.request_back.swap(false, Ordering::AcqRel);
+        let action = match POWER_ITEMS.get(state.index) {
+            _ if back => None,
+            _ if !info.start_game => {
+                let new_index = self.menu.safe_index(MenuSel::Power, info.unsafe_index);
+                return Ok(GamepieState::Power(
+                    game_index,
+                    MenuState::moved(new_index, &info),
+                ));
+            }
+            // Plugin screens are listed after the menu's own items
+            None => {
+                let screen = state.index - POWER_ITEMS.len();
+                info!("Gamepie State: Plugin Screen {}", screen);
+                return Ok(GamepieState::PluginScreen(
+                    game_index,
+                    screen,
+                    MenuState::new(0, true),
+                ));
+            }
+            Some(PowerItem::Back) => None,
+            // Done once back at the game list
+            Some(PowerItem::Rescan) => {
+                self.rescan_pending = true;
+                None
+            }
+            Some(PowerItem::Shutdown) => Some(PowerAction::Off),
+            Some(PowerItem::Reboot) => Some(PowerAction::Reboot),
+        };
+        match action {
+            Some(action) => {
+                info!("Gamepie State: Exit ({:?})", action);
+                self.power = Some(action);
+                Ok(GamepieState::ExitGame)
+            }
+            None => {
+                info!("Gamepie State: Select Game");
+                Ok(GamepieState::SelectGame(MenuState::new(game_index, true)))
+            }
+        }
+    }
+
+    // List the items on a plugin's screen, asking for them each time as they
+    // can change, and pass on the one selected
+    fn plugin_screen(
+        &mut self,
+        game_index: usize,
+        screen: usize,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        self.menu
+            .set_plugin_items(self.plugins.screen_items(screen));
+        match crate::proxy::libretro::with_proxy(|p| {
+            self.menu
+                .draw_menu(p.borrow_screen(), MenuSel::Plugin, state.index)
+        }) {
+            Some(res) => res?,
+            None => error!("Menu executed before proxy created"),
+        };
+
+        let info = match self.get_menu_info(&state, MenuSel::Plugin) {
+            Some(info) => info,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::ExitGame);
+        }
+        if info.back || self.request_back.swap(false, Ordering::AcqRel) {
+            info!("Gamepie State: Power");
+            let index = POWER_ITEMS.len() + screen;
+            return Ok(GamepieState::Power(game_index, MenuState::new(index, true)));
+        }
+        // Items may have gone since the screen was drawn
+        if info.start_game && state.index < self.menu.num_items(MenuSel::Plugin) {
+            if let Some(msg) = self.plugins.screen_select(screen, state.index) {
+                if !self
+                    .notifier
+                    .toast(ScreenToast::info(ScreenMessage::Message(msg)))
+                {
+                    warn!("Failed to send plugin message");
+                }
+            }
+            return Ok(GamepieState::PluginScreen(
+                game_index,
+                screen,
+                MenuState::new(state.index, true),
+            ));
+        }
+        let new_index = self.menu.safe_index(MenuSel::Plugin, info.unsafe_index);
+        Ok(GamepieState::PluginScreen(
+            game_index,
+            screen,
+            MenuState::moved(new_index, &info),
+        ))
+    }
+
+    // Toggle the latency test's marker on each press of A and time how long
+    // the change takes to reach the screen
+    fn latency_test(
+        &mut self,
+        game_index: usize,
+        mut test: LatencyTest,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        let exit = crate::proxy::libretro::with_proxy(|p| {
+            p.input_poll();
+            let held = |b: RetroPadButton| p.input_state(0, b) == 1;
+            let exit = held(RetroPadButton::Start) && held(RetroPadButton::Select);
+            test.input(held(RetroPadButton::A), Instant::now());
+            let screen = p.borrow_screen();
+            test.update(screen.last_shown(), Instant::now());
+            self.menu
+                .draw_latency_test(screen, test.lit, &test.lines())?;
+            test.drawn(screen.frames_submitted());
+            Ok::<_, Box<dyn Error>>(exit)
+        });
+
+        let exit = match exit {
+            Some(exit) => exit?,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            test.log();
+            Ok(GamepieState::ExitGame)
+        } else if exit || self.request_back.swap(false, Ordering::AcqRel) {
+            test.log();
+            info!("Gamepie State: Select Game");
+            Ok(GamepieState::SelectGame(MenuState::new(game_index, true)))
+        } else {
+            Ok(GamepieState::LatencyTest(game_index, test))
+        }
+    }
+
+    // Offer to copy the report from the last crash to a USB drive, the
+    // report is kept in the crash directory either way
+    fn crash_report(
+        &mut self,
+        report: PathBuf,
+        state: MenuState,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        let drive = crate::crash::usb_drive();
+        let name = report
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let input = crate::proxy::libretro::with_proxy(|p| {
+            let drive = drive.as_ref().map(|d| d.to_string_lossy());
+            self.menu
+                .draw_crash_report(p.borrow_screen(), &name, drive.as_deref())?;
+            p.input_poll();
+            let pressed = [RetroPadButton::A, RetroPadButton::B].map(|b| p.input_state(0, b) == 1);
+            Ok::<_, Box<dyn Error>>(pressed)
+        });
+        let [a, b] = match input {
+            Some(res) => res?,
+            None => return Ok(GamepieState::Error(GamepieError::System)),
+        };
+        if self.request_exit.load(Ordering::Acquire) {
+            return Ok(GamepieState::ExitGame);
+        }
+        let back = self.request_back.swap(false, Ordering::AcqRel);
+        if state.pressed && !back {
+            return Ok(GamepieState::CrashReport(report, MenuState::new(0, a || b)));
+        }
+        match (a, drive) {
+            (true, Some(drive)) => {
+                let msg = match crate::crash::copy_to(&report, &drive) {
+                    Ok(_) => ScreenToast::info(ScreenMessage::Message(String::from(
+                        "Crash report copied",
+                    ))),
+                    Err(e) => {
+                        warn!("Failed to copy crash report: {}", e);
+                        ScreenToast::error(ScreenMessage::Message(String::from(
+                            "Crash report not copied",
+                        )))
+                    }
+                };
+                if !self.notifier.toast(msg) {
+                    warn!("Failed to send crash report message");
+                }
+            }
+            // Nothing to copy to yet
+            (true, None) => return Ok(GamepieState::CrashReport(report, MenuState::new(0, true))),
+            (false, _) if !b && !back => {
+                return Ok(GamepieState::CrashReport(report, MenuState::default()))
+            }
+            (false, _) => {}
+        }
+        crate::crash::clear_pending(&self.config.dirs.crashes());
+        Ok(self.enter_menu())
+    }
+
+    // Start party mode with the games marked in the menu
+    fn start_party(&mut self) -> Result<GamepieState, Box<dyn Error>> {
+        let games = self.menu.marked_paths();
+        self.menu.clear_marks();
+        info!(
+            "Starting party with {} games, {:?} each",
+            games.len(),
+            self.config.party.turn
+        );
+        self.party = Some(Party::new(games, self.config.party.turn));
+        self.load_party_game()
+    }
+
+    // Load the game whose turn it is in party mode, carrying on from where it
+    // was left last time round
+    fn load_party_game(&mut self) -> Result<GamepieState, Box<dyn Error>> {
+        let path = match &self.party {
+            Some(party) => String::from(party.current()),
+            None => return Ok(GamepieState::Init),
+        };
+        let cinfo = self.get_cores_for_game(&path).into_iter().next();
+        let cinfo = match cinfo {
+            _ if !Path::new(&path).is_file() => {
+                self.party = None;
+                return Ok(GamepieState::Error(GamepieError::GameMissing));
+            }
+            Some(c) => c,
+            None => {
+                self.party = None;
+                return Ok(GamepieState::Error(GamepieError::NoCore));
+            }
+        };
+        info!("Party game: {}", path);
+        self.start_loading(cinfo, &path, true)
+    }
+
+    // Load a game on a worker thread, the screen shows progress until the
+    // core is ready
+    fn start_loading(
+        &mut self,
+        cinfo: CoreInfo,
+        game: &str,
+        party: bool,
+    ) -> Result<GamepieState, Box<dyn Error>> {
+        let audio = crate::proxy::audio::get();
+        Core::create_proxy(
+            &self.config,
+            &mut self.screen,
+            self.error_tx.clone(),
+            audio.clone(),
+        )?;
+        let path = PathBuf::from(game);
+        crate::crash::running(Some(&cinfo), Some(&path));
+        let config = self.config.clone();
+        let error_tx = self.error_tx.clone();
+        let trace_env = self.trace_env;
+        let preloaded = self.preload.take(&path);
+        let loading = Loading::spawn(Path::new(game), party, move || {
+            Core::new(cinfo, &path, &config, error_tx, audio, trace_env, preloaded)
+        })?;
+        info!("Gamepie State: Loading");
+        Ok(GamepieState::Loading(Box::new(loading)))
+    }
+
+    // Start the loaded game, party games carry on from their saved state
+    fn finish_loading(&mut self, loading: Loading) -> GamepieState {
+        let game = loading.game().to_path_buf();
+        let party = loading.is_party();
+        match loading.join() {
+            Ok(mut core) => {
+                if party {
+                    if let Err(e) = core.load_state_file(&self.config.dirs) {
+                        warn!("Failed to load party state: {}", e);
+                    }
+                    if let Some(party) = &mut self.party {
+                        party.start_turn();
+                    }
+                }
+                self.record_play(&game);
+                core.set_save_interval(self.settings.autosave_interval);
+                info!("Gamepie State: Game");
+                GamepieState::Game(Box::new(core))
+            }
+            Err(e) => {
+                if party {
+                    self.party = None;
+                }
+                GamepieState::Error(e)
+            }
+        }
+    }
+
+    fn record_play(&mut self, game: &Path) {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.history.played(game, time);
+        self.history.save();
+        self.settings.last_game = Some(game.to_string_lossy().into_owned());
+    }
+
+    // Show the recent and favourite games at the top of the game list
+    fn update_pinned(&mut self) {
+        let recent = self.history.recent(RECENT_GAMES);
+        self.menu.set_pinned(&recent, &self.history.favourites());
+    }
+
+    fn toggle_favourite(&mut self, index: usize) {
+        let path = self.menu.get_path(index);
+        if path.is_empty() {
+            return;
+        }
+        let msg = if self.history.toggle_favourite(Path::new(&path)) {
+            "Added to favourites"
+        } else {
+            "Removed from favourites"
+        };
+        self.history.save();
+        self.update_pinned();
+        let toast = ScreenToast::info(ScreenMessage::Message(String::from(msg)));
+        if !self.notifier.toast(toast) {
+            warn!("Failed to send favourite message");
+        }
+    }
+
+    // Warn that the battery is low, once any game has been saved, and exit
+    // to power off if configured to
+    // Write the save for an event if it is configured to, with a save state
+    // if asked for. Returns whether the game was saved.
+    fn autosave(&self, core: &mut Core, event: AutosaveEvent) -> bool {
+        if !self.config.autosave.on.contains(&event) {
+            return false;
+        }
+        debug!("Saving for {:?}", event);
+        core.flush_save();
+        if self.config.autosave.state {
+            if let Err(e) = core.save_state_file(&self.config.dirs) {
+                warn!("Failed to save state: {}", e);
+            }
+        }
+        true
+    }
+
+    fn handle_low_battery(&mut self, saved: bool) {
+        warn!("Low battery");
+        let shutdown = self
+            .config
+            .gpio
+            .low_battery
+            .as_ref()
+            .is_some_and(|b| b.shutdown);
+        let msg = match (saved, shutdown) {
+            (_, true) => "Low battery, shutting down",
+            (true, false) => "Low battery, game saved",
+            (false, false) => "Low battery",
+        };
+        let toast = ScreenToast::error(ScreenMessage::Message(String::from(msg)));
+        if !self.notifier.toast(toast) {
+            warn!("Failed to send low battery toast");
+        }
+        if shutdown {
+            self.power = Some(PowerAction::Off);
+            self.request_exit.store(true, Ordering::Release);
+        }
+    }
+
+    fn party_countdown(&self, secs: u64) {
+        let msg = if secs >= 60 {
+            format!("Next game in {} min", secs / 60)
+        } else {
+            format!("Next game in {}s", secs)
+        };
+        let toast = ScreenToast::new(
+            ScreenMessage::Message(msg),
+            PARTY_TOAST_DURATION,
+            INFO_COLOUR,
+        );
+        if !self.notifier.toast(toast) {
+            warn!("Failed to send party countdown");
+        }
+    }
+
+    fn handle_hotkey(&self, core: &mut Core, hotkey: Hotkey) {
+        debug!("Hotkey: {:?}", hotkey);
+        let toast = match hotkey {
+            Hotkey::QuickSave => match core.quick_save() {
+                Ok(_) => ScreenToast::info(ScreenMessage::Message(String::from("Quick saved"))),
+                Err(_) => {
+                    ScreenToast::error(ScreenMessage::Message(String::from("Quick save failed")))
+                }
+            },
+            Hotkey::QuickLoad => match core.quick_load() {
+                Ok(_) => ScreenToast::info(ScreenMessage::Message(String::from("Quick loaded"))),
+                Err(_) => {
+                    ScreenToast::error(ScreenMessage::Message(String::from("Quick load failed")))
+                }
+            },
+            // Applied each frame while held
+            Hotkey::Rewind => return,
+            Hotkey::FastForward => {
+                let speed = core.fast_forward_speed();
+                let msg = if speed < self.config.fast_forward.max_speed {
+                    format!("Fast-forward {}x, running hot", speed)
+                } else {
+                    format!("Fast-forward {}x", speed)
+                };
+                ScreenToast::info(ScreenMessage::Message(msg))
+            }
+            // Handled by the game loop, as it changes state
+            Hotkey::Pause => return,
+            Hotkey::Plugin(button) => match self.plugins.hotkey(button) {
+                Some(msg) => ScreenToast::info(ScreenMessage::Message(msg)),
+                None => return,
+            },
+            Hotkey::AirplaneMode => {
+                let on = !crate::radio::airplane_mode();
+                match crate::radio::set_airplane_mode(on) {
+                    Ok(_) => {
+                        let msg = if on { "Airplane mode on" } else { "Radios on" };
+                        ScreenToast::info(ScreenMessage::Message(String::from(msg)))
+                    }
+                    Err(e) => {
+                        warn!("Failed to set airplane mode: {}", e);
+                        ScreenToast::error(ScreenMessage::Message(String::from(
+                            "Airplane mode failed",
+                        )))
+                    }
+                }
+            }
+            Hotkey::MusicMode | Hotkey::ScreenOff => {
+                let off = !self.screen_off.fetch_xor(true, Ordering::AcqRel);
+                // Either hotkey turns the screen back on, only music mode
+                // tells the core it doesn't need to render
+                let music = off && hotkey == Hotkey::MusicMode;
+                if music {
+                    info!("Music mode on");
+                } else {
+                    info!("Screen {}", if off { "off" } else { "on" });
+                }
+                crate::proxy::libretro::with_proxy(|p| {
+                    p.set_video_enabled(!music);
+                    p.set_display_enabled(!off);
+                });
+                if off {
+                    // Nothing to see with the screen off
+                    return;
+                }
+                ScreenToast::info(ScreenMessage::Message(String::from("Screen on")))
+            }
+        };
+        if !self.notifier.toast(toast) {
+            warn!("Failed to send hotkey toast");
+        }
+    }
+
     fn main_loop(&mut self) -> Result<(), Box<dyn Error>> {
         loop {
             match self.state {
@@ -502,19 +2586,62 @@ impl Gamepie {
                     error!("GPIO thread panicked");
                 }
             }
-            None => error!("No GPIO thread"),
+            None => {
+                if !self.desktop {
+                    error!("No GPIO thread");
+                }
+            }
+        }
+        if let Some(mqtt) = &mut self.mqtt {
+            debug!("Waiting for MQTT thread");
+            mqtt.join();
+        }
+        if let Some(netplay) = &mut self.netplay {
+            debug!("Waiting for netplay thread");
+            netplay.join();
+        }
+        if let Some(library) = &mut self.library {
+            debug!("Waiting for library thread");
+            library.join();
         }
+        if let Some(watcher) = &mut self.rom_watcher {
+            debug!("Waiting for ROM watcher");
+            watcher.join();
+        }
+        // Puts back the governors there were before gamepie started
+        self.governor = None;
+
+        // Settings are left as they were before safe mode
+        if !self.safe {
+            if let Some(volume) = crate::proxy::audio::volume() {
+                self.settings.volume = Some(volume);
+            }
+            self.settings.save();
+        }
+        // Stopping on purpose isn't a crash
+        self.boot.reset();
 
         debug!("Reclaiming screen");
-        self.screen = crate::proxy::libretro::destroy();
+        crate::proxy::libretro::destroy(&mut self.screen);
+        if let Some(to) = self.screen.lent_to() {
+            warn!("Screen still lent to {}", to);
+        }
 
         info!("Shutting down");
+        match self.power {
+            Some(action) if self.desktop => info!("Not running {:?} on the desktop", action),
+            Some(action) => action.run(),
+            None => {}
+        }
         Ok(())
     }
 
     pub fn run(mut self) -> Result<(), Box<dyn Error>> {
         debug!("Starting gamepie");
-        self.main_loop()?;
+        if let Err(e) = self.main_loop() {
+            crate::crash::write_report(&format!("error: {}", e));
+            return Err(e);
+        }
         Ok(())
     }
 }
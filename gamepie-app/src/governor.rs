@@ -0,0 +1,154 @@
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
+
+use gamepie_core::config::CpuConfig;
+
+const CPUFREQ_DIR: &str = "/sys/devices/system/cpu/cpufreq";
+
+// The governor files of a cpufreq policy, and the governor it had before
+struct Policy {
+    path: PathBuf,
+    original: String,
+}
+
+fn read(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Switches the CPU governor between the menus and demanding games, so the
+/// Pi runs at full speed for heavier cores without staying hot in the menus.
+/// The governors there were before are put back when dropped.
+pub(crate) struct Governor {
+    policies: Vec<Policy>,
+    game: String,
+    menu: String,
+    boosted: Option<bool>,
+    // Writing needs root, only warned about once
+    failed: bool,
+}
+
+impl Governor {
+    pub fn start(config: &CpuConfig) -> Option<Self> {
+        Self::open(config, Path::new(CPUFREQ_DIR))
+    }
+
+    // Policies in `dir` that have both governors
+    fn open(config: &CpuConfig, dir: &Path) -> Option<Self> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                debug!("No cpufreq policies in '{}': {}", dir.display(), e);
+                return None;
+            }
+        };
+        let mut policies = Vec::new();
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with("policy") {
+                continue;
+            }
+            let policy = entry.path();
+            let available = read(&policy.join("scaling_available_governors")).unwrap_or_default();
+            let has = |g: &str| available.split_whitespace().any(|a| a == g);
+            if !has(&config.game_governor) || !has(&config.menu_governor) {
+                warn!(
+                    "CPU governors '{}' and '{}' not both available for {}, have: {}",
+                    config.game_governor,
+                    config.menu_governor,
+                    entry.file_name().to_string_lossy(),
+                    available
+                );
+                continue;
+            }
+            let path = policy.join("scaling_governor");
+            if let Some(original) = read(&path) {
+                policies.push(Policy { path, original });
+            }
+        }
+        if policies.is_empty() {
+            return None;
+        }
+        policies.sort_by(|a, b| a.path.cmp(&b.path));
+        Some(Governor {
+            policies,
+            game: config.game_governor.clone(),
+            menu: config.menu_governor.clone(),
+            boosted: None,
+            failed: false,
+        })
+    }
+
+    fn set(&mut self, governor: impl Fn(&Policy) -> String) {
+        for policy in &self.policies {
+            if let Err(e) = std::fs::write(&policy.path, governor(policy)) {
+                warn!(
+                    "Failed to set CPU governor '{}': {}",
+                    policy.path.display(),
+                    e
+                );
+                self.failed = true;
+                return;
+            }
+        }
+    }
+
+    /// Use the game governor if `boost` is set, otherwise the menu one.
+    /// Only written when this changes.
+    pub fn update(&mut self, boost: bool) {
+        if self.failed || self.boosted == Some(boost) {
+            return;
+        }
+        self.boosted = Some(boost);
+        let governor = if boost { &self.game } else { &self.menu }.clone();
+        info!("CPU governor: {}", governor);
+        self.set(|_| governor.clone());
+    }
+}
+
+impl Drop for Governor {
+    fn drop(&mut self) {
+        if self.boosted.is_some() && !self.failed {
+            debug!("Restoring CPU governors");
+            self.set(|p| p.original.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, Governor};
+    use gamepie_core::config::CpuConfig;
+    use gamepie_core::testing::TestDir;
+
+    #[test]
+    fn switches() {
+        let dir = TestDir::new("governor");
+        let dir = dir.path();
+        let policy = dir.join("policy0");
+        std::fs::create_dir_all(&policy).unwrap();
+        std::fs::write(
+            policy.join("scaling_available_governors"),
+            "conservative ondemand userspace powersave performance schedutil\n",
+        )
+        .unwrap();
+        let current = policy.join("scaling_governor");
+        std::fs::write(&current, "schedutil\n").unwrap();
+
+        let config = CpuConfig::default();
+        let mut governor = Governor::open(&config, dir).unwrap();
+        governor.update(true);
+        assert_eq!(read(&current).unwrap(), "performance");
+        governor.update(false);
+        assert_eq!(read(&current).unwrap(), "ondemand");
+        drop(governor);
+        assert_eq!(read(&current).unwrap(), "schedutil");
+
+        // Not used without the governors wanted
+        let config = CpuConfig {
+            game_governor: String::from("turbo"),
+            ..CpuConfig::default()
+        };
+        assert!(Governor::open(&config, dir).is_none());
+    }
+}
@@ -1,5 +1,15 @@
-use rppal::gpio::{InputPin, Level, OutputPin};
+use log::{error, info, warn};
+use rppal::gpio::{InputPin, Level, OutputPin, Trigger};
 use std::error::Error;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use gamepie_controller::button_from_str;
+use gamepie_core::config::{
+    EncoderConfig, EncoderControl, GpioConfig, GpioPadConfig, LowBatteryConfig,
+};
+use gamepie_libretrobind::enums::RetroPadButton;
 
 const BUTTON_A: u8 = 5;
 const BUTTON_B: u8 = 6;
@@ -9,6 +19,24 @@ const BUTTON_Y: u8 = 24;
 const LED_BACKLIGHT: u8 = 13;
 const AUDIO_ENABLE: u8 = 25;
 
+// Edges closer together than this are contact bounce
+const ENCODER_DEBOUNCE: Duration = Duration::from_millis(2);
+// Turning the wheel quickly moves further per detent
+const ENCODER_FAST: Duration = Duration::from_millis(40);
+const ENCODER_MEDIUM: Duration = Duration::from_millis(100);
+
+const BACKLIGHT_PWM_FREQUENCY: f64 = 200.0;
+const BRIGHTNESS_STEP: f32 = 0.05;
+// Don't allow the backlight to be turned fully off with the wheel
+const BRIGHTNESS_MIN: f32 = 0.1;
+
+/// How often the buttons are read when they are used as a RetroPad
+pub const PAD_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// The low battery signal must be held this long to count, as monitors can
+// dip briefly under load
+const LOW_BATTERY_CONFIRM: Duration = Duration::from_secs(1);
+
 pub struct GpioValue {
     pub a: bool,
     pub b: bool,
@@ -22,6 +50,169 @@ impl GpioValue {
     }
 }
 
+// Quadrature rotary encoder, steps are accumulated from an interrupt on
+// pin A and collected by the GPIO thread.
+struct Encoder {
+    // Kept to hold the interrupt, pin B is owned by the callback
+    _a: InputPin,
+    steps: Arc<AtomicI32>,
+    control: EncoderControl,
+}
+
+impl Encoder {
+    fn new(gpio: &rppal::gpio::Gpio, config: &EncoderConfig) -> Result<Self, Box<dyn Error>> {
+        let mut a = gpio.get(config.a)?.into_input_pullup();
+        let b = gpio.get(config.b)?.into_input_pullup();
+        let steps = Arc::new(AtomicI32::new(0));
+        let s2 = steps.clone();
+        let mut last = Instant::now();
+
+        a.set_async_interrupt(Trigger::FallingEdge, move |_| {
+            let now = Instant::now();
+            let interval = now - last;
+            if interval < ENCODER_DEBOUNCE {
+                return;
+            }
+            last = now;
+
+            let accel = if interval < ENCODER_FAST {
+                4
+            } else if interval < ENCODER_MEDIUM {
+                2
+            } else {
+                1
+            };
+            // B leads A when turning clockwise
+            let step = if b.read() == Level::High {
+                accel
+            } else {
+                -accel
+            };
+            s2.fetch_add(step, Ordering::AcqRel);
+        })?;
+
+        info!(
+            "Rotary encoder on pins {}/{} ({:?})",
+            config.a, config.b, config.control
+        );
+        Ok(Encoder {
+            _a: a,
+            steps,
+            control: config.control,
+        })
+    }
+}
+
+// Low battery signal from a battery monitor, polled by the GPIO thread
+struct LowBattery {
+    pin: InputPin,
+    active: Level,
+    since: Option<Instant>,
+    reported: bool,
+}
+
+impl LowBattery {
+    fn new(gpio: &rppal::gpio::Gpio, config: &LowBatteryConfig) -> Result<Self, Box<dyn Error>> {
+        let pin = gpio.get(config.pin)?;
+        // Pulled to the inactive level so an unconnected pin isn't low battery
+        let (pin, active) = if config.active_high {
+            (pin.into_input_pulldown(), Level::High)
+        } else {
+            (pin.into_input_pullup(), Level::Low)
+        };
+        info!("Low battery signal on pin {}", config.pin);
+        Ok(LowBattery {
+            pin,
+            active,
+            since: None,
+            reported: false,
+        })
+    }
+
+    // True once each time the battery becomes low
+    fn poll(&mut self) -> bool {
+        if self.pin.read() != self.active {
+            self.since = None;
+            self.reported = false;
+            return false;
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if !self.reported && since.elapsed() >= LOW_BATTERY_CONFIRM {
+            self.reported = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// First time the light level changed at the photodiode since it was last
+/// cleared, set from its interrupt and read by the latency test
+#[derive(Clone, Default)]
+pub struct LightEdge(Arc<Mutex<Option<Instant>>>);
+
+impl LightEdge {
+    fn lock(&self) -> MutexGuard<'_, Option<Instant>> {
+        match self.0.lock() {
+            Ok(g) => g,
+            Err(e) => {
+                error!("Poisoned mutex for photodiode");
+                e.into_inner()
+            }
+        }
+    }
+
+    /// Forget any change seen so far
+    pub fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    /// When the light level first changed since it was cleared
+    pub fn take(&self) -> Option<Instant> {
+        self.lock().take()
+    }
+}
+
+// Photodiode (through a comparator) over the latency test's marker, either
+// edge is a change in brightness
+struct Photodiode {
+    // Kept to hold the interrupt
+    _pin: InputPin,
+    edge: LightEdge,
+}
+
+impl Photodiode {
+    fn new(gpio: &rppal::gpio::Gpio, pin: u8) -> Result<Self, Box<dyn Error>> {
+        let mut input = gpio.get(pin)?.into_input();
+        let edge = LightEdge::default();
+        let e2 = edge.clone();
+        input.set_async_interrupt(Trigger::Both, move |_| {
+            let now = Instant::now();
+            let mut first = e2.lock();
+            if first.is_none() {
+                *first = Some(now);
+            }
+        })?;
+        info!("Photodiode on pin {}", pin);
+        Ok(Photodiode { _pin: input, edge })
+    }
+}
+
+/// Whether the Y button is held, checked while starting to start in safe
+/// mode
+pub fn safe_mode_held() -> bool {
+    let held = || -> Result<bool, Box<dyn Error>> {
+        let pin = rppal::gpio::Gpio::new()?.get(BUTTON_Y)?.into_input_pullup();
+        // Give the pull up time to settle
+        std::thread::sleep(Duration::from_millis(1));
+        Ok(pin.read() == Level::Low)
+    };
+    held().unwrap_or_else(|e| {
+        warn!("Failed to read safe mode button: {}", e);
+        false
+    })
+}
+
 pub struct Gpio {
     a: InputPin,
     b: InputPin,
@@ -29,6 +220,22 @@ pub struct Gpio {
     y: InputPin,
     backlight: OutputPin,
     audio_en: OutputPin,
+    encoder: Option<Encoder>,
+    low_battery: Option<LowBattery>,
+    // RetroPad buttons for A, B, X and Y, when used as a RetroPad
+    pad: Option<[Option<RetroPadButton>; 4]>,
+    photodiode: Option<Photodiode>,
+    brightness: f32,
+}
+
+fn pad_buttons(config: &GpioPadConfig) -> [Option<RetroPadButton>; 4] {
+    [&config.a, &config.b, &config.x, &config.y].map(|name| {
+        let button = button_from_str(name);
+        if button.is_none() {
+            warn!("Unknown RetroPad button '{}' for GPIO", name);
+        }
+        button
+    })
 }
 
 impl Gpio {
@@ -42,7 +249,77 @@ impl Gpio {
         GpioValue { a, b, x, y }
     }
 
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    /// RetroPad buttons held, if the buttons are used as a RetroPad rather
+    /// than for volume and back
+    pub fn pad(&self, value: &GpioValue) -> Option<Vec<RetroPadButton>> {
+        let held = [value.a, value.b, value.x, value.y];
+        self.pad.map(|pad| {
+            pad.into_iter()
+                .zip(held)
+                .filter_map(|(button, held)| button.filter(|_| held))
+                .collect()
+        })
+    }
+
+    /// Changes seen by the photodiode, if there is one
+    pub fn light_edge(&self) -> Option<LightEdge> {
+        self.photodiode.as_ref().map(|p| p.edge.clone())
+    }
+
+    /// Take the steps the encoder has turned since the last call, positive
+    /// is clockwise. Returns `None` if there is no encoder.
+    pub fn encoder_steps(&self) -> Option<(EncoderControl, i32)> {
+        self.encoder
+            .as_ref()
+            .map(|e| (e.control, e.steps.swap(0, Ordering::AcqRel)))
+    }
+
+    /// Check the battery monitor, true once each time the battery becomes
+    /// low
+    pub fn low_battery(&mut self) -> bool {
+        self.low_battery.as_mut().is_some_and(|b| b.poll())
+    }
+
+    /// Adjust the backlight brightness by a number of steps, returning the
+    /// new brightness.
+    pub fn adjust_brightness(&mut self, steps: i32) -> f32 {
+        let brightness =
+            (self.brightness + steps as f32 * BRIGHTNESS_STEP).clamp(BRIGHTNESS_MIN, 1.0);
+        if brightness != self.brightness {
+            let result = if brightness >= 1.0 {
+                self.backlight
+                    .clear_pwm()
+                    .map(|_| self.backlight.set_high())
+            } else {
+                self.backlight
+                    .set_pwm_frequency(BACKLIGHT_PWM_FREQUENCY, brightness as f64)
+            };
+            match result {
+                Ok(_) => self.brightness = brightness,
+                Err(e) => warn!("Failed to set backlight brightness: {}", e),
+            }
+        }
+        self.brightness
+    }
+
+    /// Turn the backlight off, or back on at the current brightness
+    pub fn set_backlight(&mut self, on: bool) {
+        let result = if !on {
+            self.backlight.clear_pwm().map(|_| self.backlight.set_low())
+        } else if self.brightness >= 1.0 {
+            self.backlight
+                .clear_pwm()
+                .map(|_| self.backlight.set_high())
+        } else {
+            self.backlight
+                .set_pwm_frequency(BACKLIGHT_PWM_FREQUENCY, self.brightness as f64)
+        };
+        if let Err(e) = result {
+            warn!("Failed to set backlight: {}", e);
+        }
+    }
+
+    pub fn new(config: &GpioConfig) -> Result<Self, Box<dyn Error>> {
         let gpio = rppal::gpio::Gpio::new()?;
         let a_pin = gpio.get(BUTTON_A)?;
         let b_pin = gpio.get(BUTTON_B)?;
@@ -50,6 +327,18 @@ impl Gpio {
         let y_pin = gpio.get(BUTTON_Y)?;
         let backlight = gpio.get(LED_BACKLIGHT)?;
         let audio_en = gpio.get(AUDIO_ENABLE)?;
+        let encoder = match &config.encoder {
+            Some(e) => Some(Encoder::new(&gpio, e)?),
+            None => None,
+        };
+        let low_battery = match &config.low_battery {
+            Some(b) => Some(LowBattery::new(&gpio, b)?),
+            None => None,
+        };
+        let photodiode = match config.photodiode {
+            Some(pin) => Some(Photodiode::new(&gpio, pin)?),
+            None => None,
+        };
         Ok(Gpio {
             a: a_pin.into_input_pullup(),
             b: b_pin.into_input_pullup(),
@@ -57,12 +346,20 @@ impl Gpio {
             y: y_pin.into_input_pullup(),
             backlight: backlight.into_output_high(),
             audio_en: audio_en.into_output_high(),
+            encoder,
+            low_battery,
+            pad: config.pad.as_ref().map(pad_buttons),
+            photodiode,
+            brightness: 1.0,
         })
     }
 }
 
 impl Drop for Gpio {
     fn drop(&mut self) {
+        if self.backlight.clear_pwm().is_err() {
+            warn!("Failed to stop backlight PWM");
+        }
         self.backlight.write(Level::Low);
         self.audio_en.write(Level::Low)
     }
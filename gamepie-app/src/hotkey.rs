@@ -0,0 +1,96 @@
+use log::warn;
+
+use gamepie_libretrobind::enums::RetroPadButton;
+
+/// Actions triggered by holding Select and pressing another button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Hotkey {
+    QuickSave,
+    QuickLoad,
+    /// Turn off the screen, leaving audio playing
+    MusicMode,
+    /// Turn off the screen, leaving the game running as normal
+    ScreenOff,
+    /// Step back through recent states while held
+    Rewind,
+    /// Run faster than normal while held
+    FastForward,
+    /// Turn all radios off, or back on
+    AirplaneMode,
+    /// Stop the game and show the pause menu over it
+    Pause,
+    /// Handled by a plugin
+    Plugin(RetroPadButton),
+}
+
+const HOTKEY_MODIFIER: RetroPadButton = RetroPadButton::Select;
+const HOTKEYS: &[(RetroPadButton, Hotkey)] = &[
+    (RetroPadButton::L, Hotkey::QuickSave),
+    (RetroPadButton::R, Hotkey::QuickLoad),
+    (RetroPadButton::X, Hotkey::MusicMode),
+    (RetroPadButton::Y, Hotkey::ScreenOff),
+    (RetroPadButton::Left, Hotkey::Rewind),
+    (RetroPadButton::Right, Hotkey::FastForward),
+    (RetroPadButton::B, Hotkey::AirplaneMode),
+    (RetroPadButton::Start, Hotkey::Pause),
+];
+
+pub(crate) struct Hotkeys {
+    // Hotkey that was active on the last check, to only trigger on press
+    held: Option<Hotkey>,
+    plugins: Vec<RetroPadButton>,
+}
+
+impl Hotkeys {
+    pub fn new() -> Self {
+        Hotkeys {
+            held: None,
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Add hotkeys handled by a plugin, on buttons that aren't in use
+    pub fn add_plugin_hotkeys(&mut self, buttons: Vec<RetroPadButton>) {
+        for button in buttons {
+            let used = button == HOTKEY_MODIFIER
+                || HOTKEYS.iter().any(|(b, _)| *b == button)
+                || self.plugins.contains(&button);
+            if used {
+                warn!("Hotkey {:?} is already in use", button);
+            } else {
+                self.plugins.push(button);
+            }
+        }
+    }
+
+    /// Check the current controller state for a newly pressed hotkey, uses
+    /// the state from the last input poll by the core.
+    pub fn check(&mut self) -> Option<Hotkey> {
+        let current = crate::proxy::libretro::with_proxy(|p| {
+            if p.input_state(0, HOTKEY_MODIFIER) == 1 {
+                HOTKEYS
+                    .iter()
+                    .find(|(b, _)| p.input_state(0, *b) == 1)
+                    .map(|(_, h)| *h)
+                    .or_else(|| {
+                        self.plugins
+                            .iter()
+                            .find(|b| p.input_state(0, **b) == 1)
+                            .map(|b| Hotkey::Plugin(*b))
+                    })
+            } else {
+                None
+            }
+        })
+        .flatten();
+
+        let pressed = if current != self.held { current } else { None };
+        self.held = current;
+        pressed
+    }
+
+    /// Hotkey held at the last check
+    pub fn held(&self) -> Option<Hotkey> {
+        self.held
+    }
+}
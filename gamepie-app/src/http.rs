@@ -0,0 +1,490 @@
+use log::{debug, error, info, warn};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use gamepie_core::dirs::{self, GamepieDirs};
+
+use crate::jpeg::JpegEncoder;
+use crate::json::{self, JsonValue};
+use crate::pipe::PipeCommand;
+
+// Small HTTP/1.1 server for managing gamepie with a browser or curl:
+//   GET  /games               games in the ROM directories, as JSON
+//   PUT  /roms/<file>         upload a ROM to the first ROM directory
+//   POST /launch              start a game, {"path": "game.gb", "core": "Gambatte"}
+//   POST /stop                stop the game
+//   GET  /screenshot          the last frame the game drew, as a JPEG
+//   GET  /screenshots         saved screenshots and recordings, as JSON
+//   GET  /screenshots/<file>  download one of them
+//   GET  /log                 recent log lines
+// Requests are handled one at a time, one per connection.
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_LINES: usize = 64;
+const MAX_UPLOAD_LEN: u64 = 1 << 30;
+const MAX_JSON_LEN: u64 = 4096;
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    // Decoded, without any query string
+    path: String,
+    length: u64,
+    expect_continue: bool,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(File, u64),
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Body,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            content_type,
+            body: Body::Bytes(body),
+        }
+    }
+
+    fn text(status: u16, text: &str) -> Self {
+        Self::new(
+            status,
+            "text/plain; charset=utf-8",
+            format!("{}\n", text).into_bytes(),
+        )
+    }
+
+    fn json(text: String) -> Self {
+        Self::new(200, "application/json", text.into_bytes())
+    }
+
+    fn write_to<W: Write>(self, out: &mut W) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        let len = match &self.body {
+            Body::Bytes(b) => b.len() as u64,
+            Body::File(_, len) => *len,
+        };
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status, reason, self.content_type, len
+        )?;
+        match self.body {
+            Body::Bytes(b) => out.write_all(&b)?,
+            Body::File(f, len) => {
+                io::copy(&mut f.take(len), out)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Files can only be named directly in a directory
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\0'])
+}
+
+// Files in a directory, sorted by name
+fn list_files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| e.path().is_file())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| valid_name(name))
+            .collect(),
+        Err(e) => {
+            warn!("Can't read '{}': {}", dir.display(), e);
+            Vec::new()
+        }
+    };
+    files.sort_unstable_by_key(|f| f.to_lowercase());
+    files
+}
+
+// Read the request line and headers, `None` if the connection closed first
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (String::from(m), t),
+        _ => return Err(invalid("bad request line")),
+    };
+    let path = target.split('?').next().unwrap_or_default();
+    let path = percent_decode(path).ok_or_else(|| invalid("bad path"))?;
+
+    let mut length = 0;
+    let mut expect_continue = false;
+    for _ in 0..MAX_HEADER_LINES {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            return Ok(Some(Request {
+                method,
+                path,
+                length,
+                expect_continue,
+            }));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.parse().map_err(|_| invalid("bad length"))?;
+            } else if name.eq_ignore_ascii_case("expect") {
+                expect_continue = value.eq_ignore_ascii_case("100-continue");
+            }
+        }
+    }
+    Err(invalid("too many headers"))
+}
+
+struct Server {
+    dirs: GamepieDirs,
+    commands: mpsc::Sender<PipeCommand>,
+}
+
+impl Server {
+    fn command(&self, cmd: PipeCommand) -> Response {
+        debug!("HTTP command: {:?}", cmd);
+        if self.commands.send(cmd).is_err() {
+            return Response::text(503, "gamepie is stopping");
+        }
+        Response::text(202, "accepted")
+    }
+
+    fn games(&self) -> Response {
+        let mut games = Vec::new();
+        for dir in self.dirs.rom_dirs() {
+            let mut files = dirs::game_files(dir);
+            files.sort_unstable_by_key(|f| f.to_string_lossy().to_lowercase());
+            for file in files {
+                // Relative to the ROM directory, as given to launch
+                games.push(format!(
+                    "{{\"file\":{},\"path\":{}}}",
+                    json_string(&file.to_string_lossy()),
+                    json_string(&dir.join(&file).to_string_lossy())
+                ));
+            }
+        }
+        Response::json(format!("[{}]", games.join(",")))
+    }
+
+    fn upload<R: Read>(&self, name: &str, length: u64, body: &mut R) -> Response {
+        if !valid_name(name) {
+            return Response::text(400, "invalid file name");
+        }
+        if length == 0 {
+            return Response::text(411, "upload needs a Content-Length");
+        }
+        if length > MAX_UPLOAD_LEN {
+            return Response::text(413, "file too big");
+        }
+        let dir = match self.dirs.rom_dirs().first() {
+            Some(dir) => dir.to_path_buf(),
+            None => return Response::text(503, "no ROM directory"),
+        };
+        // Written to a hidden file first, so a partial upload isn't listed
+        let part = dir.join(format!(".{}.part", name));
+        let written = File::create(&part).and_then(|mut f| {
+            let n = io::copy(&mut body.take(length), &mut f)?;
+            f.sync_all()?;
+            Ok(n)
+        });
+        match written {
+            Ok(n) if n == length => {}
+            Ok(_) => {
+                let _ = std::fs::remove_file(&part);
+                return Response::text(400, "upload incomplete");
+            }
+            Err(e) => {
+                error!("Failed to write upload '{}': {}", name, e);
+                let _ = std::fs::remove_file(&part);
+                return Response::text(500, "can't write file");
+            }
+        }
+        if let Err(e) = std::fs::rename(&part, dir.join(name)) {
+            error!("Failed to rename upload '{}': {}", name, e);
+            return Response::text(500, "can't write file");
+        }
+        info!("Uploaded '{}' ({} bytes)", name, length);
+        if self.commands.send(PipeCommand::Rescan).is_err() {
+            warn!("Failed to send rescan command");
+        }
+        Response::text(201, "uploaded")
+    }
+
+    // Start a game by path, or by file name in one of the ROM directories
+    fn launch<R: Read>(&self, length: u64, body: &mut R) -> Response {
+        let mut text = String::new();
+        if length > MAX_JSON_LEN || body.take(length).read_to_string(&mut text).is_err() {
+            return Response::text(400, "expected a JSON object");
+        }
+        let object = match json::parse(&text) {
+            Some(o @ JsonValue::Object(_)) => o,
+            _ => return Response::text(400, "expected a JSON object"),
+        };
+        let path = match object.get("path").and_then(|p| p.as_str()) {
+            Some(p) => PathBuf::from(p),
+            None => return Response::text(400, "launch needs a path"),
+        };
+        let path = if path.is_absolute() {
+            Some(path)
+        } else {
+            self.dirs
+                .rom_dirs()
+                .iter()
+                .map(|d| d.join(&path))
+                .find(|p| p.is_file())
+        };
+        match path {
+            Some(path) if path.is_file() && dirs::is_game_file(&path) => {
+                let core = object
+                    .get("core")
+                    .and_then(|c| c.as_str())
+                    .map(String::from);
+                self.command(PipeCommand::Launch(path, core))
+            }
+            _ => Response::text(404, "game not found"),
+        }
+    }
+
+    fn screenshot(&self) -> Response {
+        let frame = crate::proxy::libretro::with_proxy(|p| {
+            let (width, height) = p.screen_size()?;
+            let data = p.borrow_screen().last_frame()?.to_vec();
+            Some((width, height, data))
+        })
+        .flatten();
+        match frame {
+            Some((width, height, data)) => {
+                let mut jpeg = Vec::new();
+                JpegEncoder::new(width.into(), height.into(), JPEG_QUALITY)
+                    .encode(&data, &mut jpeg);
+                Response::new(200, "image/jpeg", jpeg)
+            }
+            None => Response::text(404, "no game has been drawn"),
+        }
+    }
+
+    fn stats(&self) -> Response {
+        let stats = crate::proxy::libretro::with_proxy(|p| p.frame_stats()).flatten();
+        let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+        match stats {
+            Some(s) => Response::json(format!(
+                "{{\"fps\":{:.2},\"frames\":{},\"run_ms\":{:.2},\"run_max_ms\":{:.2},\"blit_ms\":{:.2},\"blit_max_ms\":{:.2},\"audio_ms\":{:.1},\"audio_max_ms\":{:.1},\"dropped\":{}}}",
+                s.fps(),
+                s.frames,
+                ms(s.run_average()),
+                ms(s.run_max),
+                ms(s.blit_average()),
+                ms(s.blit_max),
+                ms(s.audio_average()),
+                ms(s.audio_max),
+                s.dropped
+            )),
+            None => Response::text(404, "no game has run"),
+        }
+    }
+
+    // Linked to by the QR code on error screens
+    fn diagnostics(&self) -> Response {
+        let mut page = format!("gamepie {}\n", env!("CARGO_PKG_VERSION"));
+        if let Some(e) = crate::crash::last_error() {
+            page += &format!("Last error: {}\n", e);
+        }
+        page += "\nRecent log:\n";
+        for line in crate::crash::recent_log() {
+            page += &line;
+            page.push('\n');
+        }
+        Response::new(200, "text/plain; charset=utf-8", page.into_bytes())
+    }
+
+    fn screenshots(&self) -> Response {
+        let files: Vec<String> = list_files(self.dirs.screenshots())
+            .iter()
+            .map(|f| json_string(f))
+            .collect();
+        Response::json(format!("[{}]", files.join(",")))
+    }
+
+    fn download(&self, name: &str) -> Response {
+        if !valid_name(name) {
+            return Response::text(400, "invalid file name");
+        }
+        let path = self.dirs.screenshots().join(name);
+        let content_type = match path.extension().and_then(|e| e.to_str()) {
+            Some("ppm") => "image/x-portable-pixmap",
+            Some("wav") => "audio/wav",
+            Some("avi") => "video/x-msvideo",
+            _ => "application/octet-stream",
+        };
+        match File::open(&path).and_then(|f| Ok((f.metadata()?.len(), f))) {
+            Ok((len, file)) => Response {
+                status: 200,
+                content_type,
+                body: Body::File(file, len),
+            },
+            Err(_) => Response::text(404, "not found"),
+        }
+    }
+
+    fn route<R: Read>(&self, req: &Request, body: &mut R) -> Response {
+        let path = req.path.as_str();
+        match (req.method.as_str(), path) {
+            ("GET", "/games") => self.games(),
+            ("PUT", _) if path.starts_with("/roms/") => {
+                self.upload(&path["/roms/".len()..], req.length, body)
+            }
+            ("POST", "/launch") => self.launch(req.length, body),
+            ("POST", "/stop") => self.command(PipeCommand::Stop),
+            ("GET", "/screenshot") => self.screenshot(),
+            ("GET", "/screenshots") => self.screenshots(),
+            ("GET", "/stats") => self.stats(),
+            ("GET", _) if path.starts_with("/screenshots/") => {
+                self.download(&path["/screenshots/".len()..])
+            }
+            ("GET", "/diagnostics") => self.diagnostics(),
+            ("GET", "/log") => {
+                let mut log = crate::crash::recent_log().join("\n");
+                log.push('\n');
+                Response::new(200, "text/plain; charset=utf-8", log.into_bytes())
+            }
+            _ => Response::text(404, "not found"),
+        }
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let req = match read_request(&mut reader) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                debug!("Bad HTTP request: {}", e);
+                return Response::text(400, "bad request").write_to(&mut &stream);
+            }
+        };
+        debug!("HTTP {} {}", req.method, req.path);
+        if req.expect_continue {
+            (&stream).write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        self.route(&req, &mut reader).write_to(&mut &stream)
+    }
+}
+
+/// Serve the management API on `addr`, returns the port it is on. The
+/// thread blocks waiting for connections, so it isn't joined and ends when
+/// the process exits.
+pub(crate) fn start(
+    addr: &str,
+    dirs: &GamepieDirs,
+    commands: mpsc::Sender<PipeCommand>,
+) -> io::Result<u16> {
+    let listener = TcpListener::bind(addr)?;
+    let port = listener.local_addr()?.port();
+    info!("Serving HTTP on {}", addr);
+    let server = Server {
+        dirs: dirs.clone(),
+        commands,
+    };
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = server.handle(stream) {
+                        debug!("HTTP connection failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("HTTP accept failed: {}", e),
+            }
+        }
+    });
+    Ok(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_string, percent_decode, read_request, valid_name, Request};
+
+    #[test]
+    fn requests() {
+        let mut head: &[u8] =
+            b"PUT /roms/Some%20Game.gb?x=1 HTTP/1.1\r\nHost: pi\r\ncontent-length: 12\r\nExpect: 100-continue\r\n\r\nbody";
+        assert_eq!(
+            read_request(&mut head).unwrap(),
+            Some(Request {
+                method: String::from("PUT"),
+                path: String::from("/roms/Some Game.gb"),
+                length: 12,
+                expect_continue: true,
+            })
+        );
+        assert_eq!(head, b"body");
+        assert!(read_request(&mut &b"GET\r\n\r\n"[..]).is_err());
+        assert_eq!(read_request(&mut &b""[..]).unwrap(), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert!(!valid_name("../gamepie.toml"));
+        assert!(!valid_name(".hidden"));
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+    }
+}
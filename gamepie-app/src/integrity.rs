@@ -0,0 +1,160 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Cores are hashed when they are found and the hash kept, so a core that
+// changes without being deliberately replaced (e.g. a partial copy) is
+// noticed. Files are only hashed again if their size or modified time
+// changes, to keep startup quick.
+
+#[derive(Clone, PartialEq)]
+struct CoreRecord {
+    hash: u64,
+    size: u64,
+    modified: u64,
+}
+
+// FNV-1a, stable between builds unlike the standard library hasher
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        for b in &buf[..n] {
+            hash ^= u64::from(*b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hashes of the cores seen before, stored as a TOML file
+pub(crate) struct CoreRecords {
+    path: PathBuf,
+    cores: HashMap<String, CoreRecord>,
+}
+
+impl CoreRecords {
+    pub fn load(path: &Path) -> Self {
+        let mut cores = HashMap::new();
+        let table = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|c| c.parse::<toml::Value>().ok());
+        if let Some(toml::Value::Table(table)) = table {
+            for (core, value) in table {
+                // Stored as strings, as TOML integers are signed
+                let int = |key: &str| {
+                    value
+                        .get(key)
+                        .and_then(|v| v.as_str())
+                        .and_then(|v| u64::from_str_radix(v, 16).ok())
+                };
+                match (int("hash"), int("size"), int("modified")) {
+                    (Some(hash), Some(size), Some(modified)) => {
+                        cores.insert(
+                            core,
+                            CoreRecord {
+                                hash,
+                                size,
+                                modified,
+                            },
+                        );
+                    }
+                    _ => warn!("Invalid core record for '{}'", core),
+                }
+            }
+        }
+
+        CoreRecords {
+            path: path.to_path_buf(),
+            cores,
+        }
+    }
+
+    /// Whether a core has been seen before
+    pub fn contains(&self, core: &Path) -> bool {
+        self.cores.contains_key(core.to_string_lossy().as_ref())
+    }
+
+    /// Record the current state of a core, returns true if it has changed
+    /// since it was last seen.
+    pub fn check(&mut self, core: &Path) -> bool {
+        let meta = match std::fs::metadata(core) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Failed to check core '{}': {}", core.display(), e);
+                return false;
+            }
+        };
+        let size = meta.len();
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let key = core.to_string_lossy().into_owned();
+        let previous = self.cores.get(&key).cloned();
+        if let Some(p) = &previous {
+            if p.size == size && p.modified == modified {
+                return false;
+            }
+        }
+
+        let hash = match hash_file(core) {
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Failed to hash core '{}': {}", core.display(), e);
+                return false;
+            }
+        };
+        debug!("Core '{}' hash {:016x}", core.display(), hash);
+        self.cores.insert(
+            key,
+            CoreRecord {
+                hash,
+                size,
+                modified,
+            },
+        );
+        match previous {
+            Some(p) if p.hash != hash => {
+                warn!(
+                    "Core '{}' has changed since it was last seen ({} -> {} bytes)",
+                    core.display(),
+                    p.size,
+                    size
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn save(&self) {
+        let table = self
+            .cores
+            .iter()
+            .map(|(k, r)| {
+                let mut t = toml::value::Table::new();
+                let hex = |v: u64| toml::Value::String(format!("{:x}", v));
+                t.insert(String::from("hash"), hex(r.hash));
+                t.insert(String::from("size"), hex(r.size));
+                t.insert(String::from("modified"), hex(r.modified));
+                (k.clone(), toml::Value::Table(t))
+            })
+            .collect();
+        let contents = toml::Value::Table(table).to_string();
+        if let Err(e) = std::fs::write(&self.path, contents) {
+            warn!(
+                "Failed to save core hashes '{}': {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
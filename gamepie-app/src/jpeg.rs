@@ -0,0 +1,379 @@
+// Baseline JPEG encoder for RGB565 frames, with 4:2:0 chroma subsampling
+// and the example tables from the JPEG standard (Annex K)
+
+// Natural order index of each coefficient in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const LUMA_QUANT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMA_QUANT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// Number of codes of each length from 1 to 16 bits, then the values
+const LUMA_DC_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const CHROMA_DC_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const LUMA_AC_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const CHROMA_AC_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// Code and length in bits for each value of a Huffman table
+struct Huffman {
+    codes: [(u16, u8); 256],
+}
+
+impl Huffman {
+    fn new(bits: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0_u16;
+        let mut values = values.iter();
+        for (i, count) in bits.iter().enumerate() {
+            for _ in 0..*count {
+                if let Some(v) = values.next() {
+                    codes[usize::from(*v)] = (code, i as u8 + 1);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Huffman { codes }
+    }
+}
+
+// Writes bits to the entropy coded data, stuffing a zero after each 0xff
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter<'_> {
+    fn put(&mut self, value: u16, len: u8) {
+        if len == 0 {
+            return;
+        }
+        self.bits = (self.bits << len) | (u32::from(value) & ((1 << len) - 1));
+        self.count += u32::from(len);
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.bits >> self.count) as u8;
+            self.out.push(byte);
+            if byte == 0xff {
+                self.out.push(0);
+            }
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    // Pad the last byte with ones
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let pad = 8 - self.count as u8;
+            self.put((1 << pad) - 1, pad);
+        }
+    }
+}
+
+// Bits needed for a coefficient, and how it is written in that many bits
+fn magnitude(v: i32) -> (u8, u16) {
+    let size = (32 - v.unsigned_abs().leading_zeros()) as u8;
+    let bits = if v < 0 { v - 1 } else { v };
+    (size, (bits as u32 & ((1 << size) - 1)) as u16)
+}
+
+// Quantisation table for a quality (1-100), as libjpeg scales them
+fn scale_quant(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = u32::from(quality.clamp(1, 100));
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    base.map(|q| ((u32::from(q) * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+pub(crate) struct JpegEncoder {
+    width: usize,
+    height: usize,
+    luma_quant: [u16; 64],
+    chroma_quant: [u16; 64],
+    luma_dc: Huffman,
+    luma_ac: Huffman,
+    chroma_dc: Huffman,
+    chroma_ac: Huffman,
+    // Cosines for the DCT, scaled so the transform is orthonormal
+    cos: [[f32; 8]; 8],
+    // Markers up to the start of the entropy coded data, the same for every
+    // frame
+    header: Vec<u8>,
+    // Planes of the frame being encoded, chroma at half size
+    y: Vec<f32>,
+    cb: Vec<f32>,
+    cr: Vec<f32>,
+}
+
+impl JpegEncoder {
+    pub fn new(width: usize, height: usize, quality: u8) -> Self {
+        let luma_quant = scale_quant(&LUMA_QUANT, quality);
+        let chroma_quant = scale_quant(&CHROMA_QUANT, quality);
+        let mut cos = [[0.0; 8]; 8];
+        for (u, row) in cos.iter_mut().enumerate() {
+            let c = if u == 0 { 0.5_f32.sqrt() } else { 1.0 };
+            for (x, v) in row.iter_mut().enumerate() {
+                let angle = (2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0;
+                *v = c * 0.5 * angle.cos();
+            }
+        }
+
+        let mut header = vec![0xff, 0xd8];
+        for (id, table) in [(0, &luma_quant), (1, &chroma_quant)] {
+            let mut body = vec![id];
+            body.extend(ZIGZAG.iter().map(|i| table[*i] as u8));
+            segment(&mut header, 0xdb, &body);
+        }
+        let mut sof = vec![8];
+        sof.extend_from_slice(&(height as u16).to_be_bytes());
+        sof.extend_from_slice(&(width as u16).to_be_bytes());
+        // Y sampled 2x2, Cb and Cr 1x1
+        sof.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        segment(&mut header, 0xc0, &sof);
+        let tables: [(u8, &[u8; 16], &[u8]); 4] = [
+            (0x00, &LUMA_DC_BITS, &DC_VALUES),
+            (0x10, &LUMA_AC_BITS, &LUMA_AC_VALUES),
+            (0x01, &CHROMA_DC_BITS, &DC_VALUES),
+            (0x11, &CHROMA_AC_BITS, &CHROMA_AC_VALUES),
+        ];
+        for (class, bits, values) in tables {
+            let mut body = vec![class];
+            body.extend_from_slice(bits);
+            body.extend_from_slice(values);
+            segment(&mut header, 0xc4, &body);
+        }
+        segment(&mut header, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+        let cw = width.div_ceil(2);
+        let ch = height.div_ceil(2);
+        JpegEncoder {
+            width,
+            height,
+            luma_quant,
+            chroma_quant,
+            luma_dc: Huffman::new(&LUMA_DC_BITS, &DC_VALUES),
+            luma_ac: Huffman::new(&LUMA_AC_BITS, &LUMA_AC_VALUES),
+            chroma_dc: Huffman::new(&CHROMA_DC_BITS, &DC_VALUES),
+            chroma_ac: Huffman::new(&CHROMA_AC_BITS, &CHROMA_AC_VALUES),
+            cos,
+            header,
+            y: vec![0.0; width * height],
+            cb: vec![0.0; cw * ch],
+            cr: vec![0.0; cw * ch],
+        }
+    }
+
+    // Split the frame into Y, Cb and Cr planes, level shifted around zero,
+    // averaging each 2x2 block for the chroma
+    fn convert(&mut self, data: &[u16]) {
+        let cw = self.width.div_ceil(2);
+        self.cb.fill(0.0);
+        self.cr.fill(0.0);
+        for (i, px) in data.iter().take(self.width * self.height).enumerate() {
+            let r = ((px >> 11) & 0x1f) as f32 * (255.0 / 31.0);
+            let g = ((px >> 5) & 0x3f) as f32 * (255.0 / 63.0);
+            let b = (px & 0x1f) as f32 * (255.0 / 31.0);
+            self.y[i] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+            let c = (i / self.width / 2) * cw + (i % self.width) / 2;
+            self.cb[c] += (-0.168_736 * r - 0.331_264 * g + 0.5 * b) / 4.0;
+            self.cr[c] += (0.5 * r - 0.418_688 * g - 0.081_312 * b) / 4.0;
+        }
+    }
+
+    // Copy an 8x8 block from a plane, repeating the edge past its end
+    fn block(plane: &[f32], width: usize, height: usize, bx: usize, by: usize) -> [f32; 64] {
+        let mut block = [0.0; 64];
+        for (i, v) in block.iter_mut().enumerate() {
+            let x = (bx + i % 8).min(width - 1);
+            let y = (by + i / 8).min(height - 1);
+            *v = plane[y * width + x];
+        }
+        block
+    }
+
+    // Transform, quantise and write a block, returns its DC coefficient
+    fn encode_block(
+        &self,
+        bits: &mut BitWriter,
+        block: &[f32; 64],
+        quant: &[u16; 64],
+        dc: &Huffman,
+        ac: &Huffman,
+        prev_dc: i32,
+    ) -> i32 {
+        // Rows, then columns
+        let mut rows = [0.0_f32; 64];
+        for y in 0..8 {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..8).map(|x| self.cos[u][x] * block[y * 8 + x]).sum();
+            }
+        }
+        let mut coeffs = [0_i32; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let f: f32 = (0..8).map(|y| self.cos[v][y] * rows[y * 8 + u]).sum();
+                let i = v * 8 + u;
+                coeffs[i] = (f / f32::from(quant[i])).round() as i32;
+            }
+        }
+
+        let value = coeffs[0];
+        let (size, extra) = magnitude(value - prev_dc);
+        let (code, len) = dc.codes[usize::from(size)];
+        bits.put(code, len);
+        bits.put(extra, size);
+        let mut run = 0;
+        for i in ZIGZAG.iter().skip(1) {
+            let c = coeffs[*i];
+            if c == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                let (code, len) = ac.codes[0xf0];
+                bits.put(code, len);
+                run -= 16;
+            }
+            let (size, extra) = magnitude(c);
+            let (code, len) = ac.codes[(run << 4) | usize::from(size)];
+            bits.put(code, len);
+            bits.put(extra, size);
+            run = 0;
+        }
+        if run > 0 {
+            // End of block
+            let (code, len) = ac.codes[0x00];
+            bits.put(code, len);
+        }
+        value
+    }
+
+    /// Encode an RGB565 frame, replacing the contents of `out`
+    pub fn encode(&mut self, data: &[u16], out: &mut Vec<u8>) {
+        self.convert(data);
+        out.clear();
+        out.extend_from_slice(&self.header);
+        let (w, h) = (self.width, self.height);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let mut bits = BitWriter {
+            out,
+            bits: 0,
+            count: 0,
+        };
+        let mut dc = [0; 3];
+        for my in (0..h).step_by(16) {
+            for mx in (0..w).step_by(16) {
+                for (bx, by) in [(0, 0), (8, 0), (0, 8), (8, 8)] {
+                    let block = Self::block(&self.y, w, h, mx + bx, my + by);
+                    dc[0] = self.encode_block(
+                        &mut bits,
+                        &block,
+                        &self.luma_quant,
+                        &self.luma_dc,
+                        &self.luma_ac,
+                        dc[0],
+                    );
+                }
+                for (plane, prev) in [(&self.cb, 1), (&self.cr, 2)] {
+                    let block = Self::block(plane, cw, ch, mx / 2, my / 2);
+                    dc[prev] = self.encode_block(
+                        &mut bits,
+                        &block,
+                        &self.chroma_quant,
+                        &self.chroma_dc,
+                        &self.chroma_ac,
+                        dc[prev],
+                    );
+                }
+            }
+        }
+        bits.flush();
+        out.extend_from_slice(&[0xff, 0xd9]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{magnitude, JpegEncoder, CHROMA_AC_BITS, CHROMA_AC_VALUES, LUMA_AC_BITS};
+    use super::{LUMA_AC_VALUES, LUMA_DC_BITS};
+
+    #[test]
+    fn encode() {
+        let count = |bits: &[u8; 16]| bits.iter().map(|b| usize::from(*b)).sum::<usize>();
+        assert_eq!(count(&LUMA_DC_BITS), 12);
+        assert_eq!(count(&LUMA_AC_BITS), LUMA_AC_VALUES.len());
+        assert_eq!(count(&CHROMA_AC_BITS), CHROMA_AC_VALUES.len());
+        assert_eq!(magnitude(0), (0, 0));
+        assert_eq!(magnitude(5), (3, 0b101));
+        assert_eq!(magnitude(-5), (3, 0b010));
+
+        // Not a multiple of the block size, so edges are padded
+        let (w, h) = (36, 20);
+        let frame: Vec<u16> = (0..w * h).map(|i| (i * 37) as u16).collect();
+        let mut encoder = JpegEncoder::new(w, h, 80);
+        let mut out = Vec::new();
+        encoder.encode(&frame, &mut out);
+        assert_eq!(out[..2], [0xff, 0xd8]);
+        assert_eq!(out[out.len() - 2..], [0xff, 0xd9]);
+        let header = encoder.header.len();
+        // Markers can't appear in the entropy coded data
+        let data = &out[header..out.len() - 2];
+        assert!(data.windows(2).all(|p| p[0] != 0xff || p[1] == 0));
+
+        let flat = vec![0xffff; w * h];
+        let mut small = Vec::new();
+        encoder.encode(&flat, &mut small);
+        assert!(small.len() < out.len());
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Only small documents are read, commands from launchers and RetroArch
+// playlists, so rather than pull in a JSON library there is a small parser.
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum JsonValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Null,
+    Array(Vec<JsonValue>),
+    Object(HashMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    /// Member of an object
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(o) => o.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.skip_space();
+        self.chars.next_if_eq(&c).map(|_| ())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(s),
+                '\\' => match self.chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok()?;
+                        s.push(char::from_u32(code)?);
+                    }
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<JsonValue> {
+        self.skip_space();
+        match self.chars.peek()? {
+            '"' => self.string().map(JsonValue::String),
+            '[' => self.array().map(JsonValue::Array),
+            '{' => self.object().map(JsonValue::Object),
+            c if *c == '-' || c.is_ascii_digit() => {
+                let mut num = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    num.push(c);
+                }
+                num.parse().ok().map(JsonValue::Number)
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Some(JsonValue::Bool(true)),
+                    "false" => Some(JsonValue::Bool(false)),
+                    "null" => Some(JsonValue::Null),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Vec<JsonValue>> {
+        let mut array = Vec::new();
+        self.expect('[')?;
+        if self.expect(']').is_none() {
+            loop {
+                array.push(self.value()?);
+                if self.expect(',').is_none() {
+                    self.expect(']')?;
+                    break;
+                }
+            }
+        }
+        Some(array)
+    }
+
+    fn object(&mut self) -> Option<HashMap<String, JsonValue>> {
+        let mut map = HashMap::new();
+        self.expect('{')?;
+        if self.expect('}').is_none() {
+            loop {
+                let key = self.string()?;
+                self.expect(':')?;
+                map.insert(key, self.value()?);
+                if self.expect(',').is_none() {
+                    self.expect('}')?;
+                    break;
+                }
+            }
+        }
+        Some(map)
+    }
+}
+
+/// Parse a JSON document, `None` if it isn't valid or has trailing text
+pub(crate) fn parse(text: &str) -> Option<JsonValue> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.value()?;
+    parser.skip_space();
+    match parser.chars.next() {
+        Some(_) => None,
+        None => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, JsonValue};
+
+    #[test]
+    fn nested() {
+        let doc = parse(r#"{"items": [{"label": "A"}, {"label": "B!"}], "n": null}"#);
+        let doc = doc.unwrap();
+        let items = doc.get("items").and_then(|i| i.as_array()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].get("label").and_then(|l| l.as_str()), Some("B!"));
+        assert_eq!(doc.get("n"), Some(&JsonValue::Null));
+        assert_eq!(parse("[]"), Some(JsonValue::Array(Vec::new())));
+        assert!(parse("[1, 2").is_none());
+    }
+}
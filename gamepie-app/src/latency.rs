@@ -0,0 +1,222 @@
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::gpio::LightEdge;
+
+// Measurements kept for the statistics, older ones are dropped
+const MAX_SAMPLES: usize = 100;
+// A change not seen within this long is counted as missed
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Recent latency measurements of one kind
+#[derive(Default)]
+struct Samples {
+    samples: VecDeque<Duration>,
+    missed: u32,
+}
+
+impl Samples {
+    fn add(&mut self, latency: Duration) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    /// Minimum, mean and maximum of the measurements
+    fn summary(&self) -> Option<(Duration, Duration, Duration)> {
+        let min = *self.samples.iter().min()?;
+        let max = *self.samples.iter().max()?;
+        let mean = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        Some((min, mean, max))
+    }
+
+    fn describe(&self, name: &str) -> String {
+        let missed = match self.missed {
+            0 => String::new(),
+            n => format!(", {} missed", n),
+        };
+        match self.summary() {
+            Some((min, mean, max)) => format!(
+                "{}: {:.1}/{:.1}/{:.1} ms (n={}{})",
+                name,
+                ms(min),
+                ms(mean),
+                ms(max),
+                self.samples.len(),
+                missed
+            ),
+            None => format!("{}: -{}", name, missed),
+        }
+    }
+}
+
+// A press whose change hasn't been seen yet
+struct Pending {
+    pressed: Instant,
+    // First frame drawn with the change
+    frame: Option<u64>,
+    blit_done: bool,
+    light_done: bool,
+}
+
+/// Input-to-display latency test. Each press of A toggles a marker on the
+/// screen, timing how long from the press being read until the frame with
+/// the change has been sent over SPI, and until a photodiode over the marker
+/// sees it change if there is one.
+pub(crate) struct LatencyTest {
+    /// Whether the marker is lit
+    pub lit: bool,
+    held: bool,
+    pending: Option<Pending>,
+    blit: Samples,
+    light: Option<(LightEdge, Samples)>,
+}
+
+impl LatencyTest {
+    pub fn new(light: Option<LightEdge>) -> Self {
+        LatencyTest {
+            lit: false,
+            held: false,
+            pending: None,
+            blit: Samples::default(),
+            light: light.map(|edge| (edge, Samples::default())),
+        }
+    }
+
+    /// Note whether A is held, toggling the marker on a fresh press. Presses
+    /// while a change is still being timed are ignored.
+    pub fn input(&mut self, held: bool, now: Instant) {
+        let press = held && !self.held;
+        self.held = held;
+        if !press || self.pending.is_some() {
+            return;
+        }
+        self.lit = !self.lit;
+        if let Some((edge, _)) = &self.light {
+            edge.clear();
+        }
+        self.pending = Some(Pending {
+            pressed: now,
+            frame: None,
+            blit_done: false,
+            light_done: self.light.is_none(),
+        });
+    }
+
+    /// Note the number of a frame drawn with the marker as it is now
+    pub fn drawn(&mut self, frame: u64) {
+        if let Some(p) = &mut self.pending {
+            p.frame.get_or_insert(frame);
+        }
+    }
+
+    /// Check whether the change has reached the screen, given the newest
+    /// frame sent to it
+    pub fn update(&mut self, shown: Option<(u64, Instant)>, now: Instant) {
+        let p = match &mut self.pending {
+            Some(p) => p,
+            None => return,
+        };
+        if !p.blit_done {
+            if let (Some(frame), Some((seq, at))) = (p.frame, shown) {
+                if seq >= frame {
+                    let latency = at.saturating_duration_since(p.pressed);
+                    debug!("Blit latency {:.1} ms", ms(latency));
+                    self.blit.add(latency);
+                    p.blit_done = true;
+                }
+            }
+        }
+        if let Some((edge, samples)) = &mut self.light {
+            if !p.light_done {
+                if let Some(at) = edge.take() {
+                    let latency = at.saturating_duration_since(p.pressed);
+                    debug!("Photodiode latency {:.1} ms", ms(latency));
+                    samples.add(latency);
+                    p.light_done = true;
+                }
+            }
+        }
+
+        if p.blit_done && p.light_done {
+            self.pending = None;
+        } else if now.saturating_duration_since(p.pressed) >= SAMPLE_TIMEOUT {
+            if !p.blit_done {
+                self.blit.missed += 1;
+            }
+            if let Some((_, samples)) = &mut self.light {
+                if !p.light_done {
+                    samples.missed += 1;
+                }
+            }
+            self.pending = None;
+        }
+    }
+
+    /// Measurements so far, as min/mean/max, to show on the screen
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.blit.describe("Blit")];
+        match &self.light {
+            Some((_, samples)) => lines.push(samples.describe("Photodiode")),
+            None => lines.push(String::from("Photodiode: not set up")),
+        }
+        lines
+    }
+
+    /// Log the results when leaving the test
+    pub fn log(&self) {
+        for line in self.lines() {
+            info!("Latency test {}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyTest, SAMPLE_TIMEOUT};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn blit_latency() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut test = LatencyTest::new(None);
+
+        test.input(true, start);
+        assert!(test.lit);
+        test.drawn(5);
+        test.drawn(6);
+        // Older frame shown, still waiting
+        test.update(Some((4, start + ms(10))), start + ms(10));
+        // Held, and pressed again while waiting, so ignored
+        test.input(true, start + ms(12));
+        test.input(false, start + ms(14));
+        test.input(true, start + ms(16));
+        assert!(test.lit);
+        test.update(Some((5, start + ms(20))), start + ms(21));
+        assert_eq!(test.blit.summary(), Some((ms(20), ms(20), ms(20))));
+
+        let second = start + ms(100);
+        test.input(false, second);
+        test.input(true, second);
+        assert!(!test.lit);
+        test.drawn(9);
+        test.update(Some((9, second + ms(40))), second + ms(40));
+        assert_eq!(test.blit.summary(), Some((ms(20), ms(30), ms(40))));
+
+        // Never shown
+        let third = second + ms(100);
+        test.input(false, third);
+        test.input(true, third);
+        test.drawn(10);
+        test.update(None, third + SAMPLE_TIMEOUT);
+        assert_eq!(test.blit.missed, 1);
+        assert_eq!(test.lines()[0], "Blit: 20.0/30.0/40.0 ms (n=2, 1 missed)");
+    }
+}
@@ -1,6 +1,50 @@
 mod core;
+mod coretest;
+mod crash;
+#[cfg(feature = "sdl")]
+mod desktop;
+mod feedback;
+mod frameskip;
 mod gamepie;
+mod governor;
 mod gpio;
+mod hotkey;
+mod http;
+mod integrity;
+mod jpeg;
+mod json;
+mod latency;
+mod library;
+mod loading;
+mod mdns;
+mod mqtt;
+mod netplay;
+mod notify;
+mod party;
+mod pipe;
+mod plugin;
+mod preload;
 mod proxy;
+mod radio;
+mod recorder;
+mod recovery;
+mod retroarch;
+mod rewind;
+mod saves;
+mod scraper;
+mod sensor;
+mod slots;
+mod states;
+mod storage;
+mod thermal;
+mod watchdog;
+mod watcher;
 
+pub use coretest::test_core;
+pub use crash::RecentLog;
 pub use gamepie::Gamepie;
+pub use plugin::Plugin;
+pub use retroarch::import_retroarch;
+pub use saves::{saves, SaveCommand};
+pub use scraper::scrape;
+pub use states::{states, StateCommand};
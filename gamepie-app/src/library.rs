@@ -0,0 +1,207 @@
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, UNIX_EPOCH};
+
+use gamepie_core::checksum::{crc32, hex, sha1};
+use gamepie_core::config::Config;
+use gamepie_core::dirs;
+use gamepie_core::library::{Library, LibraryEntry};
+
+use crate::scraper::dat::{rom_data, Dat};
+
+// Larger ROMs, e.g. CD images, aren't hashed. Redump DATs list their tracks
+// rather than the image.
+const MAX_HASH_SIZE: u64 = 64 * 1024 * 1024;
+// Progress is saved after this many ROMs are hashed, so a large library
+// isn't hashed again if gamepie stops part way through
+const SAVE_INTERVAL: usize = 20;
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+pub(crate) type Titles = HashMap<PathBuf, String>;
+
+fn roms(rom_dirs: &[&Path]) -> Vec<PathBuf> {
+    rom_dirs
+        .iter()
+        .flat_map(|d| dirs::game_files(d).into_iter().map(|f| d.join(f)))
+        .collect()
+}
+
+fn hash(rom: &Path, size: u64, modified: u64) -> std::io::Result<LibraryEntry> {
+    let data = std::fs::read(rom)?;
+    let data = rom_data(&data);
+    Ok(LibraryEntry {
+        size,
+        modified,
+        crc32: crc32(data),
+        sha1: hex(&sha1(data)),
+        title: None,
+    })
+}
+
+struct Scanner {
+    library_file: PathBuf,
+    rom_dirs: Vec<PathBuf>,
+    dats: Vec<PathBuf>,
+    running: Arc<AtomicBool>,
+}
+
+impl Scanner {
+    // Hash the ROMs that are new or have changed, and look up the titles
+    // of any not yet found in the DATs
+    fn scan(&self) -> Titles {
+        let mut library = Library::load(&self.library_file);
+        let dat = match Dat::load(&self.dats) {
+            Ok(dat) => Some(dat),
+            Err(e) => {
+                debug!("Library games won't be named: {}", e);
+                None
+            }
+        };
+        let rom_dirs: Vec<&Path> = self.rom_dirs.iter().map(|d| d.as_path()).collect();
+        let roms = roms(&rom_dirs);
+        library.retain(&roms);
+
+        let mut hashed = 0;
+        for rom in &roms {
+            if !self.running.load(Ordering::Acquire) {
+                break;
+            }
+            let meta = match std::fs::metadata(rom) {
+                Ok(m) if m.len() <= MAX_HASH_SIZE => m,
+                _ => continue,
+            };
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            let mut entry = match library.get(rom, meta.len(), modified) {
+                Some(e) if e.title.is_some() || dat.is_none() => continue,
+                // The DATs may have changed since
+                Some(e) => e.clone(),
+                None => match hash(rom, meta.len(), modified) {
+                    Ok(e) => {
+                        hashed += 1;
+                        e
+                    }
+                    Err(e) => {
+                        warn!("Failed to hash '{}': {}", rom.display(), e);
+                        continue;
+                    }
+                },
+            };
+            if let Some(dat) = &dat {
+                entry.title = dat.title(entry.crc32, Some(&entry.sha1), rom).cloned();
+            }
+            library.insert(rom, entry);
+            if hashed > 0 && hashed % SAVE_INTERVAL == 0 {
+                library.save();
+            }
+        }
+        library.save();
+
+        let titles = library.titles();
+        info!(
+            "Library has {} ROMs, {} newly hashed, {} named",
+            roms.len(),
+            hashed,
+            titles.len()
+        );
+        titles
+    }
+
+    fn run(self, requests: mpsc::Receiver<()>, titles: mpsc::Sender<Titles>) {
+        // Hashing shouldn't slow down a game being played
+        // SAFETY: only changes the priority of this thread
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            debug!("Failed to lower library priority");
+        }
+        while self.running.load(Ordering::Acquire) {
+            match requests.recv_timeout(POLL_TIMEOUT) {
+                Ok(()) => {
+                    // Requests made during a scan need only one more
+                    requests.try_iter().for_each(drop);
+                    if titles.send(self.scan()).is_err() {
+                        break;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        debug!("Library thread finished");
+    }
+}
+
+/// Hashes the ROMs in the background, with the results kept in the library
+/// file, and names them from the scraper's DAT files
+pub(crate) struct LibraryScanner {
+    requests: mpsc::Sender<()>,
+    titles: mpsc::Receiver<Titles>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LibraryScanner {
+    /// Start the thread, with a scan of the ROMs found now
+    pub fn start(config: &Config, running: Arc<AtomicBool>) -> Self {
+        let scanner = Scanner {
+            library_file: config.dirs.library_file(),
+            rom_dirs: config
+                .dirs
+                .rom_dirs()
+                .iter()
+                .map(|d| d.to_path_buf())
+                .collect(),
+            dats: config.scraper.dats.clone(),
+            running,
+        };
+        let (requests, request_rx) = mpsc::channel();
+        let (titles_tx, titles) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name(String::from("library"))
+            .spawn(move || scanner.run(request_rx, titles_tx));
+        let thread = match thread {
+            Ok(t) => Some(t),
+            Err(e) => {
+                warn!("Failed to start library thread: {}", e);
+                None
+            }
+        };
+        let scanner = LibraryScanner {
+            requests,
+            titles,
+            thread,
+        };
+        scanner.rescan();
+        scanner
+    }
+
+    /// Titles saved by earlier scans, for the menu before a scan finishes
+    pub fn saved_titles(config: &Config) -> Titles {
+        Library::load(&config.dirs.library_file()).titles()
+    }
+
+    /// Scan again, e.g. after ROMs have been added
+    pub fn rescan(&self) {
+        // Only fails if the thread has stopped
+        let _ = self.requests.send(());
+    }
+
+    /// Titles from the last scan to finish, if there is a new one
+    pub fn titles(&self) -> Option<Titles> {
+        self.titles.try_iter().last()
+    }
+
+    /// Wait for the thread to finish, `running` must have been cleared
+    pub fn join(&mut self) {
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("Library thread panicked");
+            }
+        }
+    }
+}
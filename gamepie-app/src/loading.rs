@@ -0,0 +1,85 @@
+use log::error;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use gamepie_core::error::GamepieError;
+use gamepie_core::metadata;
+
+use crate::core::Core;
+
+// How often the loading spinner moves on a step
+const SPIN_STEP: Duration = Duration::from_millis(100);
+
+/// Core and game being loaded on a worker thread, so the menu can show
+/// progress rather than freezing while large games are read.
+pub(crate) struct Loading {
+    handle: JoinHandle<Result<Core, GamepieError>>,
+    game: PathBuf,
+    name: String,
+    started: Instant,
+    // Party games restore their state once loaded
+    party: bool,
+}
+
+impl Loading {
+    pub fn spawn<F>(game: &Path, party: bool, load: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<Core, Box<dyn Error>> + Send + 'static,
+    {
+        let handle = std::thread::Builder::new()
+            .name(String::from("loading"))
+            .spawn(move || {
+                load().map_err(|e| {
+                    error!("Failed to load game: {}", e);
+                    e.downcast_ref::<GamepieError>()
+                        .copied()
+                        .unwrap_or(GamepieError::GameLoadError)
+                })
+            })?;
+        Ok(Loading {
+            handle,
+            game: game.to_path_buf(),
+            name: metadata::get_str(game, "name").unwrap_or_else(|| {
+                game.file_stem()
+                    .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+            }),
+            started: Instant::now(),
+            party,
+        })
+    }
+
+    pub fn game(&self) -> &Path {
+        &self.game
+    }
+
+    /// Name to show while loading, from the metadata or the file name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_party(&self) -> bool {
+        self.party
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Step of the spinner to show, moving on over time
+    pub fn step(&self) -> usize {
+        (self.started.elapsed().as_millis() / SPIN_STEP.as_millis()) as usize
+    }
+
+    /// Wait for the loaded core
+    pub fn join(self) -> Result<Core, GamepieError> {
+        match self.handle.join() {
+            Ok(res) => res,
+            Err(_) => {
+                error!("Loading thread panicked");
+                Err(GamepieError::GameLoadError)
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use log::{debug, info, warn};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::time::{Duration, Instant};
+
+// Minimal mDNS responder (RFC 6762) advertising the HTTP API as a DNS-SD
+// service, so tools can find gamepie without knowing its address. Shares
+// the port with avahi if it is running.
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const SERVICE: &str = "_gamepie._tcp.local";
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// Set on records only gamepie answers for, so caches replace old values
+const CACHE_FLUSH: u16 = 0x8000;
+
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+// How often the addresses are checked, so a new one is announced
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_NAME_POINTERS: usize = 16;
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+fn put_record(out: &mut Vec<u8>, name: &str, rtype: u16, class: u16, ttl: u32, data: &[u8]) {
+    put_name(out, name);
+    out.extend_from_slice(&rtype.to_be_bytes());
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&ttl.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+// Read a possibly compressed name, lower case, returns it and the position
+// after it in the message
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    for _ in 0..MAX_NAME_POINTERS {
+        loop {
+            let len = usize::from(*msg.get(pos)?);
+            if len == 0 {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            if len & 0xc0 == 0xc0 {
+                let target = ((len & 0x3f) << 8) | usize::from(*msg.get(pos + 1)?);
+                end.get_or_insert(pos + 2);
+                pos = target;
+                break;
+            }
+            let label = msg.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).to_lowercase());
+            pos += 1 + len;
+        }
+    }
+    None
+}
+
+// Names and types asked about by a query, `None` if it isn't a query
+fn questions(msg: &[u8]) -> Option<Vec<(String, u16)>> {
+    let flags = u16::from_be_bytes([*msg.get(2)?, *msg.get(3)?]);
+    if flags & 0x8000 != 0 {
+        return None;
+    }
+    let count = u16::from_be_bytes([*msg.get(4)?, *msg.get(5)?]);
+    let mut pos = 12;
+    let mut questions = Vec::new();
+    for _ in 0..count {
+        let (name, next) = read_name(msg, pos)?;
+        let qtype = u16::from_be_bytes([*msg.get(next)?, *msg.get(next + 1)?]);
+        questions.push((name, qtype));
+        pos = next + 4;
+    }
+    Some(questions)
+}
+
+// IPv4 addresses of the network interfaces, other than loopback
+pub(crate) fn ipv4_addrs() -> Vec<Ipv4Addr> {
+    let mut addrs = Vec::new();
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return addrs;
+    }
+    let mut ifa = ifap;
+    while !ifa.is_null() {
+        let entry = unsafe { &*ifa };
+        let loopback = entry.ifa_flags & libc::IFF_LOOPBACK as libc::c_uint != 0;
+        if !entry.ifa_addr.is_null()
+            && !loopback
+            && i32::from(unsafe { (*entry.ifa_addr).sa_family }) == libc::AF_INET
+        {
+            let sin = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+            addrs.push(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)));
+        }
+        ifa = entry.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    addrs.sort_unstable();
+    addrs
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().replace('.', "-"))
+        .ok()
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| String::from("gamepie"))
+}
+
+// UDP socket on the mDNS port, shared with any other responder
+fn bind_socket() -> io::Result<UdpSocket> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Closes the socket if setting it up fails
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &one as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: MDNS_PORT.to_be(),
+        sin_addr: libc::in_addr { s_addr: 0 },
+        sin_zero: [0; 8],
+    };
+    let res = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    socket.join_multicast_v4(&MDNS_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+/// Service advertised over mDNS
+struct Service {
+    host: String,
+    instance: String,
+    port: u16,
+    txt: Vec<String>,
+}
+
+impl Service {
+    fn host_name(&self) -> String {
+        format!("{}.local", self.host)
+    }
+
+    fn instance_name(&self) -> String {
+        format!("{}.{}", self.instance, SERVICE)
+    }
+
+    // Whether a question is about the service or this host
+    fn asked(&self, name: &str, qtype: u16) -> bool {
+        let is = |n: &str, t: u16| name == n.to_lowercase() && (qtype == t || qtype == TYPE_ANY);
+        is(SERVICES, TYPE_PTR)
+            || is(SERVICE, TYPE_PTR)
+            || is(&self.instance_name(), TYPE_SRV)
+            || is(&self.instance_name(), TYPE_TXT)
+            || is(&self.host_name(), TYPE_A)
+    }
+
+    // Every record, answering any question about the service
+    fn response(&self, id: u16, addrs: &[Ipv4Addr]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&id.to_be_bytes());
+        // Authoritative answer
+        out.extend_from_slice(&0x8400_u16.to_be_bytes());
+        out.extend_from_slice(&0_u16.to_be_bytes());
+        out.extend_from_slice(&(4 + addrs.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0; 4]);
+
+        let instance = self.instance_name();
+        let mut data = Vec::new();
+        put_name(&mut data, SERVICE);
+        put_record(&mut out, SERVICES, TYPE_PTR, CLASS_IN, SERVICE_TTL, &data);
+        data.clear();
+        put_name(&mut data, &instance);
+        put_record(&mut out, SERVICE, TYPE_PTR, CLASS_IN, SERVICE_TTL, &data);
+        data.clear();
+        // Priority and weight
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&self.port.to_be_bytes());
+        put_name(&mut data, &self.host_name());
+        let flush = CLASS_IN | CACHE_FLUSH;
+        put_record(&mut out, &instance, TYPE_SRV, flush, HOST_TTL, &data);
+        data.clear();
+        for entry in &self.txt {
+            let entry = &entry.as_bytes()[..entry.len().min(255)];
+            data.push(entry.len() as u8);
+            data.extend_from_slice(entry);
+        }
+        put_record(&mut out, &instance, TYPE_TXT, flush, SERVICE_TTL, &data);
+        for addr in addrs {
+            put_record(
+                &mut out,
+                &self.host_name(),
+                TYPE_A,
+                flush,
+                HOST_TTL,
+                &addr.octets(),
+            );
+        }
+        out
+    }
+
+    fn run(self, socket: UdpSocket) {
+        let group = SocketAddr::V4(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT));
+        let mut addrs = Vec::new();
+        let mut checked: Option<Instant> = None;
+        let mut buf = [0; 1500];
+        loop {
+            if checked.is_none_or(|c| c.elapsed() >= CHECK_INTERVAL) {
+                checked = Some(Instant::now());
+                let now = ipv4_addrs();
+                if now != addrs {
+                    info!("Advertising {} at {:?}", self.instance_name(), now);
+                    addrs = now;
+                    if let Err(e) = socket.send_to(&self.response(0, &addrs), group) {
+                        debug!("mDNS announcement failed: {}", e);
+                    }
+                }
+            }
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    warn!("mDNS receive failed: {}", e);
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let msg = &buf[..len];
+            let asked = questions(msg)
+                .is_some_and(|q| q.iter().any(|(name, qtype)| self.asked(name, *qtype)));
+            if !asked || addrs.is_empty() {
+                continue;
+            }
+            // Queries from other ports are one-shot lookups, answered
+            // directly with their ID
+            let res = if from.port() == MDNS_PORT {
+                socket.send_to(&self.response(0, &addrs), group)
+            } else {
+                let id = u16::from_be_bytes([msg[0], msg[1]]);
+                socket.send_to(&self.response(id, &addrs), from)
+            };
+            if let Err(e) = res {
+                debug!("mDNS response failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Advertise the HTTP API on `port` as a `_gamepie._tcp` service. The
+/// thread isn't joined and ends when the process exits.
+pub(crate) fn start(port: u16, model: Option<String>, netplay: Option<u16>) -> io::Result<()> {
+    let socket = bind_socket()?;
+    let host = hostname();
+    let mut txt = vec![
+        format!("version={}", env!("CARGO_PKG_VERSION")),
+        String::from("path=/"),
+    ];
+    if let Some(model) = model {
+        txt.push(format!("model={}", model));
+    }
+    if let Some(netplay) = netplay {
+        txt.push(format!("netplay={}", netplay));
+    }
+    let service = Service {
+        instance: host.clone(),
+        host,
+        port,
+        txt,
+    };
+    std::thread::spawn(move || service.run(socket));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{put_name, questions, read_name, Service, TYPE_A, TYPE_PTR, TYPE_SRV};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn answers() {
+        let service = Service {
+            host: String::from("pie"),
+            instance: String::from("Pie"),
+            port: 8080,
+            txt: vec![String::from("model=Pi")],
+        };
+        // Query for the service type, then the instance name compressed as
+        // a label in front of a pointer to it
+        let mut query = vec![0, 7, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        put_name(&mut query, "_gamepie._tcp.local");
+        query.extend_from_slice(&[0, 12, 0, 1]);
+        query.extend_from_slice(&[3, b'P', b'I', b'E', 0xc0, 12, 0, 33, 0, 1]);
+        let asked = questions(&query).unwrap();
+        assert_eq!(
+            asked,
+            vec![
+                (String::from("_gamepie._tcp.local"), TYPE_PTR),
+                (String::from("pie._gamepie._tcp.local"), TYPE_SRV),
+            ]
+        );
+        assert!(asked.iter().all(|(n, t)| service.asked(n, *t)));
+        assert!(!service.asked("other.local", TYPE_A));
+
+        let response = service.response(7, &[Ipv4Addr::new(192, 168, 1, 2)]);
+        assert_eq!(&response[..12], &[0, 7, 0x84, 0, 0, 0, 0, 5, 0, 0, 0, 0]);
+        assert_eq!(questions(&response), None);
+        assert_eq!(
+            read_name(&response, 12).unwrap().0,
+            "_services._dns-sd._udp.local"
+        );
+        assert!(response.ends_with(&[0, 4, 192, 168, 1, 2]));
+    }
+}
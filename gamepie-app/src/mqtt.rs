@@ -0,0 +1,432 @@
+use log::{debug, error, info, trace, warn};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use gamepie_core::commands::{AudioCmd, AudioMsg};
+use gamepie_core::config::MqttConfig;
+
+// Minimal MQTT 3.1.1 client, only QoS 0 is used in both directions which
+// is enough for dashboard state and simple commands.
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBLISH_RETAIN: u8 = 0x31;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// For the rest of a packet once it starts, which can take longer than a poll
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+// Notifications kept while not connected, older ones are dropped
+const QUEUED_NOTIFICATIONS: usize = 16;
+// Largest packet read, commands are short so anything bigger is dropped
+// along with the connection rather than allocated
+const MAX_PACKET: usize = 4096;
+
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// State changes to publish
+pub(crate) enum MqttEvent {
+    GameStarted(String),
+    GameStopped,
+    /// Published once, not retained
+    Notification(String),
+}
+
+fn encode_length(mut len: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    let bytes = s.as_bytes();
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = vec![header];
+    encode_length(body.len(), &mut p);
+    p.extend_from_slice(body);
+    p
+}
+
+struct Connection {
+    stream: TcpStream,
+    topic: String,
+    last_sent: Instant,
+}
+
+impl Connection {
+    fn connect(config: &MqttConfig) -> Result<Self, Box<dyn Error>> {
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(&config.broker)?
+            .next()
+            .ok_or("broker address not found")?;
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let mut conn = Connection {
+            stream,
+            topic: config.topic.clone(),
+            last_sent: Instant::now(),
+        };
+
+        // Clean session with a retained "offline" will, so dashboards can
+        // tell if the device drops off the network.
+        let mut body = Vec::new();
+        encode_str("MQTT", &mut body);
+        body.push(4);
+        body.push(0x02 | 0x04 | 0x20);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        encode_str(&config.client_id, &mut body);
+        encode_str(&conn.topic("state"), &mut body);
+        encode_str("offline", &mut body);
+        conn.send(&packet(CONNECT, &body))?;
+
+        match conn.read_packet()? {
+            Some((CONNACK, body)) if body.len() == 2 && body[1] == 0 => {}
+            Some((CONNACK, body)) => {
+                return Err(format!("connection refused ({:?})", body.get(1)).into())
+            }
+            _ => return Err("no CONNACK from broker".into()),
+        }
+        conn.stream.set_read_timeout(Some(POLL_TIMEOUT))?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u16.to_be_bytes());
+        encode_str(&conn.topic("command"), &mut body);
+        body.push(0);
+        conn.send(&packet(SUBSCRIBE, &body))?;
+
+        Ok(conn)
+    }
+
+    fn topic(&self, sub: &str) -> String {
+        format!("{}/{}", self.topic, sub)
+    }
+
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.stream.write_all(data)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn publish(&mut self, sub: &str, payload: &str, retain: bool) -> std::io::Result<()> {
+        trace!("MQTT publish {}/{} = {}", self.topic, sub, payload);
+        let mut body = Vec::new();
+        encode_str(&self.topic(sub), &mut body);
+        body.extend_from_slice(payload.as_bytes());
+        let header = if retain { PUBLISH_RETAIN } else { PUBLISH };
+        self.send(&packet(header, &body))
+    }
+
+    fn ping_if_needed(&mut self) -> std::io::Result<()> {
+        if self.last_sent.elapsed() > KEEP_ALIVE / 2 {
+            self.send(&[PINGREQ, 0])?;
+        }
+        Ok(())
+    }
+
+    // Read a packet, returns None if nothing arrives before the read timeout
+    fn read_packet(&mut self) -> std::io::Result<Option<(u8, Vec<u8>)>> {
+        let mut header = [0u8; 1];
+        match self.stream.read(&mut header) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.stream.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        let body = decode_length(&mut self.stream).and_then(|len| {
+            let mut body = vec![0; len];
+            self.stream.read_exact(&mut body)?;
+            Ok(body)
+        });
+        self.stream.set_read_timeout(Some(POLL_TIMEOUT))?;
+        Ok(Some((header[0], body?)))
+    }
+
+    // Get the payload of a message on the command topic
+    fn command(&self, header: u8, body: &[u8]) -> Option<String> {
+        if header & 0xf0 != PUBLISH || body.len() < 2 {
+            return None;
+        }
+        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let topic = body.get(2..2 + topic_len)?;
+        // Subscribed at QoS 0 so there will be no packet identifier
+        let payload = body.get(2 + topic_len..)?;
+        if topic == self.topic("command").as_bytes() {
+            Some(String::from_utf8_lossy(payload).trim().to_string())
+        } else {
+            None
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.publish("state", "offline", true).is_err() || self.send(&[DISCONNECT, 0]).is_err() {
+            debug!("MQTT connection already closed");
+        }
+    }
+}
+
+// Read the remaining length of a packet, failing if it is over MAX_PACKET
+fn decode_length(stream: &mut impl Read) -> std::io::Result<usize> {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+    }
+    if len > MAX_PACKET {
+        warn!("MQTT packet of {} bytes is too large", len);
+        return Err(ErrorKind::InvalidData.into());
+    }
+    Ok(len)
+}
+
+// Capacity of the first power supply that reports one, if any
+fn read_battery() -> Option<u8> {
+    std::fs::read_dir(POWER_SUPPLY_PATH)
+        .ok()?
+        .flatten()
+        .find_map(|d| std::fs::read_to_string(d.path().join("capacity")).ok())
+        .and_then(|c| c.trim().parse().ok())
+}
+
+pub(crate) struct Mqtt {
+    tx: mpsc::Sender<MqttEvent>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct MqttThread {
+    config: MqttConfig,
+    rx: mpsc::Receiver<MqttEvent>,
+    running: Arc<AtomicBool>,
+    request_back: Arc<AtomicBool>,
+    request_exit: Arc<AtomicBool>,
+    audio: mpsc::Sender<AudioMsg>,
+    game: Option<String>,
+    notifications: VecDeque<String>,
+}
+
+impl MqttThread {
+    fn handle_command(&self, command: &str) {
+        info!("MQTT command: '{}'", command);
+        match command {
+            "back" => self.request_back.store(true, Ordering::Release),
+            "exit" => self.request_exit.store(true, Ordering::Release),
+            "volume_up" | "volume_down" => {
+                let cmd = if command == "volume_up" {
+                    AudioCmd::VolumeUp
+                } else {
+                    AudioCmd::VolumeDown
+                };
+                if self.audio.send(AudioMsg::Command(cmd)).is_err() {
+                    warn!("Failed to send volume command");
+                }
+            }
+            _ => warn!("Unknown MQTT command '{}'", command),
+        }
+    }
+
+    fn publish_state(&self, conn: &mut Connection) -> std::io::Result<()> {
+        match &self.game {
+            Some(game) => {
+                conn.publish("state", "playing", true)?;
+                conn.publish("game", game, true)
+            }
+            None => {
+                conn.publish("state", "menu", true)?;
+                conn.publish("game", "", true)
+            }
+        }
+    }
+
+    fn publish_status(&self, conn: &mut Connection) -> std::io::Result<()> {
+        if let Some(t) = crate::thermal::read_temperature() {
+            conn.publish("temperature", &format!("{:.1}", t), false)?;
+        }
+        if let Some(b) = read_battery() {
+            conn.publish("battery", &b.to_string(), false)?;
+        }
+        Ok(())
+    }
+
+    fn take_events(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.rx.try_recv() {
+            self.game = match event {
+                MqttEvent::GameStarted(g) => Some(g),
+                MqttEvent::GameStopped => None,
+                MqttEvent::Notification(n) => {
+                    if self.notifications.len() == QUEUED_NOTIFICATIONS {
+                        self.notifications.pop_front();
+                    }
+                    self.notifications.push_back(n);
+                    continue;
+                }
+            };
+            changed = true;
+        }
+        changed
+    }
+
+    fn publish_notifications(&mut self, conn: &mut Connection) -> std::io::Result<()> {
+        while let Some(n) = self.notifications.pop_front() {
+            conn.publish("notification", &n, false)?;
+        }
+        Ok(())
+    }
+
+    // Run until the connection fails or gamepie is shutting down
+    fn run_connection(&mut self, conn: &mut Connection) -> std::io::Result<()> {
+        self.take_events();
+        self.publish_state(conn)?;
+        self.publish_status(conn)?;
+        let mut status_time = Instant::now();
+
+        while self.running.load(Ordering::Acquire) {
+            if self.take_events() {
+                self.publish_state(conn)?;
+            }
+            self.publish_notifications(conn)?;
+            if status_time.elapsed() > STATUS_INTERVAL {
+                self.publish_status(conn)?;
+                status_time = Instant::now();
+            }
+            conn.ping_if_needed()?;
+
+            // Blocks for up to the poll timeout
+            match conn.read_packet()? {
+                Some((SUBACK, _)) => debug!("MQTT subscribed to commands"),
+                Some((PINGRESP, _)) => trace!("MQTT ping response"),
+                Some((header, body)) => match conn.command(header, &body) {
+                    Some(c) => self.handle_command(&c),
+                    None => trace!("Ignoring MQTT packet {:#04x}", header),
+                },
+                None => {}
+            }
+        }
+
+        conn.disconnect();
+        Ok(())
+    }
+
+    fn run(mut self) {
+        while self.running.load(Ordering::Acquire) {
+            match Connection::connect(&self.config) {
+                Ok(mut conn) => {
+                    info!("Connected to MQTT broker {}", self.config.broker);
+                    if let Err(e) = self.run_connection(&mut conn) {
+                        warn!("MQTT connection lost: {}", e);
+                    }
+                }
+                Err(e) => warn!("Failed to connect to MQTT broker: {}", e),
+            }
+
+            // Wait before reconnecting, but keep checking for shutdown
+            let wait = Instant::now();
+            while self.running.load(Ordering::Acquire) && wait.elapsed() < RECONNECT_INTERVAL {
+                std::thread::sleep(POLL_TIMEOUT);
+            }
+        }
+        debug!("MQTT thread finished");
+    }
+}
+
+impl Mqtt {
+    pub fn start(
+        config: MqttConfig,
+        running: Arc<AtomicBool>,
+        request_back: Arc<AtomicBool>,
+        request_exit: Arc<AtomicBool>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let state = MqttThread {
+            config,
+            rx,
+            running,
+            request_back,
+            request_exit,
+            audio: crate::proxy::audio::get(),
+            game: None,
+            notifications: VecDeque::new(),
+        };
+        let thread = Some(std::thread::spawn(move || state.run()));
+
+        Mqtt { tx, thread }
+    }
+
+    /// Channel for events sent from elsewhere, e.g. notifications
+    pub fn sender(&self) -> mpsc::Sender<MqttEvent> {
+        self.tx.clone()
+    }
+
+    pub fn send(&self, event: MqttEvent) {
+        if self.tx.send(event).is_err() {
+            warn!("Failed to send MQTT event");
+        }
+    }
+
+    /// Wait for the thread to finish, `running` must have been cleared
+    pub fn join(&mut self) {
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("MQTT thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_length, encode_length, packet, PINGREQ};
+
+    #[test]
+    fn remaining_length() {
+        let mut out = Vec::new();
+        encode_length(127, &mut out);
+        assert_eq!(out, vec![0x7f]);
+        out.clear();
+        encode_length(321, &mut out);
+        assert_eq!(out, vec![0xc1, 0x02]);
+        assert_eq!(packet(PINGREQ, &[]), vec![PINGREQ, 0]);
+        assert_eq!(decode_length(&mut &[0xc1, 0x02][..]).unwrap(), 321);
+        // Up to 256 MiB can be sent, but isn't allocated
+        assert!(decode_length(&mut &[0xff, 0xff, 0xff, 0x7f][..]).is_err());
+        assert!(decode_length(&mut &[0x81, 0x40][..]).is_err());
+    }
+}
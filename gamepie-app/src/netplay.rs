@@ -0,0 +1,252 @@
+use log::{debug, error, info, warn};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use gamepie_core::commands::{ScreenMessage, ScreenToast};
+use gamepie_core::config::NetplayConfig;
+use gamepie_screen::{FrameTap, RecordFrame, Screen};
+
+use crate::jpeg::JpegEncoder;
+
+// Each connection starts with "GPNP", the protocol version, the frame width
+// and height as big endian u16s, and 1 if the device's buttons are used or
+// 0 if it can only watch. Each frame is then sent as its length as a big
+// endian u32 followed by a JPEG. The device playing sends the buttons it
+// holds as a big endian u16 RetroPad mask whenever they change.
+const MAGIC: &[u8; 4] = b"GPNP";
+const VERSION: u8 = 1;
+
+const MAX_CLIENTS: usize = 4;
+const JPEG_QUALITY: u8 = 70;
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+// Devices that can't take a frame in this time are disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn header(width: u16, height: u16, input: bool) -> Vec<u8> {
+    let mut h = Vec::with_capacity(10);
+    h.extend_from_slice(MAGIC);
+    h.push(VERSION);
+    h.extend_from_slice(&width.to_be_bytes());
+    h.extend_from_slice(&height.to_be_bytes());
+    h.push(u8::from(input));
+    h
+}
+
+fn frame_message(jpeg: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.extend_from_slice(&(jpeg.len() as u32).to_be_bytes());
+    out.extend_from_slice(jpeg);
+}
+
+fn toast(toasts: &mpsc::Sender<ScreenToast>, text: String) {
+    if toasts
+        .send(ScreenToast::info(ScreenMessage::Message(text)))
+        .is_err()
+    {
+        warn!("Failed to send netplay toast");
+    }
+}
+
+// Press the buttons sent by the device playing until it disconnects
+fn read_buttons(mut stream: TcpStream, port: u32, playing: Arc<AtomicBool>) {
+    let mut buf = [0; 2];
+    while stream.read_exact(&mut buf).is_ok() {
+        gamepie_controller::set_remote_buttons(port, u16::from_be_bytes(buf));
+    }
+    gamepie_controller::set_remote_buttons(port, 0);
+    playing.store(false, Ordering::Release);
+}
+
+struct Client {
+    stream: TcpStream,
+    addr: SocketAddr,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Client {
+    fn close(mut self) {
+        // Also ends the reader's blocking read
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(reader) = self.reader.take() {
+            if reader.join().is_err() {
+                error!("Netplay reader for {} panicked", self.addr);
+            }
+        }
+    }
+}
+
+struct NetplayThread {
+    listener: TcpListener,
+    frames: mpsc::Receiver<RecordFrame>,
+    running: Arc<AtomicBool>,
+    toasts: mpsc::Sender<ScreenToast>,
+    encoder: JpegEncoder,
+    width: u16,
+    height: u16,
+    input: bool,
+    port: u32,
+    clients: Vec<Client>,
+    // Whether a device is playing, cleared by its reader when it leaves
+    playing: Arc<AtomicBool>,
+}
+
+impl NetplayThread {
+    fn accept(&mut self) {
+        loop {
+            let (mut stream, addr) = match self.listener.accept() {
+                Ok(conn) => conn,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Netplay accept failed: {}", e);
+                    return;
+                }
+            };
+            if self.clients.len() >= MAX_CLIENTS {
+                info!("Netplay refused {}, too many connected", addr);
+                continue;
+            }
+            let play = self.input && !self.playing.swap(true, Ordering::AcqRel);
+            let setup = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_nodelay(true))
+                .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                .and_then(|_| stream.write_all(&header(self.width, self.height, play)));
+            let reader = match (setup, play) {
+                (Err(e), _) => {
+                    warn!("Netplay setup for {} failed: {}", addr, e);
+                    if play {
+                        self.playing.store(false, Ordering::Release);
+                    }
+                    continue;
+                }
+                (Ok(()), false) => None,
+                (Ok(()), true) => match stream.try_clone() {
+                    Ok(read) => {
+                        let (port, playing) = (self.port, self.playing.clone());
+                        Some(std::thread::spawn(move || {
+                            read_buttons(read, port, playing)
+                        }))
+                    }
+                    Err(e) => {
+                        warn!("Netplay can't read buttons from {}: {}", addr, e);
+                        self.playing.store(false, Ordering::Release);
+                        None
+                    }
+                },
+            };
+            let role = if reader.is_some() {
+                "playing"
+            } else {
+                "watching"
+            };
+            info!("Netplay: {} {}", addr, role);
+            toast(&self.toasts, format!("{} {}", addr.ip(), role));
+            self.clients.push(Client {
+                stream,
+                addr,
+                reader,
+            });
+        }
+    }
+
+    fn send(&mut self, data: &[u16], jpeg: &mut Vec<u8>, msg: &mut Vec<u8>) {
+        self.encoder.encode(data, jpeg);
+        frame_message(jpeg, msg);
+        let mut i = 0;
+        while i < self.clients.len() {
+            if let Err(e) = self.clients[i].stream.write_all(msg) {
+                let client = self.clients.swap_remove(i);
+                info!("Netplay: {} left ({})", client.addr, e);
+                toast(&self.toasts, format!("{} left", client.addr.ip()));
+                client.close();
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn run(mut self) {
+        let mut jpeg = Vec::new();
+        let mut msg = Vec::new();
+        while self.running.load(Ordering::Acquire) {
+            self.accept();
+            match self.frames.recv_timeout(POLL_TIMEOUT) {
+                // Frames are only encoded while someone is watching
+                Ok((_, data)) if !self.clients.is_empty() => self.send(&data, &mut jpeg, &mut msg),
+                Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        for client in self.clients.drain(..) {
+            client.close();
+        }
+        debug!("Netplay thread finished");
+    }
+}
+
+/// Streams the game to other devices on the network, with the buttons from
+/// the first to connect pressed for another player
+pub(crate) struct Netplay {
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Netplay {
+    /// Start listening, copying the frames drawn to `screen`
+    pub fn start(
+        config: &NetplayConfig,
+        screen: &mut Screen,
+        running: Arc<AtomicBool>,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", config.port))?;
+        listener.set_nonblocking(true)?;
+        let (width, height) = (screen.width(), screen.height());
+        // A single frame is queued, newer frames are dropped while it waits
+        let (tx, frames) = mpsc::sync_channel(1);
+        screen.set_frame_tap(FrameTap::Stream, Some(tx));
+        let state = NetplayThread {
+            listener,
+            frames,
+            running,
+            toasts: screen.overlay_channel(),
+            encoder: JpegEncoder::new(width.into(), height.into(), JPEG_QUALITY),
+            width,
+            height,
+            input: config.input,
+            port: config.player - 1,
+            clients: Vec::new(),
+            playing: Arc::new(AtomicBool::new(false)),
+        };
+        info!("Netplay listening on port {}", config.port);
+        let thread = Some(std::thread::spawn(move || state.run()));
+        Ok(Netplay { thread })
+    }
+
+    /// Wait for the thread to finish, `running` must have been cleared
+    pub fn join(&mut self) {
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("Netplay thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame_message, header};
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            header(320, 240, true),
+            vec![b'G', b'P', b'N', b'P', 1, 0x01, 0x40, 0x00, 0xf0, 1]
+        );
+        let mut msg = vec![9];
+        frame_message(&[0xff, 0xd8, 0xff, 0xd9], &mut msg);
+        assert_eq!(msg, vec![0, 0, 0, 4, 0xff, 0xd8, 0xff, 0xd9]);
+    }
+}
@@ -0,0 +1,103 @@
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gamepie_core::commands::{FeedbackMsg, FeedbackSource, ScreenToast};
+use gamepie_core::config::NotifyConfig;
+use gamepie_core::error::GamepieError;
+use gamepie_core::problem::Severity;
+
+use crate::feedback::NOTIFICATION_PULSE;
+use crate::mqtt::MqttEvent;
+use crate::plugin::Plugins;
+
+fn wants(filter: Option<Severity>, severity: Severity) -> bool {
+    filter.is_some_and(|min| severity >= min)
+}
+
+/// Sends notifications on to each sink that wants their severity: toasts
+/// on screen, a feedback pulse, MQTT and a log file. Plugins get them all.
+pub(crate) struct Notifier {
+    config: NotifyConfig,
+    screen: mpsc::Sender<ScreenToast>,
+    mqtt: Option<mpsc::Sender<MqttEvent>>,
+    log: Option<File>,
+    plugins: Plugins,
+}
+
+impl Notifier {
+    pub fn new(config: &NotifyConfig, screen: mpsc::Sender<ScreenToast>, log: &Path) -> Self {
+        let log = config.log.and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log)
+                .map_err(|e| warn!("Failed to open '{}': {}", log.display(), e))
+                .ok()
+        });
+        Notifier {
+            config: config.clone(),
+            screen,
+            mqtt: None,
+            log,
+            plugins: Plugins::default(),
+        }
+    }
+
+    /// Also publish notifications to MQTT
+    pub fn set_mqtt(&mut self, mqtt: mpsc::Sender<MqttEvent>) {
+        self.mqtt = Some(mqtt);
+    }
+
+    /// Also pass notifications to plugins
+    pub fn set_plugins(&mut self, plugins: Plugins) {
+        self.plugins = plugins;
+    }
+
+    /// Send a toast to the sinks, false if the screen has gone
+    pub fn toast(&self, toast: ScreenToast) -> bool {
+        let severity = toast.severity();
+        self.others(severity, &toast.text());
+        !wants(self.config.screen, severity) || self.screen.send(toast).is_ok()
+    }
+
+    /// Send a fatal error to the sinks other than the screen, which shows
+    /// it in full instead
+    pub fn fatal(&self, e: &GamepieError) {
+        self.others(Severity::Error, &format!("{} {}", e.code_label(), e));
+    }
+
+    fn others(&self, severity: Severity, text: &str) {
+        self.plugins.notify(severity, text);
+        if wants(self.config.led, severity) {
+            let pulse = FeedbackMsg::Pulse(FeedbackSource::Notification, NOTIFICATION_PULSE);
+            crate::proxy::feedback::send(pulse);
+        }
+        if let Some(mqtt) = self
+            .mqtt
+            .as_ref()
+            .filter(|_| wants(self.config.mqtt, severity))
+        {
+            let msg = format!("{}: {}", severity, text);
+            if mqtt.send(MqttEvent::Notification(msg)).is_err() {
+                warn!("Failed to send notification to MQTT");
+            }
+        }
+        if let Some(mut log) = self
+            .log
+            .as_ref()
+            .filter(|_| wants(self.config.log, severity))
+        {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if let Err(e) = writeln!(log, "{} {}: {}", time, severity, text) {
+                warn!("Failed to log notification: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+// Seconds left at which the countdown is shown every second
+const COUNTDOWN_FINAL: u64 = 10;
+
+/// Party mode, where several games are played in turn for a fixed time each
+pub(crate) struct Party {
+    games: Vec<String>,
+    current: usize,
+    turn: Duration,
+    turn_start: Instant,
+    // Last countdown shown, so each is only shown once
+    shown: Option<u64>,
+}
+
+impl Party {
+    pub fn new(games: Vec<String>, turn: Duration) -> Self {
+        Party {
+            games,
+            current: 0,
+            turn,
+            turn_start: Instant::now(),
+            shown: None,
+        }
+    }
+
+    /// Game whose turn it is
+    pub fn current(&self) -> &str {
+        &self.games[self.current]
+    }
+
+    /// Move on to the next game, going back to the first after the last
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.games.len();
+    }
+
+    /// Start timing a turn, once the game has loaded
+    pub fn start_turn(&mut self) {
+        self.turn_start = Instant::now();
+        self.shown = None;
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.turn.saturating_sub(self.turn_start.elapsed())
+    }
+
+    /// Seconds left to show, on each whole minute and every second near the
+    /// end of the turn
+    pub fn countdown(&mut self) -> Option<u64> {
+        let secs = self.remaining().as_millis().div_ceil(1000) as u64;
+        let show = secs > 0 && (secs <= COUNTDOWN_FINAL || secs.is_multiple_of(60));
+        if show && self.shown != Some(secs) {
+            self.shown = Some(secs);
+            Some(secs)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Party;
+    use std::time::Duration;
+
+    #[test]
+    fn rotation() {
+        let games = vec![String::from("a.gb"), String::from("b.nes")];
+        let mut party = Party::new(games, Duration::from_secs(120));
+        assert_eq!(party.current(), "a.gb");
+        party.next();
+        assert_eq!(party.current(), "b.nes");
+        party.next();
+        assert_eq!(party.current(), "a.gb");
+        // Two minutes left is shown once
+        assert_eq!(party.countdown(), Some(120));
+        assert_eq!(party.countdown(), None);
+    }
+}
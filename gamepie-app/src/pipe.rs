@@ -0,0 +1,179 @@
+use log::{debug, error, info, warn};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use crate::json::{self, JsonValue};
+
+// Commands from an external launcher, one JSON object per line, e.g.
+//   {"cmd": "launch", "path": "/roms/game.gb", "core": "Gambatte"}
+//   {"cmd": "stop"}
+//   {"cmd": "screenshot", "path": "/tmp/shot.ppm"}
+//   {"cmd": "volume", "delta": -2}
+//   {"cmd": "record", "path": "/tmp/music.wav"}
+//   {"cmd": "stop_record"}
+//   {"cmd": "dump_memory", "address": 49152, "length": 8192, "path": "/tmp/wram.bin"}
+//   {"cmd": "rescan"}
+
+/// Commands read from the pipe
+#[derive(Debug, PartialEq)]
+pub(crate) enum PipeCommand {
+    /// Start a game, optionally with a specific core
+    Launch(PathBuf, Option<String>),
+    /// Stop the running game and return to the menu
+    Stop,
+    /// Save the next frame, to the screenshot directory if no path given
+    Screenshot(Option<PathBuf>),
+    /// Change volume by a number of steps
+    Volume(i32),
+    /// Record the game's audio, to the screenshot directory if no path given
+    Record(Option<PathBuf>),
+    StopRecord,
+    /// Write a region of the running game's memory, from an emulated
+    /// address, to a file
+    DumpMemory(usize, usize, PathBuf),
+    /// Find the games again, after ROMs have been added or removed
+    Rescan,
+}
+
+fn parse_command(line: &str) -> Result<PipeCommand, String> {
+    let object = match json::parse(line) {
+        Some(JsonValue::Object(o)) => o,
+        _ => return Err(String::from("invalid JSON object")),
+    };
+    let string = |key: &str| match object.get(key) {
+        Some(JsonValue::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let unsigned = |key: &str| match object.get(key) {
+        Some(JsonValue::Number(n)) if n.fract() == 0.0 && *n >= 0.0 => Some(*n as usize),
+        _ => None,
+    };
+
+    match string("cmd").as_deref() {
+        Some("launch") => match string("path") {
+            Some(path) => Ok(PipeCommand::Launch(PathBuf::from(path), string("core"))),
+            None => Err(String::from("launch needs a path")),
+        },
+        Some("stop") => Ok(PipeCommand::Stop),
+        Some("screenshot") => Ok(PipeCommand::Screenshot(string("path").map(PathBuf::from))),
+        Some("volume") => match object.get("delta") {
+            Some(JsonValue::Number(n)) if n.fract() == 0.0 => Ok(PipeCommand::Volume(*n as i32)),
+            _ => Err(String::from("volume needs an integer delta")),
+        },
+        Some("record") => Ok(PipeCommand::Record(string("path").map(PathBuf::from))),
+        Some("stop_record") => Ok(PipeCommand::StopRecord),
+        Some("rescan") => Ok(PipeCommand::Rescan),
+        Some("dump_memory") => match (unsigned("address"), unsigned("length"), string("path")) {
+            (Some(address), Some(length), Some(path)) => Ok(PipeCommand::DumpMemory(
+                address,
+                length,
+                PathBuf::from(path),
+            )),
+            _ => Err(String::from(
+                "dump_memory needs an address, length and path",
+            )),
+        },
+        Some(c) => Err(format!("unknown command '{}'", c)),
+        None => Err(String::from("missing cmd")),
+    }
+}
+
+// Read commands until the reader is closed, returns false if the receiver
+// has gone.
+fn read_commands<R: BufRead>(reader: R, tx: &mpsc::Sender<PipeCommand>) -> bool {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("Failed to read command: {}", e);
+                return true;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_command(&line) {
+            Ok(cmd) => {
+                debug!("Pipe command: {:?}", cmd);
+                if tx.send(cmd).is_err() {
+                    return false;
+                }
+            }
+            Err(e) => warn!("Ignoring command '{}': {}", line, e),
+        }
+    }
+    true
+}
+
+/// Start reading commands from `path`, or stdin if the path is "-", and
+/// sending them to `tx`. A FIFO is reopened when the writer closes it so
+/// launchers can connect more than once. The thread blocks on the pipe, so
+/// it isn't joined and ends when the process exits.
+pub(crate) fn start(path: &str, tx: mpsc::Sender<PipeCommand>) {
+    let path = PathBuf::from(path);
+    std::thread::spawn(move || {
+        if path.as_os_str() == "-" {
+            info!("Reading commands from stdin");
+            read_commands(std::io::stdin().lock(), &tx);
+        } else {
+            info!("Reading commands from '{}'", path.display());
+            loop {
+                // Opening a FIFO blocks until there is a writer
+                match File::open(&path) {
+                    Ok(f) => {
+                        let fifo = matches!(f.metadata(), Ok(m) if m.file_type().is_fifo());
+                        if !read_commands(BufReader::new(f), &tx) || !fifo {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to open command pipe: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        debug!("Command pipe closed");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_command, PipeCommand};
+    use std::path::PathBuf;
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            parse_command(r#"{"cmd": "launch", "path": "/roms/a \"b\".gb"}"#),
+            Ok(PipeCommand::Launch(PathBuf::from("/roms/a \"b\".gb"), None))
+        );
+        assert_eq!(parse_command(r#"{"cmd":"stop"}"#), Ok(PipeCommand::Stop));
+        assert_eq!(
+            parse_command(r#"{"cmd":"rescan"}"#),
+            Ok(PipeCommand::Rescan)
+        );
+        assert_eq!(
+            parse_command(r#"{"cmd": "record"}"#),
+            Ok(PipeCommand::Record(None))
+        );
+        assert_eq!(
+            parse_command(r#"{ "delta": -2, "cmd": "volume" }"#),
+            Ok(PipeCommand::Volume(-2))
+        );
+        assert!(parse_command(r#"{"cmd": "volume", "delta": 1.5}"#).is_err());
+        assert_eq!(
+            parse_command(r#"{"cmd": "dump_memory", "address": 49152, "length": 16, "path": "m"}"#),
+            Ok(PipeCommand::DumpMemory(0xc000, 16, PathBuf::from("m")))
+        );
+        assert!(parse_command(
+            r#"{"cmd": "dump_memory", "address": -1, "length": 1, "path": "m"}"#
+        )
+        .is_err());
+        assert!(parse_command(r#"{"cmd": "stop"} trailing"#).is_err());
+        assert!(parse_command("stop").is_err());
+    }
+}
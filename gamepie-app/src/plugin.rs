@@ -0,0 +1,155 @@
+use log::{info, warn};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use gamepie_core::problem::Severity;
+use gamepie_libretrobind::enums::RetroPadButton;
+
+/// Extension compiled into the frontend, for integrations that don't belong
+/// in gamepie itself such as LED matrices or scoreboards. Every method has a
+/// default that does nothing, so a plugin only implements what it uses.
+pub trait Plugin: Send {
+    fn name(&self) -> &str;
+
+    /// Called with each notification, whatever the configured sinks
+    fn notify(&mut self, _severity: Severity, _text: &str) {}
+
+    /// Buttons the plugin handles when pressed with Select during a game.
+    /// Buttons already used by gamepie's hotkeys are ignored.
+    fn hotkeys(&self) -> Vec<RetroPadButton> {
+        Vec::new()
+    }
+
+    /// One of the plugin's hotkeys was pressed, returns a message to show
+    fn hotkey(&mut self, _button: RetroPadButton) -> Option<String> {
+        None
+    }
+
+    /// Titles of screens added to the power menu
+    fn screens(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Items listed on one of the plugin's screens, asked for each time it
+    /// is drawn so they can change
+    fn screen_items(&mut self, _screen: usize) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// An item was selected on one of the plugin's screens, returns a
+    /// message to show
+    fn screen_select(&mut self, _screen: usize, _item: usize) -> Option<String> {
+        None
+    }
+}
+
+/// Plugins that have been added, shared with the notifier
+#[derive(Clone, Default)]
+pub(crate) struct Plugins {
+    plugins: Arc<Mutex<Vec<Box<dyn Plugin>>>>,
+}
+
+impl Plugins {
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<dyn Plugin>>> {
+        // A plugin that panicked is left as it was
+        self.plugins.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add(&self, plugin: Box<dyn Plugin>) {
+        info!("Added plugin '{}'", plugin.name());
+        self.lock().push(plugin);
+    }
+
+    pub fn notify(&self, severity: Severity, text: &str) {
+        for plugin in self.lock().iter_mut() {
+            plugin.notify(severity, text);
+        }
+    }
+
+    /// Pass a hotkey to the first plugin that handles it
+    pub fn hotkey(&self, button: RetroPadButton) -> Option<String> {
+        let mut plugins = self.lock();
+        match plugins.iter_mut().find(|p| p.hotkeys().contains(&button)) {
+            Some(plugin) => plugin.hotkey(button),
+            None => {
+                warn!("No plugin for hotkey {:?}", button);
+                None
+            }
+        }
+    }
+
+    /// Titles of every plugin's screens, in the order they were added
+    pub fn screens(&self) -> Vec<String> {
+        self.lock().iter().flat_map(|p| p.screens()).collect()
+    }
+
+    // Apply `f` to the plugin that owns a screen, with its own index for it
+    fn with_screen<T, F>(&self, screen: usize, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut dyn Plugin, usize) -> T,
+    {
+        let mut plugins = self.lock();
+        let mut first = 0;
+        for plugin in plugins.iter_mut() {
+            let count = plugin.screens().len();
+            if screen < first + count {
+                return Some(f(plugin.as_mut(), screen - first));
+            }
+            first += count;
+        }
+        None
+    }
+
+    pub fn screen_items(&self, screen: usize) -> Vec<String> {
+        self.with_screen(screen, |p, s| p.screen_items(s))
+            .unwrap_or_default()
+    }
+
+    pub fn screen_select(&self, screen: usize, item: usize) -> Option<String> {
+        self.with_screen(screen, |p, s| p.screen_select(s, item))
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plugin, Plugins};
+
+    struct Counter {
+        name: &'static str,
+        screens: usize,
+        selected: Vec<(usize, usize)>,
+    }
+
+    impl Plugin for Counter {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn screens(&self) -> Vec<String> {
+            (0..self.screens)
+                .map(|s| format!("{} {}", self.name, s))
+                .collect()
+        }
+
+        fn screen_select(&mut self, screen: usize, item: usize) -> Option<String> {
+            self.selected.push((screen, item));
+            Some(format!("{} {:?}", self.name, self.selected))
+        }
+    }
+
+    #[test]
+    fn screens() {
+        let plugins = Plugins::default();
+        for (name, screens) in [("a", 2), ("b", 0), ("c", 1)] {
+            plugins.add(Box::new(Counter {
+                name,
+                screens,
+                selected: Vec::new(),
+            }));
+        }
+        assert_eq!(plugins.screens(), vec!["a 0", "a 1", "c 0"]);
+        assert_eq!(plugins.screen_select(1, 4).as_deref(), Some("a [(1, 4)]"));
+        assert_eq!(plugins.screen_select(2, 0).as_deref(), Some("c [(0, 0)]"));
+        assert_eq!(plugins.screen_select(3, 0), None);
+    }
+}
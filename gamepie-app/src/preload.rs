@@ -0,0 +1,108 @@
+use log::{debug, warn};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// How long a game must stay selected before it is read, so scrolling
+// through the menu doesn't start a read for every game passed
+const HOVER_DELAY: Duration = Duration::from_millis(300);
+// Larger games, e.g. CD images, are left for the core to read
+const PRELOAD_MAX: u64 = 64 * 1024 * 1024;
+// Read in chunks so an evicted read stops promptly
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// Game selected in the menu, and its read once started
+struct Preload {
+    path: PathBuf,
+    since: Instant,
+    // Only tried once, even if the file couldn't be read
+    tried: bool,
+    read: Option<JoinHandle<Option<Vec<u8>>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Preload {
+    fn start(&mut self) {
+        self.tried = true;
+        let size = match std::fs::metadata(&self.path) {
+            Ok(m) if m.is_file() && m.len() <= PRELOAD_MAX => m.len(),
+            _ => return,
+        };
+        let path = self.path.clone();
+        let cancelled = self.cancel.clone();
+        let spawned = std::thread::Builder::new()
+            .name(String::from("preload"))
+            .spawn(move || {
+                let mut file = File::open(&path).ok()?;
+                let mut data = Vec::with_capacity(size as usize);
+                let mut chunk = vec![0; CHUNK_SIZE];
+                loop {
+                    if cancelled.load(Ordering::Acquire) {
+                        return None;
+                    }
+                    match file.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(n) => data.extend_from_slice(&chunk[..n]),
+                        Err(e) => {
+                            warn!("Failed to preload '{}': {}", path.display(), e);
+                            return None;
+                        }
+                    }
+                }
+                debug!("Preloaded '{}' ({} bytes)", path.display(), data.len());
+                Some(data)
+            });
+        match spawned {
+            Ok(handle) => self.read = Some(handle),
+            Err(e) => warn!("Failed to start preload: {}", e),
+        }
+    }
+}
+
+impl Drop for Preload {
+    fn drop(&mut self) {
+        // The thread isn't waited for, it stops at its next chunk
+        self.cancel.store(true, Ordering::Release);
+    }
+}
+
+/// Reads the game selected in the menu in the background, so starting it
+/// doesn't have to wait for the whole file to come off the SD card.
+#[derive(Default)]
+pub(crate) struct Preloader {
+    current: Option<Preload>,
+}
+
+impl Preloader {
+    /// Note the game selected in the menu, starting to read it once it has
+    /// been selected for a while. Any other game's data is dropped.
+    pub fn hover(&mut self, path: &Path) {
+        match &mut self.current {
+            Some(p) if p.path == path => {
+                if !p.tried && p.since.elapsed() >= HOVER_DELAY {
+                    p.start();
+                }
+            }
+            _ => {
+                self.current = Some(Preload {
+                    path: path.to_path_buf(),
+                    since: Instant::now(),
+                    tried: false,
+                    read: None,
+                    cancel: Arc::new(AtomicBool::new(false)),
+                })
+            }
+        }
+    }
+
+    /// Contents of a game if it was being preloaded, waiting for the rest of
+    /// it to be read. Any other game's data is dropped.
+    pub fn take(&mut self, path: &Path) -> Option<Vec<u8>> {
+        let mut preload = self.current.take().filter(|p| p.path == path)?;
+        preload.read.take()?.join().ok().flatten()
+    }
+}
@@ -1,9 +1,11 @@
 use lazy_static::lazy_static;
 use log::{error, trace};
 use std::sync::{mpsc, Mutex};
+use std::time::Duration;
 
 use gamepie_audio::Audio;
-use gamepie_core::commands::{AudioMsg, ScreenToast};
+use gamepie_core::commands::{AudioMsg, AudioStatus, ScreenToast};
+use gamepie_core::config::AudioConfig;
 use gamepie_core::problem::Problem;
 
 lazy_static! {
@@ -30,7 +32,11 @@ pub(crate) fn get() -> mpsc::Sender<AudioMsg> {
     }
 }
 
-pub(crate) fn try_create(overlay_tx: mpsc::Sender<ScreenToast>, error_tx: mpsc::Sender<Problem>) {
+pub(crate) fn try_create(
+    config: &AudioConfig,
+    overlay_tx: mpsc::Sender<ScreenToast>,
+    error_tx: mpsc::Sender<Problem>,
+) {
     trace!("Creating proxy object for audio");
     let mut guard = match AUDIO.lock() {
         Ok(g) => g,
@@ -46,7 +52,58 @@ pub(crate) fn try_create(overlay_tx: mpsc::Sender<ScreenToast>, error_tx: mpsc::
     };
 
     if replace {
-        let audio = Audio::new(overlay_tx, error_tx);
+        let audio = Audio::new(config, overlay_tx, error_tx);
         *guard = Some(audio);
     }
 }
+
+pub(crate) fn underruns() -> u32 {
+    let guard = match AUDIO.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for audio proxy");
+            e.into_inner()
+        }
+    };
+
+    (*guard).as_ref().map_or(0, |a| a.underruns())
+}
+
+pub(crate) fn take_status() -> Option<AudioStatus> {
+    let guard = match AUDIO.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for audio proxy");
+            e.into_inner()
+        }
+    };
+
+    (*guard).as_ref().and_then(|a| a.take_status())
+}
+
+pub(crate) fn latency() -> Duration {
+    let guard = match AUDIO.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for audio proxy");
+            e.into_inner()
+        }
+    };
+
+    (*guard).as_ref().map_or(Duration::ZERO, |a| a.latency())
+}
+
+pub(crate) fn volume() -> Option<u8> {
+    let guard = match AUDIO.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for audio proxy");
+            e.into_inner()
+        }
+    };
+
+    (*guard)
+        .as_ref()
+        .filter(|a| a.is_real())
+        .map(|a| a.current_volume())
+}
@@ -0,0 +1,36 @@
+use lazy_static::lazy_static;
+use log::{error, trace};
+use std::sync::{mpsc, Mutex};
+
+use gamepie_core::commands::FeedbackMsg;
+
+lazy_static! {
+    static ref FEEDBACK: Mutex<Option<mpsc::Sender<FeedbackMsg>>> = Mutex::new(None);
+}
+
+pub(crate) fn set(tx: mpsc::Sender<FeedbackMsg>) {
+    let mut guard = match FEEDBACK.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for feedback proxy");
+            e.into_inner()
+        }
+    };
+    *guard = Some(tx);
+}
+
+/// Send a feedback request, dropped if there are no actuators
+pub(crate) fn send(msg: FeedbackMsg) {
+    let guard = match FEEDBACK.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for feedback proxy");
+            e.into_inner()
+        }
+    };
+    if let Some(tx) = (*guard).as_ref() {
+        if tx.send(msg).is_err() {
+            trace!("Feedback thread has finished");
+        }
+    }
+}
@@ -1,14 +1,23 @@
 use log::{error, trace, warn};
 use std::error::Error;
 
+use gamepie_core::commands::{FeedbackMsg, RumbleEffect};
 use gamepie_libretro::callbacks::retro_environment_callback_inner;
-use gamepie_libretro::proxy::{ProxyWarning, RetroProxy};
+use gamepie_libretro::proxy::{ProxyWarning, RetroProxy, MAX_PORTS};
 use gamepie_libretrobind::bind::{
     retro_audio_sample_batch_t, retro_audio_sample_t, retro_environment_t, retro_input_poll_t,
-    retro_input_state_t, retro_video_refresh_t, size_t, RETRO_DEVICE_JOYPAD,
+    retro_input_state_t, retro_rumble_effect, retro_rumble_effect_RETRO_RUMBLE_STRONG,
+    retro_rumble_effect_RETRO_RUMBLE_WEAK, retro_sensor_action,
+    retro_sensor_action_RETRO_SENSOR_ACCELEROMETER_DISABLE,
+    retro_sensor_action_RETRO_SENSOR_ACCELEROMETER_ENABLE,
+    retro_sensor_action_RETRO_SENSOR_GYROSCOPE_DISABLE,
+    retro_sensor_action_RETRO_SENSOR_GYROSCOPE_ENABLE, retro_video_refresh_t, size_t,
+    RETRO_DEVICE_ANALOG, RETRO_DEVICE_JOYPAD, RETRO_DEVICE_KEYBOARD,
 };
 use gamepie_libretrobind::enums::RetroDevice;
 
+use crate::proxy::sensor::SensorKind;
+
 unsafe extern "C" fn retro_environment_callback(
     cmd: ::std::os::raw::c_uint,
     data: *mut ::std::os::raw::c_void,
@@ -98,9 +107,18 @@ pub fn retro_set_video_refresh(lib: &libloading::Library) -> Result<(), Box<dyn
 extern "C" fn retro_input_poll_callback() {
     let f = |p: &mut RetroProxy| {
         p.input_poll();
+        (p.keyboard_callback(), p.take_key_events())
     };
-    if crate::proxy::libretro::with_proxy(f).is_none() {
-        error!("Callback executed before core loaded")
+    match crate::proxy::libretro::with_proxy(f) {
+        // Called without the proxy locked, in case the core calls back into
+        // the frontend
+        Some((Some(cb), events)) => {
+            for e in events {
+                unsafe { cb(e.down, e.keycode, e.character, e.modifiers) };
+            }
+        }
+        Some((None, _)) => {}
+        None => error!("Callback executed before core loaded"),
     }
 }
 
@@ -124,16 +142,73 @@ pub fn retro_set_input_poll(
     }
 }
 
+pub(crate) unsafe extern "C" fn retro_set_rumble_state_callback(
+    port: ::std::os::raw::c_uint,
+    effect: retro_rumble_effect,
+    strength: u16,
+) -> bool {
+    let feedback_effect = if effect == retro_rumble_effect_RETRO_RUMBLE_STRONG {
+        RumbleEffect::Strong
+    } else if effect == retro_rumble_effect_RETRO_RUMBLE_WEAK {
+        RumbleEffect::Weak
+    } else {
+        return false;
+    };
+    if port >= MAX_PORTS {
+        return false;
+    }
+    // Controllers that support force feedback rumble for their own port
+    crate::proxy::libretro::with_proxy(|p| p.set_rumble(port, effect, strength));
+    // The device only has the one motor, for the first player
+    if port == 0 {
+        let strength = f32::from(strength) / f32::from(u16::MAX);
+        crate::proxy::feedback::send(FeedbackMsg::Rumble(feedback_effect, strength));
+    }
+    true
+}
+
+pub(crate) unsafe extern "C" fn retro_set_sensor_state_callback(
+    port: ::std::os::raw::c_uint,
+    action: retro_sensor_action,
+    _rate: ::std::os::raw::c_uint,
+) -> bool {
+    // The sensor is part of the device, so only for the first player
+    if port != 0 {
+        return false;
+    }
+    let (kind, enabled) = if action == retro_sensor_action_RETRO_SENSOR_ACCELEROMETER_ENABLE {
+        (SensorKind::Accelerometer, true)
+    } else if action == retro_sensor_action_RETRO_SENSOR_ACCELEROMETER_DISABLE {
+        (SensorKind::Accelerometer, false)
+    } else if action == retro_sensor_action_RETRO_SENSOR_GYROSCOPE_ENABLE {
+        (SensorKind::Gyroscope, true)
+    } else if action == retro_sensor_action_RETRO_SENSOR_GYROSCOPE_DISABLE {
+        (SensorKind::Gyroscope, false)
+    } else {
+        return false;
+    };
+    crate::proxy::sensor::set_enabled(kind, enabled)
+}
+
+pub(crate) unsafe extern "C" fn retro_sensor_get_input_callback(
+    port: ::std::os::raw::c_uint,
+    id: ::std::os::raw::c_uint,
+) -> f32 {
+    if port != 0 {
+        return 0.0;
+    }
+    crate::proxy::sensor::input(id as usize)
+}
+
 extern "C" fn retro_input_state_callback(
     port: ::std::os::raw::c_uint,
     device: ::std::os::raw::c_uint,
-    // Index unused as not applicable for joypad
-    _index: ::std::os::raw::c_uint,
+    // Index only applies to analog
+    index: ::std::os::raw::c_uint,
     id: ::std::os::raw::c_uint,
 ) -> i16 {
     match crate::proxy::libretro::with_proxy(|p| {
-        if port != 0 {
-            // Only expect any controller on port 0
+        if port >= MAX_PORTS {
             let msg = format!("Trying to get input for port {}", port);
             p.warn_once(ProxyWarning::DevicePort, &msg);
             return 0;
@@ -142,12 +217,17 @@ extern "C" fn retro_input_state_callback(
         if device == RETRO_DEVICE_JOYPAD {
             let button = num::FromPrimitive::from_u32(id);
             match button {
-                Some(b) => p.input_state(b),
+                Some(b) => p.input_state(port, b),
                 None => {
                     warn!("Unknown button");
                     0
                 }
             }
+        } else if device == RETRO_DEVICE_ANALOG {
+            p.input_state_analog(port, index, id)
+        } else if device == RETRO_DEVICE_KEYBOARD {
+            // There is one keyboard, shared by all ports
+            p.key_state(id)
         } else {
             let msg = format!(
                 "Unsupported input device: {}",
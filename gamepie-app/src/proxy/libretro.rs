@@ -1,14 +1,17 @@
 use lazy_static::lazy_static;
 use log::{error, trace, warn};
 use std::ops::DerefMut;
-use std::sync::{mpsc, Mutex};
+use std::sync::{mpsc, Mutex, TryLockError};
 
 use gamepie_core::commands::AudioMsg;
+use gamepie_core::config::InputConfig;
+use gamepie_core::error::GamepieError;
 use gamepie_core::portable::PString;
 use gamepie_core::problem::Problem;
 use gamepie_libretro::proxy::RetroProxy;
+use gamepie_libretrobind::bind::retro_sensor_interface;
 use gamepie_libretrobind::types::RetroSystemAvInfo;
-use gamepie_screen::Screen;
+use gamepie_screen::{Screen, ScreenHandle};
 
 lazy_static! {
     static ref PROXY: Mutex<Option<RetroProxy>> = Mutex::new(None);
@@ -29,12 +32,30 @@ where
     guard.deref_mut().as_mut().map(f)
 }
 
+/// As `with_proxy`, but gives up rather than waiting if the proxy is in use,
+/// e.g. for a crash report after a panic while it was locked
+pub(crate) fn try_with_proxy<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce(&mut RetroProxy) -> T,
+{
+    let mut guard = match PROXY.try_lock() {
+        Ok(g) => g,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return None,
+    };
+
+    guard.deref_mut().as_mut().map(f)
+}
+
+/// Create a new proxy, replacing any existing proxy, lending it the screen.
 pub(crate) fn create(
     system_dir: PString,
-    screen: Option<Screen>,
+    save_dir: PString,
+    screens: &mut ScreenHandle,
     error_channel: mpsc::Sender<Problem>,
     audio_channel: mpsc::Sender<AudioMsg>,
-) {
+    input: &InputConfig,
+) -> Result<(), GamepieError> {
     trace!("Creating proxy object for libretro callbacks");
     let mut guard = match PROXY.lock() {
         Ok(g) => g,
@@ -43,21 +64,79 @@ pub(crate) fn create(
             e.into_inner()
         }
     };
-    // Take old proxy to drop if needed
-    let old_proxy = (*guard).take();
-    let new_screen = match old_proxy {
-        Some(mut old_proxy) => {
-            // If there is an old proxy, it should have a screen
-            assert!(screen.is_none(), "screen passed in with existing proxy");
-            old_proxy.take_screen()
-        }
-        None => {
-            // Must take old screen
-            screen
+    // Take back the screen from any old proxy before lending it again
+    if let Some(screen) = (*guard).as_mut().and_then(|p| p.take_screen()) {
+        screens.give_back(screen);
+    }
+    *guard = None;
+    let screen = screens
+        .lend("libretro proxy")
+        .ok_or(GamepieError::NoVideo)?;
+    *guard = Some(new_proxy(
+        system_dir,
+        save_dir,
+        Some(screen),
+        error_channel,
+        audio_channel,
+        input,
+    ));
+    Ok(())
+}
+
+/// Create a proxy without the device's screen, frames from the core are
+/// counted and drawn to `screen` if there is one. Used to test cores.
+pub(crate) fn create_headless(
+    system_dir: PString,
+    save_dir: PString,
+    screen: Option<Screen>,
+    error_channel: mpsc::Sender<Problem>,
+    audio_channel: mpsc::Sender<AudioMsg>,
+    input: &InputConfig,
+) {
+    trace!("Creating headless proxy object for libretro callbacks");
+    let mut guard = match PROXY.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for libretro proxy");
+            e.into_inner()
         }
     };
-    let proxy = RetroProxy::new(system_dir, new_screen, error_channel, audio_channel);
-    *guard = Some(proxy);
+    *guard = Some(new_proxy(
+        system_dir,
+        save_dir,
+        screen,
+        error_channel,
+        audio_channel,
+        input,
+    ));
+}
+
+fn new_proxy(
+    system_dir: PString,
+    save_dir: PString,
+    screen: Option<Screen>,
+    error_channel: mpsc::Sender<Problem>,
+    audio_channel: mpsc::Sender<AudioMsg>,
+    input: &InputConfig,
+) -> RetroProxy {
+    let mut proxy = RetroProxy::new(
+        system_dir,
+        save_dir,
+        screen,
+        error_channel,
+        audio_channel,
+        input,
+    );
+    proxy.set_rumble_callback(Some(
+        crate::proxy::functions::retro_set_rumble_state_callback,
+    ));
+    if crate::proxy::sensor::reset() {
+        proxy.set_sensor_interface(Some(retro_sensor_interface {
+            set_sensor_state: Some(crate::proxy::functions::retro_set_sensor_state_callback),
+            get_sensor_input: Some(crate::proxy::functions::retro_sensor_get_input_callback),
+        }));
+    }
+    proxy
 }
 
 pub(crate) fn set_av(av: RetroSystemAvInfo) {
@@ -79,7 +158,7 @@ pub(crate) fn set_av(av: RetroSystemAvInfo) {
     }
 }
 
-pub(crate) fn destroy() -> Option<Screen> {
+pub(crate) fn destroy(screens: &mut ScreenHandle) {
     trace!("Destroying proxy object");
     let mut guard = match PROXY.lock() {
         Ok(g) => g,
@@ -89,8 +168,7 @@ pub(crate) fn destroy() -> Option<Screen> {
         }
     };
     let old_proxy = (*guard).take();
-    match old_proxy {
-        Some(mut old_proxy) => old_proxy.take_screen(),
-        None => None,
+    if let Some(screen) = old_proxy.and_then(|mut p| p.take_screen()) {
+        screens.give_back(screen);
     }
 }
@@ -1,3 +1,5 @@
 pub(crate) mod audio;
+pub(crate) mod feedback;
 pub(crate) mod functions;
 pub(crate) mod libretro;
+pub(crate) mod sensor;
@@ -0,0 +1,90 @@
+use lazy_static::lazy_static;
+use log::{debug, error};
+use std::sync::Mutex;
+
+// Readings from the motion sensor, accelerometer X/Y/Z then gyroscope
+// X/Y/Z, as libretro numbers its sensor inputs
+pub(crate) const SENSOR_INPUTS: usize = 6;
+
+/// Sensors a core can turn on
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SensorKind {
+    Accelerometer,
+    Gyroscope,
+}
+
+#[derive(Default)]
+struct Readings {
+    values: [f32; SENSOR_INPUTS],
+    accel: bool,
+    gyro: bool,
+}
+
+lazy_static! {
+    // None if there is no sensor
+    static ref SENSOR: Mutex<Option<Readings>> = Mutex::new(None);
+}
+
+fn with_sensor<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce(&mut Readings) -> T,
+{
+    let mut guard = match SENSOR.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for sensor proxy");
+            e.into_inner()
+        }
+    };
+    (*guard).as_mut().map(f)
+}
+
+/// Note that a sensor has been found, so cores are offered it
+pub(crate) fn set_available() {
+    let mut guard = match SENSOR.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for sensor proxy");
+            e.into_inner()
+        }
+    };
+    *guard = Some(Readings::default());
+}
+
+/// Turn the sensors off for a new core, returns whether there is a sensor
+pub(crate) fn reset() -> bool {
+    with_sensor(|r| *r = Readings::default()).is_some()
+}
+
+/// Turn a sensor on or off for the core, false if there is no sensor
+pub(crate) fn set_enabled(kind: SensorKind, enabled: bool) -> bool {
+    with_sensor(|r| {
+        debug!("{:?} enabled: {}", kind, enabled);
+        match kind {
+            SensorKind::Accelerometer => r.accel = enabled,
+            SensorKind::Gyroscope => r.gyro = enabled,
+        }
+    })
+    .is_some()
+}
+
+/// Whether the core wants readings, so the sensor can sleep when it doesn't
+pub(crate) fn wanted() -> bool {
+    with_sensor(|r| r.accel || r.gyro).unwrap_or(false)
+}
+
+pub(crate) fn update(values: [f32; SENSOR_INPUTS]) {
+    with_sensor(|r| r.values = values);
+}
+
+/// Latest reading for a libretro sensor input, 0 for sensors that are off
+pub(crate) fn input(id: usize) -> f32 {
+    with_sensor(|r| {
+        let on = if id < 3 { r.accel } else { r.gyro };
+        match r.values.get(id) {
+            Some(v) if on => *v,
+            _ => 0.0,
+        }
+    })
+    .unwrap_or(0.0)
+}
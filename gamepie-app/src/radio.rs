@@ -0,0 +1,43 @@
+use log::{debug, info};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Radios (Wi-Fi, Bluetooth) are blocked through the kernel's rfkill
+// interface, the same as `rfkill block all`.
+const RFKILL_PATH: &str = "/sys/class/rfkill";
+
+fn radios() -> Vec<PathBuf> {
+    match std::fs::read_dir(RFKILL_PATH) {
+        Ok(dir) => dir.flatten().map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn blocked(radio: &Path) -> bool {
+    matches!(std::fs::read_to_string(radio.join("soft")), Ok(s) if s.trim() == "1")
+}
+
+/// Check if airplane mode is on, i.e. every radio is blocked
+pub(crate) fn airplane_mode() -> bool {
+    let radios = radios();
+    !radios.is_empty() && radios.iter().all(|r| blocked(r))
+}
+
+/// Block or unblock all radios
+pub(crate) fn set_airplane_mode(on: bool) -> io::Result<()> {
+    let radios = radios();
+    if radios.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no radios"));
+    }
+    for radio in radios {
+        let name = std::fs::read_to_string(radio.join("name")).unwrap_or_default();
+        debug!(
+            "{} radio '{}'",
+            if on { "Blocking" } else { "Unblocking" },
+            name.trim()
+        );
+        std::fs::write(radio.join("soft"), if on { "1" } else { "0" })?;
+    }
+    info!("Airplane mode {}", if on { "on" } else { "off" });
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Kept under the 1 GiB that AVI files without an OpenDML index are limited
+// to by many players
+pub(crate) const MAX_FILE_LEN: u64 = 1 << 30;
+
+// Index flag for frames that can be decoded alone, every MJPEG frame
+const AVIIF_KEYFRAME: u32 = 0x10;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIF_ISINTERLEAVED: u32 = 0x100;
+
+const AUDIO_CHANNELS: u16 = 2;
+const AUDIO_BLOCK: u16 = AUDIO_CHANNELS * 2;
+
+// Fields in the header filled in when the file is finished
+struct Patches {
+    riff_len: u64,
+    total_frames: u64,
+    video_len: u64,
+    audio_len: u64,
+    movi_len: u64,
+}
+
+fn fourcc(out: &mut Vec<u8>, code: &[u8; 4]) {
+    out.extend_from_slice(code);
+}
+
+fn u16le(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn u32le(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+// Start a chunk or list, returns where its length goes
+fn open(out: &mut Vec<u8>, code: &[u8; 4]) -> usize {
+    fourcc(out, code);
+    u32le(out, 0);
+    out.len() - 4
+}
+
+// Fill in the length of a chunk or list opened at `at`
+fn close(out: &mut [u8], at: usize) {
+    let len = (out.len() - at - 4) as u32;
+    out[at..at + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Writes MJPEG video and 16 bit stereo PCM audio to an AVI file, with an
+/// index of the chunks at the end
+pub(crate) struct AviWriter {
+    file: BufWriter<File>,
+    patches: Patches,
+    // Position of the "movi" list type, that index offsets are from
+    movi: u64,
+    len: u64,
+    index: Vec<u8>,
+    frames: u32,
+    audio_blocks: u32,
+}
+
+impl AviWriter {
+    pub fn create(
+        path: &Path,
+        width: u16,
+        height: u16,
+        fps: f64,
+        sample_rate: u32,
+    ) -> std::io::Result<Self> {
+        // Frame rate as a fraction, to the nearest 1/1000 of a frame
+        let scale = 1000;
+        let rate = (fps * f64::from(scale)).round().max(1.0) as u32;
+        let mut h = Vec::new();
+        let riff = open(&mut h, b"RIFF");
+        fourcc(&mut h, b"AVI ");
+
+        let hdrl = open(&mut h, b"LIST");
+        fourcc(&mut h, b"hdrl");
+        let avih = open(&mut h, b"avih");
+        u32le(&mut h, (1_000_000.0 / fps.max(1.0)) as u32);
+        u32le(&mut h, 0);
+        u32le(&mut h, 0);
+        u32le(&mut h, AVIF_HASINDEX | AVIF_ISINTERLEAVED);
+        let total_frames = h.len();
+        u32le(&mut h, 0);
+        u32le(&mut h, 0);
+        // Streams
+        u32le(&mut h, 2);
+        u32le(&mut h, 0);
+        u32le(&mut h, width.into());
+        u32le(&mut h, height.into());
+        h.extend_from_slice(&[0; 16]);
+        close(&mut h, avih);
+
+        let strl = open(&mut h, b"LIST");
+        fourcc(&mut h, b"strl");
+        let strh = open(&mut h, b"strh");
+        fourcc(&mut h, b"vids");
+        fourcc(&mut h, b"MJPG");
+        // Flags, priority and language, initial frames
+        h.extend_from_slice(&[0; 12]);
+        u32le(&mut h, scale);
+        u32le(&mut h, rate);
+        u32le(&mut h, 0);
+        let video_len = h.len();
+        u32le(&mut h, 0);
+        u32le(&mut h, 0);
+        // Default quality, and frames vary in size
+        u32le(&mut h, u32::MAX);
+        u32le(&mut h, 0);
+        h.extend_from_slice(&[0; 4]);
+        u16le(&mut h, width);
+        u16le(&mut h, height);
+        close(&mut h, strh);
+        let strf = open(&mut h, b"strf");
+        u32le(&mut h, 40);
+        u32le(&mut h, width.into());
+        u32le(&mut h, height.into());
+        u16le(&mut h, 1);
+        u16le(&mut h, 24);
+        fourcc(&mut h, b"MJPG");
+        u32le(&mut h, u32::from(width) * u32::from(height) * 3);
+        h.extend_from_slice(&[0; 16]);
+        close(&mut h, strf);
+        close(&mut h, strl);
+
+        let strl = open(&mut h, b"LIST");
+        fourcc(&mut h, b"strl");
+        let strh = open(&mut h, b"strh");
+        fourcc(&mut h, b"auds");
+        h.extend_from_slice(&[0; 16]);
+        u32le(&mut h, AUDIO_BLOCK.into());
+        u32le(&mut h, sample_rate * u32::from(AUDIO_BLOCK));
+        u32le(&mut h, 0);
+        let audio_len = h.len();
+        u32le(&mut h, 0);
+        u32le(&mut h, 0);
+        u32le(&mut h, u32::MAX);
+        u32le(&mut h, AUDIO_BLOCK.into());
+        h.extend_from_slice(&[0; 8]);
+        close(&mut h, strh);
+        let strf = open(&mut h, b"strf");
+        // PCM
+        u16le(&mut h, 1);
+        u16le(&mut h, AUDIO_CHANNELS);
+        u32le(&mut h, sample_rate);
+        u32le(&mut h, sample_rate * u32::from(AUDIO_BLOCK));
+        u16le(&mut h, AUDIO_BLOCK);
+        u16le(&mut h, 16);
+        close(&mut h, strf);
+        close(&mut h, strl);
+        close(&mut h, hdrl);
+
+        let movi_len = open(&mut h, b"LIST");
+        let movi = h.len() as u64;
+        fourcc(&mut h, b"movi");
+        let len = h.len() as u64;
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&h)?;
+        Ok(AviWriter {
+            file,
+            patches: Patches {
+                riff_len: riff as u64,
+                total_frames: total_frames as u64,
+                video_len: video_len as u64,
+                audio_len: audio_len as u64,
+                movi_len: movi_len as u64,
+            },
+            movi,
+            len,
+            index: Vec::new(),
+            frames: 0,
+            audio_blocks: 0,
+        })
+    }
+
+    /// Size of the file so far, including the index still to be written
+    pub fn len(&self) -> u64 {
+        self.len + self.index.len() as u64
+    }
+
+    fn chunk(&mut self, code: &[u8; 4], data: &[u8], flags: u32) -> std::io::Result<()> {
+        let mut entry = Vec::with_capacity(16);
+        fourcc(&mut entry, code);
+        u32le(&mut entry, flags);
+        u32le(&mut entry, (self.len - self.movi) as u32);
+        u32le(&mut entry, data.len() as u32);
+        self.index.extend_from_slice(&entry);
+
+        self.file.write_all(code)?;
+        self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.len += 8 + data.len() as u64;
+        // Chunks start on even offsets
+        if data.len() % 2 == 1 {
+            self.file.write_all(&[0])?;
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Add a JPEG frame
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> std::io::Result<()> {
+        self.chunk(b"00dc", jpeg, AVIIF_KEYFRAME)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Add interleaved stereo samples
+    pub fn write_audio(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.chunk(b"01wb", &bytes, AVIIF_KEYFRAME)?;
+        self.audio_blocks += (samples.len() / usize::from(AUDIO_CHANNELS)) as u32;
+        Ok(())
+    }
+
+    /// Write the index, fill in the lengths in the header and close the
+    /// file. Returns the number of frames.
+    pub fn finish(mut self) -> std::io::Result<u32> {
+        let movi_end = self.len;
+        self.file.write_all(b"idx1")?;
+        self.file
+            .write_all(&(self.index.len() as u32).to_le_bytes())?;
+        self.file.write_all(&self.index)?;
+        self.len += 8 + self.index.len() as u64;
+
+        let p = &self.patches;
+        let fields = [
+            (p.riff_len, self.len - p.riff_len - 4),
+            (p.total_frames, self.frames.into()),
+            (p.video_len, self.frames.into()),
+            (p.audio_len, self.audio_blocks.into()),
+            (p.movi_len, movi_end - p.movi_len - 4),
+        ];
+        for (at, value) in fields {
+            self.file.seek(SeekFrom::Start(at))?;
+            self.file.write_all(&(value as u32).to_le_bytes())?;
+        }
+        self.file.flush()?;
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AviWriter;
+    use gamepie_core::testing::TestDir;
+
+    #[test]
+    fn chunks() {
+        let dir = TestDir::new("avi");
+        let path = dir.join("test.avi");
+        let mut avi = AviWriter::create(&path, 320, 240, 59.73, 32768).unwrap();
+        avi.write_frame(&[0xff, 0xd8, 0xff]).unwrap();
+        avi.write_audio(&[1, 2, 3, 4]).unwrap();
+        avi.write_frame(&[0xff, 0xd8]).unwrap();
+        let len = avi.len();
+        assert_eq!(avi.finish().unwrap(), 2);
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(data.len() as u64, len + 8);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let find = |code: &[u8]| data.windows(4).position(|w| w == code).unwrap();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, data.len() - 8);
+        // Total frames in the main header
+        assert_eq!(u32_at(find(b"avih") + 8 + 16), 2);
+        // Frame rate as rate / scale
+        let strh = find(b"strh");
+        assert_eq!(u32_at(strh + 8 + 20), 1000);
+        assert_eq!(u32_at(strh + 8 + 24), 59730);
+        let movi = find(b"movi");
+        assert_eq!(u32_at(movi - 4) as usize, find(b"idx1") - movi);
+        // Odd sized frame is padded, offsets are from "movi"
+        let idx = find(b"idx1") + 8;
+        assert_eq!(&data[idx..idx + 4], b"00dc");
+        assert_eq!(u32_at(idx + 8), 4);
+        assert_eq!(u32_at(idx + 16 + 8), 4 + 12);
+        assert_eq!(u32_at(idx + 32 + 8), 4 + 12 + 16);
+    }
+}
@@ -0,0 +1,120 @@
+use log::{debug, error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use gamepie_screen::RecordFrame;
+
+mod avi;
+
+use avi::{AviWriter, MAX_FILE_LEN};
+
+use crate::jpeg::JpegEncoder;
+
+// Frames waiting to be encoded, newer frames are dropped while it is full
+const FRAME_QUEUE: usize = 2;
+const JPEG_QUALITY: u8 = 80;
+// How often queued audio is written while no frames arrive
+const AUDIO_POLL: Duration = Duration::from_millis(100);
+
+// Encode frames and write them with the audio until the frame channel is
+// closed. Frames that were dropped repeat the one before, so the video
+// keeps time with the audio.
+fn record(
+    mut avi: AviWriter,
+    mut encoder: JpegEncoder,
+    frames: mpsc::Receiver<RecordFrame>,
+    audio: mpsc::Receiver<Vec<i16>>,
+) -> std::io::Result<(u32, u64)> {
+    let mut jpeg = Vec::new();
+    let mut last_seq = None;
+    let mut dropped = 0;
+    let mut full = false;
+    loop {
+        let frame = match frames.recv_timeout(AUDIO_POLL) {
+            Ok(frame) => Some(frame),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if avi.len() >= MAX_FILE_LEN {
+            if !full {
+                warn!("Recording is full");
+                full = true;
+            }
+            // Drain the channels until the recording is stopped
+            audio.try_iter().for_each(drop);
+            continue;
+        }
+        if let Some((seq, data)) = frame {
+            if let Some(last) = last_seq {
+                let missed = seq.saturating_sub(last + 1);
+                for _ in 0..missed {
+                    avi.write_frame(&jpeg)?;
+                }
+                dropped += missed;
+            }
+            last_seq = Some(seq);
+            encoder.encode(&data, &mut jpeg);
+            avi.write_frame(&jpeg)?;
+        }
+        for samples in audio.try_iter() {
+            avi.write_audio(&samples)?;
+        }
+    }
+    for samples in audio.try_iter() {
+        avi.write_audio(&samples)?;
+    }
+    Ok((avi.finish()?, dropped))
+}
+
+/// Records the frames sent to the screen, after scaling, and the game's
+/// audio to an MJPEG AVI file. Stops when dropped, once the file is done.
+pub(crate) struct Recorder {
+    path: PathBuf,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Start recording what the proxy draws and plays
+    pub fn start(path: &Path, fps: f64, sample_rate: u32) -> std::io::Result<Self> {
+        let (width, height) = crate::proxy::libretro::with_proxy(|p| p.screen_size())
+            .flatten()
+            .ok_or_else(|| std::io::Error::other("no screen to record"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let avi = AviWriter::create(path, width, height, fps, sample_rate)?;
+        let encoder = JpegEncoder::new(width.into(), height.into(), JPEG_QUALITY);
+        let (frame_tx, frames) = mpsc::sync_channel(FRAME_QUEUE);
+        let (audio_tx, audio) = mpsc::channel();
+        let name = path.display().to_string();
+        let handle = std::thread::spawn(move || match record(avi, encoder, frames, audio) {
+            Ok((frames, dropped)) => {
+                info!("Recorded {} frames to '{}'", frames, name);
+                if dropped > 0 {
+                    debug!("{} frames repeated as the encoder fell behind", dropped);
+                }
+            }
+            Err(e) => error!("Failed to record '{}': {}", name, e),
+        });
+        crate::proxy::libretro::with_proxy(|p| p.set_recorder(Some(frame_tx), Some(audio_tx)));
+        info!("Recording video to '{}'", path.display());
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Closing the channels lets the thread finish the file
+        crate::proxy::libretro::with_proxy(|p| p.set_recorder(None, None));
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                warn!("Recorder for '{}' panicked", self.path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Kept in the root directory rather than the configured directories, so
+// they still work when the config file is what is broken
+const BOOT_FILE: &str = "boot_attempts";
+const BACKUP_DIR: &str = "config_backups";
+const BACKUP_PREFIX: &str = "gamepie-";
+// Starts in a row that stopped before settling, before safe mode is used
+const CRASH_LOOP_STARTS: u32 = 3;
+// Running this long counts as a good start
+const BOOT_SETTLE: Duration = Duration::from_secs(60);
+// Backups of the config file kept, oldest are removed first
+const CONFIG_BACKUPS: usize = 5;
+
+/// Backup of the config file that can be restored
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ConfigBackup {
+    pub path: PathBuf,
+    /// Seconds since the backup was made
+    pub age: u64,
+}
+
+// Backups in a directory, newest first
+fn list_backups(dir: &Path) -> Vec<ConfigBackup> {
+    let now = SystemTime::now();
+    let mut backups: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with(BACKUP_PREFIX))
+        })
+        .filter_map(|p| Some((std::fs::metadata(&p).ok()?.modified().ok()?, p)))
+        .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.0));
+    backups
+        .into_iter()
+        .map(|(modified, path)| ConfigBackup {
+            path,
+            age: now.duration_since(modified).unwrap_or_default().as_secs(),
+        })
+        .collect()
+}
+
+// Copy the config file into the backup directory, unless it is the same as
+// the newest backup. Returns the new backup, if one was made.
+fn backup_config(config: &Path, dir: &Path) -> std::io::Result<Option<PathBuf>> {
+    let contents = match std::fs::read(config) {
+        Ok(c) => c,
+        // Nothing to back up while running on the defaults
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let backups = list_backups(dir);
+    if let Some(newest) = backups.first() {
+        if std::fs::read(&newest.path).is_ok_and(|c| c == contents) {
+            return Ok(None);
+        }
+    }
+    std::fs::create_dir_all(dir)?;
+    let mut secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let name = |secs| dir.join(format!("{}{}.toml", BACKUP_PREFIX, secs));
+    // Don't replace a backup made in the same second
+    while name(secs).exists() {
+        secs += 1;
+    }
+    let path = name(secs);
+    std::fs::write(&path, contents)?;
+    for old in backups.iter().skip(CONFIG_BACKUPS - 1) {
+        if let Err(e) = std::fs::remove_file(&old.path) {
+            warn!("Failed to remove '{}': {}", old.path.display(), e);
+        }
+    }
+    Ok(Some(path))
+}
+
+/// Counts starts that stop before running for a while, so a crash loop can
+/// start in safe mode, and backs up the config file once a start has
+/// settled so it can be restored from the recovery menu.
+pub(crate) struct Boot {
+    counter: PathBuf,
+    backups: PathBuf,
+    config: PathBuf,
+    started: Instant,
+    settled: bool,
+}
+
+impl Boot {
+    /// Note that gamepie is starting, returns whether the last few starts
+    /// all stopped before settling
+    pub fn start(root: &Path, config: &Path) -> (Self, bool) {
+        let counter = root.join(BOOT_FILE);
+        let starts = std::fs::read_to_string(&counter)
+            .ok()
+            .and_then(|c| c.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+        if let Err(e) = std::fs::write(&counter, (starts + 1).to_string()) {
+            warn!("Failed to count start: {}", e);
+        }
+        if starts > 0 {
+            warn!("{} previous starts stopped early", starts);
+        }
+        let boot = Boot {
+            counter,
+            backups: root.join(BACKUP_DIR),
+            config: config.to_path_buf(),
+            started: Instant::now(),
+            settled: false,
+        };
+        (boot, starts >= CRASH_LOOP_STARTS)
+    }
+
+    /// Call regularly, once gamepie has run for a while the start is good.
+    /// The config file is backed up then, unless running in safe mode.
+    pub fn tick(&mut self, safe: bool) {
+        if self.settled || self.started.elapsed() < BOOT_SETTLE {
+            return;
+        }
+        self.settled = true;
+        debug!("Start settled");
+        self.reset();
+        if safe {
+            return;
+        }
+        match backup_config(&self.config, &self.backups) {
+            Ok(Some(path)) => info!("Backed up config to '{}'", path.display()),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to back up config: {}", e),
+        }
+    }
+
+    /// Forget the starts so far, so the next start isn't in safe mode
+    pub fn reset(&self) {
+        match std::fs::remove_file(&self.counter) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to reset start count: {}", e),
+        }
+    }
+
+    /// Backups of the config file, newest first
+    pub fn backups(&self) -> Vec<ConfigBackup> {
+        list_backups(&self.backups)
+    }
+
+    /// Replace the config file with a backup
+    pub fn restore(&self, backup: &ConfigBackup) -> std::io::Result<()> {
+        std::fs::copy(&backup.path, &self.config)?;
+        info!("Restored config from '{}'", backup.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backup_config, list_backups, Boot, CONFIG_BACKUPS, CRASH_LOOP_STARTS};
+    use gamepie_core::testing::TestDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn crash_loop() {
+        let dir = TestDir::new("boot");
+        let dir = dir.path();
+        let config = dir.join("gamepie.toml");
+
+        for _ in 0..CRASH_LOOP_STARTS {
+            assert!(!Boot::start(dir, &config).1);
+        }
+        let (boot, crash_loop) = Boot::start(dir, &config);
+        assert!(crash_loop);
+        boot.reset();
+        assert!(!Boot::start(dir, &config).1);
+    }
+
+    #[test]
+    fn backups() {
+        let dir = TestDir::new("backup");
+        let dir = dir.path();
+        let config = dir.join("gamepie.toml");
+        let backups = dir.join("backups");
+
+        assert_eq!(backup_config(&config, &backups).unwrap(), None);
+        std::fs::write(&config, "[video]\n").unwrap();
+        let first = backup_config(&config, &backups).unwrap().unwrap();
+        // Unchanged, so not backed up again
+        assert_eq!(backup_config(&config, &backups).unwrap(), None);
+
+        // Older backups, so the first is still the newest
+        let now = SystemTime::now();
+        for n in 0..CONFIG_BACKUPS {
+            let path = backups.join(format!("gamepie-{}.toml", n));
+            std::fs::write(&path, n.to_string()).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(60 * (n as u64 + 1)))
+                .unwrap();
+        }
+        std::fs::write(&config, "[menu]\n").unwrap();
+        let second = backup_config(&config, &backups).unwrap().unwrap();
+        let kept: Vec<_> = list_backups(&backups).into_iter().map(|b| b.path).collect();
+        assert_eq!(kept.len(), CONFIG_BACKUPS);
+        assert_eq!(kept[1], first);
+        assert!(kept.contains(&second));
+    }
+}
@@ -0,0 +1,206 @@
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use gamepie_core::config::Config;
+use gamepie_core::dirs;
+use gamepie_core::metadata;
+
+use crate::json::{self, JsonValue};
+
+// Lines per entry in playlists from before RetroArch 1.7.6: path, label,
+// core path, core name, CRC and database name
+const OLD_ENTRY_LINES: usize = 6;
+// Characters RetroArch replaces with '_' in thumbnail file names
+const THUMB_UNSAFE: &str = "&*/:`<>?\\|\"";
+// Images the menu looks for next to a game
+const THUMB_EXT: &str = "png";
+
+#[derive(Debug, PartialEq)]
+struct PlaylistEntry {
+    path: String,
+    label: String,
+}
+
+// Read a playlist, either JSON or the older line based format
+fn parse_playlist(contents: &str) -> Vec<PlaylistEntry> {
+    if contents.trim_start().starts_with('{') {
+        let doc = match json::parse(contents) {
+            Some(d) => d,
+            None => {
+                warn!("Invalid JSON playlist");
+                return Vec::new();
+            }
+        };
+        let items = doc
+            .get("items")
+            .and_then(JsonValue::as_array)
+            .unwrap_or(&[]);
+        items
+            .iter()
+            .filter_map(|item| {
+                Some(PlaylistEntry {
+                    path: String::from(item.get("path")?.as_str()?),
+                    label: String::from(item.get("label")?.as_str()?),
+                })
+            })
+            .collect()
+    } else {
+        let lines: Vec<&str> = contents.lines().collect();
+        lines
+            .chunks_exact(OLD_ENTRY_LINES)
+            .map(|e| PlaylistEntry {
+                path: String::from(e[0]),
+                label: String::from(e[1]),
+            })
+            .collect()
+    }
+}
+
+// File name RetroArch gives the thumbnails of an entry
+fn thumb_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if THUMB_UNSAFE.contains(c) { '_' } else { c })
+        .collect();
+    format!("{}.{}", name, THUMB_EXT)
+}
+
+// File name of the game an entry is for, without any file inside an archive
+// (e.g. "game.zip#game.gb")
+fn entry_file(path: &str) -> Option<&str> {
+    let path = path.split('#').next()?;
+    Path::new(path).file_name()?.to_str()
+}
+
+// Give a game a name, keeping the rest of any metadata it already has,
+// returns false if it already had one
+fn import_name(rom: &Path, label: &str) -> Result<bool, Box<dyn Error>> {
+    let mut table = match metadata::load(rom) {
+        Some(toml::Value::Table(t)) => t,
+        _ => toml::value::Table::new(),
+    };
+    if table.contains_key("name") {
+        return Ok(false);
+    }
+    table.insert(String::from("name"), toml::Value::String(label.into()));
+    std::fs::write(metadata::path(rom), toml::Value::Table(table).to_string())?;
+    Ok(true)
+}
+
+// Copy box art next to a game for the menu, unless it already has an image,
+// returns false if nothing was copied
+fn import_image(rom: &Path, art: &Path) -> Result<bool, Box<dyn Error>> {
+    let mut sidecar = rom.as_os_str().to_os_string();
+    sidecar.push(".");
+    sidecar.push(THUMB_EXT);
+    let sidecar = PathBuf::from(sidecar);
+    let has_image = metadata::get_str(rom, "image").is_some()
+        || sidecar.exists()
+        || rom.with_extension(THUMB_EXT).exists();
+    if has_image || !art.is_file() {
+        return Ok(false);
+    }
+    std::fs::copy(art, &sidecar)?;
+    Ok(true)
+}
+
+/// Import names and box art for the games in gamepie's ROM directories from
+/// RetroArch's playlists and thumbnails, games are matched by file name.
+/// Existing names and images are kept.
+pub fn import_retroarch(
+    root_dir: &str,
+    config_file: Option<&str>,
+    retroarch: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(root_dir, config_file);
+    let mut roms: HashMap<String, PathBuf> = HashMap::new();
+    for dir in config.dirs.rom_dirs() {
+        for file in dirs::game_files(dir) {
+            let name = file.file_name().and_then(|n| n.to_str()).map(String::from);
+            if let Some(name) = name {
+                roms.entry(name).or_insert_with(|| dir.join(&file));
+            }
+        }
+    }
+
+    let playlists = std::fs::read_dir(retroarch.join("playlists"))
+        .map_err(|e| format!("no RetroArch playlists in '{}': {}", retroarch.display(), e))?;
+    let (mut names, mut images, mut missing) = (0, 0, 0);
+    for playlist in playlists.flatten() {
+        let path = playlist.path();
+        if path.extension().is_none_or(|e| e != "lpl") {
+            continue;
+        }
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to read '{}': {}", path.display(), e);
+                continue;
+            }
+        };
+        let entries = parse_playlist(&contents);
+        info!("{}: {} games", path.display(), entries.len());
+        // Thumbnails are kept in a directory named after the playlist
+        let thumbs = path
+            .file_stem()
+            .map(|s| retroarch.join("thumbnails").join(s).join("Named_Boxarts"));
+        for entry in entries {
+            let rom = match entry_file(&entry.path).and_then(|f| roms.get(f)) {
+                Some(r) => r,
+                None => {
+                    debug!("'{}' isn't in the ROM directories", entry.path);
+                    missing += 1;
+                    continue;
+                }
+            };
+            if import_name(rom, &entry.label)? {
+                names += 1;
+            }
+            if let Some(thumbs) = &thumbs {
+                if import_image(rom, &thumbs.join(thumb_name(&entry.label)))? {
+                    images += 1;
+                }
+            }
+        }
+    }
+
+    info!(
+        "Imported {} names and {} images, {} games not found",
+        names, images, missing
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_file, parse_playlist, thumb_name, PlaylistEntry};
+
+    #[test]
+    fn playlists() {
+        let json = r#"{
+          "version": "1.5",
+          "items": [
+            {
+              "path": "/home/pi/roms/Tetris (World).gb",
+              "label": "Tetris",
+              "core_path": "DETECT",
+              "crc32": "46DF91AD|crc"
+            }
+          ]
+        }"#;
+        let old = "/roms/sonic.zip#sonic.md\nSonic\nDETECT\nDETECT\nDETECT\nSega.lpl\n";
+        assert_eq!(
+            parse_playlist(json),
+            vec![PlaylistEntry {
+                path: String::from("/home/pi/roms/Tetris (World).gb"),
+                label: String::from("Tetris"),
+            }]
+        );
+        let old = parse_playlist(old);
+        assert_eq!(old[0].label, "Sonic");
+        assert_eq!(entry_file(&old[0].path), Some("sonic.zip"));
+        assert_eq!(thumb_name("Q*bert: Qubes"), "Q_bert_ Qubes.png");
+    }
+}
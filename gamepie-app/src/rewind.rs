@@ -0,0 +1,140 @@
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use gamepie_core::config::RewindConfig;
+use gamepie_libretrobind::utils;
+
+// Longest gap between captures when the core is slow to save its state
+const MAX_INTERVAL: u32 = 60;
+
+/// Recent states of the core, captured every few frames into a buffer of
+/// limited size, oldest states are dropped to make room for new ones. The
+/// budget is shared with the quick save.
+pub(crate) struct Rewind {
+    states: VecDeque<Vec<u8>>,
+    budget: usize,
+    min_interval: u32,
+    interval: u32,
+    countdown: u32,
+    failed: bool,
+}
+
+impl Rewind {
+    /// `shared_budget` is the memory for all states held in memory, the
+    /// rewind buffer uses no more than its own budget of that
+    pub fn new(config: &RewindConfig, shared_budget: usize) -> Self {
+        Rewind {
+            states: VecDeque::new(),
+            budget: config.memory_budget.min(shared_budget),
+            min_interval: config.interval,
+            interval: config.interval,
+            countdown: 0,
+            failed: false,
+        }
+    }
+
+    // Memory used by the buffered states
+    fn used(&self) -> usize {
+        self.states.iter().map(|s| s.len()).sum()
+    }
+
+    /// Drop the oldest states until no more than `limit` bytes are used
+    pub fn trim(&mut self, limit: usize) {
+        while self.used() > limit {
+            self.states.pop_front();
+        }
+    }
+
+    /// Called after each frame, captures the state when due. If saving the
+    /// state takes too much of the frame time, captures are made less often.
+    /// `reserved` is the memory of the budget used by the quick save.
+    pub fn capture(&mut self, lib: &libloading::Library, frame_time: Duration, reserved: usize) {
+        if self.failed {
+            return;
+        }
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return;
+        }
+        self.countdown = self.interval - 1;
+
+        let start = Instant::now();
+        // Reuse the oldest state's buffer once the budget is reached
+        let budget = self.budget.saturating_sub(reserved);
+        let size = self.states.back().map_or(0, |s| s.len());
+        let mut data = Vec::new();
+        while !self.states.is_empty() && self.used() + size > budget {
+            data = self.states.pop_front().unwrap_or_default();
+        }
+        if utils::serialize_into(lib, &mut data).is_err() {
+            warn!("Rewind disabled, core failed to save state");
+            self.failed = true;
+            self.states.clear();
+            return;
+        }
+        if data.len() > self.budget {
+            warn!(
+                "Rewind disabled, state of {} bytes exceeds budget",
+                data.len()
+            );
+            self.failed = true;
+            self.states.clear();
+            return;
+        }
+        if data.len() > budget {
+            // No room beside the quick save
+            return;
+        }
+        self.states.push_back(data);
+
+        let elapsed = start.elapsed();
+        if elapsed > frame_time / 4 && self.interval < MAX_INTERVAL {
+            self.interval = (self.interval * 2).min(MAX_INTERVAL);
+            debug!(
+                "Rewind capture took {:?}, capturing every {} frames",
+                elapsed, self.interval
+            );
+        } else if elapsed < frame_time / 16 && self.interval > self.min_interval {
+            self.interval -= 1;
+        }
+    }
+
+    /// Restore the most recent state and remove it from the buffer, returns
+    /// false if there are no states left.
+    pub fn step_back(&mut self, lib: &libloading::Library) -> bool {
+        match self.states.pop_back() {
+            Some(state) => {
+                let restored = utils::unserialize_state(lib, &state).is_ok();
+                // Capture again as soon as the rewind ends
+                self.countdown = 0;
+                restored
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewind;
+    use gamepie_core::config::RewindConfig;
+
+    #[test]
+    fn shared_budget() {
+        let config = RewindConfig::default();
+        let mut rewind = Rewind::new(&config, 1000);
+        assert_eq!(rewind.budget, 1000);
+        rewind
+            .states
+            .extend([vec![1; 300], vec![2; 300], vec![3; 300]]);
+        // Room for a quick save of 500 bytes
+        rewind.trim(500);
+        assert_eq!(rewind.used(), 300);
+        assert_eq!(rewind.states[0][0], 3);
+        assert_eq!(
+            Rewind::new(&config, usize::MAX).budget,
+            config.memory_budget
+        );
+    }
+}
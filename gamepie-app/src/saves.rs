@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use gamepie_core::checksum::crc32;
+use gamepie_core::config::Config;
+use gamepie_core::dirs;
+use gamepie_core::stats::Stats;
+use gamepie_core::{SAVEDATA_EXT, TIMED_SAVES};
+
+// Suffix of the save kept when another is imported over it
+pub(crate) const IMPORT_BACKUP: &str = ".bak";
+
+/// Save file operations, for managing saves from the command line
+pub enum SaveCommand {
+    /// List every save
+    List,
+    /// Show the details of the save for a game
+    Inspect(String),
+    /// Copy the save for a game to a file
+    Export(String, PathBuf),
+    /// Replace the save for a game with a file, `force` allows the size to
+    /// differ from the current save
+    Import(PathBuf, String, bool),
+}
+
+fn save_path(config: &Config, game: &str) -> Result<PathBuf, Box<dyn Error>> {
+    // Saves are named after the game's filename, so accept a path too
+    let save = config.dirs.save_file(Path::new(game));
+    save.ok_or_else(|| format!("'{}' isn't a game", game).into())
+}
+
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_os_string();
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+// Short description of how long ago a file was changed
+pub(crate) fn age(modified: SystemTime) -> String {
+    let secs = modified.elapsed().unwrap_or_default().as_secs();
+    if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h ago", secs / (60 * 60))
+    } else {
+        format!("{}d ago", secs / (60 * 60 * 24))
+    }
+}
+
+pub(crate) fn describe(path: &Path) -> Option<String> {
+    let meta = std::fs::metadata(path).ok()?;
+    let modified = meta.modified().map_or_else(|_| String::from("?"), age);
+    Some(format!("{} bytes, {}", meta.len(), modified))
+}
+
+fn list(config: &Config) -> Result<(), Box<dyn Error>> {
+    let save_dir = config.dirs.save_dir();
+    let mut saves: Vec<PathBuf> = std::fs::read_dir(save_dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(SAVEDATA_EXT))
+        .collect();
+    saves.sort();
+    for save in saves {
+        let name = save.file_stem().unwrap_or_default().to_string_lossy();
+        println!("{:40} {}", name, describe(&save).unwrap_or_default());
+    }
+    Ok(())
+}
+
+fn inspect(config: &Config, game: &str) -> Result<(), Box<dyn Error>> {
+    let save = save_path(config, game)?;
+    let data =
+        std::fs::read(&save).map_err(|e| format!("can't read save '{}': {}", save.display(), e))?;
+    println!("Save:     {}", save.display());
+    println!("          {}", describe(&save).unwrap_or_default());
+    println!("CRC32:    {:08x}", crc32(&data));
+    for suffix in TIMED_SAVES.iter().chain(&[IMPORT_BACKUP]) {
+        let backup = with_suffix(&save, suffix);
+        if let Some(d) = describe(&backup) {
+            println!("Backup:   {} ({})", backup.display(), d);
+        }
+    }
+
+    let file = save.file_stem().unwrap_or_default();
+    let rom = config.dirs.rom_dirs().iter().find_map(|d| {
+        dirs::game_files(d)
+            .into_iter()
+            .find(|f| f.file_name() == Some(file))
+            .map(|f| d.join(f))
+    });
+    match rom {
+        Some(rom) => {
+            let crc = crc32(&std::fs::read(&rom)?);
+            println!("ROM:      {} (CRC32 {:08x})", rom.display(), crc);
+            let stats = Stats::load(&config.dirs.stats_file());
+            if let Some(s) = stats.get(&rom) {
+                println!("Sessions: {}, {}", s.sessions, s.summary());
+                println!(
+                    "Unshown:  {} frames replaced before drawn",
+                    s.screen_dropped
+                );
+            }
+        }
+        None => println!("ROM:      not found"),
+    }
+    Ok(())
+}
+
+fn export(config: &Config, game: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
+    let save = save_path(config, game)?;
+    std::fs::copy(&save, dest)
+        .map_err(|e| format!("can't copy save '{}': {}", save.display(), e))?;
+    println!("Exported '{}' to '{}'", save.display(), dest.display());
+    Ok(())
+}
+
+fn import(config: &Config, src: &Path, game: &str, force: bool) -> Result<(), Box<dyn Error>> {
+    let save = save_path(config, game)?;
+    let len = std::fs::metadata(src)?.len();
+    if let Ok(current) = std::fs::metadata(&save) {
+        // Cores reject saves that aren't the size of their save memory
+        if current.len() != len && !force {
+            return Err(format!(
+                "'{}' is {} bytes but the current save is {} bytes, use --force to import anyway",
+                src.display(),
+                len,
+                current.len()
+            )
+            .into());
+        }
+        let backup = with_suffix(&save, IMPORT_BACKUP);
+        std::fs::copy(&save, &backup)?;
+        println!("Kept the current save as '{}'", backup.display());
+    }
+    std::fs::copy(src, &save)?;
+    // Timed saves are newer than the imported save, but are only used if
+    // the save is missing or damaged, so they're left alone.
+    println!("Imported '{}' to '{}'", src.display(), save.display());
+    Ok(())
+}
+
+/// Run a save file command against the configured save directory
+pub fn saves(
+    root_dir: &str,
+    config_file: Option<&str>,
+    command: SaveCommand,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(root_dir, config_file);
+    match command {
+        SaveCommand::List => list(&config),
+        SaveCommand::Inspect(game) => inspect(&config, &game),
+        SaveCommand::Export(game, dest) => export(&config, &game, &dest),
+        SaveCommand::Import(src, game, force) => import(&config, &src, &game, force),
+    }
+}
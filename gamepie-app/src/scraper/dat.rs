@@ -0,0 +1,188 @@
+use log::{debug, info};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use gamepie_core::checksum::crc32;
+
+use super::{Backend, GameMetadata};
+
+// Games from Logiqx XML DAT files (as used by No-Intro and Redump), matched
+// on the CRC or SHA-1 of the ROM, or its file name if it has been modified.
+// There is no need for a full XML parser, DATs are a flat list of tags.
+
+/// Game titles from DAT files
+pub(crate) struct Dat {
+    by_crc: HashMap<u32, String>,
+    by_sha1: HashMap<String, String>,
+    by_name: HashMap<String, String>,
+}
+
+/// Offline backend using DAT files
+pub(crate) struct DatBackend {
+    dat: Dat,
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Get an attribute from the inside of a tag, e.g. `rom name="a" crc="b"`
+fn attribute(tag: &str, key: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(key) {
+        let before = rest[..pos].chars().last();
+        rest = &rest[pos + key.len()..];
+        if matches!(before, Some(c) if c.is_whitespace()) {
+            if let Some(value) = rest.trim_start().strip_prefix('=') {
+                let value = value.trim_start().strip_prefix('"')?;
+                let end = value.find('"')?;
+                return Some(unescape(&value[..end]));
+            }
+        }
+    }
+    None
+}
+
+// A ROM listed in a DAT
+struct DatRom {
+    file: String,
+    crc: Option<u32>,
+    sha1: Option<String>,
+    title: String,
+}
+
+fn parse(xml: &str) -> Vec<DatRom> {
+    let mut roms = Vec::new();
+    let mut title = String::new();
+    for piece in xml.split('<').skip(1) {
+        let (tag, text) = piece.split_once('>').unwrap_or((piece, ""));
+        let name = tag.split_whitespace().next().unwrap_or("");
+        match name {
+            "game" | "machine" => title = attribute(tag, "name").unwrap_or_default(),
+            "description" => title = unescape(text.trim()),
+            "rom" => {
+                if let Some(file) = attribute(tag, "name") {
+                    roms.push(DatRom {
+                        file,
+                        crc: attribute(tag, "crc").and_then(|c| u32::from_str_radix(&c, 16).ok()),
+                        sha1: attribute(tag, "sha1").map(|s| s.to_lowercase()),
+                        title: title.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    roms
+}
+
+/// The data DATs list for a ROM, without the iNES header of NES ROMs
+pub(crate) fn rom_data(data: &[u8]) -> &[u8] {
+    if data.starts_with(b"NES\x1a") && data.len() > 16 {
+        &data[16..]
+    } else {
+        data
+    }
+}
+
+impl Dat {
+    pub fn load(paths: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        if paths.is_empty() {
+            return Err("no DAT files configured".into());
+        }
+        let mut by_crc = HashMap::new();
+        let mut by_sha1 = HashMap::new();
+        let mut by_name = HashMap::new();
+        for path in paths {
+            let xml = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+            let roms = parse(&xml);
+            info!("{} ROMs in '{}'", roms.len(), path.display());
+            for rom in roms {
+                if let Some(crc) = rom.crc {
+                    by_crc.insert(crc, rom.title.clone());
+                }
+                if let Some(sha1) = rom.sha1 {
+                    by_sha1.insert(sha1, rom.title.clone());
+                }
+                by_name.insert(rom.file.to_lowercase(), rom.title);
+            }
+        }
+        Ok(Dat {
+            by_crc,
+            by_sha1,
+            by_name,
+        })
+    }
+
+    /// Title of a ROM from its checksums, as from `rom_data`, or its file
+    /// name if neither is listed
+    pub fn title(&self, crc: u32, sha1: Option<&str>, rom: &Path) -> Option<&String> {
+        let by_sum = sha1
+            .and_then(|s| self.by_sha1.get(s))
+            .or_else(|| self.by_crc.get(&crc));
+        by_sum.or_else(|| {
+            let file = rom
+                .file_name()
+                .map(|f| f.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            debug!("No checksum match for '{}', trying file name", file);
+            self.by_name.get(&file)
+        })
+    }
+}
+
+impl DatBackend {
+    pub fn load(paths: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        Ok(DatBackend {
+            dat: Dat::load(paths)?,
+        })
+    }
+}
+
+impl Backend for DatBackend {
+    fn name(&self) -> &'static str {
+        "DAT"
+    }
+
+    fn lookup(&mut self, rom: &Path) -> Result<Option<GameMetadata>, Box<dyn Error>> {
+        let data = std::fs::read(rom)?;
+        let title = self.dat.title(crc32(rom_data(&data)), None, rom);
+        Ok(title.map(|t| GameMetadata { name: t.clone() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn logiqx() {
+        let xml = r#"<?xml version="1.0"?>
+<datafile>
+    <game name="Tetris (World) (Rev 1)">
+        <description>Tetris (World) (Rev 1)</description>
+        <rom name="Tetris (World) (Rev 1).gb" size="32768" crc="46df91ad" sha1="74591CC9501AF93873F9A5D3EB12DA12C0723BBC"/>
+    </game>
+    <game name="Dr. Mario &amp; Friends">
+        <rom name="Dr. Mario.gb" size="32768"/>
+    </game>
+</datafile>"#;
+        let roms = parse(xml);
+        assert_eq!(roms.len(), 2);
+        assert_eq!(roms[0].crc, Some(0x46df_91ad));
+        assert_eq!(
+            roms[0].sha1.as_deref(),
+            Some("74591cc9501af93873f9a5d3eb12da12c0723bbc")
+        );
+        assert_eq!(roms[0].title, "Tetris (World) (Rev 1)");
+        assert_eq!(roms[1].file, "Dr. Mario.gb");
+        assert_eq!(roms[1].crc, None);
+        assert_eq!(roms[1].title, "Dr. Mario & Friends");
+    }
+}
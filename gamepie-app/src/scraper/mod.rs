@@ -0,0 +1,150 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use gamepie_core::config::{Config, ScraperConfig};
+use gamepie_core::dirs;
+use gamepie_core::metadata;
+
+pub(crate) mod dat;
+mod screenscraper;
+mod thegamesdb;
+mod web;
+
+use dat::DatBackend;
+use screenscraper::ScreenScraper;
+use thegamesdb::TheGamesDb;
+
+// Progress is written to the queue file after this many games, so a large
+// library can be scraped over several runs.
+const QUEUE_SAVE_INTERVAL: usize = 10;
+
+/// Metadata written alongside a ROM, and shown by the menu
+pub(crate) struct GameMetadata {
+    pub name: String,
+}
+
+/// Source of game metadata
+pub(crate) trait Backend {
+    fn name(&self) -> &'static str;
+
+    /// Shortest time between lookups, for services with rate limits
+    fn min_interval(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+
+    /// Look up a ROM, `None` if the backend doesn't know the game. Errors
+    /// stop the scrape, leaving the rest of the queue for next time.
+    fn lookup(&mut self, rom: &Path) -> Result<Option<GameMetadata>, Box<dyn Error>>;
+}
+
+fn backend(config: &ScraperConfig) -> Result<Box<dyn Backend>, Box<dyn Error>> {
+    match config.backend.as_str() {
+        "dat" => Ok(Box::new(DatBackend::load(&config.dats)?)),
+        "screenscraper" => Ok(Box::new(ScreenScraper::new(config)?)),
+        "thegamesdb" => Ok(Box::new(TheGamesDb::new(config)?)),
+        b => Err(format!("unknown scraper backend '{}'", b).into()),
+    }
+}
+
+// ROMs without metadata, to be looked up
+struct Queue {
+    path: PathBuf,
+    roms: VecDeque<PathBuf>,
+}
+
+impl Queue {
+    // Continue a previous scrape if there is one, otherwise queue every ROM
+    // that has no metadata.
+    fn load(path: &Path, rom_dirs: &[&Path]) -> Self {
+        let roms: VecDeque<PathBuf> = match std::fs::read_to_string(path) {
+            Ok(contents) if !contents.trim().is_empty() => {
+                info!("Resuming scrape from '{}'", path.display());
+                contents.lines().map(PathBuf::from).collect()
+            }
+            _ => rom_dirs
+                .iter()
+                .flat_map(|d| dirs::game_files(d).into_iter().map(|f| d.join(f)))
+                .filter(|p| !metadata::path(p).exists())
+                .collect(),
+        };
+        Queue {
+            path: path.to_path_buf(),
+            roms,
+        }
+    }
+
+    fn save(&self) {
+        let result = if self.roms.is_empty() {
+            match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        } else {
+            let contents: Vec<_> = self.roms.iter().map(|p| p.to_string_lossy()).collect();
+            std::fs::write(&self.path, contents.join("\n"))
+        };
+        if let Err(e) = result {
+            warn!(
+                "Failed to save scrape queue '{}': {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn write_metadata(rom: &Path, meta: &GameMetadata) -> std::io::Result<()> {
+    let mut table = toml::value::Table::new();
+    table.insert(String::from("name"), toml::Value::String(meta.name.clone()));
+    std::fs::write(metadata::path(rom), toml::Value::Table(table).to_string())
+}
+
+/// Look up metadata for every game in the ROM directories that doesn't have
+/// any, writing it next to the game for the menu.
+pub fn scrape(root_dir: &str, config_file: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(root_dir, config_file);
+    let mut backend = backend(&config.scraper)?;
+    let interval = config.scraper.interval.max(backend.min_interval());
+    let mut queue = Queue::load(&config.dirs.scrape_queue_file(), &config.dirs.rom_dirs());
+    info!(
+        "Scraping {} games with {} backend",
+        queue.roms.len(),
+        backend.name()
+    );
+
+    let mut found = 0;
+    let mut done = 0;
+    let mut last: Option<Instant> = None;
+    while let Some(rom) = queue.roms.front().cloned() {
+        if let Some(wait) = last.and_then(|l| interval.checked_sub(l.elapsed())) {
+            std::thread::sleep(wait);
+        }
+        last = Some(Instant::now());
+
+        match backend.lookup(&rom) {
+            Ok(Some(meta)) => {
+                info!("{} -> {}", rom.display(), meta.name);
+                write_metadata(&rom, &meta)?;
+                found += 1;
+            }
+            Ok(None) => info!("{} not found", rom.display()),
+            Err(e) => {
+                warn!("Lookup failed for '{}': {}", rom.display(), e);
+                queue.save();
+                return Err(e);
+            }
+        }
+        queue.roms.pop_front();
+        done += 1;
+        if done % QUEUE_SAVE_INTERVAL == 0 {
+            queue.save();
+        }
+    }
+    queue.save();
+
+    info!("Found metadata for {} of {} games", found, done);
+    Ok(())
+}
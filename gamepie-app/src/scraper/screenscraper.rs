@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+use gamepie_core::checksum::crc32;
+use gamepie_core::config::ScraperConfig;
+
+use super::dat::rom_data;
+use super::web::{encode, get};
+use super::{Backend, GameMetadata};
+use crate::json::{self, JsonValue};
+
+// ScreenScraper matches ROMs by CRC, size and name. Free accounts are
+// allowed about one request a second.
+
+const API: &str = "https://api.screenscraper.fr/api2/jeuInfos.php";
+const MIN_INTERVAL: Duration = Duration::from_millis(1200);
+// Regions of the names to prefer, ScreenScraper's own name last
+const REGIONS: [&str; 5] = ["wor", "us", "eu", "jp", "ss"];
+
+/// Online backend using ScreenScraper
+pub(crate) struct ScreenScraper {
+    credentials: String,
+}
+
+// Name from a `jeuInfos` response
+fn title(response: &JsonValue) -> Option<String> {
+    let names = response
+        .get("response")?
+        .get("jeu")?
+        .get("noms")?
+        .as_array()?;
+    let region = |r: &str| {
+        names
+            .iter()
+            .find(|n| n.get("region").and_then(|v| v.as_str()) == Some(r))
+    };
+    REGIONS
+        .iter()
+        .find_map(|r| region(r))
+        .or_else(|| names.first())
+        .and_then(|n| n.get("text"))
+        .and_then(|t| t.as_str())
+        .map(|t| t.to_string())
+}
+
+impl ScreenScraper {
+    pub fn new(config: &ScraperConfig) -> Result<Self, Box<dyn Error>> {
+        let (Some(id), Some(password)) = (&config.dev_id, &config.dev_password) else {
+            return Err("screenscraper needs dev_id and dev_password".into());
+        };
+        let mut credentials = format!(
+            "devid={}&devpassword={}&softname=gamepie",
+            encode(id),
+            encode(password)
+        );
+        if let (Some(user), Some(password)) = (&config.user, &config.password) {
+            credentials += &format!("&ssid={}&sspassword={}", encode(user), encode(password));
+        }
+        Ok(ScreenScraper { credentials })
+    }
+}
+
+impl Backend for ScreenScraper {
+    fn name(&self) -> &'static str {
+        "ScreenScraper"
+    }
+
+    fn min_interval(&self) -> Duration {
+        MIN_INTERVAL
+    }
+
+    fn lookup(&mut self, rom: &Path) -> Result<Option<GameMetadata>, Box<dyn Error>> {
+        let data = std::fs::read(rom)?;
+        let file = rom.file_name().unwrap_or_default().to_string_lossy();
+        let url = format!(
+            "{}?{}&output=json&crc={:08X}&romtaille={}&romnom={}",
+            API,
+            self.credentials,
+            crc32(rom_data(&data)),
+            data.len(),
+            encode(&file)
+        );
+        let Some(body) = get(&url)? else {
+            return Ok(None);
+        };
+        let response = json::parse(&body).ok_or("invalid response from ScreenScraper")?;
+        Ok(title(&response).map(|name| GameMetadata { name }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::title;
+    use crate::json::parse;
+
+    #[test]
+    fn names() {
+        let response = parse(
+            r#"{"response": {"jeu": {"id": "1", "noms": [
+                {"region": "ss", "text": "Tetris (SS)"},
+                {"region": "us", "text": "Tetris"},
+                {"region": "jp", "text": "Tetris (JP)"}
+            ]}}}"#,
+        )
+        .unwrap();
+        assert_eq!(title(&response).as_deref(), Some("Tetris"));
+        let other = parse(r#"{"response": {"jeu": {"noms": [{"region": "fr", "text": "X"}]}}}"#);
+        assert_eq!(title(&other.unwrap()).as_deref(), Some("X"));
+        assert_eq!(title(&parse(r#"{"response": {}}"#).unwrap()), None);
+    }
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+use gamepie_core::config::ScraperConfig;
+
+use super::web::{encode, get};
+use super::{Backend, GameMetadata};
+use crate::json::{self, JsonValue};
+
+// TheGamesDB only searches by name, so ROMs are looked up by their file
+// name without the tags No-Intro adds, e.g. "(World) (Rev 1)".
+
+const API: &str = "https://api.thegamesdb.net/v1/Games/ByGameName";
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Online backend using TheGamesDB
+pub(crate) struct TheGamesDb {
+    api_key: String,
+}
+
+// Name to search for, the file name without its extension and tags
+fn search_name(rom: &Path) -> String {
+    let stem = rom.file_stem().unwrap_or_default().to_string_lossy();
+    let end = stem.find(['(', '[']).unwrap_or(stem.len());
+    stem[..end].trim().to_string()
+}
+
+// Title from a `ByGameName` response, an exact match if there is one
+fn title(response: &JsonValue, name: &str) -> Option<String> {
+    let games = response.get("data")?.get("games")?.as_array()?;
+    let titles: Vec<&str> = games
+        .iter()
+        .filter_map(|g| g.get("game_title").and_then(|t| t.as_str()))
+        .collect();
+    titles
+        .iter()
+        .find(|t| t.eq_ignore_ascii_case(name))
+        .or_else(|| titles.first())
+        .map(|t| t.to_string())
+}
+
+impl TheGamesDb {
+    pub fn new(config: &ScraperConfig) -> Result<Self, Box<dyn Error>> {
+        let api_key = config
+            .api_key
+            .clone()
+            .ok_or("thegamesdb needs an api_key")?;
+        Ok(TheGamesDb { api_key })
+    }
+}
+
+impl Backend for TheGamesDb {
+    fn name(&self) -> &'static str {
+        "TheGamesDB"
+    }
+
+    fn min_interval(&self) -> Duration {
+        MIN_INTERVAL
+    }
+
+    fn lookup(&mut self, rom: &Path) -> Result<Option<GameMetadata>, Box<dyn Error>> {
+        let name = search_name(rom);
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let url = format!(
+            "{}?apikey={}&name={}",
+            API,
+            encode(&self.api_key),
+            encode(&name)
+        );
+        let Some(body) = get(&url)? else {
+            return Ok(None);
+        };
+        let response = json::parse(&body).ok_or("invalid response from TheGamesDB")?;
+        Ok(title(&response, &name).map(|name| GameMetadata { name }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_name, title};
+    use crate::json::parse;
+    use std::path::Path;
+
+    #[test]
+    fn names() {
+        let rom = Path::new("roms/Tetris (World) (Rev 1).gb");
+        assert_eq!(search_name(rom), "Tetris");
+        assert_eq!(search_name(Path::new("Dr. Mario [!].gb")), "Dr. Mario");
+
+        let response = parse(
+            r#"{"code": 200, "data": {"count": 2, "games": [
+                {"id": 1, "game_title": "Tetris Plus"},
+                {"id": 2, "game_title": "Tetris"}
+            ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(title(&response, "tetris").as_deref(), Some("Tetris"));
+        assert_eq!(title(&response, "Tet").as_deref(), Some("Tetris Plus"));
+        let none = parse(r#"{"code": 200, "data": {"count": 0, "games": []}}"#).unwrap();
+        assert_eq!(title(&none, "Tetris"), None);
+    }
+}
@@ -0,0 +1,56 @@
+use log::trace;
+use std::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Online backends fetch over HTTPS with curl, which is on Raspberry Pi OS,
+// rather than building in a TLS stack. The URL is given on stdin so the
+// credentials in it aren't in the process list.
+
+const TIMEOUT_SECS: u32 = 30;
+
+/// Percent-encode a query parameter
+pub(crate) fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Fetch a page, `None` if the server doesn't have it (404). Other failures
+/// are errors, so the scrape stops and can be resumed.
+pub(crate) fn get(url: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--config", "-"])
+        .args(["--max-time", &TIMEOUT_SECS.to_string()])
+        .args(["--write-out", "\n%{http_code}"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        writeln!(
+            stdin,
+            "url = \"{}\"",
+            url.replace('\\', "\\\\").replace('"', "\\\"")
+        )?;
+    }
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("curl failed: {}", err.trim()).into());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let (body, status) = text.rsplit_once('\n').unwrap_or(("", &text));
+    trace!("HTTP {}, {} bytes", status, body.len());
+    match status.trim() {
+        "200" => Ok(Some(body.to_string())),
+        "404" => Ok(None),
+        s => Err(format!("HTTP status {}: {}", s, body.trim()).into()),
+    }
+}
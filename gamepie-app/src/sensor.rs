@@ -0,0 +1,136 @@
+use log::{debug, info, warn};
+use rppal::i2c::I2c;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use gamepie_core::config::SensorConfig;
+
+use crate::proxy::sensor::SENSOR_INPUTS;
+
+// MPU-6050 registers, also used by the MPU-6500 and MPU-9250
+const PWR_MGMT_1: u8 = 0x6b;
+const GYRO_CONFIG: u8 = 0x1b;
+const ACCEL_CONFIG: u8 = 0x1c;
+// Accelerometer, temperature then gyroscope, each a big endian i16
+const ACCEL_XOUT_H: u8 = 0x3b;
+const READING_LEN: usize = 14;
+const TEMP_LEN: usize = 2;
+const SLEEP: u8 = 0x40;
+
+// Full scale ranges of +-2 g and +-250 degrees per second, given to cores
+// in m/s^2 and rad/s
+const ACCEL_SCALE: f32 = 9.80665 / 16384.0;
+const GYRO_SCALE: f32 = PI / 180.0 / 131.0;
+
+// How often the sensor is read while a core wants it, and checked when it
+// doesn't
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Convert a raw reading to the libretro sensor inputs
+fn convert(raw: &[u8; READING_LEN]) -> [f32; SENSOR_INPUTS] {
+    let mut values = [0.0; SENSOR_INPUTS];
+    for (i, v) in values.iter_mut().enumerate() {
+        // The gyroscope follows the temperature
+        let offset = if i < 3 { i * 2 } else { i * 2 + TEMP_LEN };
+        let raw = f32::from(i16::from_be_bytes([raw[offset], raw[offset + 1]]));
+        *v = raw * if i < 3 { ACCEL_SCALE } else { GYRO_SCALE };
+    }
+    values
+}
+
+struct Imu {
+    i2c: I2c,
+    awake: bool,
+}
+
+impl Imu {
+    fn new(config: &SensorConfig) -> Result<Self, Box<dyn Error>> {
+        let mut i2c = I2c::with_bus(config.bus)?;
+        i2c.set_slave_address(config.address)?;
+        i2c.smbus_write_byte(GYRO_CONFIG, 0)?;
+        i2c.smbus_write_byte(ACCEL_CONFIG, 0)?;
+        i2c.smbus_write_byte(PWR_MGMT_1, SLEEP)?;
+        Ok(Imu { i2c, awake: false })
+    }
+
+    fn set_awake(&mut self, awake: bool) -> Result<(), Box<dyn Error>> {
+        if awake != self.awake {
+            debug!("Motion sensor awake: {}", awake);
+            self.i2c
+                .smbus_write_byte(PWR_MGMT_1, if awake { 0 } else { SLEEP })?;
+            self.awake = awake;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<[f32; SENSOR_INPUTS], Box<dyn Error>> {
+        let mut raw = [0; READING_LEN];
+        self.i2c.block_read(ACCEL_XOUT_H, &mut raw)?;
+        Ok(convert(&raw))
+    }
+}
+
+/// Start the thread that reads the motion sensor for cores, readings are
+/// taken through `proxy::sensor`. The sensor sleeps while no core wants it.
+pub(crate) fn start(config: &SensorConfig, running: Arc<AtomicBool>) {
+    if !config.enabled {
+        debug!("No motion sensor");
+        return;
+    }
+    let mut imu = match Imu::new(config) {
+        Ok(i) => i,
+        Err(e) => {
+            warn!("Motion sensor unavailable: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Motion sensor on I2C bus {} at {:#04x}",
+        config.bus, config.address
+    );
+    crate::proxy::sensor::set_available();
+    std::thread::spawn(move || {
+        // Errors are only logged once until the sensor reads again
+        let mut failed = false;
+        while running.load(Ordering::Acquire) {
+            let wanted = crate::proxy::sensor::wanted();
+            let res = imu.set_awake(wanted).and_then(|_| match wanted {
+                true => imu.read().map(crate::proxy::sensor::update),
+                false => Ok(()),
+            });
+            match res {
+                Ok(_) => failed = false,
+                Err(e) if !failed => {
+                    warn!("Failed to read motion sensor: {}", e);
+                    failed = true;
+                }
+                Err(_) => {}
+            }
+            std::thread::sleep(if wanted { POLL_INTERVAL } else { IDLE_INTERVAL });
+        }
+        if let Err(e) = imu.set_awake(false) {
+            warn!("Failed to sleep motion sensor: {}", e);
+        }
+        debug!("Sensor thread finished");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, ACCEL_SCALE, GYRO_SCALE};
+
+    #[test]
+    fn readings() {
+        // 1 g on Z, temperature, then -1 on gyroscope X
+        let raw = [0, 0, 0, 0, 0x40, 0, 0x12, 0x34, 0xff, 0xff, 0, 0, 0, 0];
+        let values = convert(&raw);
+        assert_eq!(values[2], 16384.0 * ACCEL_SCALE);
+        assert!((values[2] - 9.81).abs() < 0.01);
+        assert_eq!(values[3], -GYRO_SCALE);
+        assert_eq!(values[4], 0.0);
+    }
+}
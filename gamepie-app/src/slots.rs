@@ -0,0 +1,112 @@
+use std::time::SystemTime;
+
+use gamepie_core::TIMED_SAVES;
+
+/// A copy of a game's save RAM that can be restored, the save written when
+/// the game exits or one of the timed saves written while it runs
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SaveSlot {
+    pub name: String,
+    pub path: String,
+    pub modified: SystemTime,
+}
+
+impl SaveSlot {
+    fn find(name: String, path: String) -> Option<Self> {
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        Some(SaveSlot {
+            name,
+            path,
+            modified,
+        })
+    }
+
+    /// Seconds since the slot was saved
+    pub fn age(&self) -> u64 {
+        self.modified.elapsed().unwrap_or_default().as_secs()
+    }
+}
+
+// Timed saves, named for the menu
+fn timed(save: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    TIMED_SAVES
+        .iter()
+        .enumerate()
+        .map(move |(i, suffix)| (format!("Autosave {}", i + 1), format!("{}{}", save, suffix)))
+}
+
+/// Slots of a save that exist, newest first
+pub(crate) fn slots(save: &str) -> Vec<SaveSlot> {
+    let mut slots: Vec<SaveSlot> = std::iter::once((String::from("Save"), String::from(save)))
+        .chain(timed(save))
+        .filter_map(|(name, path)| SaveSlot::find(name, path))
+        .collect();
+    slots.sort_by_key(|s| std::cmp::Reverse(s.modified));
+    slots
+}
+
+/// Newest timed save that is `valid`, to recover from when the save itself
+/// is missing or damaged
+pub(crate) fn newest_timed<F>(save: &str, valid: F) -> Option<SaveSlot>
+where
+    F: Fn(&str) -> bool,
+{
+    timed(save)
+        .filter(|(_, path)| valid(path))
+        .filter_map(|(name, path)| SaveSlot::find(name, path))
+        .max_by_key(|s| s.modified)
+}
+
+/// Alternates between the timed save files, so a save cut off part way
+/// through still leaves the previous one
+pub(crate) struct TimedSaves {
+    save: String,
+    next: usize,
+}
+
+impl TimedSaves {
+    pub fn new(save: &str) -> Self {
+        TimedSaves {
+            save: String::from(save),
+            next: 0,
+        }
+    }
+
+    /// File for the next timed save
+    pub fn next_path(&mut self) -> String {
+        let path = format!("{}{}", self.save, TIMED_SAVES[self.next]);
+        self.next = (self.next + 1) % TIMED_SAVES.len();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{newest_timed, slots, TimedSaves};
+    use gamepie_core::testing::TestDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn slots_newest_first() {
+        let dir = TestDir::new("slots");
+        let save = dir.join("game.sav").to_string_lossy().into_owned();
+
+        let mut timed = TimedSaves::new(&save);
+        let first = timed.next_path();
+        let second = timed.next_path();
+        assert_eq!(first, format!("{}.0", save));
+        assert_eq!(second, format!("{}.1", save));
+        assert_eq!(timed.next_path(), first);
+
+        assert!(slots(&save).is_empty());
+        let now = SystemTime::now();
+        for (path, age) in [(&save, 60), (&first, 120), (&second, 0)] {
+            let file = std::fs::File::create(path).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        let names: Vec<String> = slots(&save).into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["Autosave 2", "Save", "Autosave 1"]);
+        let newest = newest_timed(&save, |p| p != second);
+        assert_eq!(newest.map(|s| s.path), Some(first));
+    }
+}
@@ -0,0 +1,310 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gamepie_core::checksum::crc32;
+use gamepie_core::config::Config;
+use gamepie_core::dirs;
+use gamepie_libretrobind::functions;
+
+use crate::integrity::hash_file;
+use crate::saves::{age, describe, with_suffix, IMPORT_BACKUP};
+
+// State files start with a header saying which core build wrote them, as
+// cores can crash or misbehave restoring a state from another build. States
+// without one (e.g. from RetroArch) are the core's data alone.
+const MAGIC: &[u8; 4] = b"GPST";
+const VERSION: u8 = 1;
+const STATE_EXT: &str = "state";
+
+/// Header written before the core's data in a state file
+#[derive(Debug, PartialEq)]
+pub(crate) struct StateHeader {
+    /// Library name and version the core reports
+    pub core: String,
+    pub version: String,
+    /// Hash of the core library, as kept for the integrity check
+    pub fingerprint: u64,
+    /// CRC32 of the ROM, if the core was given its data
+    pub rom_crc: Option<u32>,
+    /// When the state was saved, in seconds since the Unix epoch
+    pub saved: u64,
+}
+
+impl StateHeader {
+    pub fn new(core: &str, version: &str, fingerprint: u64, rom_crc: Option<u32>) -> Self {
+        let saved = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        StateHeader {
+            core: core.to_string(),
+            version: version.to_string(),
+            fingerprint,
+            rom_crc,
+            saved,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut h = Vec::new();
+        h.extend_from_slice(MAGIC);
+        h.push(VERSION);
+        h.extend_from_slice(&self.fingerprint.to_le_bytes());
+        h.push(u8::from(self.rom_crc.is_some()));
+        h.extend_from_slice(&self.rom_crc.unwrap_or(0).to_le_bytes());
+        h.extend_from_slice(&self.saved.to_le_bytes());
+        for s in [&self.core, &self.version] {
+            let s = &s.as_bytes()[..s.len().min(usize::from(u8::MAX))];
+            h.push(s.len() as u8);
+            h.extend_from_slice(s);
+        }
+        h
+    }
+
+    /// Split a state file into its header and the core's data. States
+    /// without a header are returned whole.
+    pub fn parse(data: &[u8]) -> Result<(Option<StateHeader>, &[u8]), String> {
+        let Some(rest) = data.strip_prefix(MAGIC) else {
+            return Ok((None, data));
+        };
+        let mut at = 0;
+        let mut take = |n: usize| {
+            let field = rest.get(at..at + n).ok_or("state header is truncated");
+            at += n;
+            field
+        };
+        let version = take(1)?[0];
+        if version != VERSION {
+            return Err(format!("unknown state header version {}", version));
+        }
+        let fingerprint = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let has_crc = take(1)?[0] != 0;
+        let crc = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let saved = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let mut text = || -> Result<String, &str> {
+            let len = usize::from(take(1)?[0]);
+            Ok(String::from_utf8_lossy(take(len)?).into_owned())
+        };
+        let core = text()?;
+        let version = text()?;
+        let header = StateHeader {
+            core,
+            version,
+            fingerprint,
+            rom_crc: has_crc.then_some(crc),
+            saved,
+        };
+        Ok((Some(header), &rest[at..]))
+    }
+}
+
+/// State file operations, for managing save states from the command line
+pub enum StateCommand {
+    /// List every state
+    List,
+    /// Show the details of the state for a game
+    Inspect(String),
+    /// Copy the state for a game to a file, `raw` leaves out the header
+    Export(String, PathBuf, bool),
+    /// Replace the state for a game with a file, a state without a header
+    /// is given one for the core library
+    Import(PathBuf, String, Option<PathBuf>),
+}
+
+fn state_path(config: &Config, game: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let state = config.dirs.state_file(Path::new(game));
+    state.ok_or_else(|| format!("'{}' isn't a game", game).into())
+}
+
+fn read_state(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    std::fs::read(path).map_err(|e| format!("can't read state '{}': {}", path.display(), e).into())
+}
+
+// The installed core with a fingerprint, hashing each as the records are
+// kept by the frontend
+fn find_core(config: &Config, fingerprint: u64) -> Option<PathBuf> {
+    let cores = std::fs::read_dir(config.dirs.emulators()).ok()?;
+    cores
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| hash_file(p).is_ok_and(|h| h == fingerprint))
+}
+
+fn list(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut states: Vec<PathBuf> = std::fs::read_dir(config.dirs.states())?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(STATE_EXT))
+        .collect();
+    states.sort();
+    for state in states {
+        let name = state.file_stem().unwrap_or_default().to_string_lossy();
+        let core = match std::fs::read(&state).map(|d| StateHeader::parse(&d).map(|h| h.0)) {
+            Ok(Ok(Some(h))) => format!("{} {}", h.core, h.version),
+            Ok(Ok(None)) => String::from("no header"),
+            _ => String::from("unreadable"),
+        };
+        println!(
+            "{:40} {}, {}",
+            name,
+            describe(&state).unwrap_or_default(),
+            core
+        );
+    }
+    Ok(())
+}
+
+fn inspect(config: &Config, game: &str) -> Result<(), Box<dyn Error>> {
+    let path = state_path(config, game)?;
+    let data = read_state(&path)?;
+    let (header, state) = StateHeader::parse(&data)?;
+    println!("State:    {}", path.display());
+    println!("          {}", describe(&path).unwrap_or_default());
+    println!("CRC32:    {:08x}", crc32(state));
+    let backup = with_suffix(&path, IMPORT_BACKUP);
+    if let Some(d) = describe(&backup) {
+        println!("Backup:   {} ({})", backup.display(), d);
+    }
+    let Some(header) = header else {
+        println!("Core:     unknown, the state has no header");
+        return Ok(());
+    };
+    println!("Core:     {} {}", header.core, header.version);
+    println!("          fingerprint {:016x}", header.fingerprint);
+    match find_core(config, header.fingerprint) {
+        Some(core) => println!("          installed as '{}'", core.display()),
+        None => println!("          not installed, the state won't load"),
+    }
+    let saved = UNIX_EPOCH + Duration::from_secs(header.saved);
+    println!("Saved:    {}", age(saved));
+
+    let file = path.file_stem().unwrap_or_default();
+    let rom = config.dirs.rom_dirs().iter().find_map(|d| {
+        dirs::game_files(d)
+            .into_iter()
+            .find(|f| f.file_name() == Some(file))
+            .map(|f| d.join(f))
+    });
+    match (header.rom_crc, rom) {
+        (Some(crc), Some(rom)) => {
+            let current = crc32(&std::fs::read(&rom)?);
+            let matches = if crc == current { "matches" } else { "differs" };
+            println!(
+                "ROM:      CRC32 {:08x}, '{}' {} ({:08x})",
+                crc,
+                rom.display(),
+                matches,
+                current
+            );
+        }
+        (Some(crc), None) => println!("ROM:      CRC32 {:08x}, not found", crc),
+        (None, Some(rom)) => println!("ROM:      {}, CRC32 not recorded", rom.display()),
+        (None, None) => println!("ROM:      not found"),
+    }
+    Ok(())
+}
+
+fn export(config: &Config, game: &str, dest: &Path, raw: bool) -> Result<(), Box<dyn Error>> {
+    let path = state_path(config, game)?;
+    let data = read_state(&path)?;
+    if raw {
+        // For other frontends, which only want the core's data
+        let (_, state) = StateHeader::parse(&data)?;
+        std::fs::write(dest, state)?;
+    } else {
+        std::fs::write(dest, &data)?;
+    }
+    println!("Exported '{}' to '{}'", path.display(), dest.display());
+    Ok(())
+}
+
+fn import(
+    config: &Config,
+    src: &Path,
+    game: &str,
+    core: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let path = state_path(config, game)?;
+    let mut data = read_state(src)?;
+    match (StateHeader::parse(&data)?, core) {
+        ((Some(header), _), None) => {
+            if find_core(config, header.fingerprint).is_none() {
+                println!(
+                    "Warning: saved by {} {}, which isn't installed",
+                    header.core, header.version
+                );
+            }
+        }
+        ((_, state), Some(core)) => {
+            // Converted from another frontend's state, or moved to a
+            // different build of the core
+            let lib = functions::load_library(core)?;
+            let info = functions::get_system_info(&lib)?;
+            let header = StateHeader::new(
+                &info.library_name,
+                &info.library_version,
+                hash_file(core)?,
+                None,
+            );
+            let mut converted = header.to_bytes();
+            converted.extend_from_slice(state);
+            data = converted;
+            println!(
+                "Marked as saved by {} {}",
+                info.library_name, info.library_version
+            );
+        }
+        ((None, _), None) => {
+            return Err(format!(
+                "'{}' has no header, use --core to give the core it is for",
+                src.display()
+            )
+            .into())
+        }
+    }
+    if path.is_file() {
+        let backup = with_suffix(&path, IMPORT_BACKUP);
+        std::fs::copy(&path, &backup)?;
+        println!("Kept the current state as '{}'", backup.display());
+    }
+    std::fs::create_dir_all(config.dirs.states())?;
+    std::fs::write(&path, &data)?;
+    println!("Imported '{}' to '{}'", src.display(), path.display());
+    Ok(())
+}
+
+/// Run a save state command against the configured states directory
+pub fn states(
+    root_dir: &str,
+    config_file: Option<&str>,
+    command: StateCommand,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(root_dir, config_file);
+    match command {
+        StateCommand::List => list(&config),
+        StateCommand::Inspect(game) => inspect(&config, &game),
+        StateCommand::Export(game, dest, raw) => export(&config, &game, &dest, raw),
+        StateCommand::Import(src, game, core) => import(&config, &src, &game, core.as_deref()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateHeader;
+
+    #[test]
+    fn header() {
+        let header = StateHeader::new("Gambatte", "v0.5.0 abc123", 0x0123_4567_89ab_cdef, Some(7));
+        let mut data = header.to_bytes();
+        data.extend_from_slice(b"core data");
+        let (parsed, state) = StateHeader::parse(&data).unwrap();
+        assert_eq!(parsed, Some(header));
+        assert_eq!(state, b"core data");
+
+        // Other frontends' states are all core data
+        assert_eq!(StateHeader::parse(b"raw").unwrap(), (None, &b"raw"[..]));
+        assert!(StateHeader::parse(&data[..20]).is_err());
+        data[4] = 9;
+        assert!(StateHeader::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use gamepie_core::config::StorageConfig;
+use gamepie_core::dirs::GamepieDirs;
+use gamepie_core::{SAVEDATA_EXT, TIMED_SAVES};
+
+// Free space only changes quickly when something big is written
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// How often the warning is shown again while space stays low
+const WARN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Space available to gamepie on the filesystem holding `path`, in bytes
+pub(crate) fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // Both fit in u64 on every target, but are narrower on some
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// A file that can be deleted to free space
+struct Evictable {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Evictable {
+    fn new(path: PathBuf) -> Option<Self> {
+        let meta = std::fs::metadata(&path).ok()?;
+        Some(Evictable {
+            path,
+            size: meta.len(),
+            modified: meta.modified().ok()?,
+        })
+    }
+}
+
+// Files that aren't needed to play: environment traces, and the older of
+// each game's timed saves. The newer timed save is kept so there is always
+// a backup.
+fn evictable(dirs: &GamepieDirs) -> Vec<Evictable> {
+    let files = |dir: &Path| -> Vec<PathBuf> {
+        match std::fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    let mut found: Vec<Evictable> = files(&dirs.traces())
+        .into_iter()
+        .filter_map(Evictable::new)
+        .collect();
+
+    let mut timed: HashMap<String, Vec<Evictable>> = HashMap::new();
+    for path in files(dirs.save_dir()) {
+        let name = path.to_string_lossy().into_owned();
+        let save = TIMED_SAVES.iter().find_map(|s| name.strip_suffix(s));
+        if let Some(save) = save.filter(|s| s.ends_with(&format!(".{}", SAVEDATA_EXT))) {
+            let save = String::from(save);
+            if let Some(file) = Evictable::new(path) {
+                timed.entry(save).or_default().push(file);
+            }
+        }
+    }
+    for (_, mut saves) in timed {
+        saves.sort_by_key(|s| s.modified);
+        saves.pop();
+        found.extend(saves);
+    }
+    found
+}
+
+// Least recently written files first, until at least `needed` bytes would
+// be freed
+fn choose(mut files: Vec<Evictable>, needed: u64) -> Vec<Evictable> {
+    files.sort_by_key(|f| f.modified);
+    let mut freed = 0;
+    files
+        .into_iter()
+        .take_while(|f| {
+            let more = freed < needed;
+            freed += f.size;
+            more
+        })
+        .collect()
+}
+
+/// Watches free space where saves are written, deleting files that aren't
+/// needed when it runs low so that saving doesn't fail
+pub(crate) struct Storage {
+    config: StorageConfig,
+    last_check: Option<Instant>,
+    last_warning: Option<Instant>,
+}
+
+impl Storage {
+    pub fn new(config: &StorageConfig) -> Self {
+        Storage {
+            config: config.clone(),
+            last_check: None,
+            last_warning: None,
+        }
+    }
+
+    /// Check free space if due, freeing some if low. Returns a message to
+    /// show if space is still low.
+    pub fn update(&mut self, dirs: &GamepieDirs) -> Option<String> {
+        if self
+            .last_check
+            .is_some_and(|t| t.elapsed() < CHECK_INTERVAL)
+        {
+            return None;
+        }
+        self.last_check = Some(Instant::now());
+        let dir = dirs.save_dir();
+        let mut free = free_space(dir)?;
+        if free >= self.config.low {
+            self.last_warning = None;
+            return None;
+        }
+        warn!(
+            "Low storage: {} kB free on '{}'",
+            free / 1024,
+            dir.display()
+        );
+
+        if self.config.evict {
+            for file in choose(evictable(dirs), self.config.low - free) {
+                match std::fs::remove_file(&file.path) {
+                    Ok(_) => info!("Deleted '{}' to free space", file.path.display()),
+                    Err(e) => warn!("Can't delete '{}': {}", file.path.display(), e),
+                }
+            }
+            free = free_space(dir)?;
+            debug!("{} kB free after deleting", free / 1024);
+            if free >= self.config.low {
+                self.last_warning = None;
+                return None;
+            }
+        }
+
+        if self
+            .last_warning
+            .is_some_and(|t| t.elapsed() < WARN_INTERVAL)
+        {
+            return None;
+        }
+        self.last_warning = Some(Instant::now());
+        Some(format!("Storage low, {} MB free", free / (1024 * 1024)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose, Evictable};
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn oldest_first() {
+        let files = || {
+            [("new", 1), ("old", 100), ("mid", 50)]
+                .into_iter()
+                .map(|(name, age)| Evictable {
+                    path: PathBuf::from(name),
+                    size: 10,
+                    modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1000 - age),
+                })
+                .collect()
+        };
+        let chosen = |needed| -> Vec<PathBuf> {
+            choose(files(), needed)
+                .into_iter()
+                .map(|f| f.path)
+                .collect()
+        };
+        assert!(chosen(0).is_empty());
+        assert_eq!(chosen(15), ["old", "mid"].map(PathBuf::from));
+        assert_eq!(chosen(100).len(), 3);
+    }
+}
@@ -0,0 +1,107 @@
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+use gamepie_core::config::ThermalConfig;
+
+const TEMPERATURE_PATH: &str = "/sys/class/thermal/thermal_zone0/temp";
+// Temperature changes slowly, so there is no need to read it every frame
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// SoC temperature in degrees C
+pub(crate) fn read_temperature() -> Option<f32> {
+    let temp = std::fs::read_to_string(TEMPERATURE_PATH).ok()?;
+    temp.trim().parse::<f32>().ok().map(|t| t / 1000.0)
+}
+
+// Whether to skip frames at this temperature, between the two thresholds
+// the current setting is kept so it doesn't flip back and forth.
+fn should_skip(config: &ThermalConfig, skipping: bool, temp: f32) -> bool {
+    if temp >= config.frameskip_temp {
+        true
+    } else if temp <= config.resume_temp {
+        false
+    } else {
+        skipping
+    }
+}
+
+/// Skips frames while the SoC is hot, reducing the load before the kernel
+/// starts throttling the CPU part way through a game.
+pub(crate) struct Thermal {
+    config: ThermalConfig,
+    last_check: Instant,
+    skipping: bool,
+}
+
+impl Thermal {
+    pub fn new(config: &ThermalConfig) -> Self {
+        Thermal {
+            config: config.clone(),
+            last_check: Instant::now(),
+            skipping: false,
+        }
+    }
+
+    /// Fast-forward speed allowed, lowered from `max` while hot
+    pub fn fast_forward_cap(&self, max: u32) -> u32 {
+        if self.skipping {
+            max.min(self.config.fast_forward_max)
+        } else {
+            max
+        }
+    }
+
+    /// Frames to skip after each frame drawn, zero if not skipping
+    pub fn frameskip(&self) -> u32 {
+        if self.skipping {
+            self.config.frameskip
+        } else {
+            0
+        }
+    }
+
+    /// Check the temperature if due, returns the new setting if frame
+    /// skipping has been turned on or off.
+    pub fn update(&mut self) -> Option<bool> {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let temp = read_temperature()?;
+        let skip = should_skip(&self.config, self.skipping, temp);
+        if skip == self.skipping {
+            return None;
+        }
+        self.skipping = skip;
+        if skip {
+            warn!("SoC at {:.1}C, skipping frames", temp);
+        } else {
+            info!("SoC at {:.1}C, no longer skipping frames", temp);
+        }
+        Some(skip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{should_skip, Thermal};
+    use gamepie_core::config::ThermalConfig;
+
+    #[test]
+    fn hysteresis() {
+        let config = ThermalConfig::default();
+        assert!(!should_skip(&config, false, 72.0));
+        assert!(should_skip(&config, false, 76.0));
+        assert!(should_skip(&config, true, 72.0));
+        assert!(!should_skip(&config, true, 69.5));
+    }
+
+    #[test]
+    fn fast_forward_cap() {
+        let mut thermal = Thermal::new(&ThermalConfig::default());
+        assert_eq!(thermal.fast_forward_cap(4), 4);
+        thermal.skipping = true;
+        assert_eq!(thermal.fast_forward_cap(4), 2);
+        assert_eq!(thermal.fast_forward_cap(1), 1);
+    }
+}
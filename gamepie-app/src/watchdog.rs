@@ -0,0 +1,179 @@
+use log::{debug, error, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use gamepie_core::config::WatchdogConfig;
+
+use crate::core::Core;
+
+// How often the watchdog thread checks for progress
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Exit status when a core hangs, the service restarts gamepie to the menu
+const HUNG_EXIT_CODE: i32 = 3;
+// Time given to the GPIO thread to turn off the backlight before exiting
+const GPIO_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Cores that have hung, stored as a TOML file. Cores in the list aren't
+/// loaded until they are replaced with a different build.
+pub(crate) struct Quarantine {
+    path: PathBuf,
+    cores: toml::value::Table,
+}
+
+impl Quarantine {
+    pub fn load(path: &Path) -> Self {
+        let cores = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|c| c.parse::<toml::Value>().ok())
+            .and_then(|v| match v {
+                toml::Value::Table(t) => Some(t),
+                _ => None,
+            })
+            .unwrap_or_default();
+        Quarantine {
+            path: path.to_path_buf(),
+            cores,
+        }
+    }
+
+    pub fn contains(&self, core: &Path) -> bool {
+        self.cores.contains_key(&*core.to_string_lossy())
+    }
+
+    /// Add a core that hung while running a game
+    pub fn add(&mut self, core: &Path, game: &Path) {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut t = toml::value::Table::new();
+        t.insert(
+            String::from("game"),
+            toml::Value::String(game.to_string_lossy().into_owned()),
+        );
+        t.insert(String::from("time"), toml::Value::Integer(time as i64));
+        self.cores
+            .insert(core.to_string_lossy().into_owned(), toml::Value::Table(t));
+        self.save();
+    }
+
+    /// Let a core be loaded again, e.g. once it has been replaced
+    pub fn release(&mut self, core: &Path) {
+        if self.cores.remove(&*core.to_string_lossy()).is_some() {
+            debug!("Core '{}' released from quarantine", core.display());
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let contents = toml::Value::Table(self.cores.clone()).to_string();
+        if let Err(e) = std::fs::write(&self.path, contents) {
+            warn!("Failed to save quarantine '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+// Core being watched, and when the main loop last came round
+struct Running {
+    core: PathBuf,
+    game: PathBuf,
+    last: Instant,
+    // Writes the game's save, as the core can't be dropped once hung
+    save: Option<Box<dyn FnOnce() + Send>>,
+}
+
+/// Watches the main loop while a game is running. If the core stops
+/// returning for longer than the timeout the main thread can't be recovered,
+/// so the core is quarantined, the save written, the other threads stopped
+/// and gamepie exits to be restarted at the menu by its service.
+pub(crate) struct Watchdog {
+    running: Arc<Mutex<Option<Running>>>,
+}
+
+impl Watchdog {
+    /// `gpio_stopped` is disconnected once the GPIO thread has finished and
+    /// turned off the backlight, after `threads_running` is cleared
+    pub fn new(
+        config: &WatchdogConfig,
+        quarantine: &Path,
+        threads_running: Arc<AtomicBool>,
+        gpio_stopped: Option<mpsc::Receiver<()>>,
+    ) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let running: Arc<Mutex<Option<Running>>> = Arc::new(Mutex::new(None));
+        let watched = running.clone();
+        let timeout = config.timeout;
+        let quarantine = quarantine.to_path_buf();
+        let spawned = std::thread::Builder::new()
+            .name(String::from("watchdog"))
+            .spawn(move || loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                let mut guard = match watched.lock() {
+                    Ok(g) => g,
+                    Err(e) => e.into_inner(),
+                };
+                if let Some(r) = guard.as_mut().filter(|r| r.last.elapsed() > timeout) {
+                    error!(
+                        "Core '{}' hung for {:?} running '{}', quarantining it",
+                        r.core.display(),
+                        timeout,
+                        r.game.display()
+                    );
+                    Quarantine::load(&quarantine).add(&r.core, &r.game);
+                    crate::crash::write_report(&format!("core hung for {:?}", timeout));
+                    if let Some(save) = r.save.take() {
+                        save();
+                    }
+                    threads_running.store(false, Ordering::Release);
+                    if let Some(stopped) = &gpio_stopped {
+                        if let Err(mpsc::RecvTimeoutError::Timeout) =
+                            stopped.recv_timeout(GPIO_STOP_TIMEOUT)
+                        {
+                            warn!("GPIO thread didn't stop");
+                        }
+                    }
+                    std::process::exit(HUNG_EXIT_CODE);
+                }
+            });
+        match spawned {
+            Ok(_) => Some(Watchdog { running }),
+            Err(e) => {
+                warn!("Failed to start watchdog: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Note progress running a core, starting to watch it if it is new
+    pub fn feed(&self, core: &Core) {
+        let mut guard = match self.running.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        match guard.as_mut() {
+            Some(r) if r.core == core.core_path() && r.game == core.game_path() => {
+                r.last = Instant::now()
+            }
+            _ => {
+                *guard = Some(Running {
+                    core: core.core_path().to_path_buf(),
+                    game: core.game_path().to_path_buf(),
+                    last: Instant::now(),
+                    save: Some(core.hang_save()),
+                })
+            }
+        }
+    }
+
+    /// Stop watching, once no core is running
+    pub fn disarm(&self) {
+        let mut guard = match self.running.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        *guard = None;
+    }
+}
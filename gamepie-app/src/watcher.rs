@@ -0,0 +1,176 @@
+use log::{debug, error, info, warn};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use gamepie_core::dirs;
+
+use crate::pipe::PipeCommand;
+
+// The directories must be quiet for this long before the games are found
+// again, so copying many ROMs only rescans once
+const SETTLE: Duration = Duration::from_secs(2);
+const POLL_TIMEOUT_MS: i32 = 200;
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_CLOSE_WRITE;
+// Size of inotify_event before the name
+const EVENT_HEADER: usize = 16;
+
+// Names of the files changed in a buffer of inotify events, without hidden
+// files such as partial uploads
+fn changed(buf: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut at = 0;
+    while at + EVENT_HEADER <= buf.len() {
+        let len = u32::from_ne_bytes([buf[at + 12], buf[at + 13], buf[at + 14], buf[at + 15]]);
+        let start = at + EVENT_HEADER;
+        let end = (start + len as usize).min(buf.len());
+        // The name is padded with NULs
+        let name = buf[start..end].split(|b| *b == 0).next().unwrap_or(&[]);
+        let name = String::from_utf8_lossy(name);
+        if !name.is_empty() && !name.starts_with('.') {
+            names.push(name.into_owned());
+        }
+        at = end;
+    }
+    names
+}
+
+struct Inotify(libc::c_int);
+
+impl Inotify {
+    // Watch the ROM directories and the folders within them. Watching a
+    // directory again keeps the same watch, so this is repeated to pick up
+    // new folders.
+    fn watch_all(&self, rom_dirs: &[PathBuf]) {
+        let folders = rom_dirs
+            .iter()
+            .flat_map(|d| dirs::rom_folders(d).into_iter().map(|f| d.join(f)));
+        for dir in rom_dirs.iter().cloned().chain(folders) {
+            let added = CString::new(dir.as_os_str().as_bytes()).map(|path| {
+                // SAFETY: the path is a valid C string
+                unsafe { libc::inotify_add_watch(self.0, path.as_ptr(), WATCH_MASK) }
+            });
+            if !matches!(added, Ok(wd) if wd >= 0) {
+                let e = std::io::Error::last_os_error();
+                warn!("Can't watch '{}' for new ROMs: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor is owned and not used after this
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn watch(
+    inotify: Inotify,
+    rom_dirs: Vec<PathBuf>,
+    commands: mpsc::Sender<PipeCommand>,
+    running: Arc<AtomicBool>,
+) {
+    inotify.watch_all(&rom_dirs);
+    let mut buf = vec![0u8; 4096];
+    let mut pending: Option<Instant> = None;
+    while running.load(Ordering::Acquire) {
+        let mut poll = libc::pollfd {
+            fd: inotify.0,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: a single valid pollfd
+        let ready = unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT_MS) };
+        if ready > 0 {
+            // SAFETY: reads at most the length of the buffer
+            let read = unsafe { libc::read(inotify.0, buf.as_mut_ptr().cast(), buf.len()) };
+            if read > 0 {
+                let names = changed(&buf[..read as usize]);
+                if !names.is_empty() {
+                    debug!("ROMs changed: {}", names.join(", "));
+                    pending = Some(Instant::now());
+                }
+            }
+        }
+        if pending.is_some_and(|p| p.elapsed() >= SETTLE) {
+            pending = None;
+            info!("ROM directories changed, finding games again");
+            inotify.watch_all(&rom_dirs);
+            if commands.send(PipeCommand::Rescan).is_err() {
+                break;
+            }
+        }
+    }
+    debug!("ROM watcher finished");
+}
+
+/// Finds the games again when files in the ROM directories, or the folders
+/// within them, are added, removed or written
+pub(crate) struct RomWatcher {
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RomWatcher {
+    pub fn start(
+        rom_dirs: &[&Path],
+        commands: mpsc::Sender<PipeCommand>,
+        running: Arc<AtomicBool>,
+    ) -> std::io::Result<Self> {
+        // SAFETY: no pointers are passed
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let inotify = Inotify(fd);
+        let rom_dirs = rom_dirs.iter().map(|d| d.to_path_buf()).collect();
+        let thread = std::thread::Builder::new()
+            .name(String::from("rom-watcher"))
+            .spawn(move || watch(inotify, rom_dirs, commands, running))?;
+        Ok(RomWatcher {
+            thread: Some(thread),
+        })
+    }
+
+    /// Wait for the thread to finish, `running` must have been cleared
+    pub fn join(&mut self) {
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("ROM watcher panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::changed;
+
+    fn event(name: &str) -> Vec<u8> {
+        let len = (name.len() + 1).next_multiple_of(16);
+        let mut e = vec![0; 12];
+        e.extend_from_slice(&(len as u32).to_ne_bytes());
+        e.extend_from_slice(name.as_bytes());
+        e.resize(16 + len, 0);
+        e
+    }
+
+    #[test]
+    fn events() {
+        let mut buf = event("Tetris.gb");
+        buf.extend(event(".Zelda.gb.part"));
+        buf.extend(event("Super Mario Land (World) (Rev 1).gb"));
+        assert_eq!(
+            changed(&buf),
+            vec!["Tetris.gb", "Super Mario Land (World) (Rev 1).gb"]
+        );
+    }
+}
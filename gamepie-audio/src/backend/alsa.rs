@@ -0,0 +1,171 @@
+use log::{debug, warn};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use std::time::Duration;
+
+use super::{Backend, Output};
+
+// Buffer length asked for, ALSA picks the nearest it can do
+const ALSA_LATENCY_US: c_uint = 60_000;
+
+const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+#[cfg(target_endian = "little")]
+const SND_PCM_FORMAT_S16: c_int = 2;
+#[cfg(target_endian = "big")]
+const SND_PCM_FORMAT_S16: c_int = 3;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+const CHANNELS: usize = 2;
+
+#[repr(C)]
+struct SndPcm {
+    _private: [u8; 0],
+}
+
+#[link(name = "asound")]
+extern "C" {
+    fn snd_pcm_open(
+        pcm: *mut *mut SndPcm,
+        name: *const c_char,
+        stream: c_int,
+        mode: c_int,
+    ) -> c_int;
+    fn snd_pcm_set_params(
+        pcm: *mut SndPcm,
+        format: c_int,
+        access: c_int,
+        channels: c_uint,
+        rate: c_uint,
+        soft_resample: c_int,
+        latency: c_uint,
+    ) -> c_int;
+    fn snd_pcm_writei(pcm: *mut SndPcm, buffer: *const c_void, frames: c_ulong) -> c_long;
+    fn snd_pcm_recover(pcm: *mut SndPcm, err: c_int, silent: c_int) -> c_int;
+    fn snd_pcm_delay(pcm: *mut SndPcm, delay: *mut c_long) -> c_int;
+    fn snd_pcm_drop(pcm: *mut SndPcm) -> c_int;
+    fn snd_pcm_close(pcm: *mut SndPcm) -> c_int;
+    fn snd_strerror(err: c_int) -> *const c_char;
+}
+
+fn error(err: c_int) -> String {
+    // Static strings owned by ALSA
+    unsafe { CStr::from_ptr(snd_strerror(err)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Plays straight to an ALSA device, without SDL's extra buffering
+pub(crate) struct AlsaBackend {
+    device: String,
+}
+
+impl AlsaBackend {
+    pub fn new(device: &str) -> Self {
+        AlsaBackend {
+            device: String::from(device),
+        }
+    }
+}
+
+impl Backend for AlsaBackend {
+    fn name(&self) -> &'static str {
+        "ALSA"
+    }
+
+    fn open(&mut self, freq: i32, min_latency: Duration) -> Result<Box<dyn Output>, String> {
+        let latency = ALSA_LATENCY_US.max(min_latency.as_micros() as c_uint);
+        let name = CString::new(self.device.as_str()).map_err(|e| e.to_string())?;
+        let mut pcm = std::ptr::null_mut();
+        let err = unsafe { snd_pcm_open(&mut pcm, name.as_ptr(), SND_PCM_STREAM_PLAYBACK, 0) };
+        if err < 0 {
+            return Err(format!("can't open '{}': {}", self.device, error(err)));
+        }
+        // Closed on drop from here on
+        let output = AlsaOutput {
+            pcm,
+            buffer_frames: (u64::from(latency) * freq as u64 / 1_000_000).max(1) as c_long,
+        };
+        let err = unsafe {
+            snd_pcm_set_params(
+                output.pcm,
+                SND_PCM_FORMAT_S16,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                CHANNELS as c_uint,
+                freq as c_uint,
+                1,
+                latency,
+            )
+        };
+        if err < 0 {
+            return Err(format!("can't set up '{}': {}", self.device, error(err)));
+        }
+        debug!("Opened ALSA device '{}'", self.device);
+        Ok(Box::new(output))
+    }
+}
+
+struct AlsaOutput {
+    pcm: *mut SndPcm,
+    // Size of the buffer asked for, ALSA's may differ a little
+    buffer_frames: c_long,
+}
+
+impl Output for AlsaOutput {
+    // Blocks while the device's buffer is full, which paces the audio thread
+    fn queue(&mut self, samples: &[i16]) -> Result<(), String> {
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let frames = (rest.len() / CHANNELS) as c_ulong;
+            let written =
+                unsafe { snd_pcm_writei(self.pcm, rest.as_ptr() as *const c_void, frames) };
+            if written < 0 {
+                // Recovers from underruns, which happen if the game stalls
+                let err = unsafe { snd_pcm_recover(self.pcm, written as c_int, 1) };
+                if err < 0 {
+                    return Err(error(err));
+                }
+                warn!("ALSA recovered from: {}", error(written as c_int));
+                continue;
+            }
+            rest = &rest[written as usize * CHANNELS..];
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        let mut delay: c_long = 0;
+        let err = unsafe { snd_pcm_delay(self.pcm, &mut delay) };
+        // An error here is usually an underrun
+        err < 0 || delay <= 0
+    }
+
+    fn occupancy(&self) -> u32 {
+        let mut delay: c_long = 0;
+        if unsafe { snd_pcm_delay(self.pcm, &mut delay) } < 0 {
+            return 0;
+        }
+        (delay.clamp(0, self.buffer_frames) * 100 / self.buffer_frames) as u32
+    }
+
+    // Includes the device's own delay, not just ALSA's buffer
+    fn queued(&self) -> u32 {
+        let mut delay: c_long = 0;
+        if unsafe { snd_pcm_delay(self.pcm, &mut delay) } < 0 {
+            return 0;
+        }
+        delay.max(0) as u32
+    }
+
+    fn stop(&mut self) {
+        unsafe {
+            snd_pcm_drop(self.pcm);
+        }
+    }
+}
+
+impl Drop for AlsaOutput {
+    fn drop(&mut self) {
+        unsafe {
+            snd_pcm_close(self.pcm);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use log::warn;
+use std::time::Duration;
+
+use gamepie_core::config::{AudioBackend, AudioConfig};
+
+#[cfg(feature = "alsa")]
+mod alsa;
+#[cfg(feature = "sdl")]
+mod sdl;
+
+#[cfg(not(any(feature = "sdl", feature = "alsa")))]
+compile_error!("gamepie-audio needs the sdl or alsa feature");
+
+/// Somewhere to play audio, which opens outputs at a sample rate
+pub(crate) trait Backend {
+    fn name(&self) -> &'static str;
+
+    /// Open an output for interleaved stereo samples at `freq` Hz, buffering
+    /// at least `min_latency` of audio
+    fn open(&mut self, freq: i32, min_latency: Duration) -> Result<Box<dyn Output>, String>;
+}
+
+/// An open output, stopped when dropped
+pub(crate) trait Output {
+    /// Queue samples to be played
+    fn queue(&mut self, samples: &[i16]) -> Result<(), String>;
+
+    /// Whether everything queued has been played, i.e. the output has run
+    /// dry
+    fn is_empty(&self) -> bool;
+
+    /// Percentage of the output's buffer filled
+    fn occupancy(&self) -> u32;
+
+    /// Frames queued but not yet played, i.e. how long a sample queued now
+    /// waits to be heard
+    fn queued(&self) -> u32;
+
+    /// Stop playing and throw away anything queued
+    fn stop(&mut self);
+}
+
+/// Create the configured backend, or another that was built in if it
+/// wasn't
+pub(crate) fn create(config: &AudioConfig) -> Result<Box<dyn Backend>, String> {
+    match config.backend {
+        #[cfg(feature = "alsa")]
+        AudioBackend::Alsa => return Ok(Box::new(alsa::AlsaBackend::new(&config.device))),
+        #[cfg(feature = "sdl")]
+        AudioBackend::Sdl => return Ok(Box::new(sdl::SdlBackend::new()?)),
+        #[allow(unreachable_patterns)]
+        b => warn!("{:?} audio backend not built in", b),
+    }
+    #[cfg(feature = "sdl")]
+    return Ok(Box::new(sdl::SdlBackend::new()?));
+    #[cfg(not(feature = "sdl"))]
+    return Ok(Box::new(alsa::AlsaBackend::new(&config.device)));
+}
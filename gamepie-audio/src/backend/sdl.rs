@@ -0,0 +1,73 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::{AudioSubsystem, Sdl};
+use std::time::Duration;
+
+use super::{Backend, Output};
+
+// Samples per channel in SDL's buffer
+const SDL_SAMPLES: u16 = 2048;
+// Largest buffer SDL is asked for, in samples per channel
+const SDL_SAMPLES_MAX: u16 = 32768;
+// Bytes per sample for all channels
+const FRAME_BYTES: u32 = 4;
+
+pub(crate) struct SdlBackend {
+    _sdl: Sdl,
+    subsys: AudioSubsystem,
+}
+
+impl SdlBackend {
+    pub fn new() -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let subsys = sdl.audio()?;
+        Ok(SdlBackend { _sdl: sdl, subsys })
+    }
+}
+
+impl Backend for SdlBackend {
+    fn name(&self) -> &'static str {
+        "SDL"
+    }
+
+    fn open(&mut self, freq: i32, min_latency: Duration) -> Result<Box<dyn Output>, String> {
+        // SDL wants a power of two
+        let min_samples = (min_latency.as_secs_f64() * f64::from(freq)).ceil() as u32;
+        let samples = min_samples
+            .next_power_of_two()
+            .clamp(u32::from(SDL_SAMPLES), u32::from(SDL_SAMPLES_MAX)) as u16;
+        let desired = AudioSpecDesired {
+            freq: Some(freq),
+            channels: Some(2),
+            samples: Some(samples),
+        };
+        let queue = self.subsys.open_queue::<i16, _>(None, &desired)?;
+        queue.resume();
+        Ok(Box::new(queue))
+    }
+}
+
+impl Output for AudioQueue<i16> {
+    fn queue(&mut self, samples: &[i16]) -> Result<(), String> {
+        self.queue_audio(samples)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    // The queue can grow past SDL's buffer, so is full at the buffer size
+    fn occupancy(&self) -> u32 {
+        let buffer = u32::from(self.spec().samples) * FRAME_BYTES;
+        self.size().min(buffer) * 100 / buffer.max(1)
+    }
+
+    // SDL's buffer being played from isn't counted, so this is a little low
+    fn queued(&self) -> u32 {
+        self.size() / FRAME_BYTES
+    }
+
+    fn stop(&mut self) {
+        self.pause();
+        self.clear();
+    }
+}
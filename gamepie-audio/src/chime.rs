@@ -0,0 +1,75 @@
+#[cfg(feature = "sdl")]
+use sdl2::audio::{AudioCVT, AudioFormat, AudioSpecWAV};
+use std::path::Path;
+
+/// Rate the chime is played at
+pub(crate) const CHIME_RATE: i32 = 44100;
+
+// Notes of the built in chime, in Hz, and how long each lasts in ms
+const NOTES: [(f32, i32); 2] = [(659.25, 120), (880.0, 250)];
+// Amplitude of the tone as a fraction of full scale, before the volume
+const CHIME_LEVEL: f32 = 0.5;
+
+/// Built in chime, two rising notes that decay, as interleaved stereo
+/// samples at `rate`
+pub(crate) fn tone(rate: i32) -> Vec<i16> {
+    let mut samples = Vec::new();
+    for (freq, ms) in NOTES {
+        let secs = ms as f32 / 1000.0;
+        for i in 0..rate * ms / 1000 {
+            let t = i as f32 / rate as f32;
+            // Short attack to avoid a click, then an exponential decay
+            let attack = (t / 0.005).min(1.0);
+            let decay = (-t * 4.0 / secs).exp();
+            let x = (t * freq * std::f32::consts::TAU).sin() * attack * decay * CHIME_LEVEL;
+            let s = (x * f32::from(i16::MAX)) as i16;
+            samples.push(s);
+            samples.push(s);
+        }
+    }
+    samples
+}
+
+/// Load a WAV file as interleaved stereo samples at `CHIME_RATE`
+#[cfg(feature = "sdl")]
+pub(crate) fn load_wav(path: &Path) -> Result<Vec<i16>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let cvt = AudioCVT::new(
+        wav.format,
+        wav.channels,
+        wav.freq,
+        AudioFormat::s16_sys(),
+        2,
+        CHIME_RATE,
+    )?;
+    let bytes = cvt.convert(wav.buffer().to_vec());
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_ne_bytes([b[0], b[1]]))
+        .collect())
+}
+
+/// WAV files are converted by SDL, which isn't built in
+#[cfg(not(feature = "sdl"))]
+pub(crate) fn load_wav(path: &Path) -> Result<Vec<i16>, String> {
+    Err(format!(
+        "can't load '{}', WAV chimes need SDL",
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tone, CHIME_RATE};
+
+    #[test]
+    fn chime_tone() {
+        let samples = tone(CHIME_RATE);
+        // Stereo, about 370 ms long
+        assert_eq!(samples.len() % 2, 0);
+        assert_eq!(samples.len() / 2, 5292 + 11025);
+        // Fades in and out without clicks
+        assert_eq!(samples[0], 0);
+        assert!(samples.last().unwrap().abs() < 1000);
+    }
+}
@@ -1,37 +1,138 @@
 use embedded_graphics::{pixelcolor::Rgb565, prelude::RgbColor};
 use log::{debug, error, info, warn};
 use std::error::Error;
-use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-use gamepie_core::commands::{AudioCmd, AudioMsg, ScreenMessage, ScreenToast};
+use gamepie_core::commands::{AudioCmd, AudioMsg, AudioStatus, ScreenMessage, ScreenToast};
+use gamepie_core::config::AudioConfig;
 use gamepie_core::error::GamepieError;
 use gamepie_core::problem::Problem;
 
+mod backend;
+mod chime;
+mod record;
+
+use record::WavWriter;
+
 pub struct Audio {
     _handle: JoinHandle<()>,
     sender: mpsc::Sender<AudioMsg>,
     real: bool,
+    shared: Arc<Shared>,
+    status: mpsc::Receiver<AudioStatus>,
+}
+
+// Kept up to date by the audio thread for the frontend to read
+struct Shared {
+    underruns: AtomicU32,
+    // Latest latency of the queue in microseconds, 0 when stopped
+    latency_us: AtomicU32,
+    volume: AtomicU8,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            underruns: AtomicU32::new(0),
+            latency_us: AtomicU32::new(0),
+            volume: AtomicU8::new(VOL_DEFAULT),
+        }
+    }
 }
 
 // GB/NES is 44.1kHz, GBA is 32.768kHz
 
-// Volume is inverse as this is the divisor
-const VOL_DEFAULT: i16 = 4;
-const VOL_MAX: i16 = 0;
-const VOL_MIN: i16 = 15;
+// Volume is 0-100, mapped onto a range of attenuation in dB so each step
+// sounds about the same. The default is close to the old default of -24 dB.
+const VOL_DEFAULT: u8 = 60;
+const VOL_MAX: u8 = 100;
+const VOL_STEP: u8 = 5;
+const VOL_RANGE_DB: f32 = 60.0;
+
+// Samples above this fraction of full scale are softly compressed rather
+// than clipped
+const SOFT_CLIP_KNEE: f32 = 0.9;
 
 const ERROR_REPEAT_TIMEOUT: Duration = Duration::from_secs(4);
 const AUDIO_ERROR_TIME: Duration = Duration::from_secs(1);
 
+// Longest latency a core can ask for, as the libretro API expects
+const MAX_LATENCY_MS: u32 = 512;
+// Queues filled less than this are reported as likely to run dry
+const UNDERRUN_LIKELY_PCT: u32 = 25;
+
+/// Linear gain for a volume (0-100)
+fn gain(volume: u8) -> f32 {
+    if volume == 0 {
+        0.0
+    } else {
+        let volume = f32::from(volume.min(VOL_MAX)) / f32::from(VOL_MAX);
+        10.0_f32.powf((volume - 1.0) * VOL_RANGE_DB / 20.0)
+    }
+}
+
+// Compress samples (in full scale units) above the knee smoothly towards
+// full scale, so loud peaks don't clip harshly
+fn soft_clip(x: f32) -> f32 {
+    let mag = x.abs();
+    if mag <= SOFT_CLIP_KNEE {
+        x
+    } else {
+        let range = 1.0 - SOFT_CLIP_KNEE;
+        let over = (mag - SOFT_CLIP_KNEE) / range;
+        (SOFT_CLIP_KNEE + range * over.tanh()).copysign(x)
+    }
+}
+
+// Applies the volume to samples, with triangular dither so quiet audio
+// fades out as noise rather than distorting as it loses bits
+struct Mixer {
+    gain: f32,
+    // xorshift state for the dither, doesn't need to be good randomness
+    seed: u32,
+}
+
+impl Mixer {
+    fn new(volume: u8) -> Self {
+        Mixer {
+            gain: gain(volume),
+            seed: 0x2545_f491,
+        }
+    }
+
+    fn set_volume(&mut self, volume: u8) {
+        self.gain = gain(volume);
+    }
+
+    // Uniform random value in [0, 1)
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn process(&mut self, sample: i16) -> i16 {
+        if self.gain >= 1.0 {
+            // Unity gain, samples are already exact
+            return sample;
+        }
+        let full_scale = f32::from(i16::MAX);
+        let x = f32::from(sample) * self.gain / full_scale;
+        let dither = (self.random() - self.random()) / full_scale;
+        let y = soft_clip(x + dither) * full_scale;
+        y.round() as i16
+    }
+}
+
 impl Audio {
-    pub fn volume(v: i16) -> f32 {
-        let v: f32 = v.into();
-        let vmax: f32 = VOL_MAX.into();
-        let vmin: f32 = VOL_MIN.into();
-        let new_v = (v - vmax) / (vmin - vmax);
-        1.0 - new_v
+    /// Volume (0-100) as a fraction, for display
+    pub fn volume(v: u8) -> f32 {
+        f32::from(v.min(VOL_MAX)) / f32::from(VOL_MAX)
     }
 
     fn problem() -> Problem {
@@ -63,63 +164,165 @@ impl Audio {
         }
     }
 
+    fn start_recording(path: &Path, freq: i32) -> Option<WavWriter> {
+        WavWriter::create(path, freq)
+            .map_err(|e| warn!("Failed to record to '{}': {}", path.display(), e))
+            .ok()
+    }
+
+    fn stop_recording(recording: Option<WavWriter>) {
+        if let Some(Err(e)) = recording.map(|r| r.finish()) {
+            warn!("Failed to finish recording: {}", e);
+        }
+    }
+
     fn audio_thread(
+        config: AudioConfig,
         rx: mpsc::Receiver<AudioMsg>,
         overlay_tx: mpsc::Sender<ScreenToast>,
         error_tx: mpsc::Sender<Problem>,
+        shared: Arc<Shared>,
+        status_tx: mpsc::Sender<AudioStatus>,
     ) -> Result<(), Box<dyn Error>> {
         let mut last_error = None;
+        // The queue is empty when audio starts, so only count underruns
+        // after the first samples
+        let mut started = false;
 
-        let sdl = sdl2::init()?;
-        let subsys = sdl.audio()?;
+        let mut backend = backend::create(&config)?;
+        info!("Using {} audio", backend.name());
 
-        let mut device: Option<sdl2::audio::AudioQueue<i16>> = None;
+        let mut device: Option<Box<dyn backend::Output>> = None;
+        // Kept open while the boot chime plays, closed when a game starts
+        let mut chime_device: Option<Box<dyn backend::Output>> = None;
         let mut volume = VOL_DEFAULT;
+        let mut mixer = Mixer::new(volume);
+        // Sample rate of the game's output, and how it was asked to be set up
+        let mut freq = None;
+        let mut min_latency = Duration::ZERO;
+        let mut report_status = false;
+        // File to record to once the game starts, and the recording
+        let mut record_path: Option<PathBuf> = None;
+        let mut recording: Option<WavWriter> = None;
 
         while let Ok(msg) = rx.recv() {
             match msg {
                 AudioMsg::Command(cmd) => match cmd {
-                    AudioCmd::Start(freq) => {
-                        if let Some(d) = &device {
-                            d.pause();
-                            d.clear();
+                    AudioCmd::Start(new_freq) => {
+                        if let Some(mut chime) = chime_device.take() {
+                            chime.stop();
+                        }
+                        if let Some(d) = &mut device {
+                            d.stop();
                             Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
                             warn!("Audio started but device already exists");
                         }
-                        info!("Creating audio device: {} Hz", freq);
-                        let new_desired = sdl2::audio::AudioSpecDesired {
-                            freq: Some(freq),
-                            channels: Some(2),
-                            samples: Some(2048),
-                        };
-                        match subsys.open_queue::<i16, _>(None, &new_desired) {
+                        info!("Creating audio device: {} Hz", new_freq);
+                        freq = Some(new_freq);
+                        match backend.open(new_freq, min_latency) {
                             Ok(new_device) => {
-                                info!("Got audio device: {} Hz", new_device.spec().freq);
-                                new_device.resume();
                                 device = Some(new_device);
+                                started = false;
                             }
                             Err(e) => {
                                 Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
                                 error!("Couldn't initialise audio queue: {}", e)
                             }
                         }
+                        if let Some(path) = record_path.take() {
+                            recording = Self::start_recording(&path, new_freq);
+                        }
                     }
                     AudioCmd::Stop => {
-                        match &device {
-                            Some(device) => {
-                                device.pause();
-                                device.clear();
-                            }
+                        match &mut device {
+                            Some(device) => device.stop(),
                             None => {
                                 Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
                                 warn!("Audio stopped but no device present");
                             }
                         }
                         device = None;
+                        freq = None;
+                        shared.latency_us.store(0, Ordering::Relaxed);
+                        Self::stop_recording(recording.take());
+                    }
+                    AudioCmd::StartRecord(path) => {
+                        Self::stop_recording(recording.take());
+                        match freq {
+                            Some(f) => recording = Self::start_recording(&path, f),
+                            None => {
+                                info!("Recording audio when a game starts");
+                                record_path = Some(path);
+                            }
+                        }
+                    }
+                    AudioCmd::StopRecord => {
+                        record_path = None;
+                        match recording.take() {
+                            Some(r) => Self::stop_recording(Some(r)),
+                            None => warn!("Audio isn't being recorded"),
+                        }
+                    }
+                    AudioCmd::ReportStatus(report) => {
+                        debug!("Audio status reporting: {}", report);
+                        report_status = report;
+                    }
+                    AudioCmd::MinLatency(ms) => {
+                        let latency = Duration::from_millis(u64::from(ms.min(MAX_LATENCY_MS)));
+                        if latency == min_latency {
+                            continue;
+                        }
+                        info!("Minimum audio latency set to {:?}", latency);
+                        min_latency = latency;
+                        // Reopen the running device with the new buffer
+                        if let (Some(d), Some(f)) = (&mut device, freq) {
+                            d.stop();
+                            device = None;
+                            match backend.open(f, min_latency) {
+                                Ok(new_device) => {
+                                    device = Some(new_device);
+                                    started = false;
+                                }
+                                Err(e) => {
+                                    Self::send_error_check(
+                                        Self::problem(),
+                                        &mut last_error,
+                                        &error_tx,
+                                    );
+                                    error!("Couldn't reopen audio queue: {}", e)
+                                }
+                            }
+                        }
+                    }
+                    AudioCmd::Chime(path) => {
+                        if device.is_some() {
+                            debug!("Game audio already started, skipping chime");
+                            continue;
+                        }
+                        let samples = match &path {
+                            Some(p) => chime::load_wav(p),
+                            None => Ok(chime::tone(chime::CHIME_RATE)),
+                        };
+                        let played = samples.and_then(|s| {
+                            let mut output = backend.open(chime::CHIME_RATE, Duration::ZERO)?;
+                            let s: Vec<i16> = s.into_iter().map(|d| mixer.process(d)).collect();
+                            output.queue(&s)?;
+                            Ok(output)
+                        });
+                        match played {
+                            Ok(queue) => {
+                                debug!("Playing boot chime");
+                                chime_device = Some(queue);
+                            }
+                            Err(e) => {
+                                Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
+                                error!("Couldn't play boot chime: {}", e);
+                            }
+                        }
                     }
                     AudioCmd::VolumeDown => {
-                        let new_volume = volume + 1;
-                        volume = std::cmp::min(VOL_MIN, new_volume);
+                        volume = volume.saturating_sub(VOL_STEP);
+                        mixer.set_volume(volume);
                         if overlay_tx
                             .send(ScreenToast::info(ScreenMessage::VolumeDown(Self::volume(
                                 volume,
@@ -131,8 +334,8 @@ impl Audio {
                         debug!("Volume set to {}", volume);
                     }
                     AudioCmd::VolumeUp => {
-                        let new_volume = volume - 1;
-                        volume = std::cmp::max(VOL_MAX, new_volume);
+                        volume = std::cmp::min(VOL_MAX, volume + VOL_STEP);
+                        mixer.set_volume(volume);
                         if overlay_tx
                             .send(ScreenToast::info(ScreenMessage::VolumeUp(Self::volume(
                                 volume,
@@ -143,17 +346,60 @@ impl Audio {
                         }
                         debug!("Volume set to {}", volume);
                     }
+                    AudioCmd::SetVolume(v) => {
+                        let message = if v >= volume {
+                            ScreenMessage::VolumeUp
+                        } else {
+                            ScreenMessage::VolumeDown
+                        };
+                        volume = std::cmp::min(VOL_MAX, v);
+                        mixer.set_volume(volume);
+                        if overlay_tx
+                            .send(ScreenToast::info(message(Self::volume(volume))))
+                            .is_err()
+                        {
+                            warn!("Failed to send volume popup");
+                        }
+                        debug!("Volume set to {}", volume);
+                    }
+                    AudioCmd::RestoreVolume(v) => {
+                        volume = std::cmp::min(VOL_MAX, v);
+                        mixer.set_volume(volume);
+                        debug!("Volume restored to {}", volume);
+                    }
                 },
-                AudioMsg::Data(data) => match &device {
+                AudioMsg::Data(data) => match &mut device {
                     Some(device) => {
-                        let mut new_vec = Vec::new();
-                        for d in data {
-                            new_vec.push(d >> volume);
+                        if started && device.is_empty() {
+                            shared.underruns.fetch_add(1, Ordering::Relaxed);
+                        }
+                        started = true;
+                        if let Some(r) = &mut recording {
+                            if let Err(e) = r.write(&data) {
+                                warn!("Failed to record audio: {}", e);
+                                recording = None;
+                            }
                         }
-                        if device.queue_audio(new_vec.as_ref()).is_err() {
+                        let new_vec: Vec<i16> =
+                            data.into_iter().map(|d| mixer.process(d)).collect();
+                        if device.queue(new_vec.as_ref()).is_err() {
                             Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
                             warn!("Failed to queue audio");
                         }
+                        if let Some(f) = freq.filter(|f| *f > 0) {
+                            let us = u64::from(device.queued()) * 1_000_000 / f as u64;
+                            let us = us.min(u32::MAX.into()) as u32;
+                            shared.latency_us.store(us, Ordering::Relaxed);
+                        }
+                        if report_status {
+                            let occupancy = device.occupancy();
+                            let status = AudioStatus {
+                                occupancy,
+                                underrun_likely: occupancy < UNDERRUN_LIKELY_PCT,
+                            };
+                            // Only fails once the frontend has gone
+                            let _ = status_tx.send(status);
+                        }
                     }
                     None => {
                         Self::send_error_check(Self::problem(), &mut last_error, &error_tx);
@@ -161,14 +407,23 @@ impl Audio {
                     }
                 },
             }
+            shared.volume.store(volume, Ordering::Relaxed);
         }
         Ok(())
     }
 
-    pub fn new(overlay_tx: mpsc::Sender<ScreenToast>, error_tx: mpsc::Sender<Problem>) -> Self {
+    pub fn new(
+        config: &AudioConfig,
+        overlay_tx: mpsc::Sender<ScreenToast>,
+        error_tx: mpsc::Sender<Problem>,
+    ) -> Self {
+        let config = config.clone();
         let (tx, rx) = mpsc::channel::<AudioMsg>();
+        let shared = Arc::new(Shared::default());
+        let s2 = shared.clone();
+        let (status_tx, status) = mpsc::channel::<AudioStatus>();
         let handle = std::thread::spawn(move || {
-            match Self::audio_thread(rx, overlay_tx, error_tx.clone()) {
+            match Self::audio_thread(config, rx, overlay_tx, error_tx.clone(), s2, status_tx) {
                 Ok(_) => {
                     info!("Audio queue closed cleanly");
                 }
@@ -191,17 +446,23 @@ impl Audio {
             _handle: handle,
             sender: tx,
             real: true,
+            shared,
+            status,
         }
     }
 
     pub fn dummy() -> Self {
         let (tx, rx) = mpsc::channel::<AudioMsg>();
         let handle = std::thread::spawn(move || while rx.recv().is_ok() {});
+        // Never reports a status
+        let (_, status) = mpsc::channel::<AudioStatus>();
 
         Audio {
             _handle: handle,
             sender: tx,
             real: false,
+            shared: Arc::new(Shared::default()),
+            status,
         }
     }
 
@@ -209,7 +470,52 @@ impl Audio {
         self.real
     }
 
+    /// Number of times the queue has run dry while playing
+    pub fn underruns(&self) -> u32 {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Volume (0-100) last set
+    pub fn current_volume(&self) -> u8 {
+        self.shared.volume.load(Ordering::Relaxed)
+    }
+
+    /// How long audio queued now takes to be heard, zero if nothing is
+    /// playing
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.shared.latency_us.load(Ordering::Relaxed).into())
+    }
+
+    /// Latest fill level of the queue since the last call, if reporting is
+    /// on and samples have been played
+    pub fn take_status(&self) -> Option<AudioStatus> {
+        self.status.try_iter().last()
+    }
+
     pub fn get_sender(&self) -> mpsc::Sender<AudioMsg> {
         self.sender.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{gain, soft_clip, Mixer, VOL_DEFAULT};
+
+    #[test]
+    fn volume_gain() {
+        assert_eq!(gain(0), 0.0);
+        assert_eq!(gain(100), 1.0);
+        assert!((1..=100).all(|v| gain(v) > gain(v - 1)));
+        // Default is close to the old 4 bit shift
+        assert!((gain(VOL_DEFAULT) - 1.0 / 16.0).abs() < 0.01);
+
+        assert_eq!(soft_clip(0.5), 0.5);
+        assert!(soft_clip(4.0) <= 1.0);
+        assert!(soft_clip(-4.0) >= -1.0);
+
+        let mut mixer = Mixer::new(100);
+        assert_eq!(mixer.process(i16::MIN), i16::MIN);
+        mixer.set_volume(0);
+        assert!(mixer.process(i16::MAX).abs() <= 1);
+    }
+}
@@ -0,0 +1,108 @@
+use log::{info, warn};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+const HEADER_LEN: u32 = 44;
+
+/// Writes interleaved stereo samples to a 16 bit PCM WAV file. The sizes in
+/// the header are filled in when the recording is finished.
+pub(crate) struct WavWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    rate: u32,
+    // Bytes of samples written so far
+    data_len: u32,
+    full: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, rate: i32) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let rate = rate as u32;
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+        file.write_all(b"RIFF")?;
+        // Both sizes are filled in by finish
+        file.write_all(&0_u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16_u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1_u16.to_le_bytes())?;
+        file.write_all(&CHANNELS.to_le_bytes())?;
+        file.write_all(&rate.to_le_bytes())?;
+        file.write_all(&(rate * u32::from(block_align)).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0_u32.to_le_bytes())?;
+        info!("Recording audio to '{}'", path.display());
+        Ok(WavWriter {
+            path: path.to_path_buf(),
+            file,
+            rate,
+            data_len: 0,
+            full: false,
+        })
+    }
+
+    /// Add samples, those past the largest size a WAV file can hold are
+    /// dropped
+    pub fn write(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        let space = (u32::MAX - HEADER_LEN - self.data_len) / u32::from(BYTES_PER_SAMPLE);
+        if samples.len() > space as usize {
+            if !self.full {
+                warn!("Recording '{}' is full", self.path.display());
+                self.full = true;
+            }
+            return Ok(());
+        }
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.file.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    /// Fill in the sizes in the header and close the file
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(4))
+            .and_then(|_| {
+                self.file
+                    .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())
+            })
+            .and_then(|_| self.file.seek(SeekFrom::Start(u64::from(HEADER_LEN) - 4)))
+            .and_then(|_| self.file.write_all(&self.data_len.to_le_bytes()))
+            .and_then(|_| self.file.flush())?;
+        let secs = self.data_len / u32::from(CHANNELS * BYTES_PER_SAMPLE) / self.rate.max(1);
+        info!("Recorded {}s of audio to '{}'", secs, self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WavWriter;
+    use gamepie_core::testing::TestDir;
+
+    #[test]
+    fn wav_header() {
+        let dir = TestDir::new("record");
+        let path = dir.join("test.wav");
+        let mut wav = WavWriter::create(&path, 32768).unwrap();
+        wav.write(&[1, -1, 2, -2]).unwrap();
+        wav.write(&[i16::MAX, i16::MIN]).unwrap();
+        wav.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 12);
+        assert_eq!(u32_at(24), 32768);
+        assert_eq!(u32_at(28), 32768 * 4);
+        assert_eq!(u32_at(40), 12);
+        assert_eq!(&data[44..48], &[1, 0, 0xff, 0xff]);
+    }
+}
@@ -1,150 +1,713 @@
-use evdev_rs::{Device, DeviceWrapper, InputEvent, ReadFlag};
+use evdev_rs::enums::{EventCode, EV_ABS, EV_KEY};
+use evdev_rs::{Device, DeviceWrapper, InputEvent, ReadFlag, ReadStatus, TimeVal};
 use glob::glob;
-use log::{error, info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use num_traits::ToPrimitive;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::OpenOptions;
 use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use gamepie_core::commands::{ScreenMessage, ScreenToast};
+use gamepie_core::problem::Problem;
+use gamepie_libretrobind::bind::{
+    retro_key_RETROK_LSHIFT, retro_mod_RETROKMOD_SHIFT, retro_rumble_effect,
+    retro_rumble_effect_RETRO_RUMBLE_STRONG, RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_ID_ANALOG_Y,
+    RETRO_DEVICE_INDEX_ANALOG_BUTTON, RETRO_DEVICE_INDEX_ANALOG_LEFT,
+    RETRO_DEVICE_INDEX_ANALOG_RIGHT,
+};
 use gamepie_libretrobind::enums::RetroPadButton;
 
-use crate::mapping::{get_mapping, map_empty};
+use crate::external::external_mask;
+use crate::hotplug::Hotplug;
+use crate::keyboard::{self, KeyEvent};
+use crate::mapping::{load_mappings, DeviceMapping, Input, Mapping};
+use crate::rumble::Rumble;
 
-pub struct Controller {
-    device: Option<Device>,
-    keys: HashMap<RetroPadButton, i16>,
-    mapping: fn(InputEvent) -> Vec<(RetroPadButton, i16)>,
-}
+/// Number of ports controllers are connected to, controllers are given the
+/// first free port in the order they are found.
+pub const MAX_PORTS: u32 = 4;
 
-impl Controller {
-    pub fn new() -> Self {
-        let mut controller = Self::empty();
+// RetroPad change for a port, with the time it was read from the device.
+// The port is ignored for keyboard events.
+type QueuedEvent = (Instant, usize, Input);
 
-        if !controller.try_get_controller() {
-            warn!("No input device");
+// How often to look for controllers while a port is free, or not all of the
+// devices in a combined mapping are connected, if /dev/input can't be watched
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// After events have been dropped, drain the sync delta and rebuild the
+// button state from the device state libevdev has re-read, so buttons
+// released while the buffer was full don't stay pressed.
+fn resync(mapping: &DeviceMapping, d: &Device) -> Vec<Input> {
+    warn!("Input events dropped, resynchronising");
+    while let Ok((ReadStatus::Sync, _)) = d.next_event(ReadFlag::SYNC) {}
+
+    // Release everything, then apply whatever is currently pressed
+    let mut events = release_all();
+    let time = TimeVal::new(0, 0);
+    let keys = EventCode::EV_KEY(EV_KEY::KEY_RESERVED)
+        .iter()
+        .take_while(|c| matches!(c, EventCode::EV_KEY(_)));
+    for code in keys.filter(|c| d.has(c)) {
+        if let Some(v) = d.event_value(&code).filter(|v| *v != 0) {
+            events.extend(mapping.map(d, InputEvent::new(&time, &code, v)));
+        }
+    }
+    // Axes always need applying as centred isn't necessarily zero
+    let axes = EventCode::EV_ABS(EV_ABS::ABS_X)
+        .iter()
+        .take_while(|c| matches!(c, EventCode::EV_ABS(_)));
+    for code in axes.filter(|c| d.has(c)) {
+        if let Some(v) = d.event_value(&code) {
+            events.extend(mapping.map(d, InputEvent::new(&time, &code, v)));
         }
+    }
+
+    events
+}
+
+// Release all buttons and centre the sticks
+fn release_all() -> Vec<Input> {
+    let buttons = (RetroPadButton::B as u32..=RetroPadButton::R3 as u32)
+        .map(|b| Input::Button(RetroPadButton::new(b), 0));
+    let sticks = [
+        RETRO_DEVICE_INDEX_ANALOG_LEFT,
+        RETRO_DEVICE_INDEX_ANALOG_RIGHT,
+    ]
+    .into_iter()
+    .flat_map(|i| {
+        [RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_ID_ANALOG_Y]
+            .into_iter()
+            .map(move |id| Input::Analog(i, id, 0))
+    });
+    buttons.chain(sticks).collect()
+}
 
-        controller
+// The devices making up one player's RetroPad
+struct Pad {
+    // Index of the mapping in use
+    mapping: usize,
+    // Open devices, with the index of their mapping in the pad's mapping and
+    // their path, so the same device isn't used by two pads
+    devices: Vec<(usize, PathBuf, Device)>,
+    // Force feedback from the first device that supports it, with the path
+    // of that device
+    rumble: Option<(PathBuf, Rumble)>,
+    // Strength of the strong and weak motors
+    strength: [u16; 2],
+}
+
+// Strength of one rumble motor for a port
+type RumbleRequest = (usize, retro_rumble_effect, u16);
+
+// Owns the input devices and drains their events on a separate thread, so
+// that a slow retro_run can't leave events in the kernel buffer long
+// enough for it to overflow.
+struct Poller {
+    mappings: Vec<Mapping>,
+    // Pad connected to each port, pads keep their port until all of their
+    // devices are removed
+    pads: Vec<Option<Pad>>,
+    // Keyboards that aren't used by any pad, shared by all ports
+    keyboards: Vec<(PathBuf, Device)>,
+    rumble: mpsc::Receiver<RumbleRequest>,
+    // Raw events from pads are only sent while watched, for the input tester
+    watch_raw: Arc<AtomicBool>,
+    raw: mpsc::Sender<String>,
+    // Player 1 has an analog stick mapped
+    analog: Arc<AtomicBool>,
+    // A device has been removed from a pad since this was last cleared
+    disconnected: Arc<AtomicBool>,
+    last_scan: Option<Instant>,
+    hotplug: Option<Hotplug>,
+    // Connections are shown as toasts, apart from those found at startup
+    problems: mpsc::Sender<Problem>,
+    announce: bool,
+}
+
+// Name of a device for messages, the mapping's if the device has none
+fn device_name<'a>(d: &'a Device, mapping: &'a Mapping) -> &'a str {
+    d.name().unwrap_or(&mapping.name)
+}
+
+fn send_toast(problems: &mpsc::Sender<Problem>, toast: ScreenToast) {
+    if problems.send(Problem::warn(toast)).is_err() {
+        warn!("Failed to send controller message");
     }
+}
 
+fn log_device(d: &Device) {
+    match d.name() {
+        Some(name) => info!("Input device: '{}'", name),
+        None => info!("Input device: UNNAMED"),
+    }
+
+    info!(
+        "Input device: {:#04x}:{:#04x}",
+        d.vendor_id(),
+        d.product_id()
+    );
+}
+
+impl Poller {
+    // Look for devices to complete connected pads, then for new pads to
+    // connect to free ports. Returns true if any device was added.
     fn try_get_controller(&mut self) -> bool {
         trace!("Trying to find controllers");
-        let mut found = false;
-        let mut devices = Vec::new();
+        self.last_scan = Some(Instant::now());
+        let mut paths = Vec::new();
         if let Ok(g) = glob("/dev/input/event*") {
             for d in g.flatten() {
-                devices.push(d);
+                let open = self
+                    .pads
+                    .iter()
+                    .flatten()
+                    .any(|pad| pad.devices.iter().any(|(_, p, _)| *p == d))
+                    || self.keyboards.iter().any(|(p, _)| *p == d);
+                if !open {
+                    paths.push(d);
+                }
             }
         }
 
         let mut options = OpenOptions::new();
         options.read(true);
         options.custom_flags(libc::O_NONBLOCK);
-        for dev in &devices {
-            if let Ok(f) = options.open(dev) {
+        let mut found = Vec::new();
+        for dev in paths {
+            if let Ok(f) = options.open(&dev) {
                 if let Ok(d) = Device::new_from_file(f) {
-                    let mapping = get_mapping(&d);
-                    match mapping {
-                        Some(map) => {
-                            match d.name() {
-                                Some(name) => info!("Input device: '{}'", name),
-                                None => info!("Input device: UNNAMED"),
-                            }
+                    found.push((dev, d));
+                }
+            }
+        }
+
+        let mut added = false;
+        for port in 0..self.pads.len() {
+            let mapping = match &self.pads[port] {
+                Some(pad) => pad.mapping,
+                None => {
+                    let matched = self.mappings.iter().position(|m| {
+                        m.devices
+                            .iter()
+                            .any(|dm| found.iter().any(|(_, d)| dm.matches(d)))
+                    });
+                    match matched {
+                        Some(m) => {
+                            self.pads[port] = Some(Pad {
+                                mapping: m,
+                                devices: Vec::new(),
+                                rumble: None,
+                                strength: [0, 0],
+                            });
+                            m
+                        }
+                        None => continue,
+                    }
+                }
+            };
 
-                            info!(
-                                "Input device: {:#04x}:{:#04x}",
-                                d.vendor_id(),
-                                d.product_id()
-                            );
+            let pad = self.pads[port].as_mut().expect("pad connected");
+            let mapping = &self.mappings[mapping];
+            let mut pad_added = false;
+            for (i, dm) in mapping.devices.iter().enumerate() {
+                if pad.devices.iter().any(|(j, _, _)| *j == i) {
+                    continue;
+                }
+                if let Some(pos) = found.iter().position(|(_, d)| dm.matches(d)) {
+                    let (path, d) = found.swap_remove(pos);
+                    log_device(&d);
+                    if self.announce {
+                        let msg = format!("Controller connected: {}", device_name(&d, mapping));
+                        send_toast(
+                            &self.problems,
+                            ScreenToast::info(ScreenMessage::Message(msg)),
+                        );
+                    }
+                    if pad.rumble.is_none() {
+                        pad.rumble = Rumble::open(&path, &d).map(|r| (path.clone(), r));
+                    }
+                    pad.devices.push((i, path, d));
+                    pad_added = true;
+                }
+            }
+            if pad_added {
+                info!(
+                    "Using mapping '{}' for player {} ({}/{} devices)",
+                    mapping.name,
+                    port + 1,
+                    pad.devices.len(),
+                    mapping.devices.len()
+                );
+                added = true;
+            }
+        }
+        for (path, d) in found.into_iter().filter(|(_, d)| keyboard::is_keyboard(d)) {
+            log_device(&d);
+            info!("Using '{}' as a keyboard", path.display());
+            self.keyboards.push((path, d));
+            added = true;
+        }
+        if !added {
+            trace!("No mapping for any new input device");
+        }
 
-                            self.device = Some(d);
-                            self.mapping = map;
+        added
+    }
 
-                            found = true;
-                            break;
+    // Read all pending keyboard events, returns false if the controller has
+    // gone
+    fn poll_keyboards(&mut self, tx: &mpsc::Sender<QueuedEvent>) -> bool {
+        let mut lost = Vec::new();
+        for (n, (_, d)) in self.keyboards.iter().enumerate() {
+            loop {
+                match d.next_event(ReadFlag::NORMAL) {
+                    Ok((ReadStatus::Success, event)) => {
+                        let key = match &event.event_code {
+                            // Auto repeat (2) is left to the core
+                            EventCode::EV_KEY(k) if event.value != 2 => keyboard::retro_key(k),
+                            _ => None,
+                        };
+                        if let Some(k) = key {
+                            let input = Input::Key(k, event.value != 0);
+                            if tx.send((Instant::now(), 0, input)).is_err() {
+                                return false;
+                            }
                         }
-                        None => {
-                            trace!("No mapping for: {:?}", dev);
+                    }
+                    Ok((_, _)) => {
+                        // Buffer overflowed, drain the sync delta rather
+                        // than replay it as keys may now be out of order
+                        warn!("Keyboard events dropped");
+                        while let Ok((ReadStatus::Sync, _)) = d.next_event(ReadFlag::SYNC) {}
+                    }
+                    Err(e) => {
+                        if e.raw_os_error() == Some(19) {
+                            // ENODEV
+                            lost.push(n);
+                        } else if e.kind() != std::io::ErrorKind::WouldBlock {
+                            error!("Keyboard error {:?}", e);
                         }
+                        break;
                     }
                 }
             }
         }
 
-        found
+        if !lost.is_empty() {
+            for n in lost.into_iter().rev() {
+                self.keyboards.remove(n);
+            }
+            warn!("Keyboard removed");
+            // Keys held on the removed keyboard would otherwise stick
+            let now = Instant::now();
+            for k in keyboard::all_keys() {
+                if tx.send((now, 0, Input::Key(k, false))).is_err() {
+                    return false;
+                }
+            }
+        }
+
+        true
     }
 
-    fn empty() -> Self {
-        Controller {
-            device: None,
-            keys: HashMap::new(),
-            mapping: map_empty,
+    fn complete(&self) -> bool {
+        self.pads.iter().all(|pad| match pad {
+            Some(pad) => pad.devices.len() == self.mappings[pad.mapping].devices.len(),
+            None => false,
+        })
+    }
+
+    // Apply rumble requested since the last poll
+    fn update_rumble(&mut self) {
+        let mut changed = Vec::new();
+        while let Ok((port, effect, strength)) = self.rumble.try_recv() {
+            if let Some(Some(pad)) = self.pads.get_mut(port) {
+                let motor = if effect == retro_rumble_effect_RETRO_RUMBLE_STRONG {
+                    0
+                } else {
+                    1
+                };
+                pad.strength[motor] = strength;
+                changed.push(port);
+            }
+        }
+        for port in changed {
+            if let Some(pad) = self.pads[port].as_mut() {
+                if let Some((_, rumble)) = pad.rumble.as_mut() {
+                    rumble.set(pad.strength[0], pad.strength[1]);
+                }
+            }
         }
     }
 
-    pub fn input_poll(&mut self) {
-        if self.device.is_none() {
+    // Read all pending events, returns false if the controller has gone
+    fn poll(&mut self, tx: &mpsc::Sender<QueuedEvent>) -> bool {
+        let rescan = match (&self.hotplug, self.last_scan) {
+            (_, None) => true,
+            (Some(hotplug), Some(_)) => hotplug.take_changed(),
+            (None, Some(t)) => !self.complete() && t.elapsed() > RESCAN_INTERVAL,
+        };
+        if rescan {
             self.try_get_controller();
         }
+        // Cheap enough to check every poll, rather than on each change
+        let analog = self.pads[0].as_ref().is_some_and(|pad| {
+            let mapping = &self.mappings[pad.mapping];
+            pad.devices
+                .iter()
+                .any(|(i, _, _)| mapping.devices[*i].has_analog())
+        });
+        self.analog.store(analog, Ordering::Release);
+        if !self.poll_keyboards(tx) {
+            return false;
+        }
+        self.update_rumble();
 
-        let mut need_to_destruct = false;
+        let watch_raw = self.watch_raw.load(Ordering::Acquire);
+        for (port, slot) in self.pads.iter_mut().enumerate() {
+            let pad = match slot {
+                Some(pad) => pad,
+                None => continue,
+            };
+            let mapping = &self.mappings[pad.mapping];
 
-        if let Some(d) = &self.device {
-            let mut try_get = true;
-            while try_get {
-                let ev = d.next_event(ReadFlag::NORMAL);
-                match ev {
-                    Ok((status, event)) => {
-                        if status == evdev_rs::ReadStatus::Sync {
-                            warn!("SYNC");
-                        }
-                        let events = (self.mapping)(event);
-                        for (k, v) in events {
-                            self.keys.insert(k, v);
-                        }
-                    }
-                    Err(e) => {
-                        try_get = false;
-                        if let Some(os) = e.raw_os_error() {
-                            if os == 19 {
-                                // ENODEV
-                                // Destruct and try again if device not present
-                                need_to_destruct = true;
+            let mut lost = Vec::new();
+            for (n, (i, _, d)) in pad.devices.iter().enumerate() {
+                let dm = &mapping.devices[*i];
+                loop {
+                    match d.next_event(ReadFlag::NORMAL) {
+                        Ok((status, event)) => {
+                            let now = Instant::now();
+                            if watch_raw && !matches!(event.event_code, EventCode::EV_SYN(_)) {
+                                let raw =
+                                    format!("P{} {} {}", port + 1, event.event_code, event.value);
+                                // Only fails if the controller is gone
+                                let _ = self.raw.send(raw);
+                            }
+                            let events = if status == ReadStatus::Sync {
+                                // Event is SYN_DROPPED, the buffer overflowed
+                                resync(dm, d)
+                            } else {
+                                dm.map(d, event)
+                            };
+                            for input in events {
+                                if tx.send((now, port, input)).is_err() {
+                                    return false;
+                                }
                             }
                         }
-                        match e.kind() {
-                            std::io::ErrorKind::WouldBlock => {}
-                            _ => {
-                                error!("Error kind {:?}", e.kind());
-                                error!("Error {:?}", e);
+                        Err(e) => {
+                            if let Some(os) = e.raw_os_error() {
+                                if os == 19 {
+                                    // ENODEV
+                                    // Destruct and try again if device not present
+                                    lost.push(n);
+                                }
+                            }
+                            match e.kind() {
+                                std::io::ErrorKind::WouldBlock => {}
+                                _ => {
+                                    error!("Error kind {:?}", e.kind());
+                                    error!("Error {:?}", e);
+                                }
                             }
+                            break;
                         }
                     }
                 }
             }
+
+            if !lost.is_empty() {
+                for n in lost.into_iter().rev() {
+                    let (_, path, d) = pad.devices.remove(n);
+                    if pad.rumble.as_ref().is_some_and(|(p, _)| *p == path) {
+                        pad.rumble = None;
+                    }
+                    let msg = format!("Controller disconnected: {}", device_name(&d, mapping));
+                    send_toast(
+                        &self.problems,
+                        ScreenToast::error(ScreenMessage::Message(msg)),
+                    );
+                }
+                warn!("Input device removed from player {}", port + 1);
+                self.disconnected.store(true, Ordering::Release);
+                // Buttons held on the removed device would otherwise stick
+                let now = Instant::now();
+                for input in release_all() {
+                    if tx.send((now, port, input)).is_err() {
+                        return false;
+                    }
+                }
+                if pad.devices.is_empty() {
+                    *slot = None;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+// RetroPad state of one port
+#[derive(Default)]
+struct PadState {
+    keys: HashMap<RetroPadButton, i16>,
+    // Stick positions by (index, id)
+    analog: HashMap<(u32, u32), i16>,
+}
+
+pub struct Controller {
+    pads: Vec<PadState>,
+    // Keys held, by RETROK code
+    keys: HashSet<u32>,
+    // Key changes since the last poll, for the core's keyboard callback
+    key_events: Vec<KeyEvent>,
+    // Keys typed on the on-screen keyboard, with whether shift is needed.
+    // Each is pressed on one poll and released on the next.
+    typed: VecDeque<(u32, bool)>,
+    typing: Option<(u32, bool)>,
+    rumble: mpsc::Sender<RumbleRequest>,
+    watch_raw: Arc<AtomicBool>,
+    raw: mpsc::Receiver<String>,
+    analog: Arc<AtomicBool>,
+    disconnected: Arc<AtomicBool>,
+    events: mpsc::Receiver<QueuedEvent>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Controller {
+    /// Start polling for input, the devices are checked for events every
+    /// `poll_interval` independently of the frame rate. User mappings are
+    /// loaded from `mapping_dir`. Controllers plugged in or removed later
+    /// are shown as toasts through `problems`.
+    pub fn new(
+        poll_interval: Duration,
+        mapping_dir: &Path,
+        problems: mpsc::Sender<Problem>,
+    ) -> Self {
+        let (rumble, rumble_rx) = mpsc::channel();
+        let (raw_tx, raw) = mpsc::channel();
+        let watch_raw = Arc::new(AtomicBool::new(false));
+        let analog = Arc::new(AtomicBool::new(false));
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let mut poller = Poller {
+            mappings: load_mappings(mapping_dir),
+            pads: (0..MAX_PORTS).map(|_| None).collect(),
+            keyboards: Vec::new(),
+            rumble: rumble_rx,
+            watch_raw: watch_raw.clone(),
+            raw: raw_tx,
+            analog: analog.clone(),
+            disconnected: disconnected.clone(),
+            last_scan: None,
+            hotplug: Hotplug::start(),
+            problems,
+            announce: false,
+        };
+
+        if !poller.try_get_controller() {
+            warn!("No input device");
+        }
+        poller.announce = true;
+
+        let (tx, events) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let r2 = running.clone();
+        let thread = Some(std::thread::spawn(move || {
+            while r2.load(Ordering::Acquire) && poller.poll(&tx) {
+                std::thread::sleep(poll_interval);
+            }
+            debug!("Input thread finished");
+        }));
+
+        Controller {
+            pads: (0..MAX_PORTS).map(|_| PadState::default()).collect(),
+            keys: HashSet::new(),
+            key_events: Vec::new(),
+            typed: VecDeque::new(),
+            typing: None,
+            rumble,
+            watch_raw,
+            raw,
+            analog,
+            disconnected,
+            events,
+            running,
+            thread,
+        }
+    }
+
+    /// Apply events queued by the input thread since the last poll
+    pub fn input_poll(&mut self) {
+        let mut oldest = None;
+        while let Ok((time, port, input)) = self.events.try_recv() {
+            oldest.get_or_insert(time);
+            match input {
+                Input::Button(k, v) => {
+                    self.pads[port].keys.insert(k, v);
+                }
+                Input::Analog(index, id, v) => {
+                    self.pads[port].analog.insert((index, id), v);
+                }
+                Input::Key(k, down) => self.key_change(k, down),
+            }
+        }
+        if let Some(t) = oldest {
+            trace!("Input latency up to {:?}", t.elapsed());
+        }
+        self.type_next();
+    }
+
+    // Release the key typed on the last poll, or press the next one
+    fn type_next(&mut self) {
+        if let Some((keycode, shift)) = self.typing.take() {
+            self.key_change(keycode, false);
+            if shift {
+                self.key_change(retro_key_RETROK_LSHIFT, false);
+            }
+        } else if let Some((keycode, shift)) = self.typed.pop_front() {
+            if shift {
+                self.key_change(retro_key_RETROK_LSHIFT, true);
+            }
+            self.key_change(keycode, true);
+            self.typing = Some((keycode, shift));
+        }
+    }
+
+    /// Type text as if on a keyboard, one key each poll. Characters without
+    /// a key are skipped.
+    pub fn type_text(&mut self, text: &str) {
+        for c in text.chars() {
+            match keyboard::char_key(c) {
+                Some(key) => self.typed.push_back(key),
+                None => warn!("No key to type '{}'", c),
+            }
         }
+    }
+
+    fn key_change(&mut self, keycode: u32, down: bool) {
+        // Releases are also sent for keys that weren't held
+        let changed = if down {
+            self.keys.insert(keycode)
+        } else {
+            self.keys.remove(&keycode)
+        };
+        if !changed && !down {
+            return;
+        }
+        let modifiers = self.keys.iter().fold(0, |m, k| m | keyboard::modifier(*k));
+        let shift = modifiers & retro_mod_RETROKMOD_SHIFT != 0;
+        self.key_events.push(KeyEvent {
+            down,
+            keycode,
+            character: if down {
+                keyboard::character(keycode, shift)
+            } else {
+                0
+            },
+            // Flags fit in u16
+            modifiers: modifiers as u16,
+        });
+    }
 
-        if need_to_destruct {
-            self.device = None;
+    /// Set the strength of a rumble motor for a port, ignored if the port's
+    /// controller doesn't support force feedback.
+    pub fn set_rumble(&self, port: u32, effect: retro_rumble_effect, strength: u16) {
+        if self.rumble.send((port as usize, effect, strength)).is_err() {
+            warn!("Input thread not running for rumble");
         }
     }
 
-    pub fn input_state(&self, id: RetroPadButton) -> i16 {
+    /// Whether player 1's controller has an analog stick mapped
+    pub fn has_analog(&self) -> bool {
+        self.analog.load(Ordering::Acquire)
+    }
+
+    /// Whether a controller has been unplugged since this was last called
+    pub fn take_disconnected(&self) -> bool {
+        self.disconnected.swap(false, Ordering::AcqRel)
+    }
+
+    /// Start or stop collecting raw evdev events from pads, e.g. to check
+    /// a new mapping.
+    pub fn watch_raw(&self, watch: bool) {
+        self.watch_raw.store(watch, Ordering::Release);
+        if !watch {
+            // Discard anything sent before the input thread saw the change
+            self.take_raw_events();
+        }
+    }
+
+    /// Take the raw events since they were last taken, as text
+    pub fn take_raw_events(&self) -> Vec<String> {
+        self.raw.try_iter().collect()
+    }
+
+    /// Take the key changes since they were last taken
+    pub fn take_key_events(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(&mut self.key_events)
+    }
+
+    /// State of a key for RETRO_DEVICE_KEYBOARD, by RETROK code
+    pub fn key_state(&self, keycode: u32) -> i16 {
+        i16::from(self.keys.contains(&keycode))
+    }
+
+    /// Button state for a port, ports without a controller read as nothing
+    /// pressed. Buttons held on other inputs are added to port 0.
+    pub fn input_state(&self, port: u32, id: RetroPadButton) -> i16 {
+        let pad = match self.pads.get(port as usize) {
+            Some(pad) => pad,
+            None => return 0,
+        };
+        let external = external_mask(port);
         if id == RetroPadButton::Mask {
-            let mut result = 0;
-            for (b, val) in &self.keys {
+            let mut result = external;
+            for (b, val) in &pad.keys {
                 let id = b.to_u32().expect("button u32");
                 result |= val << id;
             }
             result
         } else {
-            *self.keys.get(&id).unwrap_or(&0)
+            let held = id
+                .to_u32()
+                .is_some_and(|i| i < 16 && external & (1 << i) != 0);
+            i16::from(held) | *pad.keys.get(&id).unwrap_or(&0)
+        }
+    }
+
+    /// Analog state for RETRO_DEVICE_ANALOG, sticks that aren't mapped are
+    /// centred and analog buttons are full scale when pressed.
+    pub fn input_state_analog(&self, port: u32, index: u32, id: u32) -> i16 {
+        if index == RETRO_DEVICE_INDEX_ANALOG_BUTTON {
+            if self.input_state(port, RetroPadButton::new(id)) != 0 {
+                i16::MAX
+            } else {
+                0
+            }
+        } else {
+            match self.pads.get(port as usize) {
+                Some(pad) => *pad.analog.get(&(index, id)).unwrap_or(&0),
+                None => 0,
+            }
         }
     }
 }
 
-impl Default for Controller {
-    fn default() -> Self {
-        Self::new()
+impl Drop for Controller {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                error!("Input thread panicked");
+            }
+        }
     }
 }
@@ -0,0 +1,47 @@
+use log::warn;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use gamepie_libretrobind::enums::RetroPadButton;
+use num_traits::ToPrimitive;
+
+use crate::controller::MAX_PORTS;
+
+// Player 1 buttons held on inputs other than controllers, as a RetroPad
+// mask. Global as a controller is created for each proxy, while the inputs
+// are read for as long as gamepie runs.
+static HELD: AtomicU16 = AtomicU16::new(0);
+
+// Buttons held by remote players, as a RetroPad mask for each port
+static REMOTE: [AtomicU16; MAX_PORTS as usize] = [const { AtomicU16::new(0) }; MAX_PORTS as usize];
+
+/// Set the buttons held on inputs other than controllers, e.g. GPIO buttons,
+/// which are pressed on player 1's RetroPad along with its controller
+pub fn set_external_buttons(buttons: &[RetroPadButton]) {
+    let mask = buttons
+        .iter()
+        .filter_map(|b| b.to_u32())
+        .filter(|id| *id < u16::BITS)
+        .fold(0, |m, id| m | (1 << id));
+    HELD.store(mask, Ordering::Release);
+}
+
+/// Set the buttons held by a remote player, as a RetroPad mask, which are
+/// pressed on the port's RetroPad along with any controller
+pub fn set_remote_buttons(port: u32, mask: u16) {
+    match REMOTE.get(port as usize) {
+        Some(held) => held.store(mask, Ordering::Release),
+        None => warn!("No port {} for remote buttons", port),
+    }
+}
+
+pub(crate) fn external_mask(port: u32) -> i16 {
+    let remote = REMOTE
+        .get(port as usize)
+        .map_or(0, |r| r.load(Ordering::Acquire));
+    let held = if port == 0 {
+        HELD.load(Ordering::Acquire)
+    } else {
+        0
+    };
+    (held | remote) as i16
+}
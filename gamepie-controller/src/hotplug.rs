@@ -0,0 +1,111 @@
+use log::{debug, warn};
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+const INPUT_DIR: &str = "/dev/input";
+// How long to block waiting for changes before checking for exit
+const WAIT_MS: i32 = 250;
+// Room for several events, each name is at most NAME_MAX
+const BUFFER_SIZE: usize = 4096;
+
+// Whether a batch of inotify events includes an evdev device
+fn has_event_device(buf: &[u8]) -> bool {
+    let header = std::mem::size_of::<libc::inotify_event>();
+    let mut offset = 0;
+    while offset + header <= buf.len() {
+        // The kernel writes whole events, a header then the name
+        let event = unsafe {
+            std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+        };
+        let name_start = offset + header;
+        let name_end = std::cmp::min(name_start + event.len as usize, buf.len());
+        if buf[name_start..name_end].starts_with(b"event") {
+            return true;
+        }
+        offset = name_end;
+    }
+    false
+}
+
+/// Watches /dev/input on its own thread, so devices are rescanned as soon
+/// as they are plugged in or removed rather than on a timer.
+pub(crate) struct Hotplug {
+    changed: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Hotplug {
+    /// Start watching, None if inotify isn't available
+    pub fn start() -> Option<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            warn!("Failed to watch for input devices");
+            return None;
+        }
+        let dir = CString::new(INPUT_DIR).expect("no nul in path");
+        // Attribute changes catch udev setting permissions after creation
+        let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_ATTRIB;
+        if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+            warn!("Failed to watch '{}'", INPUT_DIR);
+            unsafe { libc::close(fd) };
+            return None;
+        }
+
+        let changed = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+        let (c2, r2) = (changed.clone(), running.clone());
+        let spawned = std::thread::Builder::new()
+            .name(String::from("hotplug"))
+            .spawn(move || {
+                let mut buf = [0u8; BUFFER_SIZE];
+                let mut pfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                while r2.load(Ordering::Acquire) {
+                    if unsafe { libc::poll(&mut pfd, 1, WAIT_MS) } <= 0 {
+                        continue;
+                    }
+                    let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+                    if n > 0 && has_event_device(&buf[..n as usize]) {
+                        debug!("Input devices changed");
+                        c2.store(true, Ordering::Release);
+                    }
+                }
+                unsafe { libc::close(fd) };
+                debug!("Hotplug thread finished");
+            });
+        match spawned {
+            Ok(thread) => Some(Hotplug {
+                changed,
+                running,
+                thread: Some(thread),
+            }),
+            Err(e) => {
+                warn!("Failed to start hotplug thread: {}", e);
+                unsafe { libc::close(fd) };
+                None
+            }
+        }
+    }
+
+    /// Whether devices have been added or removed since this was last called
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::AcqRel)
+    }
+}
+
+impl Drop for Hotplug {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(t) = self.thread.take() {
+            if t.join().is_err() {
+                warn!("Hotplug thread panicked");
+            }
+        }
+    }
+}
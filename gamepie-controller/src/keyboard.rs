@@ -0,0 +1,225 @@
+use evdev_rs::enums::{EventCode, EV_KEY};
+use evdev_rs::{Device, DeviceWrapper};
+
+use gamepie_libretrobind::bind::*;
+
+// Keys that type a character, with the character when shifted. The RETROK
+// code of these keys is the unshifted character. Assumes a US layout.
+const CHAR_KEYS: &[(EV_KEY, char, char)] = &[
+    (EV_KEY::KEY_A, 'a', 'A'),
+    (EV_KEY::KEY_B, 'b', 'B'),
+    (EV_KEY::KEY_C, 'c', 'C'),
+    (EV_KEY::KEY_D, 'd', 'D'),
+    (EV_KEY::KEY_E, 'e', 'E'),
+    (EV_KEY::KEY_F, 'f', 'F'),
+    (EV_KEY::KEY_G, 'g', 'G'),
+    (EV_KEY::KEY_H, 'h', 'H'),
+    (EV_KEY::KEY_I, 'i', 'I'),
+    (EV_KEY::KEY_J, 'j', 'J'),
+    (EV_KEY::KEY_K, 'k', 'K'),
+    (EV_KEY::KEY_L, 'l', 'L'),
+    (EV_KEY::KEY_M, 'm', 'M'),
+    (EV_KEY::KEY_N, 'n', 'N'),
+    (EV_KEY::KEY_O, 'o', 'O'),
+    (EV_KEY::KEY_P, 'p', 'P'),
+    (EV_KEY::KEY_Q, 'q', 'Q'),
+    (EV_KEY::KEY_R, 'r', 'R'),
+    (EV_KEY::KEY_S, 's', 'S'),
+    (EV_KEY::KEY_T, 't', 'T'),
+    (EV_KEY::KEY_U, 'u', 'U'),
+    (EV_KEY::KEY_V, 'v', 'V'),
+    (EV_KEY::KEY_W, 'w', 'W'),
+    (EV_KEY::KEY_X, 'x', 'X'),
+    (EV_KEY::KEY_Y, 'y', 'Y'),
+    (EV_KEY::KEY_Z, 'z', 'Z'),
+    (EV_KEY::KEY_1, '1', '!'),
+    (EV_KEY::KEY_2, '2', '@'),
+    (EV_KEY::KEY_3, '3', '#'),
+    (EV_KEY::KEY_4, '4', '$'),
+    (EV_KEY::KEY_5, '5', '%'),
+    (EV_KEY::KEY_6, '6', '^'),
+    (EV_KEY::KEY_7, '7', '&'),
+    (EV_KEY::KEY_8, '8', '*'),
+    (EV_KEY::KEY_9, '9', '('),
+    (EV_KEY::KEY_0, '0', ')'),
+    (EV_KEY::KEY_SPACE, ' ', ' '),
+    (EV_KEY::KEY_MINUS, '-', '_'),
+    (EV_KEY::KEY_EQUAL, '=', '+'),
+    (EV_KEY::KEY_LEFTBRACE, '[', '{'),
+    (EV_KEY::KEY_RIGHTBRACE, ']', '}'),
+    (EV_KEY::KEY_BACKSLASH, '\\', '|'),
+    (EV_KEY::KEY_SEMICOLON, ';', ':'),
+    (EV_KEY::KEY_APOSTROPHE, '\'', '"'),
+    (EV_KEY::KEY_GRAVE, '`', '~'),
+    (EV_KEY::KEY_COMMA, ',', '<'),
+    (EV_KEY::KEY_DOT, '.', '>'),
+    (EV_KEY::KEY_SLASH, '/', '?'),
+];
+
+// Keys that don't type a character
+const OTHER_KEYS: &[(EV_KEY, retro_key)] = &[
+    (EV_KEY::KEY_BACKSPACE, retro_key_RETROK_BACKSPACE),
+    (EV_KEY::KEY_TAB, retro_key_RETROK_TAB),
+    (EV_KEY::KEY_ENTER, retro_key_RETROK_RETURN),
+    (EV_KEY::KEY_PAUSE, retro_key_RETROK_PAUSE),
+    (EV_KEY::KEY_ESC, retro_key_RETROK_ESCAPE),
+    (EV_KEY::KEY_DELETE, retro_key_RETROK_DELETE),
+    (EV_KEY::KEY_KP0, retro_key_RETROK_KP0),
+    (EV_KEY::KEY_KP1, retro_key_RETROK_KP1),
+    (EV_KEY::KEY_KP2, retro_key_RETROK_KP2),
+    (EV_KEY::KEY_KP3, retro_key_RETROK_KP3),
+    (EV_KEY::KEY_KP4, retro_key_RETROK_KP4),
+    (EV_KEY::KEY_KP5, retro_key_RETROK_KP5),
+    (EV_KEY::KEY_KP6, retro_key_RETROK_KP6),
+    (EV_KEY::KEY_KP7, retro_key_RETROK_KP7),
+    (EV_KEY::KEY_KP8, retro_key_RETROK_KP8),
+    (EV_KEY::KEY_KP9, retro_key_RETROK_KP9),
+    (EV_KEY::KEY_KPDOT, retro_key_RETROK_KP_PERIOD),
+    (EV_KEY::KEY_KPSLASH, retro_key_RETROK_KP_DIVIDE),
+    (EV_KEY::KEY_KPASTERISK, retro_key_RETROK_KP_MULTIPLY),
+    (EV_KEY::KEY_KPMINUS, retro_key_RETROK_KP_MINUS),
+    (EV_KEY::KEY_KPPLUS, retro_key_RETROK_KP_PLUS),
+    (EV_KEY::KEY_KPENTER, retro_key_RETROK_KP_ENTER),
+    (EV_KEY::KEY_KPEQUAL, retro_key_RETROK_KP_EQUALS),
+    (EV_KEY::KEY_UP, retro_key_RETROK_UP),
+    (EV_KEY::KEY_DOWN, retro_key_RETROK_DOWN),
+    (EV_KEY::KEY_RIGHT, retro_key_RETROK_RIGHT),
+    (EV_KEY::KEY_LEFT, retro_key_RETROK_LEFT),
+    (EV_KEY::KEY_INSERT, retro_key_RETROK_INSERT),
+    (EV_KEY::KEY_HOME, retro_key_RETROK_HOME),
+    (EV_KEY::KEY_END, retro_key_RETROK_END),
+    (EV_KEY::KEY_PAGEUP, retro_key_RETROK_PAGEUP),
+    (EV_KEY::KEY_PAGEDOWN, retro_key_RETROK_PAGEDOWN),
+    (EV_KEY::KEY_F1, retro_key_RETROK_F1),
+    (EV_KEY::KEY_F2, retro_key_RETROK_F2),
+    (EV_KEY::KEY_F3, retro_key_RETROK_F3),
+    (EV_KEY::KEY_F4, retro_key_RETROK_F4),
+    (EV_KEY::KEY_F5, retro_key_RETROK_F5),
+    (EV_KEY::KEY_F6, retro_key_RETROK_F6),
+    (EV_KEY::KEY_F7, retro_key_RETROK_F7),
+    (EV_KEY::KEY_F8, retro_key_RETROK_F8),
+    (EV_KEY::KEY_F9, retro_key_RETROK_F9),
+    (EV_KEY::KEY_F10, retro_key_RETROK_F10),
+    (EV_KEY::KEY_F11, retro_key_RETROK_F11),
+    (EV_KEY::KEY_F12, retro_key_RETROK_F12),
+    (EV_KEY::KEY_NUMLOCK, retro_key_RETROK_NUMLOCK),
+    (EV_KEY::KEY_CAPSLOCK, retro_key_RETROK_CAPSLOCK),
+    (EV_KEY::KEY_SCROLLLOCK, retro_key_RETROK_SCROLLOCK),
+    (EV_KEY::KEY_RIGHTSHIFT, retro_key_RETROK_RSHIFT),
+    (EV_KEY::KEY_LEFTSHIFT, retro_key_RETROK_LSHIFT),
+    (EV_KEY::KEY_RIGHTCTRL, retro_key_RETROK_RCTRL),
+    (EV_KEY::KEY_LEFTCTRL, retro_key_RETROK_LCTRL),
+    (EV_KEY::KEY_RIGHTALT, retro_key_RETROK_RALT),
+    (EV_KEY::KEY_LEFTALT, retro_key_RETROK_LALT),
+    (EV_KEY::KEY_LEFTMETA, retro_key_RETROK_LSUPER),
+    (EV_KEY::KEY_RIGHTMETA, retro_key_RETROK_RSUPER),
+    (EV_KEY::KEY_SYSRQ, retro_key_RETROK_PRINT),
+    (EV_KEY::KEY_MENU, retro_key_RETROK_MENU),
+];
+
+// Modifier flags held by each modifier key
+const MODIFIERS: &[(retro_key, retro_mod)] = &[
+    (retro_key_RETROK_LSHIFT, retro_mod_RETROKMOD_SHIFT),
+    (retro_key_RETROK_RSHIFT, retro_mod_RETROKMOD_SHIFT),
+    (retro_key_RETROK_LCTRL, retro_mod_RETROKMOD_CTRL),
+    (retro_key_RETROK_RCTRL, retro_mod_RETROKMOD_CTRL),
+    (retro_key_RETROK_LALT, retro_mod_RETROKMOD_ALT),
+    (retro_key_RETROK_RALT, retro_mod_RETROKMOD_ALT),
+    (retro_key_RETROK_LSUPER, retro_mod_RETROKMOD_META),
+    (retro_key_RETROK_RSUPER, retro_mod_RETROKMOD_META),
+];
+
+/// Key press or release for the core's keyboard callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub down: bool,
+    /// RETROK code
+    pub keycode: u32,
+    /// Character typed, zero if none
+    pub character: u32,
+    /// RETROKMOD flags
+    pub modifiers: u16,
+}
+
+/// RETROK code for an evdev key
+pub(crate) fn retro_key(key: &EV_KEY) -> Option<retro_key> {
+    CHAR_KEYS
+        .iter()
+        .find(|(k, _, _)| k == key)
+        .map(|(_, c, _)| *c as retro_key)
+        .or_else(|| OTHER_KEYS.iter().find(|(k, _)| k == key).map(|(_, r)| *r))
+}
+
+/// Every key that can be reported, e.g. to release them all
+pub(crate) fn all_keys() -> impl Iterator<Item = retro_key> {
+    CHAR_KEYS
+        .iter()
+        .map(|(_, c, _)| *c as retro_key)
+        .chain(OTHER_KEYS.iter().map(|(_, r)| *r))
+}
+
+/// Character typed by a key, zero if it doesn't type one
+pub(crate) fn character(keycode: retro_key, shift: bool) -> u32 {
+    CHAR_KEYS
+        .iter()
+        .find(|(_, c, _)| *c as retro_key == keycode)
+        .map_or(0, |(_, c, s)| if shift { *s as u32 } else { *c as u32 })
+}
+
+/// Key that types a character, and whether shift is needed for it. New
+/// lines are typed with Return.
+pub(crate) fn char_key(c: char) -> Option<(retro_key, bool)> {
+    if c == '\n' {
+        return Some((retro_key_RETROK_RETURN, false));
+    }
+    CHAR_KEYS.iter().find_map(|(_, plain, shifted)| {
+        if *plain == c {
+            Some((*plain as retro_key, false))
+        } else if *shifted == c {
+            Some((*plain as retro_key, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// Modifier flag set while a key is held, zero if not a modifier
+pub(crate) fn modifier(keycode: retro_key) -> retro_mod {
+    MODIFIERS
+        .iter()
+        .find(|(k, _)| *k == keycode)
+        .map_or(0, |(_, m)| *m)
+}
+
+/// Devices with letter keys and a space bar are treated as keyboards,
+/// which excludes controllers and power buttons that report keys.
+pub(crate) fn is_keyboard(d: &Device) -> bool {
+    [EV_KEY::KEY_A, EV_KEY::KEY_Z, EV_KEY::KEY_SPACE]
+        .into_iter()
+        .all(|k| d.has(&EventCode::EV_KEY(k)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{char_key, character, modifier, retro_key};
+    use evdev_rs::enums::EV_KEY;
+    use gamepie_libretrobind::bind::*;
+
+    #[test]
+    fn key_codes() {
+        assert_eq!(retro_key(&EV_KEY::KEY_A), Some(retro_key_RETROK_a));
+        assert_eq!(retro_key(&EV_KEY::KEY_1), Some(retro_key_RETROK_1));
+        assert_eq!(retro_key(&EV_KEY::KEY_F1), Some(retro_key_RETROK_F1));
+        assert_eq!(retro_key(&EV_KEY::BTN_SOUTH), None);
+        assert_eq!(character(retro_key_RETROK_a, true), 'A' as u32);
+        assert_eq!(character(retro_key_RETROK_SLASH, false), '/' as u32);
+        assert_eq!(character(retro_key_RETROK_F1, false), 0);
+        assert_eq!(modifier(retro_key_RETROK_RSHIFT), retro_mod_RETROKMOD_SHIFT);
+        assert_eq!(char_key('a'), Some((retro_key_RETROK_a, false)));
+        assert_eq!(char_key('A'), Some((retro_key_RETROK_a, true)));
+        assert_eq!(char_key(' '), Some((retro_key_RETROK_SPACE, false)));
+        assert_eq!(char_key('?'), Some((retro_key_RETROK_SLASH, true)));
+        assert_eq!(char_key('\n'), Some((retro_key_RETROK_RETURN, false)));
+        assert_eq!(char_key('\u{e9}'), None);
+    }
+}
@@ -1,9 +1,11 @@
-use evdev_rs::InputEvent;
-use gamepie_libretrobind::enums::RetroPadButton;
-
 mod controller;
+mod external;
+mod hotplug;
+mod keyboard;
 mod mapping;
+mod rumble;
 
 pub use controller::*;
-
-pub(crate) type MappingFn = fn(InputEvent) -> Vec<(RetroPadButton, i16)>;
+pub use external::{set_external_buttons, set_remote_buttons};
+pub use keyboard::KeyEvent;
+pub use mapping::button_from_str;
@@ -1,246 +1,381 @@
-use evdev_rs::enums::{EventCode, EventType, EV_ABS, EV_KEY};
+use evdev_rs::enums::{EventCode, EventType};
 use evdev_rs::{Device, DeviceWrapper, InputEvent};
-use log::{trace, warn};
+use log::{debug, info, trace, warn};
+use std::path::Path;
 
+use gamepie_libretrobind::bind::{
+    RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_ID_ANALOG_Y, RETRO_DEVICE_INDEX_ANALOG_LEFT,
+    RETRO_DEVICE_INDEX_ANALOG_RIGHT,
+};
 use gamepie_libretrobind::enums::RetroPadButton;
 
-use crate::MappingFn;
+// Mappings are defined in TOML files, each mapping has one or more devices
+// which are combined into a single RetroPad, e.g. the two halves of a split
+// controller. Each device has layers of buttons and axes that are all
+// applied to its events. Mappings shipped here are used if no user mapping
+// matches a device.
+const MAPPINGS: &[(&str, &str)] = &[
+    ("8bitdo", include_str!("../mappings/8bitdo.toml")),
+    ("switchwired", include_str!("../mappings/switchwired.toml")),
+    ("joycon", include_str!("../mappings/joycon.toml")),
+];
 
-// Mappings are defined as a function from an input event to a list of
-// RetroPad button values.
-// Currently these are hard-coded but would be better as configuration
-// files if supporting more varied controllers.
+/// RetroPad button by name, e.g. "Start"
+pub fn button_from_str(name: &str) -> Option<RetroPadButton> {
+    match name {
+        "B" => Some(RetroPadButton::B),
+        "Y" => Some(RetroPadButton::Y),
+        "Select" => Some(RetroPadButton::Select),
+        "Start" => Some(RetroPadButton::Start),
+        "Up" => Some(RetroPadButton::Up),
+        "Down" => Some(RetroPadButton::Down),
+        "Left" => Some(RetroPadButton::Left),
+        "Right" => Some(RetroPadButton::Right),
+        "A" => Some(RetroPadButton::A),
+        "X" => Some(RetroPadButton::X),
+        "L" => Some(RetroPadButton::L),
+        "R" => Some(RetroPadButton::R),
+        "L2" => Some(RetroPadButton::L2),
+        "R2" => Some(RetroPadButton::R2),
+        "L3" => Some(RetroPadButton::L3),
+        "R3" => Some(RetroPadButton::R3),
+        _ => None,
+    }
+}
 
-pub(crate) fn get_mapping(device: &Device) -> Option<MappingFn> {
-    let vid = device.vendor_id();
-    let pid = device.product_id();
-    match device.name() {
-        Some(name) => trace!("Input device: '{}'", name),
-        None => trace!("Input device: UNNAMED"),
+fn button(value: Option<&toml::Value>) -> Option<RetroPadButton> {
+    let name = value?.as_str()?;
+    let button = button_from_str(name);
+    if button.is_none() {
+        warn!("Unknown RetroPad button '{}'", name);
     }
-    trace!("Input device: {:#04x}:{:#04x}", vid, pid);
+    button
+}
 
-    match (vid, pid) {
-        (0x45e, 0x2e0) => Some(map_8bitdo),
-        (0x20d6, 0xa711) => Some(map_switchwired),
-        _ => None,
+/// Change to the RetroPad or keyboard state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Input {
+    Button(RetroPadButton, i16),
+    /// Analog stick axis (index, id, value) using the libretro numbering
+    Analog(u32, u32, i16),
+    /// Keyboard key (RETROK code) pressed or released
+    Key(u32, bool),
+}
+
+// Scale an axis value to the full i16 range used by libretro
+fn scale_axis(value: i32, min: i32, max: i32, invert: bool) -> i16 {
+    if max <= min {
+        return 0;
     }
+    let (value, min, max) = (
+        i64::from(value.clamp(min, max)),
+        i64::from(min),
+        i64::from(max),
+    );
+    let scaled = (value - min) * 65535 / (max - min) - 32768;
+    let scaled = if invert { -1 - scaled } else { scaled };
+    scaled.clamp(i16::MIN.into(), i16::MAX.into()) as i16
 }
 
-fn map_switchwired(event: InputEvent) -> Vec<(RetroPadButton, i16)> {
-    let mut result = Vec::new();
-    if event.is_type(&EventType::EV_KEY) {
-        let id = match event.event_code {
-            EventCode::EV_KEY(key) => match key {
-                EV_KEY::BTN_C => Some(RetroPadButton::A),
-                EV_KEY::BTN_EAST => Some(RetroPadButton::B),
-                EV_KEY::BTN_NORTH => Some(RetroPadButton::X),
-                EV_KEY::BTN_SOUTH => Some(RetroPadButton::Y),
-                EV_KEY::BTN_Z => Some(RetroPadButton::R),
-                EV_KEY::BTN_TR => Some(RetroPadButton::R2),
-                EV_KEY::BTN_START => Some(RetroPadButton::R3),
-                EV_KEY::BTN_WEST => Some(RetroPadButton::L),
-                EV_KEY::BTN_TL => Some(RetroPadButton::L2),
-                EV_KEY::BTN_SELECT => Some(RetroPadButton::L3),
-                EV_KEY::BTN_TL2 => Some(RetroPadButton::Select),
-                EV_KEY::BTN_TR2 => Some(RetroPadButton::Start),
-                EV_KEY::BTN_THUMBL => Some(RetroPadButton::Select),
-                EV_KEY::BTN_MODE => Some(RetroPadButton::Start),
-                _ => {
-                    warn!("Unexpected key: {:?}", key);
-                    None
-                }
-            },
+enum Layer {
+    /// Keys mapped directly to buttons
+    Buttons(Vec<(EventCode, RetroPadButton)>),
+    /// Absolute axis as a pair of buttons, pressed when the value is at or
+    /// beyond the thresholds
+    Axis {
+        code: EventCode,
+        negative: RetroPadButton,
+        positive: RetroPadButton,
+        low: i32,
+        high: i32,
+    },
+    /// Absolute axis as an analog stick axis, the range defaults to the
+    /// range the device reports
+    Analog {
+        code: EventCode,
+        index: u32,
+        id: u32,
+        range: Option<(i32, i32)>,
+        invert: bool,
+    },
+}
+
+impl Layer {
+    fn parse_buttons(buttons: &toml::Value) -> Option<Self> {
+        let table = buttons.as_table()?;
+        let mut keys = Vec::new();
+        for (k, v) in table {
+            match (EventCode::from_str(&EventType::EV_KEY, k), button(Some(v))) {
+                (Some(code), Some(b)) => keys.push((code, b)),
+                (None, _) => warn!("Unknown key '{}'", k),
+                (_, None) => {}
+            }
+        }
+        Some(Layer::Buttons(keys))
+    }
+
+    fn parse_axis(axis: &toml::Value) -> Option<Self> {
+        let name = axis.get("code").and_then(|c| c.as_str())?;
+        let code = match EventCode::from_str(&EventType::EV_ABS, name) {
+            Some(c) => c,
+            None => {
+                warn!("Unknown axis '{}'", name);
+                return None;
+            }
+        };
+        let threshold = |key: &str, default: i32| {
+            axis.get(key)
+                .and_then(|t| t.as_integer())
+                .map_or(default, |t| t as i32)
+        };
+
+        Some(Layer::Axis {
+            code,
+            negative: button(axis.get("negative"))?,
+            positive: button(axis.get("positive"))?,
+            low: threshold("low", -1),
+            high: threshold("high", 1),
+        })
+    }
+
+    fn parse_analog(analog: &toml::Value) -> Option<Self> {
+        let name = analog.get("code").and_then(|c| c.as_str())?;
+        let code = match EventCode::from_str(&EventType::EV_ABS, name) {
+            Some(c) => c,
+            None => {
+                warn!("Unknown axis '{}'", name);
+                return None;
+            }
+        };
+        let index = match analog.get("stick").and_then(|s| s.as_str()) {
+            Some("left") => RETRO_DEVICE_INDEX_ANALOG_LEFT,
+            Some("right") => RETRO_DEVICE_INDEX_ANALOG_RIGHT,
             _ => {
-                warn!("Key event with mismatched code: {:?}", event);
-                None
+                warn!("Analog '{}' needs a stick of \"left\" or \"right\"", name);
+                return None;
             }
         };
-        let value = match event.value.try_into() {
-            Ok(v) => Some(v),
-            Err(_) => {
-                warn!("Input value out of range");
-                None
+        let id = match analog.get("axis").and_then(|a| a.as_str()) {
+            Some("x") => RETRO_DEVICE_ID_ANALOG_X,
+            Some("y") => RETRO_DEVICE_ID_ANALOG_Y,
+            _ => {
+                warn!("Analog '{}' needs an axis of \"x\" or \"y\"", name);
+                return None;
             }
         };
-        if let (Some(id), Some(val)) = (id, value) {
-            result.push((id, val));
-        }
-    } else if event.is_type(&EventType::EV_ABS) {
-        match event.event_code {
-            EventCode::EV_ABS(abs) => match abs {
-                EV_ABS::ABS_HAT0Y => match event.value {
-                    -1 => {
-                        result.push((RetroPadButton::Up, 1));
-                        result.push((RetroPadButton::Down, 0));
-                    }
-                    0 => {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 0));
-                    }
-                    1 => {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 1));
-                    }
-                    _ => {
-                        warn!("Unexpected axis value: {}", event.value);
-                    }
-                },
-                EV_ABS::ABS_HAT0X => match event.value {
-                    -1 => {
-                        result.push((RetroPadButton::Left, 1));
-                        result.push((RetroPadButton::Right, 0));
-                    }
-                    0 => {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 0));
-                    }
-                    1 => {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 1));
-                    }
-                    _ => {
-                        warn!("Unexpected axis value: {}", event.value);
-                    }
-                },
-                EV_ABS::ABS_X => {
-                    // Axis is from 0 to 255
-                    let upper_bits = (event.value >> 6) & 0x3;
-                    if upper_bits == 0 {
-                        result.push((RetroPadButton::Left, 1));
-                        result.push((RetroPadButton::Right, 0));
-                    } else if upper_bits == 3 {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 1));
-                    } else {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 0));
-                    }
-                }
-                EV_ABS::ABS_Y => {
-                    let upper_bits = (event.value >> 6) & 0x3;
-                    if upper_bits == 0 {
-                        result.push((RetroPadButton::Up, 1));
-                        result.push((RetroPadButton::Down, 0));
-                    } else if upper_bits == 3 {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 1));
-                    } else {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 0));
-                    }
+        let limit = |key: &str| analog.get(key).and_then(|t| t.as_integer());
+        let range = match (limit("min"), limit("max")) {
+            (Some(min), Some(max)) => Some((min as i32, max as i32)),
+            _ => None,
+        };
+
+        Some(Layer::Analog {
+            code,
+            index,
+            id,
+            range,
+            invert: analog
+                .get("invert")
+                .and_then(|i| i.as_bool())
+                .unwrap_or(false),
+        })
+    }
+
+    // Add the RetroPad changes for an event, returns false if this layer
+    // doesn't use the event
+    fn map(&self, device: &Device, event: &InputEvent, result: &mut Vec<Input>) -> bool {
+        match self {
+            Layer::Buttons(keys) => match keys.iter().find(|(c, _)| *c == event.event_code) {
+                Some((_, b)) => {
+                    // Treat autorepeat (2) as held
+                    result.push(Input::Button(*b, i16::from(event.value != 0)));
+                    true
                 }
-                EV_ABS::ABS_Z => {
-                    // Z-Axis unused
+                None => false,
+            },
+            Layer::Axis {
+                code,
+                negative,
+                positive,
+                low,
+                high,
+            } => {
+                if *code == event.event_code {
+                    result.push(Input::Button(*negative, i16::from(event.value <= *low)));
+                    result.push(Input::Button(*positive, i16::from(event.value >= *high)));
+                    true
+                } else {
+                    false
                 }
-                EV_ABS::ABS_RZ => {
-                    // RZ-Axis unused
+            }
+            Layer::Analog {
+                code,
+                index,
+                id,
+                range,
+                invert,
+            } => {
+                if *code != event.event_code {
+                    return false;
                 }
-                _ => {
-                    warn!("Unexpected axis event: {:?}", event);
+                let range = range.or_else(|| {
+                    device
+                        .abs_info(code)
+                        .map(|info| (info.minimum, info.maximum))
+                });
+                match range {
+                    Some((min, max)) => {
+                        let value = scale_axis(event.value, min, max, *invert);
+                        result.push(Input::Analog(*index, *id, value));
+                    }
+                    None => warn!("No range for analog axis {}", code),
                 }
-            },
-            _ => {
-                warn!("Key event with mismatched code: {:?}", event);
+                true
             }
         }
-    } else if event.is_type(&EventType::EV_SYN) || event.is_type(&EventType::EV_MSC) {
-        // SYN/MSC unused
-    } else {
-        warn!("Event: {:?}", event);
     }
-    result
 }
 
-fn map_8bitdo(event: InputEvent) -> Vec<(RetroPadButton, i16)> {
-    let mut result = Vec::new();
-    if event.is_type(&EventType::EV_KEY) {
-        let id = match event.event_code {
-            EventCode::EV_KEY(key) => match key {
-                EV_KEY::BTN_TR => Some(RetroPadButton::Start),
-                EV_KEY::BTN_TL => Some(RetroPadButton::Select),
-                EV_KEY::BTN_EAST => Some(RetroPadButton::A),
-                EV_KEY::BTN_SOUTH => Some(RetroPadButton::B),
-                EV_KEY::BTN_WEST => Some(RetroPadButton::L),
-                EV_KEY::BTN_Z => Some(RetroPadButton::R),
-                EV_KEY::BTN_NORTH => Some(RetroPadButton::X),
-                EV_KEY::BTN_C => Some(RetroPadButton::Y),
-                _ => {
-                    warn!("Unexpected key: {:?}", key);
-                    None
-                }
-            },
-            _ => {
-                warn!("Key event with mismatched code: {:?}", event);
-                None
-            }
+/// Mapping for one physical device
+pub(crate) struct DeviceMapping {
+    vendor: u16,
+    product: u16,
+    // Distinguishes devices sharing IDs, e.g. a controller and its IMU
+    name: Option<String>,
+    layers: Vec<Layer>,
+}
+
+impl DeviceMapping {
+    fn parse(device: &toml::Value) -> Option<Self> {
+        let id = |key: &str| {
+            device
+                .get(key)
+                .and_then(|i| i.as_integer())
+                .and_then(|i| u16::try_from(i).ok())
         };
-        let value = match event.value.try_into() {
-            Ok(v) => Some(v),
-            Err(_) => {
-                warn!("Input value out of range");
-                None
+        let mut layers = Vec::new();
+        if let Some(buttons) = device.get("buttons") {
+            layers.extend(Layer::parse_buttons(buttons));
+        }
+        if let Some(axes) = device.get("axis").and_then(|a| a.as_array()) {
+            layers.extend(axes.iter().filter_map(Layer::parse_axis));
+        }
+        if let Some(analog) = device.get("analog").and_then(|a| a.as_array()) {
+            layers.extend(analog.iter().filter_map(Layer::parse_analog));
+        }
+
+        Some(DeviceMapping {
+            vendor: id("vendor")?,
+            product: id("product")?,
+            name: device
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(String::from),
+            layers,
+        })
+    }
+
+    /// Whether any axis of the device is mapped to an analog stick
+    pub fn has_analog(&self) -> bool {
+        self.layers
+            .iter()
+            .any(|l| matches!(l, Layer::Analog { .. }))
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        device.vendor_id() == self.vendor
+            && device.product_id() == self.product
+            && match &self.name {
+                Some(n) => device.name() == Some(n.as_str()),
+                None => true,
+            }
+    }
+
+    /// Get the RetroPad changes for an event from the device, from all
+    /// layers
+    pub fn map(&self, device: &Device, event: InputEvent) -> Vec<Input> {
+        let mut result = Vec::new();
+        let mut used = false;
+        for layer in &self.layers {
+            used |= layer.map(device, &event, &mut result);
+        }
+        if !used && (event.is_type(&EventType::EV_KEY) || event.is_type(&EventType::EV_ABS)) {
+            trace!("Unmapped event: {:?}", event);
+        }
+        result
+    }
+}
+
+/// A logical RetroPad made from one or more devices
+pub(crate) struct Mapping {
+    pub name: String,
+    pub devices: Vec<DeviceMapping>,
+}
+
+impl Mapping {
+    fn parse(source: &str, contents: &str) -> Option<Self> {
+        let value = match contents.parse::<toml::Value>() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Invalid mapping '{}': {}", source, e);
+                return None;
             }
         };
-        if let (Some(id), Some(val)) = (id, value) {
-            result.push((id, val));
+        let devices: Vec<DeviceMapping> = value
+            .get("device")
+            .and_then(|d| d.as_array())
+            .map(|d| d.iter().filter_map(DeviceMapping::parse).collect())
+            .unwrap_or_default();
+        if devices.is_empty() {
+            warn!("Mapping '{}' has no valid devices", source);
+            return None;
         }
-    } else if event.is_type(&EventType::EV_ABS) {
-        match event.event_code {
-            EventCode::EV_ABS(abs) => match abs {
-                EV_ABS::ABS_Y => match event.value {
-                    0 => {
-                        result.push((RetroPadButton::Up, 1));
-                        result.push((RetroPadButton::Down, 0));
-                    }
-                    32768 => {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 0));
-                    }
-                    65535 => {
-                        result.push((RetroPadButton::Up, 0));
-                        result.push((RetroPadButton::Down, 1));
-                    }
-                    _ => {
-                        warn!("Unexpected axis value: {}", event.value);
-                    }
-                },
-                EV_ABS::ABS_X => match event.value {
-                    0 => {
-                        result.push((RetroPadButton::Left, 1));
-                        result.push((RetroPadButton::Right, 0));
-                    }
-                    32768 => {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 0));
-                    }
-                    65535 => {
-                        result.push((RetroPadButton::Left, 0));
-                        result.push((RetroPadButton::Right, 1));
-                    }
-                    _ => {
-                        warn!("Unexpected axis value: {}", event.value);
+        let name = value.get("name").and_then(|n| n.as_str()).unwrap_or(source);
+
+        Some(Mapping {
+            name: String::from(name),
+            devices,
+        })
+    }
+}
+
+/// Load the mappings from the mapping directory followed by the built in
+/// mappings, the first mapping to match a device is used.
+pub(crate) fn load_mappings(dir: &Path) -> Vec<Mapping> {
+    let mut mappings = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    if let Some(m) = Mapping::parse(&path.display().to_string(), &contents) {
+                        info!("Loaded mapping '{}'", m.name);
+                        mappings.push(m);
                     }
-                },
-                _ => {
-                    warn!("Unexpected axis event: {:?}", event);
                 }
-            },
-            _ => {
-                warn!("Key event with mismatched code: {:?}", event);
+                Err(e) => warn!("Failed to read mapping '{}': {}", path.display(), e),
             }
         }
-    } else if event.is_type(&EventType::EV_SYN) || event.is_type(&EventType::EV_MSC) {
-        // SYN/MSC unused
-    } else {
-        match event.event_type() {
-            Some(t) => warn!("Event type '{}' unexpected", t),
-            None => warn!("Event with no type: {:?}", event),
-        }
     }
-    result
+    for (name, contents) in MAPPINGS {
+        mappings.extend(Mapping::parse(name, contents));
+    }
+    debug!("{} controller mappings", mappings.len());
+    mappings
 }
 
-pub(crate) fn map_empty(_: InputEvent) -> Vec<(RetroPadButton, i16)> {
-    Vec::with_capacity(0)
+#[cfg(test)]
+mod tests {
+    use super::scale_axis;
+
+    #[test]
+    fn axis_scaling() {
+        assert_eq!(scale_axis(0, 0, 255, false), i16::MIN);
+        assert_eq!(scale_axis(255, 0, 255, false), i16::MAX);
+        assert_eq!(scale_axis(0, -32768, 32767, false), 0);
+        assert_eq!(scale_axis(-40000, -32768, 32767, true), i16::MAX);
+        assert_eq!(scale_axis(5, 10, 10, false), 0);
+    }
 }
@@ -0,0 +1,121 @@
+use evdev_rs::enums::{EventCode, EV_FF};
+use evdev_rs::{Device, DeviceWrapper};
+use log::{debug, warn};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+const EV_FF_TYPE: u16 = 0x15;
+const FF_RUMBLE_TYPE: u16 = 0x50;
+
+// _IOW('E', 0x80, struct ff_effect) and _IOW('E', 0x81, int)
+const fn iow(nr: u64, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | ((b'E' as u64) << 8) | nr
+}
+const EVIOCSFF: u64 = iow(0x80, std::mem::size_of::<libc::ff_effect>());
+const EVIOCRMFF: u64 = iow(0x81, std::mem::size_of::<libc::c_int>());
+
+/// Rumble through evdev force feedback, on devices that support FF_RUMBLE.
+/// One effect is uploaded and updated with the strength of each motor.
+pub(crate) struct Rumble {
+    // Opened separately for writing, as input devices are only read
+    file: File,
+    effect: i16,
+    playing: bool,
+}
+
+impl Rumble {
+    /// Set up rumble for a device, `None` if it can't rumble
+    pub fn open(path: &Path, d: &Device) -> Option<Self> {
+        if !d.has(&EventCode::EV_FF(EV_FF::FF_RUMBLE)) {
+            return None;
+        }
+        let file = match OpenOptions::new().write(true).open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Can't open '{}' for rumble: {}", path.display(), e);
+                return None;
+            }
+        };
+        let mut rumble = Rumble {
+            file,
+            // New effects are uploaded with an ID of -1
+            effect: -1,
+            playing: false,
+        };
+        if !rumble.upload(0, 0) {
+            return None;
+        }
+        debug!("Rumble supported by '{}'", path.display());
+        Some(rumble)
+    }
+
+    fn upload(&mut self, strong: u16, weak: u16) -> bool {
+        // Safe as all zeros is a valid ff_effect
+        let mut effect: libc::ff_effect = unsafe { std::mem::zeroed() };
+        effect.type_ = FF_RUMBLE_TYPE;
+        effect.id = self.effect;
+        // Length of zero plays until stopped
+        effect.replay.length = 0;
+        let magnitudes = libc::ff_rumble_effect {
+            strong_magnitude: strong,
+            weak_magnitude: weak,
+        };
+        // Safe as the rumble effect is the start of the union
+        unsafe {
+            let u = &mut effect.u as *mut _ as *mut libc::ff_rumble_effect;
+            *u = magnitudes;
+        }
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), EVIOCSFF as _, &mut effect) };
+        if ret < 0 {
+            warn!(
+                "Failed to upload rumble effect: {}",
+                std::io::Error::last_os_error()
+            );
+            false
+        } else {
+            self.effect = effect.id;
+            true
+        }
+    }
+
+    fn play(&mut self, play: bool) {
+        if play == self.playing {
+            return;
+        }
+        let event = libc::input_event {
+            time: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            type_: EV_FF_TYPE,
+            code: self.effect as u16,
+            value: i32::from(play),
+        };
+        let size = std::mem::size_of::<libc::input_event>();
+        // Safe as input_event is plain data
+        let bytes = unsafe { std::slice::from_raw_parts(&event as *const _ as *const u8, size) };
+        match self.file.write_all(bytes) {
+            Ok(_) => self.playing = play,
+            Err(e) => warn!("Failed to play rumble effect: {}", e),
+        }
+    }
+
+    /// Set the strength of each motor, stopping if both are zero
+    pub fn set(&mut self, strong: u16, weak: u16) {
+        if strong == 0 && weak == 0 {
+            self.play(false);
+        } else if self.upload(strong, weak) {
+            self.play(true);
+        }
+    }
+}
+
+impl Drop for Rumble {
+    fn drop(&mut self) {
+        // Removing the effect also stops it
+        let id = libc::c_int::from(self.effect);
+        unsafe { libc::ioctl(self.file.as_raw_fd(), EVIOCRMFF as _, id) };
+    }
+}
@@ -0,0 +1,115 @@
+use log::warn;
+
+/// Aspect ratio to show a game at, from its metadata. Without one, games
+/// are shown with square pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AspectRatio {
+    /// Whatever the core reports in its geometry
+    Core,
+    /// Width divided by height
+    Ratio(f32),
+}
+
+impl AspectRatio {
+    /// Parse `aspect_ratio` from game metadata, either a number, a ratio
+    /// such as "4:3" or "core"
+    pub fn parse(value: &toml::Value) -> Option<Self> {
+        let ratio = match value {
+            toml::Value::Float(f) => Some(*f as f32),
+            toml::Value::Integer(i) => Some(*i as f32),
+            toml::Value::String(s) if s == "core" => return Some(AspectRatio::Core),
+            toml::Value::String(s) => s.split_once(':').and_then(|(w, h)| {
+                let w: f32 = w.trim().parse().ok()?;
+                let h: f32 = h.trim().parse().ok()?;
+                Some(w / h)
+            }),
+            _ => None,
+        };
+        match ratio {
+            Some(r) if r.is_finite() && r > 0.0 => Some(AspectRatio::Ratio(r)),
+            _ => {
+                warn!("Invalid aspect ratio: {}", value);
+                None
+            }
+        }
+    }
+
+    /// Ratio to show the game at, given the ratio the core reports, which
+    /// is zero if the core doesn't give one
+    pub fn resolve(&self, core: f32) -> Option<f32> {
+        match self {
+            AspectRatio::Core if core > 0.0 => Some(core),
+            AspectRatio::Core => None,
+            AspectRatio::Ratio(r) => Some(*r),
+        }
+    }
+}
+
+/// How frames from a core are scaled to the screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleMode {
+    /// Centred at their own size and cropped if too big, unless the game
+    /// has an aspect ratio in its metadata when it is shrunk to fit
+    Crop,
+    /// The largest whole multiple of the frame's height that fits
+    Integer,
+    /// As large as fits at the aspect ratio
+    Fit,
+    /// Filling the screen
+    Stretch,
+}
+
+impl ScaleMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "crop" => Some(ScaleMode::Crop),
+            "integer" => Some(ScaleMode::Integer),
+            "fit" => Some(ScaleMode::Fit),
+            "stretch" => Some(ScaleMode::Stretch),
+            _ => {
+                warn!("Invalid scaling mode '{}'", s);
+                None
+            }
+        }
+    }
+
+    /// Next mode, for cycling through them in a menu
+    pub fn next(&self) -> Self {
+        match self {
+            ScaleMode::Crop => ScaleMode::Integer,
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Stretch,
+            ScaleMode::Stretch => ScaleMode::Crop,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScaleMode::Crop => "Crop",
+            ScaleMode::Integer => "Integer",
+            ScaleMode::Fit => "Fit",
+            ScaleMode::Stretch => "Stretch",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AspectRatio, ScaleMode};
+
+    #[test]
+    fn parse_ratios() {
+        let parse = |s: &str| {
+            let v = format!("a = {}", s).parse::<toml::Value>().unwrap();
+            AspectRatio::parse(&v["a"])
+        };
+        assert_eq!(parse("\"4:3\""), Some(AspectRatio::Ratio(4.0 / 3.0)));
+        assert_eq!(parse("1.5"), Some(AspectRatio::Ratio(1.5)));
+        assert_eq!(parse("\"core\""), Some(AspectRatio::Core));
+        assert_eq!(parse("\"4:0\""), None);
+        assert_eq!(parse("\"wide\""), None);
+        assert_eq!(AspectRatio::Core.resolve(0.0), None);
+        assert_eq!(ScaleMode::parse("fit"), Some(ScaleMode::Fit));
+        assert_eq!(ScaleMode::Stretch.next(), ScaleMode::Crop);
+    }
+}
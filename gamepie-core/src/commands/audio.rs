@@ -1,11 +1,41 @@
+use std::path::PathBuf;
+
 /// Commands to control an audio channel
 pub enum AudioCmd {
     /// Start the audio channel at the specified frequency
     Start(i32),
     VolumeUp,
     VolumeDown,
+    /// Set the volume (0-100)
+    SetVolume(u8),
+    /// Set the volume (0-100) without showing it, e.g. to the volume saved
+    /// when gamepie last ran
+    RestoreVolume(u8),
     /// Stop the audio channel
     Stop,
+    /// Play the boot chime, the built in tone or a WAV file, if a game
+    /// hasn't started the channel
+    Chime(Option<PathBuf>),
+    /// Report the queue's fill level after each batch of samples, for cores
+    /// that skip frames when audio is falling behind
+    ReportStatus(bool),
+    /// Minimum latency in milliseconds asked for by the core, 0 for the
+    /// default. The channel is restarted if it is running.
+    MinLatency(u32),
+    /// Write the game's audio, before the volume is applied, to a WAV file.
+    /// Starts with the next game if none is running, and ends when the game
+    /// stops.
+    StartRecord(PathBuf),
+    StopRecord,
+}
+
+/// How full the audio queue is, reported back while the core asks for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioStatus {
+    /// Percentage of the buffer filled
+    pub occupancy: u32,
+    /// The queue is close to running dry
+    pub underrun_likely: bool,
 }
 
 /// The format of a message to the audio channel, which can contain a command
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// What requested feedback, each source has its own configured intensity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedbackSource {
+    Rumble,
+    Menu,
+    Notification,
+}
+
+/// Rumble motors a core can control, the strongest is used for the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RumbleEffect {
+    Strong,
+    Weak,
+}
+
+/// Requests to the feedback thread, which drives whatever actuators are
+/// present.
+pub enum FeedbackMsg {
+    /// Set the rumble strength (0-1) requested by the core, until changed
+    Rumble(RumbleEffect, f32),
+    /// Short pulse on all actuators
+    Pulse(FeedbackSource, Duration),
+    /// Stop all feedback, e.g. when a core is unloaded mid-rumble
+    Stop,
+}
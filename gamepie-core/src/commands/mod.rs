@@ -1,5 +1,7 @@
 mod audio;
+mod feedback;
 mod screen;
 
 pub use audio::*;
+pub use feedback::*;
 pub use screen::*;
@@ -3,9 +3,12 @@ use log::{debug, warn};
 use std::fmt::Display;
 use std::time::{Duration, Instant};
 
+use crate::problem::Severity;
+
 pub enum ScreenMessage {
     VolumeUp(f32),
     VolumeDown(f32),
+    Brightness(f32),
     AudioIssue,
     Unstable,
     VideoIssue,
@@ -17,6 +20,7 @@ impl Display for ScreenMessage {
         match self {
             ScreenMessage::VolumeUp(v) => write!(f, "volume up {:.1}", v),
             ScreenMessage::VolumeDown(v) => write!(f, "volume down {:.1}", v),
+            ScreenMessage::Brightness(b) => write!(f, "brightness {:.2}", b),
             ScreenMessage::AudioIssue => write!(f, "audio problem"),
             ScreenMessage::VideoIssue => write!(f, "video problem"),
             ScreenMessage::Unstable => write!(f, "unstable"),
@@ -30,6 +34,7 @@ pub struct ScreenToast {
     duration: Duration,
     start: Instant,
     colour: Rgb565,
+    severity: Severity,
 }
 
 impl ScreenToast {
@@ -39,6 +44,7 @@ impl ScreenToast {
             duration,
             start: Instant::now(),
             colour,
+            severity: Severity::Info,
         }
     }
 
@@ -48,6 +54,7 @@ impl ScreenToast {
             duration: crate::INFO_DURATION,
             start: Instant::now(),
             colour: crate::INFO_COLOUR,
+            severity: Severity::Info,
         }
     }
 
@@ -57,6 +64,7 @@ impl ScreenToast {
             duration: crate::ERROR_DURATION,
             start: Instant::now(),
             colour: crate::ERROR_COLOUR,
+            severity: Severity::Warning,
         }
     }
 
@@ -73,6 +81,18 @@ impl ScreenToast {
         &self.colour
     }
 
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Text of the message, without the quotes used when logging
+    pub fn text(&self) -> String {
+        match &self.message {
+            ScreenMessage::Message(m) => m.clone(),
+            m => m.to_string(),
+        }
+    }
+
     pub fn log(&self) {
         match &self.message {
             ScreenMessage::VolumeUp(_) => {
@@ -81,6 +101,9 @@ impl ScreenToast {
             ScreenMessage::VolumeDown(_) => {
                 debug!("{}", self);
             }
+            ScreenMessage::Brightness(_) => {
+                debug!("{}", self);
+            }
             ScreenMessage::AudioIssue => {
                 warn!("{}", self);
             }
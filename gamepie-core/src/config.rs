@@ -0,0 +1,1323 @@
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::aspect::ScaleMode;
+use crate::commands::FeedbackSource;
+use crate::dirs::GamepieDirs;
+use crate::lang::Language;
+use crate::problem::Severity;
+use crate::MAPPING_PATH;
+
+/// Name of the configuration file, looked for in the root directory if
+/// another path isn't provided.
+pub const CONFIG_FILE: &str = "gamepie.toml";
+
+#[derive(Clone, Debug)]
+pub struct CoreOptionsConfig {
+    /// Use the shipped option presets rather than the core defaults
+    pub presets: bool,
+}
+
+impl Default for CoreOptionsConfig {
+    fn default() -> Self {
+        CoreOptionsConfig { presets: true }
+    }
+}
+
+impl CoreOptionsConfig {
+    fn parse(&mut self, options: &toml::Value) {
+        if let Some(p) = options.get("presets").and_then(|p| p.as_bool()) {
+            self.presets = p;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SaveStateConfig {
+    /// Maximum memory (bytes) to use for states held in memory
+    pub memory_budget: usize,
+}
+
+impl Default for SaveStateConfig {
+    fn default() -> Self {
+        SaveStateConfig {
+            memory_budget: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl SaveStateConfig {
+    fn parse(&mut self, states: &toml::Value) {
+        match states.get("memory_budget_kb").map(|b| b.as_integer()) {
+            Some(Some(b)) if b >= 0 => self.memory_budget = b as usize * 1024,
+            Some(_) => warn!("Invalid save state memory budget"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartyConfig {
+    /// Time each game is played for before switching to the next
+    pub turn: Duration,
+}
+
+impl Default for PartyConfig {
+    fn default() -> Self {
+        PartyConfig {
+            turn: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl PartyConfig {
+    fn parse(&mut self, party: &toml::Value) {
+        match party.get("minutes").map(|m| m.as_integer()) {
+            Some(Some(m)) if m > 0 => self.turn = Duration::from_secs(m as u64 * 60),
+            Some(_) => warn!("Invalid party turn length"),
+            None => {}
+        }
+    }
+}
+
+/// Sound played once audio is ready at boot
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootChime {
+    Silent,
+    /// The built in tone
+    Tone,
+    /// A WAV file
+    Wav(PathBuf),
+}
+
+/// Where audio is played
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioBackend {
+    /// SDL's audio queue
+    Sdl,
+    /// Written straight to an ALSA device
+    Alsa,
+}
+
+#[derive(Clone, Debug)]
+pub struct AudioConfig {
+    pub chime: BootChime,
+    pub backend: AudioBackend,
+    /// ALSA device to play to, e.g. "default" or "hw:0,0"
+    pub device: String,
+    /// Latency above which a warning is logged while a game runs
+    pub latency_warn: Duration,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            chime: BootChime::Tone,
+            backend: AudioBackend::Sdl,
+            device: String::from("default"),
+            latency_warn: Duration::from_millis(150),
+        }
+    }
+}
+
+impl AudioConfig {
+    fn parse(&mut self, root: &Path, audio: &toml::Value) {
+        match audio.get("backend").and_then(|b| b.as_str()) {
+            Some("sdl") => self.backend = AudioBackend::Sdl,
+            Some("alsa") => self.backend = AudioBackend::Alsa,
+            Some(b) => warn!("Unknown audio backend '{}'", b),
+            None => {}
+        }
+        if let Some(d) = audio.get("device").and_then(|d| d.as_str()) {
+            self.device = String::from(d);
+        }
+        match audio.get("latency_warn_ms").map(|l| l.as_integer()) {
+            Some(Some(l)) if l > 0 => self.latency_warn = Duration::from_millis(l as u64),
+            Some(_) => warn!("Invalid audio latency warning threshold"),
+            None => {}
+        }
+        match audio.get("chime") {
+            Some(toml::Value::Boolean(true)) => self.chime = BootChime::Tone,
+            Some(toml::Value::Boolean(false)) => self.chime = BootChime::Silent,
+            Some(toml::Value::String(s)) => self.chime = BootChime::Wav(root.join(s)),
+            Some(_) => warn!("Invalid boot chime"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StorageConfig {
+    /// Free space (bytes) below which storage is low
+    pub low: u64,
+    /// Delete traces and old timed saves when low
+    pub evict: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            low: 64 * 1024 * 1024,
+            evict: true,
+        }
+    }
+}
+
+impl StorageConfig {
+    fn parse(&mut self, storage: &toml::Value) {
+        match storage.get("low_mb").map(|l| l.as_integer()) {
+            Some(Some(l)) if l >= 0 => self.low = l as u64 * 1024 * 1024,
+            Some(_) => warn!("Invalid low storage threshold"),
+            None => {}
+        }
+        if let Some(e) = storage.get("evict").and_then(|e| e.as_bool()) {
+            self.evict = e;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// Time without a frame from the core before it is taken to have hung
+    pub timeout: Duration,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            enabled: true,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    fn parse(&mut self, watchdog: &toml::Value) {
+        if let Some(e) = watchdog.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        match watchdog.get("timeout_secs").map(|t| t.as_integer()) {
+            Some(Some(t)) if t > 0 => self.timeout = Duration::from_secs(t as u64),
+            Some(_) => warn!("Invalid watchdog timeout"),
+            None => {}
+        }
+    }
+}
+
+/// Events that write the save while a game runs, besides the timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutosaveEvent {
+    /// The pause menu is opened
+    Pause,
+    /// A controller is unplugged
+    Disconnect,
+    /// The battery becomes low
+    LowBattery,
+    /// Back is pressed to leave the game
+    Back,
+}
+
+#[derive(Clone, Debug)]
+pub struct AutosaveConfig {
+    pub on: Vec<AutosaveEvent>,
+    /// Also write a save state for the events
+    pub state: bool,
+}
+
+impl Default for AutosaveConfig {
+    fn default() -> Self {
+        AutosaveConfig {
+            on: vec![
+                AutosaveEvent::Pause,
+                AutosaveEvent::Disconnect,
+                AutosaveEvent::LowBattery,
+                AutosaveEvent::Back,
+            ],
+            state: false,
+        }
+    }
+}
+
+impl AutosaveConfig {
+    fn parse(&mut self, autosave: &toml::Value) {
+        match autosave.get("on").map(|o| o.as_array()) {
+            Some(Some(events)) => {
+                self.on = events
+                    .iter()
+                    .filter_map(|e| match e.as_str() {
+                        Some("pause") => Some(AutosaveEvent::Pause),
+                        Some("disconnect") => Some(AutosaveEvent::Disconnect),
+                        Some("low_battery") => Some(AutosaveEvent::LowBattery),
+                        Some("back") => Some(AutosaveEvent::Back),
+                        _ => {
+                            warn!("Unknown autosave event {}", e);
+                            None
+                        }
+                    })
+                    .collect()
+            }
+            Some(None) => warn!("Invalid autosave events"),
+            None => {}
+        }
+        if let Some(s) = autosave.get("state").and_then(|s| s.as_bool()) {
+            self.state = s;
+        }
+    }
+}
+
+/// Lowest severity sent to each notification sink, None turns a sink off
+#[derive(Clone, Debug)]
+pub struct NotifyConfig {
+    /// Toasts on screen
+    pub screen: Option<Severity>,
+    /// Pulse of the feedback motor, buzzer and LED
+    pub led: Option<Severity>,
+    /// The `notification` MQTT topic
+    pub mqtt: Option<Severity>,
+    /// Lines appended to the notification log
+    pub log: Option<Severity>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            screen: Some(Severity::Info),
+            led: Some(Severity::Warning),
+            mqtt: Some(Severity::Warning),
+            log: None,
+        }
+    }
+}
+
+impl NotifyConfig {
+    fn parse(&mut self, notify: &toml::Value) {
+        for (key, filter) in [
+            ("screen", &mut self.screen),
+            ("led", &mut self.led),
+            ("mqtt", &mut self.mqtt),
+            ("log", &mut self.log),
+        ] {
+            match notify
+                .get(key)
+                .map(|v| v.as_str().and_then(Severity::parse_filter))
+            {
+                Some(Some(f)) => *filter = f,
+                Some(_) => warn!("Invalid notification filter for {}", key),
+                None => {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SensorConfig {
+    /// Whether there is an MPU-6050 style accelerometer and gyroscope
+    pub enabled: bool,
+    /// I2C bus the module is on
+    pub bus: u8,
+    pub address: u16,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            enabled: false,
+            bus: 1,
+            address: 0x68,
+        }
+    }
+}
+
+impl SensorConfig {
+    fn parse(&mut self, sensor: &toml::Value) {
+        if let Some(e) = sensor.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        match sensor.get("bus").map(|b| b.as_integer()) {
+            Some(Some(b)) if (0..=255).contains(&b) => self.bus = b as u8,
+            Some(_) => warn!("Invalid sensor I2C bus"),
+            None => {}
+        }
+        match sensor.get("address").map(|a| a.as_integer()) {
+            Some(Some(a)) if (0x08..=0x77).contains(&a) => self.address = a as u16,
+            Some(_) => warn!("Invalid sensor I2C address"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VideoConfig {
+    /// How games are scaled to the screen, unless their metadata says
+    /// otherwise
+    pub scaling: ScaleMode,
+    pub display: DisplayConfig,
+    /// Show frame stats over games
+    pub hud: bool,
+    /// Most frames skipped for each drawn when a core can't keep up, zero
+    /// to never skip
+    pub max_frameskip: u32,
+}
+
+/// Where frames are shown
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisplayConfig {
+    /// SPI screen driven by fbcp-ili9341, as built for (e.g. the Pirate
+    /// Audio HAT)
+    Spi,
+    /// Linux framebuffer device, e.g. HDMI. Frames are drawn at the size
+    /// given and scaled up by a whole number to fit.
+    Framebuffer {
+        device: PathBuf,
+        width: u16,
+        height: u16,
+    },
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            scaling: ScaleMode::Crop,
+            display: DisplayConfig::Spi,
+            hud: false,
+            max_frameskip: 2,
+        }
+    }
+}
+
+impl VideoConfig {
+    fn parse(&mut self, video: &toml::Value) {
+        if let Some(s) = video.get("scaling").and_then(|s| s.as_str()) {
+            if let Some(mode) = ScaleMode::parse(s) {
+                self.scaling = mode;
+            }
+        }
+        if let Some(hud) = video.get("hud").and_then(|h| h.as_bool()) {
+            self.hud = hud;
+        }
+        match video.get("max_frameskip").map(|f| f.as_integer()) {
+            Some(Some(f)) if (0..=4).contains(&f) => self.max_frameskip = f as u32,
+            Some(_) => warn!("Invalid max_frameskip, must be 0-4"),
+            None => {}
+        }
+        self.parse_display(video);
+    }
+
+    // Also used in safe mode, as nothing can be shown without it
+    fn parse_display(&mut self, video: &toml::Value) {
+        let size = |key: &str, default: u16| match video.get(key).map(|s| s.as_integer()) {
+            Some(Some(s)) if (16..=4096).contains(&s) => s as u16,
+            Some(_) => {
+                warn!("Invalid display {}", key);
+                default
+            }
+            None => default,
+        };
+        match video.get("driver").and_then(|d| d.as_str()) {
+            Some("spi") => self.display = DisplayConfig::Spi,
+            Some("fbdev") => {
+                let device = video
+                    .get("device")
+                    .and_then(|d| d.as_str())
+                    .unwrap_or("/dev/fb0");
+                self.display = DisplayConfig::Framebuffer {
+                    device: PathBuf::from(device),
+                    width: size("width", 240),
+                    height: size("height", 240),
+                };
+            }
+            Some(d) => warn!("Unknown display driver '{}'", d),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedbackConfig {
+    /// Vibration motor pin, driven with PWM for strength
+    pub motor: Option<u8>,
+    /// Piezo buzzer pin
+    pub piezo: Option<u8>,
+    /// LED pin, flashed for pulses
+    pub led: Option<u8>,
+    /// Intensity (0-1) of each source
+    pub rumble: f32,
+    pub menu: f32,
+    pub notification: f32,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            motor: None,
+            piezo: None,
+            led: None,
+            rumble: 1.0,
+            menu: 0.3,
+            notification: 1.0,
+        }
+    }
+}
+
+impl FeedbackConfig {
+    fn parse(&mut self, feedback: &toml::Value) {
+        let pin = |key: &str| match feedback.get(key).map(|p| p.as_integer()) {
+            Some(Some(p)) if (0..=27).contains(&p) => Some(p as u8),
+            Some(_) => {
+                warn!("Invalid feedback pin '{}'", key);
+                None
+            }
+            None => None,
+        };
+        self.motor = pin("motor");
+        self.piezo = pin("piezo");
+        self.led = pin("led");
+
+        let intensity = |key: &str, default: f32| match feedback.get(key) {
+            Some(toml::Value::Float(f)) => (*f as f32).clamp(0.0, 1.0),
+            Some(toml::Value::Integer(i)) => (*i as f32).clamp(0.0, 1.0),
+            Some(_) => {
+                warn!("Invalid feedback intensity '{}'", key);
+                default
+            }
+            None => default,
+        };
+        self.rumble = intensity("rumble", self.rumble);
+        self.menu = intensity("menu", self.menu);
+        self.notification = intensity("notification", self.notification);
+    }
+
+    /// Configured intensity for a source
+    pub fn intensity(&self, source: FeedbackSource) -> f32 {
+        match source {
+            FeedbackSource::Rumble => self.rumble,
+            FeedbackSource::Menu => self.menu,
+            FeedbackSource::Notification => self.notification,
+        }
+    }
+
+    /// Check if there are any actuators to drive
+    pub fn any(&self) -> bool {
+        self.motor.is_some() || self.piezo.is_some() || self.led.is_some()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RewindConfig {
+    pub enabled: bool,
+    /// Maximum memory (bytes) to use for the rewind buffer
+    pub memory_budget: usize,
+    /// Frames between captures, captures are made less often if the core
+    /// takes too long to save its state
+    pub interval: u32,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig {
+            enabled: false,
+            memory_budget: 16 * 1024 * 1024,
+            interval: 2,
+        }
+    }
+}
+
+impl RewindConfig {
+    fn parse(&mut self, rewind: &toml::Value) {
+        if let Some(e) = rewind.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        match rewind.get("memory_budget_kb").map(|b| b.as_integer()) {
+            Some(Some(b)) if b >= 0 => self.memory_budget = b as usize * 1024,
+            Some(_) => warn!("Invalid rewind memory budget"),
+            None => {}
+        }
+        match rewind.get("interval_frames").map(|i| i.as_integer()) {
+            Some(Some(i)) if i > 0 && i <= u32::MAX.into() => self.interval = i as u32,
+            Some(_) => warn!("Invalid rewind interval"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FastForwardConfig {
+    /// Most frames run for each one shown while fast-forwarding
+    pub max_speed: u32,
+}
+
+impl Default for FastForwardConfig {
+    fn default() -> Self {
+        FastForwardConfig { max_speed: 4 }
+    }
+}
+
+impl FastForwardConfig {
+    fn parse(&mut self, fast_forward: &toml::Value) {
+        match fast_forward.get("max_speed").map(|s| s.as_integer()) {
+            Some(Some(s)) if (2..=16).contains(&s) => self.max_speed = s as u32,
+            Some(_) => warn!("Invalid fast-forward speed, must be 2-16"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ThermalConfig {
+    pub enabled: bool,
+    /// Temperature (C) to start skipping frames at, before the SoC reaches
+    /// its own throttling temperature
+    pub frameskip_temp: f32,
+    /// Temperature (C) to stop skipping frames at
+    pub resume_temp: f32,
+    /// Frames skipped for each frame drawn while hot
+    pub frameskip: u32,
+    /// Fast-forward speed limit while hot
+    pub fast_forward_max: u32,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            enabled: true,
+            frameskip_temp: 75.0,
+            resume_temp: 70.0,
+            frameskip: 1,
+            fast_forward_max: 2,
+        }
+    }
+}
+
+impl ThermalConfig {
+    fn parse(&mut self, thermal: &toml::Value) {
+        if let Some(e) = thermal.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        if let Some(t) = thermal.get("frameskip_temp").and_then(|t| t.as_float()) {
+            self.frameskip_temp = t as f32;
+        }
+        if let Some(t) = thermal.get("resume_temp").and_then(|t| t.as_float()) {
+            self.resume_temp = t as f32;
+        }
+        if self.resume_temp > self.frameskip_temp {
+            warn!("Thermal resume temperature above frameskip temperature");
+            self.resume_temp = self.frameskip_temp;
+        }
+        match thermal.get("frameskip").map(|f| f.as_integer()) {
+            Some(Some(f)) if f > 0 && f <= 4 => self.frameskip = f as u32,
+            Some(_) => warn!("Invalid frameskip, must be 1-4"),
+            None => {}
+        }
+        match thermal.get("fast_forward_max").map(|s| s.as_integer()) {
+            Some(Some(s)) if (1..=16).contains(&s) => self.fast_forward_max = s as u32,
+            Some(_) => warn!("Invalid hot fast-forward speed, must be 1-16"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CpuConfig {
+    /// Change the CPU governor between games and menus
+    pub enabled: bool,
+    /// Least performance level a core must give for its game to use the
+    /// game governor, 0 for every game
+    pub performance_level: u32,
+    /// Governor while a demanding game runs
+    pub game_governor: String,
+    /// Governor in the menus, and for other games
+    pub menu_governor: String,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        CpuConfig {
+            enabled: true,
+            performance_level: 4,
+            game_governor: String::from("performance"),
+            menu_governor: String::from("ondemand"),
+        }
+    }
+}
+
+impl CpuConfig {
+    fn parse(&mut self, cpu: &toml::Value) {
+        if let Some(e) = cpu.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        match cpu.get("performance_level").map(|l| l.as_integer()) {
+            Some(Some(l)) if l >= 0 && l <= u32::MAX.into() => self.performance_level = l as u32,
+            Some(_) => warn!("Invalid CPU performance level"),
+            None => {}
+        }
+        if let Some(g) = cpu.get("game_governor").and_then(|g| g.as_str()) {
+            self.game_governor = String::from(g);
+        }
+        if let Some(g) = cpu.get("menu_governor").and_then(|g| g.as_str()) {
+            self.menu_governor = String::from(g);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MenuConfig {
+    /// Show different dumps of the same game as one entry
+    pub dedupe: bool,
+    /// Regions in order of preference, for picking the version to start
+    pub regions: Vec<String>,
+    /// Show a QR code of the error on the error screen
+    pub error_qr: bool,
+    /// Group the game list by system, switched with Left/Right
+    pub group_systems: bool,
+    /// Language of gamepie's own text, e.g. errors
+    pub language: Language,
+    /// Find the games again when the ROM directories change
+    pub watch: bool,
+}
+
+impl Default for MenuConfig {
+    fn default() -> Self {
+        MenuConfig {
+            dedupe: true,
+            regions: Vec::new(),
+            error_qr: false,
+            group_systems: false,
+            language: Language::English,
+            watch: true,
+        }
+    }
+}
+
+impl MenuConfig {
+    fn parse(&mut self, menu: &toml::Value) {
+        if let Some(d) = menu.get("dedupe").and_then(|d| d.as_bool()) {
+            self.dedupe = d;
+        }
+        if let Some(q) = menu.get("error_qr").and_then(|q| q.as_bool()) {
+            self.error_qr = q;
+        }
+        if let Some(g) = menu.get("group_systems").and_then(|g| g.as_bool()) {
+            self.group_systems = g;
+        }
+        if let Some(w) = menu.get("watch").and_then(|w| w.as_bool()) {
+            self.watch = w;
+        }
+        if let Some(l) = menu.get("language").and_then(|l| l.as_str()) {
+            self.language = Language::parse(l).unwrap_or(self.language);
+        }
+        if let Some(r) = menu.get("regions").and_then(|r| r.as_array()) {
+            self.regions = r
+                .iter()
+                .filter_map(|r| r.as_str())
+                .map(String::from)
+                .collect();
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InputConfig {
+    /// How often the input thread checks the controller for events
+    pub poll_interval: Duration,
+    /// Directory of user controller mappings
+    pub mappings: PathBuf,
+}
+
+impl InputConfig {
+    fn new(root: &Path) -> Self {
+        InputConfig {
+            poll_interval: Duration::from_millis(4),
+            mappings: root.join(MAPPING_PATH),
+        }
+    }
+
+    fn parse(&mut self, root: &Path, input: &toml::Value) {
+        match input.get("poll_interval_ms").map(|p| p.as_integer()) {
+            Some(Some(p)) if (1..=100).contains(&p) => {
+                self.poll_interval = Duration::from_millis(p as u64)
+            }
+            Some(_) => warn!("Invalid input poll interval"),
+            None => {}
+        }
+        if let Some(p) = input.get("mappings").and_then(|p| p.as_str()) {
+            self.mappings = GamepieDirs::resolve(root, p);
+        }
+    }
+}
+
+/// Where game metadata is scraped from
+#[derive(Clone, Debug)]
+pub struct ScraperConfig {
+    /// Name of the backend to use
+    pub backend: String,
+    /// DAT files for the offline backend
+    pub dats: Vec<PathBuf>,
+    /// Minimum time between lookups, on top of any limit of the backend
+    pub interval: Duration,
+    /// Account for the online backends, ScreenScraper's is optional
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Developer credentials ScreenScraper gives each application
+    pub dev_id: Option<String>,
+    pub dev_password: Option<String>,
+    /// TheGamesDB API key
+    pub api_key: Option<String>,
+}
+
+impl Default for ScraperConfig {
+    fn default() -> Self {
+        ScraperConfig {
+            backend: String::from("dat"),
+            dats: Vec::new(),
+            interval: Duration::ZERO,
+            user: None,
+            password: None,
+            dev_id: None,
+            dev_password: None,
+            api_key: None,
+        }
+    }
+}
+
+impl ScraperConfig {
+    fn parse(&mut self, root: &Path, scraper: &toml::Value) {
+        if let Some(b) = scraper.get("backend").and_then(|b| b.as_str()) {
+            self.backend = b.to_lowercase();
+        }
+        match scraper.get("dats") {
+            Some(toml::Value::String(p)) => self.dats = vec![GamepieDirs::resolve(root, p)],
+            Some(toml::Value::Array(a)) => {
+                self.dats = a
+                    .iter()
+                    .filter_map(|p| p.as_str())
+                    .map(|p| GamepieDirs::resolve(root, p))
+                    .collect();
+            }
+            Some(v) => warn!("Invalid DAT files: {}", v),
+            None => {}
+        }
+        match scraper.get("interval_ms").map(|i| i.as_integer()) {
+            Some(Some(i)) if i >= 0 => self.interval = Duration::from_millis(i as u64),
+            Some(_) => warn!("Invalid scraper interval"),
+            None => {}
+        }
+        for (key, value) in [
+            ("user", &mut self.user),
+            ("password", &mut self.password),
+            ("dev_id", &mut self.dev_id),
+            ("dev_password", &mut self.dev_password),
+            ("api_key", &mut self.api_key),
+        ] {
+            match scraper.get(key).map(|v| v.as_str()) {
+                Some(Some(s)) => *value = Some(s.to_string()),
+                Some(None) => warn!("Invalid scraper {}", key),
+                None => {}
+            }
+        }
+    }
+}
+
+/// What turning the rotary encoder adjusts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncoderControl {
+    Volume,
+    Brightness,
+}
+
+/// Rotary encoder wired to two GPIO pins (BCM numbering)
+#[derive(Clone, Debug)]
+pub struct EncoderConfig {
+    pub a: u8,
+    pub b: u8,
+    pub control: EncoderControl,
+}
+
+impl EncoderConfig {
+    fn parse(encoder: &toml::Value) -> Option<Self> {
+        let pin = |name: &str| match encoder.get(name).and_then(|p| p.as_integer()) {
+            Some(p) if (0..=27).contains(&p) => Some(p as u8),
+            Some(p) => {
+                warn!("Invalid encoder pin '{}' = {}", name, p);
+                None
+            }
+            None => {
+                warn!("Encoder pin '{}' not set", name);
+                None
+            }
+        };
+        let control = match encoder.get("control").and_then(|c| c.as_str()) {
+            Some("volume") | None => EncoderControl::Volume,
+            Some("brightness") => EncoderControl::Brightness,
+            Some(c) => {
+                warn!("Unknown encoder control '{}', using volume", c);
+                EncoderControl::Volume
+            }
+        };
+
+        Some(EncoderConfig {
+            a: pin("a")?,
+            b: pin("b")?,
+            control,
+        })
+    }
+}
+
+/// Low battery signal from a battery monitor, on the pin the screen driver
+/// shows its low battery icon for
+#[derive(Clone, Debug)]
+pub struct LowBatteryConfig {
+    pub pin: u8,
+    /// Whether the pin is high when the battery is low
+    pub active_high: bool,
+    /// Shut down once the game has been saved
+    pub shutdown: bool,
+}
+
+impl Default for LowBatteryConfig {
+    fn default() -> Self {
+        LowBatteryConfig {
+            pin: 26,
+            active_high: false,
+            shutdown: false,
+        }
+    }
+}
+
+impl LowBatteryConfig {
+    fn parse(&mut self, battery: &toml::Value) {
+        match battery.get("pin").map(|p| p.as_integer()) {
+            Some(Some(p)) if (0..=27).contains(&p) => self.pin = p as u8,
+            Some(_) => warn!("Invalid low battery pin"),
+            None => {}
+        }
+        if let Some(a) = battery.get("active_high").and_then(|a| a.as_bool()) {
+            self.active_high = a;
+        }
+        if let Some(s) = battery.get("shutdown").and_then(|s| s.as_bool()) {
+            self.shutdown = s;
+        }
+    }
+}
+
+/// RetroPad buttons, by name, pressed by the A, B, X and Y GPIO buttons in
+/// place of volume and back
+#[derive(Clone, Debug)]
+pub struct GpioPadConfig {
+    pub a: String,
+    pub b: String,
+    pub x: String,
+    pub y: String,
+}
+
+impl Default for GpioPadConfig {
+    fn default() -> Self {
+        GpioPadConfig {
+            a: String::from("A"),
+            b: String::from("B"),
+            x: String::from("Start"),
+            y: String::from("Select"),
+        }
+    }
+}
+
+impl GpioPadConfig {
+    fn parse(&mut self, pad: &toml::Value) {
+        for (key, button) in [
+            ("a", &mut self.a),
+            ("b", &mut self.b),
+            ("x", &mut self.x),
+            ("y", &mut self.y),
+        ] {
+            match pad.get(key).map(|b| b.as_str()) {
+                Some(Some(b)) => *button = String::from(b),
+                Some(None) => warn!("Invalid GPIO pad button for {}", key),
+                None => {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GpioConfig {
+    pub encoder: Option<EncoderConfig>,
+    pub low_battery: Option<LowBatteryConfig>,
+    pub pad: Option<GpioPadConfig>,
+    /// Pin of a photodiode over the latency test's marker, for measuring
+    /// when the screen actually changes
+    pub photodiode: Option<u8>,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        GpioConfig {
+            encoder: None,
+            low_battery: Some(LowBatteryConfig::default()),
+            pad: None,
+            photodiode: None,
+        }
+    }
+}
+
+impl GpioConfig {
+    fn parse(&mut self, gpio: &toml::Value) {
+        if let Some(encoder) = gpio.get("encoder") {
+            self.encoder = EncoderConfig::parse(encoder);
+        }
+        match gpio.get("low_battery") {
+            Some(toml::Value::Boolean(false)) => self.low_battery = None,
+            Some(b @ toml::Value::Table(_)) => {
+                let mut battery = LowBatteryConfig::default();
+                battery.parse(b);
+                self.low_battery = Some(battery);
+            }
+            Some(toml::Value::Boolean(true)) | None => {}
+            Some(_) => warn!("Invalid low battery config"),
+        }
+        match gpio.get("pad") {
+            Some(toml::Value::Boolean(true)) => self.pad = Some(GpioPadConfig::default()),
+            Some(p @ toml::Value::Table(_)) => {
+                let mut pad = GpioPadConfig::default();
+                pad.parse(p);
+                self.pad = Some(pad);
+            }
+            Some(toml::Value::Boolean(false)) | None => {}
+            Some(_) => warn!("Invalid GPIO pad config"),
+        }
+        match gpio.get("photodiode").map(|p| p.as_integer()) {
+            Some(Some(p)) if (0..=27).contains(&p) => self.photodiode = Some(p as u8),
+            Some(_) => warn!("Invalid photodiode pin"),
+            None => {}
+        }
+    }
+}
+
+const MQTT_PORT: u16 = 1883;
+
+// Broker address with a port, the default one if not given. IPv6 addresses
+// are given in brackets with a port, e.g. `[fd00::1]:1883`, or bare without.
+fn broker_address(broker: &str) -> Option<String> {
+    if let Some(rest) = broker.strip_prefix('[') {
+        let (host, port) = rest.split_once(']')?;
+        let port = match port {
+            "" => MQTT_PORT,
+            p => p.strip_prefix(':')?.parse().ok()?,
+        };
+        return Some(format!("[{}]:{}", host, port));
+    }
+    match broker.rsplit_once(':') {
+        Some((host, _)) if host.contains(':') => Some(format!("[{}]:{}", broker, MQTT_PORT)),
+        Some((host, port)) => {
+            let port: u16 = port.parse().ok()?;
+            (!host.is_empty()).then(|| format!("{}:{}", host, port))
+        }
+        None => Some(format!("{}:{}", broker, MQTT_PORT)),
+    }
+}
+
+/// MQTT broker to publish state to
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    /// Broker address as `host:port`, or `[address]:port` for IPv6
+    pub broker: String,
+    /// Prefix for all topics
+    pub topic: String,
+    pub client_id: String,
+}
+
+impl MqttConfig {
+    fn parse(mqtt: &toml::Value) -> Option<Self> {
+        let broker = match mqtt.get("broker").and_then(|b| b.as_str()) {
+            Some(b) => match broker_address(b) {
+                Some(b) => b,
+                None => {
+                    warn!("Invalid MQTT broker '{}'", b);
+                    return None;
+                }
+            },
+            None => {
+                warn!("No MQTT broker set");
+                return None;
+            }
+        };
+        let topic = mqtt
+            .get("topic")
+            .and_then(|t| t.as_str())
+            .unwrap_or("gamepie")
+            .trim_end_matches('/');
+        let client_id = mqtt
+            .get("client_id")
+            .and_then(|c| c.as_str())
+            .unwrap_or("gamepie");
+
+        Some(MqttConfig {
+            broker,
+            topic: String::from(topic),
+            client_id: String::from(client_id),
+        })
+    }
+}
+
+/// Streaming the game to other devices on the network, which can also play
+#[derive(Clone, Debug)]
+pub struct NetplayConfig {
+    pub enabled: bool,
+    /// TCP port listened on
+    pub port: u16,
+    /// Accept buttons from the first device to connect, otherwise devices
+    /// can only watch
+    pub input: bool,
+    /// Player the remote buttons are pressed for, from 1
+    pub player: u32,
+}
+
+impl Default for NetplayConfig {
+    fn default() -> Self {
+        NetplayConfig {
+            enabled: false,
+            port: 5390,
+            input: true,
+            player: 2,
+        }
+    }
+}
+
+impl NetplayConfig {
+    fn parse(&mut self, netplay: &toml::Value) {
+        if let Some(e) = netplay.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+        match netplay.get("port").map(|p| p.as_integer()) {
+            Some(Some(p)) if (1..=i64::from(u16::MAX)).contains(&p) => self.port = p as u16,
+            Some(_) => warn!("Invalid netplay port"),
+            None => {}
+        }
+        if let Some(i) = netplay.get("input").and_then(|i| i.as_bool()) {
+            self.input = i;
+        }
+        match netplay.get("player").map(|p| p.as_integer()) {
+            Some(Some(p)) if (1..=4).contains(&p) => self.player = p as u32,
+            Some(_) => warn!("Invalid netplay player, should be 1 to 4"),
+            None => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LibraryConfig {
+    /// Hash new ROMs in the background, naming them from the scraper's DAT
+    /// files in the menu
+    pub enabled: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        LibraryConfig { enabled: true }
+    }
+}
+
+impl LibraryConfig {
+    fn parse(&mut self, library: &toml::Value) {
+        if let Some(e) = library.get("enabled").and_then(|e| e.as_bool()) {
+            self.enabled = e;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub dirs: GamepieDirs,
+    pub core_options: CoreOptionsConfig,
+    pub gpio: GpioConfig,
+    pub save_states: SaveStateConfig,
+    pub rewind: RewindConfig,
+    pub fast_forward: FastForwardConfig,
+    pub thermal: ThermalConfig,
+    pub cpu: CpuConfig,
+    pub feedback: FeedbackConfig,
+    pub scraper: ScraperConfig,
+    pub library: LibraryConfig,
+    pub mqtt: Option<MqttConfig>,
+    pub netplay: NetplayConfig,
+    pub input: InputConfig,
+    pub menu: MenuConfig,
+    pub party: PartyConfig,
+    pub audio: AudioConfig,
+    pub video: VideoConfig,
+    pub storage: StorageConfig,
+    pub watchdog: WatchdogConfig,
+    pub sensor: SensorConfig,
+    pub autosave: AutosaveConfig,
+    pub notify: NotifyConfig,
+}
+
+impl Config {
+    // Every section at its defaults
+    fn defaults(root: &Path) -> Self {
+        Config {
+            dirs: GamepieDirs::new(root),
+            core_options: CoreOptionsConfig::default(),
+            gpio: GpioConfig::default(),
+            save_states: SaveStateConfig::default(),
+            rewind: RewindConfig::default(),
+            fast_forward: FastForwardConfig::default(),
+            thermal: ThermalConfig::default(),
+            cpu: CpuConfig::default(),
+            feedback: FeedbackConfig::default(),
+            scraper: ScraperConfig::default(),
+            library: LibraryConfig::default(),
+            mqtt: None,
+            netplay: NetplayConfig::default(),
+            input: InputConfig::new(root),
+            menu: MenuConfig::default(),
+            party: PartyConfig::default(),
+            audio: AudioConfig::default(),
+            video: VideoConfig::default(),
+            storage: StorageConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            sensor: SensorConfig::default(),
+            autosave: AutosaveConfig::default(),
+            notify: NotifyConfig::default(),
+        }
+    }
+
+    // Path of the configuration file, in the root directory unless given
+    fn file(root: &Path, config_file: Option<&str>) -> PathBuf {
+        match config_file {
+            Some(f) => PathBuf::from(f),
+            None => root.join(CONFIG_FILE),
+        }
+    }
+
+    /// Load only the paths from the configuration file, everything else is
+    /// left at the defaults. Used in safe mode, so a bad setting can't stop
+    /// the menu from starting, while the games can still be found.
+    pub fn load_safe(root_dir: &str, config_file: Option<&str>) -> Self {
+        let root = Path::new(root_dir);
+        let mut config = Self::defaults(root);
+        let file = Self::file(root, config_file);
+        let value = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|c| c.parse::<toml::Value>().ok());
+        if let Some(paths) = value.as_ref().and_then(|v| v.get("paths")) {
+            config.dirs.parse(root, paths);
+        }
+        if let Some(video) = value.as_ref().and_then(|v| v.get("video")) {
+            config.video.parse_display(video);
+        }
+        info!(
+            "Safe mode, only using paths and display from '{}'",
+            file.display()
+        );
+        config.dirs.log();
+        config
+    }
+
+    /// Load the configuration file, any missing or invalid values will
+    /// be replaced with the defaults.
+    pub fn load(root_dir: &str, config_file: Option<&str>) -> Self {
+        let root = Path::new(root_dir);
+        let mut config = Self::defaults(root);
+        let file = Self::file(root, config_file);
+
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => match contents.parse::<toml::Value>() {
+                Ok(value) => {
+                    info!("Using config: {}", file.display());
+                    if let Some(paths) = value.get("paths") {
+                        config.dirs.parse(root, paths);
+                    }
+                    if let Some(options) = value.get("core_options") {
+                        config.core_options.parse(options);
+                    }
+                    if let Some(gpio) = value.get("gpio") {
+                        config.gpio.parse(gpio);
+                    }
+                    if let Some(states) = value.get("save_states") {
+                        config.save_states.parse(states);
+                    }
+                    if let Some(rewind) = value.get("rewind") {
+                        config.rewind.parse(rewind);
+                    }
+                    if let Some(ff) = value.get("fast_forward") {
+                        config.fast_forward.parse(ff);
+                    }
+                    if let Some(thermal) = value.get("thermal") {
+                        config.thermal.parse(thermal);
+                    }
+                    if let Some(cpu) = value.get("cpu") {
+                        config.cpu.parse(cpu);
+                    }
+                    if let Some(feedback) = value.get("feedback") {
+                        config.feedback.parse(feedback);
+                    }
+                    if let Some(scraper) = value.get("scraper") {
+                        config.scraper.parse(root, scraper);
+                    }
+                    if let Some(library) = value.get("library") {
+                        config.library.parse(library);
+                    }
+                    if let Some(mqtt) = value.get("mqtt") {
+                        config.mqtt = MqttConfig::parse(mqtt);
+                    }
+                    if let Some(netplay) = value.get("netplay") {
+                        config.netplay.parse(netplay);
+                    }
+                    if let Some(input) = value.get("input") {
+                        config.input.parse(root, input);
+                    }
+                    if let Some(menu) = value.get("menu") {
+                        config.menu.parse(menu);
+                    }
+                    if let Some(party) = value.get("party") {
+                        config.party.parse(party);
+                    }
+                    if let Some(audio) = value.get("audio") {
+                        config.audio.parse(root, audio);
+                    }
+                    if let Some(video) = value.get("video") {
+                        config.video.parse(video);
+                    }
+                    if let Some(storage) = value.get("storage") {
+                        config.storage.parse(storage);
+                    }
+                    if let Some(watchdog) = value.get("watchdog") {
+                        config.watchdog.parse(watchdog);
+                    }
+                    if let Some(sensor) = value.get("sensor") {
+                        config.sensor.parse(sensor);
+                    }
+                    if let Some(autosave) = value.get("autosave") {
+                        config.autosave.parse(autosave);
+                    }
+                    if let Some(notify) = value.get("notify") {
+                        config.notify.parse(notify);
+                    }
+                }
+                Err(e) => warn!("Invalid config '{}': {}", file.display(), e),
+            },
+            Err(_) => info!("No config file, using defaults"),
+        }
+
+        config.dirs.log();
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::broker_address as broker;
+
+    #[test]
+    fn broker_ports() {
+        assert_eq!(broker("mqtt.local").as_deref(), Some("mqtt.local:1883"));
+        assert_eq!(broker("10.0.0.2:8883").as_deref(), Some("10.0.0.2:8883"));
+        assert_eq!(broker("fd00::1").as_deref(), Some("[fd00::1]:1883"));
+        assert_eq!(broker("[fd00::1]").as_deref(), Some("[fd00::1]:1883"));
+        assert_eq!(broker("[fd00::1]:8883").as_deref(), Some("[fd00::1]:8883"));
+        assert_eq!(broker("mqtt.local:port"), None);
+        assert_eq!(broker("[fd00::1]8883"), None);
+        assert_eq!(broker(":1883"), None);
+    }
+}
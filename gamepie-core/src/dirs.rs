@@ -0,0 +1,446 @@
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{
+    CHEAT_PATH, CORE_HASH_FILE, CRASH_PATH, EMU_PATH, HISTORY_FILE, IMAGE_EXTS, LIBRARY_FILE,
+    METADATA_EXT, NOTIFY_LOG_FILE, OPTIONS_PATH, QUARANTINE_FILE, ROM_PATH, SAVEDATA_EXT,
+    SAVE_PATH, SCRAPE_QUEUE_FILE, SCREENSHOT_PATH, SETTINGS_FILE, STATE_PATH, STATS_FILE, SYS_PATH,
+    THEME_PATH, TIMED_SAVES, TRACE_PATH,
+};
+
+/// Locations of the directories used by gamepie, and the files within them.
+/// Relative paths in the configuration file are taken to be relative to the
+/// root directory.
+#[derive(Clone, Debug)]
+pub struct GamepieDirs {
+    emulators: PathBuf,
+    roms: Vec<PathBuf>,
+    saves: PathBuf,
+    system: PathBuf,
+    screenshots: PathBuf,
+    states: PathBuf,
+    themes: PathBuf,
+    cheats: PathBuf,
+    // Used if the configured save directory is on storage that has gone
+    fallback_saves: PathBuf,
+}
+
+impl GamepieDirs {
+    pub(crate) fn new(root: &Path) -> Self {
+        GamepieDirs {
+            emulators: root.join(EMU_PATH),
+            roms: vec![root.join(ROM_PATH)],
+            saves: root.join(SAVE_PATH),
+            system: root.join(SYS_PATH),
+            screenshots: root.join(SCREENSHOT_PATH),
+            states: root.join(STATE_PATH),
+            themes: root.join(THEME_PATH),
+            cheats: root.join(CHEAT_PATH),
+            fallback_saves: root.join(SAVE_PATH),
+        }
+    }
+
+    pub(crate) fn resolve(root: &Path, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            root.join(path)
+        }
+    }
+
+    pub(crate) fn parse(&mut self, root: &Path, paths: &toml::Value) {
+        match paths.get("roms") {
+            Some(toml::Value::String(p)) => self.roms = vec![Self::resolve(root, p)],
+            Some(toml::Value::Array(a)) => {
+                self.roms = a
+                    .iter()
+                    .filter_map(|p| match p.as_str() {
+                        Some(p) => Some(Self::resolve(root, p)),
+                        None => {
+                            warn!("Ignoring non-string ROM directory: {}", p);
+                            None
+                        }
+                    })
+                    .collect();
+            }
+            Some(v) => warn!("Invalid ROM directories: {}", v),
+            None => {}
+        }
+        for (key, dir) in [
+            ("emulators", &mut self.emulators),
+            ("saves", &mut self.saves),
+            ("system", &mut self.system),
+            ("screenshots", &mut self.screenshots),
+            ("states", &mut self.states),
+            ("themes", &mut self.themes),
+            ("cheats", &mut self.cheats),
+        ] {
+            if let Some(p) = paths.get(key).and_then(|p| p.as_str()) {
+                *dir = Self::resolve(root, p);
+            }
+        }
+    }
+
+    // Directories gamepie writes to, which are created if missing
+    fn writable(&self) -> [PathBuf; 8] {
+        [
+            self.fallback_saves.clone(),
+            self.system.join(OPTIONS_PATH),
+            self.system.join(TRACE_PATH),
+            self.system.join(CRASH_PATH),
+            self.screenshots.clone(),
+            self.states.clone(),
+            self.themes.clone(),
+            self.cheats.clone(),
+        ]
+    }
+
+    /// Check the directories read from exist and create those that are
+    /// written to. Problems are logged rather than returned, as gamepie
+    /// can still run without them, e.g. to show the error on screen.
+    pub fn create(&self) {
+        if !self.emulators.is_dir() {
+            warn!("Emulator directory '{}' missing", self.emulators.display());
+        }
+        // Warns about missing ROM directories
+        self.rom_dirs();
+        for dir in self.writable() {
+            if !dir.is_dir() {
+                match std::fs::create_dir_all(&dir) {
+                    Ok(_) => debug!("Created '{}'", dir.display()),
+                    Err(e) => warn!("Can't create '{}': {}", dir.display(), e),
+                }
+            }
+        }
+    }
+
+    pub fn emulators(&self) -> &Path {
+        &self.emulators
+    }
+
+    /// ROM directories that are currently available
+    pub fn rom_dirs(&self) -> Vec<&Path> {
+        self.roms
+            .iter()
+            .filter(|p| {
+                let present = p.is_dir();
+                if !present {
+                    warn!("ROM directory '{}' not available", p.display());
+                }
+                present
+            })
+            .map(|p| p.as_path())
+            .collect()
+    }
+
+    /// Directory to write saves to, if the configured directory is on
+    /// removable storage that isn't present, the default save directory
+    /// will be used instead.
+    pub fn save_dir(&self) -> &Path {
+        if self.saves.is_dir() {
+            &self.saves
+        } else {
+            warn!(
+                "Save directory '{}' not available, using '{}'",
+                self.saves.display(),
+                self.fallback_saves.display()
+            );
+            &self.fallback_saves
+        }
+    }
+
+    /// Save file for a game, named after the game's filename
+    pub fn save_file(&self, game: &Path) -> Option<PathBuf> {
+        let mut file = game.file_name()?.to_os_string();
+        file.push(".");
+        file.push(SAVEDATA_EXT);
+        Some(self.save_dir().join(file))
+    }
+
+    /// When a game was last saved, from the newest of its save and timed
+    /// saves
+    pub fn last_saved(&self, game: &Path) -> Option<SystemTime> {
+        let save = self.save_file(game)?;
+        std::iter::once(save.clone())
+            .chain(TIMED_SAVES.iter().map(|suffix| {
+                let mut timed = save.clone().into_os_string();
+                timed.push(suffix);
+                PathBuf::from(timed)
+            }))
+            .filter_map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .max()
+    }
+
+    /// Play statistics, kept with the saves
+    pub fn stats_file(&self) -> PathBuf {
+        self.save_dir().join(STATS_FILE)
+    }
+
+    /// System directory given to cores, e.g. for BIOS files
+    pub fn system(&self) -> &Path {
+        &self.system
+    }
+
+    /// Checksums of the cores seen on previous runs
+    pub fn core_records_file(&self) -> PathBuf {
+        self.system.join(CORE_HASH_FILE)
+    }
+
+    /// Cores that have hung, and won't be loaded until they are replaced
+    pub fn quarantine_file(&self) -> PathBuf {
+        self.system.join(QUARANTINE_FILE)
+    }
+
+    /// Play history and favourites
+    pub fn history_file(&self) -> PathBuf {
+        self.system.join(HISTORY_FILE)
+    }
+
+    /// Settings changed from gamepie, e.g. the volume
+    pub fn settings_file(&self) -> PathBuf {
+        self.system.join(SETTINGS_FILE)
+    }
+
+    /// Notifications kept by the log sink
+    pub fn notify_log_file(&self) -> PathBuf {
+        self.system.join(NOTIFY_LOG_FILE)
+    }
+
+    /// ROMs waiting to have metadata scraped
+    pub fn scrape_queue_file(&self) -> PathBuf {
+        self.system.join(SCRAPE_QUEUE_FILE)
+    }
+
+    /// Checksums and titles of the ROMs found
+    pub fn library_file(&self) -> PathBuf {
+        self.system.join(LIBRARY_FILE)
+    }
+
+    /// Saved options for a core
+    pub fn core_options_file(&self, core: &str) -> PathBuf {
+        let file = format!("{}.toml", core.replace(' ', "_"));
+        self.system.join(OPTIONS_PATH).join(file)
+    }
+
+    /// Environment traces, one per game session
+    pub fn traces(&self) -> PathBuf {
+        self.system.join(TRACE_PATH)
+    }
+
+    /// Crash reports, one archive per crash
+    pub fn crashes(&self) -> PathBuf {
+        self.system.join(CRASH_PATH)
+    }
+
+    /// Environment trace for a core, one per game session
+    pub fn trace_file(&self, core: &str, time: u64) -> PathBuf {
+        let file = format!("{}-{}.log", core.replace(' ', "_"), time);
+        self.traces().join(file)
+    }
+
+    pub fn screenshots(&self) -> &Path {
+        &self.screenshots
+    }
+
+    pub fn states(&self) -> &Path {
+        &self.states
+    }
+
+    /// State file for a game, named after the game's filename
+    pub fn state_file(&self, game: &Path) -> Option<PathBuf> {
+        let mut file = game.file_name()?.to_os_string();
+        file.push(".state");
+        Some(self.states.join(file))
+    }
+
+    /// Whether a game has a save state
+    pub fn has_state(&self, game: &Path) -> bool {
+        self.state_file(game).is_some_and(|s| s.is_file())
+    }
+
+    pub fn themes(&self) -> &Path {
+        &self.themes
+    }
+
+    pub fn cheats(&self) -> &Path {
+        &self.cheats
+    }
+
+    pub fn log(&self) {
+        debug!("Emulators: {}", self.emulators.display());
+        for r in &self.roms {
+            debug!("ROMs: {}", r.display());
+        }
+        debug!("Saves: {}", self.saves.display());
+        debug!("System: {}", self.system.display());
+        debug!("Screenshots: {}", self.screenshots.display());
+        debug!("States: {}", self.states.display());
+        debug!("Themes: {}", self.themes.display());
+        debug!("Cheats: {}", self.cheats.display());
+    }
+}
+
+// Folders within a ROM directory are followed this deep, in case a link
+// points back up the tree
+const MAX_ROM_DEPTH: usize = 4;
+
+// Visit the files and folders below `dir`/`rel`, with their paths relative to
+// `dir` and whether they are folders. Hidden files and folders are skipped.
+fn walk_roms(dir: &Path, rel: &Path, depth: usize, visit: &mut dyn FnMut(PathBuf, bool)) {
+    let entries = match std::fs::read_dir(dir.join(rel)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Can't read '{}': {}", dir.join(rel).display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name.as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        let path = rel.join(&name);
+        // Only links need a stat, to see what they point to
+        let is_dir = match entry.file_type() {
+            Ok(t) if t.is_symlink() => entry.path().is_dir(),
+            Ok(t) => t.is_dir(),
+            Err(_) => continue,
+        };
+        if is_dir {
+            if depth < MAX_ROM_DEPTH {
+                visit(path.clone(), true);
+                walk_roms(dir, &path, depth + 1, visit);
+            }
+        } else {
+            visit(path, false);
+        }
+    }
+}
+
+/// Files in a ROM directory and the folders within it (e.g. `gb/Tetris.gb`),
+/// as paths relative to it. Hidden files and folders are skipped.
+pub fn rom_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    walk_roms(dir, Path::new(""), 0, &mut |path, is_dir| {
+        if !is_dir {
+            files.push(path);
+        }
+    });
+    files
+}
+
+/// Whether a file in a ROM directory is a game, rather than the metadata or
+/// box art kept beside one
+pub fn is_game_file(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str());
+    !ext.is_some_and(|e| e == METADATA_EXT || IMAGE_EXTS.iter().any(|i| e.eq_ignore_ascii_case(i)))
+}
+
+/// Games in a ROM directory and the folders within it, as for `rom_files`
+pub fn game_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = rom_files(dir);
+    files.retain(|f| is_game_file(f));
+    files
+}
+
+/// Folders within a ROM directory, as paths relative to it
+pub fn rom_folders(dir: &Path) -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    walk_roms(dir, Path::new(""), 0, &mut |path, is_dir| {
+        if is_dir {
+            folders.push(path);
+        }
+    });
+    folders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_game_file, rom_files, rom_folders, GamepieDirs};
+    use crate::testing::TestDir;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn relative_paths_from_root() {
+        let root = Path::new("/gamepie");
+        let mut dirs = GamepieDirs::new(root);
+        let value = "saves = \"/mnt/data\"\nroms = [\"roms\", \"/media/usb\"]\ncheats = \"codes\""
+            .parse::<toml::Value>()
+            .unwrap();
+        dirs.parse(root, &value);
+        assert_eq!(dirs.saves, PathBuf::from("/mnt/data"));
+        assert_eq!(
+            dirs.roms,
+            vec![PathBuf::from("/gamepie/roms"), PathBuf::from("/media/usb")]
+        );
+        assert_eq!(dirs.system(), Path::new("/gamepie/sys"));
+        assert_eq!(dirs.cheats(), Path::new("/gamepie/codes"));
+        assert_eq!(dirs.states(), Path::new("/gamepie/states"));
+    }
+
+    #[test]
+    fn single_rom_dir() {
+        let root = Path::new("/gamepie");
+        let mut dirs = GamepieDirs::new(root);
+        let value = "roms = \"games\"".parse::<toml::Value>().unwrap();
+        dirs.parse(root, &value);
+        assert_eq!(dirs.roms, vec![PathBuf::from("/gamepie/games")]);
+    }
+
+    #[test]
+    fn nested_roms() {
+        let dir = TestDir::new("rom-tree");
+        let dir = dir.path();
+        std::fs::create_dir_all(dir.join("gb/hacks")).unwrap();
+        std::fs::create_dir_all(dir.join(".hidden")).unwrap();
+        for file in [
+            "Sonic.md",
+            "gb/Tetris.gb",
+            "gb/hacks/Tetris DX.gb",
+            ".hidden/a.gb",
+            ".b.part",
+        ] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let mut files = rom_files(dir);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("Sonic.md"),
+                PathBuf::from("gb/Tetris.gb"),
+                PathBuf::from("gb/hacks/Tetris DX.gb")
+            ]
+        );
+        let mut folders = rom_folders(dir);
+        folders.sort();
+        assert_eq!(
+            folders,
+            vec![PathBuf::from("gb"), PathBuf::from("gb/hacks")]
+        );
+    }
+
+    #[test]
+    fn game_file_filter() {
+        assert!(is_game_file(Path::new("gb/Tetris.gb")));
+        assert!(is_game_file(Path::new("Doom.wad")));
+        assert!(!is_game_file(Path::new("gb/Tetris.toml")));
+        assert!(!is_game_file(Path::new("gb/Tetris.gb.png")));
+        assert!(!is_game_file(Path::new("Tetris.BMP")));
+    }
+
+    #[test]
+    fn core_files() {
+        let dirs = GamepieDirs::new(Path::new("/gamepie"));
+        assert_eq!(
+            dirs.core_options_file("Snes9x 2005"),
+            PathBuf::from("/gamepie/sys/config/Snes9x_2005.toml")
+        );
+        assert_eq!(
+            dirs.trace_file("mGBA", 12),
+            PathBuf::from("/gamepie/sys/traces/mGBA-12.log")
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use std::error::Error;
 use std::fmt::Display;
 
+use crate::lang::Language;
+
 #[derive(Clone, Copy, Debug)]
 pub enum GamepieError {
     /// No games found
@@ -21,22 +23,129 @@ pub enum GamepieError {
     NoVideo,
     /// String error
     String,
+    /// Game no longer present (removable storage)
+    GameMissing,
+    /// Save state couldn't be created or restored
+    SaveState,
 }
 
-impl Display for GamepieError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+impl GamepieError {
+    /// Stable number for the error, shown with the description so it can be
+    /// looked up whatever language the screen is in. Numbers aren't reused.
+    pub fn code(&self) -> u16 {
         match self {
-            GamepieError::NoGames => write!(f, "no games found"),
-            GamepieError::GameLoadError => write!(f, "game load error"),
-            GamepieError::NoCore => write!(f, "no compatible core"),
-            GamepieError::System => write!(f, "internal system error"),
-            GamepieError::MismatchSave => write!(f, "mismatched save"),
-            GamepieError::UnsupportedVideo => write!(f, "unsupported video"),
-            GamepieError::NoAudio => write!(f, "audio error"),
-            GamepieError::NoVideo => write!(f, "video error"),
-            GamepieError::String => write!(f, "string error"),
+            GamepieError::NoGames => 1,
+            GamepieError::GameLoadError => 2,
+            GamepieError::NoCore => 3,
+            GamepieError::System => 4,
+            GamepieError::MismatchSave => 5,
+            GamepieError::UnsupportedVideo => 6,
+            GamepieError::NoAudio => 7,
+            GamepieError::NoVideo => 8,
+            GamepieError::String => 9,
+            GamepieError::GameMissing => 10,
+            GamepieError::SaveState => 11,
+        }
+    }
+
+    /// Code as shown on screen, e.g. "E-0002"
+    pub fn code_label(&self) -> String {
+        format!("E-{:04}", self.code())
+    }
+
+    /// Description of the error in a language
+    pub fn describe(&self, lang: Language) -> &'static str {
+        match (self, lang) {
+            (GamepieError::NoGames, Language::English) => "no games found",
+            (GamepieError::NoGames, Language::French) => "aucun jeu trouvé",
+            (GamepieError::NoGames, Language::German) => "keine Spiele gefunden",
+            (GamepieError::NoGames, Language::Spanish) => "no se encontraron juegos",
+            (GamepieError::GameLoadError, Language::English) => "game load error",
+            (GamepieError::GameLoadError, Language::French) => "erreur de chargement",
+            (GamepieError::GameLoadError, Language::German) => "Fehler beim Laden",
+            (GamepieError::GameLoadError, Language::Spanish) => "error al cargar el juego",
+            (GamepieError::NoCore, Language::English) => "no compatible core",
+            (GamepieError::NoCore, Language::French) => "aucun émulateur compatible",
+            (GamepieError::NoCore, Language::German) => "kein passender Core",
+            (GamepieError::NoCore, Language::Spanish) => "ningún núcleo compatible",
+            (GamepieError::System, Language::English) => "internal system error",
+            (GamepieError::System, Language::French) => "erreur système interne",
+            (GamepieError::System, Language::German) => "interner Systemfehler",
+            (GamepieError::System, Language::Spanish) => "error interno del sistema",
+            (GamepieError::MismatchSave, Language::English) => "mismatched save",
+            (GamepieError::MismatchSave, Language::French) => "sauvegarde incompatible",
+            (GamepieError::MismatchSave, Language::German) => "Spielstand passt nicht",
+            (GamepieError::MismatchSave, Language::Spanish) => "partida incompatible",
+            (GamepieError::UnsupportedVideo, Language::English) => "unsupported video",
+            (GamepieError::UnsupportedVideo, Language::French) => "vidéo non prise en charge",
+            (GamepieError::UnsupportedVideo, Language::German) => "Video nicht unterstützt",
+            (GamepieError::UnsupportedVideo, Language::Spanish) => "vídeo no compatible",
+            (GamepieError::NoAudio, Language::English) => "audio error",
+            (GamepieError::NoAudio, Language::French) => "erreur audio",
+            (GamepieError::NoAudio, Language::German) => "Audiofehler",
+            (GamepieError::NoAudio, Language::Spanish) => "error de audio",
+            (GamepieError::NoVideo, Language::English) => "video error",
+            (GamepieError::NoVideo, Language::French) => "erreur vidéo",
+            (GamepieError::NoVideo, Language::German) => "Videofehler",
+            (GamepieError::NoVideo, Language::Spanish) => "error de vídeo",
+            (GamepieError::String, Language::English) => "string error",
+            (GamepieError::String, Language::French) => "erreur de texte",
+            (GamepieError::String, Language::German) => "Textfehler",
+            (GamepieError::String, Language::Spanish) => "error de texto",
+            (GamepieError::GameMissing, Language::English) => "game not found",
+            (GamepieError::GameMissing, Language::French) => "jeu introuvable",
+            (GamepieError::GameMissing, Language::German) => "Spiel nicht gefunden",
+            (GamepieError::GameMissing, Language::Spanish) => "juego no encontrado",
+            (GamepieError::SaveState, Language::English) => "save state error",
+            (GamepieError::SaveState, Language::French) => "erreur de sauvegarde rapide",
+            (GamepieError::SaveState, Language::German) => "Fehler beim Schnellspeichern",
+            (GamepieError::SaveState, Language::Spanish) => "error de guardado rápido",
         }
     }
 }
 
+/// Heading of the error screen in a language
+pub fn error_heading(lang: Language) -> &'static str {
+    match lang {
+        Language::English => "Error",
+        Language::French => "Erreur",
+        Language::German => "Fehler",
+        Language::Spanish => "Error",
+    }
+}
+
+impl Display for GamepieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.describe(Language::English))
+    }
+}
+
 impl Error for GamepieError {}
+
+#[cfg(test)]
+mod tests {
+    use super::GamepieError;
+
+    #[test]
+    fn codes() {
+        let errors = [
+            GamepieError::NoGames,
+            GamepieError::GameLoadError,
+            GamepieError::NoCore,
+            GamepieError::System,
+            GamepieError::MismatchSave,
+            GamepieError::UnsupportedVideo,
+            GamepieError::NoAudio,
+            GamepieError::NoVideo,
+            GamepieError::String,
+            GamepieError::GameMissing,
+            GamepieError::SaveState,
+        ];
+        let mut codes: Vec<u16> = errors.iter().map(|e| e.code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(GamepieError::GameLoadError.code_label(), "E-0002");
+        assert_eq!(GamepieError::GameMissing.to_string(), "game not found");
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use crate::table::{ParseEntry, TomlTable};
+
+/// When a game was last played and how often, and whether it is a favourite
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlayRecord {
+    /// Seconds since the Unix epoch, zero if never played
+    pub last_played: u64,
+    pub plays: u64,
+    pub favourite: bool,
+}
+
+impl ParseEntry for PlayRecord {
+    const NAME: &'static str = "history";
+
+    fn parse(value: &toml::Value) -> Option<Self> {
+        let int = |key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_integer())
+                .and_then(|v| u64::try_from(v).ok())
+        };
+        Some(PlayRecord {
+            last_played: int("last_played")?,
+            plays: int("plays")?,
+            favourite: value.get("favourite").and_then(|v| v.as_bool())?,
+        })
+    }
+
+    fn to_value(&self) -> toml::Value {
+        let int = |v: u64| toml::Value::Integer(v as i64);
+        let mut table = toml::value::Table::new();
+        table.insert(String::from("last_played"), int(self.last_played));
+        table.insert(String::from("plays"), int(self.plays));
+        table.insert(
+            String::from("favourite"),
+            toml::Value::Boolean(self.favourite),
+        );
+        toml::Value::Table(table)
+    }
+}
+
+/// Play history and favourites for each game, by filename, stored as a TOML
+/// file
+pub struct History(TomlTable<PlayRecord>);
+
+impl History {
+    pub fn load(path: &Path) -> Self {
+        History(TomlTable::load(path))
+    }
+
+    // By filename, as for stats
+    fn key(game: &Path) -> String {
+        match game.file_name() {
+            Some(f) => f.to_string_lossy().into_owned(),
+            None => game.to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn get(&self, game: &Path) -> Option<&PlayRecord> {
+        self.0.get(&Self::key(game))
+    }
+
+    /// Record a game being started at a time (seconds since the Unix epoch)
+    pub fn played(&mut self, game: &Path, time: u64) {
+        let record = self.0.get_or_default(Self::key(game));
+        record.last_played = time;
+        record.plays += 1;
+    }
+
+    /// Add or remove a game from the favourites, returning whether it is
+    /// now a favourite
+    pub fn toggle_favourite(&mut self, game: &Path) -> bool {
+        let record = self.0.get_or_default(Self::key(game));
+        record.favourite = !record.favourite;
+        record.favourite
+    }
+
+    /// Filenames of the most recently played games, most recent first
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let mut played: Vec<_> = self.0.iter().filter(|(_, r)| r.plays > 0).collect();
+        played.sort_unstable_by(|a, b| b.1.last_played.cmp(&a.1.last_played).then(a.0.cmp(b.0)));
+        played
+            .into_iter()
+            .take(count)
+            .map(|(g, _)| g.to_string())
+            .collect()
+    }
+
+    /// Filenames of the favourite games, sorted
+    pub fn favourites(&self) -> Vec<String> {
+        let mut favourites: Vec<String> = self
+            .0
+            .iter()
+            .filter(|(_, r)| r.favourite)
+            .map(|(g, _)| g.to_string())
+            .collect();
+        favourites.sort_unstable_by_key(|g| g.to_lowercase());
+        favourites
+    }
+
+    pub fn save(&self) {
+        self.0.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::testing::TestDir;
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("history");
+        let path = dir.join("history.toml");
+
+        let mut history = History::load(&path);
+        history.played(Path::new("/roms/Tetris.gb"), 100);
+        history.played(Path::new("/roms/Zelda.gb"), 200);
+        history.played(Path::new("/media/usb/Tetris.gb"), 300);
+        assert!(history.toggle_favourite(Path::new("/roms/Mario.gb")));
+        history.save();
+
+        let history = History::load(&path);
+        assert_eq!(history.recent(5), vec!["Tetris.gb", "Zelda.gb"]);
+        assert_eq!(history.recent(1), vec!["Tetris.gb"]);
+        assert_eq!(history.favourites(), vec!["Mario.gb"]);
+        assert_eq!(
+            history.get(Path::new("Tetris.gb")).map(|r| r.plays),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use log::warn;
+
+/// Language of text shown by gamepie itself, cores pick their own
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Language {
+    #[default]
+    English,
+    French,
+    German,
+    Spanish,
+}
+
+impl Language {
+    /// Parse a language code, e.g. "fr"
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "en" => Some(Language::English),
+            "fr" => Some(Language::French),
+            "de" => Some(Language::German),
+            "es" => Some(Language::Spanish),
+            _ => {
+                warn!("Unsupported language '{}'", s);
+                None
+            }
+        }
+    }
+}
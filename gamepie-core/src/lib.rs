@@ -1,12 +1,25 @@
 use embedded_graphics::{pixelcolor::Rgb565, prelude::RgbColor};
 use std::time::Duration;
 
+pub mod aspect;
+pub mod checksum;
 pub mod commands;
+pub mod config;
+pub mod dirs;
 pub mod error;
+pub mod history;
+pub mod lang;
+pub mod library;
 pub mod log;
+pub mod metadata;
 pub mod portable;
 pub mod problem;
+pub mod settings;
+pub mod stats;
+pub mod table;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod types;
 
 pub use types::*;
@@ -15,9 +28,29 @@ pub const EMU_PATH: &str = "emulators";
 pub const ROM_PATH: &str = "roms";
 pub const SAVE_PATH: &str = "saves";
 pub const SYS_PATH: &str = "sys";
+pub const MAPPING_PATH: &str = "mappings";
+pub const SCREENSHOT_PATH: &str = "screenshots";
+pub const STATE_PATH: &str = "states";
+pub const THEME_PATH: &str = "themes";
+pub const CHEAT_PATH: &str = "cheats";
+pub const TRACE_PATH: &str = "traces";
+pub const CRASH_PATH: &str = "crashes";
+pub const OPTIONS_PATH: &str = "config";
 
 pub const METADATA_EXT: &str = "toml";
+// Images that sit beside games, and aren't games themselves
+pub const IMAGE_EXTS: [&str; 2] = ["bmp", "png"];
 pub const SAVEDATA_EXT: &str = "sav";
+// Suffixes of the files timed saves alternate between
+pub const TIMED_SAVES: [&str; 2] = [".0", ".1"];
+pub const STATS_FILE: &str = "stats.toml";
+pub const CORE_HASH_FILE: &str = "cores.toml";
+pub const QUARANTINE_FILE: &str = "quarantine.toml";
+pub const HISTORY_FILE: &str = "history.toml";
+pub const SETTINGS_FILE: &str = "settings.toml";
+pub const NOTIFY_LOG_FILE: &str = "notifications.log";
+pub const SCRAPE_QUEUE_FILE: &str = "scrape-queue.txt";
+pub const LIBRARY_FILE: &str = "library.toml";
 
 const SPLASH_TIME_SECS: u64 = 3;
 const MENU_FRAME_TIME_MS: u64 = 30;
@@ -0,0 +1,141 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::table::{ParseEntry, TomlTable};
+
+/// Checksums of a ROM, and its title from a DAT file if it was found. The
+/// checksums are of the ROM without any iNES header, as DATs list them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryEntry {
+    /// Size and modification time (seconds since the Unix epoch) of the
+    /// file when it was hashed
+    pub size: u64,
+    pub modified: u64,
+    pub crc32: u32,
+    /// Lower case hex
+    pub sha1: String,
+    pub title: Option<String>,
+}
+
+impl ParseEntry for LibraryEntry {
+    const NAME: &'static str = "library";
+
+    fn parse(value: &toml::Value) -> Option<Self> {
+        let int = |key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_integer())
+                .and_then(|v| u64::try_from(v).ok())
+        };
+        let string = |key: &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+        Some(LibraryEntry {
+            size: int("size")?,
+            modified: int("modified")?,
+            crc32: string("crc32").and_then(|c| u32::from_str_radix(&c, 16).ok())?,
+            sha1: string("sha1")?,
+            title: string("title"),
+        })
+    }
+
+    fn to_value(&self) -> toml::Value {
+        let int = |v: u64| toml::Value::Integer(v as i64);
+        let mut table = toml::value::Table::new();
+        table.insert(String::from("size"), int(self.size));
+        table.insert(String::from("modified"), int(self.modified));
+        table.insert(
+            String::from("crc32"),
+            toml::Value::String(format!("{:08x}", self.crc32)),
+        );
+        table.insert(String::from("sha1"), toml::Value::String(self.sha1.clone()));
+        if let Some(title) = &self.title {
+            table.insert(String::from("title"), toml::Value::String(title.clone()));
+        }
+        toml::Value::Table(table)
+    }
+}
+
+/// Checksums and titles of the ROMs found, by path, stored as a TOML file so
+/// each ROM is only hashed again if it changes
+pub struct Library(TomlTable<LibraryEntry>);
+
+impl Library {
+    pub fn load(path: &Path) -> Self {
+        Library(TomlTable::load(path))
+    }
+
+    fn key(game: &Path) -> String {
+        game.to_string_lossy().into_owned()
+    }
+
+    /// The entry for a ROM, if it hasn't changed since it was hashed
+    pub fn get(&self, game: &Path, size: u64, modified: u64) -> Option<&LibraryEntry> {
+        self.0
+            .get(&Self::key(game))
+            .filter(|e| e.size == size && e.modified == modified)
+    }
+
+    pub fn insert(&mut self, game: &Path, entry: LibraryEntry) {
+        self.0.insert(Self::key(game), entry);
+    }
+
+    /// Forget ROMs that are no longer found
+    pub fn retain(&mut self, found: &[PathBuf]) {
+        let found: HashSet<String> = found.iter().map(|p| Self::key(p)).collect();
+        self.0.retain(|k, _| found.contains(k));
+    }
+
+    /// Titles of the ROMs that were found in a DAT, for the menu
+    pub fn titles(&self) -> HashMap<PathBuf, String> {
+        self.0
+            .iter()
+            .filter_map(|(k, e)| Some((PathBuf::from(k), e.title.clone()?)))
+            .collect()
+    }
+
+    pub fn save(&self) {
+        self.0.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Library, LibraryEntry};
+    use crate::testing::TestDir;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("library");
+        let path = dir.join("library.toml");
+
+        let entry = |title: Option<&str>| LibraryEntry {
+            size: 32768,
+            modified: 100,
+            crc32: 0x46df_91ad,
+            sha1: String::from("74591cc9501af93873f9a5d3eb12da12c0723bbc"),
+            title: title.map(String::from),
+        };
+        let mut library = Library::load(&path);
+        library.insert(Path::new("/roms/Tetris.gb"), entry(Some("Tetris (World)")));
+        library.insert(Path::new("/roms/hack.gb"), entry(None));
+        library.insert(Path::new("/roms/gone.gb"), entry(None));
+        library.retain(&[
+            PathBuf::from("/roms/Tetris.gb"),
+            PathBuf::from("/roms/hack.gb"),
+        ]);
+        library.save();
+
+        let library = Library::load(&path);
+        let tetris = Path::new("/roms/Tetris.gb");
+        assert_eq!(
+            library.get(tetris, 32768, 100),
+            Some(&entry(Some("Tetris (World)")))
+        );
+        // Changed since it was hashed
+        assert_eq!(library.get(tetris, 32768, 101), None);
+        assert_eq!(library.get(Path::new("/roms/gone.gb"), 32768, 100), None);
+        let titles = library.titles();
+        assert_eq!(titles.len(), 1);
+        assert_eq!(titles[tetris], "Tetris (World)");
+    }
+}
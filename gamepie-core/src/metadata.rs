@@ -0,0 +1,70 @@
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::METADATA_EXT;
+
+/// Metadata file for a game, `<rom>.toml` next to it
+pub fn path(game: &Path) -> PathBuf {
+    let mut meta = game.as_os_str().to_os_string();
+    meta.push(".");
+    meta.push(METADATA_EXT);
+    PathBuf::from(meta)
+}
+
+/// Load the metadata for a game, `None` if it has none or it isn't valid
+pub fn load(game: &Path) -> Option<toml::Value> {
+    let file = std::fs::read_to_string(path(game)).ok()?;
+    match file.parse::<toml::Value>() {
+        Ok(meta) => Some(meta),
+        Err(e) => {
+            warn!("Invalid metadata for '{}': {}", game.display(), e);
+            None
+        }
+    }
+}
+
+/// Load a single string from a game's metadata, e.g. `name`
+pub fn get_str(game: &Path, key: &str) -> Option<String> {
+    load(game)?.get(key)?.as_str().map(String::from)
+}
+
+/// Core options to use for this game in place of the presets and saved
+/// values, from the `[options]` table
+pub fn options(meta: &toml::Value) -> HashMap<String, String> {
+    let table = match meta.get("options").and_then(|o| o.as_table()) {
+        Some(t) => t,
+        None => return HashMap::new(),
+    };
+    table
+        .iter()
+        .filter_map(|(k, v)| match v.as_str() {
+            Some(v) => Some((k.clone(), String::from(v))),
+            None => {
+                warn!("Option '{}' in metadata is not a string", k);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::options;
+
+    #[test]
+    fn option_overrides() {
+        let meta: toml::Value = r#"
+            preferred_core = "gambatte"
+            [options]
+            gambatte_gb_colorization = "auto"
+            frameskip = 2
+        "#
+        .parse()
+        .unwrap();
+        let opts = options(&meta);
+        assert_eq!(opts.len(), 1);
+        assert_eq!(opts["gambatte_gb_colorization"], "auto");
+        assert!(options(&toml::Value::Table(Default::default())).is_empty());
+    }
+}
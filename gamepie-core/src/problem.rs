@@ -1,6 +1,43 @@
+use log::warn;
+use std::fmt::Display;
+
 use crate::commands::ScreenToast;
 use crate::error::GamepieError;
 
+/// How serious a notification is, sinks can ignore those below a severity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Parse the lowest severity a sink shows, Some(None) for "off"
+    pub fn parse_filter(s: &str) -> Option<Option<Self>> {
+        match s {
+            "info" => Some(Some(Severity::Info)),
+            "warning" => Some(Some(Severity::Warning)),
+            "error" => Some(Some(Severity::Error)),
+            "off" => Some(None),
+            _ => {
+                warn!("Unknown severity '{}'", s);
+                None
+            }
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
 pub enum Problem {
     Fatal(GamepieError),
     Warn(ScreenToast),
@@ -14,4 +51,11 @@ impl Problem {
     pub fn warn(s: ScreenToast) -> Problem {
         Problem::Warn(s)
     }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::Fatal(_) => Severity::Error,
+            Problem::Warn(s) => s.severity(),
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::aspect::ScaleMode;
+use crate::table::write_atomic;
+
+// How often save RAM is written while a game runs, unless changed
+const AUTOSAVE_DEFAULT: Duration = Duration::from_secs(60);
+
+/// Settings changed while gamepie runs, rather than in the config file,
+/// kept between runs as a TOML file. Unset settings use the defaults.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    path: PathBuf,
+    /// Volume (0-100)
+    pub volume: Option<u8>,
+    /// Scaling of games, in place of the configured one
+    pub scaling: Option<ScaleMode>,
+    /// Name of a theme in the themes directory
+    pub theme: Option<String>,
+    /// How often save RAM is written while a game runs
+    pub autosave_interval: Duration,
+    /// Path of the game last started, selected in the menu at startup
+    pub last_game: Option<String>,
+    /// Use the faster CPU governor while demanding games run
+    pub cpu_boost: bool,
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Self {
+        let mut settings = Settings {
+            path: path.to_path_buf(),
+            volume: None,
+            scaling: None,
+            theme: None,
+            autosave_interval: AUTOSAVE_DEFAULT,
+            last_game: None,
+            cpu_boost: true,
+        };
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            // Nothing has been changed yet
+            Err(_) => return settings,
+        };
+        let value = match contents.parse::<toml::Value>() {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Invalid settings file '{}': {}", path.display(), e);
+                return settings;
+            }
+        };
+        let string = |key: &str| value.get(key).and_then(|v| v.as_str());
+        match value.get("volume").map(|v| v.as_integer()) {
+            Some(Some(v)) if (0..=100).contains(&v) => settings.volume = Some(v as u8),
+            Some(_) => warn!("Invalid volume setting"),
+            None => {}
+        }
+        settings.scaling = string("scaling").and_then(ScaleMode::parse);
+        settings.theme = string("theme").map(String::from);
+        match value.get("autosave_secs").map(|v| v.as_integer()) {
+            Some(Some(s)) if s > 0 => settings.autosave_interval = Duration::from_secs(s as u64),
+            Some(_) => warn!("Invalid autosave interval setting"),
+            None => {}
+        }
+        settings.last_game = string("last_game").map(String::from);
+        if let Some(boost) = value.get("cpu_boost").and_then(|b| b.as_bool()) {
+            settings.cpu_boost = boost;
+        }
+        debug!("Loaded settings from '{}'", path.display());
+        settings
+    }
+
+    pub fn save(&self) {
+        let mut table = toml::value::Table::new();
+        let string = |s: &str| toml::Value::String(String::from(s));
+        if let Some(volume) = self.volume {
+            table.insert(String::from("volume"), toml::Value::Integer(volume.into()));
+        }
+        if let Some(scaling) = self.scaling {
+            let mode = scaling.label().to_lowercase();
+            table.insert(String::from("scaling"), string(&mode));
+        }
+        if let Some(theme) = &self.theme {
+            table.insert(String::from("theme"), string(theme));
+        }
+        table.insert(
+            String::from("autosave_secs"),
+            toml::Value::Integer(self.autosave_interval.as_secs() as i64),
+        );
+        if let Some(game) = &self.last_game {
+            table.insert(String::from("last_game"), string(game));
+        }
+        table.insert(
+            String::from("cpu_boost"),
+            toml::Value::Boolean(self.cpu_boost),
+        );
+        let contents = toml::Value::Table(table).to_string();
+        if let Err(e) = write_atomic(&self.path, &contents) {
+            warn!("Failed to save settings '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Settings, AUTOSAVE_DEFAULT};
+    use crate::aspect::ScaleMode;
+    use crate::testing::TestDir;
+    use std::time::Duration;
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("settings");
+        let path = dir.join("settings.toml");
+
+        let mut settings = Settings::load(&path);
+        assert_eq!(settings.volume, None);
+        settings.volume = Some(35);
+        settings.scaling = Some(ScaleMode::Integer);
+        settings.autosave_interval = Duration::from_secs(30);
+        settings.last_game = Some(String::from("/roms/Tetris.gb"));
+        settings.cpu_boost = false;
+        settings.save();
+
+        assert_eq!(Settings::load(&path), settings);
+    }
+
+    #[test]
+    fn bad_files() {
+        let dir = TestDir::new("settings-bad");
+        let path = dir.join("settings.toml");
+        let defaults = Settings::load(&path);
+        assert_eq!(defaults.autosave_interval, AUTOSAVE_DEFAULT);
+        assert!(defaults.cpu_boost);
+
+        std::fs::write(&path, "volume = [35").unwrap();
+        assert_eq!(Settings::load(&path), defaults);
+
+        // Invalid settings use the defaults, the others are kept
+        std::fs::write(
+            &path,
+            r#"
+            volume = 150
+            scaling = "sideways"
+            autosave_secs = 0
+            cpu_boost = "no"
+            theme = "dark"
+            "#,
+        )
+        .unwrap();
+        let settings = Settings::load(&path);
+        assert_eq!(settings.volume, None);
+        assert_eq!(settings.scaling, None);
+        assert_eq!(settings.autosave_interval, AUTOSAVE_DEFAULT);
+        assert!(settings.cpu_boost);
+        assert_eq!(settings.theme.as_deref(), Some("dark"));
+        std::fs::write(&path, "volume = \"loud\"").unwrap();
+        assert_eq!(Settings::load(&path).volume, None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use crate::table::{ParseEntry, TomlTable};
+
+/// Performance of a game, totalled over all sessions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameStats {
+    pub sessions: u64,
+    pub frames: u64,
+    pub seconds: f64,
+    pub dropped: u64,
+    /// Frames drawn but replaced before the screen was ready for them
+    pub screen_dropped: u64,
+    pub underruns: u64,
+    /// Highest audio latency seen in any session, in milliseconds
+    pub peak_latency_ms: u64,
+}
+
+impl GameStats {
+    pub fn fps(&self) -> f64 {
+        if self.seconds > 0.0 {
+            self.frames as f64 / self.seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Short description for the menu
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{:.1} fps, {} drops, {} underruns",
+            self.fps(),
+            self.dropped,
+            self.underruns
+        );
+        if self.peak_latency_ms > 0 {
+            summary.push_str(&format!(", {} ms audio", self.peak_latency_ms));
+        }
+        summary
+    }
+
+    fn add(&mut self, other: &GameStats) {
+        self.sessions += other.sessions;
+        self.frames += other.frames;
+        self.seconds += other.seconds;
+        self.dropped += other.dropped;
+        self.screen_dropped += other.screen_dropped;
+        self.underruns += other.underruns;
+        self.peak_latency_ms = self.peak_latency_ms.max(other.peak_latency_ms);
+    }
+}
+
+impl ParseEntry for GameStats {
+    const NAME: &'static str = "stats";
+
+    fn parse(value: &toml::Value) -> Option<Self> {
+        let int = |key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_integer())
+                .and_then(|v| u64::try_from(v).ok())
+        };
+        Some(GameStats {
+            sessions: int("sessions")?,
+            frames: int("frames")?,
+            seconds: value.get("seconds").and_then(|v| v.as_float())?,
+            dropped: int("dropped")?,
+            // Not in stats saved by older versions
+            screen_dropped: int("screen_dropped").unwrap_or(0),
+            underruns: int("underruns")?,
+            peak_latency_ms: int("peak_latency_ms").unwrap_or(0),
+        })
+    }
+
+    fn to_value(&self) -> toml::Value {
+        // Counts will be well within range of i64
+        let int = |v: u64| toml::Value::Integer(v as i64);
+        let mut table = toml::value::Table::new();
+        table.insert(String::from("sessions"), int(self.sessions));
+        table.insert(String::from("frames"), int(self.frames));
+        table.insert(String::from("seconds"), toml::Value::Float(self.seconds));
+        table.insert(String::from("dropped"), int(self.dropped));
+        table.insert(String::from("screen_dropped"), int(self.screen_dropped));
+        table.insert(String::from("underruns"), int(self.underruns));
+        table.insert(String::from("peak_latency_ms"), int(self.peak_latency_ms));
+        toml::Value::Table(table)
+    }
+}
+
+/// Statistics for each game, by filename, stored as a TOML file
+pub struct Stats(TomlTable<GameStats>);
+
+impl Stats {
+    pub fn load(path: &Path) -> Self {
+        Stats(TomlTable::load(path))
+    }
+
+    // Games are stored by filename so stats follow games that move between
+    // ROM directories
+    fn key(game: &Path) -> String {
+        match game.file_name() {
+            Some(f) => f.to_string_lossy().into_owned(),
+            None => game.to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn get(&self, game: &Path) -> Option<&GameStats> {
+        self.0.get(&Self::key(game))
+    }
+
+    /// Add a session to the totals for a game
+    pub fn add(&mut self, game: &Path, session: &GameStats) {
+        self.0.get_or_default(Self::key(game)).add(session);
+    }
+
+    pub fn save(&self) {
+        self.0.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameStats, Stats};
+    use crate::testing::TestDir;
+    use std::path::Path;
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("stats");
+        let path = dir.join("stats.toml");
+        let session = GameStats {
+            sessions: 1,
+            frames: 600,
+            seconds: 10.0,
+            dropped: 2,
+            screen_dropped: 3,
+            underruns: 1,
+            peak_latency_ms: 80,
+        };
+
+        let mut stats = Stats::load(&path);
+        stats.add(Path::new("/roms/Game (USA).gb"), &session);
+        stats.add(Path::new("/media/usb/Game (USA).gb"), &session);
+        stats.save();
+
+        let stats = Stats::load(&path);
+        let game = stats.get(Path::new("Game (USA).gb")).expect("stats saved");
+        assert_eq!(game.sessions, 2);
+        assert_eq!(game.dropped, 4);
+        assert_eq!(game.screen_dropped, 6);
+        assert_eq!(game.peak_latency_ms, 80);
+        assert!((game.fps() - 60.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,183 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A value stored for each game in a `TomlTable`
+pub trait ParseEntry: Sized {
+    /// What the values are, for log messages
+    const NAME: &'static str;
+
+    /// `None` if the value is invalid, so it is dropped when loading
+    fn parse(value: &toml::Value) -> Option<Self>;
+    fn to_value(&self) -> toml::Value;
+}
+
+/// Values for each game, stored as a TOML file with a table per game. Invalid
+/// games are dropped when loading, and an unreadable file is started again.
+pub struct TomlTable<T> {
+    path: PathBuf,
+    entries: HashMap<String, T>,
+}
+
+impl<T: ParseEntry> TomlTable<T> {
+    pub fn load(path: &Path) -> Self {
+        let mut entries = HashMap::new();
+        if let Ok(contents) = std::fs::read_to_string(path) {
+            match contents.parse::<toml::Value>() {
+                Ok(toml::Value::Table(table)) => {
+                    for (game, value) in &table {
+                        match T::parse(value) {
+                            Some(e) => {
+                                entries.insert(game.clone(), e);
+                            }
+                            None => warn!("Invalid {} for '{}'", T::NAME, game),
+                        }
+                    }
+                }
+                Ok(_) => warn!("Invalid {} file '{}'", T::NAME, path.display()),
+                Err(e) => warn!("Invalid {} file '{}': {}", T::NAME, path.display(), e),
+            }
+        }
+        debug!("Loaded {} for {} games", T::NAME, entries.len());
+
+        TomlTable {
+            path: path.to_path_buf(),
+            entries,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: T) {
+        self.entries.insert(key, entry);
+    }
+
+    /// The value for a game, added as the default if there isn't one
+    pub fn get_or_default(&mut self, key: String) -> &mut T
+    where
+        T: Default,
+    {
+        self.entries.entry(key).or_default()
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &T) -> bool) {
+        self.entries.retain(|k, v| keep(k, v));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn save(&self) {
+        let table = self
+            .entries
+            .iter()
+            .map(|(k, v)| (k.clone(), v.to_value()))
+            .collect();
+        let contents = toml::Value::Table(table).to_string();
+        if let Err(e) = write_atomic(&self.path, &contents) {
+            warn!(
+                "Failed to save {} '{}': {}",
+                T::NAME,
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Write a file by replacing it with a complete copy, so losing power part way
+/// through (e.g. saving on low battery) leaves the old file rather than a
+/// truncated one
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    let written = File::create(&temp).and_then(|mut f| {
+        f.write_all(contents.as_bytes())?;
+        f.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| std::fs::rename(&temp, path)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseEntry, TomlTable};
+    use crate::testing::TestDir;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Plays(u64);
+
+    impl ParseEntry for Plays {
+        const NAME: &'static str = "plays";
+
+        fn parse(value: &toml::Value) -> Option<Self> {
+            let plays = value.get("plays")?.as_integer()?;
+            u64::try_from(plays).ok().map(Plays)
+        }
+
+        fn to_value(&self) -> toml::Value {
+            let mut table = toml::value::Table::new();
+            table.insert(String::from("plays"), toml::Value::Integer(self.0 as i64));
+            toml::Value::Table(table)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TestDir::new("table");
+        let path = dir.join("plays.toml");
+        let mut table = TomlTable::<Plays>::load(&path);
+        assert_eq!(table.iter().count(), 0);
+        table.get_or_default(String::from("Tetris.gb")).0 += 2;
+        table.insert(String::from("Zelda.gb"), Plays(1));
+        table.insert(String::from("gone.gb"), Plays(1));
+        table.retain(|k, _| k != "gone.gb");
+        table.save();
+
+        let table = TomlTable::<Plays>::load(&path);
+        assert_eq!(table.get("Tetris.gb"), Some(&Plays(2)));
+        assert_eq!(table.get("Zelda.gb"), Some(&Plays(1)));
+        assert_eq!(table.get("gone.gb"), None);
+        // Only the file itself is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn bad_files() {
+        let dir = TestDir::new("table-bad");
+        let path = dir.join("plays.toml");
+
+        // Started again if it can't be read, and saved over
+        std::fs::write(&path, "[\"Tetris.gb\"").unwrap();
+        let mut table = TomlTable::<Plays>::load(&path);
+        assert_eq!(table.iter().count(), 0);
+        table.insert(String::from("Tetris.gb"), Plays(1));
+        table.save();
+        assert_eq!(
+            TomlTable::<Plays>::load(&path).get("Tetris.gb"),
+            Some(&Plays(1))
+        );
+
+        // Games with invalid values are dropped, and the rest kept
+        std::fs::write(
+            &path,
+            "[\"Good.gb\"]\nplays = 1\n[\"Negative.gb\"]\nplays = -1\n\
+             [\"Wrong.gb\"]\nplays = \"one\"\n[\"Missing.gb\"]\n",
+        )
+        .unwrap();
+        let table = TomlTable::<Plays>::load(&path);
+        assert_eq!(table.iter().count(), 1);
+        assert_eq!(table.get("Good.gb"), Some(&Plays(1)));
+
+        std::fs::write(&path, "plays = 1").unwrap();
+        assert_eq!(TomlTable::<Plays>::load(&path).iter().count(), 0);
+    }
+}
@@ -44,6 +44,19 @@ impl CoreInfo {
         self.sys_info.library_name.clone()
     }
 
+    /// Whether this is the core a game's metadata prefers, by its library
+    /// name or file name, e.g. "Gambatte", "gambatte" or "gambatte_libretro"
+    pub fn matches(&self, preferred: &str) -> bool {
+        let stem = std::path::Path::new(&self.path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("");
+        let short = stem.strip_suffix("_libretro").unwrap_or(stem);
+        [self.sys_info.library_name.as_str(), stem, short]
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(preferred))
+    }
+
     pub fn path(&self) -> &str {
         &self.path
     }
@@ -10,16 +10,22 @@ use gamepie_core::log::gamepie_log_shim;
 use gamepie_core::portable::PStr;
 use gamepie_core::problem::Problem;
 use gamepie_libretrobind::bind::{
-    retro_controller_description, retro_controller_info, retro_core_option_definition,
-    retro_core_option_display, retro_core_option_value, retro_core_options_intl,
-    retro_game_geometry, retro_input_descriptor, retro_language_RETRO_LANGUAGE_ENGLISH,
-    retro_log_callback, retro_memory_map, retro_message, retro_pixel_format,
-    retro_pixel_format_RETRO_PIXEL_FORMAT_RGB565, retro_variable, RETRO_ENVIRONMENT_EXPERIMENTAL,
-    RETRO_ENVIRONMENT_PRIVATE,
+    retro_audio_buffer_status_callback, retro_controller_description, retro_controller_info,
+    retro_core_option_definition, retro_core_option_display, retro_core_option_v2_category,
+    retro_core_option_v2_definition, retro_core_option_value, retro_core_options_intl,
+    retro_core_options_v2, retro_core_options_v2_intl, retro_frame_time_callback,
+    retro_game_geometry, retro_game_info_ext, retro_input_descriptor, retro_keyboard_callback,
+    retro_language_RETRO_LANGUAGE_ENGLISH, retro_log_callback, retro_memory_map, retro_message,
+    retro_pixel_format, retro_pixel_format_RETRO_PIXEL_FORMAT_RGB565, retro_rumble_interface,
+    retro_sensor_interface, retro_subsystem_info, retro_system_av_info, retro_variable,
+    RETRO_ENVIRONMENT_EXPERIMENTAL, RETRO_ENVIRONMENT_PRIVATE,
 };
 use gamepie_libretrobind::enums::{identify_button, RetroDevice, RetroEnvironment};
+use gamepie_libretrobind::functions;
+use gamepie_libretrobind::memory::{MemoryDescriptor, MemoryMap};
 
 use crate::proxy::RetroProxy;
+use crate::subsystem::{Subsystem, SubsystemRom};
 
 // TODO could have the proxy in a RwLock so quicker for callbacks that
 // are only reading from the proxy. Or RefCell to allow mutating just the
@@ -83,6 +89,200 @@ unsafe fn set_variables_v1(
     Ok(())
 }
 
+// A string from the core that may be NULL
+unsafe fn opt_pstr<'a>(
+    ptr: *const std::os::raw::c_char,
+) -> Result<Option<PStr<'a>>, Box<dyn Error>> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        Ok(Some(PStr::from_ptr(ptr)?))
+    }
+}
+
+unsafe fn set_variables_v2(
+    options: *const retro_core_options_v2,
+    proxy: &mut RetroProxy,
+) -> Result<(), Box<dyn Error>> {
+    let categories = (*options).categories;
+    if !categories.is_null() {
+        let mut offset = 0;
+        let mut cat: retro_core_option_v2_category = *categories.offset(offset);
+        while !cat.key.is_null() {
+            let key = PStr::from_ptr(cat.key)?;
+            let desc = opt_pstr(cat.desc)?.map(String::from).unwrap_or_default();
+            let info = opt_pstr(cat.info)?.map(String::from).unwrap_or_default();
+            proxy.add_var_category(&key, &desc, &info);
+
+            offset += 1;
+            cat = *categories.offset(offset);
+        }
+    }
+
+    let vars = (*options).definitions;
+    let mut offset = 0;
+    let mut var: retro_core_option_v2_definition = *vars.offset(offset);
+    while !var.key.is_null() {
+        let key = PStr::from_ptr(var.key)?;
+        let descr = PStr::from_ptr(var.desc)?;
+        let info = opt_pstr(var.info)?.map(String::from).unwrap_or_default();
+        let mut values = Vec::new();
+        for v in var.values.iter().take_while(|v| !v.value.is_null()) {
+            values.push((PStr::from_ptr(v.value)?, opt_pstr(v.label)?));
+        }
+        let default = opt_pstr(var.default_value)?;
+        let category = opt_pstr(var.category_key)?.map(String::from);
+
+        proxy.add_var_v2(
+            &key,
+            &descr,
+            &info,
+            values.as_slice(),
+            default.as_ref(),
+            category.as_deref(),
+        );
+
+        offset += 1;
+        var = *vars.offset(offset);
+    }
+    proxy.log_vars();
+    Ok(())
+}
+
+unsafe fn set_subsystems(
+    info: *const retro_subsystem_info,
+    proxy: &mut RetroProxy,
+) -> Result<(), Box<dyn Error>> {
+    let string = |ptr: *const std::os::raw::c_char| {
+        if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    };
+    let mut subsystems = Vec::new();
+    let mut offset = 0;
+    let mut sub: retro_subsystem_info = *info.offset(offset);
+    // Terminated by an entry of zeros
+    while !sub.ident.is_null() {
+        let mut roms = Vec::new();
+        for i in 0..sub.num_roms {
+            let rom = *sub.roms.offset(i.try_into()?);
+            roms.push(SubsystemRom {
+                desc: string(rom.desc),
+                extensions: string(rom.valid_extensions),
+                need_fullpath: rom.need_fullpath,
+                required: rom.required,
+            });
+        }
+        subsystems.push(Subsystem {
+            ident: string(sub.ident),
+            desc: string(sub.desc),
+            id: sub.id,
+            roms,
+        });
+
+        offset += 1;
+        sub = *info.offset(offset);
+    }
+    proxy.set_subsystems(subsystems);
+    Ok(())
+}
+
+unsafe fn trace_str(ptr: *const std::os::raw::c_char) -> String {
+    if ptr.is_null() {
+        String::from("null")
+    } else {
+        format!("\"{}\"", CStr::from_ptr(ptr).to_string_lossy())
+    }
+}
+
+// Decode the input of an environment command for the trace
+unsafe fn trace_args(c: &Option<RetroEnvironment>, data: *mut std::os::raw::c_void) -> String {
+    if data.is_null() {
+        return String::from("null");
+    }
+    match c {
+        Some(RetroEnvironment::GetVariable) => {
+            let var = data as *const retro_variable;
+            format!("key={}", trace_str((*var).key))
+        }
+        Some(RetroEnvironment::SetVariable) => {
+            let var = data as *const retro_variable;
+            format!("{}={}", trace_str((*var).key), trace_str((*var).value))
+        }
+        Some(RetroEnvironment::SetGeometry) => {
+            let g = data as *const retro_game_geometry;
+            format!(
+                "{}x{} (max {}x{}) aspect {}",
+                (*g).base_width,
+                (*g).base_height,
+                (*g).max_width,
+                (*g).max_height,
+                (*g).aspect_ratio
+            )
+        }
+        Some(RetroEnvironment::SetSystemAvInfo) => {
+            let av = data as *const retro_system_av_info;
+            format!(
+                "{}x{} (max {}x{}) {} fps {} Hz",
+                (*av).geometry.base_width,
+                (*av).geometry.base_height,
+                (*av).geometry.max_width,
+                (*av).geometry.max_height,
+                (*av).timing.fps,
+                (*av).timing.sample_rate
+            )
+        }
+        Some(RetroEnvironment::SetPixelFormat) => {
+            format!("format={}", *(data as *const retro_pixel_format))
+        }
+        Some(RetroEnvironment::SetPerformanceLevel) | Some(RetroEnvironment::SetRotation) => {
+            format!("{}", *(data as *const std::os::raw::c_uint))
+        }
+        Some(RetroEnvironment::SetMessage) => {
+            let msg = data as *const retro_message;
+            format!("{} for {} frames", trace_str((*msg).msg), (*msg).frames)
+        }
+        Some(RetroEnvironment::SetCoreOptionsDisplay) => {
+            let disp = data as *const retro_core_option_display;
+            format!("{} visible={}", trace_str((*disp).key), (*disp).visible)
+        }
+        _ => format!("{:p}", data),
+    }
+}
+
+// Decode what was returned to the core through the data pointer
+unsafe fn trace_result(
+    c: &Option<RetroEnvironment>,
+    data: *mut std::os::raw::c_void,
+    ret: bool,
+) -> String {
+    if data.is_null() {
+        return String::new();
+    }
+    match c {
+        Some(RetroEnvironment::GetVariable) if ret => {
+            trace_str((*(data as *const retro_variable)).value)
+        }
+        Some(RetroEnvironment::GetSystemDirectory) | Some(RetroEnvironment::GetSaveDirectory) => {
+            trace_str(*(data as *const *const std::os::raw::c_char))
+        }
+        Some(RetroEnvironment::GetVariableUpdate)
+        | Some(RetroEnvironment::GetCanDupe)
+        | Some(RetroEnvironment::GetFastforwarding) => {
+            format!("{}", *(data as *const bool))
+        }
+        Some(RetroEnvironment::GetAudioVideoEnable) => {
+            format!("{:#x}", *(data as *const std::os::raw::c_int))
+        }
+        Some(RetroEnvironment::GetCoreOptionsVersion) | Some(RetroEnvironment::GetLanguage) => {
+            format!("{}", *(data as *const std::os::raw::c_uint))
+        }
+        _ => String::new(),
+    }
+}
+
 /// Libretro Environment callback
 ///
 /// # Safety
@@ -92,6 +292,32 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
     cmd: ::std::os::raw::c_uint,
     data: *mut ::std::os::raw::c_void,
     proxy: &mut RetroProxy,
+) -> bool {
+    if proxy.env_trace().is_none() {
+        let ret = environment(cmd, data, proxy);
+        proxy.note_env_call(cmd, ret);
+        return ret;
+    }
+
+    let c = num::FromPrimitive::from_u32(cmd);
+    let args = trace_args(&c, data);
+    let ret = environment(cmd, data, proxy);
+    let result = trace_result(&c, data, ret);
+    let name = match &c {
+        Some(c) => format!("{:?}", c),
+        None => String::from("Unknown"),
+    };
+    if let Some(t) = proxy.env_trace() {
+        t.record(cmd, &name, &args, ret, &result);
+    }
+    proxy.note_env_call(cmd, ret);
+    ret
+}
+
+unsafe fn environment(
+    cmd: ::std::os::raw::c_uint,
+    data: *mut ::std::os::raw::c_void,
+    proxy: &mut RetroProxy,
 ) -> bool {
     let c = num::FromPrimitive::from_u32(cmd);
     let experimental = (cmd & RETRO_ENVIRONMENT_EXPERIMENTAL) == RETRO_ENVIRONMENT_EXPERIMENTAL;
@@ -113,34 +339,45 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
             false
         }
         Some(RetroEnvironment::SetGeometry) => {
-            let var = data as *const retro_game_geometry;
-            let new_width = (*var).base_width;
-            let new_height = (*var).base_height;
-            match proxy.get_av() {
-                Some(av) => {
-                    let (width, height) = (av.geometry.base_width, av.geometry.base_height);
-                    if new_width != width || new_height != height {
-                        warn!(
-                            "Trying to set geometry to {}x{}, doesn't match expected dimensions {}x{}",
-                            (*var).base_width,
-                            (*var).base_height,
-                            width,
-                            height
-                        );
-                        proxy.problem(Problem::warn(ScreenToast::error(ScreenMessage::VideoIssue)));
-                        false
-                    } else {
-                        true
-                    }
-                }
-                None => true,
-            }
+            let geometry = functions::game_geometry(&*(data as *const retro_game_geometry));
+            debug!(
+                "Geometry changed to {}x{}",
+                geometry.base_width, geometry.base_height
+            );
+            proxy.set_geometry(geometry);
+            true
+        }
+        Some(RetroEnvironment::SetSystemAvInfo) => {
+            let av = functions::av_info(&*(data as *const retro_system_av_info));
+            debug!(
+                "AV info changed to {}x{} at {} fps, {} Hz",
+                av.geometry.base_width,
+                av.geometry.base_height,
+                av.timing.fps,
+                av.timing.sample_rate
+            );
+            proxy.change_av(av)
         }
         Some(RetroEnvironment::GetSystemDirectory) => {
             let var = data as *mut *const std::os::raw::c_char;
             *var = proxy.sys_dir().as_ptr();
             false
         }
+        Some(RetroEnvironment::GetSaveDirectory) => {
+            // For cores that write their own saves, rather than through
+            // RETRO_MEMORY_SAVE_RAM
+            let var = data as *mut *const std::os::raw::c_char;
+            *var = proxy.save_dir().as_ptr();
+            true
+        }
+        Some(RetroEnvironment::GetGameInfoExt) => match proxy.game_info() {
+            Some(info) => {
+                *(data as *mut *const retro_game_info_ext) = info;
+                true
+            }
+            // Only available while loading a game
+            None => false,
+        },
         Some(RetroEnvironment::GetVariable) => {
             let var = data as *mut retro_variable;
 
@@ -214,6 +451,26 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
                 })
                 .is_ok()
         }
+        Some(RetroEnvironment::SetCoreOptionsV2) => {
+            debug!("Setting core options (v2)");
+            let options = data as *const retro_core_options_v2;
+            // True tells the core that categories are supported
+            set_variables_v2(options, proxy)
+                .map_err(|e| {
+                    error!("Variable error: {}", e);
+                })
+                .is_ok()
+        }
+        Some(RetroEnvironment::SetCoreOptionsV2Intl) => {
+            debug!("Setting core options (v2-intl)");
+            let options_intl = data as *const retro_core_options_v2_intl;
+            // US is default English options and must be present
+            set_variables_v2((*options_intl).us, proxy)
+                .map_err(|e| {
+                    error!("Variable error: {}", e);
+                })
+                .is_ok()
+        }
         Some(RetroEnvironment::SetPixelFormat) => {
             let pfmt = data as *const retro_pixel_format;
             if *pfmt == retro_pixel_format_RETRO_PIXEL_FORMAT_RGB565 {
@@ -241,6 +498,14 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
             *avint = val;
             true
         }
+        Some(RetroEnvironment::SetSubsystemInfo) => {
+            let info = data as *const retro_subsystem_info;
+            set_subsystems(info, proxy)
+                .map_err(|e| {
+                    error!("Subsystem error: {}", e);
+                })
+                .is_ok()
+        }
         Some(RetroEnvironment::SetControllerInfo) => {
             let mut any_error = false;
             let info_arr = data as *const retro_controller_info;
@@ -270,30 +535,17 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
         }
         Some(RetroEnvironment::SetMemoryMaps) => {
             let maps = data as *const retro_memory_map;
-            let num = (*maps).num_descriptors;
-            if num != 0 {
-                debug!("Memory map:")
-            }
-            for i in 0..num {
-                let isz: isize = i.try_into().expect("too much memory");
-                let map = (*maps).descriptors.offset(isz);
-                let start = (*map).start;
-                let end = (*map).start + (*map).len;
-                if (*map).addrspace.is_null() {
-                    debug!("  {:#010x} -> {:#010x}", start, end);
-                } else {
-                    let n = CStr::from_ptr((*map).addrspace);
-                    let name = n.to_str().expect("non UTF-8");
-                    debug!("  {:#010x} -> {:#010x} {}", start, end, name);
-                }
-            }
+            let num = (*maps).num_descriptors as usize;
+            let descriptors = (0..num)
+                .map(|i| MemoryDescriptor::new(&*(*maps).descriptors.add(i)))
+                .collect();
+            proxy.set_memory_map(MemoryMap::new(descriptors));
             true
         }
         Some(RetroEnvironment::GetCoreOptionsVersion) => {
-            // Not many cores seem to support v2 options,
-            // so only support v1.
+            // v2 adds categories, cores fall back to v1 or v0 if older
             let version = data as *mut u32;
-            *version = 1;
+            *version = 2;
             true
         }
         Some(RetroEnvironment::GetVfsInterface) => {
@@ -305,9 +557,15 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
             *dupe = true;
             true
         }
+        Some(RetroEnvironment::GetFastforwarding) => {
+            let fast_forward = data as *mut bool;
+            *fast_forward = proxy.fast_forwarding();
+            true
+        }
         Some(RetroEnvironment::SetPerformanceLevel) => {
             let perf = data as *const ::std::os::raw::c_uint;
             info!("Performance level: {}", *perf);
+            proxy.set_performance_level(*perf);
             true
         }
         Some(RetroEnvironment::GetLanguage) => {
@@ -370,8 +628,49 @@ pub unsafe extern "C" fn retro_environment_callback_inner(
             }
         }
         Some(RetroEnvironment::GetInputBitmasks) => true,
+        Some(RetroEnvironment::SetKeyboardCallback) => {
+            let cb = data as *const retro_keyboard_callback;
+            proxy.set_keyboard_callback((*cb).callback);
+            true
+        }
+        Some(RetroEnvironment::SetFrameTimeCallback) => {
+            let cb = data as *const retro_frame_time_callback;
+            debug!("Frame time reference: {} us", (*cb).reference);
+            proxy.set_frame_time_callback(*cb);
+            true
+        }
+        Some(RetroEnvironment::SetAudioBufferStatusCallback) => {
+            let cb = data as *const retro_audio_buffer_status_callback;
+            proxy.set_audio_buffer_status_callback(cb.as_ref().and_then(|cb| cb.callback));
+            true
+        }
+        Some(RetroEnvironment::SetMinimumAudioLatency) => {
+            let ms = data as *const u32;
+            match ms.as_ref() {
+                Some(ms) => {
+                    debug!("Minimum audio latency: {} ms", ms);
+                    proxy.set_min_audio_latency(*ms);
+                    true
+                }
+                None => false,
+            }
+        }
         Some(RetroEnvironment::SetSupportAchievements) => false,
-        Some(RetroEnvironment::GetRumbleInterface) => false,
+        Some(RetroEnvironment::GetSensorInterface) => match proxy.sensor_interface() {
+            Some(sensor) => {
+                *(data as *mut retro_sensor_interface) = sensor;
+                true
+            }
+            None => false,
+        },
+        Some(RetroEnvironment::GetRumbleInterface) => match proxy.rumble_callback() {
+            Some(set_rumble_state) => {
+                let rumble = data as *mut retro_rumble_interface;
+                (*rumble).set_rumble_state = Some(set_rumble_state);
+                true
+            }
+            None => false,
+        },
         Some(c) => {
             warn!("Unsupported command: {:?} ({},{})", c, p_str, e_str);
             false
@@ -1,3 +1,8 @@
 pub mod callbacks;
+mod presets;
 pub mod proxy;
+pub mod requirements;
+pub mod stats;
+pub mod subsystem;
+mod trace;
 mod vars;
@@ -0,0 +1,37 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+
+// Option presets for popular cores, tuned for the Pi Zero. Keyed on the
+// lower-case library name reported by the core.
+const PRESETS: &[(&str, &str)] = &[
+    ("gambatte", include_str!("../presets/gambatte.toml")),
+    ("mgba", include_str!("../presets/mgba.toml")),
+    ("quicknes", include_str!("../presets/quicknes.toml")),
+];
+
+/// Get the preset option values for a core, empty if there are no presets
+/// for this core.
+pub fn get_presets(library_name: &str) -> HashMap<String, String> {
+    let mut presets = HashMap::new();
+    let name = library_name.to_lowercase();
+
+    if let Some((_, preset)) = PRESETS.iter().find(|(n, _)| *n == name) {
+        match preset.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => {
+                for (k, v) in table {
+                    match v.as_str() {
+                        Some(v) => {
+                            presets.insert(k, String::from(v));
+                        }
+                        None => warn!("Preset '{}' is not a string", k),
+                    }
+                }
+            }
+            Ok(_) => warn!("Presets for '{}' are not a table", name),
+            Err(e) => warn!("Invalid presets for '{}': {}", name, e),
+        }
+        debug!("Found {} option presets for '{}'", presets.len(), name);
+    }
+
+    presets
+}
@@ -1,17 +1,35 @@
 use log::{error, info, warn};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use gamepie_controller::Controller;
-use gamepie_core::commands::{AudioMsg, ScreenMessage, ScreenToast};
+pub use gamepie_controller::MAX_PORTS;
+use gamepie_controller::{Controller, KeyEvent};
+use gamepie_core::aspect::{AspectRatio, ScaleMode};
+use gamepie_core::commands::{AudioCmd, AudioMsg, ScreenMessage, ScreenToast};
+use gamepie_core::config::InputConfig;
 use gamepie_core::portable::{PStr, PString};
 use gamepie_core::problem::Problem;
-use gamepie_libretrobind::enums::RetroPadButton;
-use gamepie_libretrobind::types::RetroSystemAvInfo;
-use gamepie_screen::Screen;
+use gamepie_libretrobind::bind::{
+    retro_audio_buffer_status_callback_t, retro_frame_time_callback, retro_game_info_ext,
+    retro_keyboard_event_t, retro_rumble_effect, retro_sensor_interface, retro_set_rumble_state_t,
+};
+use gamepie_libretrobind::enums::{RetroEnvironment, RetroPadButton};
+use gamepie_libretrobind::functions::RetroGameInfoExt;
+use gamepie_libretrobind::memory::MemoryMap;
+use gamepie_libretrobind::types::{RetroGameGeometry, RetroSystemAvInfo, RetroSystemTiming};
+use gamepie_libretrobind::utils::SaveRegion;
+use gamepie_screen::{FrameTap, RecordFrame, Screen};
 
+use crate::stats::{FrameStats, StatsCollector};
+use crate::subsystem::Subsystem;
+use crate::trace::EnvTrace;
 use crate::vars::RetroVars;
 
+// Environment calls kept for crash reports
+const RECENT_ENV_CALLS: usize = 32;
+
 #[derive(PartialEq, Eq, Hash)]
 pub enum ProxyWarning {
     DevicePort,
@@ -20,37 +38,103 @@ pub enum ProxyWarning {
 
 pub struct RetroProxy {
     system_dir: PString,
+    save_dir: PString,
     error_channel: mpsc::Sender<Problem>,
     vars: RetroVars,
     audio_en: bool,
     video_en: bool,
+    display_en: bool,
+    skip_frame: bool,
+    fast_forward: bool,
     audio: mpsc::Sender<AudioMsg>,
+    // Copy of the audio while recording video
+    record_audio: Option<mpsc::Sender<Vec<i16>>>,
     controller: Controller,
     screen: Option<Screen>,
     av: Option<RetroSystemAvInfo>,
+    // Timing changed by the core since the frontend last looked
+    timing_changed: bool,
+    // Aspect ratio override from the game's metadata
+    aspect: Option<AspectRatio>,
+    scale_mode: ScaleMode,
     warnings: HashSet<ProxyWarning>,
+    env_trace: Option<EnvTrace>,
+    // Most recent environment calls, with their result and how many times
+    // they were repeated
+    recent_env: VecDeque<(u32, bool, u32)>,
+    // Every environment call, with how many times it was made and how many
+    // of those succeeded, when testing a core
+    env_counts: Option<BTreeMap<u32, (u32, u32)>>,
+    // Frames from the video callback and stereo frames of audio
+    video_frames: u64,
+    audio_frames: u64,
+    stats: StatsCollector,
+    // Frame stats shown over the game
+    hud: bool,
+    // How demanding the core says the game is, from SetPerformanceLevel
+    performance_level: Option<u32>,
+    rumble: retro_set_rumble_state_t,
+    sensor: Option<retro_sensor_interface>,
+    keyboard: retro_keyboard_event_t,
+    frame_time: Option<retro_frame_time_callback>,
+    audio_buffer_status: retro_audio_buffer_status_callback_t,
+    memory_map: MemoryMap,
+    subsystems: Vec<Subsystem>,
+    game_info: Option<RetroGameInfoExt>,
 }
 
 impl RetroProxy {
     pub fn new(
         system_dir: PString,
+        save_dir: PString,
         screen: Option<Screen>,
         error_channel: mpsc::Sender<Problem>,
         audio_channel: mpsc::Sender<AudioMsg>,
+        input: &InputConfig,
     ) -> Self {
-        let controller = Controller::new();
+        let controller =
+            Controller::new(input.poll_interval, &input.mappings, error_channel.clone());
+        // The screen may have been lent to an earlier proxy showing stats
+        let mut screen = screen;
+        if let Some(screen) = &mut screen {
+            screen.set_hud(None);
+        }
 
         RetroProxy {
             system_dir,
+            save_dir,
             error_channel,
             vars: RetroVars::new(),
             audio_en: true,
             video_en: true,
+            display_en: true,
+            skip_frame: false,
+            fast_forward: false,
             audio: audio_channel,
+            record_audio: None,
             controller,
             screen,
             av: None,
+            timing_changed: false,
+            aspect: None,
+            scale_mode: ScaleMode::Crop,
             warnings: HashSet::new(),
+            env_trace: None,
+            recent_env: VecDeque::new(),
+            env_counts: None,
+            video_frames: 0,
+            audio_frames: 0,
+            stats: StatsCollector::new(),
+            hud: false,
+            performance_level: None,
+            rumble: None,
+            sensor: None,
+            keyboard: None,
+            frame_time: None,
+            audio_buffer_status: None,
+            memory_map: MemoryMap::default(),
+            subsystems: Vec::new(),
+            game_info: None,
         }
     }
 
@@ -63,6 +147,164 @@ impl RetroProxy {
         &self.system_dir
     }
 
+    pub fn save_dir(&self) -> &PString {
+        &self.save_dir
+    }
+
+    /// Write environment calls from the core to a trace file
+    pub fn trace_env(&mut self, path: &Path) {
+        match EnvTrace::create(path) {
+            Ok(t) => self.env_trace = Some(t),
+            Err(e) => warn!("Failed to create trace '{}': {}", path.display(), e),
+        }
+    }
+
+    pub(crate) fn env_trace(&mut self) -> Option<&mut EnvTrace> {
+        self.env_trace.as_mut()
+    }
+
+    // Note an environment call for crash reports, repeats of the last call
+    // are counted rather than kept
+    pub(crate) fn note_env_call(&mut self, cmd: u32, ret: bool) {
+        if let Some(counts) = &mut self.env_counts {
+            let (calls, supported) = counts.entry(cmd).or_default();
+            *calls += 1;
+            *supported += u32::from(ret);
+        }
+        match self.recent_env.back_mut() {
+            Some((c, r, count)) if *c == cmd && *r == ret => *count += 1,
+            _ => {
+                if self.recent_env.len() == RECENT_ENV_CALLS {
+                    self.recent_env.pop_front();
+                }
+                self.recent_env.push_back((cmd, ret, 1));
+            }
+        }
+    }
+
+    /// The most recent environment calls from the core, oldest first
+    pub fn recent_env_calls(&self) -> Vec<String> {
+        self.recent_env
+            .iter()
+            .map(|(cmd, ret, count)| {
+                let c: Option<RetroEnvironment> = num::FromPrimitive::from_u32(*cmd);
+                let name = match c {
+                    Some(c) => format!("{:?}", c),
+                    None => format!("Unknown ({})", cmd),
+                };
+                match count {
+                    1 => format!("{} -> {}", name, ret),
+                    n => format!("{} -> {} (x{})", name, ret, n),
+                }
+            })
+            .collect()
+    }
+
+    /// Count every environment call from now on, for `env_call_counts`
+    pub fn count_env_calls(&mut self) {
+        self.env_counts = Some(BTreeMap::new());
+    }
+
+    /// Name of each environment call made since `count_env_calls`, with how
+    /// many times it was made and how many of those succeeded
+    pub fn env_call_counts(&self) -> Vec<(String, u32, u32)> {
+        self.env_counts
+            .iter()
+            .flatten()
+            .map(|(cmd, (calls, supported))| {
+                let c: Option<RetroEnvironment> = num::FromPrimitive::from_u32(*cmd);
+                let name = match c {
+                    Some(c) => format!("{:?}", c),
+                    None => format!("Unknown ({})", cmd),
+                };
+                (name, *calls, *supported)
+            })
+            .collect()
+    }
+
+    /// Frames the core has sent to the video callback
+    pub fn video_frames(&self) -> u64 {
+        self.video_frames
+    }
+
+    /// Stereo frames the core has sent to the audio callbacks
+    pub fn audio_frames(&self) -> u64 {
+        self.audio_frames
+    }
+
+    /// Add a frame run by the core to the stats, `run` being the time the
+    /// core took including drawing and `audio` how much audio is queued
+    /// after it. Returns the stats of the last second once it is over.
+    pub fn end_frame(&mut self, run: Duration, audio: Duration) -> Option<FrameStats> {
+        let stats = self.stats.frame(run, audio, Instant::now());
+        if let (Some(stats), true, Some(screen)) = (stats, self.hud, &mut self.screen) {
+            screen.set_hud(Some(stats.hud()));
+        }
+        stats
+    }
+
+    /// Count a frame that took longer than the frame time
+    pub fn frame_dropped(&mut self) {
+        self.stats.dropped();
+    }
+
+    /// Stats of the last full second the core ran for
+    pub fn frame_stats(&self) -> Option<FrameStats> {
+        self.stats.last()
+    }
+
+    /// Show the frame stats over the game, updated each second
+    pub fn set_hud(&mut self, hud: bool) {
+        self.hud = hud;
+        let lines = self.stats.last().filter(|_| hud).map(|s| s.hud());
+        if let Some(screen) = &mut self.screen {
+            screen.set_hud(lines);
+        }
+    }
+
+    pub fn hud(&self) -> bool {
+        self.hud
+    }
+
+    pub fn set_performance_level(&mut self, level: u32) {
+        self.performance_level = Some(level);
+    }
+
+    /// Performance level the core gave for the game, higher levels need
+    /// more CPU. `None` if the core didn't say.
+    pub fn performance_level(&self) -> Option<u32> {
+        self.performance_level
+    }
+
+    /// Use the shipped option presets for this core in place of the core's
+    /// own defaults.
+    pub fn use_presets(&mut self, library_name: &str) {
+        self.vars
+            .set_presets(crate::presets::get_presets(library_name));
+    }
+
+    /// Use option values saved to `path` by a previous session, and save any
+    /// changes back to it with `save_options`.
+    pub fn use_saved_options(&mut self, path: &Path) {
+        self.vars.load(path);
+    }
+
+    /// Use option values from the game's metadata over both the presets and
+    /// saved values. These aren't saved, so other games keep their values.
+    pub fn use_game_options(&mut self, options: HashMap<String, String>) {
+        self.vars.set_overrides(options);
+    }
+
+    pub fn save_options(&self) {
+        self.vars.save();
+    }
+
+    /// Put the core's options back to their defaults, deleting the values
+    /// saved for it
+    pub fn reset_options(&mut self) {
+        self.vars.reset();
+    }
+
     pub fn add_var_v0(&mut self, key: &PStr, descr: &PStr) {
         self.vars.add_v0(key, descr);
     }
@@ -78,15 +320,30 @@ impl RetroProxy {
         self.vars.add_v1(key, descr, info, values, default);
     }
 
+    pub fn add_var_v2(
+        &mut self,
+        key: &PStr,
+        descr: &PStr,
+        info: &str,
+        values: &[(PStr, Option<PStr>)],
+        default: Option<&PStr>,
+        category: Option<&str>,
+    ) {
+        self.vars
+            .add_v2(key, descr, info, values, default, category);
+    }
+
+    pub fn add_var_category(&mut self, key: &PStr, desc: &str, info: &str) {
+        self.vars.add_category(key, desc, info);
+    }
+
     pub fn get_var(&self, k: &str) -> *const ::std::os::raw::c_char {
         self.vars.get_var(k)
     }
 
     pub fn log_vars(&self) {
         info!("Vars:");
-        for v in self.vars.get_vars() {
-            v.log_var();
-        }
+        self.vars.log();
     }
 
     pub fn vars_updated(&mut self) -> bool {
@@ -102,22 +359,210 @@ impl RetroProxy {
     }
 
     pub fn audio_enabled(&self) -> bool {
-        self.audio_en
+        self.audio_en && !self.fast_forward
     }
 
     pub fn video_enabled(&self) -> bool {
-        self.video_en
+        self.video_en && !self.skip_frame
+    }
+
+    /// Disable video for audio only use, reported to the core through
+    /// GET_AUDIO_VIDEO_ENABLE. Frames from cores that render anyway are
+    /// dropped.
+    pub fn set_video_enabled(&mut self, enabled: bool) {
+        self.video_en = enabled;
+    }
+
+    /// Skip rendering the next frame, cores that check GET_AUDIO_VIDEO_ENABLE
+    /// can save the work of rendering it.
+    pub fn set_skip_frame(&mut self, skip: bool) {
+        self.skip_frame = skip;
+    }
+
+    /// Whether the game is being fast-forwarded, reported to the core
+    /// through GET_FASTFORWARDING. Audio is dropped until it stops.
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn fast_forwarding(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Turn the display off, frames are dropped and the screen is blanked
+    /// while the core carries on as normal.
+    pub fn set_display_enabled(&mut self, enabled: bool) {
+        self.display_en = enabled;
+        if !enabled {
+            if let Some(screen) = self.screen.as_mut() {
+                screen.blank();
+            }
+        }
+    }
+
+    /// Set the function given to cores that ask for the rumble interface,
+    /// cores aren't offered rumble if there is none.
+    pub fn set_rumble_callback(&mut self, cb: retro_set_rumble_state_t) {
+        self.rumble = cb;
+    }
+
+    pub fn rumble_callback(&self) -> retro_set_rumble_state_t {
+        self.rumble
+    }
+
+    /// Set the functions given to cores that ask for the sensor interface,
+    /// cores aren't offered sensors if there is no motion sensor.
+    pub fn set_sensor_interface(&mut self, sensor: Option<retro_sensor_interface>) {
+        self.sensor = sensor;
+    }
+
+    pub fn sensor_interface(&self) -> Option<retro_sensor_interface> {
+        self.sensor
+    }
+
+    /// Set the memory map given by the core, or clear it once the core's
+    /// memory is gone
+    pub fn set_memory_map(&mut self, map: MemoryMap) {
+        self.memory_map = map;
+    }
+
+    /// Save RAM regions from the core's memory map
+    pub fn save_regions(&self) -> Vec<SaveRegion> {
+        self.memory_map.save_regions()
+    }
+
+    /// Read bytes at an emulated address through the core's memory map,
+    /// returns how many were read. Only valid while the game is loaded.
+    pub fn peek_memory(&self, addr: usize, buf: &mut [u8]) -> usize {
+        self.memory_map.peek(addr, buf)
+    }
+
+    pub fn set_subsystems(&mut self, subsystems: Vec<Subsystem>) {
+        info!("Subsystems:");
+        for s in &subsystems {
+            s.log();
+        }
+        self.subsystems = subsystems;
+    }
+
+    /// Find a subsystem advertised by the core by its identifier
+    pub fn subsystem(&self, ident: &str) -> Option<Subsystem> {
+        self.subsystems.iter().find(|s| s.ident == ident).cloned()
+    }
+
+    /// Set the games being loaded, for cores that ask for extended game
+    /// information while loading. Cleared once the games are loaded.
+    pub fn set_game_info(&mut self, info: Option<RetroGameInfoExt>) {
+        self.game_info = info;
+    }
+
+    pub fn game_info(&self) -> Option<*const retro_game_info_ext> {
+        self.game_info.as_ref().map(|g| g.as_ptr())
+    }
+
+    /// Set the core's callback for keyboard events
+    pub fn set_keyboard_callback(&mut self, cb: retro_keyboard_event_t) {
+        self.keyboard = cb;
+    }
+
+    pub fn keyboard_callback(&self) -> retro_keyboard_event_t {
+        self.keyboard
+    }
+
+    /// Set the core's callback for the time between frames, used by cores
+    /// that pace themselves
+    pub fn set_frame_time_callback(&mut self, cb: retro_frame_time_callback) {
+        self.frame_time = cb.callback.map(|_| cb);
+    }
+
+    pub fn frame_time_callback(&self) -> Option<retro_frame_time_callback> {
+        self.frame_time
+    }
+
+    /// Set the core's callback for the audio queue's fill level, the audio
+    /// thread only reports it while there is one
+    pub fn set_audio_buffer_status_callback(&mut self, cb: retro_audio_buffer_status_callback_t) {
+        self.audio_buffer_status = cb;
+        let msg = AudioMsg::Command(AudioCmd::ReportStatus(cb.is_some()));
+        if self.audio.send(msg).is_err() {
+            warn!("Failed to send audio status reporting");
+        }
+    }
+
+    pub fn audio_buffer_status_callback(&self) -> retro_audio_buffer_status_callback_t {
+        self.audio_buffer_status
+    }
+
+    /// Set the least audio latency the core wants, in milliseconds
+    pub fn set_min_audio_latency(&mut self, ms: u32) {
+        if self
+            .audio
+            .send(AudioMsg::Command(AudioCmd::MinLatency(ms)))
+            .is_err()
+        {
+            warn!("Failed to send minimum audio latency");
+        }
     }
 
     pub fn input_poll(&mut self) {
         self.controller.input_poll();
     }
 
-    pub fn input_state(&self, id: RetroPadButton) -> i16 {
-        self.controller.input_state(id)
+    /// Type text into the core, as key presses over the next polls
+    pub fn type_text(&mut self, text: &str) {
+        self.controller.type_text(text);
+    }
+
+    /// Key changes since the last call, to pass to the keyboard callback
+    pub fn take_key_events(&mut self) -> Vec<KeyEvent> {
+        self.controller.take_key_events()
+    }
+
+    /// Whether player 1 has a controller with analog sticks
+    pub fn has_analog(&self) -> bool {
+        self.controller.has_analog()
+    }
+
+    /// Whether a controller has been unplugged since this was last called
+    pub fn take_disconnected(&self) -> bool {
+        self.controller.take_disconnected()
+    }
+
+    /// Start or stop collecting raw controller events for the input tester
+    pub fn watch_raw_input(&self, watch: bool) {
+        self.controller.watch_raw(watch)
     }
 
-    pub fn audio_sample(&self, s: Vec<i16>) {
+    pub fn take_raw_input(&self) -> Vec<String> {
+        self.controller.take_raw_events()
+    }
+
+    pub fn set_rumble(&self, port: u32, effect: retro_rumble_effect, strength: u16) {
+        self.controller.set_rumble(port, effect, strength)
+    }
+
+    pub fn key_state(&self, keycode: u32) -> i16 {
+        self.controller.key_state(keycode)
+    }
+
+    pub fn input_state(&self, port: u32, id: RetroPadButton) -> i16 {
+        self.controller.input_state(port, id)
+    }
+
+    pub fn input_state_analog(&self, port: u32, index: u32, id: u32) -> i16 {
+        self.controller.input_state_analog(port, index, id)
+    }
+
+    pub fn audio_sample(&mut self, s: Vec<i16>) {
+        self.audio_frames += s.len() as u64 / 2;
+        if let Some(tx) = &self.record_audio {
+            if tx.send(s.clone()).is_err() {
+                self.record_audio = None;
+            }
+        }
+        if self.fast_forward {
+            return;
+        }
         if self.audio.send(AudioMsg::Data(s)).is_err() {
             warn!("Failed to send to audio thread");
             if self
@@ -132,10 +577,41 @@ impl RetroProxy {
     }
 
     pub fn draw(&mut self, width: u16, height: u16, pitch: u16, data: &[u8]) {
-        self.screen
-            .as_mut()
-            .expect("no screen")
-            .draw(width, height, pitch, data);
+        self.video_frames += 1;
+        // No screen when testing a core
+        if !self.video_en || !self.display_en || self.skip_frame || self.screen.is_none() {
+            return;
+        }
+        let core = self.av.map_or(0.0, |av| av.geometry.aspect_ratio);
+        let aspect = match self.aspect {
+            Some(a) => a.resolve(core),
+            // Cropping keeps square pixels unless the metadata says otherwise
+            None if self.scale_mode != ScaleMode::Crop && core > 0.0 => Some(core),
+            None => None,
+        };
+        let start = Instant::now();
+        self.screen.as_mut().expect("no screen").draw(
+            width,
+            height,
+            pitch,
+            data,
+            self.scale_mode,
+            aspect,
+        );
+        self.stats.blit(start.elapsed());
+    }
+
+    /// Show the game at an aspect ratio rather than with square pixels
+    pub fn set_aspect(&mut self, aspect: Option<AspectRatio>) {
+        self.aspect = aspect;
+    }
+
+    pub fn scale_mode(&self) -> ScaleMode {
+        self.scale_mode
+    }
+
+    pub fn set_scale_mode(&mut self, mode: ScaleMode) {
+        self.scale_mode = mode;
     }
 
     // TODO unused?
@@ -155,6 +631,81 @@ impl RetroProxy {
         self.av = av;
     }
 
+    /// Take new dimensions from the core, the screen scales each frame from
+    /// its own size so only the aspect ratio needs to be kept
+    pub fn set_geometry(&mut self, geometry: RetroGameGeometry) {
+        if let Some(av) = &mut self.av {
+            av.geometry.base_width = geometry.base_width;
+            av.geometry.base_height = geometry.base_height;
+            // Zero or less leaves the aspect ratio as it was
+            if geometry.aspect_ratio > 0.0 {
+                av.geometry.aspect_ratio = geometry.aspect_ratio;
+            }
+        }
+    }
+
+    /// Take new AV info from the core, restarting audio at the new sample
+    /// rate if it has changed. Returns false, keeping the old info, if the
+    /// frame or sample rate is invalid.
+    pub fn change_av(&mut self, av: RetroSystemAvInfo) -> bool {
+        let RetroSystemTiming { fps, sample_rate } = av.timing;
+        // Timing a frame or restarting audio with these would fail, so the
+        // old values are kept
+        if !fps.is_finite() || fps <= 0.0 || !sample_rate.is_finite() || sample_rate < 1.0 {
+            warn!("Invalid AV info, {} fps {} Hz, ignored", fps, sample_rate);
+            return false;
+        }
+        if let Some(old) = self.av {
+            if old.timing.sample_rate != av.timing.sample_rate {
+                info!(
+                    "Sample rate changed from {} Hz to {} Hz",
+                    old.timing.sample_rate, av.timing.sample_rate
+                );
+                let freq = av.timing.sample_rate as i32;
+                let restart = [
+                    AudioMsg::Command(AudioCmd::Stop),
+                    AudioMsg::Command(AudioCmd::Start(freq)),
+                ];
+                if restart.into_iter().any(|m| self.audio.send(m).is_err()) {
+                    warn!("Failed to restart audio");
+                    self.problem(Problem::warn(ScreenToast::error(ScreenMessage::AudioIssue)));
+                }
+            }
+            if old.timing.fps != av.timing.fps {
+                self.timing_changed = true;
+            }
+        }
+        self.av = Some(av);
+        true
+    }
+
+    /// AV info if the core has changed its frame rate since last called
+    pub fn take_timing_change(&mut self) -> Option<RetroSystemAvInfo> {
+        if std::mem::take(&mut self.timing_changed) {
+            self.av
+        } else {
+            None
+        }
+    }
+
+    /// Size of the screen, if there is one
+    pub fn screen_size(&self) -> Option<(u16, u16)> {
+        self.screen.as_ref().map(|s| (s.width(), s.height()))
+    }
+
+    /// Copy the frames drawn and the audio played to a recording, or stop
+    /// copying them
+    pub fn set_recorder(
+        &mut self,
+        frames: Option<mpsc::SyncSender<RecordFrame>>,
+        audio: Option<mpsc::Sender<Vec<i16>>>,
+    ) {
+        self.record_audio = audio;
+        if let Some(screen) = self.screen.as_mut() {
+            screen.set_frame_tap(FrameTap::Record, frames);
+        }
+    }
+
     pub fn take_screen(&mut self) -> Option<Screen> {
         self.screen.take()
     }
@@ -0,0 +1,105 @@
+use log::warn;
+use std::path::Path;
+
+// What popular cores need, so problems can be shown before a game is started
+// rather than failing in confusing ways once it is running.
+const REQUIREMENTS: &str = include_str!("../requirements.toml");
+
+/// Requirements of a core, from the built in list
+#[derive(Debug, Default, PartialEq)]
+pub struct Requirements {
+    bios: Vec<String>,
+    // Extensions of the games that need the BIOS, all games if empty
+    bios_for: Vec<String>,
+    analog: bool,
+    heavy: bool,
+}
+
+impl Requirements {
+    fn parse(core: &toml::Value) -> Self {
+        let strings = |key: &str| -> Vec<String> {
+            core.get(key)
+                .and_then(|v| v.as_array())
+                .map(|a| {
+                    a.iter()
+                        .filter_map(|s| s.as_str())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let flag = |key: &str| core.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+        Requirements {
+            bios: strings("bios"),
+            bios_for: strings("bios_for"),
+            analog: flag("analog"),
+            heavy: flag("heavy"),
+        }
+    }
+
+    /// Short hints for the requirements that aren't met for a game, e.g. a
+    /// BIOS missing from `sys_dir`
+    pub fn hints(&self, game: &Path, sys_dir: &Path, has_analog: bool) -> Vec<String> {
+        let mut hints = Vec::new();
+        let ext = game
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        let needs_bios =
+            self.bios_for.is_empty() || ext.is_some_and(|e| self.bios_for.contains(&e));
+        if needs_bios {
+            for bios in &self.bios {
+                if !sys_dir.join(bios).is_file() {
+                    hints.push(format!("needs {}", bios));
+                }
+            }
+        }
+        if self.analog && !has_analog {
+            hints.push(String::from("needs analog"));
+        }
+        if self.heavy {
+            hints.push(String::from("may be slow"));
+        }
+        hints
+    }
+}
+
+/// Get the requirements of a core, none if the core isn't in the list
+pub fn get_requirements(library_name: &str) -> Requirements {
+    let name = library_name.to_lowercase();
+    match REQUIREMENTS.parse::<toml::Value>() {
+        Ok(table) => table
+            .get(&name)
+            .map(Requirements::parse)
+            .unwrap_or_default(),
+        Err(e) => {
+            warn!("Invalid core requirements: {}", e);
+            Requirements::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_requirements;
+    use std::path::Path;
+
+    #[test]
+    fn hints() {
+        let sys = Path::new("/nonexistent");
+        let nestopia = get_requirements("Nestopia");
+        assert!(nestopia.hints(Path::new("smb.nes"), sys, false).is_empty());
+        assert_eq!(
+            nestopia.hints(Path::new("zelda.FDS"), sys, false),
+            vec![String::from("needs disksys.rom")]
+        );
+        let n64 = get_requirements("Mupen64Plus-Next");
+        assert_eq!(
+            n64.hints(Path::new("mario.z64"), sys, false),
+            vec![String::from("needs analog"), String::from("may be slow")]
+        );
+        assert!(get_requirements("Gambatte")
+            .hints(Path::new("tetris.gb"), sys, false)
+            .is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+
+// Stats are collected a second at a time, for the HUD
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Timing of the frames run over a period, to find where the time goes
+/// when a game is slow
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub frames: u32,
+    pub elapsed: Duration,
+    /// Time in the core running a frame, without drawing it
+    pub run_total: Duration,
+    pub run_max: Duration,
+    /// Time scaling a frame for the screen
+    pub blit_total: Duration,
+    pub blit_max: Duration,
+    /// Audio queued to be played, read once a frame
+    pub audio_total: Duration,
+    pub audio_max: Duration,
+    /// Frames that took longer than the frame time
+    pub dropped: u32,
+}
+
+impl FrameStats {
+    fn average(&self, total: Duration) -> Duration {
+        total.checked_div(self.frames).unwrap_or_default()
+    }
+
+    pub fn run_average(&self) -> Duration {
+        self.average(self.run_total)
+    }
+
+    pub fn blit_average(&self) -> Duration {
+        self.average(self.blit_total)
+    }
+
+    pub fn audio_average(&self) -> Duration {
+        self.average(self.audio_total)
+    }
+
+    pub fn fps(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            0.0
+        } else {
+            f64::from(self.frames) / self.elapsed.as_secs_f64()
+        }
+    }
+
+    /// Add the stats of a later period
+    pub fn add(&mut self, other: &FrameStats) {
+        self.frames += other.frames;
+        self.elapsed += other.elapsed;
+        self.run_total += other.run_total;
+        self.run_max = self.run_max.max(other.run_max);
+        self.blit_total += other.blit_total;
+        self.blit_max = self.blit_max.max(other.blit_max);
+        self.audio_total += other.audio_total;
+        self.audio_max = self.audio_max.max(other.audio_max);
+        self.dropped += other.dropped;
+    }
+
+    /// One line for the log
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.1} fps, run {:.1} ms (max {:.1}), blit {:.1} ms (max {:.1}), audio {:.0} ms (max {:.0}), {} dropped",
+            self.fps(),
+            ms(self.run_average()),
+            ms(self.run_max),
+            ms(self.blit_average()),
+            ms(self.blit_max),
+            ms(self.audio_average()),
+            ms(self.audio_max),
+            self.dropped
+        )
+    }
+
+    /// Short lines to show over the game
+    pub fn hud(&self) -> Vec<String> {
+        vec![
+            format!("{:.1} fps {} drop", self.fps(), self.dropped),
+            format!(
+                "run {:.1}/{:.1} ms",
+                ms(self.run_average()),
+                ms(self.run_max)
+            ),
+            format!(
+                "blit {:.1}/{:.1} ms",
+                ms(self.blit_average()),
+                ms(self.blit_max)
+            ),
+            format!(
+                "audio {:.0}/{:.0} ms",
+                ms(self.audio_average()),
+                ms(self.audio_max)
+            ),
+        ]
+    }
+}
+
+// Collects the stats for each frame, a period at a time
+pub(crate) struct StatsCollector {
+    current: FrameStats,
+    start: Instant,
+    // Blit time of the frame being run, the core may draw more than once
+    blit: Duration,
+    last: Option<FrameStats>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        StatsCollector {
+            current: FrameStats::default(),
+            start: Instant::now(),
+            blit: Duration::ZERO,
+            last: None,
+        }
+    }
+
+    pub fn blit(&mut self, time: Duration) {
+        self.blit += time;
+    }
+
+    pub fn dropped(&mut self) {
+        self.current.dropped += 1;
+    }
+
+    // Add a frame, `run` includes the time drawing it. Returns the stats of
+    // the period if this frame finished it.
+    pub fn frame(&mut self, run: Duration, audio: Duration, now: Instant) -> Option<FrameStats> {
+        let blit = std::mem::take(&mut self.blit);
+        let run = run.saturating_sub(blit);
+        let s = &mut self.current;
+        s.frames += 1;
+        s.run_total += run;
+        s.run_max = s.run_max.max(run);
+        s.blit_total += blit;
+        s.blit_max = s.blit_max.max(blit);
+        s.audio_total += audio;
+        s.audio_max = s.audio_max.max(audio);
+
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed < STATS_INTERVAL {
+            return None;
+        }
+        self.current.elapsed = elapsed;
+        let stats = std::mem::take(&mut self.current);
+        self.start = now;
+        self.last = Some(stats);
+        self.last
+    }
+
+    // Stats of the last full period
+    pub fn last(&self) -> Option<FrameStats> {
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameStats, StatsCollector, STATS_INTERVAL};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn collect() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let mut stats = StatsCollector::new();
+        stats.start = start;
+        stats.blit(ms(2));
+        assert_eq!(stats.frame(ms(10), ms(40), start + ms(16)), None);
+        stats.blit(ms(4));
+        stats.dropped();
+        assert_eq!(stats.last(), None);
+        let second = stats.frame(ms(20), ms(60), start + STATS_INTERVAL).unwrap();
+        assert_eq!(second.frames, 2);
+        assert_eq!(second.run_average(), ms(12));
+        assert_eq!(second.run_max, ms(16));
+        assert_eq!(second.blit_average(), ms(3));
+        assert_eq!(second.audio_max, ms(60));
+        assert_eq!(second.dropped, 1);
+        assert_eq!(second.fps(), 2.0);
+        assert_eq!(stats.last(), Some(second));
+
+        let mut total = FrameStats::default();
+        total.add(&second);
+        total.add(&second);
+        assert_eq!(total.frames, 4);
+        assert_eq!(total.run_max, ms(16));
+        assert_eq!(total.fps(), 2.0);
+        assert_eq!(FrameStats::default().run_average(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,61 @@
+use log::info;
+
+/// Content a core can load as a set, e.g. a Super Game Boy BIOS and a Game
+/// Boy game, as given by SET_SUBSYSTEM_INFO.
+#[derive(Clone, Debug)]
+pub struct Subsystem {
+    pub ident: String,
+    pub desc: String,
+    /// Game type passed to `retro_load_game_special`
+    pub id: u32,
+    pub roms: Vec<SubsystemRom>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubsystemRom {
+    pub desc: String,
+    /// Extensions separated by '|'
+    pub extensions: String,
+    pub need_fullpath: bool,
+    pub required: bool,
+}
+
+impl Subsystem {
+    pub fn log(&self) {
+        info!("  {} ({}): {}", self.ident, self.id, self.desc);
+        for rom in &self.roms {
+            let required = if rom.required { "" } else { ", optional" };
+            info!("    {} [{}{}]", rom.desc, rom.extensions, required);
+        }
+    }
+
+    /// Check a number of ROMs can be loaded, all required ROMs must be given
+    pub fn accepts(&self, num_roms: usize) -> bool {
+        num_roms <= self.roms.len() && self.roms[num_roms..].iter().all(|r| !r.required)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Subsystem, SubsystemRom};
+
+    #[test]
+    fn required_roms() {
+        let rom = |required| SubsystemRom {
+            desc: String::new(),
+            extensions: String::new(),
+            need_fullpath: false,
+            required,
+        };
+        let sub = Subsystem {
+            ident: String::from("sgb"),
+            desc: String::new(),
+            id: 1,
+            roms: vec![rom(true), rom(true), rom(false)],
+        };
+        assert!(!sub.accepts(1));
+        assert!(sub.accepts(2));
+        assert!(sub.accepts(3));
+        assert!(!sub.accepts(4));
+    }
+}
@@ -0,0 +1,86 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Some cores poll commands such as GetVariableUpdate every frame, so only
+// the first few calls of each command per window are written.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const RATE_LIMIT: u32 = 10;
+
+struct Rate {
+    start: Instant,
+    count: u32,
+    suppressed: u32,
+}
+
+/// Log of the environment calls made by a core
+pub struct EnvTrace {
+    out: LineWriter<File>,
+    start: Instant,
+    rates: HashMap<u32, Rate>,
+}
+
+impl EnvTrace {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let out = LineWriter::new(File::create(path)?);
+        info!("Tracing environment calls to '{}'", path.display());
+        Ok(EnvTrace {
+            out,
+            start: Instant::now(),
+            rates: HashMap::new(),
+        })
+    }
+
+    // Check if a call should be written, writing a note of any calls that
+    // were suppressed once the window has passed.
+    fn allow(&mut self, cmd: u32, name: &str) -> bool {
+        let now = Instant::now();
+        let rate = self.rates.entry(cmd).or_insert(Rate {
+            start: now,
+            count: 0,
+            suppressed: 0,
+        });
+        if now.duration_since(rate.start) > RATE_WINDOW {
+            let suppressed = rate.suppressed;
+            *rate = Rate {
+                start: now,
+                count: 0,
+                suppressed: 0,
+            };
+            if suppressed > 0 {
+                let line = format!("{} suppressed {} calls", name, suppressed);
+                self.write(&line);
+            }
+        }
+        let rate = self.rates.get_mut(&cmd).expect("rate entry");
+        if rate.count < RATE_LIMIT {
+            rate.count += 1;
+            true
+        } else {
+            rate.suppressed += 1;
+            false
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        let time = self.start.elapsed().as_secs_f64();
+        if let Err(e) = writeln!(self.out, "{:10.3} {}", time, line) {
+            warn!("Failed to write environment trace: {}", e);
+        }
+    }
+
+    /// Record a call, `args` is the input to the command and `result` is
+    /// anything the frontend returned through the data pointer.
+    pub fn record(&mut self, cmd: u32, name: &str, args: &str, ret: bool, result: &str) {
+        if self.allow(cmd, name) {
+            let line = format!("{} ({:#x}) [{}] -> {} [{}]", name, cmd, args, ret, result);
+            self.write(&line);
+        }
+    }
+}
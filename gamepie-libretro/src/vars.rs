@@ -1,6 +1,7 @@
 use colored::*;
 use log::{debug, info, trace, warn};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use gamepie_core::portable::{PStr, PString};
@@ -9,10 +10,14 @@ use gamepie_core::portable::{PStr, PString};
 pub(crate) struct RetroVar {
     key: String,
     value: PString, // Need to be able to pass to C
+    // Value before any saved option is applied
+    default: PString,
     description: String,
     extra_desc: String,
     values: Vec<(PString, PString)>,
     visible: bool,
+    // Key of the category from v2 options, if any
+    category: Option<String>,
 }
 
 impl std::hash::Hash for RetroVar {
@@ -37,7 +42,7 @@ impl RetroVar {
     pub fn new_v1(
         key: &PStr,
         descr: &PStr,
-        info: &PStr,
+        info: &str,
         vals: &[(PStr, Option<PStr>)],
         default: Option<&PStr>,
     ) -> Self {
@@ -66,11 +71,13 @@ impl RetroVar {
 
         RetroVar {
             key,
+            default: value.clone(),
             value,
             description,
             extra_desc,
             values,
             visible: true,
+            category: None,
         }
     }
 
@@ -98,11 +105,13 @@ impl RetroVar {
 
             Some(RetroVar {
                 key,
+                default: value.clone(),
                 value,
                 description,
                 extra_desc: String::from(""),
                 values,
                 visible: true,
+                category: None,
             })
         } else {
             warn!("Malformed variable: '{}'", descr);
@@ -114,10 +123,12 @@ impl RetroVar {
         RetroVar {
             key: String::from(key),
             value: PString::from_str("").expect("fixed string"),
+            default: PString::from_str("").expect("fixed string"),
             description: String::from(""),
             extra_desc: String::from(""),
             values: Vec::new(),
             visible: false,
+            category: None,
         }
     }
 
@@ -169,10 +180,33 @@ impl RetroVar {
             false
         }
     }
+
+    fn apply(&mut self, value: &str) -> bool {
+        match self.values.iter().find(|(v, _)| v.to_str() == value) {
+            Some((v, _)) => {
+                self.value = v.clone();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// A group of options from v2 options
+struct RetroCategory {
+    desc: String,
+    info: String,
 }
 
 pub(crate) struct RetroVars {
     vars: HashSet<RetroVar>,
+    categories: HashMap<String, RetroCategory>,
+    presets: HashMap<String, String>,
+    // Values saved from previous sessions, and the file they are saved to
+    saved: HashMap<String, String>,
+    saved_path: Option<PathBuf>,
+    // Values for this game only, which aren't saved
+    overrides: HashMap<String, String>,
     dirty: bool,
 }
 
@@ -180,13 +214,147 @@ impl RetroVars {
     pub fn new() -> Self {
         RetroVars {
             vars: HashSet::new(),
+            categories: HashMap::new(),
+            presets: HashMap::new(),
+            saved: HashMap::new(),
+            saved_path: None,
+            overrides: HashMap::new(),
             dirty: true,
         }
     }
 
+    /// Load option values saved to `path`, which are used in place of both
+    /// the presets and the core defaults. Values are saved back to the same
+    /// file by `save`.
+    pub fn load(&mut self, path: &Path) {
+        self.saved.clear();
+        self.saved_path = Some(path.to_path_buf());
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => {
+                debug!("No saved options '{}'", path.display());
+                return;
+            }
+        };
+        match contents.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => {
+                for (k, v) in table {
+                    match v.as_str() {
+                        Some(v) => {
+                            self.saved.insert(k, String::from(v));
+                        }
+                        None => warn!("Saved option '{}' is not a string", k),
+                    }
+                }
+            }
+            Ok(_) => warn!("Saved options '{}' are not a table", path.display()),
+            Err(e) => warn!("Invalid saved options '{}': {}", path.display(), e),
+        }
+        debug!("Loaded {} saved options", self.saved.len());
+    }
+
+    /// Save the options that differ from the presets or core defaults
+    pub fn save(&self) {
+        let path = match &self.saved_path {
+            Some(p) => p,
+            None => return,
+        };
+        // Overridden options keep the value saved before, if any
+        let table: toml::value::Table = self
+            .vars
+            .iter()
+            .filter_map(|v| {
+                let value = if self.overrides.contains_key(&v.key) {
+                    self.saved.get(&v.key).cloned()
+                } else if v.value != v.default {
+                    Some(v.value().to_string())
+                } else {
+                    None
+                };
+                value.map(|s| (v.key.clone(), toml::Value::String(s)))
+            })
+            .collect();
+        if table.is_empty() && !path.exists() {
+            return;
+        }
+        debug!("Saving {} options to '{}'", table.len(), path.display());
+        let contents = toml::Value::Table(table).to_string();
+        let written = match path.parent() {
+            Some(dir) => std::fs::create_dir_all(dir),
+            None => Ok(()),
+        }
+        .and_then(|_| std::fs::write(path, contents));
+        if let Err(e) = written {
+            warn!("Failed to save options '{}': {}", path.display(), e);
+        }
+    }
+
+    /// Go back to the presets or core defaults and delete the saved values.
+    /// Options set for the game in its metadata are kept.
+    pub fn reset(&mut self) {
+        self.saved.clear();
+        if let Some(path) = &self.saved_path {
+            match std::fs::remove_file(path) {
+                Ok(_) => info!("Removed saved options '{}'", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove options '{}': {}", path.display(), e),
+            }
+        }
+        let overrides = &self.overrides;
+        let vars: Vec<RetroVar> = self
+            .vars
+            .drain()
+            .map(|mut v| {
+                if !overrides.contains_key(&v.key) {
+                    v.value = v.default.clone();
+                }
+                v
+            })
+            .collect();
+        self.vars = vars.into_iter().collect();
+        self.dirty = true;
+    }
+
+    /// Set values to use instead of the core defaults when options are added
+    pub fn set_presets(&mut self, presets: HashMap<String, String>) {
+        self.presets = presets;
+    }
+
+    /// Set values for this game to use in place of the presets and saved
+    /// values
+    pub fn set_overrides(&mut self, overrides: HashMap<String, String>) {
+        self.overrides = overrides;
+    }
+
+    fn preset(&self, var: &mut RetroVar) {
+        if let Some(p) = self.presets.get(&var.key) {
+            if var.apply(p) {
+                debug!("Variable preset: {} = {}", var.key, p);
+                var.default = var.value.clone();
+            } else {
+                warn!("Preset '{}' is not valid for '{}'", p, var.key);
+            }
+        }
+        if let Some(s) = self.saved.get(&var.key) {
+            if var.apply(s) {
+                debug!("Variable saved: {} = {}", var.key, s);
+            } else {
+                warn!("Saved value '{}' is not valid for '{}'", s, var.key);
+            }
+        }
+        if let Some(o) = self.overrides.get(&var.key) {
+            if var.apply(o) {
+                debug!("Variable for game: {} = {}", var.key, o);
+            } else {
+                warn!("Game option '{}' is not valid for '{}'", o, var.key);
+            }
+        }
+    }
+
     pub fn add_v0(&mut self, key: &PStr, descr: &PStr) {
         let var = RetroVar::new_v0(key, descr);
-        if let Some(v) = var {
+        if let Some(mut v) = var {
+            self.preset(&mut v);
             if !self.vars.insert(v) {
                 warn!("Variable '{}' already exists", key);
             }
@@ -202,15 +370,58 @@ impl RetroVars {
         values: &[(PStr, Option<PStr>)],
         default: Option<&PStr>,
     ) {
-        let var = RetroVar::new_v1(key, descr, info, values, default);
+        let mut var = RetroVar::new_v1(key, descr, &String::from(info), values, default);
+        self.preset(&mut var);
         if !self.vars.insert(var) {
             warn!("Variable '{}' already exists", key);
         }
         self.dirty = true;
     }
 
-    pub fn get_vars(&self) -> &HashSet<RetroVar> {
-        &self.vars
+    pub fn add_v2(
+        &mut self,
+        key: &PStr,
+        descr: &PStr,
+        info: &str,
+        values: &[(PStr, Option<PStr>)],
+        default: Option<&PStr>,
+        category: Option<&str>,
+    ) {
+        let mut var = RetroVar::new_v1(key, descr, info, values, default);
+        var.category = category.map(String::from);
+        self.preset(&mut var);
+        if !self.vars.insert(var) {
+            warn!("Variable '{}' already exists", key);
+        }
+        self.dirty = true;
+    }
+
+    /// Add a category that v2 options can be grouped into
+    pub fn add_category(&mut self, key: &PStr, desc: &str, info: &str) {
+        let category = RetroCategory {
+            desc: String::from(desc),
+            info: String::from(info),
+        };
+        self.categories.insert(String::from(key), category);
+    }
+
+    /// Log the options, grouped by category
+    pub fn log(&self) {
+        let mut vars: Vec<&RetroVar> = self.vars.iter().collect();
+        vars.sort_by(|a, b| (&a.category, &a.key).cmp(&(&b.category, &b.key)));
+        let mut category = None;
+        for v in vars {
+            if v.category != category {
+                category = v.category.clone();
+                if let Some(key) = &category {
+                    match self.categories.get(key) {
+                        Some(c) => info!(" [{}] {}", c.desc, c.info),
+                        None => info!(" [{}]", key),
+                    }
+                }
+            }
+            v.log_var();
+        }
     }
 
     pub fn get_var(&self, k: &str) -> *const ::std::os::raw::c_char {
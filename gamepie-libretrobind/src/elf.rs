@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Enough of the ELF header to check a library can be loaded here, so that
+// a truncated or wrong architecture core gives a clear error rather than
+// whatever the dynamic loader reports.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const HEADER_LEN: usize = 20;
+const ET_DYN: u16 = 3;
+
+#[cfg(target_arch = "x86_64")]
+const MACHINE: Option<u16> = Some(62);
+#[cfg(target_arch = "aarch64")]
+const MACHINE: Option<u16> = Some(183);
+#[cfg(target_arch = "arm")]
+const MACHINE: Option<u16> = Some(40);
+#[cfg(target_arch = "x86")]
+const MACHINE: Option<u16> = Some(3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "x86"
+)))]
+const MACHINE: Option<u16> = None;
+
+const FRONTEND_BITS: u32 = if cfg!(target_pointer_width = "64") {
+    64
+} else {
+    32
+};
+
+// Architecture names as used by Rust, so they can be compared with the
+// frontend's
+fn machine_name(machine: u16) -> String {
+    match machine {
+        3 => String::from("x86"),
+        8 => String::from("mips"),
+        20 => String::from("powerpc"),
+        21 => String::from("powerpc64"),
+        40 => String::from("arm"),
+        62 => String::from("x86_64"),
+        183 => String::from("aarch64"),
+        243 => String::from("riscv"),
+        m => format!("machine type {}", m),
+    }
+}
+
+fn endian_name(little: bool) -> &'static str {
+    if little {
+        "little"
+    } else {
+        "big"
+    }
+}
+
+/// Reason a library can't be loaded by this build of the frontend
+#[derive(Debug, PartialEq)]
+pub enum ElfError {
+    /// Not an ELF file at all, e.g. a text file next to the cores
+    NotElf,
+    /// Built for another word size (bits)
+    Class(u32),
+    /// Built for the other byte order (core is little endian)
+    Endian(bool),
+    /// An ELF file, but an executable or object rather than a library
+    NotShared,
+    /// Built for another architecture (ELF machine type)
+    Machine(u16),
+}
+
+impl ElfError {
+    /// Whether the library is a core built for another system, as opposed
+    /// to a file that isn't a core
+    pub fn is_mismatch(&self) -> bool {
+        matches!(
+            self,
+            ElfError::Class(_) | ElfError::Endian(_) | ElfError::Machine(_)
+        )
+    }
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF library"),
+            ElfError::Class(bits) => write!(
+                f,
+                "core is {}-bit but frontend is {}-bit",
+                bits, FRONTEND_BITS
+            ),
+            ElfError::Endian(little) => write!(
+                f,
+                "core is {}-endian but frontend is {}-endian",
+                endian_name(*little),
+                endian_name(!*little)
+            ),
+            ElfError::NotShared => write!(f, "not a shared library"),
+            ElfError::Machine(m) => write!(
+                f,
+                "core is built for {} but frontend is {}",
+                machine_name(*m),
+                std::env::consts::ARCH
+            ),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+fn check_header(header: &[u8]) -> Result<(), ElfError> {
+    if header.len() < HEADER_LEN || !header.starts_with(ELF_MAGIC) {
+        return Err(ElfError::NotElf);
+    }
+    let bits = if header[4] == 2 { 64 } else { 32 };
+    if bits != FRONTEND_BITS {
+        return Err(ElfError::Class(bits));
+    }
+    let little = header[5] == 1;
+    if little != cfg!(target_endian = "little") {
+        return Err(ElfError::Endian(little));
+    }
+    let half = |i: usize| {
+        let bytes = [header[i], header[i + 1]];
+        if little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    };
+    if half(16) != ET_DYN {
+        return Err(ElfError::NotShared);
+    }
+    match MACHINE {
+        Some(m) if half(18) != m => Err(ElfError::Machine(half(18))),
+        _ => Ok(()),
+    }
+}
+
+/// Check a file is a shared library for the running architecture, an
+/// ElfError is returned if it isn't
+pub fn check_library(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(check_header(&header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_header, ElfError};
+
+    #[test]
+    fn elf_header() {
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let mut header = exe[..20].to_vec();
+        // Test executables are position independent, so the same type as a
+        // shared library
+        let dyn_type = if header[5] == 1 { [3, 0] } else { [0, 3] };
+        header[16..18].copy_from_slice(&dyn_type);
+        assert_eq!(check_header(&header), Ok(()));
+        assert!(check_header(&header[..10]).is_err());
+        assert!(check_header(b"#!/bin/sh\nexit 0\n\n\n").is_err());
+        header[4] ^= 3;
+        let wrong_class = check_header(&header).unwrap_err();
+        assert!(wrong_class.is_mismatch());
+        assert!(wrong_class.to_string().starts_with("core is"));
+        assert!(!ElfError::NotElf.is_mismatch());
+    }
+}
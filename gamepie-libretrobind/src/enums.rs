@@ -56,7 +56,9 @@ impl Display for RetroDevice {
 }
 
 #[repr(u32)]
-#[derive(FromPrimitive, ToPrimitive, Debug, PartialEq, std::cmp::Eq, std::hash::Hash)]
+#[derive(
+    FromPrimitive, ToPrimitive, Clone, Copy, Debug, PartialEq, std::cmp::Eq, std::hash::Hash,
+)]
 pub enum RetroPadButton {
     B = RETRO_DEVICE_ID_JOYPAD_B,
     Y = RETRO_DEVICE_ID_JOYPAD_Y,
@@ -3,14 +3,19 @@ use std::error::Error;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use gamepie_core::checksum::crc32;
 use gamepie_core::error::GamepieError;
 use gamepie_core::portable::PString;
 use gamepie_core::RetroSystemInfo;
 
-use crate::bind::{retro_game_info, retro_system_av_info, retro_system_info};
+use crate::bind::{
+    retro_game_geometry, retro_game_info, retro_game_info_ext, retro_system_av_info,
+    retro_system_info,
+};
 use crate::types::*;
 
 // TODO, should symbols be cached?
@@ -44,18 +49,30 @@ pub fn get_system_av_info(lib: &libloading::Library) -> Result<RetroSystemAvInfo
 
         let mut info: retro_system_av_info = std::mem::zeroed();
         func(&mut info as *mut retro_system_av_info);
-        let geometry = RetroGameGeometry {
-            aspect_ratio: info.geometry.aspect_ratio,
-            base_height: info.geometry.base_height,
-            base_width: info.geometry.base_width,
-            max_height: info.geometry.max_height,
-            max_width: info.geometry.max_width,
-        };
-        let timing = RetroSystemTiming {
-            fps: info.timing.fps,
-            sample_rate: info.timing.sample_rate,
-        };
-        Ok(RetroSystemAvInfo { geometry, timing })
+        Ok(av_info(&info))
+    }
+}
+
+/// Copy geometry passed by a core, e.g. through SET_GEOMETRY
+pub fn game_geometry(geometry: &retro_game_geometry) -> RetroGameGeometry {
+    RetroGameGeometry {
+        aspect_ratio: geometry.aspect_ratio,
+        base_height: geometry.base_height,
+        base_width: geometry.base_width,
+        max_height: geometry.max_height,
+        max_width: geometry.max_width,
+    }
+}
+
+/// Copy AV info passed by a core, e.g. through SET_SYSTEM_AV_INFO
+pub fn av_info(info: &retro_system_av_info) -> RetroSystemAvInfo {
+    let timing = RetroSystemTiming {
+        fps: info.timing.fps,
+        sample_rate: info.timing.sample_rate,
+    };
+    RetroSystemAvInfo {
+        geometry: game_geometry(&info.geometry),
+        timing,
     }
 }
 
@@ -90,6 +107,15 @@ pub fn run(lib: &libloading::Library) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// Restart the game, as if the console's reset button was pressed
+pub fn reset(lib: &libloading::Library) -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let func: libloading::Symbol<unsafe extern "C" fn()> = lib.get(b"retro_reset")?;
+        func();
+        Ok(())
+    }
+}
+
 pub fn deinit(lib: &libloading::Library) -> Result<(), Box<dyn Error>> {
     unsafe {
         let func: libloading::Symbol<unsafe extern "C" fn()> = lib.get(b"retro_unload_game")?;
@@ -101,45 +127,154 @@ pub fn deinit(lib: &libloading::Library) -> Result<(), Box<dyn Error>> {
 }
 
 pub struct RetroGameInfo {
-    path: String, // TODO data/size/meta for need_fullpath cores
+    path: String,
+    /// Passed through to the core, e.g. for per-game hacks
+    meta: String,
+    // Contents of the game, for cores that don't load it from the path
+    data: Option<Vec<u8>>,
+    crc: Option<u32>,
 }
 
 impl RetroGameInfo {
     pub fn new(path: &str) -> Self {
         RetroGameInfo {
             path: String::from(path),
+            meta: String::new(),
+            data: None,
+            crc: None,
         }
     }
+
+    pub fn with_meta(mut self, meta: &str) -> Self {
+        self.meta = String::from(meta);
+        self
+    }
+
+    /// Read the game into memory, for cores that don't need the full path
+    pub fn read(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut data = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut data)?;
+        self.crc = Some(crc32(&data));
+        self.data = Some(data);
+        Ok(())
+    }
+
+    /// Use contents of the game that have already been read, e.g. while it
+    /// was selected in the menu
+    pub fn with_data(mut self, data: Vec<u8>) -> Self {
+        self.crc = Some(crc32(&data));
+        self.data = Some(data);
+        self
+    }
+
+    /// CRC32 of the game, if it has been read
+    pub fn crc(&self) -> Option<u32> {
+        self.crc
+    }
+}
+
+/// Extended game information for GET_GAME_INFO_EXT, the data pointers are
+/// only valid while the games it was made from are unchanged.
+pub struct RetroGameInfoExt {
+    _strings: Vec<PString>,
+    infos: Vec<retro_game_info_ext>,
+}
+
+// Only points to the strings it owns and the games it was made from
+unsafe impl Send for RetroGameInfoExt {}
+
+impl RetroGameInfoExt {
+    pub fn new(games: &[&RetroGameInfo]) -> Result<Self, Box<dyn Error>> {
+        let mut strings = Vec::new();
+        let mut infos = Vec::new();
+        for game in games {
+            let path = Path::new(&game.path);
+            let dir = path.parent().and_then(|d| d.to_str()).unwrap_or("");
+            let name = path.file_stem().and_then(OsStr::to_str).unwrap_or("");
+            let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
+            let fields = [
+                PString::from_str(&game.path)?,
+                PString::from_str(dir)?,
+                PString::from_str(name)?,
+                PString::from_str(&ext.to_lowercase())?,
+                PString::from_str(&game.meta)?,
+            ];
+            let (data, size) = match &game.data {
+                Some(d) => (d.as_ptr() as *const std::os::raw::c_void, d.len()),
+                None => (std::ptr::null(), 0),
+            };
+            infos.push(retro_game_info_ext {
+                full_path: fields[0].as_ptr(),
+                archive_path: std::ptr::null(),
+                archive_file: std::ptr::null(),
+                dir: fields[1].as_ptr(),
+                name: fields[2].as_ptr(),
+                ext: fields[3].as_ptr(),
+                meta: fields[4].as_ptr(),
+                data,
+                size: size.try_into()?,
+                file_in_archive: false,
+                persistent_data: false,
+            });
+            // Moving the strings doesn't move their contents
+            strings.extend(fields);
+        }
+        Ok(RetroGameInfoExt {
+            _strings: strings,
+            infos,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *const retro_game_info_ext {
+        self.infos.as_ptr()
+    }
+}
+
+// Describe a game to the core, the game is read into `buffer` unless the
+// core loads it from the path itself.
+fn c_game_info(
+    game_info: &RetroGameInfo,
+    need_fullpath: bool,
+    c_path: &PString,
+    c_meta: &PString,
+    buffer: &mut Vec<u8>,
+) -> Result<retro_game_info, Box<dyn Error>> {
+    if need_fullpath {
+        Ok(retro_game_info {
+            path: c_path.as_ptr(),
+            meta: c_meta.as_ptr(),
+            size: 0,
+            data: std::ptr::null::<std::os::raw::c_void>(),
+        })
+    } else {
+        let data = match &game_info.data {
+            Some(d) => d,
+            None => {
+                let mut game_file = File::open(&game_info.path)?;
+                game_file.read_to_end(buffer)?;
+                buffer
+            }
+        };
+
+        Ok(retro_game_info {
+            path: c_path.as_ptr(),
+            meta: c_meta.as_ptr(),
+            size: data.len().try_into()?,
+            data: data.as_ptr() as *const std::os::raw::c_void,
+        })
+    }
 }
 
 pub fn load_game(
     lib: &libloading::Library,
     info: &RetroSystemInfo,
-    game_info: RetroGameInfo,
+    game_info: &RetroGameInfo,
 ) -> Result<bool, Box<dyn Error>> {
     unsafe {
         let c_path = PString::from_str(&game_info.path)?;
-        let c_meta = PString::from_str("")?;
+        let c_meta = PString::from_str(&game_info.meta)?;
         let mut buffer = Vec::new();
-        let c_info = if info.need_fullpath {
-            retro_game_info {
-                path: c_path.as_ptr(),
-                meta: c_meta.as_ptr(),
-                size: 0,
-                data: std::ptr::null::<std::os::raw::c_void>(),
-            }
-        } else {
-            let mut game_file = File::open(&game_info.path)?;
-
-            let size = game_file.read_to_end(&mut buffer)?;
-
-            retro_game_info {
-                path: c_path.as_ptr(),
-                meta: c_meta.as_ptr(),
-                size: size.try_into()?,
-                data: buffer.as_ptr() as *const std::os::raw::c_void,
-            }
-        };
+        let c_info = c_game_info(game_info, info.need_fullpath, &c_path, &c_meta, &mut buffer)?;
 
         let func: libloading::Symbol<unsafe extern "C" fn(game: *const retro_game_info) -> bool> =
             lib.get(b"retro_load_game")?;
@@ -148,16 +283,58 @@ pub fn load_game(
     }
 }
 
-pub fn set_controller_port_device(lib: &libloading::Library) -> Result<(), Box<dyn Error>> {
-    // Currently supports NES, GB, GBC, GBA
-    // Only NES supports a second player, but only support a single controller
-    // at a time, so always connect a joypad to port/player 0
+/// Load a set of games as one of the core's subsystems, each game is given
+/// with whether the core loads it from its path.
+pub fn load_game_special(
+    lib: &libloading::Library,
+    game_type: u32,
+    games: &[(RetroGameInfo, bool)],
+) -> Result<bool, Box<dyn Error>> {
+    unsafe {
+        let c_paths = games
+            .iter()
+            .map(|(g, _)| PString::from_str(&g.path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let c_metas = games
+            .iter()
+            .map(|(g, _)| PString::from_str(&g.meta))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut buffers = vec![Vec::new(); games.len()];
+        let mut c_infos = Vec::new();
+        for ((((game, need_fullpath), c_path), c_meta), buffer) in games
+            .iter()
+            .zip(&c_paths)
+            .zip(&c_metas)
+            .zip(buffers.iter_mut())
+        {
+            c_infos.push(c_game_info(game, *need_fullpath, c_path, c_meta, buffer)?);
+        }
+
+        let func: libloading::Symbol<
+            unsafe extern "C" fn(
+                game_type: ::std::os::raw::c_uint,
+                info: *const retro_game_info,
+                num_info: usize,
+            ) -> bool,
+        > = lib.get(b"retro_load_game_special")?;
+
+        Ok(func(game_type, c_infos.as_ptr(), c_infos.len()))
+    }
+}
+
+/// Connect a joypad to each of the first `ports` ports
+pub fn set_controller_port_device(
+    lib: &libloading::Library,
+    ports: u32,
+) -> Result<(), Box<dyn Error>> {
     unsafe {
         let func: libloading::Symbol<
             unsafe extern "C" fn(::std::os::raw::c_uint, ::std::os::raw::c_uint),
         > = lib.get(b"retro_set_controller_port_device")?;
 
-        func(0, crate::bind::RETRO_DEVICE_JOYPAD);
+        for port in 0..ports {
+            func(port, crate::bind::RETRO_DEVICE_JOYPAD);
+        }
         Ok(())
     }
 }
@@ -185,6 +362,41 @@ pub fn get_memory_data(
     }
 }
 
+pub fn serialize_size(lib: &libloading::Library) -> Result<usize, Box<dyn Error>> {
+    unsafe {
+        let func: libloading::Symbol<unsafe extern "C" fn() -> usize> =
+            lib.get(b"retro_serialize_size")?;
+
+        Ok(func())
+    }
+}
+
+pub fn serialize(lib: &libloading::Library, data: &mut [u8]) -> Result<bool, Box<dyn Error>> {
+    unsafe {
+        let func: libloading::Symbol<
+            unsafe extern "C" fn(*mut ::std::os::raw::c_void, usize) -> bool,
+        > = lib.get(b"retro_serialize")?;
+
+        Ok(func(
+            data.as_mut_ptr() as *mut ::std::os::raw::c_void,
+            data.len(),
+        ))
+    }
+}
+
+pub fn unserialize(lib: &libloading::Library, data: &[u8]) -> Result<bool, Box<dyn Error>> {
+    unsafe {
+        let func: libloading::Symbol<
+            unsafe extern "C" fn(*const ::std::os::raw::c_void, usize) -> bool,
+        > = lib.get(b"retro_unserialize")?;
+
+        Ok(func(
+            data.as_ptr() as *const ::std::os::raw::c_void,
+            data.len(),
+        ))
+    }
+}
+
 // Libraries are not cached as this can cause problems with some emulators that
 // don't reinitialise everything correctly causing broken audio etc.
 pub fn load_library<P>(path: P) -> Result<Arc<libloading::Library>, Box<dyn Error>>
@@ -194,6 +406,7 @@ where
     unsafe {
         let key = path.as_ref().to_str().ok_or(GamepieError::String)?;
         debug!("Loading library: '{}'", key);
+        crate::elf::check_library(Path::new(key))?;
         let lib = libloading::Library::new(key)?;
         let arc = Arc::new(lib);
         Ok(arc)
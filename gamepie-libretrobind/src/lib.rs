@@ -1,6 +1,8 @@
 pub mod bind;
+pub mod elf;
 pub mod enums;
 pub mod functions;
+pub mod memory;
 pub mod types;
 pub mod utils;
 
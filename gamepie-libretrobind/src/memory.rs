@@ -0,0 +1,208 @@
+use log::debug;
+use std::ffi::CStr;
+
+use crate::bind::{retro_memory_descriptor, size_t, RETRO_MEMDESC_SAVE_RAM};
+use crate::utils::SaveRegion;
+
+// Remove the bits set in `mask` from `addr`, moving the higher bits down
+fn reduce(mut addr: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let below = (mask - 1) & !mask;
+        addr = (addr & below) | ((addr >> 1) & !below);
+        mask = (mask & (mask - 1)) >> 1;
+    }
+    addr
+}
+
+fn size(n: size_t) -> usize {
+    n.try_into().expect("too much memory")
+}
+
+fn highest_bit(n: usize) -> usize {
+    1 << (usize::BITS - 1 - n.leading_zeros())
+}
+
+/// Block of core memory from SET_MEMORY_MAPS, and where it appears in the
+/// emulated address space.
+#[derive(Debug, Clone)]
+pub struct MemoryDescriptor {
+    // Already moved on by the descriptor's offset, null for open bus or
+    // hardware registers
+    ptr: *mut u8,
+    start: usize,
+    select: usize,
+    disconnect: usize,
+    len: usize,
+    save: bool,
+    addrspace: String,
+}
+
+// Only accessed from the emulation thread, the proxy that holds the map is
+// behind a mutex.
+unsafe impl Send for MemoryDescriptor {}
+
+impl MemoryDescriptor {
+    /// # Safety
+    ///
+    /// The descriptor's pointer must be valid for `len` bytes past its
+    /// offset for as long as the game is loaded.
+    pub unsafe fn new(desc: &retro_memory_descriptor) -> Self {
+        let ptr = if desc.ptr.is_null() {
+            std::ptr::null_mut()
+        } else {
+            (desc.ptr as *mut u8).add(size(desc.offset))
+        };
+        let addrspace = if desc.addrspace.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(desc.addrspace)
+                .to_string_lossy()
+                .into_owned()
+        };
+        MemoryDescriptor {
+            ptr,
+            start: size(desc.start),
+            select: size(desc.select),
+            disconnect: size(desc.disconnect),
+            len: size(desc.len),
+            save: desc.flags & u64::from(RETRO_MEMDESC_SAVE_RAM) != 0,
+            addrspace,
+        }
+    }
+
+    // Offset into the block of an emulated address, if this descriptor
+    // claims it. Follows libretro.h: subtract the start, pick off the
+    // disconnected bits then clear high bits until inside the length.
+    fn offset(&self, addr: usize) -> Option<usize> {
+        let claimed = if self.select == 0 {
+            addr >= self.start && (self.len == 0 || addr - self.start < self.len)
+        } else {
+            addr & self.select == self.start & self.select
+        };
+        if !claimed {
+            return None;
+        }
+        let mut offset = reduce(addr.checked_sub(self.start)?, self.disconnect);
+        if self.len != 0 {
+            while offset >= self.len {
+                offset &= !highest_bit(offset);
+            }
+        }
+        Some(offset)
+    }
+
+    fn describe(&self) -> String {
+        let end = self.start.wrapping_add(self.len);
+        let mut text = format!("{:#010x} -> {:#010x}", self.start, end);
+        if !self.addrspace.is_empty() {
+            text.push(' ');
+            text.push_str(&self.addrspace);
+        }
+        if self.save {
+            text.push_str(" (save)");
+        }
+        text
+    }
+}
+
+/// Memory map given by the core, to read bytes at emulated addresses so
+/// values in the game's RAM can be watched.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMap {
+    descriptors: Vec<MemoryDescriptor>,
+}
+
+impl MemoryMap {
+    pub fn new(descriptors: Vec<MemoryDescriptor>) -> Self {
+        if !descriptors.is_empty() {
+            debug!("Memory map:");
+        }
+        for d in &descriptors {
+            debug!("  {}", d.describe());
+        }
+        MemoryMap { descriptors }
+    }
+
+    /// Save RAM regions of the map, some cores only expose battery backed
+    /// RAM this way
+    pub fn save_regions(&self) -> Vec<SaveRegion> {
+        let mut regions = Vec::new();
+        for d in &self.descriptors {
+            if d.save && !d.ptr.is_null() && d.len != 0 {
+                let region = unsafe { SaveRegion::new(d.ptr, d.len) };
+                // Mirrors of the same memory are listed separately
+                if !regions.contains(&region) {
+                    regions.push(region);
+                }
+            }
+        }
+        regions
+    }
+
+    // The first descriptor to claim an address is the one that applies
+    fn byte(&self, addr: usize) -> Option<u8> {
+        let (desc, offset) = self
+            .descriptors
+            .iter()
+            .find_map(|d| Some((d, d.offset(addr)?)))?;
+        // Nothing is known past the end of a block with no length
+        if desc.ptr.is_null() || offset >= desc.len {
+            return None;
+        }
+        Some(unsafe { *desc.ptr.add(offset) })
+    }
+
+    /// Read bytes from `addr` onwards into `buf`, returns how many were read
+    /// before reaching an address that isn't mapped to memory
+    pub fn peek(&self, addr: usize, buf: &mut [u8]) -> usize {
+        for (i, b) in buf.iter_mut().enumerate() {
+            match addr.checked_add(i).and_then(|a| self.byte(a)) {
+                Some(v) => *b = v,
+                None => return i,
+            }
+        }
+        buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reduce, MemoryDescriptor, MemoryMap};
+
+    fn descriptor(ptr: *mut u8, start: usize, select: usize, len: usize) -> MemoryDescriptor {
+        MemoryDescriptor {
+            ptr,
+            start,
+            select,
+            disconnect: 0,
+            len,
+            save: false,
+            addrspace: String::new(),
+        }
+    }
+
+    #[test]
+    fn peek() {
+        assert_eq!(reduce(0b1011, 0b0100), 0b111);
+
+        let mut wram: Vec<u8> = (0..0x20).collect();
+        let mut hram = vec![0xaa; 0x10];
+        let map = MemoryMap::new(vec![
+            descriptor(hram.as_mut_ptr(), 0xff80, 0, 0x10),
+            // Mirrored every 0x20 bytes from 0xc000 to 0xdfff
+            descriptor(wram.as_mut_ptr(), 0xc000, 0xe000, 0x20),
+            // Open bus claims the rest of the range
+            descriptor(std::ptr::null_mut(), 0xfe00, 0xfe00, 0),
+        ]);
+
+        let mut buf = [0; 4];
+        assert_eq!(map.peek(0xc002, &mut buf), 4);
+        assert_eq!(buf, [2, 3, 4, 5]);
+        assert_eq!(map.peek(0xd03e, &mut buf), 4);
+        assert_eq!(buf, [0x1e, 0x1f, 0, 1]);
+        assert_eq!(map.peek(0xff8e, &mut buf), 2);
+        assert_eq!(buf[..2], [0xaa, 0xaa]);
+        assert_eq!(map.peek(0xfe10, &mut buf), 0);
+        assert_eq!(map.peek(0x8000, &mut buf), 0);
+    }
+}
@@ -5,22 +5,78 @@ use gamepie_core::error::GamepieError;
 
 use crate::bind::RETRO_MEMORY_SAVE_RAM;
 
-pub fn has_save_memory(lib: &libloading::Library) -> Result<bool, Box<dyn Error>> {
-    let mem_size = crate::functions::get_memory_size(lib, RETRO_MEMORY_SAVE_RAM)?;
-    Ok(mem_size != 0)
+/// Area of core memory holding save data, from the descriptors given by
+/// SET_MEMORY_MAPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRegion {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// Only accessed from the emulation thread, the proxy that holds the regions
+// is behind a mutex.
+unsafe impl Send for SaveRegion {}
+
+impl SaveRegion {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for `len` bytes for as long as the game is loaded.
+    pub unsafe fn new(ptr: *mut u8, len: usize) -> Self {
+        SaveRegion { ptr, len }
+    }
+}
+
+// Memory to save, the save RAM if the core reports any, otherwise the save
+// RAM regions of the memory map. Some cores only expose battery backed RAM
+// through the memory map.
+fn save_memory(
+    lib: &libloading::Library,
+    mapped: &[SaveRegion],
+) -> Result<Vec<SaveRegion>, Box<dyn Error>> {
+    let size = crate::functions::get_memory_size(lib, RETRO_MEMORY_SAVE_RAM)?;
+    if size != 0 {
+        let ptr = crate::functions::get_memory_data(lib, RETRO_MEMORY_SAVE_RAM)?;
+        Ok(vec![SaveRegion {
+            ptr: ptr as *mut u8,
+            len: size,
+        }])
+    } else {
+        Ok(mapped.to_vec())
+    }
+}
+
+fn save_size(memory: &[SaveRegion]) -> usize {
+    memory.iter().map(|r| r.len).sum()
+}
+
+pub fn has_save_memory(
+    lib: &libloading::Library,
+    mapped: &[SaveRegion],
+) -> Result<bool, Box<dyn Error>> {
+    let memory = save_memory(lib, mapped)?;
+    Ok(save_size(&memory) != 0)
 }
 
 pub fn try_read_into_save_mem(
     lib: &libloading::Library,
+    mapped: &[SaveRegion],
     save_path: &str,
 ) -> Result<(), Box<dyn Error>> {
     match std::fs::read(save_path) {
         Ok(data) => {
-            let save_size = crate::functions::get_memory_size(lib, RETRO_MEMORY_SAVE_RAM)?;
+            let memory = save_memory(lib, mapped)?;
+            let save_size = save_size(&memory);
             if save_size == data.len() {
-                let save_ptr = crate::functions::get_memory_data(lib, RETRO_MEMORY_SAVE_RAM)?;
-                unsafe {
-                    std::ptr::copy_nonoverlapping(data.as_ptr(), save_ptr as *mut u8, save_size);
+                let mut offset = 0;
+                for region in memory {
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            data[offset..].as_ptr(),
+                            region.ptr,
+                            region.len,
+                        );
+                    }
+                    offset += region.len;
                 }
                 debug!("Save data loaded from '{}'", save_path);
                 Ok(())
@@ -40,11 +96,78 @@ pub fn try_read_into_save_mem(
     }
 }
 
-pub fn save_to_file(lib: &libloading::Library, save_path: &str) -> Result<(), Box<dyn Error>> {
-    let save_size = crate::functions::get_memory_size(lib, RETRO_MEMORY_SAVE_RAM)?;
-    let save_ptr = crate::functions::get_memory_data(lib, RETRO_MEMORY_SAVE_RAM)?;
-    let save_slice = unsafe { std::slice::from_raw_parts(save_ptr as *mut u8, save_size) };
-    std::fs::write(save_path, save_slice)?;
+/// Check a save file exists and is the size of the core's save memory
+pub fn save_is_valid(lib: &libloading::Library, mapped: &[SaveRegion], save_path: &str) -> bool {
+    match (std::fs::metadata(save_path), save_memory(lib, mapped)) {
+        (Ok(meta), Ok(memory)) => meta.is_file() && meta.len() == save_size(&memory) as u64,
+        _ => false,
+    }
+}
+
+pub fn save_to_file(
+    lib: &libloading::Library,
+    mapped: &[SaveRegion],
+    save_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let memory = save_memory(lib, mapped)?;
+    let mut data = Vec::with_capacity(save_size(&memory));
+    for region in memory {
+        let slice = unsafe { std::slice::from_raw_parts(region.ptr, region.len) };
+        data.extend_from_slice(slice);
+    }
+    std::fs::write(save_path, data)?;
     info!("Saved to '{}'", save_path);
     Ok(())
 }
+
+/// Serialize the emulator state into memory, fails if the core doesn't
+/// support save states or the state is larger than `limit` bytes.
+pub fn serialize_state(lib: &libloading::Library, limit: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let size = crate::functions::serialize_size(lib)?;
+    if size == 0 {
+        error!("Core doesn't support save states");
+        return Err(Box::new(GamepieError::SaveState));
+    }
+    if size > limit {
+        error!(
+            "Save state of {} bytes exceeds limit of {} bytes",
+            size, limit
+        );
+        return Err(Box::new(GamepieError::SaveState));
+    }
+    let mut data = vec![0; size];
+    if crate::functions::serialize(lib, &mut data)? {
+        debug!("Serialized {} bytes of state", size);
+        Ok(data)
+    } else {
+        error!("Failed to serialize state");
+        Err(Box::new(GamepieError::SaveState))
+    }
+}
+
+/// Serialize the state into an existing buffer, resized to fit, so frequent
+/// captures don't need a new allocation each time.
+pub fn serialize_into(lib: &libloading::Library, data: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let size = crate::functions::serialize_size(lib)?;
+    if size == 0 {
+        error!("Core doesn't support save states");
+        return Err(Box::new(GamepieError::SaveState));
+    }
+    data.resize(size, 0);
+    if crate::functions::serialize(lib, data)? {
+        Ok(())
+    } else {
+        error!("Failed to serialize state");
+        Err(Box::new(GamepieError::SaveState))
+    }
+}
+
+pub fn unserialize_state(lib: &libloading::Library, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    if crate::functions::unserialize(lib, data)? {
+        debug!("Restored {} bytes of state", data.len());
+        Ok(())
+    } else {
+        error!("Failed to restore state");
+        Err(Box::new(GamepieError::SaveState))
+    }
+}
@@ -0,0 +1,153 @@
+// Copying RGB565 frames from cores into the screen's framebuffer, a row at a
+// time as this runs for every frame
+
+/// A frame from a core, as little endian RGB565 with `pitch` bytes per row
+pub(crate) struct Source<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub pitch: usize,
+}
+
+impl Source<'_> {
+    fn row(&self, y: usize, x: usize, len: usize) -> &[u8] {
+        let start = y * self.pitch + x * 2;
+        &self.data[start..start + len * 2]
+    }
+}
+
+// Convert a row of little endian pixels. When the bytes happen to be aligned
+// this is a plain copy, which libc's memcpy does with NEON where there is
+// any, otherwise the loop is simple enough to be vectorised.
+fn copy_row(dst: &mut [u16], src: &[u8]) {
+    #[cfg(target_endian = "little")]
+    {
+        // Any bit pattern is a valid u16
+        let (pre, mid, _) = unsafe { src.align_to::<u16>() };
+        if pre.is_empty() && mid.len() >= dst.len() {
+            dst.copy_from_slice(&mid[..dst.len()]);
+            return;
+        }
+    }
+    for (d, s) in dst.iter_mut().zip(src.chunks_exact(2)) {
+        *d = u16::from_le_bytes([s[0], s[1]]);
+    }
+}
+
+/// Copy a frame into the middle of `dst`, `dst_width` pixels wide, cropping
+/// it equally on each side if it is too big
+pub(crate) fn centred(dst: &mut [u16], dst_width: usize, src: &Source) {
+    let dst_height = dst.len() / dst_width;
+    let cols = std::cmp::min(src.width, dst_width);
+    let rows = std::cmp::min(src.height, dst_height);
+    // Offsets into the output, or for cropping the input
+    let (xoff, xskip) = ((dst_width - cols) / 2, (src.width - cols) / 2);
+    let (yoff, yskip) = ((dst_height - rows) / 2, (src.height - rows) / 2);
+
+    if cols == dst_width && src.pitch == dst_width * 2 {
+        // Rows are contiguous on both sides, so copy them all at once
+        let start = (yoff * dst_width)..((yoff + rows) * dst_width);
+        copy_row(&mut dst[start], src.row(yskip, 0, rows * dst_width));
+        return;
+    }
+    for y in 0..rows {
+        let out = (y + yoff) * dst_width + xoff;
+        copy_row(&mut dst[out..out + cols], src.row(y + yskip, xskip, cols));
+    }
+}
+
+/// Scale a frame to `size` with nearest neighbour sampling, which keeps pixel
+/// art sharp, into the middle of `dst`
+pub(crate) fn scaled(dst: &mut [u16], dst_width: usize, src: &Source, size: (usize, usize)) {
+    let (ow, oh) = size;
+    let dst_height = dst.len() / dst_width;
+    let (xoff, yoff) = ((dst_width - ow) / 2, (dst_height - oh) / 2);
+    // Source column for each output column, the same for every row
+    let columns: Vec<usize> = (0..ow).map(|x| x * src.width / ow).collect();
+    let mut last: Option<(usize, usize)> = None;
+    for y in 0..oh {
+        let sy = y * src.height / oh;
+        let out = (y + yoff) * dst_width + xoff;
+        match last {
+            // Rows repeated when scaling up only need copying
+            Some((prev_sy, prev_out)) if prev_sy == sy => {
+                dst.copy_within(prev_out..prev_out + ow, out);
+            }
+            _ => {
+                let row = src.row(sy, 0, src.width);
+                for (d, &sx) in dst[out..out + ow].iter_mut().zip(&columns) {
+                    *d = u16::from_le_bytes([row[sx * 2], row[sx * 2 + 1]]);
+                }
+            }
+        }
+        last = Some((sy, out));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{centred, scaled, Source};
+
+    // Frame whose pixels are their own coordinates, with padding at the end
+    // of each row
+    fn frame(width: usize, height: usize, pitch: usize) -> Vec<u8> {
+        let mut data = vec![0xff; pitch * height];
+        for y in 0..height {
+            for x in 0..width {
+                let px = ((y << 8) | x) as u16;
+                data[y * pitch + x * 2..][..2].copy_from_slice(&px.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn centre_and_crop() {
+        let data = frame(4, 2, 10);
+        let src = Source {
+            data: &data,
+            width: 4,
+            height: 2,
+            pitch: 10,
+        };
+        let mut dst = vec![0; 6 * 4];
+        centred(&mut dst, 6, &src);
+        assert_eq!(&dst[6..12], &[0, 0, 1, 2, 3, 0]);
+        assert_eq!(&dst[12..18], &[0, 0x100, 0x101, 0x102, 0x103, 0]);
+        assert!(dst[..6].iter().chain(&dst[18..]).all(|&p| p == 0));
+
+        // Too wide and tall, so the middle is shown
+        let mut dst = vec![0; 2];
+        centred(&mut dst, 2, &src);
+        assert_eq!(dst, [1, 2]);
+
+        // Same width with no padding is copied in one go, here from bytes
+        // that aren't aligned
+        let data = frame(2, 2, 4);
+        let src = Source {
+            data: &data[1..],
+            width: 2,
+            height: 1,
+            pitch: 4,
+        };
+        let mut dst = vec![0; 2 * 3];
+        centred(&mut dst, 2, &src);
+        assert_eq!(dst, [0, 0, 0x100, 0, 0, 0]);
+    }
+
+    #[test]
+    fn nearest_neighbour() {
+        let data = frame(2, 2, 4);
+        let src = Source {
+            data: &data,
+            width: 2,
+            height: 2,
+            pitch: 4,
+        };
+        let mut dst = vec![0; 4 * 4];
+        scaled(&mut dst, 4, &src, (4, 4));
+        assert_eq!(&dst[..4], &[0, 0, 1, 1]);
+        assert_eq!(&dst[4..8], &[0, 0, 1, 1]);
+        assert_eq!(&dst[12..], &[0x100, 0x100, 0x101, 0x101]);
+    }
+}
@@ -0,0 +1,37 @@
+/// Sends frames to a screen. Frames are sent from the video thread, so the
+/// next frame can be drawn meanwhile.
+pub trait ScreenDriver: Send {
+    fn width(&self) -> u16;
+    fn height(&self) -> u16;
+
+    /// Show a frame of RGB565 pixels, a row at a time. Only the parts that
+    /// changed since the last frame need to be sent, unless `force_full`.
+    fn tick(&mut self, data: &[u16], force_full: bool) -> std::io::Result<()>;
+}
+
+/// Screen driver that shows nothing, for running cores without a device.
+/// The screen still keeps the last frame drawn, see `Screen::last_frame`.
+pub struct Headless {
+    width: u16,
+    height: u16,
+}
+
+impl Headless {
+    pub fn new(width: u16, height: u16) -> Self {
+        Headless { width, height }
+    }
+}
+
+impl ScreenDriver for Headless {
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn tick(&mut self, _data: &[u16], _force_full: bool) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use log::{error, warn};
+use std::error::Error;
+use std::os::raw::c_int;
+use std::time::Instant;
+
+use gamepie_core::error::GamepieError;
+use gamepie_core::log::gamepie_log_shim;
+use gamepie_screenbind::*;
+
+use crate::driver::ScreenDriver;
+use crate::video::Backoff;
+
+const SPI_INIT_ATTEMPTS: u32 = 4;
+
+// Describe an error code from the screen driver
+fn lcd_error(code: c_int) -> &'static str {
+    match code {
+        LCD_ERR_NOT_INITIALISED => "not initialised",
+        LCD_ERR_SPI_INIT => "couldn't set up SPI",
+        LCD_ERR_SPI_STALLED => "SPI bus stalled",
+        _ => "unknown error",
+    }
+}
+
+/// SPI screen driven by fbcp-ili9341, for the display it was built for
+pub(crate) struct Fbcp {
+    width: u16,
+    height: u16,
+}
+
+impl Fbcp {
+    /// Start the driver, retrying a few times if SPI can't be set up
+    pub fn init() -> Result<Self, Box<dyn Error>> {
+        let (width, height) = unsafe { (lcd_lib_width(), lcd_lib_height()) };
+        let mut backoff = Backoff::default();
+        for attempt in 1..=SPI_INIT_ATTEMPTS {
+            let code = unsafe { lcd_lib_init(Some(gamepie_log_shim)) };
+            if code >= 0 {
+                return Ok(Fbcp { width, height });
+            }
+            if attempt < SPI_INIT_ATTEMPTS {
+                let (delay, _) = backoff.failed(Instant::now());
+                warn!(
+                    "Screen init failed ({}), retrying in {:?}",
+                    lcd_error(code),
+                    delay
+                );
+                std::thread::sleep(delay);
+            } else {
+                error!("Screen init failed: {}", lcd_error(code));
+            }
+        }
+        Err(Box::new(GamepieError::NoVideo))
+    }
+}
+
+impl ScreenDriver for Fbcp {
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn tick(&mut self, data: &[u16], force_full: bool) -> std::io::Result<()> {
+        let code = unsafe { lcd_lib_tick(data.as_ptr(), c_int::from(force_full)) };
+        if code < 0 {
+            return Err(std::io::Error::other(lcd_error(code)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Fbcp {
+    fn drop(&mut self) {
+        // Dropped by the video thread, so nothing can be drawing
+        unsafe {
+            lcd_lib_deinit();
+        }
+    }
+}
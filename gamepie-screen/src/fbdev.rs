@@ -0,0 +1,167 @@
+use log::info;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::driver::ScreenDriver;
+
+// Read a value of the framebuffer from sysfs
+fn sysfs(device: &Path, key: &str) -> std::io::Result<String> {
+    let name = device
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no framebuffer name"))?;
+    let path = Path::new("/sys/class/graphics").join(name).join(key);
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+fn invalid(what: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid framebuffer {}", what),
+    )
+}
+
+// Whole number scale and offset to centre a frame on the framebuffer, `None`
+// if it doesn't fit
+fn layout(fb: (usize, usize), frame: (usize, usize)) -> Option<(usize, usize, usize)> {
+    let scale = (fb.0 / frame.0).min(fb.1 / frame.1);
+    (scale > 0).then(|| {
+        let x = (fb.0 - frame.0 * scale) / 2;
+        let y = (fb.1 - frame.1 * scale) / 2;
+        (scale, x, y)
+    })
+}
+
+// RGB565 to the bytes of a 32 bit XRGB pixel, as little endian framebuffers
+// store it
+fn xrgb(p: u16) -> [u8; 4] {
+    let r = ((p >> 11) & 0x1f) as u8;
+    let g = ((p >> 5) & 0x3f) as u8;
+    let b = (p & 0x1f) as u8;
+    [
+        (b << 3) | (b >> 2),
+        (g << 2) | (g >> 4),
+        (r << 3) | (r >> 2),
+        0xff,
+    ]
+}
+
+/// Linux framebuffer device, e.g. HDMI on a Pi. Frames are scaled up by the
+/// largest whole number that fits and centred.
+pub(crate) struct FbDev {
+    file: File,
+    width: u16,
+    height: u16,
+    bytes_per_pixel: usize,
+    stride: usize,
+    scale: usize,
+    // Byte offset of the top left of the frame
+    origin: usize,
+    // Last frame sent, so unchanged rows can be skipped
+    last: Vec<u16>,
+    row: Vec<u8>,
+}
+
+impl FbDev {
+    /// Open a framebuffer to draw frames of `width` x `height` to
+    pub fn open(device: &Path, width: u16, height: u16) -> std::io::Result<Self> {
+        let size = sysfs(device, "virtual_size")?;
+        let (fb_w, fb_h) = size
+            .split_once(',')
+            .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+            .ok_or_else(|| invalid("size"))?;
+        let bpp: usize = sysfs(device, "bits_per_pixel")?
+            .parse()
+            .map_err(|_| invalid("depth"))?;
+        if bpp != 16 && bpp != 32 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{} bit framebuffers aren't supported", bpp),
+            ));
+        }
+        let bytes_per_pixel = bpp / 8;
+        let stride = match sysfs(device, "stride") {
+            Ok(s) => s.parse().map_err(|_| invalid("stride"))?,
+            Err(_) => fb_w * bytes_per_pixel,
+        };
+        let (scale, x, y) =
+            layout((fb_w, fb_h), (width.into(), height.into())).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}x{} doesn't fit {}x{}", width, height, fb_w, fb_h),
+                )
+            })?;
+        let file = OpenOptions::new().read(true).write(true).open(device)?;
+        info!(
+            "Framebuffer '{}' is {}x{} at {} bits, scaling by {}",
+            device.display(),
+            fb_w,
+            fb_h,
+            bpp,
+            scale
+        );
+        Ok(FbDev {
+            file,
+            width,
+            height,
+            bytes_per_pixel,
+            stride,
+            scale,
+            origin: y * stride + x * bytes_per_pixel,
+            last: Vec::new(),
+            row: Vec::new(),
+        })
+    }
+}
+
+impl ScreenDriver for FbDev {
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn tick(&mut self, data: &[u16], force_full: bool) -> std::io::Result<()> {
+        let width = usize::from(self.width);
+        let force_full = force_full || self.last.len() != data.len();
+        for (y, line) in data.chunks_exact(width).enumerate() {
+            if !force_full && self.last[y * width..(y + 1) * width] == *line {
+                continue;
+            }
+            self.row.clear();
+            for p in line {
+                for _ in 0..self.scale {
+                    match self.bytes_per_pixel {
+                        2 => self.row.extend_from_slice(&p.to_le_bytes()),
+                        _ => self.row.extend_from_slice(&xrgb(*p)),
+                    }
+                }
+            }
+            for i in 0..self.scale {
+                let offset = self.origin + (y * self.scale + i) * self.stride;
+                self.file.write_all_at(&self.row, offset as u64)?;
+            }
+        }
+        self.last.clear();
+        self.last.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{layout, xrgb};
+
+    #[test]
+    fn scaling() {
+        assert_eq!(layout((1920, 1080), (240, 240)), Some((4, 480, 60)));
+        assert_eq!(layout((320, 240), (320, 240)), Some((1, 0, 0)));
+        assert_eq!(layout((200, 200), (240, 240)), None);
+        assert_eq!(xrgb(0xf800), [0, 0, 0xff, 0xff]);
+        assert_eq!(xrgb(0x07e0), [0, 0xff, 0, 0xff]);
+        assert_eq!(xrgb(0x001f), [0xff, 0, 0, 0xff]);
+    }
+}
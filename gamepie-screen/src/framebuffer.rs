@@ -1,4 +1,20 @@
-use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use embedded_graphics::{
+    pixelcolor::{raw::RawU16, Rgb565},
+    prelude::*,
+};
+
+// Mix an RGB565 pixel with a colour, alpha is out of 255 for the colour
+fn blend(pixel: u16, colour: Rgb565, alpha: u8) -> u16 {
+    let under = Rgb565::from(RawU16::new(pixel));
+    let alpha = u16::from(alpha);
+    let mix = |a: u8, b: u8| ((u16::from(a) * (255 - alpha) + u16::from(b) * alpha) / 255) as u8;
+    Rgb565::new(
+        mix(under.r(), colour.r()),
+        mix(under.g(), colour.g()),
+        mix(under.b(), colour.b()),
+    )
+    .into_storage()
+}
 
 pub struct Framebuffer {
     width: u16,
@@ -28,6 +44,19 @@ impl Framebuffer {
     pub fn reclaim(self) -> Vec<u16> {
         self.buffer
     }
+
+    /// Replace the contents with a frame of the same size
+    pub fn copy_from(&mut self, data: &[u16]) {
+        self.buffer.copy_from_slice(data);
+    }
+
+    /// Fade the contents towards a colour, so anything drawn over it stands
+    /// out while what is underneath can still be seen
+    pub fn fade_to(&mut self, colour: Rgb565, alpha: u8) {
+        for px in self.buffer.iter_mut() {
+            *px = blend(*px, colour, alpha);
+        }
+    }
 }
 
 impl OriginDimensions for Framebuffer {
@@ -62,3 +91,20 @@ impl DrawTarget for Framebuffer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::blend;
+    use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+
+    #[test]
+    fn blending() {
+        let white = Rgb565::WHITE.into_storage();
+        assert_eq!(blend(white, Rgb565::BLACK, 0), white);
+        assert_eq!(blend(white, Rgb565::BLACK, 255), 0);
+        assert_eq!(
+            blend(white, Rgb565::BLACK, 128),
+            Rgb565::new(15, 31, 15).into_storage()
+        );
+    }
+}
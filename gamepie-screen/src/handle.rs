@@ -0,0 +1,56 @@
+use log::{debug, error};
+
+use crate::Screen;
+
+/// Owns the screen while it isn't in use, the screen is lent to whatever is
+/// drawing and must be given back before it can be lent again. Misuse is
+/// logged with who has the screen rather than panicking.
+pub struct ScreenHandle {
+    screen: Option<Screen>,
+    // Who the screen is lent to
+    lent_to: Option<&'static str>,
+}
+
+impl ScreenHandle {
+    pub fn new(screen: Screen) -> Self {
+        ScreenHandle {
+            screen: Some(screen),
+            lent_to: None,
+        }
+    }
+
+    /// Lend the screen, returns `None` if it is already lent
+    pub fn lend(&mut self, to: &'static str) -> Option<Screen> {
+        match self.screen.take() {
+            Some(screen) => {
+                debug!("Screen lent to {}", to);
+                self.lent_to = Some(to);
+                Some(screen)
+            }
+            None => {
+                error!(
+                    "Screen wanted by {} but already lent to {}",
+                    to,
+                    self.lent_to.unwrap_or("unknown")
+                );
+                None
+            }
+        }
+    }
+
+    /// Give back a screen that was lent
+    pub fn give_back(&mut self, screen: Screen) {
+        match self.lent_to.take() {
+            Some(to) => debug!("Screen given back by {}", to),
+            None => error!("Screen given back but it wasn't lent"),
+        }
+        if self.screen.replace(screen).is_some() {
+            error!("Screen given back while another screen was held");
+        }
+    }
+
+    /// Who the screen is lent to, if anyone
+    pub fn lent_to(&self) -> Option<&'static str> {
+        self.lent_to
+    }
+}
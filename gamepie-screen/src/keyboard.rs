@@ -0,0 +1,127 @@
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use profont::PROFONT_9_POINT;
+
+use gamepie_core::{TEXT_COLOUR, TEXT_SEL_COLOUR};
+
+const CHARS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Columns in the on-screen keyboard
+pub(crate) const KEYBOARD_COLUMNS: usize = 10;
+/// Keys in the on-screen keyboard, the characters then space, delete and done
+pub const KEYBOARD_KEYS: usize = CHARS.len() + 3;
+
+pub(crate) const KEY_HEIGHT: u32 = 16;
+
+/// Key on the on-screen keyboard, used to search the game list and to type
+/// into games
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Delete,
+    Done,
+}
+
+impl Key {
+    pub fn at(index: usize) -> Self {
+        match CHARS.chars().nth(index) {
+            Some(c) => Key::Char(c),
+            None if index == CHARS.len() => Key::Char(' '),
+            None if index == CHARS.len() + 1 => Key::Delete,
+            None => Key::Done,
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Key::Char(' ') => String::from("_"),
+            Key::Char(c) => c.to_string(),
+            Key::Delete => String::from("Del"),
+            Key::Done => String::from("OK"),
+        }
+    }
+}
+
+/// Direction to move the keyboard cursor in
+#[derive(Clone, Copy, Debug)]
+pub enum Move {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Move the keyboard cursor, wrapping round the edges of the grid
+pub fn move_cursor(cursor: usize, dir: Move) -> usize {
+    let rows = KEYBOARD_KEYS.div_ceil(KEYBOARD_COLUMNS);
+    let (row, col) = (cursor / KEYBOARD_COLUMNS, cursor % KEYBOARD_COLUMNS);
+    let next = match dir {
+        Move::Left => (cursor + KEYBOARD_KEYS - 1) % KEYBOARD_KEYS,
+        Move::Right => (cursor + 1) % KEYBOARD_KEYS,
+        Move::Up => ((row + rows - 1) % rows) * KEYBOARD_COLUMNS + col,
+        Move::Down => ((row + 1) % rows) * KEYBOARD_COLUMNS + col,
+    };
+    // The last row may be short
+    std::cmp::min(next, KEYBOARD_KEYS - 1)
+}
+
+/// Draw the on-screen keyboard with the key under the cursor highlighted
+pub(crate) fn draw_keyboard<D>(
+    target: &mut D,
+    area: Rectangle,
+    cursor: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let cols = KEYBOARD_COLUMNS as u32;
+    let width = area.size.width / cols;
+    let font = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
+    let font_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+    for index in 0..KEYBOARD_KEYS {
+        let (row, col) = (
+            (index / KEYBOARD_COLUMNS) as u32,
+            (index % KEYBOARD_COLUMNS) as u32,
+        );
+        let top_left = area.top_left + Size::new(col * width, row * KEY_HEIGHT);
+        let key = Rectangle::new(top_left, Size::new(width, KEY_HEIGHT));
+        let f = if index == cursor {
+            key.into_styled(PrimitiveStyle::with_stroke(TEXT_SEL_COLOUR, 1))
+                .draw(target)?;
+            font_sel
+        } else {
+            font
+        };
+        // Text baseline near the bottom of the key
+        let pos = key.center() + Point::new(0, 3);
+        Text::with_alignment(&Key::at(index).label(), pos, f, Alignment::Center).draw(target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{move_cursor, Key, Move, KEYBOARD_KEYS};
+
+    #[test]
+    fn keys() {
+        assert_eq!(Key::at(0), Key::Char('A'));
+        assert_eq!(Key::at(36), Key::Char(' '));
+        assert_eq!(Key::at(KEYBOARD_KEYS - 1), Key::Done);
+    }
+
+    #[test]
+    fn cursor_wraps() {
+        assert_eq!(move_cursor(0, Move::Left), KEYBOARD_KEYS - 1);
+        assert_eq!(move_cursor(KEYBOARD_KEYS - 1, Move::Right), 0);
+        assert_eq!(move_cursor(3, Move::Up), 33);
+        assert_eq!(move_cursor(35, Move::Down), 5);
+        // Below the short last row
+        assert_eq!(move_cursor(29, Move::Down), KEYBOARD_KEYS - 1);
+    }
+}
@@ -1,8 +1,28 @@
+mod blit;
+mod driver;
+mod fbcp;
+mod fbdev;
 mod framebuffer;
+mod handle;
+mod keyboard;
 mod menu;
 mod overlay;
+mod pause;
+mod png;
+mod power;
+mod qr;
 mod screen;
 mod sprites;
+mod system;
+mod tester;
+mod thumbnail;
+mod video;
 
+pub use driver::{Headless, ScreenDriver};
+pub use handle::ScreenHandle;
+pub use keyboard::{move_cursor, Key, Move, KEYBOARD_KEYS};
 pub use menu::{Menu, MenuSel};
+pub use pause::{PauseItem, PAUSE_ITEMS};
+pub use power::{PowerItem, POWER_ITEMS};
 pub use screen::*;
+pub use tester::PadView;
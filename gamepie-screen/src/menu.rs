@@ -1,175 +1,627 @@
 use embedded_graphics::{
     mono_font::MonoTextStyle,
+    pixelcolor::Rgb565,
     prelude::*,
-    primitives::{Circle, PrimitiveStyle},
+    primitives::{Circle, PrimitiveStyle, Rectangle},
     text::{Alignment, Text},
 };
-use log::{debug, error, warn};
+use log::{debug, info, trace, warn};
 use profont::{PROFONT_12_POINT, PROFONT_24_POINT, PROFONT_9_POINT};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use gamepie_core::error::GamepieError;
+use gamepie_core::aspect::ScaleMode;
+use gamepie_core::config::MenuConfig;
+use gamepie_core::dirs::{self, GamepieDirs};
+use gamepie_core::error::{error_heading, GamepieError};
+use gamepie_core::lang::Language;
+use gamepie_core::metadata;
 use gamepie_core::{
-    CoreInfo, BACKGROUND_COLOUR, ERROR_BACKGROUND_COLOUR, ERROR_TEXT_COLOUR, METADATA_EXT,
-    ROM_PATH, TEXT_COLOUR, TEXT_SEL_COLOUR,
+    CoreInfo, BACKGROUND_COLOUR, ERROR_BACKGROUND_COLOUR, ERROR_TEXT_COLOUR, IMAGE_EXTS,
+    TEXT_COLOUR, TEXT_SEL_COLOUR,
 };
 
 use crate::framebuffer::Framebuffer;
+use crate::keyboard::{draw_keyboard, KEYBOARD_COLUMNS, KEYBOARD_KEYS, KEY_HEIGHT};
+use crate::pause::{PauseItem, PAUSE_ITEMS};
+use crate::power::POWER_ITEMS;
+use crate::qr::{QrCode, QUIET_ZONE};
+use crate::system::{self, OTHER_SYSTEM};
+use crate::tester::{draw_pad, PadView, PAD_SIZE};
+use crate::thumbnail::{Thumbnail, THUMB_MAX};
 use crate::Screen;
 
 const MENU_TOP_MARGIN: u16 = 30;
 const MENU_LEFT_MARGIN1: i32 = 10;
 const MENU_LEFT_MARGIN2: i32 = 30;
 const MENU_ITEM_HEIGHT: u16 = 14;
+const MENU_DETAILS_TOP: i32 = 14;
 const MENU_ERR_LEFT_MARGIN: i32 = 30;
+const MENU_ERR_QR_GAP: i32 = 8;
+// Log lines put in the error QR code when there is no link, newest first
+// until they don't fit, each cut short so more can be included
+const ERR_QR_LINES: usize = 4;
+const ERR_QR_LINE_LEN: usize = 64;
+const MENU_EVENT_HEIGHT: i32 = 11;
+// Marker toggled by the latency test, in the top right corner where a
+// photodiode can be held over it
+const LATENCY_MARKER_SIZE: u32 = 64;
+const LATENCY_MARKER_MARGIN: i32 = 8;
+// How much the game is faded out behind the pause menu, out of 255
+const PAUSE_FADE: u8 = 160;
+// Jump bar of initial letters down the right edge of the game list
+const JUMP_BAR_RIGHT: i32 = 8;
+const JUMP_BAR_MAX_SPACING: i32 = 11;
+// Metadata names kept in memory, only the names near the current page are
+// needed so large libraries don't need all of their metadata loaded.
+const NAME_CACHE_SIZE: usize = 256;
+// Thumbnails are much larger than names, so only keep those for entries near
+// the selection, which is enough for scrolling back and forth.
+const THUMB_CACHE_SIZE: usize = 32;
+const THUMB_MARGIN: i32 = 10;
+// Shown before recent and favourite games at the top of the game list
+const RECENT_MARKER: char = '>';
+const FAVOURITE_MARKER: char = '+';
+// Gap between an entry and its badges
+const BADGE_GAP: i32 = 6;
+// Loading spinner, a ring of dots with one highlighted going round
+const SPINNER_DOTS: usize = 8;
+const SPINNER_RADIUS: f32 = 20.0;
+const SPINNER_DOT: u32 = 7;
+const SPINNER_TOP: i32 = 24;
 
+#[derive(Clone, Copy)]
 pub enum MenuSel {
     Game,
+    /// Versions of the game at this index
+    Variant(usize),
     Core,
+    /// Pause menu, over the last frame of the game
+    Pause,
+    /// Saves of the running game that can be restored
+    Slots,
+    /// Shutdown and reboot
+    Power,
+    /// Safe mode recovery options
+    Recovery,
+    /// Screen added by a plugin
+    Plugin,
 }
 
+// Kept small as there is one per game, the full path is only built when
+// needed.
 struct GameInfo {
-    path: String,
-    name: String,
+    dir: usize,
+    // Relative to the ROM directory, e.g. `gb/Tetris.gb` for games in a
+    // folder within it
+    file: Box<str>,
+    // Name from the game's metadata, only read for games that have a
+    // metadata file so the list can be sorted by it
+    title: Option<Box<str>>,
 }
 
-pub struct Menu {
-    games: Vec<GameInfo>,
-    emus: Vec<CoreInfo>,
-    inner: Framebuffer,
+impl GameInfo {
+    // File name without any folder
+    fn name(&self) -> &str {
+        self.file.rsplit('/').next().unwrap_or(&self.file)
+    }
+
+    // Top folder within the ROM directory, if the game is in one
+    fn folder(&self) -> Option<&str> {
+        self.file.split_once('/').map(|(f, _)| f)
+    }
 }
 
-trait Menuable {
-    fn text(&self) -> String;
+// Title with tags such as region and revision removed, plus the extension,
+// so different dumps of a game can be grouped.
+fn dedupe_key(file: &str) -> (String, String) {
+    let path = Path::new(file);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file);
+    let title = stem.split(['(', '[']).next().unwrap_or(stem);
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    (title.to_lowercase(), ext.to_lowercase())
 }
 
-impl Menuable for GameInfo {
-    fn text(&self) -> String {
-        self.name.clone()
-    }
+// Position of the first preferred region in the tags of a filename, e.g.
+// "Game (Europe, USA) (Rev 1).gb", lower is more preferred.
+fn region_rank(file: &str, regions: &[String]) -> usize {
+    let tags: Vec<String> = file
+        .split('(')
+        .skip(1)
+        .filter_map(|t| t.split(')').next())
+        .flat_map(|t| t.split(','))
+        .map(|t| t.trim().to_lowercase())
+        .collect();
+    regions
+        .iter()
+        .position(|r| tags.contains(&r.to_lowercase()))
+        .unwrap_or(regions.len())
 }
 
-impl Menuable for CoreInfo {
-    fn text(&self) -> String {
-        self.name()
+// Letter a title is listed under in the jump bar, anything that doesn't start
+// with a letter is grouped under '#'
+fn initial(file: &str) -> char {
+    match file.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase(),
+        _ => '#',
     }
 }
 
-impl Menu {
-    fn try_get_metadata(path: std::fs::DirEntry, metadata_path: &str) -> String {
-        // TODO anything other than name useful?
-        // prefered emulator?
-        if let Ok(file) = std::fs::read_to_string(metadata_path) {
-            if let Ok(meta) = file.parse::<toml::Value>() {
-                if let Some(name) = meta.get("name") {
-                    if let Some(name) = name.as_str() {
-                        return String::from(name);
-                    }
-                }
-            }
+// Initial letters of the entries, with the index of the first entry for
+// each, from the names the games are sorted by
+fn find_letters(sort_names: &[String], entries: &[Range<usize>]) -> Vec<(char, usize)> {
+    let mut letters: Vec<(char, usize)> = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let letter = initial(&sort_names[entry.start]);
+        if letters.last().is_none_or(|(l, _)| *l != letter) {
+            letters.push((letter, i));
         }
+    }
+    letters
+}
 
-        String::from(path.file_name().to_string_lossy())
+// Index to jump to from the letters in the jump bar, each with the index of
+// its first entry. Forward goes to the next letter, back goes to the start of
+// the current letter or the previous one if already there, wrapping round.
+fn jump(letters: &[(char, usize)], index: usize, forward: bool) -> usize {
+    let target = if forward {
+        letters
+            .iter()
+            .find(|(_, start)| *start > index)
+            .or(letters.first())
+    } else {
+        letters
+            .iter()
+            .rev()
+            .find(|(_, start)| *start < index)
+            .or(letters.last())
+    };
+    target.map_or(index, |(_, start)| *start)
+}
+
+// How long ago a game was saved, in the largest whole unit
+fn saved_ago(secs: u64) -> String {
+    match secs {
+        s if s < 60 => String::from("saved now"),
+        s if s < 60 * 60 => format!("saved {}m ago", s / 60),
+        s if s < 24 * 60 * 60 => format!("saved {}h ago", s / (60 * 60)),
+        s => format!("saved {}d ago", s / (24 * 60 * 60)),
     }
+}
 
-    fn process_game(path: std::fs::DirEntry) -> Option<GameInfo> {
-        if let Some(ext) = path.path().extension() {
-            if let Some(ext) = ext.to_str() {
-                if ext == METADATA_EXT {
-                    return None;
-                }
-            }
-        }
+pub struct Menu {
+    rom_dirs: Vec<PathBuf>,
+    // Where saves and states are looked for, for the badges on entries
+    dirs: GamepieDirs,
+    games: Vec<GameInfo>,
+    // Each entry in the menu is a range of games that are versions of the
+    // same title, the first being the preferred version. When grouped by
+    // system these are the entries for the current system.
+    entries: Vec<Range<usize>>,
+    all_entries: Vec<Range<usize>>,
+    // Systems found when grouping by system, and the system of each of
+    // all_entries
+    systems: Vec<String>,
+    entry_systems: Vec<usize>,
+    // System being shown, None for all of them
+    system: Option<usize>,
+    // Only games with this in their filename are shown, lower case
+    query: String,
+    // Games marked for party mode, in the order they were marked
+    marked: Vec<usize>,
+    // Recent and favourite games shown at the top of the game list, with
+    // their marker and index in all_entries
+    pinned: Vec<(char, usize)>,
+    // Markers of the pinned entries at the start of entries
+    pin_markers: Vec<char>,
+    // Initial letters of the entries, with the index of the first entry for
+    // each
+    letters: Vec<(char, usize)>,
+    names: HashMap<usize, String>,
+    // Lower case display name of each game, the entries are sorted by these
+    sort_names: Vec<String>,
+    // Titles of games identified from their checksums, used if they have no
+    // metadata name
+    titles: HashMap<PathBuf, String>,
+    // Entries without an image are cached as None so they aren't looked for
+    // again
+    thumbs: HashMap<usize, Option<Thumbnail>>,
+    // Badges for when an entry was last saved and whether it has a state
+    badges: HashMap<usize, Option<String>>,
+    // Line shown above the menu, e.g. stats for the selected game
+    details: Option<String>,
+    // Scaling of the paused game, shown in the pause menu
+    scale_mode: ScaleMode,
+    emus: Vec<CoreInfo>,
+    // Unmet requirements of each core, shown after its name
+    core_hints: Vec<Option<String>>,
+    // Names of the save slots, and seconds since each was saved
+    slots: Vec<(String, u64)>,
+    // Recovery options, with seconds since those that are backups were saved
+    recovery: Vec<(String, Option<u64>)>,
+    // Titles of plugin screens, listed after the power menu's own items
+    extras: Vec<String>,
+    // Items on the plugin screen being shown
+    plugin_items: Vec<String>,
+    autosave: Duration,
+    // Whether the paused game is being recorded
+    recording: bool,
+    // Whether frame stats are shown over the game
+    hud: bool,
+    // Whether demanding games use the faster CPU governor
+    cpu_boost: bool,
+    error_qr: bool,
+    // Diagnostics page for the error QR code, or recent log lines for it
+    error_link: Option<String>,
+    error_log: Vec<String>,
+    language: Language,
+    inner: Framebuffer,
+}
 
-        let (p, m) = match path.path().to_str() {
-            Some(p) => {
-                let path = String::from(p);
-                let meta = path.clone() + "." + METADATA_EXT;
-                (path, meta)
-            }
-            None => {
+impl Menu {
+    // String from a game's metadata, as listed in the README's "Game
+    // metadata" section
+    fn try_get_metadata(game_path: &Path, key: &str) -> Option<String> {
+        metadata::get_str(game_path, key)
+    }
+
+    fn process_game(dir: usize, path: PathBuf, root: &Path, has_meta: bool) -> Option<GameInfo> {
+        let file = match path.into_os_string().into_string() {
+            Ok(f) => f,
+            Err(_) => {
                 warn!("Path is not valid UTF-8");
                 return None;
             }
         };
-        let n = Self::try_get_metadata(path, &m);
+        if !dirs::is_game_file(Path::new(&file)) {
+            return None;
+        }
 
-        Some(GameInfo { path: p, name: n })
+        let title = has_meta
+            .then(|| Self::try_get_metadata(&root.join(&file), "name"))
+            .flatten()
+            .map(String::into_boxed_str);
+        Some(GameInfo {
+            dir,
+            file: file.into_boxed_str(),
+            title,
+        })
     }
 
-    fn find_games(root_dir: &str) -> Vec<GameInfo> {
+    fn find_games(rom_dirs: &[PathBuf], config: &MenuConfig) -> (Vec<GameInfo>, Vec<Range<usize>>) {
         let mut games = Vec::new();
 
-        match std::fs::read_dir(Path::new(root_dir).join(ROM_PATH)) {
-            Ok(paths) => {
-                for path in paths {
-                    match path {
-                        Ok(path) => {
-                            if let Some(c) = Self::process_game(path) {
-                                games.push(c);
-                            }
-                        }
-                        Err(e) => warn!("Error getting path: {}", e),
+        // Games in folders within the ROM directories are listed with the
+        // rest
+        for (i, dir) in rom_dirs.iter().enumerate() {
+            let files = dirs::rom_files(dir);
+            // Only games listed with a metadata file have it read
+            let listed: HashSet<&Path> = files.iter().map(|f| f.as_path()).collect();
+            games.extend(files.iter().filter_map(|path| {
+                let has_meta = listed.contains(metadata::path(path).as_path());
+                Self::process_game(i, path.clone(), dir, has_meta).map(|g| (g, has_meta))
+            }));
+        }
+
+        // Sorted by display name once the titles are known, see sort_entries
+        if !config.dedupe {
+            let mut games: Vec<GameInfo> = games.into_iter().map(|(g, _)| g).collect();
+            games.sort_unstable_by_key(|g| g.name().to_lowercase());
+            let entries = (0..games.len()).map(|i| i..i + 1).collect();
+            return (games, entries);
+        }
+
+        let mut keyed: Vec<_> = games
+            .into_iter()
+            .map(|(g, has_meta)| {
+                let rank = match region_rank(&g.file, &config.regions) {
+                    // Games named without region tags can give one in their
+                    // metadata
+                    r if r == config.regions.len() && has_meta => {
+                        let path = rom_dirs[g.dir].join(&*g.file);
+                        Self::try_get_metadata(&path, "region")
+                            .map_or(r, |m| region_rank(&format!("({})", m), &config.regions))
                     }
-                }
-            }
-            Err(_) => {
-                error!("Failed to read games directory");
+                    r => r,
+                };
+                (dedupe_key(&g.file), rank, g.name().to_lowercase(), g)
+            })
+            .collect();
+        keyed.sort_unstable_by(|a, b| (&a.0, a.1, &a.2).cmp(&(&b.0, b.1, &b.2)));
+
+        let mut entries: Vec<Range<usize>> = Vec::new();
+        for (i, (key, _, _, _)) in keyed.iter().enumerate() {
+            match entries.last_mut() {
+                Some(last) if keyed[last.start].0 == *key => last.end = i + 1,
+                _ => entries.push(i..i + 1),
             }
         }
+        debug!(
+            "{} games, {} after removing duplicates",
+            keyed.len(),
+            entries.len()
+        );
+        let games = keyed.into_iter().map(|(_, _, _, g)| g).collect();
+        (games, entries)
+    }
+
+    fn game_path(&self, game: &GameInfo) -> PathBuf {
+        self.rom_dirs[game.dir].join(&*game.file)
+    }
+
+    // Name from the metadata, the library's title or the file name
+    fn display_name(&self, game: &GameInfo) -> String {
+        match &game.title {
+            Some(title) => String::from(&**title),
+            None => self
+                .titles
+                .get(&self.game_path(game))
+                .cloned()
+                .unwrap_or_else(|| String::from(game.name())),
+        }
+    }
+
+    // Sort the entries by the display name of their preferred version. Games
+    // keep their indices, only the order of the entries changes.
+    fn sort_entries(&mut self) {
+        self.sort_names = self
+            .games
+            .iter()
+            .map(|g| self.display_name(g).to_lowercase())
+            .collect();
+        let mut order: Vec<usize> = (0..self.all_entries.len()).collect();
+        order.sort_by(|a, b| {
+            let a = &self.sort_names[self.all_entries[*a].start];
+            let b = &self.sort_names[self.all_entries[*b].start];
+            a.cmp(b)
+        });
+        let mut moved_to = vec![0; order.len()];
+        for (new, old) in order.iter().enumerate() {
+            moved_to[*old] = new;
+        }
+        self.all_entries = order.iter().map(|i| self.all_entries[*i].clone()).collect();
+        if !self.entry_systems.is_empty() {
+            self.entry_systems = order.iter().map(|i| self.entry_systems[*i]).collect();
+        }
+        for (_, i) in &mut self.pinned {
+            *i = moved_to[*i];
+        }
+        self.update_entries();
+    }
+
+    // Get the display name of a menu entry
+    fn game_name(&mut self, index: usize) -> String {
+        if let Some(name) = self.names.get(&index) {
+            return name.clone();
+        }
+        let entry = &self.entries[index];
+        let mut name = self.display_name(&self.games[entry.start]);
+        if entry.len() > 1 {
+            name += &format!(" +{}", entry.len() - 1);
+        }
+
+        if self.names.len() >= NAME_CACHE_SIZE {
+            // Keep the names closest to the one being loaded
+            let keep = NAME_CACHE_SIZE / 2;
+            self.names.retain(|i, _| i.abs_diff(index) < keep);
+        }
+        self.names.insert(index, name.clone());
+        name
+    }
+
+    // Badges shown after an entry, from the newest save of any of its
+    // versions and whether any of them has a state
+    fn game_badges(&mut self, index: usize) -> Option<String> {
+        if let Some(badges) = self.badges.get(&index) {
+            return badges.clone();
+        }
+        let paths: Vec<PathBuf> = self.games[self.entries[index].clone()]
+            .iter()
+            .map(|g| self.game_path(g))
+            .collect();
+        let mut badges = Vec::new();
+        if let Some(saved) = paths.iter().filter_map(|p| self.dirs.last_saved(p)).max() {
+            let secs = SystemTime::now()
+                .duration_since(saved)
+                .map_or(0, |d| d.as_secs());
+            badges.push(format!("[{}]", saved_ago(secs)));
+        }
+        if paths.iter().any(|p| self.dirs.has_state(p)) {
+            badges.push(String::from("[state]"));
+        }
+        let badges = (!badges.is_empty()).then(|| badges.join(" "));
+
+        if self.badges.len() >= NAME_CACHE_SIZE {
+            let keep = NAME_CACHE_SIZE / 2;
+            self.badges.retain(|i, _| i.abs_diff(index) < keep);
+        }
+        self.badges.insert(index, badges.clone());
+        badges
+    }
 
-        // TODO ordering other than alphabetic?
-        games.sort_unstable_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
-        games
+    /// Look at the saves again, e.g. after a game has been played
+    pub fn refresh_badges(&mut self) {
+        self.badges.clear();
+    }
+
+    // Image for a game, either named by `image` in its metadata, relative to
+    // the ROM directory, or with the same name as the game or its stem.
+    fn find_image(game_path: &Path) -> Option<PathBuf> {
+        if let Some(image) = Self::try_get_metadata(game_path, "image") {
+            let dir = game_path.parent().unwrap_or_else(|| Path::new(""));
+            return Some(dir.join(image));
+        }
+        IMAGE_EXTS
+            .iter()
+            .flat_map(|ext| {
+                let mut sidecar = game_path.as_os_str().to_os_string();
+                sidecar.push(".");
+                sidecar.push(ext);
+                [PathBuf::from(sidecar), game_path.with_extension(ext)]
+            })
+            .find(|p| p.exists())
+    }
+
+    // Load the thumbnail of a menu entry into the cache if needed
+    fn load_thumb(&mut self, index: usize) {
+        if !self.thumbs.contains_key(&index) {
+            let game = &self.games[self.entries[index].start];
+            let thumb = Self::find_image(&self.game_path(game)).and_then(|p| Thumbnail::load(&p));
+
+            if self.thumbs.len() >= THUMB_CACHE_SIZE {
+                let keep = THUMB_CACHE_SIZE / 2;
+                self.thumbs.retain(|i, _| i.abs_diff(index) < keep);
+            }
+            self.thumbs.insert(index, thumb);
+        }
     }
 
     pub fn log(&self) {
-        debug!("Games");
-        for (i, game) in self.games.iter().enumerate() {
-            debug!("  {:5} {} ({})", i, game.name, game.path);
+        debug!("{} games", self.entries.len());
+        for (i, entry) in self.entries.iter().enumerate() {
+            for game in &self.games[entry.clone()] {
+                trace!("  {:5} {}", i, self.game_path(game).display());
+            }
         }
     }
 
     pub fn set_cores(&mut self, cores: Vec<CoreInfo>) {
         self.emus = cores;
+        self.core_hints.clear();
+    }
+
+    /// Set hints for the cores, e.g. a missing BIOS, in the same order as
+    /// the cores
+    pub fn set_core_hints(&mut self, hints: Vec<Vec<String>>) {
+        self.core_hints = hints
+            .into_iter()
+            .map(|h| {
+                let badges: Vec<String> = h.iter().map(|h| format!("[{}]", h)).collect();
+                (!badges.is_empty()).then(|| badges.join(" "))
+            })
+            .collect();
+    }
+
+    /// Set the line of details shown above the menu
+    pub fn set_details(&mut self, details: Option<String>) {
+        self.details = details;
+    }
+
+    pub fn set_scale_mode(&mut self, mode: ScaleMode) {
+        self.scale_mode = mode;
+    }
+
+    /// Interval shown for the autosave item in the pause menu
+    pub fn set_autosave(&mut self, interval: Duration) {
+        self.autosave = interval;
+    }
+
+    /// Whether the performance item in the pause menu shows as on
+    pub fn set_hud(&mut self, hud: bool) {
+        self.hud = hud;
+    }
+
+    /// Whether the CPU boost item in the pause menu shows as on
+    pub fn set_cpu_boost(&mut self, boost: bool) {
+        self.cpu_boost = boost;
+    }
+
+    /// Where the error QR code links to, or without a link the recent log
+    /// lines to put in it
+    pub fn set_error_details(&mut self, link: Option<String>, log: Vec<String>) {
+        self.error_link = link;
+        self.error_log = log;
+    }
+
+    /// Whether the record item in the pause menu shows as on
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+    }
+
+    /// Set the options in the recovery menu, with the seconds since those
+    /// that are backups were saved
+    pub fn set_recovery(&mut self, items: Vec<(String, Option<u64>)>) {
+        self.recovery = items;
+    }
+
+    /// Set the titles of plugin screens, opened from the power menu
+    pub fn set_extras(&mut self, extras: Vec<String>) {
+        self.extras = extras;
+    }
+
+    /// Set the items on the plugin screen being shown
+    pub fn set_plugin_items(&mut self, items: Vec<String>) {
+        self.plugin_items = items;
+    }
+
+    // Power menu's own items, then plugin screens
+    fn power_items(&self) -> Vec<String> {
+        POWER_ITEMS
+            .iter()
+            .map(|p| String::from(p.label()))
+            .chain(self.extras.iter().cloned())
+            .collect()
+    }
+
+    /// Set the save slots to list, with the seconds since each was saved
+    pub fn set_slots(&mut self, slots: Vec<(String, u64)>) {
+        self.slots = slots;
     }
 
     fn draw_to_screen(&mut self, screen: &mut Screen) {
         screen.draw_full(self.inner.data());
     }
 
-    fn draw_menu_inner<T>(
-        window_size: usize,
-        inner: &mut Framebuffer,
-        vec: &[T],
-        index: usize,
-    ) -> Result<(), Box<dyn Error>>
-    where
-        T: Menuable,
-    {
-        let start = if (index / window_size) > vec.len() {
-            (vec.len() / window_size) * window_size
+    fn page_start(window_size: usize, len: usize, index: usize) -> usize {
+        if (index / window_size) > len {
+            (len / window_size) * window_size
         } else {
             (index / window_size) * window_size
-        };
-        let count = std::cmp::min(start + window_size, vec.len()) - start;
+        }
+    }
 
+    /// Number of items shown on one page of the menu
+    pub fn page_size(&self) -> usize {
+        ((self.inner.dim().0 - MENU_TOP_MARGIN) / MENU_ITEM_HEIGHT).into()
+    }
+
+    // Draw one page of a menu, items are the text for the page starting at
+    // index start, out of len items, with any badges after the text.
+    fn draw_menu_inner(
+        inner: &mut Framebuffer,
+        items: &[String],
+        badges: &[Option<String>],
+        start: usize,
+        len: usize,
+        index: usize,
+    ) -> Result<(), Box<dyn Error>> {
         let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
         let font_sel = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_SEL_COLOUR);
         let font_sml = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
         let font_sml_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
 
-        for i in 0..count {
+        // Leave room for the index numbers of large menus
+        let digits: u32 = len.saturating_sub(1).to_string().len().try_into()?;
+        let digit_width = PROFONT_9_POINT.character_size.width + PROFONT_9_POINT.character_spacing;
+        let number_width: i32 = (digits * digit_width).try_into()?;
+        let text_x = std::cmp::max(MENU_LEFT_MARGIN2, MENU_LEFT_MARGIN1 + number_width + 2);
+
+        for (i, text) in items.iter().enumerate() {
             let ind = i + start;
             let ii: u16 = i.try_into().expect("menu out of bounds");
-            let item = &vec[ind];
 
             let f = if index == ind { font_sel } else { font };
             let fs = if index == ind { font_sml_sel } else { font_sml };
             let y: i32 = (MENU_TOP_MARGIN + (ii * MENU_ITEM_HEIGHT)).into();
             Text::new(&ind.to_string(), Point::new(MENU_LEFT_MARGIN1, y), fs).draw(inner)?;
-            Text::new(&item.text(), Point::new(MENU_LEFT_MARGIN2, y), f).draw(inner)?;
+            let end = Text::new(text, Point::new(text_x, y), f).draw(inner)?;
+            if let Some(Some(badge)) = badges.get(i) {
+                Text::new(badge, end + Point::new(BADGE_GAP, 0), fs).draw(inner)?;
+            }
         }
 
         Ok(())
@@ -181,24 +633,150 @@ impl Menu {
         sel: MenuSel,
         index: usize,
     ) -> Result<(), Box<dyn Error>> {
-        self.inner.clear(BACKGROUND_COLOUR)?;
+        match (sel, screen.last_frame()) {
+            // Drawn over the game, faded so the menu can be read
+            (MenuSel::Pause | MenuSel::Slots, Some(frame)) => {
+                self.inner.copy_from(frame);
+                self.inner.fade_to(BACKGROUND_COLOUR, PAUSE_FADE);
+            }
+            _ => self.inner.clear(BACKGROUND_COLOUR)?,
+        }
 
-        let window_size: usize = ((self.inner.dim().0 - MENU_TOP_MARGIN) / MENU_ITEM_HEIGHT).into();
+        let window_size = self.page_size();
+        let len = self.num_items(sel);
+        let start = Self::page_start(window_size, len, index);
+        let end = std::cmp::min(start + window_size, len);
 
-        match sel {
-            MenuSel::Game => {
-                Self::draw_menu_inner(window_size, &mut self.inner, &self.games, index)?
+        // Only the names on this page are needed
+        let items: Vec<String> = match sel {
+            MenuSel::Game => (start..end)
+                .map(|i| {
+                    let mut name = self.game_name(i);
+                    if let Some(marker) = self.pin_markers.get(i) {
+                        name = format!("{} {}", marker, name);
+                    }
+                    if self.marked.contains(&self.entries[i].start) {
+                        name = format!("* {}", name);
+                    }
+                    name
+                })
+                .collect(),
+            MenuSel::Variant(e) => {
+                let first = self.entries[e].start;
+                self.games[first + start..first + end]
+                    .iter()
+                    .map(|g| String::from(g.name()))
+                    .collect()
             }
-            MenuSel::Core => {
-                Self::draw_menu_inner(window_size, &mut self.inner, &self.emus, index)?
+            MenuSel::Core => self.emus[start..end].iter().map(|c| c.name()).collect(),
+            MenuSel::Pause => PAUSE_ITEMS[start..end]
+                .iter()
+                .map(|p| match p {
+                    PauseItem::Scaling => format!("{}: {}", p.label(), self.scale_mode.label()),
+                    PauseItem::Performance => {
+                        format!("{}: {}", p.label(), if self.hud { "on" } else { "off" })
+                    }
+                    PauseItem::CpuBoost => {
+                        format!(
+                            "{}: {}",
+                            p.label(),
+                            if self.cpu_boost { "on" } else { "off" }
+                        )
+                    }
+                    PauseItem::Autosave => {
+                        format!("{}: {}s", p.label(), self.autosave.as_secs())
+                    }
+                    PauseItem::Record => {
+                        format!(
+                            "{}: {}",
+                            p.label(),
+                            if self.recording { "on" } else { "off" }
+                        )
+                    }
+                    _ => String::from(p.label()),
+                })
+                .collect(),
+            MenuSel::Slots => self.slots[start..end]
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            MenuSel::Power => self.power_items()[start..end].to_vec(),
+            MenuSel::Recovery => self.recovery[start..end]
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            MenuSel::Plugin => self.plugin_items[start..end].to_vec(),
+        };
+        let badges: Vec<Option<String>> = match sel {
+            MenuSel::Game => (start..end).map(|i| self.game_badges(i)).collect(),
+            MenuSel::Core => (start..end)
+                .map(|i| self.core_hints.get(i).cloned().flatten())
+                .collect(),
+            MenuSel::Slots => self.slots[start..end]
+                .iter()
+                .map(|(_, secs)| Some(format!("[{}]", saved_ago(*secs))))
+                .collect(),
+            MenuSel::Recovery => self.recovery[start..end]
+                .iter()
+                .map(|(_, secs)| secs.map(|s| format!("[{}]", saved_ago(s))))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Self::draw_menu_inner(&mut self.inner, &items, &badges, start, len, index)?;
+        if let MenuSel::Game = sel {
+            if index < len {
+                self.draw_thumb(index - start, index)?;
             }
+            self.draw_jump_bar(index)?;
+        }
+        let system = match (sel, self.system) {
+            (MenuSel::Game, Some(s)) => Some(&self.systems[s]),
+            _ => None,
         };
+        let details = match (system, &self.details) {
+            (Some(s), Some(d)) => Some(format!("[{}] {}", s, d)),
+            (Some(s), None) => Some(format!("[{}]", s)),
+            (None, d) => d.clone(),
+        };
+        if let Some(details) = details {
+            let font = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+            let pos = Point::new(MENU_LEFT_MARGIN1, MENU_DETAILS_TOP);
+            Text::new(&details, pos, font).draw(&mut self.inner)?;
+        }
 
         self.draw_to_screen(screen);
 
         Ok(())
     }
 
+    // Draw the thumbnail of an entry beside it, on the right of the screen
+    fn draw_thumb(&mut self, row: usize, index: usize) -> Result<(), Box<dyn Error>> {
+        let (width, height) = self.inner.dim();
+        let (width, height): (i32, i32) = (width.into(), height.into());
+        self.load_thumb(index);
+        let thumb = match self.thumbs.get(&index) {
+            Some(Some(t)) => t,
+            _ => return Ok(()),
+        };
+        let size = thumb.size();
+        let (tw, th): (i32, i32) = (size.width.try_into()?, size.height.try_into()?);
+        let row: i32 = row.try_into()?;
+        // Centred on the row where possible, text baselines are at the
+        // bottom of each row
+        let row_y = i32::from(MENU_TOP_MARGIN) + row * i32::from(MENU_ITEM_HEIGHT)
+            - i32::from(MENU_ITEM_HEIGHT) / 2;
+        let y = (row_y - th / 2).clamp(
+            i32::from(MENU_TOP_MARGIN),
+            std::cmp::max(i32::from(MENU_TOP_MARGIN), height - th),
+        );
+        // Always use the full width so the position doesn't move with each
+        // thumbnail
+        let max: i32 = THUMB_MAX.try_into()?;
+        let x = width - THUMB_MARGIN - max + (max - tw) / 2;
+        thumb.draw(&mut self.inner, Point::new(x, y))?;
+        Ok(())
+    }
+
     pub fn draw_error(
         &mut self,
         screen: &mut Screen,
@@ -207,10 +785,206 @@ impl Menu {
         self.inner.clear(ERROR_BACKGROUND_COLOUR)?;
         let font = MonoTextStyle::new(&PROFONT_12_POINT, ERROR_TEXT_COLOUR);
         let h: i32 = (self.inner.dim().0 / 2).into();
-        let err_txt = format!("{}", err);
-        Text::new("Error:", Point::new(MENU_ERR_LEFT_MARGIN, h - 14), font)
+        let heading = format!("{} {}:", error_heading(self.language), err.code_label());
+        let err_txt = err.describe(self.language);
+        Text::new(&heading, Point::new(MENU_ERR_LEFT_MARGIN, h - 14), font)
             .draw(&mut self.inner)?;
-        Text::new(&err_txt, Point::new(MENU_ERR_LEFT_MARGIN, h), font).draw(&mut self.inner)?;
+        Text::new(err_txt, Point::new(MENU_ERR_LEFT_MARGIN, h), font).draw(&mut self.inner)?;
+        if self.error_qr {
+            self.draw_error_qr(err, h + MENU_ERR_QR_GAP)?;
+        }
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    /// Draw the prompt shown after a crash, offering to copy the report to
+    /// a USB drive if one is mounted
+    pub fn draw_crash_report(
+        &mut self,
+        screen: &mut Screen,
+        report: &str,
+        drive: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.clear(ERROR_BACKGROUND_COLOUR)?;
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, ERROR_TEXT_COLOUR);
+        let font_sml = MonoTextStyle::new(&PROFONT_9_POINT, ERROR_TEXT_COLOUR);
+        let h: i32 = (self.inner.dim().0 / 2).into();
+        let copy = match drive {
+            Some(d) => format!("A: copy to {}", d),
+            None => String::from("Insert a USB drive to copy it"),
+        };
+        let lines = [
+            ("Crash report saved", font),
+            (report, font_sml),
+            (&copy, font),
+            ("B: skip", font),
+        ];
+        for (i, (text, style)) in lines.into_iter().enumerate() {
+            let y = h - 21 + i as i32 * 14;
+            Text::new(text, Point::new(MENU_ERR_LEFT_MARGIN, y), style).draw(&mut self.inner)?;
+        }
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    // Text of the error QR code, a link to the diagnostics page if there is
+    // one, otherwise the error and as many recent log lines as fit
+    fn error_qr(&self, err: GamepieError) -> Option<QrCode> {
+        if let Some(link) = &self.error_link {
+            return QrCode::encode(link.as_bytes());
+        }
+        let heading = format!(
+            "gamepie {}\n{} {:?}: {}",
+            env!("CARGO_PKG_VERSION"),
+            err.code_label(),
+            err,
+            err
+        );
+        let mut lines: Vec<&str> = self
+            .error_log
+            .iter()
+            .rev()
+            .take(ERR_QR_LINES)
+            .map(|l| {
+                l.char_indices()
+                    .nth(ERR_QR_LINE_LEN)
+                    .map_or(&l[..], |(i, _)| &l[..i])
+            })
+            .collect();
+        loop {
+            let mut text = heading.clone();
+            for line in lines.iter().rev() {
+                text.push('\n');
+                text.push_str(line);
+            }
+            if let Some(qr) = QrCode::encode(text.as_bytes()) {
+                return Some(qr);
+            }
+            lines.pop()?;
+        }
+    }
+
+    // QR code of the error below the message, so it can be photographed
+    // rather than copied off the screen.
+    fn draw_error_qr(&mut self, err: GamepieError, top: i32) -> Result<(), Box<dyn Error>> {
+        let qr = match self.error_qr(err) {
+            Some(qr) => qr,
+            None => {
+                warn!("Error too long for QR code");
+                return Ok(());
+            }
+        };
+        let (width, height) = self.inner.dim();
+        let modules = u32::try_from(qr.size())? + QUIET_ZONE * 2;
+        let space = u32::try_from(i32::from(height) - top).unwrap_or(0);
+        let scale = space / modules;
+        if scale == 0 {
+            warn!("No space for QR code");
+            return Ok(());
+        }
+        let x = (i32::from(width) - i32::try_from(modules * scale)?) / 2;
+        qr.draw(&mut self.inner, Point::new(x, top), scale)?;
+        Ok(())
+    }
+
+    /// Draw the input tester, the RetroPad for player 1 and the most
+    /// recent raw events from all players, newest at the bottom.
+    pub fn draw_input_test(
+        &mut self,
+        screen: &mut Screen,
+        pad: &PadView,
+        events: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.clear(BACKGROUND_COLOUR)?;
+        let (width, height) = self.inner.dim();
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
+        let font_sml = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
+        let font_sml_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+        Text::new(
+            "Input test",
+            Point::new(MENU_LEFT_MARGIN1, MENU_DETAILS_TOP),
+            font,
+        )
+        .draw(&mut self.inner)?;
+
+        let pad_width: i32 = PAD_SIZE.width.try_into()?;
+        let pad_top = MENU_DETAILS_TOP + 8;
+        let pad_left = (i32::from(width) - pad_width) / 2;
+        draw_pad(&mut self.inner, Point::new(pad_left, pad_top), pad)?;
+
+        // Hint on the bottom line, events fill the space above it
+        let hint_y = i32::from(height) - 4;
+        Text::new(
+            "Start+Select to exit",
+            Point::new(MENU_LEFT_MARGIN1, hint_y),
+            font_sml_sel,
+        )
+        .draw(&mut self.inner)?;
+        let events_top = pad_top + i32::try_from(PAD_SIZE.height)? + 12;
+        let rows = usize::try_from((hint_y - events_top) / MENU_EVENT_HEIGHT).unwrap_or(0);
+        let shown = &events[events.len().saturating_sub(rows)..];
+        for (i, event) in shown.iter().enumerate() {
+            let y = events_top + i32::try_from(i)? * MENU_EVENT_HEIGHT;
+            Text::new(event, Point::new(MENU_LEFT_MARGIN1, y), font_sml).draw(&mut self.inner)?;
+        }
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    /// Draw the latency test, the marker lit or dark and the measurements
+    /// so far
+    pub fn draw_latency_test(
+        &mut self,
+        screen: &mut Screen,
+        lit: bool,
+        lines: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.clear(BACKGROUND_COLOUR)?;
+        let (width, height) = self.inner.dim();
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
+        let font_sml = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
+        let font_sml_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+        Text::new(
+            "Latency test",
+            Point::new(MENU_LEFT_MARGIN1, MENU_DETAILS_TOP),
+            font,
+        )
+        .draw(&mut self.inner)?;
+
+        let size: i32 = LATENCY_MARKER_SIZE.try_into()?;
+        let marker = Rectangle::new(
+            Point::new(
+                i32::from(width) - size - LATENCY_MARKER_MARGIN,
+                LATENCY_MARKER_MARGIN,
+            ),
+            Size::new(LATENCY_MARKER_SIZE, LATENCY_MARKER_SIZE),
+        );
+        let fill = if lit { Rgb565::WHITE } else { Rgb565::BLACK };
+        marker
+            .into_styled(PrimitiveStyle::with_fill(fill))
+            .draw(&mut self.inner)?;
+        marker
+            .offset(1)
+            .into_styled(PrimitiveStyle::with_stroke(TEXT_COLOUR, 1))
+            .draw(&mut self.inner)?;
+
+        let top = MENU_DETAILS_TOP + size;
+        Text::new(
+            "A to toggle the marker",
+            Point::new(MENU_LEFT_MARGIN1, top),
+            font_sml,
+        )
+        .draw(&mut self.inner)?;
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + (i32::try_from(i)? + 2) * MENU_EVENT_HEIGHT;
+            Text::new(line, Point::new(MENU_LEFT_MARGIN1, y), font_sml).draw(&mut self.inner)?;
+        }
+        Text::new(
+            "Start+Select to exit",
+            Point::new(MENU_LEFT_MARGIN1, i32::from(height) - 4),
+            font_sml_sel,
+        )
+        .draw(&mut self.inner)?;
         self.draw_to_screen(screen);
         Ok(())
     }
@@ -227,10 +1001,362 @@ impl Menu {
         Ok(())
     }
 
+    /// Draw the game being loaded with a spinner below it, which moves on a
+    /// dot each `step`
+    pub fn draw_loading(
+        &mut self,
+        screen: &mut Screen,
+        name: &str,
+        step: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.clear(BACKGROUND_COLOUR)?;
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
+        let centre = self.inner.bounding_box().center();
+        Text::with_alignment(name, centre, font, Alignment::Center).draw(&mut self.inner)?;
+        let spinner = centre + Point::new(0, SPINNER_TOP + SPINNER_RADIUS as i32);
+        for dot in 0..SPINNER_DOTS {
+            let angle = dot as f32 * std::f32::consts::TAU / SPINNER_DOTS as f32;
+            let offset = Point::new(
+                (angle.sin() * SPINNER_RADIUS) as i32,
+                (-angle.cos() * SPINNER_RADIUS) as i32,
+            );
+            let colour = if dot == step % SPINNER_DOTS {
+                TEXT_SEL_COLOUR
+            } else {
+                TEXT_COLOUR
+            };
+            Circle::with_center(spinner + offset, SPINNER_DOT)
+                .into_styled(PrimitiveStyle::with_fill(colour))
+                .draw(&mut self.inner)?;
+        }
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    // Draw the initial letters of the games down the right edge, with the
+    // letter of the selected game highlighted
+    fn draw_jump_bar(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        if self.letters.len() < 2 {
+            return Ok(());
+        }
+        let (width, height) = self.inner.dim();
+        let top = i32::from(MENU_TOP_MARGIN);
+        let count: i32 = self.letters.len().try_into()?;
+        let spacing = std::cmp::min(JUMP_BAR_MAX_SPACING, (i32::from(height) - top) / count);
+        let current = self.letters.iter().rposition(|(_, s)| *s <= index);
+        let font = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
+        let font_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+        let x = i32::from(width) - JUMP_BAR_RIGHT;
+        let mut buf = [0; 4];
+        for (i, (letter, _)) in self.letters.iter().enumerate() {
+            let f = if current == Some(i) { font_sel } else { font };
+            let y = top + i32::try_from(i)? * spacing;
+            Text::new(letter.encode_utf8(&mut buf), Point::new(x, y), f).draw(&mut self.inner)?;
+        }
+        Ok(())
+    }
+
+    // Group the entries by system, from the folder they are in, their
+    // extension or the `system` in the metadata of those with an extension
+    // used by several systems
+    fn find_systems(&mut self) {
+        let mut systems: Vec<String> = Vec::new();
+        self.entry_systems = self
+            .all_entries
+            .iter()
+            .map(|entry| {
+                let game = &self.games[entry.start];
+                let known = game
+                    .folder()
+                    .map(system::from_folder)
+                    .or_else(|| system::from_extension(&game.file).map(String::from));
+                let name = match known {
+                    Some(s) => s,
+                    None => Self::try_get_metadata(&self.game_path(game), "system")
+                        .unwrap_or_else(|| String::from(OTHER_SYSTEM)),
+                };
+                match systems.iter().position(|s| *s == name) {
+                    Some(i) => i,
+                    None => {
+                        systems.push(name);
+                        systems.len() - 1
+                    }
+                }
+            })
+            .collect();
+        debug!("{} systems", systems.len());
+        self.systems = systems;
+    }
+
+    // Show the entries for the current system and search
+    fn update_entries(&mut self) {
+        let in_system = |i: usize| self.system.is_none_or(|s| self.entry_systems[i] == s);
+        let entries: Vec<Range<usize>> = self
+            .all_entries
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| in_system(*i))
+            .filter(|(_, e)| {
+                self.query.is_empty()
+                    || self.games[e.start]
+                        .name()
+                        .to_lowercase()
+                        .contains(&self.query)
+            })
+            .map(|(_, e)| e.clone())
+            .collect();
+        // Pinned games are left out of searches, where they'd be repeated
+        let pinned: Vec<(char, usize)> = if self.query.is_empty() {
+            self.pinned
+                .iter()
+                .filter(|(_, i)| in_system(*i))
+                .copied()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let offset = pinned.len();
+        self.letters = find_letters(&self.sort_names, &entries)
+            .into_iter()
+            .map(|(l, i)| (l, i + offset))
+            .collect();
+        self.pin_markers = pinned.iter().map(|(m, _)| *m).collect();
+        self.entries = pinned
+            .iter()
+            .map(|(_, i)| self.all_entries[*i].clone())
+            .chain(entries)
+            .collect();
+        // Cached by index, which now refers to other entries
+        self.names.clear();
+        self.thumbs.clear();
+        self.badges.clear();
+    }
+
+    /// Whether the game list can be switched between systems
+    pub fn has_systems(&self) -> bool {
+        self.systems.len() > 1
+    }
+
+    /// Show the next or previous system in the game list, all systems are
+    /// shown before the first.
+    pub fn next_system(&mut self, forward: bool) {
+        if !self.has_systems() {
+            return;
+        }
+        let next = match (self.system, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(self.systems.len() - 1),
+            (Some(s), true) if s + 1 < self.systems.len() => Some(s + 1),
+            (Some(s), false) if s > 0 => Some(s - 1),
+            (Some(_), _) => None,
+        };
+        match next {
+            Some(s) => info!("Showing {} games", self.systems[s]),
+            None => info!("Showing all games"),
+        }
+        self.system = next;
+        self.update_entries();
+    }
+
+    /// Show recent and favourite games, by filename, at the top of the game
+    /// list
+    pub fn set_pinned(&mut self, recent: &[String], favourites: &[String]) {
+        let mut by_file = HashMap::new();
+        for (i, entry) in self.all_entries.iter().enumerate() {
+            for game in &self.games[entry.clone()] {
+                by_file.insert(game.name(), i);
+            }
+        }
+        let mut pinned: Vec<(char, usize)> = Vec::new();
+        for (marker, files) in [(RECENT_MARKER, recent), (FAVOURITE_MARKER, favourites)] {
+            for file in files {
+                // Games that have gone, or other versions of a recent game
+                match by_file.get(file.as_str()) {
+                    Some(i) if !pinned.contains(&(marker, *i)) => pinned.push((marker, *i)),
+                    _ => {}
+                }
+            }
+        }
+        self.pinned = pinned;
+        self.update_entries();
+    }
+
+    /// Set the titles of games identified by the library, by path
+    pub fn set_titles(&mut self, titles: HashMap<PathBuf, String>) {
+        self.titles = titles;
+        self.sort_entries();
+    }
+
+    /// Find the games again, e.g. after ROMs have been added. Marks and
+    /// pinned games are cleared, so `set_pinned` should be called after.
+    pub fn rescan(&mut self, config: &MenuConfig) {
+        self.rom_dirs = self
+            .dirs
+            .rom_dirs()
+            .iter()
+            .map(|d| d.to_path_buf())
+            .collect();
+        let (games, entries) = Self::find_games(&self.rom_dirs, config);
+        info!("Found {} games", games.len());
+        self.games = games;
+        self.all_entries = entries;
+        self.marked.clear();
+        self.pinned.clear();
+        self.system = None;
+        self.entry_systems.clear();
+        self.sort_entries();
+        if config.group_systems {
+            self.find_systems();
+        }
+    }
+
+    /// Only show games with the query in their filename, ignoring case
+    pub fn set_query(&mut self, query: &str) {
+        self.query = query.to_lowercase();
+        self.update_entries();
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Draw the search query, the first few matches and the on-screen
+    /// keyboard
+    pub fn draw_search(
+        &mut self,
+        screen: &mut Screen,
+        cursor: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.inner.clear(BACKGROUND_COLOUR)?;
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
+        let font_sml = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_COLOUR);
+        let font_sml_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+        let query = format!("Search: {}_", self.query.to_uppercase());
+        Text::new(
+            &query,
+            Point::new(MENU_LEFT_MARGIN1, MENU_DETAILS_TOP),
+            font,
+        )
+        .draw(&mut self.inner)?;
+
+        let area = self.keyboard_area()?;
+        let keys_top = area.top_left.y;
+        let count = format!("{} matches", self.entries.len());
+        Text::new(
+            &count,
+            Point::new(MENU_LEFT_MARGIN1, keys_top - 4),
+            font_sml_sel,
+        )
+        .draw(&mut self.inner)?;
+        // Matches fill the space between the query and the count
+        let top = i32::from(MENU_TOP_MARGIN);
+        let rows = usize::try_from((keys_top - 4 - top) / MENU_EVENT_HEIGHT).unwrap_or(0);
+        for i in 0..std::cmp::min(rows, self.entries.len()) {
+            let name = self.game_name(i);
+            let y = top + i32::try_from(i)? * MENU_EVENT_HEIGHT;
+            Text::new(&name, Point::new(MENU_LEFT_MARGIN1, y), font_sml).draw(&mut self.inner)?;
+        }
+
+        draw_keyboard(&mut self.inner, area, cursor)?;
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    /// Draw the on-screen keyboard over the paused game, with the text typed
+    /// so far to send to it
+    pub fn draw_typing(
+        &mut self,
+        screen: &mut Screen,
+        cursor: usize,
+        text: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        match screen.last_frame() {
+            Some(frame) => {
+                self.inner.copy_from(frame);
+                self.inner.fade_to(BACKGROUND_COLOUR, PAUSE_FADE);
+            }
+            None => self.inner.clear(BACKGROUND_COLOUR)?,
+        }
+        let font = MonoTextStyle::new(&PROFONT_12_POINT, TEXT_COLOUR);
+        let font_sml_sel = MonoTextStyle::new(&PROFONT_9_POINT, TEXT_SEL_COLOUR);
+        Text::new(
+            &format!("Type: {}_", text),
+            Point::new(MENU_LEFT_MARGIN1, MENU_DETAILS_TOP),
+            font,
+        )
+        .draw(&mut self.inner)?;
+
+        let area = self.keyboard_area()?;
+        Text::new(
+            "OK sends the text and Enter",
+            Point::new(MENU_LEFT_MARGIN1, area.top_left.y - 4),
+            font_sml_sel,
+        )
+        .draw(&mut self.inner)?;
+        draw_keyboard(&mut self.inner, area, cursor)?;
+        self.draw_to_screen(screen);
+        Ok(())
+    }
+
+    // Space for the on-screen keyboard, along the bottom of the screen
+    fn keyboard_area(&self) -> Result<Rectangle, Box<dyn Error>> {
+        let (width, height) = self.inner.dim();
+        let keys_height = u32::try_from(KEYBOARD_KEYS.div_ceil(KEYBOARD_COLUMNS))? * KEY_HEIGHT;
+        let keys_top = i32::from(height) - i32::try_from(keys_height)? - 4;
+        Ok(Rectangle::new(
+            Point::new(MENU_LEFT_MARGIN1, keys_top),
+            Size::new(
+                u32::from(width) - 2 * u32::try_from(MENU_LEFT_MARGIN1)?,
+                keys_height,
+            ),
+        ))
+    }
+
+    /// Mark or unmark the game at an index for party mode
+    pub fn toggle_mark(&mut self, index: usize) {
+        if let Some(entry) = self.entries.get(index) {
+            match self.marked.iter().position(|m| *m == entry.start) {
+                Some(i) => {
+                    self.marked.remove(i);
+                }
+                None => self.marked.push(entry.start),
+            }
+        }
+    }
+
+    /// Paths to the games marked for party mode, in the order marked
+    pub fn marked_paths(&self) -> Vec<String> {
+        self.marked
+            .iter()
+            .map(|m| {
+                self.game_path(&self.games[*m])
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marked.clear();
+    }
+
+    /// Index to move to when jumping forward or back from an index. The game
+    /// list jumps between initial letters, other menus move a page.
+    pub fn jump(&self, sel: MenuSel, index: usize, forward: bool) -> usize {
+        match sel {
+            MenuSel::Game => jump(&self.letters, index, forward),
+            _ if forward => std::cmp::min(
+                index + self.page_size(),
+                self.num_items(sel).saturating_sub(1),
+            ),
+            _ => index.saturating_sub(self.page_size()),
+        }
+    }
+
     fn safe_index_inner<T>(&self, vec: &[T], index: usize) -> usize {
         // If max, wrapped round from zero so go to last item
         if index == usize::MAX {
-            vec.len() - 1
+            vec.len().saturating_sub(1)
         } else if index >= vec.len() {
             0
         } else {
@@ -240,8 +1366,16 @@ impl Menu {
 
     pub fn safe_index(&self, sel: MenuSel, index: usize) -> usize {
         match sel {
-            MenuSel::Game => self.safe_index_inner(&self.games, index),
+            MenuSel::Game => self.safe_index_inner(&self.entries, index),
+            MenuSel::Variant(e) => {
+                self.safe_index_inner(&self.games[self.entries[e].clone()], index)
+            }
             MenuSel::Core => self.safe_index_inner(&self.emus, index),
+            MenuSel::Pause => self.safe_index_inner(&PAUSE_ITEMS, index),
+            MenuSel::Slots => self.safe_index_inner(&self.slots, index),
+            MenuSel::Power => self.safe_index_inner(&self.power_items(), index),
+            MenuSel::Recovery => self.safe_index_inner(&self.recovery, index),
+            MenuSel::Plugin => self.safe_index_inner(&self.plugin_items, index),
         }
     }
 
@@ -249,23 +1383,65 @@ impl Menu {
         self.emus.get(index).expect("invalid index").clone()
     }
 
+    /// Index of the entry for a game's path nearest to `near`, e.g. to keep
+    /// it selected after a rescan
+    pub fn find_game(&self, path: &str, near: usize) -> Option<usize> {
+        (0..self.entries.len())
+            .filter(|i| {
+                self.games[self.entries[*i].clone()]
+                    .iter()
+                    .any(|g| self.game_path(g).as_os_str() == path)
+            })
+            .min_by_key(|i| i.abs_diff(near))
+    }
+
+    /// Path to the preferred version of a game
     pub fn get_path(&self, index: usize) -> String {
-        let game = self.games.get(index);
+        self.get_variant_path(index, 0)
+    }
+
+    pub fn get_variant_path(&self, index: usize, variant: usize) -> String {
+        let game = self
+            .entries
+            .get(index)
+            .filter(|e| variant < e.len())
+            .and_then(|e| self.games.get(e.start + variant));
         match game {
-            Some(g) => g.path.clone(),
+            // Filenames were checked to be UTF-8 and directories came from
+            // the config so will be too
+            Some(g) => self.game_path(g).to_string_lossy().into_owned(),
             None => String::from(""),
         }
     }
 
+    /// Number of items in a menu
+    pub fn num_items(&self, sel: MenuSel) -> usize {
+        match sel {
+            MenuSel::Game => self.entries.len(),
+            MenuSel::Variant(e) => self.num_variants(e),
+            MenuSel::Core => self.emus.len(),
+            MenuSel::Pause => PAUSE_ITEMS.len(),
+            MenuSel::Slots => self.slots.len(),
+            MenuSel::Power => POWER_ITEMS.len() + self.extras.len(),
+            MenuSel::Recovery => self.recovery.len(),
+            MenuSel::Plugin => self.plugin_items.len(),
+        }
+    }
+
     pub fn num_cores(&self) -> usize {
         self.emus.len()
     }
 
     pub fn num_games(&self) -> usize {
-        self.games.len()
+        self.entries.len()
+    }
+
+    /// Number of versions of the game at this index
+    pub fn num_variants(&self, index: usize) -> usize {
+        self.entries.get(index).map_or(0, |e| e.len())
     }
 
-    pub fn new(root_dir: &str, width: u16, height: u16) -> Self {
+    pub fn new(dirs: &GamepieDirs, config: &MenuConfig, width: u16, height: u16) -> Self {
         let mut buffer = Vec::new();
         let wsz: usize = width.into();
         let hsz: usize = height.into();
@@ -274,10 +1450,119 @@ impl Menu {
 
         let inner = Framebuffer::new(width, height, buffer);
 
-        Menu {
-            games: Self::find_games(root_dir),
+        let rom_dirs: Vec<PathBuf> = dirs.rom_dirs().iter().map(|d| d.to_path_buf()).collect();
+        let (games, entries) = Self::find_games(&rom_dirs, config);
+
+        let mut menu = Menu {
+            rom_dirs,
+            dirs: dirs.clone(),
+            games,
+            entries: entries.clone(),
+            all_entries: entries,
+            systems: Vec::new(),
+            entry_systems: Vec::new(),
+            system: None,
+            query: String::new(),
+            marked: Vec::new(),
+            pinned: Vec::new(),
+            pin_markers: Vec::new(),
+            letters: Vec::new(),
+            names: HashMap::new(),
+            sort_names: Vec::new(),
+            titles: HashMap::new(),
+            thumbs: HashMap::new(),
+            badges: HashMap::new(),
+            details: None,
+            scale_mode: ScaleMode::Crop,
             inner,
             emus: Vec::new(),
+            core_hints: Vec::new(),
+            slots: Vec::new(),
+            recovery: Vec::new(),
+            extras: Vec::new(),
+            plugin_items: Vec::new(),
+            autosave: Duration::ZERO,
+            recording: false,
+            hud: false,
+            cpu_boost: false,
+            error_qr: config.error_qr,
+            error_link: None,
+            error_log: Vec::new(),
+            language: config.language,
+        };
+        menu.sort_entries();
+        if config.group_systems {
+            menu.find_systems();
+        }
+        menu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedupe_key, initial, jump, region_rank, saved_ago, Menu};
+    use gamepie_core::config::Config;
+    use gamepie_core::testing::TestDir;
+    use std::collections::HashMap;
+
+    #[test]
+    fn dumps_share_key() {
+        let a = dedupe_key("Tetris (World) (Rev 1).gb");
+        let b = dedupe_key("Tetris  [!].GB");
+        assert_eq!(a, b);
+        assert_ne!(a, dedupe_key("Tetris (World).gbc"));
+    }
+
+    #[test]
+    fn preferred_region() {
+        let regions = vec![String::from("Europe"), String::from("USA")];
+        assert_eq!(region_rank("Game (USA, Europe).gba", &regions), 0);
+        assert_eq!(region_rank("Game (USA).gba", &regions), 1);
+        assert_eq!(region_rank("Game (Japan).gba", &regions), 2);
+    }
+
+    #[test]
+    fn jump_between_letters() {
+        assert_eq!(initial("1942.nes"), '#');
+        assert_eq!(initial("zelda.gb"), 'Z');
+        let letters = [('#', 0), ('A', 3), ('C', 7)];
+        assert_eq!(jump(&letters, 0, true), 3);
+        assert_eq!(jump(&letters, 4, true), 7);
+        assert_eq!(jump(&letters, 8, true), 0);
+        assert_eq!(jump(&letters, 5, false), 3);
+        assert_eq!(jump(&letters, 3, false), 0);
+        assert_eq!(jump(&letters, 0, false), 7);
+    }
+
+    #[test]
+    fn save_age() {
+        assert_eq!(saved_ago(59), "saved now");
+        assert_eq!(saved_ago(5 * 60 + 30), "saved 5m ago");
+        assert_eq!(saved_ago(3 * 60 * 60), "saved 3h ago");
+        assert_eq!(saved_ago(50 * 60 * 60), "saved 2d ago");
+    }
+
+    #[test]
+    fn sorted_by_name() {
+        let root = TestDir::new("menu");
+        let roms = root.join(gamepie_core::ROM_PATH);
+        std::fs::create_dir_all(&roms).unwrap();
+        for file in ["Alpha.gb", "zzz.gb", "Gamma.gb"] {
+            std::fs::write(roms.join(file), "").unwrap();
         }
+        std::fs::write(roms.join("zzz.gb.toml"), "name = \"Beta\"").unwrap();
+
+        let config = Config::load(&root.path().to_string_lossy(), None);
+        let mut menu = Menu::new(&config.dirs, &config.menu, 240, 240);
+        let names = |menu: &mut Menu| -> Vec<String> {
+            (0..menu.num_games()).map(|i| menu.game_name(i)).collect()
+        };
+        assert_eq!(names(&mut menu), ["Alpha.gb", "Beta", "Gamma.gb"]);
+        assert_eq!(menu.letters, [('A', 0), ('B', 1), ('G', 2)]);
+
+        // Titles from the library move games too
+        let titles = HashMap::from([(roms.join("Gamma.gb"), String::from("Aardvark"))]);
+        menu.set_titles(titles);
+        assert_eq!(names(&mut menu), ["Aardvark", "Alpha.gb", "Beta"]);
     }
 }
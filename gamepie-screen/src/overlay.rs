@@ -1,14 +1,45 @@
 use embedded_graphics::{
-    mono_font::MonoTextStyle, pixelcolor::Rgb565, prelude::*, primitives::PrimitiveStyleBuilder,
-    primitives::Rectangle, text::Text,
+    mono_font::MonoTextStyle, pixelcolor::Rgb565, prelude::*, primitives::Circle,
+    primitives::PrimitiveStyleBuilder, primitives::Rectangle, text::Text,
 };
-use profont::{PROFONT_12_POINT, PROFONT_18_POINT};
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT, PROFONT_9_POINT};
 
 use crate::sprites::*;
 use gamepie_core::commands::{ScreenMessage, ScreenToast};
 use gamepie_core::discard_error;
 
 const TOAST_LEFT_MARGIN: i32 = 30;
+const HUD_MARGIN: i32 = 2;
+
+/// Draw lines of text in the top left corner, on black so they can be read
+/// over any game
+pub(crate) fn draw_hud<T>(target: &mut T, lines: &[String])
+where
+    T: DrawTarget<Color = Rgb565, Error = std::convert::Infallible>,
+{
+    let font = &PROFONT_9_POINT;
+    let char_width = font.character_size.width + font.character_spacing;
+    let line_height = font.character_size.height;
+    let width = lines.iter().map(|l| l.len() as u32).max().unwrap_or(0) * char_width;
+    let height = lines.len() as u32 * line_height;
+    let bg = PrimitiveStyleBuilder::new()
+        .fill_color(Rgb565::BLACK)
+        .build();
+    let margin = HUD_MARGIN as u32;
+    discard_error(
+        Rectangle::new(
+            Point::zero(),
+            Size::new(width + margin * 2, height + margin * 2),
+        )
+        .into_styled(bg)
+        .draw(target),
+    );
+    let style = MonoTextStyle::new(font, Rgb565::WHITE);
+    for (n, line) in lines.iter().enumerate() {
+        let y = HUD_MARGIN + n as i32 * line_height as i32 + font.baseline as i32;
+        discard_error(Text::new(line, Point::new(HUD_MARGIN, y), style).draw(target));
+    }
+}
 
 pub(crate) struct ToastDrawer<'a> {
     toast: &'a ScreenToast,
@@ -102,6 +133,18 @@ impl ToastDrawer<'_> {
                 discard_error(SPRITE_VOL_DN.draw(&mut sprite_drawer));
                 self.draw_vol(target, bb, centre, *vol);
             }
+            ScreenMessage::Brightness(brightness) => {
+                // No sprite for brightness, so draw a filled circle in its place
+                let style = PrimitiveStyleBuilder::new()
+                    .fill_color(Rgb565::WHITE)
+                    .build();
+                discard_error(
+                    Circle::new(Point::zero(), SPRITE_DIMU)
+                        .into_styled(style)
+                        .draw(&mut translated),
+                );
+                self.draw_vol(target, bb, centre, *brightness);
+            }
             ScreenMessage::AudioIssue => {
                 discard_error(
                     Text::new(
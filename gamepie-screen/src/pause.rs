@@ -0,0 +1,58 @@
+/// Items in the pause menu, shown over the game while it is paused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PauseItem {
+    Resume,
+    QuickSave,
+    QuickLoad,
+    /// Cycles through the scaling modes
+    Scaling,
+    /// Shows frame stats over the game
+    Performance,
+    /// Uses the faster CPU governor for demanding games
+    CpuBoost,
+    /// Cycles through how often save RAM is written
+    Autosave,
+    /// Lists the saves that can be restored
+    SaveSlots,
+    /// On-screen keyboard to type into the game
+    Keyboard,
+    /// Starts or stops recording a video
+    Record,
+    /// Puts the core's options back to their defaults
+    ResetOptions,
+    Exit,
+}
+
+pub const PAUSE_ITEMS: [PauseItem; 12] = [
+    PauseItem::Resume,
+    PauseItem::QuickSave,
+    PauseItem::QuickLoad,
+    PauseItem::Scaling,
+    PauseItem::Performance,
+    PauseItem::CpuBoost,
+    PauseItem::Autosave,
+    PauseItem::SaveSlots,
+    PauseItem::Keyboard,
+    PauseItem::Record,
+    PauseItem::ResetOptions,
+    PauseItem::Exit,
+];
+
+impl PauseItem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PauseItem::Resume => "Resume",
+            PauseItem::QuickSave => "Quick save",
+            PauseItem::QuickLoad => "Quick load",
+            PauseItem::Scaling => "Scaling",
+            PauseItem::Performance => "Performance",
+            PauseItem::CpuBoost => "CPU boost",
+            PauseItem::Autosave => "Autosave",
+            PauseItem::SaveSlots => "Save slots",
+            PauseItem::Keyboard => "Type text",
+            PauseItem::Record => "Record video",
+            PauseItem::ResetOptions => "Reset core options",
+            PauseItem::Exit => "Exit game",
+        }
+    }
+}
@@ -0,0 +1,451 @@
+// PNG decoder for box art, with the inflate it needs. Only non-interlaced
+// images are read, which is how scrapers and RetroArch save thumbnails.
+// Huffman codes are decoded a bit at a time (as zlib's puff does), slow but
+// small, and thumbnails are cached once scaled.
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// Larger images are refused rather than decoded into memory
+const MAX_DIM: u32 = 4096;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order the code length code lengths are sent in
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub(crate) type PngResult<T> = Result<T, &'static str>;
+
+/// Decoded image, 8-bit RGBA
+pub(crate) struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+// Deflate data, read least significant bit first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: u32) -> PngResult<u32> {
+        while self.count < n {
+            let b = *self
+                .data
+                .get(self.pos)
+                .ok_or("compressed data is truncated")?;
+            self.pos += 1;
+            self.buf |= u32::from(b) << self.count;
+            self.count += 8;
+        }
+        let v = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+
+    // Stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+// Canonical Huffman code, as counts of each code length and the symbols in
+// code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for l in lengths {
+            counts[usize::from(*l)] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, l) in lengths.iter().enumerate() {
+            if *l != 0 {
+                symbols[usize::from(offsets[usize::from(*l)])] = sym as u16;
+                offsets[usize::from(*l)] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> PngResult<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or("invalid Huffman code");
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code")
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> PngResult<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    let mut clens = [0u8; 19];
+    for i in CLEN_ORDER.iter().take(ncode) {
+        clens[*i] = bits.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clens);
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (value, repeat) = match clen.decode(bits)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => (
+                *lengths.last().ok_or("repeat with no previous length")?,
+                3 + bits.bits(2)?,
+            ),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > nlen + ndist {
+        return Err("too many code lengths");
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    limit: usize,
+    (lit, dist): &(Huffman, Huffman),
+) -> PngResult<()> {
+    loop {
+        let sym = usize::from(lit.decode(bits)?);
+        if sym < 256 {
+            out.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let i = sym - 257;
+            let extra = u32::from(*LENGTH_EXTRA.get(i).ok_or("invalid length")?);
+            let len = usize::from(LENGTH_BASE[i]) + bits.bits(extra)? as usize;
+            let d = usize::from(dist.decode(bits)?);
+            let extra = u32::from(*DIST_EXTRA.get(d).ok_or("invalid distance")?);
+            let back = usize::from(DIST_BASE[d]) + bits.bits(extra)? as usize;
+            if back > out.len() {
+                return Err("distance too far back");
+            }
+            let start = out.len() - back;
+            for i in 0..len {
+                out.push(out[start + i]);
+            }
+        }
+        if out.len() > limit {
+            return Err("more data than the image needs");
+        }
+    }
+}
+
+/// Decompress zlib data, at most `limit` bytes
+pub(crate) fn inflate(data: &[u8], limit: usize) -> PngResult<Vec<u8>> {
+    let [cmf, flg, ..] = *data else {
+        return Err("zlib header is truncated");
+    };
+    if cmf & 0x0f != 8 || (u16::from(cmf) * 256 + u16::from(flg)) % 31 != 0 || flg & 0x20 != 0 {
+        return Err("invalid zlib header");
+    }
+    let mut bits = Bits {
+        data: &data[2..],
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits
+                    .data
+                    .get(bits.pos..bits.pos + 4)
+                    .ok_or("truncated block")?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != usize::from(!nlen) {
+                    return Err("invalid stored block");
+                }
+                bits.pos += 4;
+                let stored = bits
+                    .data
+                    .get(bits.pos..bits.pos + len)
+                    .ok_or("truncated block")?;
+                out.extend_from_slice(stored);
+                bits.pos += len;
+                if out.len() > limit {
+                    return Err("more data than the image needs");
+                }
+            }
+            1 => inflate_block(&mut bits, &mut out, limit, &fixed_codes())?,
+            2 => {
+                let codes = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, limit, &codes)?;
+            }
+            _ => return Err("invalid block type"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Undo the filter of each row in place, returning the rows without their
+// filter bytes
+fn unfilter(data: &mut [u8], stride: usize, bpp: usize) -> PngResult<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    for row in data.chunks_exact_mut(stride + 1) {
+        let (filter, row) = row.split_first_mut().ok_or("empty row")?;
+        let prev = out.len().checked_sub(stride).map(|s| s..out.len());
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev.as_ref().map_or(0, |p| out[p.start + i]);
+            let c = match &prev {
+                Some(p) if i >= bpp => out[p.start + i - bpp],
+                _ => 0,
+            };
+            row[i] = row[i].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err("invalid filter"),
+            });
+        }
+        out.extend_from_slice(row);
+    }
+    Ok(out)
+}
+
+/// Decode a PNG image
+pub(crate) fn decode(data: &[u8]) -> PngResult<Image> {
+    let mut rest = data.strip_prefix(SIGNATURE).ok_or("not a PNG image")?;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut alphas: &[u8] = &[];
+    let mut compressed = Vec::new();
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let chunk = rest.get(8..8 + len).ok_or("chunk is truncated")?;
+        match kind {
+            b"IHDR" if chunk.len() >= 13 => header = Some(chunk),
+            b"PLTE" => palette = chunk,
+            b"tRNS" => alphas = chunk,
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Skip the CRC, a damaged image shows as a damaged thumbnail
+        rest = rest.get(12 + len..).ok_or("chunk is truncated")?;
+    }
+    let header = header.ok_or("no image header")?;
+    let width = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let (depth, colour, interlace) = (header[8], header[9], header[12]);
+    if width == 0 || height == 0 || width > MAX_DIM || height > MAX_DIM {
+        return Err("unsupported image size");
+    }
+    if interlace != 0 {
+        return Err("interlaced images aren't supported");
+    }
+    let channels = match (colour, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) | (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (2, 8 | 16) => 3,
+        (6, 8 | 16) => 4,
+        _ => return Err("unsupported colour type"),
+    };
+    let bits_per_pixel = channels * usize::from(depth);
+    let stride = (width as usize * bits_per_pixel).div_ceil(8);
+    let bpp = std::cmp::max(1, bits_per_pixel / 8);
+    let size = (stride + 1) * height as usize;
+    let mut raw = inflate(&compressed, size)?;
+    if raw.len() < size {
+        return Err("image data is truncated");
+    }
+    let rows = unfilter(&mut raw[..size], stride, bpp)?;
+
+    // Sample `c` of pixel `x` in a row, scaled to 8 bits
+    let sample = |row: &[u8], x: usize, c: usize| -> u8 {
+        match depth {
+            16 => row[(x * channels + c) * 2],
+            8 => row[x * channels + c],
+            d => {
+                let bit = x * usize::from(d);
+                let v = (row[bit / 8] >> (8 - usize::from(d) - bit % 8)) & ((1 << d) - 1);
+                if colour == 3 {
+                    v
+                } else {
+                    (u16::from(v) * 255 / ((1 << d) - 1)) as u8
+                }
+            }
+        }
+    };
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in rows.chunks_exact(stride) {
+        for x in 0..width as usize {
+            let s = |c| sample(row, x, c);
+            pixels.push(match colour {
+                0 => [s(0), s(0), s(0), 255],
+                2 => [s(0), s(1), s(2), 255],
+                3 => {
+                    let i = usize::from(s(0));
+                    let rgb = palette
+                        .get(i * 3..i * 3 + 3)
+                        .ok_or("colour not in palette")?;
+                    [
+                        rgb[0],
+                        rgb[1],
+                        rgb[2],
+                        alphas.get(i).copied().unwrap_or(255),
+                    ]
+                }
+                4 => [s(0), s(0), s(0), s(1)],
+                _ => [s(0), s(1), s(2), s(3)],
+            });
+        }
+    }
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, inflate};
+
+    #[test]
+    fn dynamic_codes() {
+        let compressed = [
+            0x78, 0xda, 0xed, 0xc8, 0xa1, 0x11, 0x00, 0x00, 0x08, 0x03, 0xb1, 0x59, 0x11, 0xaf,
+            0x6b, 0xba, 0xff, 0x61, 0xd0, 0x4c, 0xf0, 0x32, 0xa1, 0x14, 0x02, 0xed, 0x24, 0xd3,
+            0x83, 0x69, 0x9a, 0xa6, 0x69, 0xbe, 0xb9, 0xf0, 0xe9, 0x9b, 0xb5,
+        ];
+        let expected: Vec<u8> = (0..1600usize)
+            .map(|i| b"eeeeeeeeettttaaoinshr"[(i * 7919 + i * i * 31) % 21])
+            .collect();
+        assert_eq!(inflate(&compressed, 1600).unwrap(), expected);
+        assert!(inflate(&compressed, 100).is_err());
+        assert!(inflate(&compressed[..20], 1600).is_err());
+    }
+
+    #[test]
+    fn rgba_filtered() {
+        // Fixed codes, the rows use the sub and up filters
+        let png = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00,
+            0x00, 0x72, 0xb6, 0x0d, 0x24, 0x00, 0x00, 0x00, 0x17, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0xda, 0x63, 0xe4, 0x12, 0x91, 0xfb, 0x0f, 0xc4, 0x8d, 0x4c, 0xac, 0x0c, 0x0c, 0x0c,
+            0x40, 0xdc, 0x00, 0x00, 0x1a, 0x56, 0x02, 0x86, 0x24, 0xe0, 0x1f, 0x1b, 0x00, 0x00,
+            0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        let image = decode(&png).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.pixels,
+            [
+                [10, 20, 30, 255],
+                [20, 40, 60, 128],
+                [15, 20, 30, 255],
+                [25, 40, 60, 0]
+            ]
+        );
+        assert!(decode(&png[..40]).is_err());
+    }
+
+    #[test]
+    fn palette_stored() {
+        // One bit per pixel, the first colour transparent
+        let png = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x01, 0x03, 0x00, 0x00,
+            0x00, 0x21, 0x2e, 0x86, 0xf7, 0x00, 0x00, 0x00, 0x06, 0x50, 0x4c, 0x54, 0x45, 0xff,
+            0x00, 0x00, 0x00, 0x00, 0xff, 0x6c, 0xa1, 0xfd, 0x8e, 0x00, 0x00, 0x00, 0x01, 0x74,
+            0x52, 0x4e, 0x53, 0x00, 0x40, 0xe6, 0xd8, 0x66, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x44,
+            0x41, 0x54, 0x78, 0x01, 0x01, 0x02, 0x00, 0xfd, 0xff, 0x00, 0x40, 0x00, 0x42, 0x00,
+            0x41, 0x20, 0xb0, 0x99, 0xcb, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+            0x42, 0x60, 0x82,
+        ];
+        let image = decode(&png).unwrap();
+        assert_eq!(
+            image.pixels,
+            [[255, 0, 0, 0], [0, 0, 255, 255], [255, 0, 0, 0]]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+/// Items in the power menu, opened from the game list
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerItem {
+    Back,
+    /// Finds the games again
+    Rescan,
+    Shutdown,
+    Reboot,
+}
+
+pub const POWER_ITEMS: [PowerItem; 4] = [
+    PowerItem::Back,
+    PowerItem::Rescan,
+    PowerItem::Shutdown,
+    PowerItem::Reboot,
+];
+
+impl PowerItem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PowerItem::Back => "Back to games",
+            PowerItem::Rescan => "Rescan games",
+            PowerItem::Shutdown => "Shutdown",
+            PowerItem::Reboot => "Reboot",
+        }
+    }
+}
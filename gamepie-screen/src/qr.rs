@@ -0,0 +1,377 @@
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+};
+
+// QR codes in byte mode with low error correction, up to version 6 (41x41)
+// which is plenty for a short message and keeps to one alignment pattern.
+// (blocks, data codewords per block, error correction codewords per block)
+const VERSIONS: [(usize, usize, usize); 6] = [
+    (1, 19, 7),
+    (1, 34, 10),
+    (1, 55, 15),
+    (1, 80, 20),
+    (1, 108, 26),
+    (2, 68, 18),
+];
+// Modules of white space needed around the code
+pub(crate) const QUIET_ZONE: u32 = 4;
+
+// Multiply in GF(2^8) with the QR polynomial
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u16::from((y >> i) & 1) * u16::from(x);
+    }
+    z as u8
+}
+
+// Reed-Solomon error correction codewords for a block
+fn ecc(data: &[u8], degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0u8; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_mul(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+    let mut result = vec![0u8; degree];
+    for b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, d) in result.iter_mut().zip(&divisor) {
+            *r ^= gf_mul(*d, factor);
+        }
+    }
+    result
+}
+
+// Error correction level and mask, with BCH error correction
+fn format_bits(mask: u8) -> u16 {
+    // Low error correction is 01
+    let data = (1 << 3) | u16::from(mask);
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    ((data << 10) | rem) ^ 0x5412
+}
+
+fn masked(mask: u8, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y).is_multiple_of(2),
+        1 => y.is_multiple_of(2),
+        2 => x.is_multiple_of(3),
+        3 => (x + y).is_multiple_of(3),
+        4 => (x / 3 + y / 2).is_multiple_of(2),
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+        _ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+    }
+}
+
+/// QR code for a short message, e.g. to photograph an error
+#[derive(Clone)]
+pub(crate) struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    // Finder, timing and format modules, which aren't masked
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode a message, `None` if it is too long
+    pub fn encode(text: &[u8]) -> Option<Self> {
+        let version = VERSIONS
+            .iter()
+            .position(|(blocks, data, _)| text.len() + 2 <= blocks * data)?;
+        let (blocks, data_len, ecc_len) = VERSIONS[version];
+        let mut qr = QrCode::new(version + 1);
+        let codewords = Self::codewords(text, blocks, data_len, ecc_len);
+        qr.place(&codewords);
+
+        // Use the mask that is least likely to confuse a reader
+        let mut best: Option<(u32, QrCode)> = None;
+        for mask in 0..8 {
+            let mut candidate = qr.clone();
+            candidate.apply_mask(mask);
+            candidate.draw_format(mask);
+            let penalty = candidate.penalty();
+            if best.as_ref().is_none_or(|(p, _)| penalty < *p) {
+                best = Some((penalty, candidate));
+            }
+        }
+        best.map(|(_, qr)| qr)
+    }
+
+    fn new(version: usize) -> Self {
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        for i in 0..size {
+            qr.set_function(6, i, i % 2 == 0);
+            qr.set_function(i, 6, i % 2 == 0);
+        }
+        qr.draw_finder(3, 3);
+        qr.draw_finder(size - 4, 3);
+        qr.draw_finder(3, size - 4);
+        if version > 1 {
+            let pos = size - 7;
+            for dy in 0usize..5 {
+                for dx in 0usize..5 {
+                    let ring = std::cmp::max(dx.abs_diff(2), dy.abs_diff(2));
+                    qr.set_function(pos - 2 + dx, pos - 2 + dy, ring != 1);
+                }
+            }
+        }
+        // Reserve the format areas until the mask is known
+        qr.draw_format(0);
+        qr
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    // Finder pattern centred on x,y, with its separator
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (fx, fy) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&fx) && (0..self.size as i32).contains(&fy) {
+                    let ring = std::cmp::max(dx.abs(), dy.abs());
+                    self.set_function(fx as usize, fy as usize, ring != 2 && ring != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_format(&mut self, mask: u8) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    // Data with its error correction, interleaved across the blocks
+    fn codewords(text: &[u8], blocks: usize, data_len: usize, ecc_len: usize) -> Vec<u8> {
+        let capacity = blocks * data_len;
+        // Byte mode, then an 8 bit length
+        let mut bits: Vec<bool> = Vec::with_capacity(capacity * 8);
+        let mut push = |value: u32, len: usize| {
+            for i in (0..len).rev() {
+                bits.push((value >> i) & 1 == 1);
+            }
+        };
+        push(0b0100, 4);
+        push(text.len() as u32, 8);
+        for b in text {
+            push(u32::from(*b), 8);
+        }
+        let terminator = std::cmp::min(4, capacity * 8 - bits.len());
+        bits.extend(std::iter::repeat_n(false, terminator));
+        while !bits.len().is_multiple_of(8) {
+            bits.push(false);
+        }
+        let mut data: Vec<u8> = bits
+            .chunks(8)
+            .map(|c| c.iter().fold(0, |b, bit| (b << 1) | u8::from(*bit)))
+            .collect();
+        for pad in [0xec, 0x11].iter().cycle() {
+            if data.len() >= capacity {
+                break;
+            }
+            data.push(*pad);
+        }
+
+        let blocks: Vec<&[u8]> = data.chunks(data_len).collect();
+        let eccs: Vec<Vec<u8>> = blocks.iter().map(|b| ecc(b, ecc_len)).collect();
+        let mut result = Vec::with_capacity(blocks.len() * (data_len + ecc_len));
+        for i in 0..data_len {
+            result.extend(blocks.iter().map(|b| b[i]));
+        }
+        for i in 0..ecc_len {
+            result.extend(eccs.iter().map(|e| e[i]));
+        }
+        result
+    }
+
+    // Fill the data in pairs of columns, zigzagging up and down from the
+    // bottom right.
+    fn place(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            // Skip the vertical timing pattern
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if !self.function[y * self.size + x] && masked(mask, x, y) {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // Score features that make codes harder to read, as in the standard
+    fn penalty(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size).map(|j| self.get(j, i)).collect::<Vec<_>>(),
+                (0..size).map(|j| self.get(i, j)).collect::<Vec<_>>(),
+            ]
+        });
+        for line in lines {
+            // Runs of the same colour
+            let mut run = 1;
+            for j in 1..=size {
+                if j < size && line[j] == line[j - 1] {
+                    run += 1;
+                } else {
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+            }
+            // Patterns that look like finders
+            let finder = [true, false, true, true, true, false, true];
+            for w in line.windows(11) {
+                if w[4..11] == finder && w[..4].iter().all(|d| !d)
+                    || w[..7] == finder && w[7..].iter().all(|d| !d)
+                {
+                    penalty += 40;
+                }
+            }
+        }
+        // Blocks of the same colour
+        for y in 1..size {
+            for x in 1..size {
+                let c = self.get(x, y);
+                if c == self.get(x - 1, y) && c == self.get(x, y - 1) && c == self.get(x - 1, y - 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        // Balance of dark and light
+        let dark = self.modules.iter().filter(|d| **d).count();
+        let percent = dark * 100 / (size * size);
+        penalty + (percent.abs_diff(50) / 5 * 10) as u32
+    }
+
+    /// Width and height in modules, not including the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Draw with the quiet zone, each module as a square of `scale` pixels
+    pub fn draw<D>(&self, target: &mut D, top_left: Point, scale: u32) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let full = (self.size as u32 + QUIET_ZONE * 2) * scale;
+        Rectangle::new(top_left, Size::new(full, full))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::WHITE))
+            .draw(target)?;
+        let origin =
+            top_left + Point::new((QUIET_ZONE * scale) as i32, (QUIET_ZONE * scale) as i32);
+        let style = PrimitiveStyle::with_fill(Rgb565::BLACK);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.get(x, y) {
+                    let p = Point::new((x as u32 * scale) as i32, (y as u32 * scale) as i32);
+                    Rectangle::new(origin + p, Size::new(scale, scale))
+                        .into_styled(style)
+                        .draw(target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ecc, format_bits, QrCode, VERSIONS};
+
+    #[test]
+    fn error_correction() {
+        // HELLO WORLD as 1-M, from the Thonky tutorial
+        let data = [
+            0x20, 0x5b, 0x0b, 0x78, 0xd1, 0x72, 0xdc, 0x4d, 0x43, 0x40, 0xec, 0x11, 0xec, 0x11,
+            0xec, 0x11,
+        ];
+        assert_eq!(
+            ecc(&data, 10),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(format_bits(0), 0b111011111000100);
+        assert_eq!(format_bits(7), 0b110100101110110);
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(QrCode::encode(b"gamepie").map(|q| q.size()), Some(21));
+        // Less the mode and length
+        let capacity = VERSIONS[5].0 * VERSIONS[5].1 - 2;
+        let long = vec![b'a'; capacity];
+        assert_eq!(QrCode::encode(&long).map(|q| q.size()), Some(41));
+        let too_long = vec![b'a'; capacity + 1];
+        assert!(QrCode::encode(&too_long).is_none());
+    }
+}
@@ -1,14 +1,86 @@
 use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
+use std::time::Instant;
 
+use gamepie_core::aspect::ScaleMode;
 use gamepie_core::commands::{ScreenMessage, ScreenToast};
-use gamepie_core::log::gamepie_log_shim;
-use gamepie_screenbind::*;
+use gamepie_core::config::DisplayConfig;
+use gamepie_core::error::GamepieError;
+use gamepie_core::problem::Problem;
 
+use crate::blit;
+use crate::driver::ScreenDriver;
+use crate::fbcp::Fbcp;
+use crate::fbdev::FbDev;
 use crate::framebuffer::Framebuffer;
-use crate::overlay::ToastDrawer;
+use crate::overlay::{draw_hud, ToastDrawer};
+use crate::video::Video;
+
+// Size to show a frame at so it has the given aspect ratio, no taller than
+// the frame and fitting on the screen
+fn scaled_size(frame: (usize, usize), aspect: f32, screen: (usize, usize)) -> (usize, usize) {
+    let mut h = std::cmp::min(frame.1, screen.1);
+    let mut w = (h as f32 * aspect).round() as usize;
+    if w > screen.0 {
+        w = screen.0;
+        h = (w as f32 / aspect).round() as usize;
+    }
+    (w.max(1), h.max(1))
+}
+
+// Largest size with the given aspect ratio that fits on the screen
+fn fit_size(aspect: f32, screen: (usize, usize)) -> (usize, usize) {
+    scaled_size(screen, aspect, screen)
+}
+
+// Size to show a frame at for a scaling mode, with an aspect ratio if
+// given, otherwise with square pixels
+fn target_size(
+    mode: ScaleMode,
+    frame: (usize, usize),
+    aspect: Option<f32>,
+    screen: (usize, usize),
+) -> (usize, usize) {
+    let square = frame.0 as f32 / frame.1 as f32;
+    match mode {
+        ScaleMode::Crop => match aspect {
+            Some(a) => scaled_size(frame, a, screen),
+            None => frame,
+        },
+        ScaleMode::Integer => {
+            let width = |h: usize| match aspect {
+                Some(a) => (h as f32 * a).round() as usize,
+                None => h * frame.0 / frame.1,
+            };
+            let scale = (1..=screen.1 / frame.1)
+                .rev()
+                .find(|n| width(frame.1 * n) <= screen.0);
+            match scale {
+                Some(n) => (width(frame.1 * n).max(1), frame.1 * n),
+                // Too big for any whole multiple, so shrink it
+                None => fit_size(aspect.unwrap_or(square), screen),
+            }
+        }
+        ScaleMode::Fit => fit_size(aspect.unwrap_or(square), screen),
+        ScaleMode::Stretch => screen,
+    }
+}
+
+/// Frame copied to a recording or stream, numbered so gaps from dropped
+/// frames can be filled
+pub type RecordFrame = (u64, Vec<u16>);
+
+/// What a copy of the frames drawn is sent to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameTap {
+    Record,
+    Stream,
+}
 
 pub struct Screen {
     width: u16,
@@ -17,6 +89,36 @@ pub struct Screen {
     toasts: Vec<ScreenToast>,
     rx: mpsc::Receiver<ScreenToast>,
     tx: mpsc::Sender<ScreenToast>,
+    // Path to save the next frame to
+    screenshot: Option<PathBuf>,
+    // Last frame from a core, without toasts, for menus shown over the game
+    last_frame: Option<Vec<u16>>,
+    // Text shown over the frames from cores
+    hud: Option<Vec<String>>,
+    // Where frames are copied, and the last frame's number
+    taps: Vec<(FrameTap, mpsc::SyncSender<RecordFrame>)>,
+    tap_seq: u64,
+    video: Video,
+}
+
+// Write an RGB565 frame as a binary PPM
+fn write_ppm(path: &Path, width: u16, height: u16, data: &[u16]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    for px in data {
+        let r = ((px >> 11) & 0x1f) as u8;
+        let g = ((px >> 5) & 0x3f) as u8;
+        let b = (px & 0x1f) as u8;
+        out.write_all(&[
+            (r << 3) | (r >> 2),
+            (g << 2) | (g >> 4),
+            (b << 3) | (b >> 2),
+        ])?;
+    }
+    out.flush()
 }
 
 // Init
@@ -50,6 +152,23 @@ impl Screen {
         }
     }
 
+    // Save the frame, before toasts are drawn over it, if requested
+    fn save_screenshot(&mut self, data: &[u16]) {
+        if let Some(path) = self.screenshot.take() {
+            let toast = match write_ppm(&path, self.width, self.height, data) {
+                Ok(_) => {
+                    info!("Saved screenshot '{}'", path.display());
+                    ScreenToast::info(ScreenMessage::Message(String::from("Screenshot saved")))
+                }
+                Err(e) => {
+                    error!("Failed to save screenshot '{}': {}", path.display(), e);
+                    ScreenToast::error(ScreenMessage::Message(String::from("Screenshot failed")))
+                }
+            };
+            self.toasts.push(toast);
+        }
+    }
+
     fn draw_toast(&mut self, vec: Vec<u16>) -> Vec<u16> {
         if let Some(toast) = &self.toast {
             let mut fb = Framebuffer::new(self.width, self.height, vec);
@@ -67,69 +186,180 @@ impl Screen {
         let w: usize = self.width.into();
         let h: usize = self.height.into();
         assert_eq!(data.len(), w * h, "data size is incorrect");
+        self.save_screenshot(data);
 
-        let data = self.draw_toast(data.to_vec());
-        unsafe {
-            lcd_lib_tick(data.as_ptr(), 1);
-        }
+        let mut fb = self.video.buffer();
+        fb.copy_from_slice(data);
+        let fb = self.draw_toast(fb);
+        self.video.submit(fb, true);
+    }
+
+    /// Clear the screen to black, for panels that still show an image with
+    /// the backlight off
+    pub fn blank(&mut self) {
+        let mut fb = self.video.buffer();
+        fb.fill(0);
+        self.video.submit(fb, true);
     }
 
-    pub fn draw(&mut self, width: u16, height: u16, pitch: u16, data: &[u8]) {
+    /// Draw a frame from a core, scaled for the mode at an aspect ratio if
+    /// given, otherwise with square pixels
+    pub fn draw(
+        &mut self,
+        width: u16,
+        height: u16,
+        pitch: u16,
+        data: &[u8],
+        mode: ScaleMode,
+        aspect: Option<f32>,
+    ) {
         self.preprocess_toast();
-        let mut fb: Vec<u16> = Vec::new();
+        let mut fb = self.video.buffer();
         let w: usize = self.width.into();
         let h: usize = self.height.into();
-        let xsz: usize = width.into();
-        let ysz: usize = height.into();
-        let psz: usize = pitch.into();
+        let src = blit::Source {
+            data,
+            width: width.into(),
+            height: height.into(),
+            pitch: pitch.into(),
+        };
 
         // TODO border
         // Drawing to library is always done at full screen size,
         // so fill in the background.
         let color = Rgb565::new(19, 6, 21);
-        fb.resize(w * h, color.into_storage());
-
-        // Offset for output
-        let xoff: usize = if xsz > w { 0 } else { (w - xsz) / 2 };
-        let yoff: usize = if ysz > h { 0 } else { (h - ysz) / 2 };
-        // Offset for input
-        let xskip = if xsz > w { (xsz - w) / 2 } else { 0 };
-        let yskip = if ysz > h { (ysz - h) / 2 } else { 0 };
-        for y in 0..ysz {
-            for x in 0..xsz {
-                let xmod = x + xoff;
-                let ymod = y + yoff;
-                // TODO efficient copying - at least can maybe keep background
-                // around (avoiding resize above)
-                if xmod < w && ymod < h {
-                    let i = ((x + xskip) * 2) + ((y + yskip) * psz);
-                    let d = (data[i] as u16) | ((data[i + 1] as u16) << 8);
-                    fb[xmod + (ymod * w)] = d;
-                }
-            }
+        fb.fill(color.into_storage());
+
+        let frame = (src.width, src.height);
+        let size = target_size(mode, frame, aspect, (w, h));
+        if size == frame {
+            // Centred, and cropped if too big
+            blit::centred(&mut fb, w, &src);
+        } else {
+            blit::scaled(&mut fb, w, &src, size);
         }
+        self.present(fb);
+    }
+
+    // Send a frame from a core to the screen, with any toast over it. A copy
+    // is kept without the toast.
+    fn present(&mut self, fb: Vec<u16>) {
+        self.save_screenshot(&fb);
+        self.copy_frame(&fb);
+        let last = self.last_frame.get_or_insert_with(Vec::new);
+        last.clear();
+        last.extend_from_slice(&fb);
         let fb = self.draw_toast(fb);
-        unsafe {
-            lcd_lib_tick(fb.as_ptr(), 0);
+        let fb = self.draw_hud(fb);
+        self.video.submit(fb, false);
+    }
+
+    fn draw_hud(&mut self, vec: Vec<u16>) -> Vec<u16> {
+        match &self.hud {
+            Some(lines) => {
+                let mut fb = Framebuffer::new(self.width, self.height, vec);
+                draw_hud(&mut fb, lines);
+                fb.reclaim()
+            }
+            None => vec,
         }
     }
 
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    /// Show lines of text over the frames from cores, e.g. frame stats, or
+    /// stop showing them if none
+    pub fn set_hud(&mut self, lines: Option<Vec<String>>) {
+        self.hud = lines;
+    }
+
+    // Copy a frame to each tap, skipping those still busy with earlier ones
+    fn copy_frame(&mut self, fb: &[u16]) {
+        if self.taps.is_empty() {
+            return;
+        }
+        self.tap_seq += 1;
+        let seq = self.tap_seq;
+        self.taps
+            .retain(|(tap, tx)| match tx.try_send((seq, fb.to_vec())) {
+                Ok(()) | Err(mpsc::TrySendError::Full(_)) => true,
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    warn!("{:?} frame copies stopped", tap);
+                    false
+                }
+            });
+    }
+
+    /// Copy frames to `tx` as they are drawn, replacing any sender for the
+    /// same tap, or stop copying them if none
+    pub fn set_frame_tap(&mut self, tap: FrameTap, tx: Option<mpsc::SyncSender<RecordFrame>>) {
+        self.taps.retain(|(t, _)| *t != tap);
+        if let Some(tx) = tx {
+            self.taps.push((tap, tx));
+        }
+    }
+
+    /// Frames drawn but replaced by a newer one before the screen was ready
+    /// for them
+    pub fn dropped_frames(&self) -> u64 {
+        self.video.dropped()
+    }
+
+    /// Number of the last frame drawn, frames are numbered from 1
+    pub fn frames_submitted(&self) -> u64 {
+        self.video.submitted()
+    }
+
+    /// Number of the newest frame sent over SPI, and when sending it
+    /// finished
+    pub fn last_shown(&self) -> Option<(u64, Instant)> {
+        self.video.shown()
+    }
+
+    /// Last frame drawn by a core, without any toasts
+    pub fn last_frame(&self) -> Option<&[u16]> {
+        self.last_frame.as_deref()
+    }
+
+    /// Start the configured screen driver. Errors while drawing are sent to
+    /// `error_tx`.
+    pub fn new(
+        display: &DisplayConfig,
+        error_tx: mpsc::Sender<Problem>,
+    ) -> Result<Self, Box<dyn Error>> {
         debug!("Initialising screen");
-        let (tx, rx) = mpsc::channel();
-        let toasts = Vec::new();
-        unsafe {
-            let width = lcd_lib_width();
-            let height = lcd_lib_height();
-            lcd_lib_init(Some(gamepie_log_shim));
-            Ok(Screen {
+        let driver: Box<dyn ScreenDriver> = match display {
+            DisplayConfig::Spi => Box::new(Fbcp::init()?),
+            DisplayConfig::Framebuffer {
+                device,
                 width,
                 height,
-                tx,
-                rx,
-                toasts,
-                toast: None,
-            })
+            } => match FbDev::open(device, *width, *height) {
+                Ok(fb) => Box::new(fb),
+                Err(e) => {
+                    error!("Can't open framebuffer '{}': {}", device.display(), e);
+                    return Err(Box::new(GamepieError::NoVideo));
+                }
+            },
+        };
+        Ok(Self::with_driver(driver, error_tx))
+    }
+
+    /// Draw to a screen driver that has been started
+    pub fn with_driver(driver: Box<dyn ScreenDriver>, error_tx: mpsc::Sender<Problem>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let (width, height) = (driver.width(), driver.height());
+        Screen {
+            width,
+            height,
+            tx,
+            rx,
+            toasts: Vec::new(),
+            toast: None,
+            screenshot: None,
+            last_frame: None,
+            hud: None,
+            taps: Vec::new(),
+            tap_seq: 0,
+            video: Video::new(driver, error_tx),
         }
     }
 
@@ -141,6 +371,11 @@ impl Screen {
         self.height
     }
 
+    /// Save the next frame drawn to `path` as a PPM image
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.screenshot = Some(path);
+    }
+
     pub fn overlay_channel(&self) -> mpsc::Sender<ScreenToast> {
         self.tx.clone()
     }
@@ -149,8 +384,53 @@ impl Screen {
 impl Drop for Screen {
     fn drop(&mut self) {
         debug!("Closing screen");
-        unsafe {
-            lcd_lib_deinit();
-        }
+        // The driver is shut down once the video thread is done with it
+        self.video.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scaled_size, target_size};
+    use gamepie_core::aspect::ScaleMode;
+
+    #[test]
+    fn aspect_scaling() {
+        // NES at 4:3 is wider than the screen, so shrinks to fit
+        assert_eq!(scaled_size((256, 240), 4.0 / 3.0, (240, 240)), (240, 180));
+        // Game Boy at its own ratio is unchanged
+        assert_eq!(scaled_size((160, 144), 10.0 / 9.0, (240, 240)), (160, 144));
+        assert_eq!(scaled_size((160, 144), 1.0, (240, 240)), (144, 144));
+    }
+
+    #[test]
+    fn scale_modes() {
+        let screen = (240, 240);
+        let gb = (160, 144);
+        // Game Boy doesn't fit twice, so is shown at its own size
+        assert_eq!(target_size(ScaleMode::Integer, gb, None, screen), gb);
+        assert_eq!(target_size(ScaleMode::Fit, gb, None, screen), (240, 216));
+        assert_eq!(target_size(ScaleMode::Stretch, gb, None, screen), screen);
+        assert_eq!(target_size(ScaleMode::Crop, gb, None, screen), gb);
+        // Smaller frames are scaled up by whole multiples
+        let small = (64, 48);
+        assert_eq!(
+            target_size(ScaleMode::Integer, small, None, screen),
+            (192, 144)
+        );
+        // Bigger frames shrink to fit, at the aspect ratio if given
+        let psx = (320, 240);
+        assert_eq!(
+            target_size(ScaleMode::Integer, psx, None, screen),
+            (240, 180)
+        );
+        assert_eq!(
+            target_size(ScaleMode::Fit, psx, Some(1.0), screen),
+            (240, 240)
+        );
+        assert_eq!(
+            target_size(ScaleMode::Crop, (256, 240), Some(4.0 / 3.0), screen),
+            (240, 180)
+        );
     }
 }
@@ -0,0 +1,85 @@
+use std::path::Path;
+
+/// Group for games whose system isn't known
+pub(crate) const OTHER_SYSTEM: &str = "Other";
+
+/// System a game is for, from its extension. Extensions shared by several
+/// systems, e.g. `bin` or `zip`, aren't known.
+pub(crate) fn from_extension(file: &str) -> Option<&'static str> {
+    let ext = Path::new(file).extension()?.to_str()?.to_ascii_lowercase();
+    let system = match ext.as_str() {
+        "nes" | "unf" | "unif" => "NES",
+        "fds" => "Famicom Disk System",
+        "sfc" | "smc" => "SNES",
+        "gb" => "Game Boy",
+        "gbc" => "Game Boy Color",
+        "gba" => "Game Boy Advance",
+        "n64" | "z64" | "v64" => "Nintendo 64",
+        "nds" => "Nintendo DS",
+        "vb" => "Virtual Boy",
+        "min" => "Pokemon Mini",
+        "md" | "gen" | "smd" => "Mega Drive",
+        "32x" => "32X",
+        "sms" => "Master System",
+        "gg" => "Game Gear",
+        "sg" => "SG-1000",
+        "pce" => "PC Engine",
+        "ngp" | "ngc" => "Neo Geo Pocket",
+        "ws" | "wsc" => "WonderSwan",
+        "lnx" => "Lynx",
+        "a26" => "Atari 2600",
+        "a78" => "Atari 7800",
+        "col" => "ColecoVision",
+        "vec" => "Vectrex",
+        _ => return None,
+    };
+    Some(system)
+}
+
+/// System for games in a folder, from common folder names (as used by
+/// EmulationStation, or the extension of the system's games), otherwise the
+/// folder's own name
+pub(crate) fn from_folder(folder: &str) -> String {
+    let lower = folder.to_ascii_lowercase();
+    let system = match lower.as_str() {
+        "famicom" => Some("NES"),
+        "snes" | "sfc" | "superfamicom" => Some("SNES"),
+        "gameboy" => Some("Game Boy"),
+        "megadrive" | "genesis" => Some("Mega Drive"),
+        "sega32x" => Some("32X"),
+        "mastersystem" => Some("Master System"),
+        "gamegear" => Some("Game Gear"),
+        "sg-1000" | "sg1000" => Some("SG-1000"),
+        "pcengine" | "tg16" => Some("PC Engine"),
+        "virtualboy" => Some("Virtual Boy"),
+        "pokemini" => Some("Pokemon Mini"),
+        "wonderswan" | "wonderswancolor" => Some("WonderSwan"),
+        "atarilynx" => Some("Lynx"),
+        "atari2600" => Some("Atari 2600"),
+        "atari7800" => Some("Atari 7800"),
+        "coleco" => Some("ColecoVision"),
+        "vectrex" => Some("Vectrex"),
+        _ => from_extension(&format!("folder.{}", lower)),
+    };
+    system.map_or_else(|| String::from(folder), String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_extension, from_folder};
+
+    #[test]
+    fn system_from_extension() {
+        assert_eq!(from_extension("Tetris (World).GB"), Some("Game Boy"));
+        assert_eq!(from_extension("Sonic.md"), Some("Mega Drive"));
+        assert_eq!(from_extension("game.zip"), None);
+        assert_eq!(from_extension("README"), None);
+    }
+
+    #[test]
+    fn system_from_folder() {
+        assert_eq!(from_folder("gb"), "Game Boy");
+        assert_eq!(from_folder("megadrive"), "Mega Drive");
+        assert_eq!(from_folder("Arcade"), "Arcade");
+    }
+}
@@ -0,0 +1,134 @@
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle},
+};
+
+use gamepie_core::{TEXT_COLOUR, TEXT_SEL_COLOUR};
+
+// Buttons by RETRO_DEVICE_ID_JOYPAD_* id
+const B: usize = 0;
+const Y: usize = 1;
+const SELECT: usize = 2;
+const START: usize = 3;
+const UP: usize = 4;
+const DOWN: usize = 5;
+const LEFT: usize = 6;
+const RIGHT: usize = 7;
+const A: usize = 8;
+const X: usize = 9;
+const L: usize = 10;
+const R: usize = 11;
+const L2: usize = 12;
+const R2: usize = 13;
+const L3: usize = 14;
+const R3: usize = 15;
+
+/// Size of the RetroPad diagram
+pub(crate) const PAD_SIZE: Size = Size::new(200, 104);
+
+const BUTTON_SIZE: u32 = 14;
+const STICK_SIZE: u32 = 28;
+// Furthest the stick position is drawn from the centre
+const STICK_TRAVEL: i32 = 10;
+
+/// RetroPad state for the input tester
+#[derive(Clone, Copy, Default)]
+pub struct PadView {
+    /// Buttons held, by RETRO_DEVICE_ID_JOYPAD_* id
+    pub buttons: [bool; 16],
+    /// Left X, left Y, right X and right Y
+    pub axes: [i16; 4],
+}
+
+fn style(pressed: bool) -> PrimitiveStyle<Rgb565> {
+    if pressed {
+        PrimitiveStyle::with_fill(TEXT_SEL_COLOUR)
+    } else {
+        PrimitiveStyle::with_stroke(TEXT_COLOUR, 1)
+    }
+}
+
+// Position of a stick, scaled so a full deflection is at the edge
+fn stick_offset(x: i16, y: i16) -> Point {
+    let scale = |v: i16| i32::from(v) * STICK_TRAVEL / i32::from(i16::MAX);
+    Point::new(scale(x), scale(y))
+}
+
+/// Draw a RetroPad with held buttons filled in and the sticks' positions
+pub(crate) fn draw_pad<D>(target: &mut D, top_left: Point, pad: &PadView) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let rect = |x, y, w, h, id: usize| {
+        Rectangle::new(top_left + Point::new(x, y), Size::new(w, h))
+            .into_styled(style(pad.buttons[id]))
+    };
+    // Shoulders
+    rect(10, 0, 40, 8, L2).draw(target)?;
+    rect(150, 0, 40, 8, R2).draw(target)?;
+    rect(10, 11, 50, 8, L).draw(target)?;
+    rect(140, 11, 50, 8, R).draw(target)?;
+
+    // D-pad
+    let dpad = Point::new(40, 45);
+    let half = (BUTTON_SIZE / 2) as i32;
+    let full = BUTTON_SIZE as i32;
+    for (id, offset) in [
+        (UP, Point::new(-half, -half - full)),
+        (DOWN, Point::new(-half, half)),
+        (LEFT, Point::new(-half - full, -half)),
+        (RIGHT, Point::new(half, -half)),
+    ] {
+        let p = top_left + dpad + offset;
+        Rectangle::new(p, Size::new(BUTTON_SIZE, BUTTON_SIZE))
+            .into_styled(style(pad.buttons[id]))
+            .draw(target)?;
+    }
+
+    // Face buttons, in the SNES layout
+    let face = Point::new(160, 45);
+    for (id, offset) in [
+        (X, Point::new(0, -full)),
+        (B, Point::new(0, full)),
+        (Y, Point::new(-full, 0)),
+        (A, Point::new(full, 0)),
+    ] {
+        let p = top_left + face + offset - Point::new(half, half);
+        Circle::new(p, BUTTON_SIZE)
+            .into_styled(style(pad.buttons[id]))
+            .draw(target)?;
+    }
+
+    rect(76, 41, 20, 8, SELECT).draw(target)?;
+    rect(104, 41, 20, 8, START).draw(target)?;
+
+    // Sticks, filled when pressed in
+    let stick_half = (STICK_SIZE / 2) as i32;
+    for (centre, (x, y), id) in [
+        (Point::new(70, 88), (pad.axes[0], pad.axes[1]), L3),
+        (Point::new(130, 88), (pad.axes[2], pad.axes[3]), R3),
+    ] {
+        let centre = top_left + centre;
+        Circle::new(centre - Point::new(stick_half, stick_half), STICK_SIZE)
+            .into_styled(style(pad.buttons[id]))
+            .draw(target)?;
+        let dot = centre + stick_offset(x, y) - Point::new(2, 2);
+        Circle::new(dot, 5)
+            .into_styled(PrimitiveStyle::with_fill(TEXT_COLOUR))
+            .draw(target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stick_offset;
+    use embedded_graphics::prelude::Point;
+
+    #[test]
+    fn stick_travel() {
+        assert_eq!(stick_offset(0, 0), Point::new(0, 0));
+        assert_eq!(stick_offset(i16::MAX, i16::MIN), Point::new(10, -10));
+    }
+}
@@ -0,0 +1,142 @@
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use log::{debug, warn};
+use std::path::Path;
+use tinybmp::Bmp;
+
+use gamepie_core::BACKGROUND_COLOUR;
+
+use crate::png;
+
+/// Largest width or height of a thumbnail in the menu
+pub(crate) const THUMB_MAX: u32 = 80;
+
+/// Game image scaled down for the menu, kept as raw pixels so drawing it
+/// while scrolling doesn't need the image to be decoded again.
+pub(crate) struct Thumbnail {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb565>,
+}
+
+// Size of an image scaled to fit in a square of max, keeping its aspect
+fn fit(width: u32, height: u32, max: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (0, 0);
+    }
+    if width >= height {
+        (max, std::cmp::max(1, height * max / width))
+    } else {
+        (std::cmp::max(1, width * max / height), max)
+    }
+}
+
+// Blend a PNG pixel over the menu background, as thumbnails are drawn
+// without transparency
+fn blend([r, g, b, a]: [u8; 4]) -> Rgb565 {
+    let bg = BACKGROUND_COLOUR;
+    let mix = |c: u8, bg: u8, max: u8| {
+        let c = u32::from(c) * u32::from(max) / 255;
+        ((c * u32::from(a) + u32::from(bg) * (255 - u32::from(a))) / 255) as u8
+    };
+    Rgb565::new(
+        mix(r, bg.r(), Rgb565::MAX_R),
+        mix(g, bg.g(), Rgb565::MAX_G),
+        mix(b, bg.b(), Rgb565::MAX_B),
+    )
+}
+
+fn load_bmp(path: &Path, bytes: &[u8]) -> Option<(Size, Vec<Rgb565>)> {
+    let bmp = match Bmp::<Rgb565>::from_slice(bytes) {
+        Ok(b) => b,
+        Err(e) => {
+            warn!("Invalid image '{}': {:?}", path.display(), e);
+            return None;
+        }
+    };
+    let size = bmp.size();
+    let mut source = vec![Rgb565::BLACK; (size.width * size.height) as usize];
+    for Pixel(p, c) in bmp.pixels() {
+        let (x, y) = (p.x as u32, p.y as u32);
+        if x < size.width && y < size.height {
+            source[(x + y * size.width) as usize] = c;
+        }
+    }
+    Some((size, source))
+}
+
+fn load_png(path: &Path, bytes: &[u8]) -> Option<(Size, Vec<Rgb565>)> {
+    match png::decode(bytes) {
+        Ok(image) => {
+            let source = image.pixels.into_iter().map(blend).collect();
+            Some((Size::new(image.width, image.height), source))
+        }
+        Err(e) => {
+            warn!("Invalid image '{}': {}", path.display(), e);
+            None
+        }
+    }
+}
+
+impl Thumbnail {
+    /// Load a BMP or PNG image as a thumbnail
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let bytes = std::fs::read(path).ok()?;
+        let (size, source) = match ext.as_deref() {
+            Some("bmp") => load_bmp(path, &bytes)?,
+            Some("png") => load_png(path, &bytes)?,
+            _ => {
+                debug!("Unsupported image format '{}'", path.display());
+                return None;
+            }
+        };
+
+        // Nearest neighbour is enough for box art at this size
+        let (width, height) = fit(size.width, size.height, THUMB_MAX);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let sx = x * size.width / width;
+                let sy = y * size.height / height;
+                source[(sx + sy * size.width) as usize]
+            })
+            .collect();
+        Some(Thumbnail {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub(crate) fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    pub(crate) fn draw<T>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Color = Rgb565>,
+    {
+        let width = self.width as i32;
+        let pixels = self.pixels.iter().enumerate().map(|(i, c)| {
+            let i = i as i32;
+            Pixel(top_left + Point::new(i % width, i / width), *c)
+        });
+        target.draw_iter(pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fit;
+
+    #[test]
+    fn fit_keeps_aspect() {
+        assert_eq!(fit(160, 144, 80), (80, 72));
+        assert_eq!(fit(224, 256, 80), (70, 80));
+        assert_eq!(fit(20, 20, 80), (80, 80));
+        assert_eq!(fit(0, 10, 80), (0, 0));
+    }
+}
@@ -0,0 +1,258 @@
+use log::{debug, error, info};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use gamepie_core::commands::{ScreenMessage, ScreenToast};
+use gamepie_core::problem::Problem;
+
+use crate::driver::ScreenDriver;
+
+// Wait after an error from the screen driver before drawing again, doubled
+// on each error up to the max
+const SPI_RETRY_MIN: Duration = Duration::from_millis(50);
+const SPI_RETRY_MAX: Duration = Duration::from_secs(2);
+
+// Backs off drawing while the screen driver is returning errors
+#[derive(Default)]
+pub(crate) struct Backoff {
+    delay: Option<Duration>,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    // Time left before drawing should be retried
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.retry_at
+            .map_or(Duration::ZERO, |t| t.saturating_duration_since(now))
+    }
+
+    pub fn failing(&self) -> bool {
+        self.delay.is_some()
+    }
+
+    // Returns the wait before retrying, and whether this is the first error
+    // since drawing last worked
+    pub fn failed(&mut self, now: Instant) -> (Duration, bool) {
+        let first = self.delay.is_none();
+        let delay = self
+            .delay
+            .map_or(SPI_RETRY_MIN, |d| std::cmp::min(d * 2, SPI_RETRY_MAX));
+        self.delay = Some(delay);
+        self.retry_at = Some(now + delay);
+        (delay, first)
+    }
+
+    // Returns whether drawing had been failing
+    pub fn succeeded(&mut self) -> bool {
+        self.retry_at = None;
+        self.delay.take().is_some()
+    }
+}
+
+struct Frame {
+    data: Vec<u16>,
+    force_full: bool,
+    // Numbered from 1 in the order submitted
+    seq: u64,
+}
+
+// Frames passed between the drawing side and the video thread. Along with
+// the frame being drawn and the one being sent there are at most three
+// buffers.
+#[derive(Default)]
+struct Frames {
+    // Newest frame not yet taken by the video thread
+    ready: Option<Frame>,
+    // Buffers to draw the next frame into
+    free: Vec<Vec<u16>>,
+    // Number of frames submitted
+    submitted: u64,
+    // Newest frame sent to the screen, and when sending it finished
+    shown: Option<(u64, Instant)>,
+    stop: bool,
+}
+
+type Shared = Arc<(Mutex<Frames>, Condvar)>;
+
+fn lock(shared: &Shared) -> MutexGuard<'_, Frames> {
+    match shared.0.lock() {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Poisoned mutex for video frames");
+            e.into_inner()
+        }
+    }
+}
+
+/// Sends frames to the screen driver on a thread of its own, so the next
+/// frame is drawn while the last is sent, e.g. over SPI. Only the newest frame
+/// waits to be sent, an older one is dropped when it is replaced.
+pub(crate) struct Video {
+    shared: Shared,
+    dropped: Arc<AtomicU64>,
+    handle: Option<JoinHandle<()>>,
+    size: usize,
+}
+
+impl Video {
+    /// Start the video thread sending frames to `driver`, which is dropped
+    /// when the thread stops. Errors from the driver are sent to `error_tx`.
+    pub fn new(driver: Box<dyn ScreenDriver>, error_tx: mpsc::Sender<Problem>) -> Self {
+        let size = usize::from(driver.width()) * usize::from(driver.height());
+        let shared: Shared = Arc::default();
+        let s2 = shared.clone();
+        let handle = std::thread::spawn(move || Self::video_thread(driver, s2, error_tx));
+        Video {
+            shared,
+            dropped: Arc::new(AtomicU64::new(0)),
+            handle: Some(handle),
+            size,
+        }
+    }
+
+    /// A buffer to draw the next frame into, holding whatever was last drawn
+    /// in it
+    pub fn buffer(&self) -> Vec<u16> {
+        lock(&self.shared)
+            .free
+            .pop()
+            .unwrap_or_else(|| vec![0; self.size])
+    }
+
+    /// Queue a frame to be shown, replacing any frame not sent yet
+    pub fn submit(&self, data: Vec<u16>, force_full: bool) {
+        let mut frames = lock(&self.shared);
+        let force_full = match frames.ready.take() {
+            Some(old) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                frames.free.push(old.data);
+                // The dropped frame may have been the one to redraw fully
+                force_full || old.force_full
+            }
+            None => force_full,
+        };
+        frames.submitted += 1;
+        let seq = frames.submitted;
+        frames.ready = Some(Frame {
+            data,
+            force_full,
+            seq,
+        });
+        self.shared.1.notify_one();
+    }
+
+    /// Number of frames replaced before they were sent to the screen
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of the last frame submitted, frames are numbered from 1
+    pub fn submitted(&self) -> u64 {
+        lock(&self.shared).submitted
+    }
+
+    /// Number of the newest frame sent to the screen, and when sending it
+    /// finished
+    pub fn shown(&self) -> Option<(u64, Instant)> {
+        lock(&self.shared).shown
+    }
+
+    /// Stop the video thread, once the frame being sent is done
+    pub fn stop(&mut self) {
+        lock(&self.shared).stop = true;
+        self.shared.1.notify_one();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Video thread panicked");
+            }
+        }
+    }
+
+    fn video_thread(
+        mut driver: Box<dyn ScreenDriver>,
+        shared: Shared,
+        error_tx: mpsc::Sender<Problem>,
+    ) {
+        let mut backoff = Backoff::default();
+        loop {
+            let mut frames = lock(&shared);
+            // Wait out any backoff first, so the newest frame is sent after
+            let remaining = backoff.remaining(Instant::now());
+            if !remaining.is_zero() {
+                frames = match shared.1.wait_timeout_while(frames, remaining, |f| !f.stop) {
+                    Ok((g, _)) => g,
+                    Err(e) => e.into_inner().0,
+                };
+            }
+            let frame = loop {
+                if frames.stop {
+                    debug!("Video thread stopping");
+                    return;
+                }
+                if let Some(frame) = frames.ready.take() {
+                    break frame;
+                }
+                frames = match shared.1.wait(frames) {
+                    Ok(g) => g,
+                    Err(e) => e.into_inner(),
+                };
+            };
+            drop(frames);
+
+            // After errors the driver's idea of what is shown may be wrong
+            let force_full = frame.force_full || backoff.failing();
+            let result = driver.tick(&frame.data, force_full);
+            let sent = Instant::now();
+            if let Err(e) = &result {
+                let (delay, first) = backoff.failed(Instant::now());
+                debug!("Screen error, retrying in {:?}", delay);
+                if first {
+                    error!("Screen driver error: {}", e);
+                    let toast = ScreenToast::error(ScreenMessage::VideoIssue);
+                    if error_tx.send(Problem::warn(toast)).is_err() {
+                        error!("Can't send to error channel");
+                    }
+                }
+            } else if backoff.succeeded() {
+                info!("Screen driver recovered");
+            }
+            let mut frames = lock(&shared);
+            if result.is_ok() {
+                frames.shown = Some((frame.seq, sent));
+            }
+            frames.free.push(frame.data);
+        }
+    }
+}
+
+impl Drop for Video {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, SPI_RETRY_MAX, SPI_RETRY_MIN};
+    use std::time::Instant;
+
+    #[test]
+    fn spi_backoff() {
+        let now = Instant::now();
+        let mut backoff = Backoff::default();
+        assert!(backoff.remaining(now).is_zero());
+        assert_eq!(backoff.failed(now), (SPI_RETRY_MIN, true));
+        assert_eq!(backoff.remaining(now), SPI_RETRY_MIN);
+        assert!(backoff.remaining(now + SPI_RETRY_MIN).is_zero());
+        assert_eq!(backoff.failed(now), (SPI_RETRY_MIN * 2, false));
+        for _ in 0..10 {
+            backoff.failed(now);
+        }
+        assert_eq!(backoff.remaining(now), SPI_RETRY_MAX);
+        assert!(backoff.succeeded());
+        assert!(backoff.remaining(now).is_zero());
+        assert!(!backoff.succeeded());
+    }
+}
@@ -50,6 +50,11 @@ uint64_t displayContentsLastChanged = 0;
 volatile bool programRunning = true;
 volatile bool initialised = false;
 
+// Time the SPI queue has to make progress before frames are dropped
+#define SPI_STALL_USECS 500000
+uint32_t lastQueueHead;
+uint64_t lastQueueProgress;
+
 const char *SignalToString(int signal)
 {
   if (signal == SIGINT) return "SIGINT";
@@ -81,12 +86,16 @@ uint16_t lcd_lib_height()
   return DISPLAY_NATIVE_HEIGHT;
 }
 
-void lcd_lib_init(log_printf_t log_func)
+int lcd_lib_init(log_printf_t log_func)
 {
   log_printf = log_func;
   log_printf(L_INFO, "LCD Driver starting\n");
   OpenMailbox();
-  InitSPI();
+  if (InitSPI() < 0)
+  {
+    CloseMailbox();
+    return LCD_ERR_SPI_INIT;
+  }
   displayContentsLastChanged = tick();
   InitLowBatterySystem();
 
@@ -110,16 +119,32 @@ void lcd_lib_init(log_printf_t log_func)
   prevFrameWasInterlacedUpdate = false;
   interlacedUpdate = false; // True if the previous update we did was an interlaced half field update.
   frameParity = 0; // For interlaced frame updates, this is either 0 or 1 to denote evens or odds.
+  lastQueueHead = spiTaskMemory->queueHead;
+  lastQueueProgress = tick();
+
   log_printf(L_DEBUG, "All initialised, now running main loop...\n");
   initialised = true;
+  return LCD_OK;
 }
 
-void lcd_lib_tick(const uint16_t *data, int force_full)
+int lcd_lib_tick(const uint16_t *data, int force_full)
 {
   if (!initialised)
   {
     log_printf(L_WARN, "LCD not initialised before trying to draw\n");
-    return;
+    return LCD_ERR_NOT_INITIALISED;
+  }
+
+  // If the SPI thread has stopped taking tasks off the queue, queueing more
+  // would block once it fills, so drop the frame and let the caller retry
+  if (spiTaskMemory->queueHead != lastQueueHead || spiTaskMemory->queueHead == spiTaskMemory->queueTail)
+  {
+    lastQueueHead = spiTaskMemory->queueHead;
+    lastQueueProgress = tick();
+  }
+  else if (tick() - lastQueueProgress > SPI_STALL_USECS)
+  {
+    return LCD_ERR_SPI_STALLED;
   }
 
   // No dealing with waiting for frames on interlacing when pushing frames.
@@ -398,6 +423,7 @@ void lcd_lib_tick(const uint16_t *data, int force_full)
   }
   statsBytesTransferred += bytesTransferred;
 #endif
+  return LCD_OK;
 }
 
 void lcd_lib_deinit()
@@ -8,10 +8,19 @@
 extern "C" {
 #endif
 
+// Results of lcd_lib_init and lcd_lib_tick
+#define LCD_OK 0
+// Drawn to before lcd_lib_init succeeded
+#define LCD_ERR_NOT_INITIALISED -1
+// Couldn't map the SPI peripheral
+#define LCD_ERR_SPI_INIT -2
+// The SPI queue hasn't moved for SPI_STALL_USECS, the frame was dropped
+#define LCD_ERR_SPI_STALLED -3
+
 uint16_t lcd_lib_width();
 uint16_t lcd_lib_height();
-void lcd_lib_init(log_printf_t log_func);
-void lcd_lib_tick(const uint16_t *data, int force_full);
+int lcd_lib_init(log_printf_t log_func);
+int lcd_lib_tick(const uint16_t *data, int force_full);
 void lcd_lib_deinit();
 
 #ifdef __cplusplus
@@ -492,10 +492,14 @@ int InitSPI()
 #else // Userland version
   // Memory map GPIO and SPI peripherals for direct access
   mem_fd = open("/dev/mem", O_RDWR|O_SYNC);
-  if (mem_fd < 0) FATAL_ERROR("can't open /dev/mem (run as sudo)");
+  if (mem_fd < 0) INIT_ERROR("can't open /dev/mem (run as sudo)");
   log_printf(L_DEBUG, "bcm_host_get_peripheral_address: %p, bcm_host_get_peripheral_size: %u, bcm_host_get_sdram_address: %p\n", bcm_host_get_peripheral_address(), bcm_host_get_peripheral_size(), bcm_host_get_sdram_address());
   bcm2835 = mmap(NULL, bcm_host_get_peripheral_size(), (PROT_READ | PROT_WRITE), MAP_SHARED, mem_fd, bcm_host_get_peripheral_address());
-  if (bcm2835 == MAP_FAILED) FATAL_ERROR("mapping /dev/mem failed");
+  if (bcm2835 == MAP_FAILED)
+  {
+    close(mem_fd);
+    INIT_ERROR("mapping /dev/mem failed");
+  }
   spi = (volatile SPIRegisterFile*)((uintptr_t)bcm2835 + BCM2835_SPI0_BASE);
   gpio = (volatile GPIORegisterFile*)((uintptr_t)bcm2835 + BCM2835_GPIO_BASE);
   // Although unaligned pointer is ok on 32-bit arch, causes a bus error on 64-bit :shrug:
@@ -22,9 +22,12 @@
 #ifdef KERNEL_MODULE
 #define LOG(...) do { printk(KERN_INFO __VA_ARGS__); } while(0)
 #define FATAL_ERROR(msg) do { pr_alert(msg "\n"); return -1; } while(0)
+#define INIT_ERROR(msg) FATAL_ERROR(msg)
 #else
 #define LOG(...) do { log_printf(L_DEBUG, __VA_ARGS__); } while(0)
 #define FATAL_ERROR(msg) do { log_printf(L_ERROR, "%s\n", msg); exit(1); } while(0)
+// Errors during init that the caller can recover from, e.g. by retrying
+#define INIT_ERROR(msg) do { log_printf(L_ERROR, "%s\n", msg); return -1; } while(0)
 #endif
 
 #ifdef KERNEL_MODULE
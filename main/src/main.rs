@@ -1,7 +1,8 @@
 use clap::Parser;
 use std::error::Error;
+use std::path::PathBuf;
 
-use gamepie_app::Gamepie;
+use gamepie_app::{Gamepie, RecentLog, SaveCommand, StateCommand};
 
 #[derive(clap::Parser)]
 #[clap(name = "GamePIE")]
@@ -18,6 +19,119 @@ struct Context {
     /// System directory
     #[clap(short, long, default_value_t = String::from("./system"))]
     system: String,
+    /// Config file (defaults to gamepie.toml in the system directory)
+    #[clap(short, long)]
+    config: Option<String>,
+    /// Read JSON commands from a FIFO, or stdin with "-"
+    #[clap(long)]
+    command_pipe: Option<String>,
+    /// Serve the HTTP management API on an address, e.g. "0.0.0.0:8080"
+    #[clap(long)]
+    http: Option<String>,
+    /// Log environment calls from cores to a file per game
+    #[clap(long)]
+    trace_env: bool,
+    /// Look up names for games without metadata, then exit
+    #[clap(long)]
+    scrape: bool,
+    /// Start with the default config and verified cores only
+    #[clap(long)]
+    safe_mode: bool,
+    /// Run in a window on a PC, with the keyboard as the buttons
+    #[clap(long)]
+    sdl: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Manage save files, then exit
+    #[clap(subcommand)]
+    Save(SaveAction),
+    /// Manage save states, then exit
+    #[clap(subcommand)]
+    State(StateAction),
+    /// Import names and box art from RetroArch's playlists, then exit
+    ImportRetroarch {
+        /// RetroArch configuration directory
+        #[clap(default_value = "~/.config/retroarch")]
+        dir: String,
+    },
+    /// Run a core without the screen or audio and report what it used,
+    /// then exit
+    TestCore {
+        /// Core library
+        core: PathBuf,
+        /// Game to run, the core is only loaded if none is given
+        rom: Option<PathBuf>,
+        /// Frames to run the game for
+        #[clap(long, default_value_t = 600)]
+        frames: u32,
+        /// Script of buttons to press, a frame number and the buttons held
+        /// from then on per line
+        #[clap(long)]
+        input: Option<PathBuf>,
+        /// Fail unless the last frame has this hash
+        #[clap(long)]
+        expect_screen: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum SaveAction {
+    /// List every save
+    List,
+    /// Show the size, checksum, backups and ROM for a game's save
+    Inspect {
+        /// Game filename
+        game: String,
+    },
+    /// Copy a game's save to a file
+    Export {
+        /// Game filename
+        game: String,
+        file: PathBuf,
+    },
+    /// Replace a game's save with a file, keeping the old save as a backup
+    Import {
+        file: PathBuf,
+        /// Game filename
+        game: String,
+        /// Import even if the size differs from the current save
+        #[clap(long)]
+        force: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum StateAction {
+    /// List every save state
+    List,
+    /// Show the core, ROM and time a game's state was saved with
+    Inspect {
+        /// Game filename
+        game: String,
+    },
+    /// Copy a game's state to a file
+    Export {
+        /// Game filename
+        game: String,
+        file: PathBuf,
+        /// Leave out the header, for other frontends
+        #[clap(long)]
+        raw: bool,
+    },
+    /// Replace a game's state with a file, keeping the old state as a backup
+    Import {
+        file: PathBuf,
+        /// Game filename
+        game: String,
+        /// Core library the state is for, needed for states from other
+        /// frontends
+        #[clap(long)]
+        core: Option<PathBuf>,
+    },
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -31,13 +145,82 @@ fn main() -> Result<(), Box<dyn Error>> {
     } else {
         log::LevelFilter::Info
     };
-    simple_logger::SimpleLogger::new()
-        .with_level(level)
-        .env()
-        .init()
-        .unwrap();
+    let logger = simple_logger::SimpleLogger::new().with_level(level).env();
+    // RUST_LOG can raise the level, as it does for simple_logger
+    let max_level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|l| l.parse().ok())
+        .map_or(level, |l: log::LevelFilter| l.max(level));
+    // Recent lines are kept for crash reports
+    RecentLog::init(Box::new(logger), max_level).unwrap();
 
-    let gamepie = Gamepie::new(args.system.as_ref())?;
+    match args.command {
+        Some(Command::Save(action)) => {
+            let command = match action {
+                SaveAction::List => SaveCommand::List,
+                SaveAction::Inspect { game } => SaveCommand::Inspect(game),
+                SaveAction::Export { game, file } => SaveCommand::Export(game, file),
+                SaveAction::Import { file, game, force } => SaveCommand::Import(file, game, force),
+            };
+            return gamepie_app::saves(&args.system, args.config.as_deref(), command);
+        }
+        Some(Command::State(action)) => {
+            let command = match action {
+                StateAction::List => StateCommand::List,
+                StateAction::Inspect { game } => StateCommand::Inspect(game),
+                StateAction::Export { game, file, raw } => StateCommand::Export(game, file, raw),
+                StateAction::Import { file, game, core } => StateCommand::Import(file, game, core),
+            };
+            return gamepie_app::states(&args.system, args.config.as_deref(), command);
+        }
+        Some(Command::ImportRetroarch { dir }) => {
+            // Not expanded by the shell when it is the default
+            let dir = match (dir.strip_prefix("~/"), std::env::var_os("HOME")) {
+                (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(dir),
+            };
+            return gamepie_app::import_retroarch(&args.system, args.config.as_deref(), &dir);
+        }
+        Some(Command::TestCore {
+            core,
+            rom,
+            frames,
+            input,
+            expect_screen,
+        }) => {
+            return gamepie_app::test_core(
+                &args.system,
+                args.config.as_deref(),
+                &core,
+                rom.as_deref(),
+                frames,
+                input.as_deref(),
+                expect_screen.as_deref(),
+            );
+        }
+        None => {}
+    }
+
+    if args.scrape {
+        return gamepie_app::scrape(&args.system, args.config.as_deref());
+    }
+
+    let mut gamepie = if args.sdl {
+        Gamepie::new_desktop(args.system.as_ref(), args.config.as_deref(), args.safe_mode)?
+    } else {
+        Gamepie::new(args.system.as_ref(), args.config.as_deref(), args.safe_mode)?
+    };
+    if let Some(pipe) = &args.command_pipe {
+        gamepie.listen(pipe);
+    }
+    if let Some(addr) = &args.http {
+        gamepie.serve_http(addr)?;
+    }
+    if args.trace_env {
+        gamepie.trace_env();
+    }
+    // Plugins are crates added as optional dependencies behind a feature,
+    // and added here with gamepie.add_plugin when it is enabled
 
     gamepie.run()?;
     Ok(())
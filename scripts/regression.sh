@@ -0,0 +1,26 @@
+#!/bin/sh
+# Run each game in a list through `gamepie test-core` and check the screen
+# it ends on, e.g. in CI after building a core or changing the frontend.
+# Each line of the list is:
+#   <core> <rom> <frames> <input script or -> <screen hash>
+# Paths are relative to the list. Blank lines and lines starting with '#'
+# are skipped. test-core prints the hash of the screen to put in the list.
+set -e
+LIST=${1:?usage: regression.sh <list> [gamepie binary]}
+GAMEPIE=${2:-target/release/main}
+DIR=$(dirname "$LIST")
+
+failed=0
+while read -r core rom frames input hash; do
+    case "$core" in ''|'#'*) continue ;; esac
+    set -- "$DIR/$core" "$DIR/$rom" --frames "$frames" --expect-screen "$hash"
+    [ "$input" = "-" ] || set -- "$@" --input "$DIR/$input"
+    if "$GAMEPIE" test-core "$@" > /dev/null 2>&1; then
+        echo "ok   $rom"
+    else
+        echo "FAIL $rom"
+        failed=$((failed + 1))
+    fi
+done < "$LIST"
+
+[ "$failed" -eq 0 ] || { echo "$failed failed"; exit 1; }